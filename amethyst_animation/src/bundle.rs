@@ -10,11 +10,13 @@ use amethyst_core::{
     SystemBundle,
 };
 use amethyst_error::Error;
+use amethyst_rendy::skinning::{CpuSkinningSystem, SkinnedBoundsSystem};
 use std::{hash::Hash, marker};
 
 /// Bundle for vertex skinning
 ///
-/// This registers `VertexSkinningSystem`.
+/// This registers `VertexSkinningSystem`, followed by the `CpuSkinningSystem` and the
+/// `SkinnedBoundsSystem` of `amethyst_rendy`, which use the joint matrices it computes.
 /// Note that the user must make sure this system runs after `TransformSystem`
 #[derive(Default)]
 pub struct VertexSkinningBundle<'a> {
@@ -41,6 +43,16 @@ impl<'a, 'b, 'c> SystemBundle<'a, 'b> for VertexSkinningBundle<'c> {
             "vertex_skinning_system",
             self.dep,
         );
+        builder.add(
+            CpuSkinningSystem::new(),
+            "cpu_skinning_system",
+            &["vertex_skinning_system"],
+        );
+        builder.add(
+            SkinnedBoundsSystem::new(),
+            "skinned_bounds_system",
+            &["vertex_skinning_system"],
+        );
         Ok(())
     }
 }
@@ -0,0 +1,140 @@
+use amethyst_core::{
+    ecs::prelude::{Entity, Join, Read, ReadStorage, System, Write},
+    math::{convert, Matrix4, Point3},
+    Parent, Transform,
+};
+use amethyst_rendy::{debug_drawing::DebugLines, palette::Srgba};
+use fnv::FnvHashSet;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+use super::resources::Skin;
+
+/// Colors of the bones drawn by `SkeletonDebugSystem`, indexed by the depth of the child joint
+/// in the hierarchy.
+const DEPTH_COLORS: [(f32, f32, f32); 6] = [
+    (1.0, 1.0, 1.0),
+    (1.0, 0.8, 0.2),
+    (0.3, 1.0, 0.3),
+    (0.2, 0.8, 1.0),
+    (0.6, 0.4, 1.0),
+    (1.0, 0.4, 0.7),
+];
+
+/// Resource controlling the skeletons drawn by `SkeletonDebugSystem`.
+#[derive(Debug, Clone)]
+pub struct SkeletonDebug {
+    /// Whether skeletons are drawn.
+    pub enabled: bool,
+    /// Length of the axes drawn at each joint, zero to hide them.
+    pub axis_length: f32,
+    /// Whether joints are labeled with their index in the `Skin`.
+    pub show_indices: bool,
+    /// Height of the joint labels.
+    pub label_size: f32,
+}
+
+impl Default for SkeletonDebug {
+    fn default() -> Self {
+        SkeletonDebug {
+            enabled: true,
+            axis_length: 0.05,
+            show_indices: false,
+            label_size: 0.02,
+        }
+    }
+}
+
+/// System drawing the skeletons of all `Skin`s through the `DebugLines` resource, while
+/// `SkeletonDebug::enabled` is set.
+///
+/// A line is drawn from each joint to its parent joint, colored by the depth of the joint in the
+/// hierarchy, along with the axes of the joint. Joint positions are read from the global
+/// `Transform`s the `VertexSkinningSystem` builds its matrices from.
+///
+/// Needs to run after global transforms have been updated for the current frame.
+#[derive(Debug, Default)]
+pub struct SkeletonDebugSystem {
+    joints: FnvHashSet<Entity>,
+}
+
+impl SkeletonDebugSystem {
+    /// Creates a new `SkeletonDebugSystem`
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for SkeletonDebugSystem {
+    type SystemData = (
+        ReadStorage<'a, Skin>,
+        ReadStorage<'a, Parent>,
+        ReadStorage<'a, Transform>,
+        Read<'a, SkeletonDebug>,
+        Option<Write<'a, DebugLines>>,
+    );
+
+    fn run(&mut self, (skins, parents, transforms, settings, lines): Self::SystemData) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("skeleton_debug_system");
+
+        let mut lines = match lines {
+            Some(lines) if settings.enabled => lines,
+            _ => return,
+        };
+        let origin = Point3::origin();
+        let position = |entity: Entity| {
+            transforms.get(entity).map(|t| {
+                let matrix = convert::<_, Matrix4<f32>>(*t.global_matrix());
+                (matrix, matrix.transform_point(&origin))
+            })
+        };
+
+        for skin in skins.join() {
+            self.joints.clear();
+            self.joints.extend(skin.joints.iter().cloned());
+            let joints = &self.joints;
+            let parent_joint = |entity: Entity| {
+                parents
+                    .get(entity)
+                    .map(|p| p.entity)
+                    .filter(|p| joints.contains(p))
+            };
+
+            for (index, &joint) in skin.joints.iter().enumerate() {
+                let (matrix, joint_position) = match position(joint) {
+                    Some(position) => position,
+                    None => continue,
+                };
+
+                let mut depth = 0;
+                let mut ancestor = parent_joint(joint);
+                while let Some(entity) = ancestor {
+                    depth += 1;
+                    if depth > skin.joints.len() {
+                        break;
+                    }
+                    ancestor = parent_joint(entity);
+                }
+                let (r, g, b) = DEPTH_COLORS[depth % DEPTH_COLORS.len()];
+                let color = Srgba::new(r, g, b, 1.0);
+
+                if let Some((_, parent_position)) = parent_joint(joint).and_then(position) {
+                    lines.draw_line(parent_position, joint_position, color);
+                }
+                if settings.axis_length > 0.0 {
+                    lines.draw_axes(&matrix, settings.axis_length);
+                }
+                if settings.show_indices {
+                    lines.draw_text(
+                        joint_position,
+                        &index.to_string(),
+                        color,
+                        settings.label_size,
+                    );
+                }
+            }
+        }
+    }
+}
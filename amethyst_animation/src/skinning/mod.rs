@@ -1,4 +1,5 @@
-pub use self::{resources::*, systems::*};
+pub use self::{debug::*, resources::*, systems::*};
 
+mod debug;
 mod resources;
 mod systems;
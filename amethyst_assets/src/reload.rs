@@ -278,3 +278,50 @@ impl<D: 'static> Reload<D> for SingleFile<D> {
         format.import(path, source, Some(objekt::clone(&format)))
     }
 }
+
+/// A `Reload` keeping a copy of the data an asset was loaded from, so `AssetStorage::reload_all`
+/// can load the asset again. It never needs a hot reload.
+pub(crate) struct KeptData<D> {
+    data: Arc<D>,
+    clone: fn(&D) -> D,
+}
+
+impl<D> KeptData<D> {
+    /// Creates a `KeptData` with a copy of `data`.
+    pub(crate) fn new(data: &D, clone: fn(&D) -> D) -> Self {
+        KeptData {
+            data: Arc::new(clone(data)),
+            clone,
+        }
+    }
+}
+
+impl<D> Clone for KeptData<D> {
+    fn clone(&self) -> Self {
+        KeptData {
+            data: self.data.clone(),
+            clone: self.clone,
+        }
+    }
+}
+
+impl<D: Send + Sync + 'static> Reload<D> for KeptData<D> {
+    fn needs_reload(&self) -> bool {
+        false
+    }
+
+    fn name(&self) -> String {
+        "<Data>".into()
+    }
+
+    fn format(&self) -> &'static str {
+        "data"
+    }
+
+    fn reload(self: Box<Self>) -> Result<FormatValue<D>, Error> {
+        Ok(FormatValue {
+            data: (self.clone)(&self.data),
+            reload: Some(self),
+        })
+    }
+}
@@ -8,7 +8,8 @@ use std::{
 
 use crossbeam::queue::MsQueue;
 use derivative::Derivative;
-use hibitset::BitSet;
+use fnv::FnvHashMap;
+use hibitset::{BitSet, BitSetLike};
 use log::{debug, error, trace, warn};
 use rayon::ThreadPool;
 
@@ -28,7 +29,7 @@ use crate::{
     asset::{Asset, FormatValue, ProcessableAsset},
     error,
     progress::Tracker,
-    reload::{HotReloadStrategy, Reload},
+    reload::{HotReloadStrategy, KeptData, Reload},
 };
 
 /// An `Allocator`, holding a counter for producing unique IDs.
@@ -54,6 +55,8 @@ pub struct AssetStorage<A: Asset> {
     pub(crate) processed: Arc<MsQueue<Processed<A>>>,
     reloads: Vec<(WeakHandle<A>, Box<dyn Reload<A::Data>>)>,
     unused_handles: MsQueue<Handle<A>>,
+    failed: FnvHashMap<u32, (WeakHandle<A>, String)>,
+    keep_data: Option<fn(&A::Data) -> A::Data>,
 }
 
 /// Returned by processor systems, describes the loading state of the asset.
@@ -148,6 +151,12 @@ impl<A: Asset> AssetStorage<A> {
         }
     }
 
+    /// The name of the asset of `handle`, if it failed to load. Handles are forgotten once their
+    /// asset is loaded, or once they are dropped.
+    pub fn failed(&self, handle: &Handle<A>) -> Option<&str> {
+        self.failed.get(&handle.id()).map(|(_, name)| name.as_str())
+    }
+
     /// Get an asset by it's handle id.
     pub fn get_by_id(&self, id: u32) -> Option<&A> {
         if self.bitset.contains(id) {
@@ -157,6 +166,14 @@ impl<A: Asset> AssetStorage<A> {
         }
     }
 
+    /// Iterate over all loaded assets together with their handles.
+    pub fn iter(&self) -> impl Iterator<Item = (&Handle<A>, &A)> {
+        self.handles
+            .iter()
+            .filter(move |handle| self.bitset.contains(handle.id()))
+            .map(move |handle| (handle, unsafe { &self.assets.get(handle.id()).0 }))
+    }
+
     /// Replace asset under given handle, incrementing the version id.
     /// Returns old asset. Panics if asset handle is empty.
     pub fn replace(&mut self, handle: &Handle<A>, asset: A) -> A {
@@ -169,6 +186,64 @@ impl<A: Asset> AssetStorage<A> {
         }
     }
 
+    /// Put an asset under a handle whose asset was unloaded, or replace its asset like `replace`.
+    /// Returns the old asset if there was one.
+    pub fn set(&mut self, handle: &Handle<A>, asset: A) -> Option<A> {
+        self.failed.remove(&handle.id());
+        if self.bitset.contains(handle.id()) {
+            Some(self.replace(handle, asset))
+        } else {
+            self.bitset.add(handle.id());
+            unsafe {
+                self.assets.insert(handle.id(), (asset, 0));
+            }
+            None
+        }
+    }
+
+    /// Keep a copy of the data of the assets loaded from data from now on, like with
+    /// `Loader::load_from_data`, so `reload_all` loads them again too.
+    pub fn keep_loaded_data(&mut self)
+    where
+        A::Data: Clone,
+    {
+        self.keep_data = Some(A::Data::clone);
+    }
+
+    /// Stop reloading the asset of `handle`, both for hot-reloading and in `reload_all`, as it
+    /// is kept up to date by other means.
+    pub fn forget_reload(&mut self, handle: &Handle<A>) {
+        let id = handle.id();
+        self.reloads
+            .retain(|(reload, _)| reload.upgrade().map_or(false, |h| h.id() != id));
+    }
+
+    /// Unload all assets, keeping their handles, and load the assets that can be hot-reloaded
+    /// again from their sources on the next `process`. Assets loaded from data are loaded again
+    /// if `keep_loaded_data` was called before they were loaded; the others stay unloaded until
+    /// they are `set` again.
+    ///
+    /// This is for assets that became invalid all at once, like the GPU copies of the renderer
+    /// after its device was lost. `drop_fn` is called with every unloaded asset.
+    pub fn reload_all<D>(&mut self, pool: &ThreadPool, mut drop_fn: D)
+    where
+        D: FnMut(A),
+    {
+        let ids: Vec<_> = (&self.bitset).iter().collect();
+        for id in ids {
+            let (asset, _) = unsafe { self.assets.remove(id) };
+            drop_fn(asset);
+        }
+        self.bitset.clear();
+
+        let reloads: Vec<_> = self.reloads.drain(..).collect();
+        for (handle, rel) in reloads {
+            if let Some(handle) = handle.upgrade() {
+                self.spawn_reload(pool, handle, rel);
+            }
+        }
+    }
+
     /// Insert preloaded asset into storage synchronously
     /// without going through usual loading step.
     /// You probably want to use `Loader::load` instead.
@@ -243,12 +318,14 @@ impl<A: Asset> AssetStorage<A> {
         F: FnMut(A::Data) -> Result<ProcessingState<A>, Error>,
     {
         {
+            let keep_data = self.keep_data;
             let mut requeue = Vec::new();
             while let Some(processed) = self.processed.try_pop() {
                 let assets = &mut self.assets;
                 let bitset = &mut self.bitset;
                 let handles = &mut self.handles;
                 let reloads = &mut self.reloads;
+                let failed = &mut self.failed;
 
                 let f = &mut f;
                 let (reload_obj, handle) = match processed {
@@ -259,7 +336,15 @@ impl<A: Asset> AssetStorage<A> {
                         tracker,
                     } => {
                         let (asset, reload_obj) = match data
-                            .map(|FormatValue { data, reload }| (data, reload))
+                            .map(|FormatValue { data, reload }| {
+                                let reload = reload.or_else(|| {
+                                    keep_data.map(|clone| {
+                                        Box::new(KeptData::new(&data, clone))
+                                            as Box<dyn Reload<A::Data>>
+                                    })
+                                });
+                                (data, reload)
+                            })
                             .and_then(|(d, rel)| f(d).map(|a| (a, rel)))
                             .with_context(|_| error::Error::Asset(name.clone()))
                         {
@@ -313,6 +398,7 @@ impl<A: Asset> AssetStorage<A> {
                                     handle,
                                     e,
                                 );
+                                failed.insert(handle.id(), (handle.downgrade(), name.clone()));
                                 tracker.fail(handle.id(), A::NAME, name, e);
 
                                 continue;
@@ -322,6 +408,7 @@ impl<A: Asset> AssetStorage<A> {
                         let id = handle.id();
                         bitset.add(id);
                         handles.push(handle.clone());
+                        failed.remove(&id);
 
                         // NOTE: the loader has to ensure that a handle will be used
                         // together with a `Data` only once.
@@ -375,14 +462,17 @@ impl<A: Asset> AssetStorage<A> {
                         };
 
                         let id = handle.id();
-                        assert!(
-                            bitset.contains(id),
-                            "Expected handle {:?} to be valid, but the asset storage says otherwise",
-                            handle,
-                        );
-                        let data = unsafe { self.assets.get_mut(id) };
-                        data.1 += 1;
-                        drop_fn(std::mem::replace(&mut data.0, asset));
+                        if bitset.contains(id) {
+                            let data = unsafe { assets.get_mut(id) };
+                            data.1 += 1;
+                            drop_fn(std::mem::replace(&mut data.0, asset));
+                        } else {
+                            // Unloaded by `reload_all`
+                            bitset.add(id);
+                            unsafe {
+                                assets.insert(id, (asset, 0));
+                            }
+                        }
 
                         (reload_obj, handle)
                     }
@@ -408,11 +498,13 @@ impl<A: Asset> AssetStorage<A> {
             skip = i;
             let handle = self.handles.swap_remove(i);
             let id = handle.id();
-            unsafe {
-                let (asset, _) = self.assets.remove(id);
-                drop_fn(asset);
+            // The asset may have been unloaded already.
+            if self.bitset.remove(id) {
+                unsafe {
+                    let (asset, _) = self.assets.remove(id);
+                    drop_fn(asset);
+                }
             }
-            self.bitset.remove(id);
 
             // Can't reuse old handle here, because otherwise weak handles would still be valid.
             // TODO: maybe just store u32?
@@ -424,6 +516,7 @@ impl<A: Asset> AssetStorage<A> {
         if count != 0 {
             debug!("{:?}: Freed {} handle ids", A::NAME, count,);
         }
+        self.failed.retain(|_, (handle, _)| !handle.is_dead());
 
         if strategy
             .map(|s| s.needs_reload(frame_number))
@@ -443,35 +536,39 @@ impl<A: Asset> AssetStorage<A> {
         {
             let (handle, rel): (WeakHandle<_>, Box<dyn Reload<_>>) = self.reloads.swap_remove(p);
 
-            let name = rel.name();
-            let format = rel.format();
             let handle = handle.upgrade();
 
             debug!(
                 "{:?}: Asset {:?} (handle id: {:?}) needs a reload using format {:?}",
                 A::NAME,
-                name,
+                rel.name(),
                 handle,
-                format,
+                rel.format(),
             );
 
             if let Some(handle) = handle {
-                let processed = self.processed.clone();
-                pool.spawn(move || {
-                    let old_reload = rel.clone();
-                    let data = rel.reload().with_context(|_| error::Error::Format(format));
-
-                    let p = Processed::HotReload {
-                        data,
-                        name,
-                        handle,
-                        old_reload,
-                    };
-                    processed.push(p);
-                });
+                self.spawn_reload(pool, handle, rel);
             }
         }
     }
+
+    fn spawn_reload(&self, pool: &ThreadPool, handle: Handle<A>, rel: Box<dyn Reload<A::Data>>) {
+        let name = rel.name();
+        let format = rel.format();
+        let processed = self.processed.clone();
+        pool.spawn(move || {
+            let old_reload = rel.clone();
+            let data = rel.reload().with_context(|_| error::Error::Format(format));
+
+            let p = Processed::HotReload {
+                data,
+                name,
+                handle,
+                old_reload,
+            };
+            processed.push(p);
+        });
+    }
 }
 
 impl<A: Asset> Default for AssetStorage<A> {
@@ -484,6 +581,8 @@ impl<A: Asset> Default for AssetStorage<A> {
             processed: Arc::new(MsQueue::new()),
             reloads: Default::default(),
             unused_handles: MsQueue::new(),
+            failed: Default::default(),
+            keep_data: None,
         }
     }
 }
@@ -628,3 +727,95 @@ impl<A> WeakHandle<A> {
         self.upgrade().is_none()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use rayon::ThreadPoolBuilder;
+
+    use super::*;
+    use crate::Loader;
+
+    struct Counter(u32);
+
+    impl Asset for Counter {
+        const NAME: &'static str = "Counter";
+        type Data = u32;
+        type HandleStorage = VecStorage<Handle<Self>>;
+    }
+
+    fn process(storage: &mut AssetStorage<Counter>, pool: &ThreadPool) {
+        storage.process(
+            |data| Ok(ProcessingState::Loaded(Counter(data))),
+            0,
+            pool,
+            None,
+        );
+    }
+
+    fn wait_loaded(
+        storage: &mut AssetStorage<Counter>,
+        handle: &Handle<Counter>,
+        pool: &ThreadPool,
+    ) -> u32 {
+        for _ in 0..1000 {
+            process(storage, pool);
+            if let Some(counter) = storage.get(handle) {
+                return counter.0;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        panic!("The asset was not loaded");
+    }
+
+    fn load(storage: &AssetStorage<Counter>, data: u32) -> Handle<Counter> {
+        let pool = Arc::new(ThreadPoolBuilder::new().num_threads(1).build().unwrap());
+        Loader::new(".", pool).load_from_data(data, (), storage)
+    }
+
+    #[test]
+    fn kept_data_is_loaded_again_by_every_reload_all() {
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let mut storage = AssetStorage::<Counter>::new();
+        storage.keep_loaded_data();
+        let handle = load(&storage, 7);
+        assert_eq!(wait_loaded(&mut storage, &handle, &pool), 7);
+
+        storage.reload_all(&pool, drop);
+        assert!(storage.get(&handle).is_none());
+        assert_eq!(wait_loaded(&mut storage, &handle, &pool), 7);
+
+        storage.reload_all(&pool, drop);
+        assert!(storage.get(&handle).is_none());
+        assert_eq!(wait_loaded(&mut storage, &handle, &pool), 7);
+    }
+
+    #[test]
+    fn data_is_only_kept_when_asked() {
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let mut storage = AssetStorage::<Counter>::new();
+        let handle = load(&storage, 7);
+        assert_eq!(wait_loaded(&mut storage, &handle, &pool), 7);
+
+        storage.reload_all(&pool, drop);
+        process(&mut storage, &pool);
+        assert!(storage.get(&handle).is_none());
+    }
+
+    #[test]
+    fn forgotten_reloads_are_not_loaded_again() {
+        let pool = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let mut storage = AssetStorage::<Counter>::new();
+        storage.keep_loaded_data();
+        let handle = load(&storage, 7);
+        assert_eq!(wait_loaded(&mut storage, &handle, &pool), 7);
+
+        storage.forget_reload(&handle);
+        storage.reload_all(&pool, drop);
+        process(&mut storage, &pool);
+        assert!(storage.get(&handle).is_none());
+        storage.set(&handle, Counter(8));
+        assert_eq!(storage.get(&handle).map(|counter| counter.0), Some(8));
+    }
+}
@@ -106,10 +106,10 @@ pub fn load_material(
             prefab.transparent = true;
         }
         AlphaMode::Mask => {
-            prefab.alpha_cutoff = material.alpha_cutoff();
+            prefab.alpha_cutoff = Some(material.alpha_cutoff());
         }
         AlphaMode::Opaque => {
-            prefab.alpha_cutoff = 0.0;
+            prefab.alpha_cutoff = Some(0.0);
         }
     }
     Ok(prefab)
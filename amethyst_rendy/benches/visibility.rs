@@ -0,0 +1,150 @@
+use amethyst_core::{
+    ecs::prelude::{Builder, Dispatcher, DispatcherBuilder, Entity, World},
+    SystemBundle, Transform, TransformBundle,
+};
+use amethyst_rendy::{camera::Camera, visibility::VisibilitySortingSystem};
+use amethyst_window::ScreenDimensions;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rayon::ThreadPoolBuilder;
+use std::sync::Arc;
+
+const STATIC_ENTITIES: usize = 50_000;
+const DYNAMIC_ENTITIES: usize = 500;
+const ROW: usize = 224;
+
+// Scatters the static entities over a 1000 x 1000 plane around a camera at the origin, which
+// looks along -Z and so sees a fraction of them.
+fn setup(system: VisibilitySortingSystem) -> (World, Dispatcher<'static, 'static>, Vec<Entity>) {
+    let mut world = World::new();
+    world.add_resource(ScreenDimensions::new(1920, 1080, 1.0));
+
+    let mut builder = DispatcherBuilder::new();
+    TransformBundle::new()
+        .build(&mut builder)
+        .expect("Failed to add transform bundle");
+    builder.add(system, "visibility_sorting_system", &["transform_system"]);
+    let mut dispatcher = builder.build();
+    dispatcher.setup(&mut world.res);
+
+    world
+        .create_entity()
+        .with(Camera::standard_3d(1920.0, 1080.0))
+        .with(Transform::default())
+        .build();
+
+    for i in 0..STATIC_ENTITIES {
+        let mut transform = Transform::default();
+        transform.set_translation_xyz(
+            (i % ROW) as f32 * 4.5 - 500.0,
+            0.0,
+            (i / ROW) as f32 * 4.5 - 500.0,
+        );
+        world.create_entity().with(transform).build();
+    }
+    let dynamic = (0..DYNAMIC_ENTITIES)
+        .map(|i| {
+            let mut transform = Transform::default();
+            transform.set_translation_xyz(i as f32 * 2.0 - 500.0, 1.0, -(i as f32) * 2.0);
+            world.create_entity().with(transform).build()
+        })
+        .collect();
+
+    // Let the static entities settle into the grid
+    for _ in 0..2 {
+        dispatcher.dispatch(&world.res);
+        world.maintain();
+    }
+
+    (world, dispatcher, dynamic)
+}
+
+fn frame(world: &mut World, dispatcher: &mut Dispatcher<'_, '_>, dynamic: &[Entity]) {
+    {
+        let mut transforms = world.write_storage::<Transform>();
+        for entity in dynamic {
+            if let Some(transform) = transforms.get_mut(*entity) {
+                transform.prepend_translation_y(0.01);
+            }
+        }
+    }
+    dispatcher.dispatch(&world.res);
+    world.maintain();
+}
+
+pub fn visibility_linear(b: &mut Criterion) {
+    b.bench_function("visibility_linear_50k_static_500_dynamic", |b| {
+        let (mut world, mut dispatcher, dynamic) = setup(VisibilitySortingSystem::new());
+        b.iter(|| frame(&mut world, &mut dispatcher, &dynamic));
+    });
+}
+
+pub fn visibility_static_grid(b: &mut Criterion) {
+    b.bench_function("visibility_static_grid_50k_static_500_dynamic", |b| {
+        let (mut world, mut dispatcher, dynamic) =
+            setup(VisibilitySortingSystem::new().with_static_grid(32.0, 1));
+        b.iter(|| frame(&mut world, &mut dispatcher, &dynamic));
+    });
+}
+
+// Places `count` entities in front of the camera, so nearly all of them are visible, and sorts
+// them with a thread pool of `threads` threads.
+fn visibility_scaling_with(b: &mut Criterion, count: usize, threads: usize) {
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .expect("Failed to create thread pool");
+
+    let mut world = World::new();
+    world.add_resource(ScreenDimensions::new(1920, 1080, 1.0));
+    let mut builder = DispatcherBuilder::new().with_pool(Arc::new(pool));
+    TransformBundle::new()
+        .build(&mut builder)
+        .expect("Failed to add transform bundle");
+    builder.add(
+        VisibilitySortingSystem::new(),
+        "visibility_sorting_system",
+        &["transform_system"],
+    );
+    let mut dispatcher = builder.build();
+    dispatcher.setup(&mut world.res);
+
+    world
+        .create_entity()
+        .with(Camera::standard_3d(1920.0, 1080.0))
+        .with(Transform::default())
+        .build();
+    for i in 0..count {
+        let mut transform = Transform::default();
+        transform.set_translation_xyz(
+            (i % 100) as f32 * 0.1 - 5.0,
+            (i / 100 % 100) as f32 * 0.1 - 5.0,
+            -20.0 - (i / 10_000) as f32,
+        );
+        world.create_entity().with(transform).build();
+    }
+    // Transforms don't change after the first frame
+    dispatcher.dispatch(&world.res);
+
+    b.bench_function(
+        &format!("visibility_{}_entities_{}_threads", count, threads),
+        move |b| b.iter(|| dispatcher.dispatch(&world.res)),
+    );
+}
+
+pub fn visibility_scaling(b: &mut Criterion) {
+    let cores = rayon::current_num_threads();
+    for &count in &[5_000, 20_000, 100_000] {
+        for &threads in &[1, cores] {
+            visibility_scaling_with(b, count, threads);
+        }
+    }
+}
+
+criterion_group!(
+    visibility,
+    visibility_linear,
+    visibility_static_grid,
+    visibility_scaling
+);
+criterion_main!(visibility);
@@ -13,6 +13,7 @@ layout(location = 0) in vec3 position_a;
 layout(location = 1) in vec4 color_a;
 layout(location = 2) in vec3 position_b;
 layout(location = 3) in vec4 color_b;
+layout(location = 4) in float line_width;
 
 const mat2 dir_mats[2] = mat2[](
     mat2(0.0, 1.0, -1.0, 0.0),
@@ -24,7 +25,10 @@ layout(location = 0) out VertexData {
 } vertex;
 
 void main() {
-    float factor = float((gl_VertexIndex & 2) >> 1);
+    // Lines up to one pixel wide are drawn as a line list, two vertices per line,
+    // wider lines as a triangle strip, four vertices per line.
+    bool thin = line_width <= 1.0;
+    float factor = thin ? float(gl_VertexIndex & 1) : float((gl_VertexIndex & 2) >> 1);
     vertex.color = mix(color_a, color_b, factor);
 
     mat4 proj_view = proj * view;
@@ -39,12 +43,16 @@ void main() {
         float coef = -proj_current.w / clip_space_dir.z;
         vec3 intersect_pos = proj_current.xyw + (clip_space_dir * coef);
         gl_Position = vec4(intersect_pos.x, intersect_pos.y, 0, intersect_pos.z);
+    } else if (thin) {
+        gl_Position = proj_current;
     } else {
         vec2 screen_a = projected_a.xy / projected_a.w;
         vec2 screen_b = projected_b.xy / projected_b.w;
         vec2 dir = normalize(screen_b - screen_a);
         vec2 normal = dir * dir_mats[gl_VertexIndex & 1];
-        normal *= proj_current.w * screen_space_thickness;
-        gl_Position = proj_current + vec4(normal, 0.0, 0.0);
+        // Extend the quad by half its width past both ends, to close the gaps at joints
+        vec2 extend = dir * (factor * 2.0 - 1.0);
+        vec2 offset = (normal + extend) * proj_current.w * screen_space_thickness * line_width;
+        gl_Position = proj_current + vec4(offset, 0.0, 0.0);
     }
 }
@@ -0,0 +1,36 @@
+#version 450
+
+layout(std140, set = 0, binding = 0) uniform ViewArgs {
+    uniform mat4 proj;
+    uniform mat4 view;
+};
+
+layout(location = 0) in vec3 position_a;
+layout(location = 1) in vec4 color_a;
+layout(location = 2) in vec3 position_b;
+layout(location = 3) in vec4 color_b;
+layout(location = 4) in float line_width;
+
+layout(location = 0) out vec4 color;
+layout(location = 1) out float width;
+
+void main() {
+    // Lines are drawn as a line list, two vertices per line, and expanded to quads by
+    // `debug_lines.geom`.
+    float factor = float(gl_VertexIndex & 1);
+    color = mix(color_a, color_b, factor);
+    width = line_width;
+
+    mat4 proj_view = proj * view;
+    vec4 projected_a = proj_view * vec4(position_a, 1.0);
+    vec4 projected_b = proj_view * vec4(position_b, 1.0);
+    vec4 proj_current = mix(projected_a, projected_b, factor);
+    vec4 proj_next = mix(projected_b, projected_a, factor);
+
+    // Move vertices behind the camera clip plane onto it
+    vec3 clip_space_dir = normalize(proj_current.xyw - proj_next.xyw);
+    float coef = -proj_current.w / clip_space_dir.z;
+    vec3 intersect_pos = proj_current.xyw + (clip_space_dir * coef);
+    vec4 clipped = vec4(intersect_pos.x, intersect_pos.y, 0, intersect_pos.z);
+    gl_Position = proj_current.w < 0 ? clipped : proj_current;
+}
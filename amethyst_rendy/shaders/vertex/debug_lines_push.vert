@@ -0,0 +1,58 @@
+#version 450
+
+layout(std140, set = 0, binding = 0) uniform ViewArgs {
+    uniform mat4 proj;
+    uniform mat4 view;
+};
+
+layout(push_constant) uniform DebugLinesArgs {
+    vec2 screen_space_thickness;
+};
+
+layout(location = 0) in vec3 position_a;
+layout(location = 1) in vec4 color_a;
+layout(location = 2) in vec3 position_b;
+layout(location = 3) in vec4 color_b;
+layout(location = 4) in float line_width;
+
+const mat2 dir_mats[2] = mat2[](
+    mat2(0.0, 1.0, -1.0, 0.0),
+    mat2(0.0, -1.0, 1.0, 0.0)
+);
+
+layout(location = 0) out VertexData {
+    vec4 color;
+} vertex;
+
+void main() {
+    // Lines up to one pixel wide are drawn as a line list, two vertices per line,
+    // wider lines as a triangle strip, four vertices per line.
+    bool thin = line_width <= 1.0;
+    float factor = thin ? float(gl_VertexIndex & 1) : float((gl_VertexIndex & 2) >> 1);
+    vertex.color = mix(color_a, color_b, factor);
+
+    mat4 proj_view = proj * view;
+    vec4 projected_a = proj_view * vec4(position_a, 1.0);
+    vec4 projected_b = proj_view * vec4(position_b, 1.0);
+    vec4 proj_current = mix(projected_a, projected_b, factor);
+
+    if (proj_current.w < 0) {
+        // vertex behind camera clip plane
+        vec4 proj_next = mix(projected_b, projected_a, factor);
+        vec3 clip_space_dir =  normalize(proj_current.xyw - proj_next.xyw);
+        float coef = -proj_current.w / clip_space_dir.z;
+        vec3 intersect_pos = proj_current.xyw + (clip_space_dir * coef);
+        gl_Position = vec4(intersect_pos.x, intersect_pos.y, 0, intersect_pos.z);
+    } else if (thin) {
+        gl_Position = proj_current;
+    } else {
+        vec2 screen_a = projected_a.xy / projected_a.w;
+        vec2 screen_b = projected_b.xy / projected_b.w;
+        vec2 dir = normalize(screen_b - screen_a);
+        vec2 normal = dir * dir_mats[gl_VertexIndex & 1];
+        // Extend the quad by half its width past both ends, to close the gaps at joints
+        vec2 extend = dir * (factor * 2.0 - 1.0);
+        vec2 offset = (normal + extend) * proj_current.w * screen_space_thickness * line_width;
+        gl_Position = proj_current + vec4(offset, 0.0, 0.0);
+    }
+}
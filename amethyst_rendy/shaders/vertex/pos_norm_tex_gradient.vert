@@ -0,0 +1,32 @@
+#version 450
+
+layout(std140, set = 0, binding = 0) uniform Projview {
+    mat4 proj;
+    mat4 view;
+};
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 tex_coord;
+layout(location = 3) in mat4 model; // instance rate
+layout(location = 7) in vec4 tint; // instance rate
+layout(location = 8) in vec4 gradient_start; // instance rate
+layout(location = 9) in vec4 gradient_end; // instance rate
+layout(location = 10) in vec4 gradient_axis; // instance rate, xyz scaled by 1 / length, w the start offset
+
+layout(location = 0) out VertexData {
+    vec3 position;
+    vec3 normal;
+    vec2 tex_coord;
+    vec4 color;
+} vertex;
+
+void main() {
+    vec4 vertex_position = model * vec4(position, 1.0);
+    vertex.position = vertex_position.xyz;
+    vertex.normal = mat3(model) * normal;
+    vertex.tex_coord = tex_coord;
+    float gradient = clamp(dot(position, gradient_axis.xyz) + gradient_axis.w, 0.0, 1.0);
+    vertex.color = tint * mix(gradient_start, gradient_end, gradient);
+    gl_Position = proj * view * vertex_position;
+}
@@ -0,0 +1,31 @@
+#version 450
+
+layout(std140, set = 0, binding = 0) uniform Projview {
+    mat4 proj;
+    mat4 view;
+};
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 tex_coord;
+layout(location = 2) in mat4 model; // instance rate
+layout(location = 6) in vec4 tint; // instance rate
+layout(location = 7) in vec4 dissolve_edge_color; // instance rate
+layout(location = 8) in vec2 dissolve; // instance rate, amount and edge width
+
+layout(location = 0) out VertexData {
+    vec3 position;
+    vec2 tex_coord;
+    vec4 color;
+} vertex;
+layout(location = 8) out vec4 out_dissolve_edge_color;
+layout(location = 9) out vec2 out_dissolve;
+
+void main() {
+    vec4 vertex_position = model * vec4(position, 1.0);
+    vertex.position = vertex_position.xyz;
+    vertex.tex_coord = tex_coord;
+    vertex.color = tint;
+    gl_Position = proj * view * vertex_position;
+    out_dissolve_edge_color = dissolve_edge_color;
+    out_dissolve = dissolve;
+}
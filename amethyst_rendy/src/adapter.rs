@@ -0,0 +1,270 @@
+//! Enumeration and selection of the GPU adapter the renderer runs on.
+//!
+//! The `RenderingSystem` picks the adapter of the factory according to its `AdapterSelection`,
+//! preferring discrete GPUs by default. Once it is set up, the `Adapters` resource describes all
+//! the adapters found and the one in use, for settings screens and bug reports.
+use crate::rendy::{
+    factory::DevicesConfigure,
+    hal::{
+        adapter::{Adapter, DeviceType, PhysicalDevice},
+        Backend,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+/// Kind of an adapter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AdapterKind {
+    /// A GPU on its own card.
+    Discrete,
+    /// A GPU built into the CPU.
+    Integrated,
+    /// A GPU of a virtual machine.
+    Virtual,
+    /// Rendering on the CPU.
+    Cpu,
+    /// Anything else.
+    Other,
+}
+
+impl From<DeviceType> for AdapterKind {
+    fn from(device_type: DeviceType) -> Self {
+        match device_type {
+            DeviceType::DiscreteGpu => AdapterKind::Discrete,
+            DeviceType::IntegratedGpu => AdapterKind::Integrated,
+            DeviceType::VirtualGpu => AdapterKind::Virtual,
+            DeviceType::Cpu => AdapterKind::Cpu,
+            _ => AdapterKind::Other,
+        }
+    }
+}
+
+/// An adapter found on the system.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdapterDescription {
+    /// Position of the adapter in the enumeration, as used by `AdapterSelection::Index`.
+    pub index: usize,
+    /// Name of the adapter given by the driver.
+    pub name: String,
+    /// PCI vendor id.
+    pub vendor: usize,
+    /// PCI device id.
+    pub device: usize,
+    /// Kind of the adapter.
+    pub kind: AdapterKind,
+    /// Size in bytes of each memory heap of the adapter.
+    pub heap_sizes: Vec<u64>,
+}
+
+impl AdapterDescription {
+    fn new<B: Backend>(index: usize, adapter: &Adapter<B>) -> Self {
+        Self {
+            index,
+            name: adapter.info.name.clone(),
+            vendor: adapter.info.vendor,
+            device: adapter.info.device,
+            kind: adapter.info.device_type.clone().into(),
+            heap_sizes: adapter.physical_device.memory_properties().memory_heaps,
+        }
+    }
+}
+
+impl fmt::Display for AdapterDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ({:?}, vendor {:#06x}, device {:#06x}, {} MiB)",
+            self.name,
+            self.kind,
+            self.vendor,
+            self.device,
+            self.heap_sizes.iter().sum::<u64>() / (1024 * 1024)
+        )
+    }
+}
+
+/// Which adapter the `RenderingSystem` renders with.
+///
+/// An index or name matching no adapter logs a warning and falls back to `PreferDiscrete`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdapterSelection {
+    /// A discrete GPU, then an integrated, virtual and CPU one.
+    PreferDiscrete,
+    /// An integrated GPU, then a discrete, virtual and CPU one.
+    PreferIntegrated,
+    /// The adapter of `AdapterDescription::index`.
+    Index(usize),
+    /// The first adapter whose name contains this text, ignoring case.
+    Name(String),
+}
+
+impl Default for AdapterSelection {
+    fn default() -> Self {
+        AdapterSelection::PreferDiscrete
+    }
+}
+
+impl AdapterSelection {
+    /// Position in `adapters` of the adapter to use, which must not be empty.
+    pub fn pick(&self, adapters: &[AdapterDescription]) -> usize {
+        assert!(!adapters.is_empty(), "No adapters found");
+        let by_kind = |order: &[AdapterKind]| {
+            adapters
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, adapter)| {
+                    order
+                        .iter()
+                        .position(|&kind| kind == adapter.kind)
+                        .unwrap_or(order.len())
+                })
+                .map_or(0, |(i, _)| i)
+        };
+        let discrete = [
+            AdapterKind::Discrete,
+            AdapterKind::Integrated,
+            AdapterKind::Virtual,
+            AdapterKind::Cpu,
+        ];
+        match self {
+            AdapterSelection::PreferDiscrete => by_kind(&discrete),
+            AdapterSelection::PreferIntegrated => by_kind(&[
+                AdapterKind::Integrated,
+                AdapterKind::Discrete,
+                AdapterKind::Virtual,
+                AdapterKind::Cpu,
+            ]),
+            AdapterSelection::Index(index) => {
+                match adapters.iter().position(|adapter| adapter.index == *index) {
+                    Some(i) => i,
+                    None => {
+                        log::warn!("There is no adapter {}, preferring a discrete GPU", index);
+                        by_kind(&discrete)
+                    }
+                }
+            }
+            AdapterSelection::Name(name) => {
+                let name = name.to_lowercase();
+                match adapters
+                    .iter()
+                    .position(|adapter| adapter.name.to_lowercase().contains(&name))
+                {
+                    Some(i) => i,
+                    None => {
+                        log::warn!(
+                            "No adapter is named like {:?}, preferring a discrete GPU",
+                            name
+                        );
+                        by_kind(&discrete)
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Resource with the adapters found when the `RenderingSystem` was set up and the one it uses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Adapters {
+    available: Vec<AdapterDescription>,
+    selected: usize,
+}
+
+impl Adapters {
+    /// All the adapters found.
+    pub fn available(&self) -> &[AdapterDescription] {
+        &self.available
+    }
+
+    /// The adapter the renderer uses.
+    pub fn selected(&self) -> &AdapterDescription {
+        &self.available[self.selected]
+    }
+}
+
+/// Picks the adapter of the factory, remembering the adapters it was given.
+#[derive(Debug, Clone)]
+pub(crate) struct SelectAdapter {
+    selection: AdapterSelection,
+    adapters: Arc<Mutex<Option<Adapters>>>,
+}
+
+impl SelectAdapter {
+    pub(crate) fn new(selection: AdapterSelection) -> Self {
+        Self {
+            selection,
+            adapters: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The adapters found by a clone of this, once it created the factory.
+    pub(crate) fn adapters(&self) -> Option<Adapters> {
+        self.adapters.lock().unwrap().take()
+    }
+}
+
+impl DevicesConfigure for SelectAdapter {
+    fn pick<B: Backend>(&self, adapters: &[Adapter<B>]) -> usize {
+        let available: Vec<_> = adapters
+            .iter()
+            .enumerate()
+            .map(|(index, adapter)| AdapterDescription::new(index, adapter))
+            .collect();
+        for adapter in &available {
+            log::debug!("Found adapter {}: {}", adapter.index, adapter);
+        }
+        let selected = self.selection.pick(&available);
+        log::info!("Rendering with {}", available[selected]);
+        *self.adapters.lock().unwrap() = Some(Adapters {
+            available,
+            selected,
+        });
+        selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn adapter(index: usize, name: &str, kind: AdapterKind) -> AdapterDescription {
+        AdapterDescription {
+            index,
+            name: name.to_string(),
+            vendor: 0,
+            device: 0,
+            kind,
+            heap_sizes: vec![],
+        }
+    }
+
+    #[test]
+    fn adapter_selection() {
+        let adapters = [
+            adapter(0, "llvmpipe", AdapterKind::Cpu),
+            adapter(1, "Intel UHD Graphics 630", AdapterKind::Integrated),
+            adapter(2, "NVIDIA GeForce GTX 1060", AdapterKind::Discrete),
+        ];
+        assert_eq!(AdapterSelection::PreferDiscrete.pick(&adapters), 2);
+        assert_eq!(AdapterSelection::PreferIntegrated.pick(&adapters), 1);
+        assert_eq!(AdapterSelection::Index(0).pick(&adapters), 0);
+        assert_eq!(AdapterSelection::Index(3).pick(&adapters), 2);
+        assert_eq!(
+            AdapterSelection::Name("geforce".to_string()).pick(&adapters),
+            2
+        );
+        assert_eq!(
+            AdapterSelection::Name("intel".to_string()).pick(&adapters),
+            1
+        );
+        assert_eq!(
+            AdapterSelection::Name("radeon".to_string()).pick(&adapters),
+            2
+        );
+        assert_eq!(AdapterSelection::PreferIntegrated.pick(&adapters[..1]), 0);
+    }
+}
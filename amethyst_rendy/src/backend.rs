@@ -0,0 +1,244 @@
+//! Choosing the backend the renderer runs on at startup.
+//!
+//! The backends compiled in are the variants of `BackendVariant`, enabled by the cargo features
+//! of the same names. A binary built with several of them picks one at startup with
+//! `select_backend`, which starts them in the order of a `BackendSelection` and takes the first
+//! that has an adapter, preferring Vulkan over Metal by default. The empty backend renders
+//! nothing, so it's only chosen when selected explicitly. The backend is fixed once chosen.
+//!
+//! The types of the renderer are generic over the backend, which user code names once through a
+//! `BackendVisitor`, run with the chosen backend by `BackendVariant::dispatch`. `Mesh` and
+//! `Texture` are enums over the backends already. `RuntimeRenderingBundle` is a
+//! `RenderingBundle` for the chosen backend, whose plugins are added by a `ConfigureRendering`:
+//!
+//! ```ignore
+//! struct Plugins(DisplayConfig);
+//!
+//! impl ConfigureRendering for Plugins {
+//!     fn configure<B: Backend>(self, bundle: RenderingBundle<B>) -> RenderingBundle<B> {
+//!         bundle
+//!             .with_plugin(RenderToWindow::from_config(self.0))
+//!             .with_plugin(RenderFlat2D::default())
+//!     }
+//! }
+//!
+//! let backend = select_backend(&BackendSelection::default())?;
+//! let game_data = GameDataBuilder::default()
+//!     .with_bundle(RuntimeRenderingBundle::new(backend, Plugins(display_config)))?;
+//! ```
+//!
+//! Once set up, the `RenderingSystem` inserts its `BackendVariant` as a resource.
+use crate::{
+    bundle::RenderingBundle,
+    error::BackendError,
+    rendy::factory::{Factory, Families},
+    types::{Backend, BackendVariant, BackendVisitor},
+};
+use amethyst_core::{bundle::SystemBundle, ecs::DispatcherBuilder};
+use amethyst_error::Error;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    str::FromStr,
+};
+
+impl BackendVariant {
+    /// Whether the backend draws anything, which the empty backend doesn't.
+    pub fn renders(self) -> bool {
+        #[cfg(feature = "empty")]
+        {
+            if self == BackendVariant::Empty {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Whether the backend starts on this machine, which needs an adapter it can run on.
+    pub fn starts(self) -> bool {
+        self.dispatch(Probe).is_ok()
+    }
+}
+
+impl fmt::Display for BackendVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for BackendVariant {
+    type Err = BackendError;
+
+    /// The backend compiled in named `name`, ignoring case.
+    fn from_str(name: &str) -> Result<Self, BackendError> {
+        BackendVariant::compiled()
+            .into_iter()
+            .find(|backend| backend.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| BackendError::Unknown(name.to_string()))
+    }
+}
+
+/// Which backends `select_backend` tries, in order.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackendSelection {
+    /// The backends tried, from the most preferred one.
+    pub preference: Vec<BackendVariant>,
+}
+
+impl Default for BackendSelection {
+    /// The backends compiled in that render, Vulkan first.
+    fn default() -> Self {
+        Self {
+            preference: BackendVariant::compiled()
+                .into_iter()
+                .filter(|backend| backend.renders())
+                .collect(),
+        }
+    }
+}
+
+impl BackendSelection {
+    /// Try the backends named in `names`, like a setting or command line argument, in order.
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<Self, BackendError> {
+        let preference = names
+            .into_iter()
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { preference })
+    }
+}
+
+/// Starts a backend, to see whether it can run.
+struct Probe;
+
+impl BackendVisitor for Probe {
+    type Output = Result<(), String>;
+
+    fn visit<B: Backend>(self) -> Result<(), String> {
+        let config: crate::rendy::factory::Config = Default::default();
+        // Some backends panic when they can't create their instance.
+        let started = panic::catch_unwind(AssertUnwindSafe(|| {
+            let result: Result<(Factory<B>, Families<B>), _> = crate::rendy::factory::init(config);
+            result.map(|(factory, families)| {
+                drop(families);
+                drop(factory);
+            })
+        }));
+        match started {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err("the backend panicked while starting".to_string()),
+        }
+    }
+}
+
+/// The first backend of `selection` that starts.
+///
+/// Every backend tried is started and shut down again, the `RenderingSystem` then starts the
+/// chosen one for good.
+pub fn select_backend(selection: &BackendSelection) -> Result<BackendVariant, BackendError> {
+    let mut tried = Vec::new();
+    for &backend in &selection.preference {
+        match backend.dispatch(Probe) {
+            Ok(()) => {
+                log::info!("Rendering with the {} backend", backend);
+                return Ok(backend);
+            }
+            Err(reason) => {
+                log::warn!("The {} backend can't be started: {}", backend, reason);
+                tried.push((backend.name().to_string(), reason));
+            }
+        }
+    }
+    Err(BackendError::Unavailable { tried })
+}
+
+/// Adds the plugins of a `RuntimeRenderingBundle` to the bundle of the chosen backend.
+pub trait ConfigureRendering {
+    /// Configure the bundle of the backend `B`.
+    fn configure<B: Backend>(self, bundle: RenderingBundle<B>) -> RenderingBundle<B>;
+}
+
+/// A `RenderingBundle` for a backend chosen at runtime, configured by a `ConfigureRendering`.
+#[derive(Debug)]
+pub struct RuntimeRenderingBundle<C> {
+    backend: BackendVariant,
+    configure: C,
+}
+
+impl<C: ConfigureRendering> RuntimeRenderingBundle<C> {
+    /// The bundle of `backend`, configured by `configure`.
+    pub fn new(backend: BackendVariant, configure: C) -> Self {
+        Self { backend, configure }
+    }
+
+    /// The backend the bundle renders with.
+    pub fn backend(&self) -> BackendVariant {
+        self.backend
+    }
+}
+
+/// Builds the configured bundle of the chosen backend.
+struct BuildBundle<'x, 'a, 'b, C> {
+    builder: &'x mut DispatcherBuilder<'a, 'b>,
+    configure: C,
+}
+
+impl<'x, 'a, 'b, C: ConfigureRendering> BackendVisitor for BuildBundle<'x, 'a, 'b, C> {
+    type Output = Result<(), Error>;
+
+    fn visit<B: Backend>(self) -> Result<(), Error> {
+        self.configure
+            .configure(RenderingBundle::<B>::new())
+            .build(self.builder)
+    }
+}
+
+impl<'a, 'b, C: ConfigureRendering> SystemBundle<'a, 'b> for RuntimeRenderingBundle<C> {
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        self.backend.dispatch(BuildBundle {
+            builder,
+            configure: self.configure,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backends_are_named_and_preferred() {
+        let compiled = BackendVariant::compiled();
+        assert!(!compiled.is_empty());
+        for backend in &compiled {
+            assert_eq!(backend.name().parse::<BackendVariant>().unwrap(), *backend);
+            assert_eq!(
+                backend
+                    .name()
+                    .to_uppercase()
+                    .parse::<BackendVariant>()
+                    .unwrap(),
+                *backend
+            );
+        }
+        assert_eq!(
+            "dx9".parse::<BackendVariant>().unwrap_err(),
+            BackendError::Unknown("dx9".to_string())
+        );
+
+        let selection = BackendSelection::default();
+        assert!(selection.preference.iter().all(|backend| backend.renders()));
+        let names: Vec<_> = compiled.iter().map(|backend| backend.name()).collect();
+        assert_eq!(
+            BackendSelection::from_names(names).unwrap().preference,
+            compiled
+        );
+
+        assert_eq!(
+            select_backend(&BackendSelection { preference: vec![] }).unwrap_err(),
+            BackendError::Unavailable { tried: vec![] }
+        );
+    }
+}
@@ -78,6 +78,9 @@ where
 ///
 /// Internally, this batch type is implemented using a `FnvHashMap` for its outer primary batching
 /// layer. The inner layer is then implemented as a tuple indexed `SmallVec`.
+///
+/// All the data inserted with the same pair of keys ends up in one batch, whatever the order of
+/// the inserts, so it can be drawn as one instanced draw.
 #[derive(Derivative, Debug)]
 #[derivative(Default(bound = ""))]
 pub struct TwoLevelBatch<PK, SK, C>
@@ -109,6 +112,24 @@ where
         self.map.retain(|_, b| !b.is_empty());
     }
 
+    /// Clears all batch data and indices, so the batches inserted next are iterated in an order
+    /// depending on these inserts only. The indices are freed, as the order also depends on the
+    /// capacity of the map.
+    pub fn clear(&mut self) {
+        self.data_count = 0;
+        self.map = Default::default();
+    }
+
+    /// Clears the batch for the next frame, with `clear` in deterministic mode and `clear_inner`
+    /// otherwise.
+    pub fn reset(&mut self, deterministic: bool) {
+        if deterministic {
+            self.clear();
+        } else {
+            self.clear_inner();
+        }
+    }
+
     /// Inserts a set of batch items.
     pub fn insert(&mut self, pk: PK, sk: SK, data: impl IntoIterator<Item = C::Item>) {
         #[cfg(feature = "profiler")]
@@ -119,9 +140,9 @@ where
         match self.map.entry(pk) {
             Entry::Occupied(mut e) => {
                 let e = e.get_mut();
-                // scan for the same key to try to combine batches.
-                // Scanning limited slots to limit complexity.
-                if let Some(batch) = e.iter_mut().take(8).find(|(k, _)| k == &sk) {
+                // scan for the same key to combine batches, the most recent one first since
+                // inserts of the same key tend to follow each other.
+                if let Some(batch) = e.iter_mut().rev().find(|(k, _)| k == &sk) {
                     batch.1.extend(instance_data);
                 } else {
                     e.push((sk, instance_data.collect()));
@@ -273,6 +294,24 @@ where
         self.map.retain(|_, b| !b.is_empty());
     }
 
+    /// Clears all data and indices, so the batches inserted next are iterated in an order
+    /// depending on these inserts only. The indices are freed, as the order also depends on the
+    /// capacity of the map.
+    pub fn clear(&mut self) {
+        self.data_count = 0;
+        self.map = Default::default();
+    }
+
+    /// Clears the batch for the next frame, with `clear` in deterministic mode and `clear_inner`
+    /// otherwise.
+    pub fn reset(&mut self, deterministic: bool) {
+        if deterministic {
+            self.clear();
+        } else {
+            self.clear_inner();
+        }
+    }
+
     /// Inserts the provided set of batch data for `PK`
     pub fn insert(&mut self, pk: PK, data: impl IntoIterator<Item = D>) {
         #[cfg(feature = "profiler")]
@@ -394,6 +433,24 @@ where
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_twolevel_batch_merges_all_inserts() {
+        let mut batch = TwoLevelBatch::<u32, u32, Vec<u32>>::default();
+        for i in 0..40 {
+            batch.insert(0, i % 10, Some(i));
+        }
+        batch.insert(1, 0, Some(40));
+        assert_eq!(batch.count(), 41);
+        let mut batches = batch
+            .iter()
+            .flat_map(|(pk, b)| b.map(move |(sk, data)| (*pk, *sk, data.len())))
+            .collect::<Vec<_>>();
+        batches.sort();
+        assert_eq!(batches.len(), 11);
+        assert_eq!(batches[9], (0, 9, 4));
+        assert_eq!(batches[10], (1, 0, 1));
+    }
+
     #[test]
     fn test_ordered_onelevel_batch_single_insert() {
         let mut batch = OrderedOneLevelBatch::<u32, u32>::default();
@@ -0,0 +1,1123 @@
+//! A render graph assembled from plugins, as an alternative to implementing `GraphCreator`.
+//!
+//! Plugins like `RenderFlat2D` or `RenderPbr3D` add their systems to the dispatcher and their
+//! render groups to the `RenderPlan`, and a target plugin like `RenderToWindow` says where the
+//! frames go. The `RenderingBundle` creates the color and depth images, orders the groups and
+//! rebuilds the graph whenever a plugin asks for it:
+//!
+//! ```rust,ignore
+//! let game_data = GameDataBuilder::default()
+//!     .with_bundle(WindowBundle::from_config_path(display_config_path))?
+//!     .with_bundle(TransformBundle::new())?
+//!     .with_bundle(
+//!         RenderingBundle::<DefaultBackend>::new()
+//!             .with_plugin(RenderToWindow::new().with_clear([0.34, 0.36, 0.52, 1.0]))
+//!             .with_plugin(RenderFlat2D::default()),
+//!     )?;
+//! ```
+use crate::{
+    adapter::AdapterSelection,
+    capture::CaptureDesc,
+    clear::{ClearDesc, DEFAULT_CLEAR_DEPTH},
+    compute::ComputeNodeDesc,
+    debug_drawing::DebugLinesSystem,
+    depth::{has_stencil, negotiate_depth_format, DepthImage, DEFAULT_DEPTH_FORMAT},
+    error::RenderPlanError,
+    exposure::{luminance_node, AutoExposureSystem},
+    fade_in::AssetReadinessSystem,
+    indirect::IndirectSupport,
+    pass::{
+        Base3DPassDef, DrawBase3DDesc, DrawBase3DTransparentDesc, DrawDebugLinesDesc,
+        DrawFlat2DDesc, DrawFlat2DTransparentDesc, DrawSkyboxDesc, FlatPassDef, PbrPassDef,
+        ShadedPassDef,
+    },
+    presentation::present_builder,
+    rendy::{
+        factory::Factory,
+        graph::{
+            render::{RenderGroupDesc, SubpassBuilder},
+            BufferId, GraphBuilder, ImageId, NodeDesc, NodeId,
+        },
+        hal::{
+            command::{ClearDepthStencil, ClearValue},
+            format::Format,
+            image::Kind,
+        },
+        wsi::Surface,
+    },
+    sprite::SpriteSheet,
+    sprite_visibility::SpriteVisibilitySortingSystem,
+    static_instances::{
+        static_culling_node, StaticCulling, StaticInstanceSet, StaticInstancesSystem,
+    },
+    system::{GraphCreator, RenderingSystem},
+    timing::{
+        group_name, timestamps_supported, GpuTimer, GpuTimingDesc, GpuTimingStats, GpuTimingStatus,
+        TimedGroupDesc,
+    },
+    transparent::TintTransparency,
+    types::Backend,
+    validation::{LabeledGroupDesc, PassMarkerDesc, ValidationConfig},
+    view::ViewDesc,
+    visibility::VisibilitySortingSystem,
+};
+use amethyst_assets::Processor;
+use amethyst_core::{
+    bundle::SystemBundle,
+    ecs::{DispatcherBuilder, ReadExpect, Resources, SystemData},
+};
+use amethyst_error::Error;
+use amethyst_window::{ScreenDimensions, SecondaryWindows, Window};
+use derivative::Derivative;
+use palette::Srgb;
+use std::{marker::PhantomData, path::PathBuf};
+
+/// Adds the systems of its plugins and a `RenderingSystem` rendering the graph they plan to the
+/// dispatcher.
+///
+/// This bundle requires the user to also add the `TransformBundle` to the dispatcher, and the
+/// `WindowBundle` when rendering to a window.
+#[allow(missing_debug_implementations)]
+pub struct RenderingBundle<B: Backend> {
+    plugins: Vec<Box<dyn RenderPlugin<B>>>,
+    gpu_timing: bool,
+    pipeline_cache: Option<PathBuf>,
+    adapter: AdapterSelection,
+    depth_formats: Vec<Format>,
+    image_count: Option<u32>,
+    frames_in_flight: Option<u32>,
+    validation: ValidationConfig,
+    deterministic: bool,
+    headless: bool,
+}
+
+impl<B: Backend> Default for RenderingBundle<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: Backend> RenderingBundle<B> {
+    /// Create a bundle without plugins.
+    pub fn new() -> Self {
+        Self {
+            plugins: Vec::new(),
+            gpu_timing: false,
+            pipeline_cache: None,
+            adapter: AdapterSelection::default(),
+            depth_formats: vec![DEFAULT_DEPTH_FORMAT],
+            image_count: None,
+            frames_in_flight: None,
+            validation: ValidationConfig::default(),
+            deterministic: false,
+            headless: false,
+        }
+    }
+
+    /// Register a plugin.
+    pub fn add_plugin(&mut self, plugin: impl RenderPlugin<B> + 'static) {
+        self.plugins.push(Box::new(plugin));
+    }
+
+    /// Register a plugin, builder style.
+    pub fn with_plugin(mut self, plugin: impl RenderPlugin<B> + 'static) -> Self {
+        self.add_plugin(plugin);
+        self
+    }
+
+    /// Measure the GPU time spent drawing every render group into the `GpuTimingStats`
+    /// resource, see the `timing` module.
+    pub fn with_gpu_timing(mut self) -> Self {
+        self.gpu_timing = true;
+        self
+    }
+
+    /// Keep the compiled pipelines in the file at `path` between runs, see the `pipeline_cache`
+    /// module.
+    pub fn with_pipeline_cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pipeline_cache = Some(path.into());
+        self
+    }
+
+    /// Render with the adapter chosen by `selection`, see the `adapter` module.
+    pub fn with_adapter(mut self, selection: AdapterSelection) -> Self {
+        self.adapter = selection;
+        self
+    }
+
+    /// Use the first of `formats` supported by the adapter for the depth images, see the `depth`
+    /// module. `D32Sfloat` is used by default.
+    pub fn with_depth_formats(mut self, formats: impl IntoIterator<Item = Format>) -> Self {
+        self.depth_formats = formats.into_iter().collect();
+        self
+    }
+
+    /// Present with `image_count` swapchain images, see the `presentation` module.
+    pub fn with_image_count(mut self, image_count: u32) -> Self {
+        self.image_count = Some(image_count);
+        self
+    }
+
+    /// Keep at most `frames_in_flight` frames in flight, see the `presentation` module.
+    pub fn with_frames_in_flight(mut self, frames_in_flight: u32) -> Self {
+        self.frames_in_flight = Some(frames_in_flight);
+        self
+    }
+
+    /// Enable the validation layer or label the passes and render groups with debug markers,
+    /// see the `validation` module.
+    pub fn with_validation(mut self, config: ValidationConfig) -> Self {
+        self.validation = config;
+        self
+    }
+
+    /// Draw the same frames for the same world in every run, for golden-image tests and replay
+    /// captures, see the `deterministic` module.
+    pub fn with_deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    /// Run the systems of the plugins without a device nor graph, like on a dedicated server.
+    /// See `RenderingSystem::with_headless`.
+    pub fn with_headless(mut self) -> Self {
+        self.headless = true;
+        self
+    }
+}
+
+impl<'a, 'b, B: Backend> SystemBundle<'a, 'b> for RenderingBundle<B> {
+    fn build(mut self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(AssetReadinessSystem::new(), "asset_readiness_system", &[]);
+        for plugin in &mut self.plugins {
+            plugin.on_build(builder)?;
+        }
+        let mut system = RenderingSystem::<B, _>::new(PluginGraph {
+            plugins: self.plugins,
+            gpu_timing: self.gpu_timing,
+            depth_formats: self.depth_formats,
+        })
+        .with_adapter(self.adapter)
+        .with_validation(self.validation);
+        if let Some(path) = self.pipeline_cache {
+            system = system.with_pipeline_cache(path);
+        }
+        if let Some(image_count) = self.image_count {
+            system = system.with_image_count(image_count);
+        }
+        if let Some(frames_in_flight) = self.frames_in_flight {
+            system = system.with_frames_in_flight(frames_in_flight);
+        }
+        if self.deterministic {
+            system = system.with_deterministic();
+        }
+        if self.headless {
+            system = system.with_headless();
+        }
+        builder.add_thread_local(system);
+        Ok(())
+    }
+}
+
+/// A part of the rendering of a `RenderingBundle`.
+pub trait RenderPlugin<B: Backend>: std::fmt::Debug {
+    /// Add the systems needed by this plugin to the dispatcher.
+    fn on_build<'a, 'b>(&mut self, _builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Check if the graph needs to be planned again. Evaluated every frame for every plugin.
+    fn should_rebuild(&mut self, _res: &Resources) -> bool {
+        false
+    }
+
+    /// Add the render groups or the target of this plugin to the plan of the graph.
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        factory: &mut Factory<B>,
+        res: &Resources,
+    ) -> Result<(), Error>;
+}
+
+/// Order of the render groups of a `RenderPlan`, drawn from the lowest to the highest.
+///
+/// Groups of custom plugins can use any `i32`, like `RenderOrder::Opaque as i32 + 1` to draw
+/// right after the opaque groups. Groups of the same order are drawn in the order their plugins
+/// were registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RenderOrder {
+    /// Before opaque geometry.
+    BeforeOpaque = 90,
+    /// Opaque geometry.
+    Opaque = 100,
+    /// After opaque geometry, like the skybox.
+    AfterOpaque = 110,
+    /// Before transparent geometry, like debug lines.
+    BeforeTransparent = 190,
+    /// Transparent geometry, sorted back to front.
+    Transparent = 200,
+    /// After transparent geometry.
+    AfterTransparent = 210,
+    /// Overlays drawn above everything else.
+    Overlay = 300,
+}
+
+impl From<RenderOrder> for i32 {
+    fn from(order: RenderOrder) -> i32 {
+        order as i32
+    }
+}
+
+/// Where the color image of a `RenderPlan` goes once drawn.
+#[derive(Debug)]
+pub enum TargetOutput<B: Backend> {
+    /// Presented to a window surface, with the mode of the `PresentationConfig` resource.
+    Surface(Surface<B>),
+    /// Kept offscreen and read back on request with the `FrameCapture` resource.
+    Capture,
+}
+
+/// The images drawn by a `RenderPlan`.
+#[derive(Debug)]
+pub struct RenderTarget<B: Backend> {
+    /// Size of the images.
+    pub kind: Kind,
+    /// Format of the color image.
+    pub format: Format,
+    /// Color the image is cleared to every frame, unless the `ClearConfig` resource sets one.
+    pub clear: [f32; 4],
+    /// Where the color image goes.
+    pub output: TargetOutput<B>,
+}
+
+type AddGroup<B> = Box<
+    dyn Fn(
+        SubpassBuilder<B, Resources>,
+        Option<(GpuTimer<B>, usize)>,
+        Option<String>,
+    ) -> SubpassBuilder<B, Resources>,
+>;
+
+/// The color image of a target of a `RenderPlan`, given to the nodes of
+/// `RenderPlan::add_color_node`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorImage {
+    /// Name of the secondary window of the target, or `None` for the main target.
+    pub window: Option<String>,
+    /// The color image.
+    pub image: ImageId,
+    /// Size of the image.
+    pub kind: Kind,
+    /// Format of the image.
+    pub format: Format,
+    /// The pass drawing the render groups into the image, which nodes reading it depend on.
+    pub pass: NodeId,
+}
+
+type AddDepthNode<B> = Box<dyn Fn(&mut GraphBuilder<B, Resources>, &DepthImage)>;
+
+type AddColorNode<B> = Box<dyn Fn(&mut GraphBuilder<B, Resources>, &ColorImage)>;
+
+/// Render groups and targets of the graph, planned by the plugins of a `RenderingBundle`.
+///
+/// All groups are drawn into the main target and into every secondary window target, each with
+/// its own images and camera, see the `view` module.
+#[allow(missing_debug_implementations)]
+pub struct RenderPlan<B: Backend> {
+    graph_builder: GraphBuilder<B, Resources>,
+    target: Option<RenderTarget<B>>,
+    windows: Vec<(String, RenderTarget<B>)>,
+    groups: Vec<(i32, String, AddGroup<B>)>,
+    compute: Vec<(i32, ComputeNodeDesc)>,
+    depth_nodes: Vec<AddDepthNode<B>>,
+    color_nodes: Vec<AddColorNode<B>>,
+    depth_format: Format,
+    gpu_timing: bool,
+    markers: bool,
+}
+
+impl<B: Backend> RenderPlan<B> {
+    fn new(gpu_timing: bool, depth_format: Format, markers: bool) -> Self {
+        Self {
+            graph_builder: GraphBuilder::new(),
+            target: None,
+            windows: Vec::new(),
+            groups: Vec::new(),
+            compute: Vec::new(),
+            depth_nodes: Vec::new(),
+            color_nodes: Vec::new(),
+            depth_format,
+            gpu_timing,
+            markers,
+        }
+    }
+
+    /// Format of the depth images of the targets, negotiated with the adapter.
+    pub fn depth_format(&self) -> Format {
+        self.depth_format
+    }
+
+    /// Fail if the depth images have no stencil aspect, for plugins drawing with the stencil.
+    pub fn require_stencil(&self) -> Result<(), Error> {
+        if has_stencil(self.depth_format) {
+            Ok(())
+        } else {
+            Err(RenderPlanError::NoStencil(self.depth_format).into())
+        }
+    }
+
+    /// Add nodes using the depth image of every target, once its render groups are drawn.
+    pub fn add_depth_node<F>(&mut self, add_node: F)
+    where
+        F: Fn(&mut GraphBuilder<B, Resources>, &DepthImage) + 'static,
+    {
+        self.depth_nodes.push(Box::new(add_node));
+    }
+
+    /// Add nodes using the color image of every target, once its render groups are drawn.
+    pub fn add_color_node<F>(&mut self, add_node: F)
+    where
+        F: Fn(&mut GraphBuilder<B, Resources>, &ColorImage) + 'static,
+    {
+        self.color_nodes.push(Box::new(add_node));
+    }
+
+    /// Set where the graph renders to. Only one plugin may set the target.
+    pub fn set_target(&mut self, target: RenderTarget<B>) -> Result<(), Error> {
+        if self.target.is_some() {
+            return Err(RenderPlanError::TargetAlreadySet.into());
+        }
+        self.target = Some(target);
+        Ok(())
+    }
+
+    /// The target set by a plugin, if any.
+    pub fn target(&self) -> Option<&RenderTarget<B>> {
+        self.target.as_ref()
+    }
+
+    /// Also render to the secondary window of the given name, with its camera of the
+    /// `WindowCameras` resource.
+    pub fn add_window_target(&mut self, window: impl Into<String>, target: RenderTarget<B>) {
+        self.windows.push((window.into(), target));
+    }
+
+    /// Draw a render group at the given order into the color and depth images of the targets.
+    pub fn add_group<G>(&mut self, order: impl Into<i32>, group: G)
+    where
+        G: RenderGroupDesc<B, Resources> + Clone + 'static,
+    {
+        self.add_group_with_buffers(order, group, Vec::new());
+    }
+
+    /// Draw a render group using buffers of the graph, like indirect commands written by a
+    /// compute node, in the order of its `RenderGroupDesc::buffers`.
+    pub fn add_group_with_buffers<G>(
+        &mut self,
+        order: impl Into<i32>,
+        group: G,
+        buffers: Vec<BufferId>,
+    ) where
+        G: RenderGroupDesc<B, Resources> + Clone + 'static,
+    {
+        let name = group_name(&group);
+        self.groups.push((
+            order.into(),
+            name,
+            Box::new(
+                move |subpass: SubpassBuilder<B, Resources>, timer, label| match timer {
+                    Some((timer, index)) => {
+                        let group = LabeledGroupDesc::new(group.clone(), label);
+                        let mut builder = TimedGroupDesc::new(group, timer, index).builder();
+                        for &buffer in &buffers {
+                            builder = builder.with_buffer(buffer);
+                        }
+                        subpass.with_group(builder)
+                    }
+                    None => {
+                        let mut builder = LabeledGroupDesc::new(group.clone(), label).builder();
+                        for &buffer in &buffers {
+                            builder = builder.with_buffer(buffer);
+                        }
+                        subpass.with_group(builder)
+                    }
+                },
+            ),
+        ));
+    }
+
+    /// Create a buffer of the graph of `size` bytes, for compute nodes and render groups.
+    pub fn create_buffer(&mut self, size: u64) -> BufferId {
+        self.graph_builder.create_buffer(size)
+    }
+
+    /// Run a compute node at the given order, see the `compute` module. Compute nodes run before
+    /// the passes drawing the render groups, from the lowest order to the highest.
+    pub fn add_compute(&mut self, order: impl Into<i32>, node: ComputeNodeDesc) {
+        self.compute.push((order.into(), node));
+    }
+
+    fn build(mut self, factory: &mut Factory<B>, res: &Resources) -> GraphBuilder<B, Resources> {
+        let mut graph_builder = std::mem::replace(&mut self.graph_builder, GraphBuilder::new());
+        let targets: Vec<_> = self
+            .target
+            .take()
+            .map(|target| (None, target))
+            .into_iter()
+            .chain(
+                self.windows
+                    .drain(..)
+                    .map(|(window, target)| (Some(window), target)),
+            )
+            .collect();
+        if targets.is_empty() {
+            if !self.groups.is_empty() {
+                log::warn!("No render plugin set a target, nothing will be drawn");
+            }
+            return graph_builder;
+        }
+
+        self.groups.sort_by_key(|(order, _, _)| *order);
+        let timing = self.build_timing(&mut graph_builder, &targets, factory, res);
+        self.compute.sort_by_key(|(order, _)| *order);
+        let mut compute = Vec::new();
+        for (_, node) in self.compute.drain(..) {
+            let node = node.add_to(&mut graph_builder, &compute);
+            compute.push(node);
+        }
+        let mut previous = None;
+        for (target_index, (window, target)) in targets.into_iter().enumerate() {
+            let view = match &window {
+                Some(window) => ViewDesc::window(window.clone()),
+                None => ViewDesc::main(),
+            };
+            let colour = graph_builder.create_image(
+                target.kind,
+                1,
+                target.format,
+                Some(ClearValue::Color(target.clear.into())),
+            );
+            let depth = graph_builder.create_image(
+                target.kind,
+                1,
+                self.depth_format,
+                Some(ClearValue::DepthStencil(ClearDepthStencil(
+                    DEFAULT_CLEAR_DEPTH,
+                    0,
+                ))),
+            );
+
+            // The view must be set after the passes of the previous target ran.
+            let mut view = view.builder();
+            if let Some(previous) = previous {
+                view = view.with_dependency(previous);
+            }
+            let view = graph_builder.add_node(view);
+
+            let target_name = window.as_ref().map_or("main", String::as_str);
+            let mut subpass = SubpassBuilder::new();
+            if self.markers {
+                subpass =
+                    subpass.with_group(PassMarkerDesc::Begin(target_name.to_string()).builder());
+            }
+            subpass = subpass.with_group(ClearDesc::new(target.clear).builder());
+            for (group_index, (_, name, add_group)) in self.groups.iter().enumerate() {
+                let timer = timing.as_ref().map(|(timer, _)| {
+                    (
+                        timer.clone(),
+                        target_index * self.groups.len() + group_index,
+                    )
+                });
+                let label = if self.markers {
+                    Some(format!("{}/{}", target_name, name))
+                } else {
+                    None
+                };
+                subpass = add_group(subpass, timer, label);
+            }
+            if self.markers {
+                subpass = subpass.with_group(PassMarkerDesc::End.builder());
+            }
+            subpass = subpass
+                .with_color(colour)
+                .with_depth_stencil(depth)
+                .with_dependency(view);
+            if let Some((_, node)) = &timing {
+                subpass = subpass.with_dependency(*node);
+            }
+            for &node in &compute {
+                subpass = subpass.with_dependency(node);
+            }
+            let pass = graph_builder.add_node(subpass.into_pass());
+            previous = Some(pass);
+
+            let depth = DepthImage {
+                window,
+                image: depth,
+                format: self.depth_format,
+                pass,
+            };
+            for add_node in &self.depth_nodes {
+                add_node(&mut graph_builder, &depth);
+            }
+            let color = ColorImage {
+                window: depth.window.clone(),
+                image: colour,
+                kind: target.kind,
+                format: target.format,
+                pass,
+            };
+            for add_node in &self.color_nodes {
+                add_node(&mut graph_builder, &color);
+            }
+
+            match target.output {
+                TargetOutput::Surface(surface) => {
+                    graph_builder.add_node(
+                        present_builder(factory, surface, colour, res).with_dependency(pass),
+                    );
+                }
+                TargetOutput::Capture => {
+                    graph_builder.add_node(
+                        CaptureDesc::default()
+                            .builder()
+                            .with_image(colour)
+                            .with_dependency(pass),
+                    );
+                }
+            }
+        }
+        graph_builder
+    }
+
+    /// Add the node timing the groups of every target if timing was requested and is supported,
+    /// and reset the `GpuTimingStats` to the timed groups.
+    fn build_timing(
+        &self,
+        graph_builder: &mut GraphBuilder<B, Resources>,
+        targets: &[(Option<String>, RenderTarget<B>)],
+        factory: &Factory<B>,
+        res: &Resources,
+    ) -> Option<(GpuTimer<B>, NodeId)> {
+        let status = if !self.gpu_timing {
+            GpuTimingStatus::Disabled
+        } else if !timestamps_supported(factory) {
+            log::warn!("The adapter doesn't support timestamp queries, GPU timing is unavailable");
+            GpuTimingStatus::Unavailable
+        } else {
+            GpuTimingStatus::Enabled
+        };
+
+        let mut names = Vec::new();
+        if status == GpuTimingStatus::Enabled {
+            for (window, _) in targets {
+                for (_, name, _) in &self.groups {
+                    names.push(match window {
+                        Some(window) => format!("{}/{}", window, name),
+                        None => name.clone(),
+                    });
+                }
+            }
+        }
+        if let Some(mut stats) = res.try_fetch_mut::<GpuTimingStats>() {
+            stats.reset(status, &names);
+        }
+        if names.is_empty() {
+            return None;
+        }
+
+        let timer = GpuTimer::new(names.len());
+        let node = graph_builder.add_node(GpuTimingDesc::new(timer.clone()).builder());
+        Some((timer, node))
+    }
+}
+
+/// Graph of a `RenderingBundle`, planned by its plugins.
+#[allow(missing_debug_implementations)]
+struct PluginGraph<B: Backend> {
+    plugins: Vec<Box<dyn RenderPlugin<B>>>,
+    gpu_timing: bool,
+    depth_formats: Vec<Format>,
+}
+
+impl<B: Backend> GraphCreator<B> for PluginGraph<B> {
+    fn rebuild(&mut self, res: &Resources) -> bool {
+        // Every plugin must see every frame, don't stop at the first one asking for a rebuild.
+        self.plugins.iter_mut().fold(false, |rebuild, plugin| {
+            plugin.should_rebuild(res) || rebuild
+        })
+    }
+
+    fn builder(&mut self, factory: &mut Factory<B>, res: &Resources) -> GraphBuilder<B, Resources> {
+        let depth_format = negotiate_depth_format(factory, &self.depth_formats);
+        let markers = res
+            .try_fetch::<ValidationConfig>()
+            .map_or(false, |config| config.markers);
+        let mut plan = RenderPlan::new(self.gpu_timing, depth_format, markers);
+        for plugin in &mut self.plugins {
+            if let Err(e) = plugin.on_plan(&mut plan, factory, res) {
+                panic!("Render plugin {:?} failed to plan the graph: {}", plugin, e);
+            }
+        }
+        plan.build(factory, res)
+    }
+}
+
+/// Render to the window of the `WindowBundle`. The `RenderingSystem` rebuilds the graph when the
+/// window is resized.
+#[derive(Debug)]
+pub struct RenderToWindow {
+    clear: [f32; 4],
+    surface_format: Option<Format>,
+}
+
+impl Default for RenderToWindow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RenderToWindow {
+    /// Render to the window, cleared to black.
+    pub fn new() -> Self {
+        Self {
+            clear: [0., 0., 0., 1.],
+            surface_format: None,
+        }
+    }
+
+    /// Clear the window to the given linear RGBA color every frame.
+    pub fn with_clear(mut self, clear: [f32; 4]) -> Self {
+        self.clear = clear;
+        self
+    }
+}
+
+impl<B: Backend> RenderPlugin<B> for RenderToWindow {
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        factory: &mut Factory<B>,
+        res: &Resources,
+    ) -> Result<(), Error> {
+        let window = <ReadExpect<'_, Window>>::fetch(res);
+        let surface = factory.create_surface(&window);
+        // cache surface format to speed things up
+        let format = *self
+            .surface_format
+            .get_or_insert_with(|| factory.get_surface_format(&surface));
+        let dimensions = <ReadExpect<'_, ScreenDimensions>>::fetch(res);
+        plan.set_target(RenderTarget {
+            kind: Kind::D2(dimensions.width() as u32, dimensions.height() as u32, 1, 1),
+            format,
+            clear: self.clear,
+            output: TargetOutput::Surface(surface),
+        })
+    }
+}
+
+/// Render to a secondary window of the `SecondaryWindows` resource, with its camera of the
+/// `WindowCameras` resource.
+///
+/// The graph is rebuilt when the window is resized or closed. Closing the window drops its
+/// surface on that rebuild, the textures and meshes stay shared with the other windows. Nothing
+/// is rendered to it while it is minimized.
+#[derive(Debug)]
+pub struct RenderToSecondaryWindow {
+    name: String,
+    clear: [f32; 4],
+    surface_format: Option<Format>,
+    planned: Option<ScreenDimensions>,
+    last: Option<ScreenDimensions>,
+}
+
+impl RenderToSecondaryWindow {
+    /// Render to the secondary window of the given name, cleared to black.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            clear: [0., 0., 0., 1.],
+            surface_format: None,
+            planned: None,
+            last: None,
+        }
+    }
+
+    /// Clear the window to the given linear RGBA color every frame.
+    pub fn with_clear(mut self, clear: [f32; 4]) -> Self {
+        self.clear = clear;
+        self
+    }
+
+    /// Dimensions of the window, or `None` if it is closed or minimized.
+    fn dimensions(&self, res: &Resources) -> Option<ScreenDimensions> {
+        res.try_fetch::<SecondaryWindows>()
+            .and_then(|windows| windows.dimensions(&self.name).cloned())
+            .filter(|d| d.width() >= 1.0 && d.height() >= 1.0)
+    }
+}
+
+impl<B: Backend> RenderPlugin<B> for RenderToSecondaryWindow {
+    fn should_rebuild(&mut self, res: &Resources) -> bool {
+        // Rebuild when the dimensions change, once they stayed the same for a frame.
+        let dimensions = self.dimensions(res);
+        if dimensions == self.planned {
+            self.last = None;
+            return false;
+        }
+        let settled = self.last == dimensions;
+        self.last = dimensions;
+        settled
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        factory: &mut Factory<B>,
+        res: &Resources,
+    ) -> Result<(), Error> {
+        self.planned = self.dimensions(res);
+        self.last = None;
+        let dimensions = match &self.planned {
+            Some(dimensions) => dimensions,
+            None => return Ok(()),
+        };
+
+        let windows = <ReadExpect<'_, SecondaryWindows>>::fetch(res);
+        let window = windows.get(&self.name).expect("Window is open");
+        let surface = factory.create_surface(window);
+        let format = *self
+            .surface_format
+            .get_or_insert_with(|| factory.get_surface_format(&surface));
+        plan.add_window_target(
+            self.name.clone(),
+            RenderTarget {
+                kind: Kind::D2(dimensions.width() as u32, dimensions.height() as u32, 1, 1),
+                format,
+                clear: self.clear,
+                output: TargetOutput::Surface(surface),
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Render to an offscreen image of a fixed size, without a window. Frames are read back with the
+/// `FrameCapture` resource.
+#[derive(Debug)]
+pub struct RenderToImage {
+    width: u32,
+    height: u32,
+    clear: [f32; 4],
+}
+
+impl RenderToImage {
+    /// Render to a `Rgba8Srgb` image of the given size, cleared to black.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            clear: [0., 0., 0., 1.],
+        }
+    }
+
+    /// Clear the image to the given linear RGBA color every frame.
+    pub fn with_clear(mut self, clear: [f32; 4]) -> Self {
+        self.clear = clear;
+        self
+    }
+}
+
+impl<B: Backend> RenderPlugin<B> for RenderToImage {
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        _res: &Resources,
+    ) -> Result<(), Error> {
+        plan.set_target(RenderTarget {
+            kind: Kind::D2(self.width, self.height, 1, 1),
+            format: Format::Rgba8Srgb,
+            clear: self.clear,
+            output: TargetOutput::Capture,
+        })
+    }
+}
+
+/// Render opaque and transparent sprites, and add the sprite sheet processor and the sprite
+/// visibility sorting system.
+#[derive(Debug, Default)]
+pub struct RenderFlat2D {
+    tint_transparency: Option<TintTransparency>,
+}
+
+impl RenderFlat2D {
+    /// Draw sprites with a translucent `Tint` as transparent, see `TintTransparency`.
+    pub fn with_tint_transparency(mut self, tint_transparency: TintTransparency) -> Self {
+        self.tint_transparency = Some(tint_transparency);
+        self
+    }
+}
+
+impl<B: Backend> RenderPlugin<B> for RenderFlat2D {
+    fn on_build<'a, 'b>(&mut self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(
+            Processor::<SpriteSheet>::new(),
+            "sprite_sheet_processor",
+            &[],
+        );
+        let mut sorting = SpriteVisibilitySortingSystem::new();
+        if let Some(tint_transparency) = self.tint_transparency.clone() {
+            sorting = sorting.with_tint_transparency(tint_transparency);
+        }
+        builder.add(
+            sorting,
+            "sprite_visibility_system",
+            &["transform_system", "asset_readiness_system"],
+        );
+        Ok(())
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        _res: &Resources,
+    ) -> Result<(), Error> {
+        plan.add_group(RenderOrder::Opaque, DrawFlat2DDesc::new());
+        plan.add_group(RenderOrder::Transparent, DrawFlat2DTransparentDesc::new());
+        Ok(())
+    }
+}
+
+/// Render opaque and transparent meshes with the shaders of a `Base3DPassDef`, and add the
+/// visibility sorting system. Register only one 3D plugin per bundle.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""), Default(bound = ""))]
+pub struct RenderBase3D<D> {
+    skinning: bool,
+    view_models: bool,
+    static_instances: Option<StaticCulling>,
+    tint_transparency: Option<TintTransparency>,
+    marker: PhantomData<D>,
+}
+
+/// Render meshes with physically based lighting.
+pub type RenderPbr3D = RenderBase3D<PbrPassDef>;
+/// Render meshes with simple shading.
+pub type RenderShaded3D = RenderBase3D<ShadedPassDef>;
+/// Render meshes without lighting.
+pub type RenderFlat3D = RenderBase3D<FlatPassDef>;
+
+impl<D> RenderBase3D<D> {
+    /// Draw skinned meshes with vertex skinning.
+    pub fn with_skinning(mut self) -> Self {
+        self.skinning = true;
+        self
+    }
+
+    /// Draw meshes with a translucent `Tint` as transparent, see `TintTransparency`.
+    pub fn with_tint_transparency(mut self, tint_transparency: TintTransparency) -> Self {
+        self.tint_transparency = Some(tint_transparency);
+        self
+    }
+
+    /// Draw the `ViewModel` entities over the world, see the `view_model` module.
+    pub fn with_view_models(mut self) -> Self {
+        self.view_models = true;
+        self
+    }
+
+    /// Cull and draw the `StaticInstances` on the GPU when the backend and the device can, with
+    /// the capacity of `culling`, see the `static_instances` module.
+    pub fn with_static_instances(mut self, culling: StaticCulling) -> Self {
+        self.static_instances = Some(culling);
+        self
+    }
+}
+
+impl<B: Backend, D: Base3DPassDef<B>> RenderPlugin<B> for RenderBase3D<D> {
+    fn on_build<'a, 'b>(&mut self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        let mut sorting = VisibilitySortingSystem::new();
+        if let Some(tint_transparency) = self.tint_transparency.clone() {
+            sorting = sorting.with_tint_transparency(tint_transparency);
+        }
+        builder.add(
+            sorting,
+            "visibility_system",
+            &["transform_system", "asset_readiness_system"],
+        );
+        if self.static_instances.is_some() {
+            builder.add(
+                StaticInstancesSystem::new(),
+                "static_instances_system",
+                &["transform_system"],
+            );
+        }
+        Ok(())
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        factory: &mut Factory<B>,
+        res: &Resources,
+    ) -> Result<(), Error> {
+        let opaque = if self.skinning {
+            DrawBase3DDesc::<B, D>::skinned()
+        } else {
+            DrawBase3DDesc::<B, D>::new()
+        };
+        // Without compute or indirect draws, the static instances are drawn like the others.
+        let gpu_culling = self
+            .static_instances
+            .filter(|_| B::variant().renders() && IndirectSupport::new(factory).indirect);
+        if let Some(mut set) = res.try_fetch_mut::<StaticInstanceSet>() {
+            set.set_culling(gpu_culling);
+        }
+        match gpu_culling {
+            Some(culling) => {
+                let instances = plan.create_buffer(culling.instances_size());
+                let commands = plan.create_buffer(culling.commands_size());
+                plan.add_compute(0, static_culling_node::<B>(culling, instances, commands));
+                plan.add_group_with_buffers(
+                    RenderOrder::Opaque,
+                    opaque.with_static_instances(culling),
+                    vec![instances, commands],
+                );
+            }
+            None => plan.add_group(RenderOrder::Opaque, opaque),
+        }
+        if self.skinning {
+            plan.add_group(
+                RenderOrder::Transparent,
+                DrawBase3DTransparentDesc::<B, D>::skinned(),
+            );
+        } else {
+            plan.add_group(
+                RenderOrder::Transparent,
+                DrawBase3DTransparentDesc::<B, D>::new(),
+            );
+        }
+        if self.view_models {
+            let view_models = if self.skinning {
+                DrawBase3DDesc::<B, D>::skinned()
+            } else {
+                DrawBase3DDesc::<B, D>::new()
+            };
+            plan.add_group(RenderOrder::AfterTransparent, view_models.for_view_models());
+        }
+        Ok(())
+    }
+}
+
+/// Render a skybox behind the opaque geometry.
+#[derive(Debug, Default)]
+pub struct RenderSkybox {
+    colors: Option<(Srgb, Srgb)>,
+}
+
+impl RenderSkybox {
+    /// Render a skybox blending from the `nadir` to the `zenith` color.
+    pub fn with_colors(nadir: Srgb, zenith: Srgb) -> Self {
+        Self {
+            colors: Some((nadir, zenith)),
+        }
+    }
+}
+
+impl<B: Backend> RenderPlugin<B> for RenderSkybox {
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        _res: &Resources,
+    ) -> Result<(), Error> {
+        let skybox = match self.colors {
+            Some((nadir, zenith)) => DrawSkyboxDesc::with_colors(nadir, zenith),
+            None => DrawSkyboxDesc::new(),
+        };
+        plan.add_group(RenderOrder::AfterOpaque, skybox);
+        Ok(())
+    }
+}
+
+/// Adapt the `Exposure` to the luminance of the frames of the main target, measured on the GPU,
+/// and add the `AutoExposureSystem`, see the `exposure` module. Without compute, the `Exposure`
+/// stays as it's set.
+#[derive(Debug, Default)]
+pub struct RenderAutoExposure;
+
+impl<B: Backend> RenderPlugin<B> for RenderAutoExposure {
+    fn on_build<'a, 'b>(&mut self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(AutoExposureSystem::new(), "auto_exposure_system", &[]);
+        Ok(())
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        _res: &Resources,
+    ) -> Result<(), Error> {
+        if !B::variant().renders() {
+            log::warn!("The backend can't compute, the exposure won't adapt to the frames");
+            return Ok(());
+        }
+        plan.add_color_node(|graph_builder, color| {
+            if color.window.is_none() {
+                luminance_node(color).add_to(graph_builder, &[color.pass]);
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Render debug lines, and add the `DebugLinesSystem` expiring timed lines.
+#[derive(Debug, Default)]
+pub struct RenderDebugLines {
+    render_layers: bool,
+}
+
+impl RenderDebugLines {
+    /// Only draw the `DebugLinesComponent`s whose `RenderLayers` are seen by the active camera.
+    pub fn with_render_layers(mut self) -> Self {
+        self.render_layers = true;
+        self
+    }
+}
+
+impl<B: Backend> RenderPlugin<B> for RenderDebugLines {
+    fn on_build<'a, 'b>(&mut self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(DebugLinesSystem::new(), "debug_lines_system", &[]);
+        Ok(())
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        _res: &Resources,
+    ) -> Result<(), Error> {
+        let lines = if self.render_layers {
+            DrawDebugLinesDesc::new().with_render_layers()
+        } else {
+            DrawDebugLinesDesc::new()
+        };
+        plan.add_group(RenderOrder::BeforeTransparent, lines);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_orders() {
+        assert!(RenderOrder::Opaque < RenderOrder::AfterOpaque);
+        assert!(i32::from(RenderOrder::Opaque) + 1 < i32::from(RenderOrder::AfterOpaque));
+        assert!(RenderOrder::BeforeTransparent < RenderOrder::Transparent);
+    }
+}
@@ -1,9 +1,11 @@
 //! Camera type with support for perspective and orthographic projections.
 
+use crate::layers::RenderLayers;
 use amethyst_assets::PrefabData;
 use amethyst_core::{
     ecs::prelude::{Component, Entity, HashMapStorage, Write, WriteStorage},
-    math::Matrix4,
+    math::{convert, Matrix4, Point3},
+    Transform,
 };
 
 use amethyst_error::Error;
@@ -418,7 +420,10 @@ impl From<Perspective> for Projection {
 
 impl From<Projection> for Camera {
     fn from(proj: Projection) -> Self {
-        Camera { inner: proj }
+        Camera {
+            inner: proj,
+            layers: RenderLayers::ALL,
+        }
     }
 }
 
@@ -444,6 +449,9 @@ impl From<Projection> for Camera {
 pub struct Camera {
     /// Graphical projection of the camera.
     inner: Projection,
+    /// Render layers seen by the camera.
+    #[serde(default)]
+    layers: RenderLayers,
 }
 
 impl Camera {
@@ -509,6 +517,46 @@ impl Camera {
     pub fn set_projection(&mut self, new: Projection) {
         self.inner = new;
     }
+
+    /// Returns the render layers seen by this camera, see `RenderLayers`.
+    pub fn layers(&self) -> RenderLayers {
+        self.layers
+    }
+
+    /// Sets the render layers seen by this camera.
+    pub fn set_layers(&mut self, layers: RenderLayers) {
+        self.layers = layers;
+    }
+
+    /// Builder style variant of `set_layers`.
+    pub fn with_layers(mut self, layers: RenderLayers) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    /// Returns the world space corners of the volume seen by this camera when placed at
+    /// `transform`, on its near and far planes.
+    ///
+    /// Corner `i` is at the left (`i & 1 == 0`) or right, top (`i & 2 == 0`) or bottom, near
+    /// (`i & 4 == 0`) or far side of the volume.
+    pub fn frustum_corners(&self, transform: &Transform) -> [Point3<f32>; 8] {
+        let world: Matrix4<f32> = convert(*transform.global_matrix());
+        let unproject = world
+            * self
+                .as_matrix()
+                .try_inverse()
+                .unwrap_or_else(Matrix4::identity);
+        let mut corners = [Point3::origin(); 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let ndc = Point3::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { 0.0 } else { 1.0 },
+            );
+            *corner = unproject.transform_point(&ndc);
+        }
+        corners
+    }
 }
 
 impl Component for Camera {
@@ -523,6 +571,17 @@ pub struct ActiveCamera {
     pub entity: Option<Entity>,
 }
 
+/// Camera resource, used by the visibility systems to choose which camera to cull and sort
+/// entities for. If no entity is set, the `ActiveCamera` is used.
+///
+/// Pointing it to a camera that stays in place, while `ActiveCamera` follows a second camera,
+/// shows what is culled from the outside, as with `DebugLinesComponent::add_frustum`.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct CullingCamera {
+    /// Camera entity
+    pub entity: Option<Entity>,
+}
+
 /// Projection prefab
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
 pub enum CameraPrefab {
@@ -540,6 +599,9 @@ pub enum CameraPrefab {
         znear: f32,
         /// The distance between the viewer (the origin) and the furthest face of the cuboid parallel to the xy-plane. If used for a 3D rendering application, this is the furthest clipping plane.
         zfar: f32,
+        /// Render layers seen by the camera, all layers if omitted.
+        #[serde(default)]
+        layers: RenderLayers,
     },
     /// Perspective prefab
     Perspective {
@@ -551,6 +613,9 @@ pub enum CameraPrefab {
         znear: f32,
         /// Far clip plane distance
         zfar: f32,
+        /// Render layers seen by the camera, all layers if omitted.
+        #[serde(default)]
+        layers: RenderLayers,
     },
 }
 
@@ -565,27 +630,28 @@ impl<'a> PrefabData<'a> for CameraPrefab {
         _: &[Entity],
         _: &[Entity],
     ) -> Result<(), Error> {
-        storage.insert(
-            entity,
-            Camera {
-                inner: match *self {
-                    CameraPrefab::Orthographic {
-                        left,
-                        right,
-                        bottom,
-                        top,
-                        znear,
-                        zfar,
-                    } => Projection::orthographic(left, right, bottom, top, znear, zfar),
-                    CameraPrefab::Perspective {
-                        aspect,
-                        fovy,
-                        znear,
-                        zfar,
-                    } => Projection::perspective(aspect, fovy, znear, zfar),
-                },
-            },
-        )?;
+        let (inner, layers) = match *self {
+            CameraPrefab::Orthographic {
+                left,
+                right,
+                bottom,
+                top,
+                znear,
+                zfar,
+                layers,
+            } => (
+                Projection::orthographic(left, right, bottom, top, znear, zfar),
+                layers,
+            ),
+            CameraPrefab::Perspective {
+                aspect,
+                fovy,
+                znear,
+                zfar,
+                layers,
+            } => (Projection::perspective(aspect, fovy, znear, zfar), layers),
+        };
+        storage.insert(entity, Camera { inner, layers })?;
         Ok(())
     }
 }
@@ -657,6 +723,38 @@ mod tests {
         assert_eq!(test_persp, de);
     }
 
+    #[test]
+    fn prefab_layers() {
+        use amethyst_core::ecs::prelude::World;
+
+        let mut world = World::new();
+        world.register::<Camera>();
+        let entity = world.entities().create();
+
+        let prefab: CameraPrefab =
+            from_str("Perspective(aspect: 1.0, fovy: 1.0, znear: 0.1, zfar: 100.0)").unwrap();
+        prefab
+            .add_to_entity(entity, &mut world.write_storage(), &[], &[])
+            .unwrap();
+        assert_eq!(
+            world.read_storage::<Camera>().get(entity).unwrap().layers(),
+            RenderLayers::ALL
+        );
+
+        let prefab: CameraPrefab = from_str(
+            "Orthographic(left: 0.0, right: 1.0, bottom: 0.0, top: 1.0, znear: 0.1, zfar: 10.0, \
+             layers: 5)",
+        )
+        .unwrap();
+        prefab
+            .add_to_entity(entity, &mut world.write_storage(), &[], &[])
+            .unwrap();
+        assert_eq!(
+            world.read_storage::<Camera>().get(entity).unwrap().layers(),
+            RenderLayers::layer(0).with(2)
+        );
+    }
+
     #[test]
     fn extract_perspective_values() {
         let proj = Perspective::new(1280.0 / 720.0, std::f32::consts::FRAC_PI_3, 0.1, 100.0);
@@ -0,0 +1,439 @@
+//! Reading rendered frames back from the GPU.
+//!
+//! Add a `CaptureDesc` node reading the final color image to a render graph, and call
+//! `FrameCapture::request` to copy the next frame into main memory. This works the same for
+//! graphs presenting to a window and for headless graphs rendering to an offscreen image, which
+//! need neither a window nor a surface.
+use crate::{
+    rendy::{
+        command::{
+            CommandBuffer, CommandPool, ExecutableState, Family, Fence, MultiShot, PendingState,
+            Queue, QueueType, SimultaneousUse, Submission, Submit, Transfer,
+        },
+        factory::Factory,
+        frame::Frames,
+        graph::{
+            gfx_acquire_barriers, gfx_release_barriers, GraphContext, ImageAccess, Node,
+            NodeBuffer, NodeDesc, NodeImage,
+        },
+        hal,
+        memory::Download,
+        resource::{Buffer, BufferInfo, Escape},
+    },
+    types::Backend,
+};
+use amethyst_core::ecs::Resources;
+use rendy::hal::format::Format;
+use std::ops::Range;
+
+/// Resource requesting and receiving the frames read back by the `CaptureDesc` node of the
+/// render graph.
+#[derive(Debug, Default)]
+pub struct FrameCapture {
+    requested: bool,
+    captured: Option<CapturedImage>,
+}
+
+impl FrameCapture {
+    /// Capture the next rendered frame. It is available from `take` once the GPU finished it,
+    /// which is usually a few frames later.
+    pub fn request(&mut self) {
+        self.requested = true;
+    }
+
+    /// Whether a capture was requested and not yet started by the render graph.
+    pub fn is_requested(&self) -> bool {
+        self.requested
+    }
+
+    /// Take the last captured frame.
+    pub fn take(&mut self) -> Option<CapturedImage> {
+        self.captured.take()
+    }
+}
+
+/// Pixels of a frame read back from the GPU, tightly packed rows from top to bottom.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedImage {
+    /// Width in pixels.
+    pub width: u32,
+    /// Height in pixels.
+    pub height: u32,
+    /// Format of the rendered image.
+    pub format: Format,
+    /// Raw pixel data in `format`.
+    pub data: Vec<u8>,
+}
+
+impl CapturedImage {
+    /// The pixels as an RGBA image, for images of 8 bit RGBA or BGRA formats like the ones of
+    /// most surfaces.
+    pub fn to_rgba(&self) -> Option<image::RgbaImage> {
+        let mut data = self.data.clone();
+        match self.format {
+            Format::Rgba8Unorm | Format::Rgba8Srgb => {}
+            Format::Bgra8Unorm | Format::Bgra8Srgb => {
+                for pixel in data.chunks_exact_mut(4) {
+                    pixel.swap(0, 2);
+                }
+            }
+            _ => return None,
+        }
+        image::RgbaImage::from_raw(self.width, self.height, data)
+    }
+}
+
+/// An RGBA frame rendered by the test bundles, with helpers to assert its colors.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedFrame {
+    image: image::RgbaImage,
+    stub: bool,
+}
+
+impl CapturedFrame {
+    /// A frame of the pixels of `image`.
+    pub fn from_rgba(image: image::RgbaImage) -> Self {
+        Self { image, stub: false }
+    }
+
+    /// A frame of transparent black pixels standing for a frame that wasn't rendered, like by
+    /// the empty backend.
+    pub fn stub(width: u32, height: u32) -> Self {
+        Self {
+            image: image::RgbaImage::new(width, height),
+            stub: true,
+        }
+    }
+
+    /// Whether the frame is a stub, whose pixels weren't rendered.
+    pub fn is_stub(&self) -> bool {
+        self.stub
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> u32 {
+        self.image.height()
+    }
+
+    /// The pixels of the frame.
+    pub fn image(&self) -> &image::RgbaImage {
+        &self.image
+    }
+
+    /// The RGBA pixel at `x`, `y` from the top left corner, if it's in the frame.
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        if x < self.width() && y < self.height() {
+            Some(self.image.get_pixel(x, y).data)
+        } else {
+            None
+        }
+    }
+
+    /// Average RGBA color from 0 to 1 of the pixels of the columns `x` and the rows `y`, clipped
+    /// to the frame. `None` when no pixel is left.
+    pub fn average_color(&self, x: Range<u32>, y: Range<u32>) -> Option<[f32; 4]> {
+        let x = x.start..x.end.min(self.width());
+        let y = y.start..y.end.min(self.height());
+        let mut sum = [0u64; 4];
+        let mut count = 0u64;
+        for row in y {
+            for column in x.clone() {
+                for (sum, channel) in sum
+                    .iter_mut()
+                    .zip(self.image.get_pixel(column, row).data.iter())
+                {
+                    *sum += u64::from(*channel);
+                }
+                count += 1;
+            }
+        }
+        if count == 0 {
+            return None;
+        }
+        let average = |sum: u64| sum as f32 / (count * 255) as f32;
+        Some([
+            average(sum[0]),
+            average(sum[1]),
+            average(sum[2]),
+            average(sum[3]),
+        ])
+    }
+}
+
+/// Render graph node copying its image to main memory when a capture is requested in the
+/// `FrameCapture` resource. Add it with the image to read back and a dependency on the nodes
+/// drawing it:
+///
+/// ```rust,ignore
+/// graph_builder.add_node(
+///     CaptureDesc::default()
+///         .builder()
+///         .with_image(color)
+///         .with_dependency(pass),
+/// );
+/// ```
+#[derive(Debug, Default)]
+pub struct CaptureDesc;
+
+/// Render graph node built by `CaptureDesc`.
+#[derive(Debug)]
+pub struct CaptureNode<B: Backend> {
+    pool: CommandPool<B, QueueType>,
+    slots: Vec<CaptureSlot<B>>,
+    width: u32,
+    height: u32,
+    format: Format,
+    size: u64,
+}
+
+/// Copy of the image for one frame in flight.
+#[derive(Debug)]
+struct CaptureSlot<B: Backend> {
+    buffer: Escape<Buffer<B>>,
+    submit: Submit<B, SimultaneousUse>,
+    command_buffer:
+        CommandBuffer<B, QueueType, PendingState<ExecutableState<MultiShot<SimultaneousUse>>>>,
+    frame: Option<u64>,
+}
+
+impl<B: Backend> NodeDesc<B, Resources> for CaptureDesc {
+    type Node = CaptureNode<B>;
+
+    fn images(&self) -> Vec<ImageAccess> {
+        vec![ImageAccess {
+            access: hal::image::Access::TRANSFER_READ,
+            layout: hal::image::Layout::TransferSrcOptimal,
+            usage: hal::image::Usage::TRANSFER_SRC,
+            stages: hal::pso::PipelineStage::TRANSFER,
+        }]
+    }
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        family: &mut Family<B>,
+        _queue: usize,
+        _aux: &Resources,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Self::Node, failure::Error> {
+        assert!(buffers.is_empty());
+        assert_eq!(images.len(), 1);
+
+        let input = &images[0];
+        let image = ctx
+            .get_image(input.id)
+            .expect("Captured image doesn't exist");
+        let extent = image.kind().extent();
+        let format = image.format();
+        let size = u64::from(extent.width)
+            * u64::from(extent.height)
+            * u64::from(format.surface_desc().bits / 8);
+
+        let mut pool = factory.create_command_pool(family)?;
+        let mut slots = Vec::new();
+        for initial in pool.allocate_buffers(ctx.frames_in_flight() as usize) {
+            let buffer = factory.create_buffer(
+                BufferInfo {
+                    size,
+                    usage: hal::buffer::Usage::TRANSFER_DST,
+                },
+                Download,
+            )?;
+
+            let mut recording = initial.begin(MultiShot(SimultaneousUse), ());
+            {
+                let mut encoder = recording.encoder();
+                unsafe {
+                    let (stages, barriers) = gfx_acquire_barriers(ctx, None, Some(input));
+                    if !barriers.is_empty() {
+                        encoder.pipeline_barrier(
+                            stages,
+                            hal::memory::Dependencies::empty(),
+                            barriers,
+                        );
+                    }
+                    encoder.copy_image_to_buffer(
+                        image.raw(),
+                        input.layout,
+                        buffer.raw(),
+                        Some(hal::command::BufferImageCopy {
+                            buffer_offset: 0,
+                            buffer_width: extent.width,
+                            buffer_height: extent.height,
+                            image_layers: hal::image::SubresourceLayers {
+                                aspects: input.range.aspects,
+                                level: 0,
+                                layers: 0..1,
+                            },
+                            image_offset: hal::image::Offset::ZERO,
+                            image_extent: extent,
+                        }),
+                    );
+                    let (stages, barriers) = gfx_release_barriers(ctx, None, Some(input));
+                    if !barriers.is_empty() {
+                        encoder.pipeline_barrier(
+                            stages,
+                            hal::memory::Dependencies::empty(),
+                            barriers,
+                        );
+                    }
+                }
+            }
+            let (submit, command_buffer) = recording.finish().submit();
+            slots.push(CaptureSlot {
+                buffer,
+                submit,
+                command_buffer,
+                frame: None,
+            });
+        }
+
+        Ok(CaptureNode {
+            pool,
+            slots,
+            width: extent.width,
+            height: extent.height,
+            format,
+            size,
+        })
+    }
+}
+
+impl<B: Backend> Node<B, Resources> for CaptureNode<B> {
+    type Capability = Transfer;
+
+    fn run<'a>(
+        &mut self,
+        _ctx: &GraphContext<B>,
+        factory: &Factory<B>,
+        queue: &mut Queue<B>,
+        aux: &Resources,
+        frames: &Frames<B>,
+        waits: &[(&'a B::Semaphore, hal::pso::PipelineStage)],
+        signals: &[&'a B::Semaphore],
+        fence: Option<&mut Fence<B>>,
+    ) {
+        let mut capture = aux.fetch_mut::<FrameCapture>();
+
+        for slot in &mut self.slots {
+            match slot.frame {
+                Some(frame) if frames.is_complete(frame) => slot.frame = None,
+                _ => continue,
+            }
+            let size = self.size;
+            let data = slot
+                .buffer
+                .map(factory.device(), 0..size)
+                .and_then(|mut mapped| unsafe {
+                    mapped
+                        .read::<u8>(factory.device(), 0..size)
+                        .map(|data| data.to_vec())
+                });
+            match data {
+                Ok(data) => {
+                    capture.captured = Some(CapturedImage {
+                        width: self.width,
+                        height: self.height,
+                        format: self.format,
+                        data,
+                    })
+                }
+                Err(e) => log::error!("Failed to read back captured frame: {}", e),
+            }
+        }
+
+        let frame = frames.next().index();
+        let slot = &mut self.slots[(frame % self.slots.len() as u64) as usize];
+        let submit = if capture.requested && slot.frame.is_none() {
+            capture.requested = false;
+            slot.frame = Some(frame);
+            Some(&slot.submit)
+        } else {
+            None
+        };
+
+        unsafe {
+            queue.submit(
+                Some(
+                    Submission::new()
+                        .wait(waits.iter().cloned())
+                        .submits(submit)
+                        .signal(signals.iter().cloned()),
+                ),
+                fence,
+            );
+        }
+    }
+
+    unsafe fn dispose(mut self, factory: &mut Factory<B>, _aux: &Resources) {
+        self.pool.free_buffers(
+            self.slots
+                .drain(..)
+                .map(|slot| slot.command_buffer.mark_complete()),
+        );
+        factory.destroy_command_pool(self.pool);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captured_bgra_to_rgba() {
+        let captured = CapturedImage {
+            width: 2,
+            height: 1,
+            format: Format::Bgra8Srgb,
+            data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+        };
+        let rgba = captured.to_rgba().unwrap();
+        assert_eq!(rgba.into_raw(), vec![3, 2, 1, 4, 7, 6, 5, 8]);
+
+        let float = CapturedImage {
+            format: Format::Rgba32Sfloat,
+            ..captured
+        };
+        assert!(float.to_rgba().is_none());
+    }
+
+    #[test]
+    fn frame_pixels_and_averages() {
+        let mut image = image::RgbaImage::new(4, 2);
+        image.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        image.put_pixel(1, 0, image::Rgba([0, 0, 255, 255]));
+        let frame = CapturedFrame::from_rgba(image);
+        assert!(!frame.is_stub());
+        assert_eq!(frame.pixel(0, 0), Some([255, 0, 0, 255]));
+        assert_eq!(frame.pixel(4, 0), None);
+        assert_eq!(frame.average_color(0..2, 0..1), Some([0.5, 0.0, 0.5, 1.0]));
+        assert_eq!(frame.average_color(2..10, 0..2), Some([0.0; 4]));
+        assert_eq!(frame.average_color(4..8, 0..2), None);
+
+        let stub = CapturedFrame::stub(4, 2);
+        assert!(stub.is_stub());
+        assert_eq!(stub.pixel(3, 1), Some([0; 4]));
+    }
+
+    #[test]
+    fn capture_is_taken_once() {
+        let mut capture = FrameCapture::default();
+        capture.request();
+        assert!(capture.is_requested());
+        capture.captured = Some(CapturedImage {
+            width: 1,
+            height: 1,
+            format: Format::Rgba8Unorm,
+            data: vec![0; 4],
+        });
+        assert!(capture.take().is_some());
+        assert!(capture.take().is_none());
+    }
+}
@@ -0,0 +1,233 @@
+//! Clear values of the targets of a `RenderingBundle`, changeable without rebuilding the graph.
+//!
+//! The color and depth images are cleared when the pass drawing them begins, to the values they
+//! were created with: the clear color of the target plugin, like `RenderToWindow::with_clear`,
+//! and a depth of `1.`. The first group of every pass reads the `ClearConfig` resource each
+//! frame, and clears the images again with its values when they differ, so the background can
+//! fade with a day and night cycle. The default `ClearConfig` keeps the values of the targets.
+//!
+//! Only the passes of a `RenderingBundle` start with that group. Graphs of another
+//! `GraphCreator` apply the `ClearConfig` by adding a `ClearDesc` first to their subpasses;
+//! the `RenderingSystem` warns once when a `ClearConfig` is set but no pass applied it.
+use crate::{
+    rendy::{
+        command::{QueueId, RenderPassEncoder},
+        factory::Factory,
+        graph::{
+            render::{PrepareResult, RenderGroup, RenderGroupDesc},
+            GraphContext, NodeBuffer, NodeImage,
+        },
+        hal::{
+            self,
+            command::{AttachmentClear, ClearColor, RawCommandBuffer},
+            pso,
+        },
+    },
+    types::Backend,
+};
+use amethyst_core::ecs::Resources;
+use serde::{Deserialize, Serialize};
+
+/// Depth the depth images are created with, for the background to be drawn.
+pub const DEFAULT_CLEAR_DEPTH: f32 = 1.;
+
+/// Resource with the values the color and depth images of every target are cleared to.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClearConfig {
+    /// Color of all targets, or `None` for the color of each target plugin.
+    pub color: Option<[f32; 4]>,
+    /// Depth of all targets.
+    pub depth: f32,
+}
+
+impl Default for ClearConfig {
+    fn default() -> Self {
+        Self {
+            color: None,
+            depth: DEFAULT_CLEAR_DEPTH,
+        }
+    }
+}
+
+impl ClearConfig {
+    /// The color and depth of a target created with the clear color `target`.
+    pub fn values(&self, target: [f32; 4]) -> ([f32; 4], f32) {
+        (self.color.unwrap_or(target), self.depth)
+    }
+}
+
+/// Render group clearing the images of a pass to the `ClearConfig` values, added first to the
+/// passes of a `RenderingBundle`. Add it first to the subpasses of other graphs for them to
+/// apply the `ClearConfig` too.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClearDesc {
+    target: [f32; 4],
+}
+
+impl ClearDesc {
+    /// Clear a target whose color image was created with the clear color `target`.
+    pub fn new(target: [f32; 4]) -> Self {
+        Self { target }
+    }
+}
+
+/// Tells whether a `ClearDesc` group applied the `ClearConfig` in the frames.
+#[derive(Debug, Default)]
+pub(crate) struct ClearWatch {
+    applied: bool,
+    warned: bool,
+}
+
+impl ClearWatch {
+    /// A pass applied the `ClearConfig` this frame.
+    pub(crate) fn applied(&mut self) {
+        self.applied = true;
+    }
+
+    /// End a frame rendered with `config`, returning `true` the first time a `ClearConfig` other
+    /// than the default one was ignored by every pass.
+    pub(crate) fn frame(&mut self, config: &ClearConfig) -> bool {
+        let applied = std::mem::replace(&mut self.applied, false);
+        let ignored = !applied && !self.warned && *config != ClearConfig::default();
+        self.warned |= ignored;
+        ignored
+    }
+}
+
+impl<B: Backend> RenderGroupDesc<B, Resources> for ClearDesc {
+    fn build(
+        self,
+        _ctx: &GraphContext<B>,
+        _factory: &mut Factory<B>,
+        _queue: QueueId,
+        _aux: &Resources,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        _subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, Resources>>, failure::Error> {
+        Ok(Box::new(Clear {
+            target: self.target,
+            rect: pso::Rect {
+                x: 0,
+                y: 0,
+                w: framebuffer_width as i16,
+                h: framebuffer_height as i16,
+            },
+            recorded: Vec::new(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct Clear {
+    target: [f32; 4],
+    rect: pso::Rect,
+    recorded: Vec<Option<([f32; 4], f32)>>,
+}
+
+impl<B: Backend> RenderGroup<B, Resources> for Clear {
+    fn prepare(
+        &mut self,
+        _factory: &Factory<B>,
+        _queue: QueueId,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        aux: &Resources,
+    ) -> PrepareResult {
+        if let Some(mut watch) = aux.try_fetch_mut::<ClearWatch>() {
+            watch.applied();
+        }
+        let values = aux
+            .try_fetch::<ClearConfig>()
+            .map_or((self.target, DEFAULT_CLEAR_DEPTH), |config| {
+                config.values(self.target)
+            });
+        if self.recorded.len() <= index {
+            self.recorded.resize(index + 1, None);
+        }
+        if self.recorded[index].replace(values) == Some(values) {
+            PrepareResult::DrawReuse
+        } else {
+            PrepareResult::DrawRecord
+        }
+    }
+
+    // Only values differing exactly from the ones the images were created with are cleared.
+    #[allow(clippy::float_cmp)]
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        _aux: &Resources,
+    ) {
+        let (color, depth) = match self.recorded.get(index) {
+            Some(&Some(values)) => values,
+            _ => return,
+        };
+        let mut clears = Vec::new();
+        if color != self.target {
+            clears.push(AttachmentClear::Color {
+                index: 0,
+                value: ClearColor::Float(color),
+            });
+        }
+        if depth != DEFAULT_CLEAR_DEPTH {
+            clears.push(AttachmentClear::DepthStencil {
+                depth: Some(depth),
+                stencil: None,
+            });
+        }
+        if clears.is_empty() {
+            return;
+        }
+        unsafe {
+            encoder.raw().clear_attachments(
+                clears,
+                Some(pso::ClearRect {
+                    rect: self.rect,
+                    layers: 0..1,
+                }),
+            );
+        }
+    }
+
+    fn dispose(self: Box<Self>, _factory: &mut Factory<B>, _aux: &Resources) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_keeps_target_values() {
+        let target = [0.34, 0.36, 0.52, 1.];
+        assert_eq!(
+            ClearConfig::default().values(target),
+            (target, DEFAULT_CLEAR_DEPTH)
+        );
+
+        let config = ClearConfig {
+            color: Some([1., 0.5, 0., 1.]),
+            depth: 0.,
+        };
+        assert_eq!(config.values(target), ([1., 0.5, 0., 1.], 0.));
+    }
+
+    #[test]
+    fn warns_once_of_an_ignored_config() {
+        let config = ClearConfig {
+            color: Some([0., 0., 0., 1.]),
+            ..Default::default()
+        };
+        let mut watch = ClearWatch::default();
+        assert!(!watch.frame(&ClearConfig::default()));
+        watch.applied();
+        assert!(!watch.frame(&config));
+        assert!(watch.frame(&config));
+        assert!(!watch.frame(&config));
+    }
+}
@@ -0,0 +1,701 @@
+//! Compute nodes of the render graph.
+//!
+//! A `ComputeNodeDesc` dispatches a SPIR-V compute shader every frame, with storage buffers and
+//! images, or sampled images, bound at set 0 in the order of its bindings. Buffers and images of
+//! the graph are declared with the access of the shader, for the graph to insert the barriers
+//! against the nodes and passes using them before and after, like a render group reading an
+//! indirect draw buffer written by the shader. Host bindings are storage buffers owned by the
+//! node, filled by the CPU before each dispatch, one per frame in flight. Retained bindings are
+//! host bindings filled again only when their version changes, for data uploaded once and kept,
+//! like the instances of the `static_instances` module. Readback bindings are storage buffers
+//! owned by the node written by the shader, read by the CPU when the frame of their slot
+//! completed, a few frames later, like the luminance of the `exposure` module.
+//!
+//! Compute nodes are added to a `RenderPlan` with `RenderPlan::add_compute`, and run before the
+//! passes of the plan in their order. See the `culling` and `static_instances` modules for the
+//! built-in compute nodes.
+use crate::{
+    rendy::{
+        command::{
+            CommandBuffer, CommandPool, Compute, Family, Fence, IndividualReset, InitialState,
+            OneShot, PendingOnceState, PrimaryLevel, Queue, Submission,
+        },
+        factory::Factory,
+        frame::Frames,
+        graph::{
+            gfx_acquire_barriers, gfx_release_barriers, BufferAccess, BufferId, GraphBuilder,
+            GraphContext, ImageAccess, ImageId, Node, NodeBuffer, NodeDesc, NodeId, NodeImage,
+        },
+        hal::{
+            self, buffer, command::RawCommandBuffer, device::Device, format, image, pso,
+            PhysicalDevice,
+        },
+        memory::Write,
+        resource::{
+            Buffer, BufferInfo, DescriptorSet, DescriptorSetLayout, Escape, Handle as RendyHandle,
+            ImageView, ImageViewInfo,
+        },
+        shader::SpirvShader,
+    },
+    types::Backend,
+    util,
+};
+use amethyst_core::ecs::Resources;
+use derivative::Derivative;
+use std::sync::Arc;
+
+/// How the shader of a `ComputeNodeDesc` accesses a binding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ComputeAccess {
+    /// The shader only reads the binding.
+    Read,
+    /// The shader only writes the binding.
+    Write,
+    /// The shader reads and writes the binding.
+    ReadWrite,
+}
+
+impl ComputeAccess {
+    /// Access of a storage buffer of the graph by a compute shader.
+    pub fn buffer_access(self) -> BufferAccess {
+        let access = match self {
+            ComputeAccess::Read => buffer::Access::SHADER_READ,
+            ComputeAccess::Write => buffer::Access::SHADER_WRITE,
+            ComputeAccess::ReadWrite => buffer::Access::SHADER_READ | buffer::Access::SHADER_WRITE,
+        };
+        BufferAccess {
+            access,
+            usage: buffer::Usage::STORAGE,
+            stages: pso::PipelineStage::COMPUTE_SHADER,
+        }
+    }
+
+    /// Access of a storage image of the graph by a compute shader, in the `General` layout.
+    pub fn image_access(self) -> ImageAccess {
+        let access = match self {
+            ComputeAccess::Read => image::Access::SHADER_READ,
+            ComputeAccess::Write => image::Access::SHADER_WRITE,
+            ComputeAccess::ReadWrite => image::Access::SHADER_READ | image::Access::SHADER_WRITE,
+        };
+        ImageAccess {
+            access,
+            usage: image::Usage::STORAGE,
+            layout: image::Layout::General,
+            stages: pso::PipelineStage::COMPUTE_SHADER,
+        }
+    }
+}
+
+/// Access of a render group drawing with the indirect commands of a buffer written by a compute
+/// node, to return from `RenderGroupDesc::buffers`.
+pub fn indirect_draw_access() -> BufferAccess {
+    BufferAccess {
+        access: buffer::Access::INDIRECT_COMMAND_READ,
+        usage: buffer::Usage::INDIRECT,
+        stages: pso::PipelineStage::DRAW_INDIRECT,
+    }
+}
+
+/// Access of a render group binding a buffer written by a compute node as vertex buffer, to
+/// return from `RenderGroupDesc::buffers`.
+pub fn vertex_buffer_access() -> BufferAccess {
+    BufferAccess {
+        access: buffer::Access::VERTEX_BUFFER_READ,
+        usage: buffer::Usage::VERTEX,
+        stages: pso::PipelineStage::VERTEX_INPUT,
+    }
+}
+
+/// Number of workgroups of `local_size` invocations needed for one invocation per item.
+pub fn workgroups(items: u32, local_size: u32) -> u32 {
+    (items + local_size - 1) / local_size
+}
+
+/// Fills a host binding with the data of the frame.
+pub type FillHost = Arc<dyn Fn(&Resources, &mut [u8]) + Send + Sync>;
+
+/// Reads a readback binding once the frame it was written in completed.
+pub type ReadHost = Arc<dyn Fn(&Resources, &[u8]) + Send + Sync>;
+
+/// Returns the version of the data of a retained binding, which is filled again when it changes.
+pub type Version = Arc<dyn Fn(&Resources) -> u64 + Send + Sync>;
+
+/// Writes the push constants of the frame and returns the number of workgroups to dispatch.
+pub type Dispatch = Arc<dyn Fn(&Resources, &mut [u32]) -> [u32; 3] + Send + Sync>;
+
+/// A storage binding of the shader of a `ComputeNodeDesc`.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub enum ComputeBinding {
+    /// A buffer of the graph.
+    Buffer(BufferId, ComputeAccess),
+    /// A color image of the graph, in the `General` layout.
+    Image(ImageId, ComputeAccess),
+    /// A color image of the graph only read by the shader, sampled with `OpImageFetch`.
+    Sampled(ImageId),
+    /// A buffer of the given size in bytes owned by the node and filled every frame.
+    Host(u64, #[derivative(Debug = "ignore")] FillHost),
+    /// A buffer of the given size in bytes owned by the node and filled only when the version
+    /// of its data changes.
+    Retained(
+        u64,
+        #[derivative(Debug = "ignore")] Version,
+        #[derivative(Debug = "ignore")] FillHost,
+    ),
+    /// A buffer of the given size in bytes owned by the node, written by the shader and read
+    /// when the frame completed.
+    Readback(u64, #[derivative(Debug = "ignore")] ReadHost),
+}
+
+impl ComputeBinding {
+    /// The size of the buffer owned by the node for host, retained and readback bindings.
+    fn host_size(&self) -> Option<u64> {
+        match self {
+            ComputeBinding::Host(size, _)
+            | ComputeBinding::Retained(size, ..)
+            | ComputeBinding::Readback(size, _) => Some(*size),
+            _ => None,
+        }
+    }
+
+    fn descriptor_type(&self) -> pso::DescriptorType {
+        match self {
+            ComputeBinding::Image(..) => pso::DescriptorType::StorageImage,
+            ComputeBinding::Sampled(..) => pso::DescriptorType::SampledImage,
+            _ => pso::DescriptorType::StorageBuffer,
+        }
+    }
+
+    /// Access of the image of image and sampled bindings, with the feature its format needs.
+    fn image_access(&self) -> Option<(ImageAccess, format::ImageFeature)> {
+        match self {
+            ComputeBinding::Image(_, access) => {
+                Some((access.image_access(), format::ImageFeature::STORAGE))
+            }
+            ComputeBinding::Sampled(_) => Some((
+                ImageAccess {
+                    access: image::Access::SHADER_READ,
+                    usage: image::Usage::SAMPLED,
+                    layout: image::Layout::ShaderReadOnlyOptimal,
+                    stages: pso::PipelineStage::COMPUTE_SHADER,
+                },
+                format::ImageFeature::SAMPLED,
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Describes a render graph node dispatching a compute shader, see the module documentation.
+#[derive(Derivative, Clone)]
+#[derivative(Debug)]
+pub struct ComputeNodeDesc {
+    shader: SpirvShader,
+    bindings: Vec<ComputeBinding>,
+    push_constants: u32,
+    #[derivative(Debug = "ignore")]
+    dispatch: Dispatch,
+}
+
+impl ComputeNodeDesc {
+    /// Dispatch a single workgroup of the compute shader `shader` every frame.
+    pub fn new(shader: SpirvShader) -> Self {
+        Self {
+            shader,
+            bindings: Vec::new(),
+            push_constants: 0,
+            dispatch: Arc::new(|_, _| [1, 1, 1]),
+        }
+    }
+
+    /// Bind the next binding of set 0.
+    pub fn with_binding(mut self, binding: ComputeBinding) -> Self {
+        self.bindings.push(binding);
+        self
+    }
+
+    /// Give the shader `words` 32 bit words of push constants, written by the dispatch function.
+    pub fn with_push_constants(mut self, words: u32) -> Self {
+        self.push_constants = words;
+        self
+    }
+
+    /// Decide the push constants and the number of workgroups every frame with `dispatch`.
+    pub fn with_dispatch<F>(mut self, dispatch: F) -> Self
+    where
+        F: Fn(&Resources, &mut [u32]) -> [u32; 3] + Send + Sync + 'static,
+    {
+        self.dispatch = Arc::new(dispatch);
+        self
+    }
+
+    /// Add the node to `graph_builder`, with its buffers and images, running after
+    /// `dependencies`.
+    pub fn add_to<B: Backend>(
+        self,
+        graph_builder: &mut GraphBuilder<B, Resources>,
+        dependencies: &[NodeId],
+    ) -> NodeId {
+        let bindings = self.bindings.clone();
+        let mut builder = NodeDesc::<B, Resources>::builder(self);
+        for binding in bindings {
+            builder = match binding {
+                ComputeBinding::Buffer(id, _) => builder.with_buffer(id),
+                ComputeBinding::Image(id, _) | ComputeBinding::Sampled(id) => {
+                    builder.with_image(id)
+                }
+                ComputeBinding::Host(..)
+                | ComputeBinding::Retained(..)
+                | ComputeBinding::Readback(..) => builder,
+            };
+        }
+        for &dependency in dependencies {
+            builder = builder.with_dependency(dependency);
+        }
+        graph_builder.add_node(builder)
+    }
+}
+
+/// Render graph node built by `ComputeNodeDesc`.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+pub struct ComputeNode<B: Backend> {
+    pipeline: B::ComputePipeline,
+    pipeline_layout: B::PipelineLayout,
+    set_layout: RendyHandle<DescriptorSetLayout<B>>,
+    #[derivative(Debug = "ignore")]
+    pool: CommandPool<B, Compute, IndividualReset>,
+    slots: Vec<ComputeSlot<B>>,
+    views: Vec<Escape<ImageView<B>>>,
+    buffers: Vec<NodeBuffer>,
+    images: Vec<NodeImage>,
+    #[derivative(Debug = "ignore")]
+    host: Vec<(usize, (Option<Version>, FillHost))>,
+    #[derivative(Debug = "ignore")]
+    readback: Vec<(usize, ReadHost)>,
+    push_constants: Vec<u32>,
+    #[derivative(Debug = "ignore")]
+    dispatch: Dispatch,
+}
+
+/// Descriptor set, host buffers and command buffer of one frame in flight.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+struct ComputeSlot<B: Backend> {
+    set: Escape<DescriptorSet<B>>,
+    host_buffers: Vec<Escape<Buffer<B>>>,
+    /// Versions of the data of the retained buffers of the slot.
+    versions: Vec<Option<u64>>,
+    #[derivative(Debug = "ignore")]
+    command_buffer: Option<SlotCommands<B>>,
+}
+
+enum SlotCommands<B: Backend> {
+    Initial(CommandBuffer<B, Compute, InitialState, PrimaryLevel, IndividualReset>),
+    Pending(CommandBuffer<B, Compute, PendingOnceState, PrimaryLevel, IndividualReset>),
+}
+
+impl<B: Backend> NodeDesc<B, Resources> for ComputeNodeDesc {
+    type Node = ComputeNode<B>;
+
+    fn buffers(&self) -> Vec<BufferAccess> {
+        self.bindings
+            .iter()
+            .filter_map(|binding| match binding {
+                ComputeBinding::Buffer(_, access) => Some(access.buffer_access()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn images(&self) -> Vec<ImageAccess> {
+        self.bindings
+            .iter()
+            .filter_map(|binding| binding.image_access().map(|(access, _)| access))
+            .collect()
+    }
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        family: &mut Family<B>,
+        _queue: usize,
+        aux: &Resources,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Self::Node, failure::Error> {
+        let set_layout: RendyHandle<DescriptorSetLayout<B>> =
+            factory
+                .create_descriptor_set_layout(util::set_layout_bindings(self.bindings.iter().map(
+                    |binding| (1, binding.descriptor_type(), pso::ShaderStageFlags::COMPUTE),
+                )))?
+                .into();
+        let (pipeline, pipeline_layout) = util::compute_pipeline(
+            factory,
+            aux,
+            &self.shader,
+            &[set_layout.raw()],
+            self.push_constants * 4,
+        )?;
+
+        let mut views = Vec::new();
+        let features = self
+            .bindings
+            .iter()
+            .filter_map(|binding| binding.image_access().map(|(_, feature)| feature));
+        for (node_image, feature) in images.iter().zip(features) {
+            let image = ctx
+                .get_image(node_image.id)
+                .expect("Image of a compute node is missing")
+                .clone();
+            let format = image.format();
+            if !factory
+                .physical()
+                .format_properties(Some(format))
+                .optimal_tiling
+                .contains(feature)
+            {
+                failure::bail!(
+                    "Images of format {:?} can't be used by compute nodes for {:?}",
+                    format,
+                    feature
+                );
+            }
+            views.push(factory.create_image_view(
+                image,
+                ImageViewInfo {
+                    view_kind: image::ViewKind::D2,
+                    format,
+                    swizzle: format::Swizzle::NO,
+                    range: image::SubresourceRange {
+                        aspects: format::Aspects::COLOR,
+                        levels: 0..1,
+                        layers: 0..1,
+                    },
+                },
+            )?);
+        }
+
+        let mut pool = factory
+            .create_command_pool(family)?
+            .with_capability::<Compute>()
+            .map_err(|_| failure::format_err!("The queue of a compute node can't compute"))?;
+        let command_buffers = pool.allocate_buffers(ctx.frames_in_flight() as usize);
+
+        let mut slots = Vec::new();
+        for command_buffer in command_buffers {
+            let set = factory.create_descriptor_set(set_layout.clone())?;
+            let mut host_buffers = Vec::new();
+            for binding in &self.bindings {
+                if let Some(size) = binding.host_size() {
+                    let info = BufferInfo {
+                        size,
+                        usage: buffer::Usage::STORAGE,
+                    };
+                    host_buffers.push(match binding {
+                        ComputeBinding::Readback(..) => {
+                            factory.create_buffer(info, rendy::memory::Download)?
+                        }
+                        _ => factory.create_buffer(info, rendy::memory::Dynamic)?,
+                    });
+                }
+            }
+
+            let (mut graph_buffers, mut views_iter, mut host_iter) =
+                (buffers.iter(), views.iter(), host_buffers.iter());
+            let writes: Vec<_> = self
+                .bindings
+                .iter()
+                .enumerate()
+                .map(|(binding, kind)| {
+                    let descriptor = match kind {
+                        ComputeBinding::Buffer(..) => {
+                            let node_buffer = graph_buffers.next().unwrap();
+                            let buffer = ctx
+                                .get_buffer(node_buffer.id)
+                                .expect("Buffer of a compute node is missing");
+                            pso::Descriptor::Buffer(
+                                buffer.raw(),
+                                util::opt_range(node_buffer.range.clone()),
+                            )
+                        }
+                        ComputeBinding::Image(..) => pso::Descriptor::Image(
+                            views_iter.next().unwrap().raw(),
+                            image::Layout::General,
+                        ),
+                        ComputeBinding::Sampled(..) => pso::Descriptor::Image(
+                            views_iter.next().unwrap().raw(),
+                            image::Layout::ShaderReadOnlyOptimal,
+                        ),
+                        ComputeBinding::Host(..)
+                        | ComputeBinding::Retained(..)
+                        | ComputeBinding::Readback(..) => {
+                            pso::Descriptor::Buffer(host_iter.next().unwrap().raw(), None..None)
+                        }
+                    };
+                    util::desc_write(set.raw(), binding as u32, descriptor)
+                })
+                .collect();
+            unsafe {
+                factory.write_descriptor_sets(writes);
+            }
+
+            slots.push(ComputeSlot {
+                set,
+                versions: vec![None; host_buffers.len()],
+                host_buffers,
+                command_buffer: Some(SlotCommands::Initial(command_buffer)),
+            });
+        }
+
+        // Indices of the host, retained and readback bindings in the buffers of a slot.
+        let host_bindings = self
+            .bindings
+            .iter()
+            .filter(|binding| binding.host_size().is_some())
+            .enumerate();
+        let host = host_bindings
+            .clone()
+            .filter_map(|(buffer, binding)| match binding {
+                ComputeBinding::Host(_, fill) => Some((buffer, (None, fill.clone()))),
+                ComputeBinding::Retained(_, version, fill) => {
+                    Some((buffer, (Some(version.clone()), fill.clone())))
+                }
+                _ => None,
+            })
+            .collect();
+        let readback = host_bindings
+            .filter_map(|(buffer, binding)| match binding {
+                ComputeBinding::Readback(_, read) => Some((buffer, read.clone())),
+                _ => None,
+            })
+            .collect();
+
+        Ok(ComputeNode {
+            pipeline,
+            pipeline_layout,
+            set_layout,
+            pool,
+            slots,
+            views,
+            buffers,
+            images,
+            host,
+            readback,
+            push_constants: vec![0; self.push_constants as usize],
+            dispatch: self.dispatch,
+        })
+    }
+}
+
+impl<B: Backend> Node<B, Resources> for ComputeNode<B> {
+    type Capability = Compute;
+
+    fn run<'a>(
+        &mut self,
+        ctx: &GraphContext<B>,
+        factory: &Factory<B>,
+        queue: &mut Queue<B>,
+        aux: &Resources,
+        frames: &Frames<B>,
+        waits: &[(&'a B::Semaphore, hal::pso::PipelineStage)],
+        signals: &[&'a B::Semaphore],
+        fence: Option<&mut Fence<B>>,
+    ) {
+        // The graph waited for the last frame of this slot to complete.
+        let index = (frames.next().index() % self.slots.len() as u64) as usize;
+        let slot = &mut self.slots[index];
+
+        for (buffer, (version, fill)) in &self.host {
+            let version = version.as_ref().map(|version| version(aux));
+            if version.is_some() && slot.versions[*buffer] == version {
+                continue;
+            }
+            slot.versions[*buffer] = version;
+            let host_buffer = &mut slot.host_buffers[*buffer];
+            let size = host_buffer.size();
+            let mut mapped = host_buffer.map(factory.device(), 0..size).unwrap();
+            let mut writer = unsafe { mapped.write::<u8>(factory.device(), 0..size).unwrap() };
+            fill(aux, unsafe { writer.slice() });
+        }
+        // The slot ran before, its readback buffers hold the results of its last frame.
+        let ran = match slot.command_buffer {
+            Some(SlotCommands::Pending(_)) => true,
+            _ => false,
+        };
+        for (buffer, read) in self.readback.iter().filter(|_| ran) {
+            let host_buffer = &mut slot.host_buffers[*buffer];
+            let size = host_buffer.size();
+            let mut mapped = host_buffer.map(factory.device(), 0..size).unwrap();
+            let data = unsafe { mapped.read::<u8>(factory.device(), 0..size).unwrap() };
+            read(aux, data);
+        }
+        let [x, y, z] = (self.dispatch)(aux, &mut self.push_constants);
+
+        let initial = match slot.command_buffer.take() {
+            Some(SlotCommands::Initial(command_buffer)) => command_buffer,
+            Some(SlotCommands::Pending(command_buffer)) => command_buffer.mark_complete().reset(),
+            None => unreachable!("Compute command buffer is being recorded"),
+        };
+        let mut recording = initial.begin(OneShot, ());
+        {
+            let mut encoder = recording.encoder();
+            let (stages, barriers) = gfx_acquire_barriers(ctx, &self.buffers, &self.images);
+            unsafe {
+                if !barriers.is_empty() {
+                    encoder.pipeline_barrier(stages, hal::memory::Dependencies::empty(), barriers);
+                }
+                encoder.bind_compute_pipeline(&self.pipeline);
+                encoder.bind_compute_descriptor_sets(
+                    &self.pipeline_layout,
+                    0,
+                    Some(slot.set.raw()),
+                    std::iter::empty(),
+                );
+                if !self.push_constants.is_empty() {
+                    encoder.raw().push_compute_constants(
+                        &self.pipeline_layout,
+                        0,
+                        &self.push_constants,
+                    );
+                }
+                if x > 0 && y > 0 && z > 0 {
+                    encoder.dispatch(x, y, z);
+                }
+                if !self.readback.is_empty() {
+                    encoder.pipeline_barrier(
+                        pso::PipelineStage::COMPUTE_SHADER..pso::PipelineStage::HOST,
+                        hal::memory::Dependencies::empty(),
+                        Some(hal::memory::Barrier::AllBuffers(
+                            buffer::Access::SHADER_WRITE..buffer::Access::HOST_READ,
+                        )),
+                    );
+                }
+            }
+            let (stages, barriers) = gfx_release_barriers(ctx, &self.buffers, &self.images);
+            if !barriers.is_empty() {
+                unsafe {
+                    encoder.pipeline_barrier(stages, hal::memory::Dependencies::empty(), barriers);
+                }
+            }
+        }
+        let (submit, pending) = recording.finish().submit_once();
+        slot.command_buffer = Some(SlotCommands::Pending(pending));
+
+        unsafe {
+            queue.submit(
+                Some(
+                    Submission::new()
+                        .wait(waits.iter().cloned())
+                        .submits(Some(submit))
+                        .signal(signals.iter().cloned()),
+                ),
+                fence,
+            );
+        }
+    }
+
+    unsafe fn dispose(mut self, factory: &mut Factory<B>, _aux: &Resources) {
+        for slot in self.slots.drain(..) {
+            match slot.command_buffer {
+                Some(SlotCommands::Initial(command_buffer)) => {
+                    self.pool.free_buffers(Some(command_buffer))
+                }
+                Some(SlotCommands::Pending(command_buffer)) => {
+                    self.pool.free_buffers(Some(command_buffer.mark_complete()))
+                }
+                None => {}
+            }
+        }
+        factory.destroy_command_pool(self.pool);
+        factory.device().destroy_compute_pipeline(self.pipeline);
+        factory
+            .device()
+            .destroy_pipeline_layout(self.pipeline_layout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workgroup_counts() {
+        assert_eq!(workgroups(0, 64), 0);
+        assert_eq!(workgroups(1, 64), 1);
+        assert_eq!(workgroups(64, 64), 1);
+        assert_eq!(workgroups(65, 64), 2);
+    }
+
+    #[test]
+    fn indirect_draws_wait_for_compute_writes() {
+        let write = ComputeAccess::Write.buffer_access();
+        let read = indirect_draw_access();
+        assert!(write.access.contains(buffer::Access::SHADER_WRITE));
+        assert_eq!(write.stages, pso::PipelineStage::COMPUTE_SHADER);
+        assert!(write.usage.contains(buffer::Usage::STORAGE));
+        assert!(read.access.contains(buffer::Access::INDIRECT_COMMAND_READ));
+        assert_eq!(read.stages, pso::PipelineStage::DRAW_INDIRECT);
+        assert!(read.usage.contains(buffer::Usage::INDIRECT));
+
+        let output = GraphBuilder::<rendy::empty::Backend, Resources>::new().create_buffer(16);
+        let desc = ComputeNodeDesc::new(SpirvShader::new(
+            Vec::new(),
+            pso::ShaderStageFlags::COMPUTE,
+            "main",
+        ))
+        .with_binding(ComputeBinding::Host(16, Arc::new(|_, _| {})))
+        .with_binding(ComputeBinding::Retained(
+            32,
+            Arc::new(|_| 0),
+            Arc::new(|_, _| {}),
+        ))
+        .with_binding(ComputeBinding::Buffer(output, ComputeAccess::Write));
+        let sizes: Vec<_> = desc
+            .bindings
+            .iter()
+            .map(ComputeBinding::host_size)
+            .collect();
+        assert_eq!(sizes, vec![Some(16), Some(32), None]);
+        let accesses = NodeDesc::<rendy::empty::Backend, Resources>::buffers(&desc);
+        assert_eq!(accesses.len(), 1);
+        assert_eq!(accesses[0].access, write.access);
+        assert!(NodeDesc::<rendy::empty::Backend, Resources>::images(&desc).is_empty());
+    }
+
+    #[test]
+    fn sampled_images_are_read_only() {
+        let mut graph = GraphBuilder::<rendy::empty::Backend, Resources>::new();
+        let color = graph.create_image(
+            image::Kind::D2(64, 64, 1, 1),
+            1,
+            format::Format::Rgba8Srgb,
+            None,
+        );
+        let desc = ComputeNodeDesc::new(SpirvShader::new(
+            Vec::new(),
+            pso::ShaderStageFlags::COMPUTE,
+            "main",
+        ))
+        .with_binding(ComputeBinding::Sampled(color))
+        .with_binding(ComputeBinding::Host(4, Arc::new(|_, _| {})))
+        .with_binding(ComputeBinding::Readback(4, Arc::new(|_, _| {})));
+        let sizes: Vec<_> = desc
+            .bindings
+            .iter()
+            .map(ComputeBinding::host_size)
+            .collect();
+        assert_eq!(sizes, vec![None, Some(4), Some(4)]);
+        assert_eq!(
+            desc.bindings[0].descriptor_type(),
+            pso::DescriptorType::SampledImage
+        );
+        let accesses = NodeDesc::<rendy::empty::Backend, Resources>::images(&desc);
+        assert_eq!(accesses.len(), 1);
+        assert_eq!(accesses[0].access, image::Access::SHADER_READ);
+        assert_eq!(accesses[0].usage, image::Usage::SAMPLED);
+        assert_eq!(accesses[0].layout, image::Layout::ShaderReadOnlyOptimal);
+        assert!(NodeDesc::<rendy::empty::Backend, Resources>::buffers(&desc).is_empty());
+    }
+}
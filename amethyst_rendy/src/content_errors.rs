@@ -0,0 +1,146 @@
+//! Errors in the content drawn, reported instead of panicking during pass execution.
+//!
+//! A sprite number out of the range of its sheet or a mesh without the vertex attributes of a
+//! pass is an error of the assets or components of a game, which shouldn't take it down. The
+//! render groups skip the draws they can't make, and report a `RenderError` carrying the entity
+//! and asset handle ids they know to the `ContentErrors` resource. Each offender is logged once,
+//! then again after `REPEAT_INTERVAL` if it keeps failing, with the number of reports in between.
+//!
+//! Tests catching content errors hard insert `ContentErrors::strict()` before the
+//! `RenderingSystem` is set up, which makes reporting panic.
+use crate::error::RenderError;
+use amethyst_core::ecs::Resources;
+use fnv::FnvHashMap;
+use std::time::{Duration, Instant};
+
+/// Interval after which an offender failing again is logged again.
+pub const REPEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Resource with the content errors reported by the render groups.
+#[derive(Debug, Default)]
+pub struct ContentErrors {
+    /// Panic on content errors, instead of logging them and skipping the draws.
+    pub strict: bool,
+    count: u64,
+    last_error: Option<RenderError>,
+    offenders: FnvHashMap<String, Offender>,
+}
+
+/// When an offender was last logged, and how many of its reports weren't.
+#[derive(Debug)]
+struct Offender {
+    logged: Instant,
+    suppressed: u64,
+}
+
+impl ContentErrors {
+    /// Content errors panicking when reported.
+    pub fn strict() -> Self {
+        Self {
+            strict: true,
+            ..Self::default()
+        }
+    }
+
+    /// Number of content errors reported since startup.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The last content error reported.
+    pub fn last_error(&self) -> Option<&RenderError> {
+        self.last_error.as_ref()
+    }
+
+    /// Report a content error, logging it unless its offender was logged recently. Panics in
+    /// strict mode.
+    pub fn report(&mut self, error: RenderError) {
+        if let Some((error, suppressed)) = self.record(error, Instant::now()) {
+            if suppressed > 0 {
+                log::error!("{} (reported {} more times)", error, suppressed);
+            } else {
+                log::error!("{}", error);
+            }
+        }
+    }
+
+    /// Count `error`, returning its message and the reports suppressed since it was last logged,
+    /// if it's to be logged.
+    fn record(&mut self, error: RenderError, now: Instant) -> Option<(String, u64)> {
+        let message = error.to_string();
+        if self.strict {
+            panic!("Content error: {}", message);
+        }
+        self.count += 1;
+        self.last_error = Some(error);
+
+        match self.offenders.get_mut(&message) {
+            Some(offender) if now.duration_since(offender.logged) < REPEAT_INTERVAL => {
+                offender.suppressed += 1;
+                None
+            }
+            Some(offender) => {
+                let suppressed = offender.suppressed;
+                *offender = Offender {
+                    logged: now,
+                    suppressed: 0,
+                };
+                Some((message, suppressed))
+            }
+            None => {
+                self.offenders.insert(
+                    message.clone(),
+                    Offender {
+                        logged: now,
+                        suppressed: 0,
+                    },
+                );
+                Some((message, 0))
+            }
+        }
+    }
+}
+
+/// Report a content error to the `ContentErrors` resource, or log it without the resource.
+pub(crate) fn report(resources: &Resources, error: RenderError) {
+    match resources.try_fetch_mut::<ContentErrors>() {
+        Some(mut errors) => errors.report(error),
+        None => log::error!("{}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite_error(entity: u32) -> RenderError {
+        RenderError::SpriteOutOfRange {
+            entity: Some(entity),
+            sprite_sheet: 3,
+            sprite_number: 8,
+            sprite_count: 4,
+        }
+    }
+
+    #[test]
+    fn offenders_are_logged_at_intervals() {
+        let mut errors = ContentErrors::default();
+        let start = Instant::now();
+
+        assert!(errors.record(sprite_error(1), start).is_some());
+        assert!(errors.record(sprite_error(1), start).is_none());
+        assert!(errors.record(sprite_error(2), start).is_some());
+        let later = start + REPEAT_INTERVAL;
+        assert_eq!(errors.record(sprite_error(1), later).unwrap().1, 1);
+        assert!(errors.record(sprite_error(1), later).is_none());
+
+        assert_eq!(errors.count(), 5);
+        assert_eq!(errors.last_error(), Some(&sprite_error(1)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn strict_mode_panics() {
+        ContentErrors::strict().report(sprite_error(1));
+    }
+}
@@ -0,0 +1,171 @@
+//! Pausing and stepping the rendering of frames.
+//!
+//! The `RenderingSystem` runs the graph according to the `RenderControl` resource. Assets keep
+//! being uploaded while it doesn't. Rendering is also paused while the window is minimized to a
+//! zero extent, and resumes when it is restored. Every change of the `RenderState` is written to
+//! the `EventChannel<RenderControlEvent>` resource, for other systems to throttle as well.
+use serde::{Deserialize, Serialize};
+
+/// How the `RenderingSystem` renders frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RenderMode {
+    /// Render every frame.
+    Running,
+    /// Render no frames.
+    Paused,
+    /// Render one frame for every `RenderControl::step`, for debugging.
+    SingleStep,
+}
+
+impl Default for RenderMode {
+    fn default() -> Self {
+        RenderMode::Running
+    }
+}
+
+/// Whether frames are rendered, as decided by the `RenderMode` and the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RenderState {
+    /// Frames are rendered.
+    Running,
+    /// No frames are rendered, as the mode is `Paused`.
+    Paused,
+    /// No frames are rendered, as the window is minimized.
+    Minimized,
+    /// Frames are rendered one `RenderControl::step` at a time.
+    SingleStep,
+}
+
+/// Event written when the `RenderState` changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderControlEvent {
+    /// The state before the change.
+    pub previous: RenderState,
+    /// The state after the change.
+    pub current: RenderState,
+}
+
+/// Resource controlling when the `RenderingSystem` renders frames.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderControl {
+    /// How frames are rendered.
+    pub mode: RenderMode,
+    #[serde(skip)]
+    steps: u32,
+    #[serde(skip)]
+    state: Option<RenderState>,
+}
+
+impl RenderControl {
+    /// Stop rendering frames.
+    pub fn pause(&mut self) {
+        self.mode = RenderMode::Paused;
+    }
+
+    /// Render every frame again.
+    pub fn resume(&mut self) {
+        self.mode = RenderMode::Running;
+    }
+
+    /// Render exactly one more frame, switching to `SingleStep`.
+    pub fn step(&mut self) {
+        self.mode = RenderMode::SingleStep;
+        self.steps += 1;
+    }
+
+    /// The state of the last frame.
+    pub fn state(&self) -> RenderState {
+        self.state.unwrap_or(RenderState::Running)
+    }
+
+    /// Update the state for a frame, with whether the window is minimized. Returns whether the
+    /// frame is rendered, and the change of state if there was one.
+    pub(crate) fn frame(&mut self, minimized: bool) -> (bool, Option<RenderControlEvent>) {
+        let state = match self.mode {
+            _ if minimized => RenderState::Minimized,
+            RenderMode::Running => RenderState::Running,
+            RenderMode::Paused => RenderState::Paused,
+            RenderMode::SingleStep => RenderState::SingleStep,
+        };
+        let event = match self.state.replace(state) {
+            Some(previous) if previous != state => Some(RenderControlEvent {
+                previous,
+                current: state,
+            }),
+            None if state != RenderState::Running => Some(RenderControlEvent {
+                previous: RenderState::Running,
+                current: state,
+            }),
+            _ => None,
+        };
+
+        let render = match state {
+            RenderState::Running => true,
+            RenderState::Paused | RenderState::Minimized => false,
+            RenderState::SingleStep if self.steps > 0 => {
+                self.steps -= 1;
+                true
+            }
+            RenderState::SingleStep => false,
+        };
+        (render, event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_steps() {
+        let mut control = RenderControl::default();
+        assert_eq!(control.frame(false), (true, None));
+
+        control.step();
+        control.step();
+        let (render, event) = control.frame(false);
+        assert!(render);
+        assert_eq!(
+            event,
+            Some(RenderControlEvent {
+                previous: RenderState::Running,
+                current: RenderState::SingleStep,
+            })
+        );
+        assert_eq!(control.frame(false), (true, None));
+        assert_eq!(control.frame(false), (false, None));
+
+        control.resume();
+        assert!(control.frame(false).0);
+    }
+
+    #[test]
+    fn pauses_while_minimized() {
+        let mut control = RenderControl::default();
+        assert_eq!(
+            control.frame(true),
+            (
+                false,
+                Some(RenderControlEvent {
+                    previous: RenderState::Running,
+                    current: RenderState::Minimized,
+                })
+            )
+        );
+        assert_eq!(control.frame(true), (false, None));
+        assert_eq!(control.state(), RenderState::Minimized);
+
+        control.pause();
+        assert_eq!(
+            control.frame(false),
+            (
+                false,
+                Some(RenderControlEvent {
+                    previous: RenderState::Minimized,
+                    current: RenderState::Paused,
+                })
+            )
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! Frustum culling of indirect draws on the GPU, a built-in compute node.
+//!
+//! Every frame, the draws of the `GpuCullingInput` resource are copied by a compute shader into a
+//! buffer of the graph, as `DrawIndexedCommand`s ready for `draw_indexed_indirect`, with the
+//! instance count of the draws whose bounding sphere is outside the frustum set to zero. The
+//! render group drawing them declares `compute::indirect_draw_access` for the buffer, and draws
+//! the first `GpuCullingInput::len` commands.
+use crate::{
+    compute::{workgroups, ComputeAccess, ComputeBinding, ComputeNodeDesc},
+    rendy::{graph::BufferId, hal::pso::ShaderStageFlags, shader::SpirvShader},
+    util,
+    visibility::Frustum,
+};
+use amethyst_core::{
+    ecs::Resources,
+    math::{convert, Vector4},
+};
+use std::sync::Arc;
+
+/// Invocations of a workgroup of the culling shader.
+const LOCAL_SIZE: u32 = 64;
+
+/// Size in bytes of a `DrawIndexedCommand`.
+pub const DRAW_COMMAND_SIZE: u64 = std::mem::size_of::<DrawIndexedCommand>() as u64;
+
+lazy_static::lazy_static! {
+    static ref FRUSTUM_CULL_COMPUTE: SpirvShader = SpirvShader::new(
+        include_bytes!("../compiled/compute/frustum_cull.comp.spv").to_vec(),
+        ShaderStageFlags::COMPUTE,
+        "main",
+    );
+}
+
+/// Parameters of an indexed indirect draw, laid out as the GPU reads them.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DrawIndexedCommand {
+    /// Number of indices drawn.
+    pub index_count: u32,
+    /// Number of instances drawn.
+    pub instance_count: u32,
+    /// First index drawn.
+    pub first_index: u32,
+    /// Value added to the indices.
+    pub vertex_offset: i32,
+    /// First instance drawn.
+    pub first_instance: u32,
+}
+
+/// A draw culled against the frustum with its bounding sphere.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CulledDraw {
+    /// The draw when the sphere is in the frustum.
+    pub command: DrawIndexedCommand,
+    /// Center of the bounding sphere, in the space of the frustum planes.
+    pub center: [f32; 3],
+    /// Radius of the bounding sphere.
+    pub radius: f32,
+}
+
+/// Resource with the draws culled by the node of `frustum_culling_node`, and the frustum they are
+/// culled against. The default frustum culls nothing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GpuCullingInput {
+    /// Planes of the frustum, with the normal pointing inside and the distance from the origin.
+    pub planes: [[f32; 4]; 6],
+    /// The draws, of which only as many as the capacity of the node are culled.
+    pub draws: Vec<CulledDraw>,
+}
+
+impl GpuCullingInput {
+    /// Cull against `frustum`.
+    pub fn set_frustum(&mut self, frustum: &Frustum) {
+        for (plane, frustum_plane) in self.planes.iter_mut().zip(&frustum.planes) {
+            let frustum_plane: Vector4<f32> = convert(*frustum_plane);
+            *plane = frustum_plane.into();
+        }
+    }
+
+    /// Number of draws culled by a node of the given capacity.
+    pub fn len(&self, capacity: u32) -> u32 {
+        self.draws.len().min(capacity as usize) as u32
+    }
+
+    /// The draw commands, as they are before culling.
+    fn commands(&self, capacity: u32) -> Vec<DrawIndexedCommand> {
+        self.draws[..self.len(capacity) as usize]
+            .iter()
+            .map(|draw| draw.command)
+            .collect()
+    }
+
+    /// The bounding spheres, as `vec4`s with the radius last.
+    fn spheres(&self, capacity: u32) -> Vec<[f32; 4]> {
+        self.draws[..self.len(capacity) as usize]
+            .iter()
+            .map(|draw| [draw.center[0], draw.center[1], draw.center[2], draw.radius])
+            .collect()
+    }
+
+    /// Push constants of the shader: the planes and the number of draws.
+    fn push_constants(&self, capacity: u32, constants: &mut [u32]) {
+        for (constant, value) in constants.iter_mut().zip(self.planes.iter().flatten()) {
+            *constant = value.to_bits();
+        }
+        constants[24] = self.len(capacity);
+    }
+}
+
+fn fill<T>(target: &mut [u8], items: &[T]) {
+    let bytes = util::slice_as_bytes(items);
+    target[..bytes.len()].copy_from_slice(bytes);
+}
+
+/// Compute node culling up to `capacity` draws of the `GpuCullingInput` resource into `output`,
+/// a buffer of the graph of at least `capacity * DRAW_COMMAND_SIZE` bytes.
+pub fn frustum_culling_node(output: BufferId, capacity: u32) -> ComputeNodeDesc {
+    let capacity = capacity.max(1);
+    ComputeNodeDesc::new(FRUSTUM_CULL_COMPUTE.clone())
+        .with_binding(ComputeBinding::Host(
+            u64::from(capacity) * 16,
+            Arc::new(move |res: &Resources, target: &mut [u8]| {
+                if let Some(input) = res.try_fetch::<GpuCullingInput>() {
+                    fill(target, &input.spheres(capacity));
+                }
+            }),
+        ))
+        .with_binding(ComputeBinding::Host(
+            u64::from(capacity) * DRAW_COMMAND_SIZE,
+            Arc::new(move |res: &Resources, target: &mut [u8]| {
+                if let Some(input) = res.try_fetch::<GpuCullingInput>() {
+                    fill(target, &input.commands(capacity));
+                }
+            }),
+        ))
+        .with_binding(ComputeBinding::Buffer(output, ComputeAccess::Write))
+        .with_push_constants(25)
+        .with_dispatch(move |res, constants| {
+            let draws = match res.try_fetch::<GpuCullingInput>() {
+                Some(input) => {
+                    input.push_constants(capacity, constants);
+                    input.len(capacity)
+                }
+                None => 0,
+            };
+            [workgroups(draws, LOCAL_SIZE), 1, 1]
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst_core::math::Matrix4;
+
+    #[test]
+    fn culling_inputs() {
+        let mut input = GpuCullingInput::default();
+        input.set_frustum(&Frustum::new(Matrix4::identity()));
+        assert_eq!(input.planes[0], [1., 0., 0., 1.]);
+        input.draws = vec![
+            CulledDraw {
+                command: DrawIndexedCommand {
+                    index_count: 6,
+                    instance_count: 1,
+                    ..Default::default()
+                },
+                center: [0.5, 0., 0.],
+                radius: 0.25,
+            };
+            3
+        ];
+        assert_eq!(input.len(2), 2);
+        assert_eq!(input.spheres(2), vec![[0.5, 0., 0., 0.25]; 2]);
+        assert_eq!(input.commands(8).len(), 3);
+
+        let mut constants = [0; 25];
+        input.push_constants(2, &mut constants);
+        assert_eq!(f32::from_bits(constants[0]), 1.);
+        assert_eq!(f32::from_bits(constants[3]), 1.);
+        assert_eq!(constants[24], 2);
+        assert_eq!(DRAW_COMMAND_SIZE, 20);
+    }
+}
@@ -1,36 +1,133 @@
 //! Debug Drawing library
-use crate::pod::IntoPod;
+use crate::{
+    camera::Camera,
+    debug_font::{self, GLYPH_ADVANCE, GLYPH_HEIGHT, GLYPH_WIDTH},
+    deterministic::Deterministic,
+    pod::IntoPod,
+};
 use amethyst_core::{
-    ecs::{Component, DenseVecStorage},
-    math::{Point3, Vector3},
+    ecs::{Component, DenseVecStorage, Read, System, Write},
+    math::{Matrix4, Point2, Point3, Vector3},
+    timing::Time,
+    Transform,
 };
 use palette::Srgba;
-use rendy::mesh::{AsVertex, Color, PosColor, VertexFormat};
+use rendy::{
+    hal::format::Format,
+    mesh::{AsVertex, Color, PosColor, VertexFormat},
+};
+
+/// Returns a unit vector perpendicular to `normal`.
+fn perpendicular(normal: &Vector3<f32>) -> Vector3<f32> {
+    let axis = if normal.x.abs() < 0.9 * normal.norm() {
+        Vector3::x()
+    } else {
+        Vector3::y()
+    };
+    normal.cross(&axis).normalize()
+}
+
+/// Returns the direction from `p0` to `p1`, or +Y if they are equal, and two unit vectors
+/// perpendicular to it.
+fn capsule_basis(p0: Point3<f32>, p1: Point3<f32>) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>) {
+    let axis = (p1 - p0)
+        .try_normalize(std::f32::EPSILON)
+        .unwrap_or_else(Vector3::y);
+    let u = perpendicular(&axis);
+    (axis, u, axis.cross(&u))
+}
 
-/// Debug lines are stored as a pair of position and color.
+/// Adds the lines of `text` starting at `origin`, advancing along `right`, with glyphs
+/// `size` high along `up`.
+#[allow(clippy::too_many_arguments)]
+fn push_text(
+    lines: &mut Vec<DebugLine>,
+    origin: Point3<f32>,
+    right: Vector3<f32>,
+    up: Vector3<f32>,
+    text: &str,
+    color: Srgba,
+    size: f32,
+    width: f32,
+) {
+    let unit = size / GLYPH_HEIGHT;
+    let (right, up) = (right * unit, up * unit);
+    let color = Color(color.into_pod());
+    for (i, c) in text.chars().enumerate() {
+        let base = origin + right * (i as f32 * GLYPH_ADVANCE);
+        debug_font::for_each_line(debug_font::glyph(c), |(x0, y0), (x1, y1)| {
+            let start = base + right * x0 + up * y0;
+            let end = base + right * x1 + up * y1;
+            lines.push(DebugLine::new(
+                PosColor {
+                    position: start.coords.into(),
+                    color,
+                },
+                PosColor {
+                    position: end.coords.into(),
+                    color,
+                },
+                width,
+            ));
+        });
+    }
+}
+
+/// Text waiting to be turned into lines facing the camera.
+#[derive(Debug, Clone, Copy)]
+struct DebugText {
+    position: Point3<f32>,
+    start: usize,
+    end: usize,
+    color: Srgba,
+    size: f32,
+    width: f32,
+}
+
+/// Debug lines are stored as a pair of position and color, and a width.
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 #[repr(C)]
 pub struct DebugLine {
     start: PosColor,
     end: PosColor,
+    /// Width in screen space pixels, zero for the width of `DebugLinesParams`
+    width: f32,
 }
 
 impl AsVertex for DebugLine {
     fn vertex() -> VertexFormat {
-        VertexFormat::new((PosColor::vertex(), PosColor::vertex()))
+        VertexFormat::new((
+            (Format::Rgb32Sfloat, "position_a"),
+            (Format::Rgba32Sfloat, "color_a"),
+            (Format::Rgb32Sfloat, "position_b"),
+            (Format::Rgba32Sfloat, "color_b"),
+            (Format::R32Sfloat, "line_width"),
+        ))
     }
 }
 
 impl DebugLine {
-    fn new(start: PosColor, end: PosColor) -> Self {
-        Self { start, end }
+    fn new(start: PosColor, end: PosColor, width: f32) -> Self {
+        Self { start, end, width }
+    }
+
+    /// Width of the line in screen space pixels, zero for the width of `DebugLinesParams`.
+    pub(crate) fn width(&self) -> f32 {
+        self.width
+    }
+
+    pub(crate) fn set_width(&mut self, width: f32) {
+        self.width = width;
     }
 }
 
-/// Parameters for renderer of debug lines. The params affect all lines.
+/// Parameters for renderer of debug lines. The params affect all lines without a width of their
+/// own, see `DebugLinesComponent::set_line_width`.
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub struct DebugLinesParams {
     /// Width of lines in screen space pixels, default is 1.0 pixel
+    ///
+    /// Lines up to one pixel wide are drawn as plain lines, wider lines as camera facing quads.
     pub line_width: f32,
 }
 
@@ -40,12 +137,45 @@ impl Default for DebugLinesParams {
     }
 }
 
+/// When the lines of a `DebugLinesComponent` are cleared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClearPolicy {
+    /// Lines are kept until `DebugLinesComponent::clear` is called.
+    Manual,
+    /// Lines are cleared after every frame they were rendered in, like those of `DebugLines`.
+    EveryFrame,
+}
+
+impl Default for ClearPolicy {
+    fn default() -> Self {
+        ClearPolicy::Manual
+    }
+}
+
+/// Resource with statistics of the `DrawDebugLines` pass, filled in when present.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DebugLinesStats {
+    /// Number of lines drawn in the last frame.
+    pub lines: usize,
+    /// Size in bytes of the vertex buffer of the last frame, it is reused and grows as needed.
+    pub buffer_bytes: u64,
+}
+
 /// Component that stores persistent debug lines to be rendered in DebugLinesPass draw pass.
-/// The vector can only be cleared manually.
+/// By default the vector can only be cleared manually, see `ClearPolicy`.
 #[derive(Debug, Default)]
 pub struct DebugLinesComponent {
     /// Lines to be rendered
     lines: Vec<DebugLine>,
+    /// Cosine and sine of the points of the last unit circle used by `add_capsule` and
+    /// `add_cylinder`
+    unit_circle: Vec<(f32, f32)>,
+    /// Width of the lines added next, zero for the width of `DebugLinesParams`
+    line_width: f32,
+    /// Labels added with `add_text`, their characters are stored in `text`
+    texts: Vec<DebugText>,
+    text: String,
+    clear_policy: ClearPolicy,
 }
 
 impl Component for DebugLinesComponent {
@@ -62,9 +192,49 @@ impl DebugLinesComponent {
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
             lines: Vec::with_capacity(capacity),
+            ..Default::default()
         }
     }
 
+    /// Reserves capacity for at least `additional` more lines.
+    pub fn reserve(&mut self, additional: usize) {
+        self.lines.reserve(additional);
+    }
+
+    /// Number of lines the component can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.lines.capacity()
+    }
+
+    /// Builder method to set when the lines are cleared, see `ClearPolicy`.
+    pub fn with_clear_policy(mut self, policy: ClearPolicy) -> Self {
+        self.clear_policy = policy;
+        self
+    }
+
+    /// Sets when the lines are cleared, see `ClearPolicy`.
+    pub fn set_clear_policy(&mut self, policy: ClearPolicy) {
+        self.clear_policy = policy;
+    }
+
+    /// Returns when the lines are cleared.
+    pub fn clear_policy(&self) -> ClearPolicy {
+        self.clear_policy
+    }
+
+    /// Builder method to set the width of the lines, see `set_line_width`.
+    pub fn with_line_width(mut self, width: f32) -> Self {
+        self.line_width = width;
+        self
+    }
+
+    /// Sets the width in screen space pixels of the lines added from now on.
+    ///
+    /// Lines added with a width of zero, the default, use the width of `DebugLinesParams`.
+    pub fn set_line_width(&mut self, width: f32) {
+        self.line_width = width;
+    }
+
     /// Adds a line to be rendered by giving a position and a direction.
     pub fn add_direction(&mut self, position: Point3<f32>, direction: Vector3<f32>, color: Srgba) {
         self.add_line(position, position + direction, color);
@@ -92,15 +262,360 @@ impl DebugLinesComponent {
                 position: end.to_homogeneous().xyz().into(),
                 color: Color(end_color.into_pod()),
             },
+            self.line_width,
         );
         self.lines.push(vertex);
     }
 
-    /// Clears lines buffer.
+    /// Adds a circle made of `segments` lines, lying in the plane perpendicular to `normal`.
+    ///
+    /// At least three segments are used.
+    pub fn add_circle(
+        &mut self,
+        center: Point3<f32>,
+        normal: Vector3<f32>,
+        radius: f32,
+        segments: u32,
+        color: Srgba,
+    ) {
+        self.add_arc(
+            center,
+            normal,
+            perpendicular(&normal) * radius,
+            2.0 * std::f32::consts::PI,
+            segments.max(3),
+            color,
+        );
+    }
+
+    /// Adds an arc made of `segments` lines, starting at `center + start` and turning by
+    /// `angle` radians counter clockwise around `normal`.
+    ///
+    /// The part of `start` along `normal` is ignored. At least one segment is used.
+    pub fn add_arc(
+        &mut self,
+        center: Point3<f32>,
+        normal: Vector3<f32>,
+        start: Vector3<f32>,
+        angle: f32,
+        segments: u32,
+        color: Srgba,
+    ) {
+        let normal = normal.normalize();
+        let u = start - normal * start.dot(&normal);
+        let v = normal.cross(&u);
+        let segments = segments.max(1);
+        let step = angle / segments as f32;
+
+        self.lines.reserve(segments as usize);
+        let mut previous = center + u;
+        for i in 1..=segments {
+            let (sin, cos) = (step * i as f32).sin_cos();
+            let point = center + u * cos + v * sin;
+            self.add_line(previous, point, color);
+            previous = point;
+        }
+    }
+
+    /// Adds a wireframe sphere made of three circles of `segments` lines, one around each axis.
+    pub fn add_sphere(&mut self, center: Point3<f32>, radius: f32, segments: u32, color: Srgba) {
+        self.add_circle(center, Vector3::x(), radius, segments, color);
+        self.add_circle(center, Vector3::y(), radius, segments, color);
+        self.add_circle(center, Vector3::z(), radius, segments, color);
+    }
+
+    /// Adds a grid of `cell_count` by `cell_count` square cells of `cell_size`, with a corner at
+    /// `origin` and its sides along the directions `axis_u` and `axis_v`.
+    ///
+    /// Every `major_every`th line, starting with the sides through `origin`, is drawn in
+    /// `major_color`. No line is major when `major_every` is zero.
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_grid(
+        &mut self,
+        origin: Point3<f32>,
+        axis_u: Vector3<f32>,
+        axis_v: Vector3<f32>,
+        cell_size: f32,
+        cell_count: u32,
+        color: Srgba,
+        major_every: usize,
+        major_color: Srgba,
+    ) {
+        let u = axis_u.normalize() * cell_size;
+        let v = axis_v.normalize() * cell_size;
+        let (side_u, side_v) = (u * cell_count as f32, v * cell_count as f32);
+        self.lines.reserve(2 * (cell_count as usize + 1));
+        for i in 0..=cell_count as usize {
+            let color = if major_every != 0 && i % major_every == 0 {
+                major_color
+            } else {
+                color
+            };
+            let start_u = origin + u * i as f32;
+            self.add_line(start_u, start_u + side_v, color);
+            let start_v = origin + v * i as f32;
+            self.add_line(start_v, start_v + side_u, color);
+        }
+    }
+
+    /// Adds the 12 edges of an axis aligned box.
+    pub fn add_box(&mut self, min: Point3<f32>, max: Point3<f32>, color: Srgba) {
+        self.add_box_corners(
+            |x, y, z| {
+                Point3::new(
+                    if x { max.x } else { min.x },
+                    if y { max.y } else { min.y },
+                    if z { max.z } else { min.z },
+                )
+            },
+            color,
+        );
+    }
+
+    /// Adds the 12 edges of an oriented box centered on the origin of `transform`, extending
+    /// `half_extents` along each of its local axes.
+    ///
+    /// Combine with `add_axes` to also show the orientation of the box.
+    pub fn add_obb(&mut self, transform: &Matrix4<f32>, half_extents: Vector3<f32>, color: Srgba) {
+        self.add_box_corners(
+            |x, y, z| {
+                let sign = |b| if b { 1.0 } else { -1.0 };
+                transform.transform_point(&Point3::new(
+                    sign(x) * half_extents.x,
+                    sign(y) * half_extents.y,
+                    sign(z) * half_extents.z,
+                ))
+            },
+            color,
+        );
+    }
+
+    /// Adds an arrow from `start` to `end`, with a head of four lines `head_size` long.
+    pub fn add_arrow(
+        &mut self,
+        start: Point3<f32>,
+        end: Point3<f32>,
+        head_size: f32,
+        color: Srgba,
+    ) {
+        let direction = end - start;
+        let length = direction.norm();
+        self.add_line(start, end, color);
+        if length <= std::f32::EPSILON {
+            return;
+        }
+
+        let direction = direction / length;
+        let u = perpendicular(&direction);
+        let v = direction.cross(&u);
+        let base = end - direction * head_size;
+        let spread = head_size * 0.5;
+        self.lines.reserve(4);
+        for offset in &[u, -u, v, -v] {
+            self.add_line(end, base + offset * spread, color);
+        }
+    }
+
+    /// Adds an arrow like `add_arrow`, whose head is enlarged when seen from `eye` so that it
+    /// spans at least `min_angle` radians, keeping it readable from far away.
+    pub fn add_arrow_clamped(
+        &mut self,
+        start: Point3<f32>,
+        end: Point3<f32>,
+        head_size: f32,
+        eye: Point3<f32>,
+        min_angle: f32,
+        color: Srgba,
+    ) {
+        let head_size = head_size.max((end - eye).norm() * min_angle.tan());
+        self.add_arrow(start, end, head_size, color);
+    }
+
+    /// Adds the 12 edges of the volume seen by `camera` when placed at `transform`, from its near
+    /// to its far plane, see `Camera::frustum_corners`.
+    pub fn add_frustum(&mut self, camera: &Camera, transform: &Transform, color: Srgba) {
+        let corners = camera.frustum_corners(transform);
+        self.add_box_corners(
+            |x, y, z| corners[x as usize | (y as usize) << 1 | (z as usize) << 2],
+            color,
+        );
+    }
+
+    /// Adds the local X, Y and Z axes of `transform` in red, green and blue, each `length` long.
+    pub fn add_axes(&mut self, transform: &Matrix4<f32>, length: f32) {
+        let origin = transform.transform_point(&Point3::origin());
+        let axes = [
+            (Vector3::x(), Srgba::new(1.0, 0.0, 0.0, 1.0)),
+            (Vector3::y(), Srgba::new(0.0, 1.0, 0.0, 1.0)),
+            (Vector3::z(), Srgba::new(0.0, 0.0, 1.0, 1.0)),
+        ];
+        for (axis, color) in axes.iter() {
+            let end = transform.transform_point(&Point3::from(axis * length));
+            self.add_line(origin, end, *color);
+        }
+    }
+
+    /// Adds a capsule around the segment from `p0` to `p1`, made of a ring of `segments` lines
+    /// around each end, four lines joining the rings and two half circles over each end.
     ///
-    /// As lines are persistent, it's necessary to use this function for updating or deleting lines.
+    /// The number of segments is rounded up to an even number of at least four.
+    pub fn add_capsule(
+        &mut self,
+        p0: Point3<f32>,
+        p1: Point3<f32>,
+        radius: f32,
+        segments: u32,
+        color: Srgba,
+    ) {
+        let circle = self.take_unit_circle(segments);
+        let (axis, u, v) = capsule_basis(p0, p1);
+        self.lines.reserve(circle.len() * 4 + 4);
+        self.add_cylinder_lines(&circle, p0, p1, u * radius, v * radius, color);
+        let half = &circle[..=circle.len() / 2];
+        for side in &[u, v] {
+            self.add_half_circle(half, p1, side * radius, axis * radius, color);
+            self.add_half_circle(half, p0, side * radius, -axis * radius, color);
+        }
+        self.unit_circle = circle;
+    }
+
+    /// Adds a cylinder between the centers `p0` and `p1` of its caps, made of a ring of `segments`
+    /// lines around each cap and four lines joining the rings.
+    ///
+    /// The number of segments is rounded up to an even number of at least four.
+    pub fn add_cylinder(
+        &mut self,
+        p0: Point3<f32>,
+        p1: Point3<f32>,
+        radius: f32,
+        segments: u32,
+        color: Srgba,
+    ) {
+        let circle = self.take_unit_circle(segments);
+        let (_, u, v) = capsule_basis(p0, p1);
+        self.lines.reserve(circle.len() * 2 + 4);
+        self.add_cylinder_lines(&circle, p0, p1, u * radius, v * radius, color);
+        self.unit_circle = circle;
+    }
+
+    /// Takes the points of the unit circle with `segments` segments, only computing them when the
+    /// number of segments changed since the last call.
+    fn take_unit_circle(&mut self, segments: u32) -> Vec<(f32, f32)> {
+        let segments = (segments.max(4) + 1) & !1;
+        let mut circle = std::mem::replace(&mut self.unit_circle, Vec::new());
+        if circle.len() != segments as usize {
+            let step = 2.0 * std::f32::consts::PI / segments as f32;
+            circle.clear();
+            circle.extend((0..segments).map(|i| {
+                let (sin, cos) = (step * i as f32).sin_cos();
+                (cos, sin)
+            }));
+        }
+        circle
+    }
+
+    fn add_cylinder_lines(
+        &mut self,
+        circle: &[(f32, f32)],
+        p0: Point3<f32>,
+        p1: Point3<f32>,
+        u: Vector3<f32>,
+        v: Vector3<f32>,
+        color: Srgba,
+    ) {
+        for &center in &[p0, p1] {
+            for (i, &(cos, sin)) in circle.iter().enumerate() {
+                let (next_cos, next_sin) = circle[(i + 1) % circle.len()];
+                self.add_line(
+                    center + u * cos + v * sin,
+                    center + u * next_cos + v * next_sin,
+                    color,
+                );
+            }
+        }
+        for &offset in &[u, -u, v, -v] {
+            self.add_line(p0 + offset, p1 + offset, color);
+        }
+    }
+
+    fn add_half_circle(
+        &mut self,
+        half: &[(f32, f32)],
+        center: Point3<f32>,
+        u: Vector3<f32>,
+        v: Vector3<f32>,
+        color: Srgba,
+    ) {
+        for pair in half.windows(2) {
+            let ((cos, sin), (next_cos, next_sin)) = (pair[0], pair[1]);
+            self.add_line(
+                center + u * cos + v * sin,
+                center + u * next_cos + v * next_sin,
+                color,
+            );
+        }
+    }
+
+    fn add_box_corners(&mut self, corner: impl Fn(bool, bool, bool) -> Point3<f32>, color: Srgba) {
+        self.lines.reserve(12);
+        for &(a, b) in &[(false, false), (true, false), (false, true), (true, true)] {
+            self.add_line(corner(false, a, b), corner(true, a, b), color);
+            self.add_line(corner(a, false, b), corner(a, true, b), color);
+            self.add_line(corner(a, b, false), corner(a, b, true), color);
+        }
+    }
+
+    /// Adds a label reading `text`, made of lines in a plane facing the camera. The label is
+    /// centered horizontally on `position`, its characters are `size` high above it.
+    ///
+    /// Only ASCII is supported, lower case letters are drawn as capitals and other characters as
+    /// boxes.
+    pub fn add_text(&mut self, position: Point3<f32>, text: &str, color: Srgba, size: f32) {
+        let start = self.text.len();
+        self.text.push_str(text);
+        self.texts.push(DebugText {
+            position,
+            start,
+            end: self.text.len(),
+            color,
+            size,
+            width: self.line_width,
+        });
+    }
+
+    /// Clears lines buffer, keeping its capacity.
+    ///
+    /// Unless the `ClearPolicy` is `EveryFrame`, lines are persistent, and it's necessary to use
+    /// this function for updating or deleting lines.
     pub fn clear(&mut self) {
         self.lines.clear();
+        self.texts.clear();
+        self.text.clear();
+    }
+
+    /// Appends the lines of the labels to `out`, facing a camera with the given `right` and `up`
+    /// directions.
+    pub(crate) fn text_lines(
+        &self,
+        right: Vector3<f32>,
+        up: Vector3<f32>,
+        out: &mut Vec<DebugLine>,
+    ) {
+        for text in &self.texts {
+            let chars = self.text[text.start..text.end].chars().count();
+            let unit = text.size / GLYPH_HEIGHT;
+            let half_width = (chars as f32 * GLYPH_ADVANCE - (GLYPH_ADVANCE - GLYPH_WIDTH)) * 0.5;
+            push_text(
+                out,
+                text.position - right * (half_width.max(0.0) * unit),
+                right,
+                up,
+                &self.text[text.start..text.end],
+                text.color,
+                text.size,
+                text.width,
+            );
+        }
     }
 
     pub(crate) fn lines(&self) -> &[DebugLine] {
@@ -110,18 +625,196 @@ impl DebugLinesComponent {
 
 /// Resource that stores non-persistent debug lines to be rendered in DebugLinesPass draw pass.
 /// The vector is automatically cleared after being rendered.
+///
+/// Lines submitted with a lifetime, through `draw_timed` or the `_timed` variants of the draw
+/// methods, are instead kept until `DebugLinesSystem` counted that many seconds down.
 #[derive(Debug, Default)]
 pub struct DebugLines {
     /// Lines to be rendered
     inner: DebugLinesComponent,
+    /// Lines with a lifetime, and their remaining seconds
+    timed: DebugLinesComponent,
+    lifetimes: Vec<f32>,
 }
 
 impl DebugLines {
     /// Creates a new debug lines component with an empty DebugLine vector.
     pub fn new() -> DebugLines {
-        Self {
-            inner: Default::default(),
-        }
+        Self::default()
+    }
+
+    /// Sets the width in screen space pixels of the lines submitted from now on, see
+    /// `DebugLinesComponent::set_line_width`.
+    pub fn set_line_width(&mut self, width: f32) {
+        self.inner.set_line_width(width);
+        self.timed.set_line_width(width);
+    }
+
+    /// Submits the lines added by `f` to be rendered for the next `seconds` seconds.
+    ///
+    /// A lifetime of `std::f32::INFINITY` keeps the lines until `clear_timed` is called.
+    pub fn draw_timed(&mut self, seconds: f32, f: impl FnOnce(&mut DebugLinesComponent)) {
+        f(&mut self.timed);
+        let len = self.timed.lines.len();
+        self.lifetimes.resize(len, seconds);
+    }
+
+    /// Removes all lines submitted with a lifetime.
+    pub fn clear_timed(&mut self) {
+        self.timed.clear();
+        self.lifetimes.clear();
+    }
+
+    /// Submits a line to be rendered for `seconds` seconds, see `draw_line`.
+    pub fn draw_line_timed(
+        &mut self,
+        start: Point3<f32>,
+        end: Point3<f32>,
+        color: Srgba,
+        seconds: f32,
+    ) {
+        self.draw_timed(seconds, |lines| lines.add_line(start, end, color));
+    }
+
+    /// Submits a line to be rendered for `seconds` seconds, see `draw_direction`.
+    pub fn draw_direction_timed(
+        &mut self,
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        color: Srgba,
+        seconds: f32,
+    ) {
+        self.draw_timed(seconds, |lines| {
+            lines.add_direction(position, direction, color)
+        });
+    }
+
+    /// Submits a line to be rendered for `seconds` seconds, see `draw_gradient_line`.
+    pub fn draw_gradient_line_timed(
+        &mut self,
+        start: Point3<f32>,
+        end: Point3<f32>,
+        start_color: Srgba,
+        end_color: Srgba,
+        seconds: f32,
+    ) {
+        self.draw_timed(seconds, |lines| {
+            lines.add_gradient_line(start, end, start_color, end_color)
+        });
+    }
+
+    /// Submits a circle to be rendered for `seconds` seconds, see `draw_circle`.
+    pub fn draw_circle_timed(
+        &mut self,
+        center: Point3<f32>,
+        normal: Vector3<f32>,
+        radius: f32,
+        segments: u32,
+        color: Srgba,
+        seconds: f32,
+    ) {
+        self.draw_timed(seconds, |lines| {
+            lines.add_circle(center, normal, radius, segments, color)
+        });
+    }
+
+    /// Submits an arc to be rendered for `seconds` seconds, see `draw_arc`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_arc_timed(
+        &mut self,
+        center: Point3<f32>,
+        normal: Vector3<f32>,
+        start: Vector3<f32>,
+        angle: f32,
+        segments: u32,
+        color: Srgba,
+        seconds: f32,
+    ) {
+        self.draw_timed(seconds, |lines| {
+            lines.add_arc(center, normal, start, angle, segments, color)
+        });
+    }
+
+    /// Submits a wireframe sphere to be rendered for `seconds` seconds, see `draw_sphere`.
+    pub fn draw_sphere_timed(
+        &mut self,
+        center: Point3<f32>,
+        radius: f32,
+        segments: u32,
+        color: Srgba,
+        seconds: f32,
+    ) {
+        self.draw_timed(seconds, |lines| {
+            lines.add_sphere(center, radius, segments, color)
+        });
+    }
+
+    /// Submits an axis aligned box to be rendered for `seconds` seconds, see `draw_box`.
+    pub fn draw_box_timed(
+        &mut self,
+        min: Point3<f32>,
+        max: Point3<f32>,
+        color: Srgba,
+        seconds: f32,
+    ) {
+        self.draw_timed(seconds, |lines| lines.add_box(min, max, color));
+    }
+
+    /// Submits an arrow to be rendered for `seconds` seconds, see `draw_arrow`.
+    pub fn draw_arrow_timed(
+        &mut self,
+        start: Point3<f32>,
+        end: Point3<f32>,
+        head_size: f32,
+        color: Srgba,
+        seconds: f32,
+    ) {
+        self.draw_timed(seconds, |lines| {
+            lines.add_arrow(start, end, head_size, color)
+        });
+    }
+
+    /// Submits a capsule to be rendered for `seconds` seconds, see `draw_capsule`.
+    pub fn draw_capsule_timed(
+        &mut self,
+        p0: Point3<f32>,
+        p1: Point3<f32>,
+        radius: f32,
+        segments: u32,
+        color: Srgba,
+        seconds: f32,
+    ) {
+        self.draw_timed(seconds, |lines| {
+            lines.add_capsule(p0, p1, radius, segments, color)
+        });
+    }
+
+    /// Submits a cylinder to be rendered for `seconds` seconds, see `draw_cylinder`.
+    pub fn draw_cylinder_timed(
+        &mut self,
+        p0: Point3<f32>,
+        p1: Point3<f32>,
+        radius: f32,
+        segments: u32,
+        color: Srgba,
+        seconds: f32,
+    ) {
+        self.draw_timed(seconds, |lines| {
+            lines.add_cylinder(p0, p1, radius, segments, color)
+        });
+    }
+
+    /// Submits an oriented box to be rendered for `seconds` seconds, see `draw_obb`.
+    pub fn draw_obb_timed(
+        &mut self,
+        transform: &Matrix4<f32>,
+        half_extents: Vector3<f32>,
+        color: Srgba,
+        seconds: f32,
+    ) {
+        self.draw_timed(seconds, |lines| {
+            lines.add_obb(transform, half_extents, color)
+        });
     }
 
     /// Submits a line to be rendered by giving a position and a direction.
@@ -146,7 +839,597 @@ impl DebugLines {
         self.inner.add_line(start, end, color);
     }
 
+    /// Submits a circle to be rendered, see `DebugLinesComponent::add_circle`.
+    pub fn draw_circle(
+        &mut self,
+        center: Point3<f32>,
+        normal: Vector3<f32>,
+        radius: f32,
+        segments: u32,
+        color: Srgba,
+    ) {
+        self.inner
+            .add_circle(center, normal, radius, segments, color);
+    }
+
+    /// Submits an arc to be rendered, see `DebugLinesComponent::add_arc`.
+    pub fn draw_arc(
+        &mut self,
+        center: Point3<f32>,
+        normal: Vector3<f32>,
+        start: Vector3<f32>,
+        angle: f32,
+        segments: u32,
+        color: Srgba,
+    ) {
+        self.inner
+            .add_arc(center, normal, start, angle, segments, color);
+    }
+
+    /// Submits a wireframe sphere to be rendered, see `DebugLinesComponent::add_sphere`.
+    pub fn draw_sphere(&mut self, center: Point3<f32>, radius: f32, segments: u32, color: Srgba) {
+        self.inner.add_sphere(center, radius, segments, color);
+    }
+
+    /// Submits a capsule to be rendered, see `DebugLinesComponent::add_capsule`.
+    pub fn draw_capsule(
+        &mut self,
+        p0: Point3<f32>,
+        p1: Point3<f32>,
+        radius: f32,
+        segments: u32,
+        color: Srgba,
+    ) {
+        self.inner.add_capsule(p0, p1, radius, segments, color);
+    }
+
+    /// Submits a cylinder to be rendered, see `DebugLinesComponent::add_cylinder`.
+    pub fn draw_cylinder(
+        &mut self,
+        p0: Point3<f32>,
+        p1: Point3<f32>,
+        radius: f32,
+        segments: u32,
+        color: Srgba,
+    ) {
+        self.inner.add_cylinder(p0, p1, radius, segments, color);
+    }
+
+    /// Submits a grid to be rendered, see `DebugLinesComponent::add_grid`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw_grid(
+        &mut self,
+        origin: Point3<f32>,
+        axis_u: Vector3<f32>,
+        axis_v: Vector3<f32>,
+        cell_size: f32,
+        cell_count: u32,
+        color: Srgba,
+        major_every: usize,
+        major_color: Srgba,
+    ) {
+        self.inner.add_grid(
+            origin,
+            axis_u,
+            axis_v,
+            cell_size,
+            cell_count,
+            color,
+            major_every,
+            major_color,
+        );
+    }
+
+    /// Submits an axis aligned box to be rendered, see `DebugLinesComponent::add_box`.
+    pub fn draw_box(&mut self, min: Point3<f32>, max: Point3<f32>, color: Srgba) {
+        self.inner.add_box(min, max, color);
+    }
+
+    /// Submits an oriented box to be rendered, see `DebugLinesComponent::add_obb`.
+    pub fn draw_obb(&mut self, transform: &Matrix4<f32>, half_extents: Vector3<f32>, color: Srgba) {
+        self.inner.add_obb(transform, half_extents, color);
+    }
+
+    /// Submits an arrow to be rendered, see `DebugLinesComponent::add_arrow`.
+    pub fn draw_arrow(
+        &mut self,
+        start: Point3<f32>,
+        end: Point3<f32>,
+        head_size: f32,
+        color: Srgba,
+    ) {
+        self.inner.add_arrow(start, end, head_size, color);
+    }
+
+    /// Submits an arrow with a minimum apparent head size to be rendered, see
+    /// `DebugLinesComponent::add_arrow_clamped`.
+    pub fn draw_arrow_clamped(
+        &mut self,
+        start: Point3<f32>,
+        end: Point3<f32>,
+        head_size: f32,
+        eye: Point3<f32>,
+        min_angle: f32,
+        color: Srgba,
+    ) {
+        self.inner
+            .add_arrow_clamped(start, end, head_size, eye, min_angle, color);
+    }
+
+    /// Submits the volume seen by a camera to be rendered, see `DebugLinesComponent::add_frustum`.
+    pub fn draw_frustum(&mut self, camera: &Camera, transform: &Transform, color: Srgba) {
+        self.inner.add_frustum(camera, transform, color);
+    }
+
+    /// Submits the local axes of `transform` to be rendered, see `DebugLinesComponent::add_axes`.
+    pub fn draw_axes(&mut self, transform: &Matrix4<f32>, length: f32) {
+        self.inner.add_axes(transform, length);
+    }
+
+    /// Submits a label to be rendered, see `DebugLinesComponent::add_text`.
+    pub fn draw_text(&mut self, position: Point3<f32>, text: &str, color: Srgba, size: f32) {
+        self.inner.add_text(position, text, color, size);
+    }
+
+    pub(crate) fn drain<'a>(&'a mut self) -> impl Iterator<Item = DebugLine> + 'a {
+        self.inner.lines.drain(..)
+    }
+
+    /// Appends the lines of the labels to `out`, see `DebugLinesComponent::text_lines`, and
+    /// removes the labels.
+    pub(crate) fn drain_text(
+        &mut self,
+        right: Vector3<f32>,
+        up: Vector3<f32>,
+        out: &mut Vec<DebugLine>,
+    ) {
+        self.inner.text_lines(right, up, out);
+        self.inner.texts.clear();
+        self.inner.text.clear();
+    }
+
+    pub(crate) fn timed_lines(&self) -> &[DebugLine] {
+        self.timed.lines()
+    }
+
+    /// Counts the lifetimes down by `delta` seconds and drops the expired lines, in place.
+    fn expire(&mut self, delta: f32) {
+        let mut kept = 0;
+        for i in 0..self.lifetimes.len() {
+            let remaining = self.lifetimes[i] - delta;
+            if remaining > 0.0 {
+                self.lifetimes[kept] = remaining;
+                self.timed.lines[kept] = self.timed.lines[i];
+                kept += 1;
+            }
+        }
+        self.lifetimes.truncate(kept);
+        self.timed.lines.truncate(kept);
+    }
+}
+
+/// Resource that stores non-persistent debug lines in framebuffer pixels, with the origin at the
+/// top left corner, to be rendered in the `DrawScreenDebugLines` draw pass.
+/// The vector is automatically cleared after being rendered.
+#[derive(Debug, Default)]
+pub struct ScreenDebugLines {
+    inner: DebugLinesComponent,
+}
+
+impl ScreenDebugLines {
+    /// Creates a new screen debug lines resource with an empty DebugLine vector.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the width in pixels of the lines submitted from now on, see
+    /// `DebugLinesComponent::set_line_width`.
+    pub fn set_line_width(&mut self, width: f32) {
+        self.inner.set_line_width(width);
+    }
+
+    /// Submits a line to be rendered by giving a start and an end position.
+    pub fn draw_line(&mut self, start: Point2<f32>, end: Point2<f32>, color: Srgba) {
+        self.draw_gradient_line(start, end, color, color);
+    }
+
+    /// Submits a line to be rendered by giving a start and an end position with separate start and end colors.
+    pub fn draw_gradient_line(
+        &mut self,
+        start: Point2<f32>,
+        end: Point2<f32>,
+        start_color: Srgba,
+        end_color: Srgba,
+    ) {
+        self.inner.add_gradient_line(
+            Point3::new(start.x, start.y, 0.0),
+            Point3::new(end.x, end.y, 0.0),
+            start_color,
+            end_color,
+        );
+    }
+
+    /// Submits the outline of the rectangle between the `min` and `max` corners to be rendered.
+    pub fn draw_rect(&mut self, min: Point2<f32>, max: Point2<f32>, color: Srgba) {
+        let corners = [
+            min,
+            Point2::new(max.x, min.y),
+            max,
+            Point2::new(min.x, max.y),
+        ];
+        for (i, corner) in corners.iter().enumerate() {
+            self.draw_line(*corner, corners[(i + 1) % 4], color);
+        }
+    }
+
+    /// Submits `text` to be rendered with its top left corner at `position`, with characters
+    /// `size` pixels high, see `DebugLinesComponent::add_text`.
+    pub fn draw_text(&mut self, position: Point2<f32>, text: &str, color: Srgba, size: f32) {
+        let width = self.inner.line_width;
+        push_text(
+            &mut self.inner.lines,
+            Point3::new(position.x, position.y + size, 0.0),
+            Vector3::x(),
+            -Vector3::y(),
+            text,
+            color,
+            size,
+            width,
+        );
+    }
+
+    /// Submits a circle made of `segments` lines to be rendered, see `DebugLinesComponent::add_circle`.
+    pub fn draw_circle(&mut self, center: Point2<f32>, radius: f32, segments: u32, color: Srgba) {
+        self.inner.add_circle(
+            Point3::new(center.x, center.y, 0.0),
+            Vector3::z(),
+            radius,
+            segments,
+            color,
+        );
+    }
+
     pub(crate) fn drain<'a>(&'a mut self) -> impl Iterator<Item = DebugLine> + 'a {
         self.inner.lines.drain(..)
     }
 }
+
+/// Counts down the lifetime of the lines submitted to `DebugLines` with `draw_timed`, and drops
+/// them once expired.
+///
+/// Note that this should run before rendering occurs.
+#[derive(Default, Debug)]
+pub struct DebugLinesSystem;
+
+impl DebugLinesSystem {
+    /// Create new debug lines system
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for DebugLinesSystem {
+    type SystemData = (
+        Read<'a, Time>,
+        Read<'a, Deterministic>,
+        Option<Write<'a, DebugLines>>,
+    );
+
+    fn run(&mut self, (time, deterministic, lines): Self::SystemData) {
+        if let Some(mut lines) = lines {
+            lines.expire(deterministic.delta_seconds(&time));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(lines: &[DebugLine]) -> impl Iterator<Item = Point3<f32>> + '_ {
+        lines.iter().flat_map(|line| {
+            let (start, end) = (line.start.position.0, line.end.position.0);
+            vec![
+                Point3::new(start[0], start[1], start[2]),
+                Point3::new(end[0], end[1], end[2]),
+            ]
+        })
+    }
+
+    #[test]
+    fn circle_and_sphere_points() {
+        let center = Point3::new(1.0, -2.0, 3.0);
+        let normal = Vector3::new(1.0, 1.0, 0.0);
+        let mut lines = DebugLinesComponent::new();
+        lines.add_circle(center, normal, 2.5, 16, Srgba::default());
+        assert_eq!(lines.lines().len(), 16);
+        for point in points(lines.lines()) {
+            assert!(((point - center).norm() - 2.5).abs() < 1e-4);
+            assert!((point - center).dot(&normal).abs() < 1e-4);
+        }
+        // The circle is closed
+        let all: Vec<_> = points(lines.lines()).collect();
+        assert!((all[0] - all[31]).norm() < 1e-4);
+
+        lines.clear();
+        lines.add_sphere(center, 0.5, 2, Srgba::default());
+        assert_eq!(lines.lines().len(), 9);
+        for point in points(lines.lines()) {
+            assert!(((point - center).norm() - 0.5).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn box_edges() {
+        let mut lines = DebugLinesComponent::new();
+        lines.add_box(
+            Point3::new(-1.0, 0.0, 2.0),
+            Point3::new(1.0, 3.0, 4.0),
+            Srgba::default(),
+        );
+        assert_eq!(lines.lines().len(), 12);
+        for line in lines.lines() {
+            let (start, end) = (line.start.position.0, line.end.position.0);
+            let differing = (0..3).filter(|&i| (start[i] - end[i]).abs() > 1e-6).count();
+            assert_eq!(differing, 1);
+        }
+
+        lines.clear();
+        let transform = Matrix4::new_translation(&Vector3::new(5.0, 0.0, 0.0))
+            * Matrix4::from_euler_angles(0.3, 0.7, 1.1);
+        let half_extents = Vector3::new(1.0, 2.0, 3.0);
+        lines.add_obb(&transform, half_extents, Srgba::default());
+        assert_eq!(lines.lines().len(), 12);
+        let center = Point3::new(5.0, 0.0, 0.0);
+        for point in points(lines.lines()) {
+            assert!(((point - center).norm() - half_extents.norm()).abs() < 1e-4);
+        }
+
+        lines.clear();
+        lines.add_axes(&transform, 2.0);
+        assert_eq!(lines.lines().len(), 3);
+    }
+
+    #[test]
+    fn timed_lines_expire() {
+        let (start, end) = (Point3::origin(), Point3::new(1.0, 0.0, 0.0));
+        let mut lines = DebugLines::new();
+        lines.draw_line(start, end, Srgba::default());
+        lines.draw_line_timed(start, end, Srgba::default(), 1.0);
+        lines.draw_sphere_timed(start, 1.0, 4, Srgba::default(), 0.25);
+        lines.draw_line_timed(start, end, Srgba::default(), std::f32::INFINITY);
+        assert_eq!(lines.timed_lines().len(), 14);
+
+        assert_eq!(lines.drain().count(), 1);
+        assert_eq!(lines.timed_lines().len(), 14);
+
+        lines.expire(0.5);
+        assert_eq!(lines.timed_lines().len(), 2);
+        lines.expire(0.5);
+        assert_eq!(lines.timed_lines().len(), 1);
+        lines.expire(1000.0);
+        assert_eq!(lines.timed_lines().len(), 1);
+
+        lines.clear_timed();
+        assert!(lines.timed_lines().is_empty());
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn screen_shapes() {
+        let mut lines = ScreenDebugLines::new();
+        lines.draw_rect(
+            Point2::new(10.0, 20.0),
+            Point2::new(30.0, 60.0),
+            Srgba::default(),
+        );
+        lines.draw_circle(Point2::new(100.0, 100.0), 8.0, 12, Srgba::default());
+        let lines: Vec<_> = lines.inner.lines().to_vec();
+        assert_eq!(lines.len(), 16);
+        for point in points(&lines) {
+            assert!(point.z.abs() < 1e-6);
+        }
+        for point in points(&lines[..4]) {
+            assert!(point.x == 10.0 || point.x == 30.0);
+            assert!(point.y == 20.0 || point.y == 60.0);
+        }
+    }
+
+    #[test]
+    fn arrow_heads() {
+        let (start, end) = (Point3::new(0.0, 0.0, 0.0), Point3::new(0.0, 0.0, -4.0));
+        let mut lines = DebugLinesComponent::new();
+        lines.add_arrow(start, end, 0.5, Srgba::default());
+        assert_eq!(lines.lines().len(), 5);
+        for point in points(&lines.lines()[1..]).filter(|p| (p - end).norm() > 1e-4) {
+            assert!((point.z + 3.5).abs() < 1e-4);
+            assert!((point.xy().coords.norm() - 0.25).abs() < 1e-4);
+        }
+
+        lines.clear();
+        let eye = Point3::new(0.0, 0.0, 96.0);
+        lines.add_arrow_clamped(start, end, 0.5, eye, 0.01, Srgba::default());
+        let expected = 100.0 * 0.01f32.tan();
+        for point in points(&lines.lines()[1..]).filter(|p| (p - end).norm() > 1e-4) {
+            assert!((point.z - (end.z + expected)).abs() < 1e-4);
+        }
+
+        lines.clear();
+        lines.add_arrow(start, start, 0.5, Srgba::default());
+        assert_eq!(lines.lines().len(), 1);
+    }
+
+    #[test]
+    fn frustum_edges() {
+        // The global matrix of a default transform is the identity, the camera looks along -Z
+        let transform = Transform::default();
+        let camera = Camera::from(crate::camera::Projection::perspective(
+            1.0,
+            std::f32::consts::FRAC_PI_2,
+            1.0,
+            100.0,
+        ));
+        let mut lines = DebugLinesComponent::new();
+        lines.add_frustum(&camera, &transform, Srgba::default());
+        assert_eq!(lines.lines().len(), 12);
+        for point in points(lines.lines()) {
+            let depth = -point.z;
+            assert!((depth - 1.0).abs() < 1e-3 || (depth - 100.0).abs() < 1e-2);
+            assert!((point.x.abs() - depth).abs() < 1e-2);
+            assert!((point.y.abs() - depth).abs() < 1e-2);
+        }
+
+        let camera = Camera::from(crate::camera::Projection::orthographic(
+            -2.0, 2.0, -1.0, 1.0, 0.5, 50.0,
+        ));
+        let corners = camera.frustum_corners(&transform);
+        for corner in corners.iter() {
+            assert!((corner.x.abs() - 2.0).abs() < 1e-4);
+            assert!((corner.y.abs() - 1.0).abs() < 1e-4);
+        }
+        assert!((corners[0].z + 0.5).abs() < 1e-4);
+        assert!((corners[7].z + 50.0).abs() < 1e-3);
+    }
+
+    /// Distance from `point` to the segment from `p0` to `p1`.
+    fn segment_distance(point: Point3<f32>, p0: Point3<f32>, p1: Point3<f32>) -> f32 {
+        let axis = p1 - p0;
+        let t = ((point - p0).dot(&axis) / axis.norm_squared())
+            .max(0.0)
+            .min(1.0);
+        (point - (p0 + axis * t)).norm()
+    }
+
+    #[test]
+    fn capsule_and_cylinder_points() {
+        let (p0, p1) = (Point3::new(1.0, 0.0, 0.0), Point3::new(1.0, 2.0, 1.0));
+        let mut lines = DebugLinesComponent::new();
+        lines.add_capsule(p0, p1, 0.5, 8, Srgba::default());
+        assert_eq!(lines.lines().len(), 8 * 4 + 4);
+        for point in points(lines.lines()) {
+            assert!((segment_distance(point, p0, p1) - 0.5).abs() < 1e-4);
+        }
+
+        lines.clear();
+        lines.add_cylinder(p0, p1, 0.5, 7, Srgba::default());
+        assert_eq!(lines.lines().len(), 8 * 2 + 4);
+        let axis = (p1 - p0).normalize();
+        for point in points(lines.lines()) {
+            let along = (point - p0).dot(&axis);
+            assert!(((point - p0 - axis * along).norm() - 0.5).abs() < 1e-4);
+        }
+
+        lines.clear();
+        lines.add_capsule(p0, p0, 1.0, 4, Srgba::default());
+        assert_eq!(lines.lines().len(), 4 * 4 + 4);
+        for point in points(lines.lines()) {
+            assert!(((point - p0).norm() - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn grid_lines() {
+        let (minor, major) = (
+            Srgba::new(0.5, 0.5, 0.5, 1.0),
+            Srgba::new(1.0, 1.0, 1.0, 1.0),
+        );
+        let origin = Point3::new(0.0, 1.0, 0.0);
+        let (axis_u, axis_v) = (Vector3::new(1.0, 0.0, 1.0), Vector3::y());
+        let mut lines = DebugLinesComponent::new();
+        lines.add_grid(origin, axis_u, axis_v * 3.0, 0.5, 10, minor, 5, major);
+        assert_eq!(lines.lines().len(), 22);
+
+        let normal = axis_u.cross(&axis_v).normalize();
+        for point in points(lines.lines()) {
+            assert!((point - origin).dot(&normal).abs() < 1e-4);
+        }
+        let majors = lines
+            .lines()
+            .iter()
+            .filter(|line| line.start.color == Color(major.into_pod()))
+            .count();
+        assert_eq!(majors, 6);
+        for line in lines.lines() {
+            let (start, end) = (line.start.position.0, line.end.position.0);
+            let length = Vector3::new(end[0] - start[0], end[1] - start[1], end[2] - start[2]);
+            assert!((length.norm() - 5.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn text_faces_camera() {
+        let mut lines = DebugLinesComponent::new();
+        let position = Point3::new(1.0, 2.0, 3.0);
+        lines.add_text(position, "HiH", Srgba::default(), 1.2);
+        lines.add_text(position, "", Srgba::default(), 1.2);
+
+        let (right, up) = (Vector3::new(0.0, 0.0, -1.0), Vector3::y());
+        let mut out = Vec::new();
+        lines.text_lines(right, up, &mut out);
+        // 'H' and 'I' have 3 lines each
+        assert_eq!(out.len(), 9);
+        let normal = right.cross(&up);
+        let (mut min, mut max) = (std::f32::MAX, std::f32::MIN);
+        for point in points(&out) {
+            let offset = point - position;
+            assert!(offset.dot(&normal).abs() < 1e-4);
+            assert!(offset.dot(&up) > -1e-4 && offset.dot(&up) < 1.2 + 1e-4);
+            min = min.min(offset.dot(&right));
+            max = max.max(offset.dot(&right));
+        }
+        // Centered on the position
+        assert!((min + max).abs() < 1e-4);
+
+        lines.clear();
+        out.clear();
+        lines.text_lines(right, up, &mut out);
+        assert!(out.is_empty());
+
+        let mut screen = ScreenDebugLines::new();
+        screen.draw_text(Point2::new(10.0, 20.0), "T", Srgba::default(), 12.0);
+        for point in points(screen.inner.lines()) {
+            assert!(point.x >= 10.0 && point.x <= 18.0);
+            assert!(point.y >= 20.0 && point.y <= 32.0);
+        }
+    }
+
+    #[test]
+    fn capacity_and_clear_policy() {
+        let mut lines = DebugLinesComponent::with_capacity(16);
+        assert!(lines.capacity() >= 16);
+        assert_eq!(lines.clear_policy(), ClearPolicy::Manual);
+        lines.reserve(100);
+        assert!(lines.capacity() >= 100);
+
+        lines.add_sphere(Point3::origin(), 1.0, 30, Srgba::default());
+        let capacity = lines.capacity();
+        lines.clear();
+        assert!(lines.lines().is_empty());
+        assert_eq!(lines.capacity(), capacity);
+
+        let lines = DebugLinesComponent::new().with_clear_policy(ClearPolicy::EveryFrame);
+        assert_eq!(lines.clear_policy(), ClearPolicy::EveryFrame);
+    }
+
+    #[test]
+    fn arc_points() {
+        let center = Point3::origin();
+        let mut lines = DebugLinesComponent::new();
+        lines.add_arc(
+            center,
+            Vector3::z(),
+            Vector3::new(2.0, 0.0, 1.0),
+            std::f32::consts::FRAC_PI_2,
+            4,
+            Srgba::default(),
+        );
+        assert_eq!(lines.lines().len(), 4);
+        for point in points(lines.lines()) {
+            assert!((point.coords.norm() - 2.0).abs() < 1e-4);
+            assert!(point.z.abs() < 1e-4);
+            assert!(point.x > -1e-4 && point.y > -1e-4);
+        }
+        let end = points(lines.lines()).last().unwrap();
+        assert!((end - Point3::new(0.0, 2.0, 0.0)).norm() < 1e-4);
+    }
+}
@@ -0,0 +1,151 @@
+//! Stroke font used to draw debug text with lines.
+//!
+//! Each printable ASCII character is a list of polylines on a grid 4 units wide and 6 units
+//! high, with the origin at the bottom left corner. A polyline is written as the digits of its
+//! points, `"0046"` being the line from `(0, 0)` to `(4, 6)`, and polylines are separated by
+//! spaces.
+
+/// Height of a glyph in grid units.
+pub(crate) const GLYPH_HEIGHT: f32 = 6.0;
+
+/// Width of a glyph in grid units.
+pub(crate) const GLYPH_WIDTH: f32 = 4.0;
+
+/// Horizontal distance between the origins of two consecutive glyphs, in grid units.
+pub(crate) const GLYPH_ADVANCE: f32 = 6.0;
+
+/// Drawn for characters without a glyph.
+const MISSING: &str = "0006464000";
+
+/// Glyphs of the characters from `' '` to `'`'`.
+const GLYPHS: [&str; 65] = [
+    "",                          // ' '
+    "2622 2120",                 // '!'
+    "1614 3634",                 // '"'
+    "1115 3135 0444 0242",       // '#'
+    "460603434000 2026",         // '$'
+    "0046 0616 3040",            // '%'
+    "400416363401103042",        // '&'
+    "2624",                      // '\''
+    "36242230",                  // '('
+    "16242210",                  // ')'
+    "2125 0145 0541",            // '*'
+    "2125 0343",                 // '+'
+    "2110",                      // ','
+    "0343",                      // '-'
+    "2021",                      // '.'
+    "0046",                      // '/'
+    "0006464000 0046",           // '0'
+    "152620 1030",               // '1'
+    "064643030040",              // '2'
+    "06464000 1343",             // '3'
+    "060343 4640",               // '4'
+    "460603434000",              // '5'
+    "460600404303",              // '6'
+    "064610",                    // '7'
+    "0006464000 0343",           // '8'
+    "430306464000",              // '9'
+    "2425 2122",                 // ':'
+    "2425 2110",                 // ';'
+    "460340",                    // '<'
+    "0242 0444",                 // '='
+    "064300",                    // '>'
+    "05163645442322 2120",       // '?'
+    "4000064642222444",          // '@'
+    "002640 1333",               // 'A'
+    "00063645443303 3342413000", // 'B'
+    "46060040",                  // 'C'
+    "00062644422000",            // 'D'
+    "46060040 0333",             // 'E'
+    "460600 0333",               // 'F'
+    "460600404323",              // 'G'
+    "0006 4640 0343",            // 'H'
+    "0646 2620 0040",            // 'I'
+    "46400002",                  // 'J'
+    "0006 460340",               // 'K'
+    "060040",                    // 'L'
+    "0006234640",                // 'M'
+    "00064046",                  // 'N'
+    "0006464000",                // 'O'
+    "0006464303",                // 'P'
+    "0006464000 2240",           // 'Q'
+    "0006464303 2340",           // 'R'
+    "460603434000",              // 'S'
+    "0646 2620",                 // 'T'
+    "06004046",                  // 'U'
+    "062046",                    // 'V'
+    "0610233046",                // 'W'
+    "0046 0640",                 // 'X'
+    "062346 2320",               // 'Y'
+    "06460040",                  // 'Z'
+    "36262030",                  // '['
+    "0640",                      // '\\'
+    "16363010",                  // ']'
+    "042644",                    // '^'
+    "0040",                      // '_'
+    "1625",                      // '`'
+];
+
+/// Glyphs of the characters from `'{'` to `'~'`.
+const BRACES: [&str; 4] = [
+    "36262413222030", // '{'
+    "2026",           // '|'
+    "16262433222010", // '}'
+    "0314233443",     // '~'
+];
+
+/// Returns the polylines of the glyph of `c`. Lower case letters are drawn as capitals.
+pub(crate) fn glyph(c: char) -> &'static str {
+    let c = c.to_ascii_uppercase();
+    if (' '..='`').contains(&c) {
+        GLYPHS[c as usize - ' ' as usize]
+    } else if ('{'..='~').contains(&c) {
+        BRACES[c as usize - '{' as usize]
+    } else {
+        MISSING
+    }
+}
+
+/// Calls `f` with the end points of every line of `glyph`, in grid units.
+pub(crate) fn for_each_line(glyph: &str, mut f: impl FnMut((f32, f32), (f32, f32))) {
+    for polyline in glyph.split(' ') {
+        let digits = polyline.as_bytes();
+        let point = |i: usize| {
+            (
+                f32::from(digits[i * 2] - b'0'),
+                f32::from(digits[i * 2 + 1] - b'0'),
+            )
+        };
+        for i in 1..digits.len() / 2 {
+            f(point(i - 1), point(i));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glyphs_stay_on_grid() {
+        for c in (b' '..=b'~').map(char::from) {
+            for polyline in glyph(c).split(' ') {
+                assert_eq!(polyline.len() % 2, 0, "Odd polyline in {:?}", c);
+                assert_ne!(polyline.len(), 2, "Single point in {:?}", c);
+                for (i, digit) in polyline.bytes().enumerate() {
+                    let max = if i % 2 == 0 { b'4' } else { b'6' };
+                    assert!((b'0'..=max).contains(&digit), "Off grid in {:?}", c);
+                }
+            }
+            if c.is_ascii_graphic() {
+                assert!(!glyph(c).is_empty(), "No glyph for {:?}", c);
+            }
+        }
+        assert_eq!(glyph('a'), glyph('A'));
+        assert_eq!(glyph('\u{e9}'), MISSING);
+
+        let mut count = 0;
+        for_each_line(glyph('E'), |_, _| count += 1);
+        assert_eq!(count, 4);
+    }
+}
@@ -0,0 +1,116 @@
+//! Format of the depth images of a `RenderingBundle`, and access to them for custom passes.
+//!
+//! The bundle tries the formats of `RenderingBundle::with_depth_formats` in order, and uses the
+//! first one the adapter can attach as a depth image, like `D24UnormS8Uint` for stencil effects
+//! or `D16Unorm` to save bandwidth. Plugins read the chosen format from the `RenderPlan`, ask for
+//! a stencil aspect with `RenderPlan::require_stencil`, and add nodes using the depth image of
+//! every target with `RenderPlan::add_depth_node`. Render groups drawn by the plan use it as
+//! their depth and stencil attachment.
+use crate::{
+    rendy::{
+        factory::Factory,
+        graph::{ImageId, NodeId},
+        hal::{
+            format::{Format, ImageFeature},
+            PhysicalDevice,
+        },
+    },
+    types::Backend,
+};
+
+/// Depth format of the graph when no preference is given.
+pub const DEFAULT_DEPTH_FORMAT: Format = Format::D32Sfloat;
+
+/// Depth formats tried, in order, when none of the preferred ones is supported.
+const FALLBACK_DEPTH_FORMATS: [Format; 4] = [
+    Format::D32Sfloat,
+    Format::D24UnormS8Uint,
+    Format::D32SfloatS8Uint,
+    Format::D16Unorm,
+];
+
+/// Whether `format` has a stencil aspect.
+pub fn has_stencil(format: Format) -> bool {
+    match format {
+        Format::S8Uint
+        | Format::D16UnormS8Uint
+        | Format::D24UnormS8Uint
+        | Format::D32SfloatS8Uint => true,
+        _ => false,
+    }
+}
+
+/// The first format of `preferred` usable as a depth attachment by the adapter of `factory`,
+/// falling back to any usable depth format with a warning.
+pub fn negotiate_depth_format<B: Backend>(factory: &Factory<B>, preferred: &[Format]) -> Format {
+    pick_depth_format(preferred, |format| {
+        factory
+            .physical()
+            .format_properties(Some(format))
+            .optimal_tiling
+            .contains(ImageFeature::DEPTH_STENCIL_ATTACHMENT)
+    })
+}
+
+fn pick_depth_format(preferred: &[Format], supported: impl Fn(Format) -> bool) -> Format {
+    if let Some(&format) = preferred.iter().find(|&&format| supported(format)) {
+        return format;
+    }
+    let format = FALLBACK_DEPTH_FORMATS
+        .iter()
+        .cloned()
+        .find(|&format| supported(format))
+        .unwrap_or(DEFAULT_DEPTH_FORMAT);
+    log::warn!(
+        "None of the depth formats {:?} is supported, using {:?}",
+        preferred,
+        format
+    );
+    format
+}
+
+/// The depth image of a target of a `RenderPlan`, given to the nodes of
+/// `RenderPlan::add_depth_node`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthImage {
+    /// Name of the secondary window of the target, or `None` for the main target.
+    pub window: Option<String>,
+    /// The depth image.
+    pub image: ImageId,
+    /// Format of the image.
+    pub format: Format,
+    /// The pass drawing the render groups into the image, which nodes reading it depend on.
+    pub pass: NodeId,
+}
+
+impl DepthImage {
+    /// Whether the image has a stencil aspect.
+    pub fn has_stencil(&self) -> bool {
+        has_stencil(self.format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_format_fallback() {
+        let stencil = [Format::D24UnormS8Uint, Format::D32SfloatS8Uint];
+        assert_eq!(
+            pick_depth_format(&stencil, |_| true),
+            Format::D24UnormS8Uint
+        );
+        assert_eq!(
+            pick_depth_format(&stencil, |format| format == Format::D32SfloatS8Uint),
+            Format::D32SfloatS8Uint
+        );
+        assert_eq!(
+            pick_depth_format(&[Format::D16Unorm], |format| format == Format::D32Sfloat),
+            Format::D32Sfloat
+        );
+        assert_eq!(pick_depth_format(&[], |_| false), DEFAULT_DEPTH_FORMAT);
+        assert!(has_stencil(Format::D24UnormS8Uint));
+        assert!(!has_stencil(Format::D16Unorm));
+    }
+}
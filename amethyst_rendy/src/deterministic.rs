@@ -0,0 +1,91 @@
+//! Deterministic rendering, drawing the same frames for the same world in every run.
+//!
+//! Golden-image tests and replay captures compare frames byte for byte, so nothing the renderer
+//! draws may depend on the wall clock, random seeds or the history of earlier frames. Rendering
+//! is deterministic while the `enabled` flag of the `Deterministic` resource is set, with
+//! `RenderingBundle::with_deterministic`, `RenderingSystem::with_deterministic` or at runtime.
+//! The test bundles enable it. Then:
+//!
+//! * `Deterministic::seed` gives `DETERMINISTIC_SEED` instead of the per frame seed of a pass, for
+//!   the passes with temporal noise. The noise of `DissolveNoise` is always generated from fixed
+//!   hashes.
+//! * The batches of the 3D passes, the sprite pass and the instanced submodule are rebuilt from
+//!   scratch every frame instead of reused, with `reset`, so the order they're submitted in only
+//!   depends on the entities of the frame and not on the earlier frames. Transparent meshes and
+//!   sprites are sorted by camera distance with ties broken by entity id in both modes.
+//! * The `DebugLinesSystem` and the `AnimatedTextureSystem` advance by `DETERMINISTIC_FRAME_TIME`
+//!   every frame instead of the frame time, for the timed debug lines and the flipbooks.
+//!
+//! Systems outside the renderer, like animations driven by `Time`, aren't affected.
+use amethyst_core::{ecs::Resources, Time};
+
+/// Seed of the passes with temporal noise in deterministic mode.
+pub const DETERMINISTIC_SEED: u64 = 0x5eed;
+
+/// Seconds the renderer advances by every frame in deterministic mode, a frame at 60 Hz.
+pub const DETERMINISTIC_FRAME_TIME: f32 = 1.0 / 60.0;
+
+/// Resource making the renderer deterministic, see the module documentation.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Deterministic {
+    /// Draw the same frames for the same world in every run.
+    pub enabled: bool,
+}
+
+impl Deterministic {
+    /// Deterministic rendering enabled.
+    pub fn enabled() -> Self {
+        Self { enabled: true }
+    }
+
+    /// The seed of a pass, `DETERMINISTIC_SEED` in deterministic mode.
+    pub fn seed(&self, seed: u64) -> u64 {
+        if self.enabled {
+            DETERMINISTIC_SEED
+        } else {
+            seed
+        }
+    }
+
+    /// Seconds animations of the renderer advance by this frame, `DETERMINISTIC_FRAME_TIME` in
+    /// deterministic mode.
+    pub fn delta_seconds(&self, time: &Time) -> f32 {
+        if self.enabled {
+            DETERMINISTIC_FRAME_TIME
+        } else {
+            time.delta_seconds()
+        }
+    }
+}
+
+/// Whether rendering is deterministic, false without the resource.
+pub(crate) fn is_deterministic(resources: &Resources) -> bool {
+    resources
+        .try_fetch::<Deterministic>()
+        .map_or(false, |deterministic| deterministic.enabled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn enabled_fixes_seeds_and_frame_time() {
+        let mut time = Time::default();
+        time.set_delta_time(Duration::from_millis(250));
+
+        let free = Deterministic::default();
+        assert_eq!(free.seed(42), 42);
+        assert_eq!(free.delta_seconds(&time), 0.25);
+
+        let fixed = Deterministic::enabled();
+        assert_eq!(fixed.seed(42), DETERMINISTIC_SEED);
+        assert_eq!(fixed.delta_seconds(&time), DETERMINISTIC_FRAME_TIME);
+
+        let mut resources = Resources::new();
+        assert!(!is_deterministic(&resources));
+        resources.insert(fixed);
+        assert!(is_deterministic(&resources));
+    }
+}
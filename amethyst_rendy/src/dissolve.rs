@@ -0,0 +1,206 @@
+//! Entities dissolving away with a noise threshold.
+//!
+//! The flat and PBR passes draw the opaque meshes with a `Dissolve` with dissolve pipelines,
+//! which discard the fragments whose noise is below its `amount`, and draw the ones just above it
+//! unlit with its `edge_color`. The amount, color and width of the edge are instance-rate data,
+//! so entities dissolving by different amounts are drawn together.
+//!
+//! The noise is sampled from the texture of the `DissolveNoise` resource, made of value noise by
+//! the `RenderingSystem` during setup, or from the texture set for the material of a mesh. GPU
+//! skinned and transparent meshes ignore `Dissolve`, and so do the passes without dissolve
+//! shaders.
+use crate::{
+    mtl::Material,
+    rendy::{
+        hal::{
+            format::Format,
+            image::{Filter, Kind, SamplerInfo, ViewKind, WrapMode},
+        },
+        texture::TextureBuilder,
+    },
+    types::{Texture, TextureData},
+};
+use amethyst_assets::Handle;
+use amethyst_core::ecs::{Component, DenseVecStorage};
+use fnv::FnvHashMap;
+use palette::Srgba;
+
+/// Width of the edge of a `Dissolve`, by default.
+pub const DEFAULT_EDGE_WIDTH: f32 = 0.08;
+
+/// Narrowest edge uploaded, as the shaders divide by its width.
+const MIN_EDGE_WIDTH: f32 = 1e-4;
+
+/// Side in texels of the noise texture.
+const NOISE_SIZE: u32 = 128;
+/// Side in texels of the cells of its coarsest octave.
+const NOISE_CELL: u32 = 16;
+
+/// Dissolves the mesh of an entity, discarding its fragments whose noise is below `amount`.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Dissolve {
+    /// Part of the mesh dissolved, from 0 for none to 1 for all of it
+    pub amount: f32,
+    /// Color of the fragments about to dissolve, drawn unlit
+    #[serde(with = "crate::serde_shim::srgba")]
+    pub edge_color: Srgba,
+    /// Width of the edge, in noise values above `amount`
+    pub edge_width: f32,
+}
+
+impl Default for Dissolve {
+    fn default() -> Self {
+        Self::new(0.0)
+    }
+}
+
+impl Dissolve {
+    /// Dissolve `amount` of a mesh, with an orange edge of the default width.
+    pub fn new(amount: f32) -> Self {
+        Self {
+            amount,
+            edge_color: Srgba::new(1.0, 0.45, 0.1, 1.0),
+            edge_width: DEFAULT_EDGE_WIDTH,
+        }
+    }
+
+    /// The amount and edge width, as they're uploaded.
+    pub(crate) fn amount_edge(&self) -> [f32; 2] {
+        [self.amount, self.edge_width.max(MIN_EDGE_WIDTH)]
+    }
+}
+
+impl Component for Dissolve {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Resource with the noise textures of the dissolving meshes.
+#[derive(Debug, Clone, Default)]
+pub struct DissolveNoise {
+    texture: Option<Handle<Texture>>,
+    materials: FnvHashMap<u32, Handle<Texture>>,
+}
+
+impl DissolveNoise {
+    /// Handle of the built-in noise texture, created when the `RenderingSystem` is set up.
+    pub fn texture(&self) -> Option<&Handle<Texture>> {
+        self.texture.as_ref()
+    }
+
+    /// Dissolve the meshes drawn with `material` with the red channel of `noise`.
+    pub fn set_material_noise(&mut self, material: &Handle<Material>, noise: Handle<Texture>) {
+        self.materials.insert(material.id(), noise);
+    }
+
+    /// Dissolve the meshes drawn with `material` with the built-in noise again.
+    pub fn remove_material_noise(
+        &mut self,
+        material: &Handle<Material>,
+    ) -> Option<Handle<Texture>> {
+        self.materials.remove(&material.id())
+    }
+
+    /// The noise of the meshes drawn with `material`.
+    pub fn noise_for(&self, material: &Handle<Material>) -> Option<&Handle<Texture>> {
+        self.materials
+            .get(&material.id())
+            .or_else(|| self.texture.as_ref())
+    }
+
+    pub(crate) fn set_texture(&mut self, texture: Handle<Texture>) {
+        self.texture = Some(texture);
+    }
+}
+
+/// Hash of a cell of the noise, from 0 to 1.
+fn hash(x: u32, y: u32) -> f32 {
+    let mut h = x.wrapping_mul(0x8da6_b343) ^ y.wrapping_mul(0xd816_3841);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    (h & 0xffff) as f32 / 65535.0
+}
+
+/// Value noise at the texel `x`, `y` of an octave with cells of `cell` texels, wrapping around
+/// the texture so it tiles.
+fn value_noise(x: u32, y: u32, cell: u32) -> f32 {
+    let cells = NOISE_SIZE / cell;
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (cx, cy) = (x / cell, y / cell);
+    let fx = smooth((x % cell) as f32 / cell as f32);
+    let fy = smooth((y % cell) as f32 / cell as f32);
+    let corner = |dx: u32, dy: u32| hash((cx + dx) % cells, (cy + dy) % cells);
+    let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * fx;
+    let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * fx;
+    top + (bottom - top) * fy
+}
+
+/// The noise at the texel `x`, `y`, of two octaves.
+fn noise_value(x: u32, y: u32) -> f32 {
+    value_noise(x, y, NOISE_CELL) * 0.65 + value_noise(x, y, NOISE_CELL / 4) * 0.35
+}
+
+/// The texels of the noise texture, as `Rgba8Unorm`.
+fn noise_texels() -> Vec<u8> {
+    let mut data = Vec::with_capacity((NOISE_SIZE * NOISE_SIZE * 4) as usize);
+    for y in 0..NOISE_SIZE {
+        for x in 0..NOISE_SIZE {
+            let value = (noise_value(x, y) * 255.0).round() as u8;
+            data.extend_from_slice(&[value, value, value, 255]);
+        }
+    }
+    data
+}
+
+/// Data of the built-in noise texture, repeated over texture coordinates.
+pub fn noise() -> TextureData {
+    let data = noise_texels();
+    let byte_size = data.len() as u64;
+    let builder = TextureBuilder::new()
+        .with_kind(Kind::D2(NOISE_SIZE, NOISE_SIZE, 1, 1))
+        .with_view_kind(ViewKind::D2)
+        .with_data_width(NOISE_SIZE)
+        .with_data_height(NOISE_SIZE)
+        .with_raw_data(data, Format::Rgba8Unorm);
+    TextureData::from(builder)
+        .with_sampler_info(SamplerInfo::new(Filter::Linear, WrapMode::Tile))
+        .with_byte_size(byte_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn noise_tiles_over_its_range() {
+        let texels = noise_texels();
+        assert_eq!(texels.len(), (NOISE_SIZE * NOISE_SIZE * 4) as usize);
+        let values: Vec<u8> = texels.chunks(4).map(|texel| texel[0]).collect();
+        assert!(*values.iter().min().unwrap() < 64);
+        assert!(*values.iter().max().unwrap() > 192);
+
+        for i in 0..NOISE_SIZE {
+            assert_eq!(
+                value_noise(NOISE_SIZE, i, NOISE_CELL),
+                value_noise(0, i, NOISE_CELL)
+            );
+            assert_eq!(
+                value_noise(i, NOISE_SIZE, NOISE_CELL),
+                value_noise(i, 0, NOISE_CELL)
+            );
+        }
+
+        let mut dissolve = Dissolve::new(0.5);
+        dissolve.edge_width = 0.0;
+        assert_eq!(dissolve.amount_edge(), [0.5, MIN_EDGE_WIDTH]);
+    }
+
+    #[test]
+    fn dissolve_args_match_their_format() {
+        use crate::{pod::DissolveVertexArgs, rendy::mesh::AsVertex};
+        assert_eq!(
+            std::mem::size_of::<DissolveVertexArgs>() as u32,
+            DissolveVertexArgs::vertex().stride
+        );
+    }
+}
@@ -0,0 +1,556 @@
+//! Support for updating the vertex data of existing meshes.
+use crate::{
+    error::MeshError,
+    mesh_util::ProceduralMesh,
+    types::{Backend, Mesh},
+    util,
+};
+use amethyst_assets::{Handle, WeakHandle};
+use derivative::Derivative;
+use fnv::FnvHashMap;
+use rendy::{
+    command::{QueueId, RenderPassEncoder},
+    factory::{BufferState, Factory},
+    hal::{
+        buffer::{Access, Usage},
+        pso::PipelineStage,
+        IndexType,
+    },
+    memory::Data,
+    mesh::{Incompatible, VertexFormat},
+    resource::{Buffer, Escape},
+};
+use std::ops::Range;
+
+/// Resource for meshes whose vertex data changes at runtime, such as cloth or destructible
+/// terrain chunks.
+///
+/// A mesh is registered together with the `ProceduralMesh` it was built from. Updates are
+/// validated against the registered data and scheduled; the `RenderingSystem` uploads pending
+/// updates during its asset loading step, before the graph is run. The `Handle<Mesh>`, its
+/// storage slot and its GPU buffers stay the same.
+///
+/// ### Buffers:
+///
+/// A dynamic mesh keeps one vertex buffer holding all of its attributes and one index buffer,
+/// both sized to the next power of two of their data. Updates are written in place through
+/// staging buffers; the buffers are only allocated again when the data outgrows them. The
+/// positions only updates write the positions and the attributes computed from them, the other
+/// attributes are left as they are on the GPU. Meshes written in place by the crate, like the
+/// ones of `Ribbon`s, only upload the ranges of vertices and indices that changed.
+///
+/// ### Frames in flight:
+///
+/// The writes are ordered after the reads of the frames already submitted, so those keep
+/// drawing the old data. Every frame recorded after the upload uses the new data, which means an
+/// update becomes visible with at most one frame of delay. Buffers that had to grow are released
+/// through rendy's deferred destruction.
+#[derive(Debug, Default)]
+pub struct DynamicMeshes {
+    meshes: FnvHashMap<u32, DynamicMesh>,
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct DynamicMesh {
+    #[derivative(Debug = "ignore")]
+    handle: WeakHandle<Mesh>,
+    data: ProceduralMesh,
+    pending: Pending,
+}
+
+/// Part of a dynamic mesh waiting for upload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Pending {
+    None,
+    /// The positions and the attributes computed from them.
+    Positions,
+    All,
+    /// The vertices and indices in the ranges, which don't overlap.
+    Ranges {
+        vertices: Vec<Range<usize>>,
+        indices: Vec<Range<usize>>,
+    },
+}
+
+impl Pending {
+    fn positions(&mut self) {
+        *self = match self {
+            Pending::None | Pending::Positions => Pending::Positions,
+            _ => Pending::All,
+        };
+    }
+
+    /// Add ranges of vertices and indices written in place.
+    pub(crate) fn ranges(&mut self, vertices: Range<usize>, indices: Range<usize>) {
+        match self {
+            Pending::None => {
+                *self = Pending::Ranges {
+                    vertices: vec![vertices],
+                    indices: vec![indices],
+                }
+            }
+            Pending::Ranges {
+                vertices: pending_vertices,
+                indices: pending_indices,
+            } => {
+                merge_range(pending_vertices, vertices);
+                merge_range(pending_indices, indices);
+            }
+            Pending::Positions => *self = Pending::All,
+            Pending::All => {}
+        }
+    }
+}
+
+/// Add `range` to `ranges`, joining the ranges it overlaps or touches.
+fn merge_range(ranges: &mut Vec<Range<usize>>, mut range: Range<usize>) {
+    if range.start >= range.end {
+        return;
+    }
+    ranges.retain(|other| {
+        if other.start <= range.end && range.start <= other.end {
+            range = range.start.min(other.start)..range.end.max(other.end);
+            false
+        } else {
+            true
+        }
+    });
+    ranges.push(range);
+}
+
+impl DynamicMeshes {
+    /// Register a mesh for dynamic updates, with the data it was built from.
+    pub fn register(&mut self, handle: &Handle<Mesh>, data: ProceduralMesh) {
+        self.meshes.insert(
+            handle.id(),
+            DynamicMesh {
+                handle: handle.downgrade(),
+                data,
+                pending: Pending::None,
+            },
+        );
+    }
+
+    /// Stop tracking updates for a mesh. Pending updates are discarded.
+    pub fn unregister(&mut self, handle: &Handle<Mesh>) {
+        self.meshes.remove(&handle.id());
+    }
+
+    /// Returns `true` if the mesh is registered for dynamic updates.
+    pub fn contains(&self, handle: &Handle<Mesh>) -> bool {
+        self.meshes.contains_key(&handle.id())
+    }
+
+    /// Schedule new vertex data for a registered mesh. The data must provide the same attributes
+    /// as the registered data, its vertex and index counts may change.
+    pub fn update(&mut self, handle: &Handle<Mesh>, data: ProceduralMesh) -> Result<(), MeshError> {
+        let mesh = self
+            .meshes
+            .get_mut(&handle.id())
+            .ok_or(MeshError::NotDynamic)?;
+        if data.layout().vertex_formats != mesh.data.layout().vertex_formats {
+            return Err(MeshError::LayoutChanged);
+        }
+        mesh.data = data;
+        mesh.pending = Pending::All;
+        Ok(())
+    }
+
+    /// Schedule new vertex positions for a registered mesh, keeping all other attributes.
+    ///
+    /// This is cheaper than `update` since only the positions, and the normals and tangents
+    /// recomputed from them if the registered data uses `with_recomputed_normals` or
+    /// `with_generated_tangents`, are written to the GPU. Provided attributes are kept as they
+    /// are.
+    pub fn update_positions_only(
+        &mut self,
+        handle: &Handle<Mesh>,
+        positions: Vec<[f32; 3]>,
+    ) -> Result<(), MeshError> {
+        let mesh = self
+            .meshes
+            .get_mut(&handle.id())
+            .ok_or(MeshError::NotDynamic)?;
+        if positions.len() != mesh.data.vertex_count() {
+            return Err(MeshError::AttributeCountMismatch {
+                attribute: "positions",
+                expected: mesh.data.vertex_count(),
+                found: positions.len(),
+            });
+        }
+        mesh.data.set_positions(positions);
+        mesh.pending.positions();
+        Ok(())
+    }
+
+    /// Write the data of a registered mesh in place, without changing its vertex and index
+    /// counts. `write` adds the ranges it wrote to the pending updates, only those are uploaded.
+    pub(crate) fn write_in_place<F>(
+        &mut self,
+        handle: &Handle<Mesh>,
+        write: F,
+    ) -> Result<(), MeshError>
+    where
+        F: FnOnce(&mut ProceduralMesh, &mut Pending),
+    {
+        let mesh = self
+            .meshes
+            .get_mut(&handle.id())
+            .ok_or(MeshError::NotDynamic)?;
+        write(&mut mesh.data, &mut mesh.pending);
+        Ok(())
+    }
+
+    /// Upload the pending updates, dropping meshes whose handles are no longer alive.
+    ///
+    /// `upload` is called with the handle, the data and the part of it that changed, and
+    /// returns `false` for meshes that are still loading; their updates stay pending.
+    pub(crate) fn upload_pending<F>(&mut self, mut upload: F)
+    where
+        F: FnMut(&Handle<Mesh>, &ProceduralMesh, &Pending) -> bool,
+    {
+        self.meshes.retain(|_, mesh| !mesh.handle.is_dead());
+        for mesh in self.meshes.values_mut() {
+            if mesh.pending == Pending::None {
+                continue;
+            }
+            if let Some(handle) = mesh.handle.upgrade() {
+                if upload(&handle, &mesh.data, &mesh.pending) {
+                    mesh.pending = Pending::None;
+                }
+            }
+        }
+    }
+
+    /// Visit the data of all live registered meshes, to upload them again after the device was
+    /// lost. Pending updates are included.
+    pub(crate) fn for_each_registered<F>(&mut self, mut upload: F)
+    where
+        F: FnMut(&Handle<Mesh>, &ProceduralMesh),
+    {
+        for mesh in self.meshes.values_mut() {
+            if let Some(handle) = mesh.handle.upgrade() {
+                upload(&handle, &mesh.data);
+                mesh.pending = Pending::None;
+            }
+        }
+    }
+}
+
+/// GPU buffers of a mesh registered in `DynamicMeshes`, written in place when it is updated.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub struct DynamicMeshBuffers<B: Backend> {
+    #[derivative(Debug = "ignore")]
+    vertex: Option<Escape<Buffer<B>>>,
+    #[derivative(Debug = "ignore")]
+    index: Option<Escape<Buffer<B>>>,
+    vertex_formats: Vec<(VertexFormat, u64)>,
+    index_type: Option<IndexType>,
+    len: u32,
+}
+
+impl<B: Backend> DynamicMeshBuffers<B> {
+    /// Allocate the buffers for `data` and upload it.
+    pub(crate) fn new(
+        factory: &Factory<B>,
+        queue: QueueId,
+        data: &ProceduralMesh,
+    ) -> Result<Self, failure::Error> {
+        let mut buffers = DynamicMeshBuffers {
+            vertex: None,
+            index: None,
+            vertex_formats: Vec::new(),
+            index_type: None,
+            len: 0,
+        };
+        buffers.write(factory, queue, data, &Pending::All)?;
+        Ok(buffers)
+    }
+
+    /// Write the `pending` part of `data` into the buffers, growing them if it doesn't fit.
+    pub(crate) fn write(
+        &mut self,
+        factory: &Factory<B>,
+        queue: QueueId,
+        data: &ProceduralMesh,
+        pending: &Pending,
+    ) -> Result<(), failure::Error> {
+        let layout = data.layout();
+        let count = u64::from(layout.vertex_count);
+        let mut size = 0;
+        let vertex_formats = layout
+            .vertex_formats
+            .into_iter()
+            .map(|format| {
+                let offset = size;
+                size += u64::from(format.stride) * count;
+                (format, offset)
+            })
+            .collect::<Vec<_>>();
+
+        let grown = util::ensure_buffer(
+            factory,
+            &mut self.vertex,
+            Usage::VERTEX | Usage::TRANSFER_DST,
+            Data,
+            size,
+        )?;
+        // Moved or resized attributes have to be written again as a whole.
+        let pending = if grown || vertex_formats != self.vertex_formats {
+            &Pending::All
+        } else {
+            pending
+        };
+        let positions_only = *pending == Pending::Positions;
+        let (vertex_ranges, index_ranges) = match pending {
+            Pending::Ranges { vertices, indices } => (Some(vertices), Some(indices)),
+            _ => (None, None),
+        };
+        let last = if grown {
+            None
+        } else {
+            Some(read_state(queue, Access::VERTEX_BUFFER_READ))
+        };
+        let vertex_data = data.vertex_data(positions_only)?;
+        if let Some(buffer) = &self.vertex {
+            for ((format, offset), bytes) in vertex_formats.iter().zip(vertex_data) {
+                if let Some(bytes) = bytes {
+                    upload_ranges(
+                        factory,
+                        buffer,
+                        *offset,
+                        &bytes,
+                        format.stride as usize,
+                        vertex_ranges,
+                        last,
+                        read_state(queue, Access::VERTEX_BUFFER_READ),
+                    )?;
+                }
+            }
+        }
+        self.vertex_formats = vertex_formats;
+
+        if !positions_only {
+            let last_index_type = self.index_type.take();
+            if let Some((index_type, bytes)) = data.index_data() {
+                let grown = util::ensure_buffer(
+                    factory,
+                    &mut self.index,
+                    Usage::INDEX | Usage::TRANSFER_DST,
+                    Data,
+                    bytes.len() as u64,
+                )?;
+                let last = if grown {
+                    None
+                } else {
+                    Some(read_state(queue, Access::INDEX_BUFFER_READ))
+                };
+                if let Some(buffer) = &self.index {
+                    upload_ranges(
+                        factory,
+                        buffer,
+                        0,
+                        &bytes,
+                        index_size(index_type),
+                        index_ranges.filter(|_| !grown && last_index_type == Some(index_type)),
+                        last,
+                        read_state(queue, Access::INDEX_BUFFER_READ),
+                    )?;
+                }
+                self.index_type = Some(index_type);
+            }
+        }
+        self.len = if self.index_type.is_some() {
+            data.indices().map_or(0, |indices| indices.len() as u32)
+        } else {
+            layout.vertex_count
+        };
+        Ok(())
+    }
+
+    /// Bind the vertex buffers of the attributes matching `formats` to consecutive bindings
+    /// starting at `first_binding`, and the index buffer if the mesh is indexed. Returns the
+    /// number of vertices, or indices, to draw.
+    pub fn bind(
+        &self,
+        first_binding: u32,
+        formats: &[VertexFormat],
+        encoder: &mut RenderPassEncoder<'_, B>,
+    ) -> Result<u32, Incompatible> {
+        let mut offsets = Vec::with_capacity(formats.len());
+        for format in formats {
+            let offset = self
+                .vertex_formats
+                .iter()
+                .find(|(own, _)| own == format)
+                .map(|(_, offset)| *offset)
+                .ok_or_else(|| Incompatible {
+                    not_found: format.clone(),
+                    in_formats: self.vertex_formats.iter().map(|(f, _)| f.clone()).collect(),
+                })?;
+            offsets.push(offset);
+        }
+        if self.len == 0 {
+            return Ok(0);
+        }
+        let vertex = self
+            .vertex
+            .as_ref()
+            .expect("Non-empty mesh without vertex buffer");
+        unsafe {
+            encoder.bind_vertex_buffers(
+                first_binding,
+                offsets.into_iter().map(|offset| (vertex.raw(), offset)),
+            );
+            if let (Some(index_type), Some(index)) = (self.index_type, &self.index) {
+                encoder.bind_index_buffer(index.raw(), 0, index_type);
+            }
+        }
+        Ok(self.len)
+    }
+
+    /// Number of vertices, or indices for indexed meshes, drawn for the mesh.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Returns `true` if the mesh draws nothing.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Type of the indices, `None` for meshes without index buffer.
+    pub fn index_type(&self) -> Option<IndexType> {
+        self.index_type
+    }
+}
+
+/// Upload the elements of `bytes` in `ranges`, or all of them, to `buffer` at `offset`.
+#[allow(clippy::too_many_arguments)]
+fn upload_ranges<B: Backend>(
+    factory: &Factory<B>,
+    buffer: &Buffer<B>,
+    offset: u64,
+    bytes: &[u8],
+    element_size: usize,
+    ranges: Option<&Vec<Range<usize>>>,
+    last: Option<BufferState>,
+    next: BufferState,
+) -> Result<(), failure::Error> {
+    let whole = [0..bytes.len() / element_size.max(1)];
+    let ranges = ranges.map_or(&whole[..], Vec::as_slice);
+    for range in ranges {
+        let start = (range.start * element_size).min(bytes.len());
+        let end = (range.end * element_size).min(bytes.len());
+        if start < end {
+            unsafe {
+                factory.upload_buffer(
+                    buffer,
+                    offset + start as u64,
+                    &bytes[start..end],
+                    last,
+                    next,
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn index_size(index_type: IndexType) -> usize {
+    match index_type {
+        IndexType::U16 => 2,
+        IndexType::U32 => 4,
+    }
+}
+
+fn read_state(queue: QueueId, access: Access) -> BufferState {
+    BufferState::new(queue)
+        .with_stage(PipelineStage::VERTEX_INPUT)
+        .with_access(access)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst_assets::{AssetStorage, Loader};
+    use rayon::ThreadPoolBuilder;
+    use std::sync::Arc;
+
+    fn triangle(offset: f32) -> ProceduralMesh {
+        ProceduralMesh::new()
+            .with_positions(vec![
+                [offset, 0.0, 0.0],
+                [offset + 1.0, 0.0, 0.0],
+                [offset, 1.0, 0.0],
+            ])
+            .with_tex_coords(vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]])
+    }
+
+    fn pending(meshes: &mut DynamicMeshes) -> Vec<Pending> {
+        let mut pending = Vec::new();
+        meshes.upload_pending(|_, _, part| {
+            pending.push(part.clone());
+            true
+        });
+        pending
+    }
+
+    #[test]
+    fn updates_keep_what_changed() {
+        let loader = Loader::new(".", Arc::new(ThreadPoolBuilder::new().build().unwrap()));
+        let storage = AssetStorage::<Mesh>::default();
+        let handle = loader.load_from_data(triangle(0.0).build().unwrap(), (), &storage);
+
+        let mut meshes = DynamicMeshes::default();
+        meshes.register(&handle, triangle(0.0));
+        assert!(pending(&mut meshes).is_empty());
+
+        meshes
+            .update_positions_only(&handle, vec![[0.0; 3]; 3])
+            .unwrap();
+        assert_eq!(pending(&mut meshes), vec![Pending::Positions]);
+
+        // Positions written after a full update don't narrow it.
+        let grown = ProceduralMesh::new()
+            .with_positions(vec![[0.0; 3]; 6])
+            .with_tex_coords(vec![[0.0; 2]; 6]);
+        meshes.update(&handle, grown).unwrap();
+        meshes
+            .update_positions_only(&handle, vec![[1.0; 3]; 6])
+            .unwrap();
+        assert_eq!(pending(&mut meshes), vec![Pending::All]);
+        assert!(pending(&mut meshes).is_empty());
+
+        // Meshes that are still loading keep their updates.
+        meshes.update(&handle, triangle(1.0)).unwrap();
+        meshes.upload_pending(|_, _, _| false);
+        assert_eq!(pending(&mut meshes), vec![Pending::All]);
+
+        // Writes in place join their ranges.
+        meshes
+            .write_in_place(&handle, |_, pending| {
+                pending.ranges(0..1, 0..0);
+                pending.ranges(2..3, 0..3);
+                pending.ranges(1..2, 0..0);
+            })
+            .unwrap();
+        assert_eq!(
+            pending(&mut meshes),
+            vec![Pending::Ranges {
+                vertices: vec![0..3],
+                indices: vec![0..3],
+            }]
+        );
+
+        assert_eq!(
+            meshes.update(
+                &handle,
+                ProceduralMesh::new().with_positions(vec![[0.0; 3]; 3])
+            ),
+            Err(MeshError::LayoutChanged)
+        );
+    }
+}
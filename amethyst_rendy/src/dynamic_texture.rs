@@ -0,0 +1,370 @@
+//! Support for creating textures from raw pixels and updating them at runtime.
+use crate::{
+    error::TextureError,
+    types::{Texture, TextureData},
+};
+use amethyst_assets::{Handle, WeakHandle};
+use derivative::Derivative;
+use fnv::FnvHashMap;
+use rendy::{
+    hal::{
+        format::Format,
+        image::{Kind, SamplerInfo, ViewKind},
+    },
+    texture::TextureBuilder,
+};
+
+/// Raw pixels of an uncompressed 2D texture, with the top row first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TexturePixels {
+    width: u32,
+    height: u32,
+    format: Format,
+    data: Vec<u8>,
+    sampler_info: SamplerInfo,
+}
+
+impl TexturePixels {
+    /// Wrap `width` by `height` pixels of `format`. Fails if the format is block compressed or
+    /// the length of `data` doesn't match the size of the texture.
+    pub fn new(
+        width: u32,
+        height: u32,
+        format: Format,
+        data: Vec<u8>,
+        sampler_info: SamplerInfo,
+    ) -> Result<Self, TextureError> {
+        let pixel_size = pixel_size(format)?;
+        let expected = width as usize * height as usize * pixel_size;
+        if width == 0 || height == 0 {
+            return Err(TextureError::EmptyTexture);
+        }
+        if data.len() != expected {
+            return Err(TextureError::PixelDataSize {
+                expected,
+                found: data.len(),
+            });
+        }
+        Ok(TexturePixels {
+            width,
+            height,
+            format,
+            data,
+            sampler_info,
+        })
+    }
+
+    /// Width in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Format of the pixels.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// The pixel data.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Replace the `width` by `height` pixels whose top left corner is at `x`, `y`. `pixels`
+    /// holds the rows of the region, in the format of the texture.
+    pub fn update_region(
+        &mut self,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+    ) -> Result<(), TextureError> {
+        let out_of_bounds = u64::from(x) + u64::from(width) > u64::from(self.width)
+            || u64::from(y) + u64::from(height) > u64::from(self.height);
+        if out_of_bounds {
+            return Err(TextureError::RegionOutOfBounds {
+                region: [x, y, width, height],
+                size: [self.width, self.height],
+            });
+        }
+        let pixel_size = pixel_size(self.format)?;
+        let row = width as usize * pixel_size;
+        if pixels.len() != row * height as usize {
+            return Err(TextureError::PixelDataSize {
+                expected: row * height as usize,
+                found: pixels.len(),
+            });
+        }
+        if row == 0 {
+            return Ok(());
+        }
+
+        let stride = self.width as usize * pixel_size;
+        for (i, source) in pixels.chunks_exact(row).enumerate() {
+            let start = (y as usize + i) * stride + x as usize * pixel_size;
+            self.data[start..start + row].copy_from_slice(source);
+        }
+        Ok(())
+    }
+
+    /// Texture data uploading a copy of the pixels.
+    pub fn texture_data(&self) -> TextureData {
+        self.clone().into()
+    }
+}
+
+impl From<TexturePixels> for TextureData {
+    fn from(pixels: TexturePixels) -> Self {
+        let byte_size = pixels.data.len() as u64;
+        TextureData::from(
+            TextureBuilder::new()
+                .with_kind(Kind::D2(pixels.width, pixels.height, 1, 1))
+                .with_view_kind(ViewKind::D2)
+                .with_data_width(pixels.width)
+                .with_data_height(pixels.height)
+                .with_raw_data(pixels.data, pixels.format),
+        )
+        .with_required_format(pixels.format)
+        .with_sampler_info(pixels.sampler_info)
+        .with_byte_size(byte_size)
+    }
+}
+
+/// Bytes per pixel of an uncompressed format.
+fn pixel_size(format: Format) -> Result<usize, TextureError> {
+    let desc = format.surface_desc();
+    if desc.dim != (1, 1) {
+        return Err(TextureError::CompressedPixels(format));
+    }
+    Ok(desc.bits as usize / 8)
+}
+
+/// Resource for textures whose pixels change at runtime, such as minimaps, procedural noise or
+/// video frames.
+///
+/// A texture is registered together with the `TexturePixels` it was created from, which are
+/// kept as a staging copy. Region updates are written to the copy and scheduled; the
+/// `RenderingSystem` uploads pending updates during its asset loading step, before the graph is
+/// run. The `Handle<Texture>` and its storage slot stay the same, only the asset version is
+/// incremented.
+///
+/// ### Frames in flight:
+///
+/// As with `DynamicMeshes`, the previous texture is released through rendy's deferred
+/// destruction, so frames that are still in flight keep sampling the old pixels. Every frame
+/// recorded after the upload samples the new pixels. Several updates scheduled between two
+/// uploads are merged into a single upload.
+///
+/// Note that the texture is re-created from the full staging copy on upload. Avoid updating a
+/// texture more often than it is drawn.
+#[derive(Debug, Default)]
+pub struct DynamicTextures {
+    textures: FnvHashMap<u32, DynamicTexture>,
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct DynamicTexture {
+    #[derivative(Debug = "ignore")]
+    handle: WeakHandle<Texture>,
+    pixels: TexturePixels,
+    dirty: bool,
+}
+
+impl DynamicTextures {
+    /// Register a texture for dynamic updates, with the pixels it was created from.
+    pub fn register(&mut self, handle: &Handle<Texture>, pixels: TexturePixels) {
+        self.textures.insert(
+            handle.id(),
+            DynamicTexture {
+                handle: handle.downgrade(),
+                pixels,
+                dirty: false,
+            },
+        );
+    }
+
+    /// Stop tracking updates for a texture. Pending updates are discarded.
+    pub fn unregister(&mut self, handle: &Handle<Texture>) {
+        self.textures.remove(&handle.id());
+    }
+
+    /// Returns `true` if the texture is registered for dynamic updates.
+    pub fn contains(&self, handle: &Handle<Texture>) -> bool {
+        self.textures.contains_key(&handle.id())
+    }
+
+    /// Current pixels of a registered texture, including pending updates.
+    pub fn pixels(&self, handle: &Handle<Texture>) -> Option<&TexturePixels> {
+        self.textures
+            .get(&handle.id())
+            .map(|texture| &texture.pixels)
+    }
+
+    /// Schedule new pixels for a region of a registered texture, see
+    /// `TexturePixels::update_region`.
+    pub fn update_region(
+        &mut self,
+        handle: &Handle<Texture>,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+    ) -> Result<(), TextureError> {
+        let texture = self
+            .textures
+            .get_mut(&handle.id())
+            .ok_or(TextureError::NotDynamic)?;
+        texture.pixels.update_region(x, y, width, height, pixels)?;
+        texture.dirty = true;
+        Ok(())
+    }
+
+    /// Take all pending updates of textures that are loaded, dropping textures whose handles
+    /// are no longer alive. Updates of textures that are still loading stay pending.
+    pub(crate) fn drain_pending<F>(&mut self, is_loaded: F) -> Vec<(Handle<Texture>, TextureData)>
+    where
+        F: Fn(&Handle<Texture>) -> bool,
+    {
+        self.textures.retain(|_, texture| !texture.handle.is_dead());
+        self.textures
+            .values_mut()
+            .filter(|texture| texture.dirty)
+            .filter_map(|texture| {
+                let handle = texture.handle.upgrade().filter(|h| is_loaded(h))?;
+                texture.dirty = false;
+                Some((handle, texture.pixels.texture_data()))
+            })
+            .collect()
+    }
+
+    /// The data of all live registered textures, to upload them again after the device was
+    /// lost. Pending updates are included.
+    pub(crate) fn registered(&mut self) -> Vec<(Handle<Texture>, TextureData)> {
+        self.textures
+            .values_mut()
+            .filter_map(|texture| {
+                let handle = texture.handle.upgrade()?;
+                texture.dirty = false;
+                Some((handle, texture.pixels.texture_data()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst_assets::{AssetStorage, Loader};
+    use rayon::ThreadPoolBuilder;
+    use rendy::hal::image::{Filter, WrapMode};
+    use std::sync::Arc;
+
+    fn gradient(width: u32, height: u32) -> TexturePixels {
+        let data = (0..width * height * 4).map(|i| i as u8).collect();
+        TexturePixels::new(
+            width,
+            height,
+            Format::Rgba8Unorm,
+            data,
+            SamplerInfo::new(Filter::Nearest, WrapMode::Clamp),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn pixel_data_must_match_size_and_format() {
+        let sampler = SamplerInfo::new(Filter::Nearest, WrapMode::Clamp);
+        assert_eq!(
+            TexturePixels::new(4, 4, Format::Rgba8Unorm, vec![0; 63], sampler.clone()),
+            Err(TextureError::PixelDataSize {
+                expected: 64,
+                found: 63
+            })
+        );
+        assert!(
+            TexturePixels::new(4, 4, Format::Rgba16Sfloat, vec![0; 128], sampler.clone()).is_ok()
+        );
+        assert_eq!(
+            TexturePixels::new(4, 4, Format::Bc1RgbaUnorm, vec![0; 8], sampler.clone()),
+            Err(TextureError::CompressedPixels(Format::Bc1RgbaUnorm))
+        );
+        assert_eq!(
+            TexturePixels::new(0, 4, Format::R8Unorm, vec![], sampler),
+            Err(TextureError::EmptyTexture)
+        );
+    }
+
+    #[test]
+    fn update_region_writes_rows() {
+        let mut pixels = gradient(4, 3);
+        let original = pixels.clone();
+        pixels.update_region(1, 1, 2, 2, &[255; 16]).unwrap();
+        for y in 0..3 {
+            for x in 0..4 {
+                let start = (y * 4 + x) * 4;
+                let inside = (1..3).contains(&x) && (1..3).contains(&y);
+                let expected = if inside {
+                    &[255; 4][..]
+                } else {
+                    &original.data()[start..start + 4]
+                };
+                assert_eq!(&pixels.data()[start..start + 4], expected);
+            }
+        }
+
+        assert_eq!(
+            pixels.update_region(3, 0, 2, 1, &[0; 8]),
+            Err(TextureError::RegionOutOfBounds {
+                region: [3, 0, 2, 1],
+                size: [4, 3],
+            })
+        );
+        assert_eq!(
+            pixels.update_region(0, 0, 2, 1, &[0; 4]),
+            Err(TextureError::PixelDataSize {
+                expected: 8,
+                found: 4
+            })
+        );
+    }
+
+    #[test]
+    fn updates_are_merged_until_loaded() {
+        let loader = Loader::new(".", Arc::new(ThreadPoolBuilder::new().build().unwrap()));
+        let storage = AssetStorage::<Texture>::default();
+        let pixels = gradient(2, 2);
+        let handle = loader.load_from_data(pixels.texture_data(), (), &storage);
+
+        let mut textures = DynamicTextures::default();
+        assert_eq!(
+            textures.update_region(&handle, 0, 0, 1, 1, &[0; 4]),
+            Err(TextureError::NotDynamic)
+        );
+        textures.register(&handle, pixels);
+        assert!(textures.drain_pending(|_| true).is_empty());
+
+        textures
+            .update_region(&handle, 0, 0, 1, 1, &[1; 4])
+            .unwrap();
+        textures
+            .update_region(&handle, 1, 1, 1, 1, &[2; 4])
+            .unwrap();
+
+        // Textures that are still loading keep their updates
+        assert!(textures.drain_pending(|_| false).is_empty());
+        let pending = textures.drain_pending(|_| true);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].0, handle);
+        let data = textures.pixels(&handle).unwrap().data();
+        assert_eq!(&data[..4], &[1; 4]);
+        assert_eq!(&data[12..], &[2; 4]);
+        assert!(textures.drain_pending(|_| true).is_empty());
+    }
+}
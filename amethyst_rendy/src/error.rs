@@ -1,5 +1,6 @@
 //! Renderer error types.
 
+use rendy::hal::{format::Format, pso::ShaderStageFlags};
 use std::{error, fmt};
 
 /// Common renderer error type.
@@ -20,3 +21,648 @@ impl fmt::Display for Error {
         }
     }
 }
+
+/// Errors produced while generating or building mesh data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshError {
+    /// A shape was requested with more subdivisions than the generator supports.
+    TooManySubdivisions {
+        /// Number of subdivisions requested.
+        requested: usize,
+        /// Maximum number of subdivisions supported.
+        max: usize,
+    },
+    /// A vertex attribute does not have one element per vertex.
+    AttributeCountMismatch {
+        /// Name of the mismatched attribute.
+        attribute: &'static str,
+        /// Number of vertices of the mesh.
+        expected: usize,
+        /// Number of elements of the attribute.
+        found: usize,
+    },
+    /// A vertex attribute required for the requested operation was not provided.
+    MissingAttribute(&'static str),
+    /// The number of indices does not describe a whole number of triangles.
+    InvalidIndexCount(usize),
+    /// An index refers to a vertex that does not exist.
+    IndexOutOfBounds {
+        /// The offending index.
+        index: u32,
+        /// Number of vertices of the mesh.
+        vertex_count: usize,
+    },
+    /// The mesh was not registered for dynamic updates.
+    NotDynamic,
+    /// A dynamic mesh update does not provide the same set of attributes as the original mesh.
+    LayoutChanged,
+    /// A mesh to merge does not provide the same set of attributes as the first mesh.
+    LayoutMismatch {
+        /// Position of the mismatched mesh in the merge input.
+        index: usize,
+    },
+    /// The entities to merge do not share a single material.
+    MaterialMismatch,
+    /// The vertex data of a `MeshBuilder` could not be read back, because it is not a triangle
+    /// list or has a vertex buffer whose format is not one of the formats of rendy with known
+    /// attributes.
+    UnreadableBuilder,
+    /// A grid needs at least two samples along each axis.
+    InvalidGridSize {
+        /// Number of samples along the x axis.
+        width: usize,
+        /// Number of samples along the z axis.
+        depth: usize,
+    },
+}
+
+impl error::Error for MeshError {}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::MeshError::*;
+
+        match *self {
+            TooManySubdivisions { requested, max } => write!(
+                fmt,
+                "Requested {} subdivisions, but at most {} are supported",
+                requested, max
+            ),
+            AttributeCountMismatch {
+                attribute,
+                expected,
+                found,
+            } => write!(
+                fmt,
+                "Expected {} elements for attribute `{}`, found {}",
+                expected, attribute, found
+            ),
+            MissingAttribute(attribute) => write!(fmt, "Missing vertex attribute `{}`", attribute),
+            InvalidIndexCount(count) => {
+                write!(fmt, "Index count {} is not a multiple of three", count)
+            }
+            IndexOutOfBounds {
+                index,
+                vertex_count,
+            } => write!(
+                fmt,
+                "Index {} is out of bounds for {} vertices",
+                index, vertex_count
+            ),
+            NotDynamic => write!(fmt, "Mesh was not registered for dynamic updates"),
+            LayoutChanged => write!(
+                fmt,
+                "Dynamic mesh update does not match the original vertex layout"
+            ),
+            LayoutMismatch { index } => write!(
+                fmt,
+                "Vertex layout of mesh {} does not match the first mesh to merge",
+                index
+            ),
+            MaterialMismatch => write!(fmt, "Merged entities must share a single material"),
+            UnreadableBuilder => write!(
+                fmt,
+                "Mesh builder vertex data is not a triangle list of known vertex formats"
+            ),
+            InvalidGridSize { width, depth } => write!(
+                fmt,
+                "Grid of {}x{} samples is too small, at least 2x2 are required",
+                width, depth
+            ),
+        }
+    }
+}
+
+/// Errors produced while decoding or uploading texture data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TextureError {
+    /// The data is not a well formed DDS file.
+    InvalidDds(&'static str),
+    /// The DDS file is well formed, but uses a pixel format or layout that can't be loaded.
+    UnsupportedDds(String),
+    /// The data is not a well formed KTX2 file.
+    InvalidKtx2(&'static str),
+    /// The KTX2 file is well formed, but uses a pixel format, supercompression or layout that
+    /// can't be loaded.
+    UnsupportedKtx2(String),
+    /// The data is not a well formed Radiance HDR file.
+    InvalidHdr(&'static str),
+    /// The Radiance HDR file is well formed, but uses a pixel format or orientation that can't be
+    /// loaded.
+    UnsupportedHdr(String),
+    /// The data is not a well formed OpenEXR file.
+    InvalidExr(&'static str),
+    /// The OpenEXR file is well formed, but uses a compression or layout that can't be loaded.
+    UnsupportedExr(String),
+    /// The images of a cubemap don't form six square faces of equal size.
+    InvalidCubemap(String),
+    /// The device can't sample textures of this format.
+    UnsupportedFormat(Format),
+    /// Raw pixels can't be provided in a block compressed format.
+    CompressedPixels(Format),
+    /// A texture created from raw pixels has no pixels.
+    EmptyTexture,
+    /// The length of raw pixel data does not match the size of the texture or region.
+    PixelDataSize {
+        /// Number of bytes required.
+        expected: usize,
+        /// Number of bytes provided.
+        found: usize,
+    },
+    /// A region to update does not fit into the texture.
+    RegionOutOfBounds {
+        /// The region as `[x, y, width, height]`.
+        region: [u32; 4],
+        /// Width and height of the texture.
+        size: [u32; 2],
+    },
+    /// The texture was not registered for dynamic updates.
+    NotDynamic,
+    /// The frames of an animated texture can't be combined into a flipbook.
+    InvalidFlipbook(String),
+    /// Raw palette indices were requested from an image that is not an indexed PNG.
+    NotIndexed,
+}
+
+impl error::Error for TextureError {}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::TextureError::*;
+
+        match *self {
+            InvalidDds(reason) => write!(fmt, "Invalid DDS file: {}", reason),
+            UnsupportedDds(ref what) => write!(fmt, "Unsupported DDS file: {}", what),
+            InvalidKtx2(reason) => write!(fmt, "Invalid KTX2 file: {}", reason),
+            UnsupportedKtx2(ref what) => write!(fmt, "Unsupported KTX2 file: {}", what),
+            InvalidHdr(reason) => write!(fmt, "Invalid HDR file: {}", reason),
+            UnsupportedHdr(ref what) => write!(fmt, "Unsupported HDR file: {}", what),
+            InvalidExr(reason) => write!(fmt, "Invalid EXR file: {}", reason),
+            UnsupportedExr(ref what) => write!(fmt, "Unsupported EXR file: {}", what),
+            InvalidCubemap(ref reason) => write!(fmt, "Invalid cubemap: {}", reason),
+            UnsupportedFormat(format) => write!(
+                fmt,
+                "Texture format {:?} is not supported by the device",
+                format
+            ),
+            CompressedPixels(format) => write!(
+                fmt,
+                "Raw pixels can't be provided in block compressed format {:?}",
+                format
+            ),
+            EmptyTexture => write!(fmt, "Texture has no pixels"),
+            PixelDataSize { expected, found } => write!(
+                fmt,
+                "Expected {} bytes of pixel data, found {}",
+                expected, found
+            ),
+            RegionOutOfBounds { region, size } => write!(
+                fmt,
+                "Region {}x{} at ({}, {}) does not fit into {}x{} texture",
+                region[2], region[3], region[0], region[1], size[0], size[1]
+            ),
+            NotDynamic => write!(fmt, "Texture was not registered for dynamic updates"),
+            InvalidFlipbook(ref reason) => write!(fmt, "Invalid flipbook: {}", reason),
+            NotIndexed => write!(fmt, "Image is not an indexed PNG"),
+        }
+    }
+}
+
+/// Errors produced while render plugins plan the render graph.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenderPlanError {
+    /// More than one plugin set the target of the graph.
+    TargetAlreadySet,
+    /// A plugin requires a stencil aspect, but the depth images have this format without one.
+    NoStencil(Format),
+}
+
+impl error::Error for RenderPlanError {}
+
+impl fmt::Display for RenderPlanError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::RenderPlanError::*;
+
+        match *self {
+            TargetAlreadySet => write!(fmt, "The render target was already set by another plugin"),
+            NoStencil(format) => write!(
+                fmt,
+                "A plugin requires a stencil, but the depth format {:?} has none",
+                format
+            ),
+        }
+    }
+}
+
+/// Errors produced while reloading shaders at runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShaderError {
+    /// The stage of a shader can't be told from its name.
+    UnknownStage(String),
+    /// A shader file could not be read.
+    Read {
+        /// Name of the shader.
+        name: String,
+        /// Why the file could not be read.
+        reason: String,
+    },
+    /// A shader file is not valid SPIR-V.
+    InvalidSpirv(String),
+    /// A GLSL shader failed to compile.
+    Compile {
+        /// Name of the shader.
+        name: String,
+        /// Output of the compiler.
+        message: String,
+    },
+}
+
+impl error::Error for ShaderError {}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::ShaderError::*;
+
+        match *self {
+            UnknownStage(ref name) => write!(fmt, "Unknown stage of shader {}", name),
+            Read {
+                ref name,
+                ref reason,
+            } => write!(fmt, "Failed to read shader {}: {}", name, reason),
+            InvalidSpirv(ref name) => write!(fmt, "Shader {} is not valid SPIR-V", name),
+            Compile {
+                ref name,
+                ref message,
+            } => write!(fmt, "Failed to compile shader {}: {}", name, message),
+        }
+    }
+}
+
+/// Errors produced while creating the shader modules and graphics pipelines of a render group.
+///
+/// Vertex attributes are given as their locations and formats.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PipelineError {
+    /// A shader module failed to be created.
+    ShaderModule {
+        /// The render group creating it.
+        group: String,
+        /// The stage of the shader.
+        stage: ShaderStageFlags,
+        /// The error of the device.
+        reason: String,
+    },
+    /// A vertex shader reads attributes the vertex buffers of its pipeline don't provide.
+    MissingVertexAttributes {
+        /// The render group creating the pipeline.
+        group: String,
+        /// Index of the pipeline in the group.
+        pipeline: usize,
+        /// Locations read by the shader without an attribute.
+        missing: Vec<u32>,
+        /// Locations read by the shader.
+        reflected: Vec<u32>,
+        /// Attributes of the vertex buffers.
+        declared: Vec<(u32, Format)>,
+    },
+    /// The device failed to create a graphics pipeline.
+    Creation {
+        /// The render group creating it.
+        group: String,
+        /// Index of the pipeline in the group.
+        pipeline: usize,
+        /// The error of the device.
+        reason: String,
+        /// Attributes of the vertex buffers.
+        declared: Vec<(u32, Format)>,
+    },
+}
+
+impl error::Error for PipelineError {}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::PipelineError::*;
+
+        match self {
+            ShaderModule {
+                group,
+                stage,
+                reason,
+            } => write!(
+                fmt,
+                "Failed to create the {:?} shader module of {}: {}",
+                stage, group, reason
+            ),
+            MissingVertexAttributes {
+                group,
+                pipeline,
+                missing,
+                reflected,
+                declared,
+            } => write!(
+                fmt,
+                "Pipeline {} of {} reads vertex attributes at locations {:?} it doesn't provide \
+                 (the shader reads {:?}, the vertex buffers provide {:?})",
+                pipeline, group, missing, reflected, declared
+            ),
+            Creation {
+                group,
+                pipeline,
+                reason,
+                declared,
+            } => write!(
+                fmt,
+                "Failed to create pipeline {} of {} with vertex attributes {:?}: {}",
+                pipeline, group, declared, reason
+            ),
+        }
+    }
+}
+
+/// Memory heap an allocation failed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryHeap {
+    /// Memory of the device.
+    Device,
+    /// Memory of the host, visible to the device.
+    Host,
+}
+
+/// Errors produced by the renderer while running.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenderError {
+    /// An allocation failed, as the heap is exhausted.
+    OutOfMemory {
+        /// Size in bytes of the allocation.
+        size: u64,
+        /// Heap the allocation was made in.
+        heap: MemoryHeap,
+    },
+    /// The device was lost, e.g. after a driver reset.
+    DeviceLost,
+    /// The device could not be created again after it was lost.
+    DeviceRecreation(String),
+    /// The device doesn't support the shader stages of a pipeline.
+    UnsupportedShaderStages(ShaderStageFlags),
+    /// The sprite number of a `SpriteRender` is out of the range of its sheet.
+    SpriteOutOfRange {
+        /// Id of the entity drawn, when known.
+        entity: Option<u32>,
+        /// Id of the handle of the sprite sheet.
+        sprite_sheet: u32,
+        /// The sprite number.
+        sprite_number: usize,
+        /// Number of sprites of the sheet.
+        sprite_count: usize,
+    },
+    /// A mesh doesn't provide the vertex attributes a render group draws with.
+    UnsupportedVertexLayout {
+        /// Id of the handle of the mesh.
+        mesh: u32,
+        /// The render group drawing it.
+        group: &'static str,
+    },
+}
+
+impl RenderError {
+    /// The error with the id of the `entity` drawn, for the variants carrying one.
+    pub fn with_entity(mut self, entity: amethyst_core::ecs::Entity) -> Self {
+        if let RenderError::SpriteOutOfRange { entity: e, .. } = &mut self {
+            *e = Some(entity.id());
+        }
+        self
+    }
+
+    /// The `OutOfMemory` error of an allocation of `size` bytes if `error` is one, or `error`.
+    pub(crate) fn from_allocation(error: failure::Error, size: u64) -> failure::Error {
+        use rendy::hal::device::{AllocationError, OutOfMemory};
+
+        let out_of_memory = error.downcast_ref::<OutOfMemory>().cloned().or_else(|| {
+            match error.downcast_ref::<AllocationError>() {
+                Some(AllocationError::OutOfMemory(out_of_memory)) => Some(out_of_memory.clone()),
+                _ => None,
+            }
+        });
+        let heap = match out_of_memory {
+            Some(OutOfMemory::OutOfDeviceMemory) => MemoryHeap::Device,
+            Some(OutOfMemory::OutOfHostMemory) => MemoryHeap::Host,
+            None => return error,
+        };
+        RenderError::OutOfMemory { size, heap }.into()
+    }
+}
+
+impl error::Error for RenderError {}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::RenderError::*;
+
+        match *self {
+            OutOfMemory { size, heap } => write!(
+                fmt,
+                "Out of {} memory allocating {} bytes",
+                match heap {
+                    MemoryHeap::Device => "device",
+                    MemoryHeap::Host => "host",
+                },
+                size
+            ),
+            DeviceLost => write!(fmt, "The device was lost"),
+            DeviceRecreation(ref error) => write!(
+                fmt,
+                "Failed to create the device again after it was lost: {}",
+                error
+            ),
+            UnsupportedShaderStages(stages) => {
+                write!(
+                    fmt,
+                    "Shader stages {:?} are not supported by the device",
+                    stages
+                )
+            }
+            SpriteOutOfRange {
+                entity,
+                sprite_sheet,
+                sprite_number,
+                sprite_count,
+            } => {
+                write!(
+                    fmt,
+                    "Sprite {} is out of range for sprite sheet {} of {} sprites",
+                    sprite_number, sprite_sheet, sprite_count
+                )?;
+                match entity {
+                    Some(entity) => write!(fmt, ", drawn by entity {}", entity),
+                    None => Ok(()),
+                }
+            }
+            UnsupportedVertexLayout { mesh, group } => write!(
+                fmt,
+                "Mesh {} lacks the vertex attributes drawn by {}",
+                mesh, group
+            ),
+        }
+    }
+}
+
+/// Errors produced while saving or resolving the sprite sheets of `SpriteRenderSerde`s.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SpriteSheetPathError {
+    /// The sprite sheet of a `SpriteRender` has no path registered in `SpriteSheetPaths`.
+    Unregistered {
+        /// Id of the handle of the sprite sheet.
+        sprite_sheet: u32,
+    },
+    /// The sprite sheet failed to load.
+    Missing {
+        /// Asset path of the sprite sheet.
+        path: String,
+    },
+    /// The sprite number is out of the range of the sprite sheet.
+    SpriteOutOfRange {
+        /// Asset path of the sprite sheet.
+        path: String,
+        /// The sprite number.
+        sprite_number: usize,
+        /// Number of sprites of the sheet.
+        sprite_count: usize,
+    },
+}
+
+impl error::Error for SpriteSheetPathError {}
+
+impl fmt::Display for SpriteSheetPathError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::SpriteSheetPathError::*;
+
+        match *self {
+            Unregistered { sprite_sheet } => write!(
+                fmt,
+                "Sprite sheet {} has no registered asset path",
+                sprite_sheet
+            ),
+            Missing { ref path } => write!(fmt, "Sprite sheet {:?} failed to load", path),
+            SpriteOutOfRange {
+                ref path,
+                sprite_number,
+                sprite_count,
+            } => write!(
+                fmt,
+                "Sprite {} is out of range for sprite sheet {:?} of {} sprites",
+                sprite_number, path, sprite_count
+            ),
+        }
+    }
+}
+
+/// Errors produced while comparing rendered frames with golden reference images.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GoldenError {
+    /// The frame and the reference have different sizes.
+    SizeMismatch {
+        /// Width and height of the reference.
+        expected: (u32, u32),
+        /// Width and height of the frame.
+        actual: (u32, u32),
+    },
+    /// The reference image doesn't exist.
+    MissingReference {
+        /// Path of the reference.
+        path: String,
+        /// Path the actual frame was written to.
+        output: String,
+    },
+    /// Too many pixels of the frame differ from the reference.
+    Mismatch {
+        /// Name of the reference.
+        name: String,
+        /// Number of pixels differing.
+        different_pixels: u32,
+        /// Number of pixels compared.
+        pixel_count: u32,
+        /// Largest difference of a channel.
+        max_channel_difference: u8,
+        /// Directory the actual and diff images were written to.
+        output: String,
+    },
+    /// The frame couldn't be rendered or read back.
+    Render(String),
+    /// Reading or writing an image failed.
+    Io(String),
+}
+
+impl error::Error for GoldenError {}
+
+impl fmt::Display for GoldenError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::GoldenError::*;
+
+        match *self {
+            SizeMismatch { expected, actual } => write!(
+                fmt,
+                "Frame of {}x{} pixels doesn't match its reference of {}x{} pixels",
+                actual.0, actual.1, expected.0, expected.1
+            ),
+            MissingReference {
+                ref path,
+                ref output,
+            } => write!(
+                fmt,
+                "Missing golden image {:?}, the frame was written to {:?}, set {} to record it",
+                path,
+                output,
+                crate::golden::UPDATE_GOLDEN_VAR
+            ),
+            Mismatch {
+                ref name,
+                different_pixels,
+                pixel_count,
+                max_channel_difference,
+                ref output,
+            } => write!(
+                fmt,
+                "Frame {:?} differs from its golden image in {} of {} pixels, by up to {}, \
+                 the frame and diff were written to {:?}",
+                name, different_pixels, pixel_count, max_channel_difference, output
+            ),
+            Render(ref e) => write!(fmt, "Failed to render the frame: {}", e),
+            Io(ref e) => write!(fmt, "Failed to read or write an image: {}", e),
+        }
+    }
+}
+
+/// Errors produced while choosing the backend of the renderer at runtime.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BackendError {
+    /// No backend compiled in has this name.
+    Unknown(String),
+    /// None of the backends tried could be started.
+    Unavailable {
+        /// Names of the backends tried, with why they failed.
+        tried: Vec<(String, String)>,
+    },
+}
+
+impl error::Error for BackendError {}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use self::BackendError::*;
+
+        match *self {
+            Unknown(ref name) => write!(fmt, "No backend {:?} is compiled in", name),
+            Unavailable { ref tried } if tried.is_empty() => {
+                write!(fmt, "No backend to render with was compiled in")
+            }
+            Unavailable { ref tried } => {
+                write!(fmt, "No backend could be started:")?;
+                for (name, reason) in tried {
+                    write!(fmt, " {} ({})", name, reason)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Events written by the `RenderingSystem` around the rendering of every frame.
+//!
+//! Frames are numbered from the start of the application, across rebuilds of the graph. A frame
+//! is retired once the fences of all its submissions signaled, so the GPU resources it used can
+//! be destroyed or read back. The frames in flight when the graph is rebuilt are retired with
+//! it, as it waits for the device to be idle. Frames of a lost device are never retired.
+use crate::{
+    presentation::PresentationConfig,
+    rendy::{
+        command::{Family, Fence, Queue, Submission, Transfer},
+        factory::Factory,
+        frame::Frames,
+        graph::{GraphContext, Node, NodeBuffer, NodeDesc, NodeImage},
+        hal,
+    },
+    types::Backend,
+};
+use amethyst_core::{ecs::Resources, shrev::EventChannel};
+use std::collections::VecDeque;
+
+/// Event written to the `EventChannel<RenderEvent>` resource.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderEvent {
+    /// The rendering of a frame starts, before the graph runs.
+    FrameBegin {
+        /// Index of the frame.
+        frame_index: u64,
+    },
+    /// The graph was rebuilt.
+    GraphRebuilt,
+    /// The graph submitted the work of the frame begun last.
+    FrameSubmitted,
+    /// The GPU finished the work of a frame.
+    FrameRetired {
+        /// Index of the frame.
+        frame_index: u64,
+    },
+}
+
+/// Render graph node telling when frames are retired, added to every graph by the
+/// `RenderingSystem`. Reports the frames in flight of the graph to the `PresentationConfig`.
+#[derive(Debug)]
+pub(crate) struct FrameEventsDesc {
+    first_frame: u64,
+}
+
+impl FrameEventsDesc {
+    /// Node of a graph whose first frame is `first_frame`.
+    pub(crate) fn new(first_frame: u64) -> Self {
+        Self { first_frame }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct FrameEventsNode {
+    first_frame: u64,
+    in_flight: VecDeque<u64>,
+}
+
+impl FrameEventsNode {
+    fn retire(&self, aux: &Resources, frame: u64) {
+        aux.fetch_mut::<EventChannel<RenderEvent>>()
+            .single_write(RenderEvent::FrameRetired {
+                frame_index: self.first_frame + frame,
+            });
+    }
+}
+
+impl<B: Backend> NodeDesc<B, Resources> for FrameEventsDesc {
+    type Node = FrameEventsNode;
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        _factory: &mut Factory<B>,
+        _family: &mut Family<B>,
+        _queue: usize,
+        aux: &Resources,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Self::Node, failure::Error> {
+        assert!(buffers.is_empty());
+        assert!(images.is_empty());
+        if let Some(mut config) = aux.try_fetch_mut::<PresentationConfig>() {
+            config.set_active_frames_in_flight(ctx.frames_in_flight());
+        }
+        Ok(FrameEventsNode {
+            first_frame: self.first_frame,
+            in_flight: VecDeque::new(),
+        })
+    }
+}
+
+impl<B: Backend> Node<B, Resources> for FrameEventsNode {
+    type Capability = Transfer;
+
+    fn run<'a>(
+        &mut self,
+        _ctx: &GraphContext<B>,
+        _factory: &Factory<B>,
+        queue: &mut Queue<B>,
+        aux: &Resources,
+        frames: &Frames<B>,
+        waits: &[(&'a B::Semaphore, hal::pso::PipelineStage)],
+        signals: &[&'a B::Semaphore],
+        fence: Option<&mut Fence<B>>,
+    ) {
+        while let Some(&frame) = self.in_flight.front() {
+            if !frames.is_complete(frame) {
+                break;
+            }
+            self.in_flight.pop_front();
+            self.retire(aux, frame);
+        }
+        self.in_flight.push_back(frames.next().index());
+
+        unsafe {
+            queue.submit(
+                Some(
+                    Submission::new()
+                        .wait(waits.iter().cloned())
+                        .signal(signals.iter().cloned()),
+                ),
+                fence,
+            );
+        }
+    }
+
+    unsafe fn dispose(self, _factory: &mut Factory<B>, aux: &Resources) {
+        for &frame in &self.in_flight {
+            self.retire(aux, frame);
+        }
+    }
+}
@@ -0,0 +1,293 @@
+//! Exposure of the HDR colors, adapting to the luminance of the frames like the eye does.
+//!
+//! The `Exposure` resource holds an exposure value, in EV at ISO 100. Each frame, `adapt` moves
+//! it towards the exposure of the average log-luminance of the frame, at `speed_up` when the
+//! frame got brighter and `speed_down` when it got darker, and clamps it between `min_ev` and
+//! `max_ev`. Set `manual` to keep the value as set, like for cutscenes.
+//!
+//! The `RenderAutoExposure` plugin measures the luminance on the GPU: the compute node of
+//! `luminance_node` averages the log2 luminance of a grid of samples of the color image of the
+//! main target once its groups are drawn, and the result is read back into the `SceneLuminance`
+//! resource when its frame completed, a few frames later. The `AutoExposureSystem` adapts the
+//! `Exposure` to it every frame. `log_average_luminance` gives the average for linear colors read
+//! back on the CPU instead, to set on the `SceneLuminance` without the plugin.
+//!
+//! `Exposure::multiplier` is the factor the HDR colors are scaled by before tone mapping. The lit
+//! 3D passes have no separate tone mapping stage, their environment is pre-exposed instead: the
+//! ambient color and the intensities of the lights are scaled by the multiplier of the
+//! `Exposure` resource when it exists, so are the colors they write to the target. The luminance
+//! measured is corrected by the multiplier of its frame. The colors of the flat and 2D passes,
+//! and the emission of the materials, aren't pre-exposed, and don't change with the exposure.
+use crate::{
+    bundle::ColorImage,
+    compute::{ComputeBinding, ComputeNodeDesc},
+    deterministic::Deterministic,
+    rendy::{hal::pso::ShaderStageFlags, shader::SpirvShader},
+};
+use amethyst_core::{
+    ecs::{Read, Resources, System, Write},
+    Time,
+};
+use std::sync::Arc;
+
+/// Calibration constant of reflected light meters, in cd/m² for an exposure value of 0.
+const METER_CALIBRATION: f32 = 12.5;
+
+/// Smallest luminance averaged, as the log of black is undefined.
+const MIN_LUMINANCE: f32 = 1e-4;
+
+/// Most samples along each axis of the image averaged by the luminance shader.
+const MAX_SAMPLES: u32 = 256;
+
+lazy_static::lazy_static! {
+    static ref LUMINANCE_COMPUTE: SpirvShader = SpirvShader::new(
+        include_bytes!("../compiled/compute/luminance.comp.spv").to_vec(),
+        ShaderStageFlags::COMPUTE,
+        "main",
+    );
+}
+
+/// Resource with the exposure of the HDR colors, see the module documentation.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Exposure {
+    /// Keep `ev` as it's set, without adapting it.
+    pub manual: bool,
+    /// Exposure value at ISO 100, adapted every frame unless `manual` is set.
+    pub ev: f32,
+    /// Lowest exposure value adapted to, for the darkest scenes.
+    pub min_ev: f32,
+    /// Highest exposure value adapted to, for the brightest scenes.
+    pub max_ev: f32,
+    /// Rate per second at which the exposure adapts to brighter frames.
+    pub speed_up: f32,
+    /// Rate per second at which the exposure adapts to darker frames.
+    pub speed_down: f32,
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self {
+            manual: false,
+            ev: 0.0,
+            min_ev: -2.0,
+            max_ev: 16.0,
+            speed_up: 3.0,
+            speed_down: 1.0,
+        }
+    }
+}
+
+impl Exposure {
+    /// Exposure kept at `ev`, without adapting it.
+    pub fn manual(ev: f32) -> Self {
+        Self {
+            manual: true,
+            ev,
+            ..Self::default()
+        }
+    }
+
+    /// The exposure value of frames with an average log2 luminance of `log_luminance`, clamped
+    /// between `min_ev` and `max_ev`.
+    pub fn target_ev(&self, log_luminance: f32) -> f32 {
+        let ev = log_luminance + (100.0 / METER_CALIBRATION).log2();
+        ev.max(self.min_ev).min(self.max_ev)
+    }
+
+    /// Adapt the exposure over `delta_seconds` to a frame with an average log2 luminance of
+    /// `log_luminance`, unless it's `manual`.
+    pub fn adapt(&mut self, log_luminance: f32, delta_seconds: f32) {
+        if self.manual {
+            return;
+        }
+        let target = self.target_ev(log_luminance);
+        let speed = if target > self.ev {
+            self.speed_up
+        } else {
+            self.speed_down
+        };
+        self.ev += (target - self.ev) * (1.0 - (-delta_seconds * speed.max(0.0)).exp());
+    }
+
+    /// Factor the HDR colors are scaled by, mapping the luminance of the exposure to middle gray.
+    pub fn multiplier(&self) -> f32 {
+        1.0 / (1.2 * self.ev.exp2())
+    }
+}
+
+/// Multiplier the environment of the lit passes is pre-exposed with, the one of the `Exposure`
+/// resource, or 1 without it.
+pub fn pre_exposure(res: &Resources) -> f32 {
+    res.try_fetch::<Exposure>()
+        .map_or(1.0, |exposure| exposure.multiplier())
+}
+
+/// Resource with the average log2 luminance of the last frame measured, without its exposure.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SceneLuminance {
+    log_average: Option<f32>,
+}
+
+impl SceneLuminance {
+    /// Set the average log2 luminance of a frame measured without `RenderAutoExposure`, like the
+    /// one of `log_average_luminance`, minus the log2 of the multiplier it was rendered with.
+    pub fn set(&mut self, log_average: f32) {
+        self.log_average = Some(log_average);
+    }
+
+    /// Take the luminance measured since the last call.
+    pub fn take(&mut self) -> Option<f32> {
+        self.log_average.take()
+    }
+}
+
+/// Adapts the `Exposure` to the `SceneLuminance` measured since the last frame, unless the
+/// exposure is `manual`.
+#[derive(Debug, Default)]
+pub struct AutoExposureSystem;
+
+impl AutoExposureSystem {
+    /// Create a new auto exposure system.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for AutoExposureSystem {
+    type SystemData = (
+        Write<'a, Exposure>,
+        Write<'a, SceneLuminance>,
+        Read<'a, Time>,
+        Read<'a, Deterministic>,
+    );
+
+    fn run(&mut self, (mut exposure, mut luminance, time, deterministic): Self::SystemData) {
+        if let Some(log_luminance) = luminance.take() {
+            exposure.adapt(log_luminance, deterministic.delta_seconds(&time));
+        }
+    }
+}
+
+/// Push constants of the luminance shader for an image of `width` by `height` pixels: its size
+/// and the distance in pixels between its samples.
+fn sampling(width: u32, height: u32) -> [u32; 3] {
+    let step = ((width.max(height) + MAX_SAMPLES - 1) / MAX_SAMPLES).max(1);
+    [width, height, step]
+}
+
+/// Compute node measuring the luminance of `color` into the `SceneLuminance` resource, see the
+/// module documentation. It must run after `color.pass`, the image must be sampled from, which
+/// every color format can be.
+pub fn luminance_node(color: &ColorImage) -> ComputeNodeDesc {
+    let constants = sampling(color.kind.extent().width, color.kind.extent().height);
+    ComputeNodeDesc::new(LUMINANCE_COMPUTE.clone())
+        .with_binding(ComputeBinding::Sampled(color.image))
+        .with_binding(ComputeBinding::Host(
+            4,
+            Arc::new(|res, target| {
+                target.copy_from_slice(&pre_exposure(res).log2().to_bits().to_ne_bytes());
+            }),
+        ))
+        .with_binding(ComputeBinding::Readback(
+            4,
+            Arc::new(|res, data| {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(&data[..4]);
+                if let Some(mut luminance) = res.try_fetch_mut::<SceneLuminance>() {
+                    luminance.set(f32::from_bits(u32::from_ne_bytes(bytes)));
+                }
+            }),
+        ))
+        .with_push_constants(3)
+        .with_dispatch(move |_, push_constants| {
+            push_constants.copy_from_slice(&constants);
+            [1, 1, 1]
+        })
+}
+
+/// Average log2 luminance of linear RGB colors, `None` without any.
+pub fn log_average_luminance(colors: impl IntoIterator<Item = [f32; 3]>) -> Option<f32> {
+    let (sum, count) = colors
+        .into_iter()
+        .fold((0.0, 0u32), |(sum, count), [r, g, b]| {
+            let luminance = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+            (sum + luminance.max(MIN_LUMINANCE).log2(), count + 1)
+        });
+    if count == 0 {
+        None
+    } else {
+        Some(sum / count as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst_core::ecs::{RunNow, World};
+
+    #[test]
+    fn exposure_adapts_at_its_rates_within_its_range() {
+        let gray = log_average_luminance(vec![[0.18; 3]; 4]).unwrap();
+        assert!((gray - 0.18f32.log2()).abs() < 1e-5);
+        assert_eq!(log_average_luminance(Vec::new()), None);
+
+        let mut brighter = Exposure::default();
+        let mut darker = Exposure {
+            ev: 4.0,
+            ..Exposure::default()
+        };
+        brighter.adapt(4.0f32.log2(), 0.1);
+        let target = darker.target_ev(0.0);
+        darker.adapt(0.0, 0.1);
+        // Brighter frames are adapted to faster, and the exposure doesn't overshoot.
+        assert!(brighter.ev > 0.0 && brighter.ev < brighter.target_ev(2.0));
+        assert!(darker.ev < 4.0 && darker.ev > target);
+        assert!(brighter.ev / 5.0 > (4.0 - darker.ev) / (4.0 - target));
+
+        let mut dark = Exposure::default();
+        for _ in 0..1000 {
+            dark.adapt(-20.0, 0.1);
+        }
+        assert!((dark.ev - dark.min_ev).abs() < 1e-3);
+
+        let mut cutscene = Exposure::manual(7.0);
+        cutscene.adapt(-20.0, 1.0);
+        assert_eq!(cutscene.ev, 7.0);
+        assert!(cutscene.multiplier() < Exposure::manual(6.0).multiplier());
+    }
+
+    #[test]
+    fn luminance_sampling_covers_the_image() {
+        assert_eq!(sampling(64, 32), [64, 32, 1]);
+        assert_eq!(sampling(1920, 1080), [1920, 1080, 8]);
+        for &(width, height) in &[(1, 1), (256, 256), (257, 100), (3840, 2160)] {
+            let [_, _, step] = sampling(width, height);
+            assert!(width.max(height) <= step * MAX_SAMPLES);
+        }
+    }
+
+    #[test]
+    fn measured_luminance_adapts_the_exposure() {
+        let mut world = World::new();
+        let mut system = AutoExposureSystem::new();
+        RunNow::setup(&mut system, &mut world.res);
+        world.add_resource(Deterministic::enabled());
+        assert_eq!(pre_exposure(&world.res), Exposure::default().multiplier());
+
+        // Nothing measured, nothing adapted.
+        system.run_now(&world.res);
+        assert_eq!(world.read_resource::<Exposure>().ev, 0.0);
+
+        world.write_resource::<SceneLuminance>().set(8.0);
+        system.run_now(&world.res);
+        assert!(world.read_resource::<Exposure>().ev > 0.0);
+        assert_eq!(world.write_resource::<SceneLuminance>().take(), None);
+
+        *world.write_resource::<Exposure>() = Exposure::manual(3.0);
+        world.write_resource::<SceneLuminance>().set(8.0);
+        system.run_now(&world.res);
+        assert_eq!(world.read_resource::<Exposure>().ev, 3.0);
+        assert_eq!(pre_exposure(&Resources::new()), 1.0);
+    }
+}
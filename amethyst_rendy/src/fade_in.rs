@@ -0,0 +1,210 @@
+//! Entities fading in once their assets are loaded, instead of popping in.
+//!
+//! The `AssetReadinessSystem` checks every frame which entities with a mesh or a sprite have all
+//! the assets they're drawn with: their `Mesh`, their `Material` and its textures, their
+//! `Texture`, or the `SpriteSheet` of their `SpriteRender` and its texture. The ready entities
+//! are kept in the `AssetReadiness` resource.
+//!
+//! An entity with a `FadeInOnLoad` is drawn transparent from the first frame all its assets are
+//! loaded, its alpha going from zero to the alpha of its `Tint` over `duration` seconds. The
+//! visibility sorting systems draw it with the transparent entities while it fades in, like an
+//! entity fading out near its `DrawDistance`. It's invisible while its assets are loading, even
+//! when fallback assets are drawn in place of the missing ones. An entity fades in only once,
+//! it doesn't fade in again when its assets are reloaded.
+use crate::{
+    deterministic::Deterministic,
+    mtl::{FullTextureSet, Material, StaticTextureSet},
+    sprite::{SpriteRender, SpriteSheet},
+    types::{Mesh, Texture},
+};
+use amethyst_assets::{AssetStorage, Handle};
+use amethyst_core::{
+    ecs::{Component, DenseVecStorage, Entities, Entity, Join, Read, ReadStorage, System, Write},
+    Time,
+};
+use fnv::FnvHashMap;
+use hibitset::{BitSet, BitSetOr};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Fades an entity in over `duration` seconds once its assets are loaded.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FadeInOnLoad {
+    /// Seconds from the first frame the assets are loaded to the entity being opaque.
+    pub duration: f32,
+}
+
+impl FadeInOnLoad {
+    /// Fade in over `duration` seconds.
+    pub fn new(duration: f32) -> Self {
+        Self { duration }
+    }
+
+    /// Alpha factor of the entity `elapsed` seconds after its assets were loaded.
+    pub fn fade(&self, elapsed: f32) -> f32 {
+        if self.duration > 0.0 {
+            (elapsed / self.duration).min(1.0).max(0.0)
+        } else {
+            1.0
+        }
+    }
+}
+
+impl Component for FadeInOnLoad {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Resource with the entities whose assets are loaded, and the alpha of those fading in.
+#[derive(Debug, Default)]
+pub struct AssetReadiness {
+    /// Entities with a mesh or a sprite whose assets are all loaded.
+    pub ready: BitSet,
+    /// Alpha factor of the entities with a `FadeInOnLoad` that aren't opaque yet, always below
+    /// one, and zero until their assets are loaded.
+    pub fade_in: FnvHashMap<Entity, f32>,
+}
+
+impl AssetReadiness {
+    /// Whether all the assets `entity` is drawn with are loaded.
+    pub fn is_ready(&self, entity: Entity) -> bool {
+        self.ready.contains(entity.id())
+    }
+
+    /// Alpha factor the tint of `entity` is multiplied with, one unless it is fading in.
+    pub fn fade(&self, entity: Entity) -> f32 {
+        self.fade_in.get(&entity).cloned().unwrap_or(1.0)
+    }
+}
+
+/// Fills the `AssetReadiness` resource, see the module documentation.
+///
+/// Added by the `RenderingBundle`. Add it before the visibility sorting systems otherwise.
+#[derive(Debug, Default)]
+pub struct AssetReadinessSystem {
+    /// Seconds since the assets of the entities fading in were loaded.
+    elapsed: FnvHashMap<Entity, f32>,
+}
+
+impl AssetReadinessSystem {
+    /// Create a new asset readiness system.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for AssetReadinessSystem {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, AssetReadiness>,
+        Read<'a, Time>,
+        Read<'a, Deterministic>,
+        Read<'a, AssetStorage<Mesh>>,
+        Read<'a, AssetStorage<Material>>,
+        Read<'a, AssetStorage<Texture>>,
+        Read<'a, AssetStorage<SpriteSheet>>,
+        ReadStorage<'a, Handle<Mesh>>,
+        ReadStorage<'a, Handle<Material>>,
+        ReadStorage<'a, Handle<Texture>>,
+        ReadStorage<'a, SpriteRender>,
+        ReadStorage<'a, FadeInOnLoad>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut readiness,
+            time,
+            deterministic,
+            mesh_storage,
+            material_storage,
+            texture_storage,
+            sheet_storage,
+            meshes,
+            materials,
+            textures,
+            sprites,
+            fade_ins,
+        ): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("asset_readiness_system");
+
+        let texture_ready = |texture: &Handle<Texture>| texture_storage.get(texture).is_some();
+        let assets_ready = |entity: Entity| {
+            meshes
+                .get(entity)
+                .map_or(true, |mesh| mesh_storage.get(mesh).is_some())
+                && materials.get(entity).map_or(true, |material| {
+                    material_storage.get(material).map_or(false, |material| {
+                        FullTextureSet::textures(material).all(texture_ready)
+                    })
+                })
+                && textures.get(entity).map_or(true, texture_ready)
+                && sprites.get(entity).map_or(true, |sprite| {
+                    sheet_storage
+                        .get(&sprite.sprite_sheet)
+                        .map_or(false, |sheet| texture_ready(&sheet.texture))
+                })
+        };
+        let readiness = &mut *readiness;
+        readiness.ready.clear();
+        for (entity, _) in (&*entities, BitSetOr(meshes.mask(), sprites.mask())).join() {
+            if assets_ready(entity) {
+                readiness.ready.add(entity.id());
+            }
+        }
+
+        let delta = deterministic.delta_seconds(&time);
+        let elapsed = &mut self.elapsed;
+        elapsed.retain(|entity, _| fade_ins.contains(*entity));
+        readiness.fade_in.clear();
+        for (entity, fade_in) in (&*entities, &fade_ins).join() {
+            let fade = if elapsed.contains_key(&entity) || assets_ready(entity) {
+                let elapsed = elapsed.entry(entity).or_insert(-delta);
+                *elapsed += delta;
+                fade_in.fade(*elapsed)
+            } else {
+                0.0
+            };
+            if fade < 1.0 {
+                readiness.fade_in.insert(entity, fade);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst_core::ecs::{Builder, RunNow, World};
+    use std::time::Duration;
+
+    #[test]
+    fn entities_fade_in_from_their_first_ready_frame() {
+        let fade_in = FadeInOnLoad::new(0.5);
+        assert_eq!(fade_in.fade(0.0), 0.0);
+        assert_eq!(fade_in.fade(0.25), 0.5);
+        assert_eq!(fade_in.fade(2.0), 1.0);
+        assert_eq!(FadeInOnLoad::new(0.0).fade(0.0), 1.0);
+
+        let mut world = World::new();
+        let mut system = AssetReadinessSystem::new();
+        RunNow::setup(&mut system, &mut world.res);
+        world
+            .write_resource::<Time>()
+            .set_delta_time(Duration::from_millis(250));
+
+        // Entities without assets are ready from the first frame.
+        let entity = world.create_entity().with(fade_in).build();
+        let fades: Vec<f32> = (0..4)
+            .map(|_| {
+                system.run_now(&world.res);
+                world.read_resource::<AssetReadiness>().fade(entity)
+            })
+            .collect();
+        assert_eq!(fades, vec![0.0, 0.5, 1.0, 1.0]);
+        assert!(world.read_resource::<AssetReadiness>().fade_in.is_empty());
+    }
+}
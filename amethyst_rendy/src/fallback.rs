@@ -0,0 +1,348 @@
+//! Fallback assets drawn in place of textures and materials that failed to load.
+//!
+//! The `RenderingSystem` creates a magenta and black checkerboard texture during setup, and a
+//! fallback `Material` made of the `MaterialDefaults` with the checkerboard as albedo. The texture
+//! and material submodules draw them in place of an asset that failed to load, or that stayed
+//! unloaded for `FallbackAssets::frames` frames, instead of skipping the objects using it. The
+//! real asset replaces its fallback once it's loaded, like after a hot reload.
+//!
+//! Every substitution is logged once, with the name of the asset when its load failed and the
+//! entities using it. Clear `enabled` to skip the objects instead, like for tests that must fail
+//! on missing assets.
+use crate::{
+    mtl::{FullTextureSet, Material, StaticTextureSet},
+    rendy::{
+        hal::{
+            format::Format,
+            image::{Filter, Kind, SamplerInfo, ViewKind, WrapMode},
+        },
+        texture::TextureBuilder,
+    },
+    sprite::{SpriteRender, SpriteSheet},
+    types::{Texture, TextureData},
+};
+use amethyst_assets::{AssetStorage, Handle};
+use amethyst_core::ecs::{Entities, Join, Read, ReadStorage, Resources, SystemData};
+use fnv::FnvHashMap;
+
+/// Frames an asset may stay unloaded before its fallback is drawn, by default.
+pub const DEFAULT_FALLBACK_FRAMES: u32 = 120;
+
+/// Side in texels of the checkerboard texture.
+const CHECKERBOARD_SIZE: u32 = 8;
+/// Side in texels of its squares.
+const CHECKERBOARD_SQUARE: u32 = 4;
+
+const MAGENTA: [u8; 4] = [255, 0, 255, 255];
+const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+/// The asset types having a fallback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FallbackKind {
+    Texture,
+    Material,
+}
+
+impl FallbackKind {
+    fn name(self) -> &'static str {
+        match self {
+            FallbackKind::Texture => "texture",
+            FallbackKind::Material => "material",
+        }
+    }
+}
+
+/// An asset drawn with its fallback, waiting to be logged.
+#[derive(Debug, Clone)]
+struct Substitution {
+    kind: FallbackKind,
+    id: u32,
+    failed: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Missing {
+    since: u64,
+    seen: u64,
+    substituted: bool,
+}
+
+/// The unloaded assets of a type, by handle id.
+#[derive(Debug, Clone, Default)]
+struct MissingAssets {
+    missing: FnvHashMap<u32, Missing>,
+}
+
+impl MissingAssets {
+    /// Track the asset `id`, unloaded at `frame`. Gives `None` while its fallback isn't due,
+    /// `Some(true)` the first time it is: once its load failed, or after `frames` frames.
+    fn due(&mut self, id: u32, failed: bool, frame: u64, frames: u32) -> Option<bool> {
+        let missing = self.missing.entry(id).or_insert(Missing {
+            since: frame,
+            seen: frame,
+            substituted: false,
+        });
+        missing.seen = frame;
+        if !failed && frame - missing.since < u64::from(frames) {
+            return None;
+        }
+        let first = !missing.substituted;
+        missing.substituted = true;
+        Some(first)
+    }
+
+    /// Forget the assets not tracked since `frame`, which were loaded or aren't drawn anymore.
+    fn retain_seen(&mut self, frame: u64) {
+        self.missing.retain(|_, missing| missing.seen >= frame);
+    }
+}
+
+/// Resource with the fallback assets, and the assets drawn with them.
+///
+/// Insert it before the `RenderingSystem` is set up to change the defaults, as the fallback
+/// assets are created during setup.
+#[derive(Debug, Clone)]
+pub struct FallbackAssets {
+    /// Draw the fallback assets in place of missing ones, instead of skipping the objects using
+    /// them.
+    pub enabled: bool,
+    /// Frames an asset may stay unloaded before its fallback is drawn. Assets that failed to
+    /// load are substituted right away.
+    pub frames: u32,
+    texture: Option<Handle<Texture>>,
+    material: Option<Handle<Material>>,
+    frame: u64,
+    textures: MissingAssets,
+    materials: MissingAssets,
+    substitutions: Vec<Substitution>,
+}
+
+impl Default for FallbackAssets {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            frames: DEFAULT_FALLBACK_FRAMES,
+            texture: None,
+            material: None,
+            frame: 0,
+            textures: MissingAssets::default(),
+            materials: MissingAssets::default(),
+            substitutions: Vec::new(),
+        }
+    }
+}
+
+impl FallbackAssets {
+    /// Fallback assets that are never drawn, skipping the objects using missing assets.
+    pub fn disabled() -> Self {
+        Self {
+            enabled: false,
+            ..Self::default()
+        }
+    }
+
+    /// Handle of the checkerboard texture.
+    pub fn texture(&self) -> Option<&Handle<Texture>> {
+        self.texture.as_ref()
+    }
+
+    /// Handle of the fallback material.
+    pub fn material(&self) -> Option<&Handle<Material>> {
+        self.material.as_ref()
+    }
+
+    /// The texture to draw in place of the unloaded `handle`, if its fallback is due. `failed`
+    /// is the name of the asset, if its load failed.
+    pub fn texture_for(
+        &mut self,
+        handle: &Handle<Texture>,
+        failed: Option<&str>,
+    ) -> Option<&Handle<Texture>> {
+        if self.substitute(FallbackKind::Texture, handle.id(), failed) {
+            self.texture.as_ref()
+        } else {
+            None
+        }
+    }
+
+    /// The material to draw in place of the unloaded `handle`, if its fallback is due. `failed`
+    /// is the name of the asset, if its load failed.
+    pub fn material_for(
+        &mut self,
+        handle: &Handle<Material>,
+        failed: Option<&str>,
+    ) -> Option<&Handle<Material>> {
+        if self.substitute(FallbackKind::Material, handle.id(), failed) {
+            self.material.as_ref()
+        } else {
+            None
+        }
+    }
+
+    fn substitute(&mut self, kind: FallbackKind, id: u32, failed: Option<&str>) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        let missing = match kind {
+            FallbackKind::Texture => &mut self.textures,
+            FallbackKind::Material => &mut self.materials,
+        };
+        match missing.due(id, failed.is_some(), self.frame, self.frames) {
+            Some(first) => {
+                if first {
+                    self.substitutions.push(Substitution {
+                        kind,
+                        id,
+                        failed: failed.map(String::from),
+                    });
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub(crate) fn set_assets(&mut self, texture: Handle<Texture>, material: Handle<Material>) {
+        self.texture = Some(texture);
+        self.material = Some(material);
+    }
+
+    /// Forget the assets not drawn this frame, and start the next one.
+    pub(crate) fn next_frame(&mut self) {
+        self.textures.retain_seen(self.frame);
+        self.materials.retain_seen(self.frame);
+        self.frame += 1;
+    }
+}
+
+/// The texels of the checkerboard texture, as `Rgba8Srgb`.
+fn checkerboard_texels() -> Vec<u8> {
+    let mut data = Vec::with_capacity((CHECKERBOARD_SIZE * CHECKERBOARD_SIZE * 4) as usize);
+    for y in 0..CHECKERBOARD_SIZE {
+        for x in 0..CHECKERBOARD_SIZE {
+            let square = x / CHECKERBOARD_SQUARE + y / CHECKERBOARD_SQUARE;
+            data.extend_from_slice(if square % 2 == 0 { &MAGENTA } else { &BLACK });
+        }
+    }
+    data
+}
+
+/// Data of the magenta and black checkerboard texture, repeated over texture coordinates.
+pub fn checkerboard() -> TextureData {
+    let data = checkerboard_texels();
+    let byte_size = data.len() as u64;
+    let builder = TextureBuilder::new()
+        .with_kind(Kind::D2(CHECKERBOARD_SIZE, CHECKERBOARD_SIZE, 1, 1))
+        .with_view_kind(ViewKind::D2)
+        .with_data_width(CHECKERBOARD_SIZE)
+        .with_data_height(CHECKERBOARD_SIZE)
+        .with_raw_data(data, Format::Rgba8Srgb);
+    TextureData::from(builder)
+        .with_sampler_info(SamplerInfo::new(Filter::Nearest, WrapMode::Tile))
+        .with_byte_size(byte_size)
+}
+
+/// Log the assets substituted since the last call, with the entities using them.
+pub(crate) fn log_substitutions(res: &Resources) {
+    let (substitutions, frames) = match res.try_fetch_mut::<FallbackAssets>() {
+        Some(mut fallback) if !fallback.substitutions.is_empty() => (
+            std::mem::replace(&mut fallback.substitutions, Vec::new()),
+            fallback.frames,
+        ),
+        _ => return,
+    };
+    let (entities, mat_storage, sheet_storage, materials, textures, sprites) = <(
+        Entities<'_>,
+        Read<'_, AssetStorage<Material>>,
+        Read<'_, AssetStorage<SpriteSheet>>,
+        ReadStorage<'_, Handle<Material>>,
+        ReadStorage<'_, Handle<Texture>>,
+        ReadStorage<'_, SpriteRender>,
+    )>::fetch(res);
+
+    for Substitution { kind, id, failed } in substitutions {
+        let mut users: Vec<u32> = match kind {
+            FallbackKind::Material => (&entities, &materials)
+                .join()
+                .filter(|(_, mat)| mat.id() == id)
+                .map(|(e, _)| e.id())
+                .collect(),
+            FallbackKind::Texture => {
+                let uses_texture = |mat: &Handle<Material>| {
+                    mat_storage.get(mat).map_or(false, |mat| {
+                        FullTextureSet::textures(mat).any(|t| t.id() == id)
+                    })
+                };
+                let textured = (&entities, &textures)
+                    .join()
+                    .filter(|(_, tex)| tex.id() == id)
+                    .map(|(e, _)| e.id());
+                let with_material = (&entities, &materials)
+                    .join()
+                    .filter(|(_, mat)| uses_texture(mat))
+                    .map(|(e, _)| e.id());
+                let sprited = (&entities, &sprites)
+                    .join()
+                    .filter(|(_, sprite)| {
+                        sheet_storage
+                            .get(&sprite.sprite_sheet)
+                            .map_or(false, |sheet| sheet.texture.id() == id)
+                    })
+                    .map(|(e, _)| e.id());
+                textured.chain(with_material).chain(sprited).collect()
+            }
+        };
+        users.sort();
+        users.dedup();
+
+        let reason = match &failed {
+            Some(name) => format!("failed to load from {:?}", name),
+            None => format!("still unloaded after {} frames", frames),
+        };
+        log::warn!(
+            "Drawing the fallback {} in place of {} {}, which {}, for entities {:?}",
+            kind.name(),
+            kind.name(),
+            id,
+            reason,
+            users
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback_is_due_on_failure_or_timeout() {
+        let mut missing = MissingAssets::default();
+        assert_eq!(missing.due(1, true, 0, 10), Some(true));
+        assert_eq!(missing.due(1, true, 1, 10), Some(false));
+
+        assert_eq!(missing.due(2, false, 0, 10), None);
+        assert_eq!(missing.due(2, false, 9, 10), None);
+        assert_eq!(missing.due(2, false, 10, 10), Some(true));
+
+        // Assets not drawn in a frame wait again.
+        missing.retain_seen(11);
+        assert_eq!(missing.due(2, false, 11, 10), None);
+    }
+
+    #[test]
+    fn checkerboard_alternates_squares() {
+        let texels = checkerboard_texels();
+        let texel = |x: u32, y: u32| {
+            let i = ((y * CHECKERBOARD_SIZE + x) * 4) as usize;
+            [texels[i], texels[i + 1], texels[i + 2], texels[i + 3]]
+        };
+        assert_eq!(
+            texels.len(),
+            (CHECKERBOARD_SIZE * CHECKERBOARD_SIZE * 4) as usize
+        );
+        assert_eq!(texel(0, 0), MAGENTA);
+        assert_eq!(texel(CHECKERBOARD_SQUARE, 0), BLACK);
+        assert_eq!(texel(0, CHECKERBOARD_SQUARE), BLACK);
+        assert_eq!(texel(CHECKERBOARD_SQUARE, CHECKERBOARD_SQUARE), MAGENTA);
+    }
+}
@@ -0,0 +1,515 @@
+//! Animated textures played back from the frames of a flipbook, such as screens or fire.
+use crate::{
+    deterministic::Deterministic,
+    error::TextureError,
+    formats::dds::layered_texture_data,
+    mtl::{Material, TextureOffset},
+    types::{Texture, TextureData},
+};
+use amethyst_assets::{
+    Asset, AssetStorage, Handle, HotReloadStrategy, Loader, ProcessingState, ThreadPool,
+};
+use amethyst_core::{
+    ecs::prelude::{
+        Component, DenseVecStorage, Join, Read, ReadExpect, ReadStorage, System, Write,
+        WriteStorage,
+    },
+    timing::Time,
+};
+use rendy::hal::{format::Format, image::SamplerInfo};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// How the frames of a flipbook are stored in its texture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FlipbookLayout {
+    /// The frames are stacked vertically in a 2D texture, with the first frame at the top.
+    /// `AnimatedTextureSystem` selects the frame by setting the UV window of the material, so
+    /// this layout works with all passes.
+    Atlas,
+    /// Every frame is a layer of a 2D array texture. The built-in passes can't sample array
+    /// textures, so this layout is meant for custom passes reading `AnimatedTexture::frame`.
+    Array,
+}
+
+impl Default for FlipbookLayout {
+    fn default() -> Self {
+        FlipbookLayout::Atlas
+    }
+}
+
+/// RGBA pixels and display durations of the frames of an animated texture, as loaded by
+/// `GifFormat` and `FrameSequenceFormat`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlipbookData {
+    width: u32,
+    height: u32,
+    layout: FlipbookLayout,
+    sampler_info: SamplerInfo,
+    pixels: Vec<u8>,
+    durations: Vec<f32>,
+}
+
+impl FlipbookData {
+    /// Create a flipbook without frames, of `width` by `height` pixels per frame.
+    pub fn new(width: u32, height: u32, layout: FlipbookLayout, sampler_info: SamplerInfo) -> Self {
+        FlipbookData {
+            width,
+            height,
+            layout,
+            sampler_info,
+            pixels: Vec::new(),
+            durations: Vec::new(),
+        }
+    }
+
+    /// Append a frame shown for `duration` seconds. `pixels` are sRGB `Rgba8` pixels with the
+    /// top row first, and must fill a whole frame.
+    pub fn push_frame(&mut self, pixels: &[u8], duration: f32) -> Result<(), TextureError> {
+        let expected = self.width as usize * self.height as usize * 4;
+        if pixels.len() != expected {
+            return Err(TextureError::PixelDataSize {
+                expected,
+                found: pixels.len(),
+            });
+        }
+        if duration.is_nan() || duration <= 0.0 {
+            return Err(TextureError::InvalidFlipbook(format!(
+                "frame {} has a duration of {} seconds, durations must be positive",
+                self.durations.len(),
+                duration
+            )));
+        }
+        self.pixels.extend_from_slice(pixels);
+        self.durations.push(duration);
+        Ok(())
+    }
+
+    /// Width of a frame in pixels.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height of a frame in pixels.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Number of frames.
+    pub fn frame_count(&self) -> usize {
+        self.durations.len()
+    }
+
+    fn texture_data(self) -> Result<(TextureData, FlipbookLayout, Vec<f32>), TextureError> {
+        let frames = self.durations.len() as u32;
+        if self.width == 0 || self.height == 0 || frames == 0 {
+            return Err(TextureError::EmptyTexture);
+        }
+        let (size, layers) = match self.layout {
+            FlipbookLayout::Atlas => ((self.width, self.height * frames), 1),
+            FlipbookLayout::Array => ((self.width, self.height), frames),
+        };
+        let data = layered_texture_data(
+            size,
+            layers,
+            false,
+            Format::Rgba8Srgb,
+            self.pixels,
+            self.sampler_info,
+        );
+        Ok((data, self.layout, self.durations))
+    }
+}
+
+/// Texture holding the frames of an animation, and how long each frame is shown.
+#[derive(Debug, Clone)]
+pub struct Flipbook {
+    texture: Handle<Texture>,
+    layout: FlipbookLayout,
+    durations: Arc<[f32]>,
+}
+
+impl Asset for Flipbook {
+    const NAME: &'static str = "renderer::Flipbook";
+    type Data = FlipbookData;
+    type HandleStorage = DenseVecStorage<Handle<Self>>;
+}
+
+impl Flipbook {
+    /// Texture of the frames.
+    pub fn texture(&self) -> &Handle<Texture> {
+        &self.texture
+    }
+
+    /// How the frames are stored in the texture.
+    pub fn layout(&self) -> FlipbookLayout {
+        self.layout
+    }
+
+    /// Number of frames.
+    pub fn frame_count(&self) -> usize {
+        self.durations.len()
+    }
+
+    /// Display duration of every frame, in seconds.
+    pub fn durations(&self) -> &[f32] {
+        &self.durations
+    }
+
+    /// UV window of `frame` in the texture. For array textures this is the whole layer.
+    pub fn uv_offset(&self, frame: usize) -> TextureOffset {
+        atlas_offset(self.layout, frame, self.durations.len())
+    }
+}
+
+fn atlas_offset(layout: FlipbookLayout, frame: usize, frames: usize) -> TextureOffset {
+    match layout {
+        FlipbookLayout::Atlas if frames > 0 => {
+            let frame = frame.min(frames - 1);
+            TextureOffset {
+                u: (0.0, 1.0),
+                v: (
+                    frame as f32 / frames as f32,
+                    (frame + 1) as f32 / frames as f32,
+                ),
+            }
+        }
+        _ => TextureOffset::default(),
+    }
+}
+
+/// How an `AnimatedTexture` continues after its last frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PlaybackMode {
+    /// Stop on the last frame.
+    Once,
+    /// Start over from the first frame.
+    Loop,
+    /// Play backwards to the first frame, then forwards again.
+    PingPong,
+}
+
+impl Default for PlaybackMode {
+    fn default() -> Self {
+        PlaybackMode::Loop
+    }
+}
+
+/// Component playing a `Flipbook` on the material of its entity, see `AnimatedTextureSystem`.
+#[derive(Debug, Clone)]
+pub struct AnimatedTexture {
+    flipbook: Handle<Flipbook>,
+    /// What happens after the last frame.
+    pub mode: PlaybackMode,
+    /// Playback speed, `1.0` plays the frames with their own durations.
+    pub speed: f32,
+    playing: bool,
+    frame: usize,
+    elapsed: f32,
+    backwards: bool,
+    finished: bool,
+}
+
+impl Component for AnimatedTexture {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl AnimatedTexture {
+    /// Start playing `flipbook` in a loop from its first frame.
+    pub fn new(flipbook: Handle<Flipbook>) -> Self {
+        AnimatedTexture {
+            flipbook,
+            mode: PlaybackMode::default(),
+            speed: 1.0,
+            playing: true,
+            frame: 0,
+            elapsed: 0.0,
+            backwards: false,
+            finished: false,
+        }
+    }
+
+    /// Set what happens after the last frame.
+    pub fn with_mode(mut self, mode: PlaybackMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Set the playback speed.
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// The played flipbook.
+    pub fn flipbook(&self) -> &Handle<Flipbook> {
+        &self.flipbook
+    }
+
+    /// Index of the current frame, which is also the layer of array flipbooks.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Returns `true` unless the animation is paused, or a `PlaybackMode::Once` animation
+    /// reached its last frame.
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Resume playback from the current frame. Animations that finished are restarted.
+    pub fn play(&mut self) {
+        if self.finished {
+            self.set_frame(0);
+        }
+        self.playing = true;
+    }
+
+    /// Pause on the current frame.
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    /// Pause and go back to the first frame.
+    pub fn stop(&mut self) {
+        self.playing = false;
+        self.set_frame(0);
+    }
+
+    /// Show `frame` from its start.
+    pub fn set_frame(&mut self, frame: usize) {
+        self.frame = frame;
+        self.elapsed = 0.0;
+        self.backwards = false;
+        self.finished = false;
+    }
+
+    /// Advance playback by `delta` seconds of the given frame durations.
+    fn advance(&mut self, delta: f32, durations: &[f32]) {
+        let frames = durations.len();
+        if frames == 0 {
+            return;
+        }
+        if self.frame >= frames {
+            self.set_frame(frames - 1);
+        }
+        if !self.playing {
+            return;
+        }
+
+        self.elapsed += delta * self.speed.max(0.0);
+        if self.mode == PlaybackMode::Loop {
+            let total: f32 = durations.iter().sum();
+            self.elapsed %= total;
+        }
+        while self.elapsed >= durations[self.frame] {
+            self.elapsed -= durations[self.frame];
+            match self.mode {
+                PlaybackMode::Once if self.frame + 1 == frames => {
+                    self.playing = false;
+                    self.finished = true;
+                    self.elapsed = 0.0;
+                }
+                PlaybackMode::Loop => self.frame = (self.frame + 1) % frames,
+                PlaybackMode::PingPong if frames == 1 => {}
+                PlaybackMode::PingPong if self.backwards => {
+                    if self.frame == 0 {
+                        self.backwards = false;
+                        self.frame = 1;
+                    } else {
+                        self.frame -= 1;
+                    }
+                }
+                PlaybackMode::PingPong if self.frame + 1 == frames => {
+                    self.backwards = true;
+                    self.frame -= 1;
+                }
+                _ => self.frame += 1,
+            }
+        }
+    }
+}
+
+/// Loads `Flipbook` assets and plays the `AnimatedTexture` components.
+///
+/// For atlas flipbooks, the albedo texture and UV window of the entity's `Handle<Material>` are
+/// replaced whenever the frame changes. All entities sharing the material show the same frame,
+/// so give every independently animated entity its own material. Changing a material rebuilds
+/// its descriptor set, which is cheap at the frame rates of flipbooks.
+///
+/// Add this system before the rendering system, after systems controlling the playback.
+#[derive(Default, Debug)]
+pub struct AnimatedTextureSystem;
+
+impl AnimatedTextureSystem {
+    /// Create new animated texture system
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for AnimatedTextureSystem {
+    type SystemData = (
+        Read<'a, Time>,
+        Read<'a, Deterministic>,
+        ReadExpect<'a, Loader>,
+        ReadExpect<'a, Arc<ThreadPool>>,
+        Option<Read<'a, HotReloadStrategy>>,
+        Write<'a, AssetStorage<Flipbook>>,
+        Read<'a, AssetStorage<Texture>>,
+        Write<'a, AssetStorage<Material>>,
+        WriteStorage<'a, AnimatedTexture>,
+        ReadStorage<'a, Handle<Material>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            time,
+            deterministic,
+            loader,
+            pool,
+            strategy,
+            mut flipbooks,
+            textures,
+            mut materials,
+            mut animated,
+            material_handles,
+        ): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("animated_texture_system");
+
+        use std::ops::Deref;
+
+        flipbooks.process(
+            |data| {
+                let (data, layout, durations) = data.texture_data()?;
+                Ok(ProcessingState::Loaded(Flipbook {
+                    texture: loader.load_from_data(data, (), &textures),
+                    layout,
+                    durations: durations.into(),
+                }))
+            },
+            time.frame_number(),
+            &**pool,
+            strategy.as_ref().map(Deref::deref),
+        );
+
+        for (animation, handle) in (&mut animated, material_handles.maybe()).join() {
+            let flipbook = match flipbooks.get(&animation.flipbook) {
+                Some(flipbook) => flipbook,
+                None => continue,
+            };
+            animation.advance(deterministic.delta_seconds(&time), flipbook.durations());
+
+            if flipbook.layout != FlipbookLayout::Atlas {
+                continue;
+            }
+            let (handle, material) = match handle.and_then(|h| Some((h, materials.get(h)?))) {
+                Some(found) => found,
+                None => continue,
+            };
+            let uv_offset = flipbook.uv_offset(animation.frame);
+            if material.uv_offset != uv_offset || material.albedo != flipbook.texture {
+                let material = Material {
+                    albedo: flipbook.texture.clone(),
+                    uv_offset,
+                    ..material.clone()
+                };
+                materials.replace(handle, material);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::ThreadPoolBuilder;
+    use rendy::hal::image::{Filter, WrapMode};
+
+    fn animation(mode: PlaybackMode) -> AnimatedTexture {
+        let loader = Loader::new(".", Arc::new(ThreadPoolBuilder::new().build().unwrap()));
+        let storage = AssetStorage::<Flipbook>::default();
+        let handle = loader.load_from_data(
+            FlipbookData::new(
+                1,
+                1,
+                FlipbookLayout::Atlas,
+                SamplerInfo::new(Filter::Nearest, WrapMode::Clamp),
+            ),
+            (),
+            &storage,
+        );
+        AnimatedTexture::new(handle).with_mode(mode)
+    }
+
+    fn frames(animation: &mut AnimatedTexture, steps: usize) -> Vec<usize> {
+        (0..steps)
+            .map(|_| {
+                animation.advance(0.1, &[0.1, 0.1, 0.1]);
+                animation.frame()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn playback_modes() {
+        let mut looping = animation(PlaybackMode::Loop);
+        assert_eq!(frames(&mut looping, 5), vec![1, 2, 0, 1, 2]);
+
+        let mut ping_pong = animation(PlaybackMode::PingPong);
+        assert_eq!(frames(&mut ping_pong, 6), vec![1, 2, 1, 0, 1, 2]);
+
+        let mut once = animation(PlaybackMode::Once);
+        assert_eq!(frames(&mut once, 4), vec![1, 2, 2, 2]);
+        assert!(!once.is_playing());
+        once.play();
+        assert_eq!(frames(&mut once, 2), vec![1, 2]);
+    }
+
+    #[test]
+    fn pause_and_speed() {
+        let mut animation = animation(PlaybackMode::Loop).with_speed(0.5);
+        assert_eq!(frames(&mut animation, 4), vec![0, 1, 1, 2]);
+        animation.pause();
+        assert_eq!(frames(&mut animation, 2), vec![2, 2]);
+        animation.play();
+        animation.speed = 1.0;
+        // Long frames skip over several frames, wrapping in loops
+        animation.advance(0.75, &[0.1, 0.1, 0.1]);
+        assert_eq!(animation.frame(), 0);
+        animation.stop();
+        assert_eq!(animation.frame(), 0);
+        assert!(!animation.is_playing());
+    }
+
+    #[test]
+    fn flipbook_frames() {
+        let sampler = SamplerInfo::new(Filter::Nearest, WrapMode::Clamp);
+        let mut data = FlipbookData::new(2, 1, FlipbookLayout::Atlas, sampler);
+        assert_eq!(
+            data.push_frame(&[0; 4], 0.1),
+            Err(TextureError::PixelDataSize {
+                expected: 8,
+                found: 4
+            })
+        );
+        assert!(data.push_frame(&[0; 8], 0.0).is_err());
+        data.push_frame(&[0; 8], 0.1).unwrap();
+        data.push_frame(&[255; 8], 0.2).unwrap();
+        assert_eq!(data.frame_count(), 2);
+
+        let offset = atlas_offset(FlipbookLayout::Atlas, 1, 4);
+        assert_eq!(offset.u, (0.0, 1.0));
+        assert_eq!(offset.v, (0.25, 0.5));
+        assert_eq!(
+            atlas_offset(FlipbookLayout::Array, 1, 4),
+            TextureOffset::default()
+        );
+    }
+}
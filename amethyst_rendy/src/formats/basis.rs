@@ -0,0 +1,546 @@
+//! Transcoder of the BasisLZ supercompressed ETC1S data of KTX2 files to RGBA8 pixels.
+//!
+//! ETC1S images share the codebooks of endpoints and selectors and the Huffman tables of the
+//! supercompression global data of the file. Each image has a slice of color blocks, and a slice
+//! of alpha blocks if the texture has alpha, that index into the codebooks. Blocks are decoded
+//! to pixels, as most devices can't sample ETC1.
+use crate::{error::TextureError, formats::dds::read_u32};
+
+const GLOBAL_HEADER_SIZE: usize = 20;
+const IMAGE_DESC_SIZE: usize = 20;
+
+const MAX_CODE_SIZE: usize = 16;
+const MAX_SYMBOLS_LOG2: u32 = 14;
+const CODE_LENGTH_CODES: usize = 21;
+const SMALL_ZERO_RUN: u32 = 17;
+const BIG_ZERO_RUN: u32 = 18;
+const SMALL_REPEAT: u32 = 19;
+/// Order in which the sizes of the codes of code lengths are stored.
+const CODE_LENGTH_ORDER: [usize; CODE_LENGTH_CODES] = [
+    17, 18, 19, 20, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15, 16,
+];
+
+/// Color components of the previous endpoint up to which the first and second delta models
+/// are used.
+const COLOR5_MODEL0_PREV_MAX: u8 = 9;
+const COLOR5_MODEL1_PREV_MAX: u8 = 21;
+const ENDPOINT_PRED_REPEAT_LAST: u32 = 256;
+const ENDPOINT_PRED_MIN_REPEAT: u32 = 3;
+const ENDPOINT_PRED_COUNT_CHUNK_BITS: u32 = 4;
+const SELECTOR_RLE_MIN: u32 = 3;
+const SELECTOR_RLE_CODES: u32 = 64;
+const SELECTOR_RLE_COUNT_CHUNK_BITS: u32 = 7;
+
+/// Modifiers of the intensity tables, by selector.
+const INTENSITIES: [[i32; 4]; 8] = [
+    [-8, -2, 2, 8],
+    [-17, -5, 5, 17],
+    [-29, -9, 9, 29],
+    [-42, -13, 13, 42],
+    [-60, -18, 18, 60],
+    [-80, -24, 24, 80],
+    [-106, -33, 33, 106],
+    [-183, -47, 47, 183],
+];
+
+/// Slices of an image in the level data, as byte ranges.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ImageDesc {
+    rgb: (usize, usize),
+    alpha: (usize, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Endpoint {
+    color5: [u8; 3],
+    intensity: u8,
+}
+
+/// Codebooks and tables of the BasisLZ global data of a KTX2 file.
+#[derive(Debug)]
+pub(crate) struct BasisLz {
+    images: Vec<ImageDesc>,
+    endpoints: Vec<Endpoint>,
+    /// Selectors of the 4x4 pixels of each codebook entry, by row.
+    selectors: Vec<[[u8; 4]; 4]>,
+    endpoint_pred: Huffman,
+    endpoint_delta: Huffman,
+    selector: Huffman,
+    selector_rle: Huffman,
+    selector_history_size: usize,
+}
+
+impl BasisLz {
+    /// Decode the global data of a file with `image_count` images, one per level, layer and
+    /// face.
+    pub(crate) fn parse(global: &[u8], image_count: usize) -> Result<Self, TextureError> {
+        const TRUNCATED: TextureError = TextureError::InvalidKtx2("BasisLZ data is truncated");
+
+        if global.len() < GLOBAL_HEADER_SIZE {
+            return Err(TRUNCATED);
+        }
+        let endpoint_count = usize::from(u16::from_le_bytes([global[0], global[1]]));
+        let selector_count = usize::from(u16::from_le_bytes([global[2], global[3]]));
+        let lengths = [
+            read_u32(global, 4) as usize,
+            read_u32(global, 8) as usize,
+            read_u32(global, 12) as usize,
+        ];
+        let mut offset = GLOBAL_HEADER_SIZE;
+        let descs = global
+            .get(offset..offset + image_count * IMAGE_DESC_SIZE)
+            .ok_or(TRUNCATED)?;
+        let images = descs
+            .chunks_exact(IMAGE_DESC_SIZE)
+            .map(|desc| {
+                let word = |index: usize| read_u32(desc, index * 4) as usize;
+                ImageDesc {
+                    rgb: (word(1), word(1) + word(2)),
+                    alpha: (word(3), word(3) + word(4)),
+                }
+            })
+            .collect();
+        offset += descs.len();
+        let mut sections = Vec::with_capacity(lengths.len());
+        for &length in &lengths {
+            sections.push(global.get(offset..offset + length).ok_or(TRUNCATED)?);
+            offset += length;
+        }
+        if endpoint_count == 0 || selector_count == 0 {
+            return Err(TextureError::InvalidKtx2("BasisLZ codebook is empty"));
+        }
+
+        let endpoints = decode_endpoints(sections[0], endpoint_count)?;
+        let selectors = decode_selectors(sections[1], selector_count)?;
+        let mut tables = Bits::new(sections[2]);
+        Ok(BasisLz {
+            images,
+            endpoints,
+            selectors,
+            endpoint_pred: Huffman::read(&mut tables)?,
+            endpoint_delta: Huffman::read(&mut tables)?,
+            selector: Huffman::read(&mut tables)?,
+            selector_rle: Huffman::read(&mut tables)?,
+            selector_history_size: tables.read(13) as usize,
+        })
+    }
+
+    /// Decode image `index` of `width` by `height` pixels to RGBA8 pixels, from the data of its
+    /// level.
+    pub(crate) fn decode(
+        &self,
+        index: usize,
+        level: &[u8],
+        (width, height): (u32, u32),
+    ) -> Result<Vec<u8>, TextureError> {
+        let desc = self
+            .images
+            .get(index)
+            .ok_or(TextureError::InvalidKtx2("missing BasisLZ image"))?;
+        let slice = |(start, end): (usize, usize)| {
+            level.get(start..end).ok_or(TextureError::InvalidKtx2(
+                "BasisLZ slice is out of its level",
+            ))
+        };
+        let mut pixels = vec![std::u8::MAX; width as usize * height as usize * 4];
+        self.decode_slice(
+            slice(desc.rgb)?,
+            (width, height),
+            &mut pixels,
+            |pixel, color| pixel[..3].copy_from_slice(&color),
+        )?;
+        if desc.alpha.1 > desc.alpha.0 {
+            self.decode_slice(
+                slice(desc.alpha)?,
+                (width, height),
+                &mut pixels,
+                |pixel, color| pixel[3] = color[1],
+            )?;
+        }
+        Ok(pixels)
+    }
+
+    /// Decode the blocks of a slice, writing the color of each of the RGBA8 `pixels` with
+    /// `write`.
+    fn decode_slice<F>(
+        &self,
+        slice: &[u8],
+        (width, height): (u32, u32),
+        pixels: &mut [u8],
+        write: F,
+    ) -> Result<(), TextureError>
+    where
+        F: Fn(&mut [u8], [u8; 3]),
+    {
+        const INVALID: TextureError = TextureError::InvalidKtx2("invalid BasisLZ slice");
+
+        let blocks_x = ((width + 3) / 4) as usize;
+        let blocks_y = ((height + 3) / 4) as usize;
+        let mut bits = Bits::new(slice);
+        let mut history = SelectorHistory::new(self.selector_history_size);
+        let history_start = self.selectors.len() as u32;
+        let rle_symbol = history_start + self.selector_history_size as u32;
+
+        // Endpoints of the previous and current block rows, and the predictors of the odd rows
+        // read with the even ones.
+        let mut rows = [vec![0usize; blocks_x], vec![0usize; blocks_x]];
+        let mut odd_preds = vec![0u32; blocks_x];
+        let mut preds = 0;
+        let mut last_pred_symbol = 0;
+        let mut pred_repeats = 0;
+        let mut selector_repeats = 0;
+        let mut endpoint_index = 0;
+        for block_y in 0..blocks_y {
+            let (upper, current) = if block_y & 1 == 0 { (1, 0) } else { (0, 1) };
+            for block_x in 0..blocks_x {
+                // Each predictor symbol holds the predictors of a group of 2x2 blocks.
+                if block_x & 1 == 0 {
+                    if block_y & 1 == 0 {
+                        if pred_repeats > 0 {
+                            pred_repeats -= 1;
+                            preds = last_pred_symbol;
+                        } else {
+                            preds = self.endpoint_pred.decode(&mut bits)?;
+                            if preds == ENDPOINT_PRED_REPEAT_LAST {
+                                pred_repeats = bits.read_vlc(ENDPOINT_PRED_COUNT_CHUNK_BITS)
+                                    + ENDPOINT_PRED_MIN_REPEAT
+                                    - 1;
+                                preds = last_pred_symbol;
+                            } else {
+                                last_pred_symbol = preds;
+                            }
+                        }
+                        odd_preds[block_x] = preds >> 4;
+                    } else {
+                        preds = odd_preds[block_x];
+                    }
+                }
+                let pred = preds & 3;
+                preds >>= 2;
+
+                endpoint_index = match pred {
+                    0 if block_x > 0 => endpoint_index,
+                    1 if block_y > 0 => rows[upper][block_x],
+                    2 if block_x > 0 && block_y > 0 => rows[upper][block_x - 1],
+                    3 => {
+                        let index =
+                            self.endpoint_delta.decode(&mut bits)? as usize + endpoint_index;
+                        if index >= self.endpoints.len() {
+                            index - self.endpoints.len()
+                        } else {
+                            index
+                        }
+                    }
+                    _ => return Err(INVALID),
+                };
+                rows[current][block_x] = endpoint_index;
+
+                let symbol = if selector_repeats > 0 {
+                    selector_repeats -= 1;
+                    history_start
+                } else {
+                    let symbol = self.selector.decode(&mut bits)?;
+                    if symbol == rle_symbol {
+                        let run = self.selector_rle.decode(&mut bits)?;
+                        selector_repeats = if run == SELECTOR_RLE_CODES - 1 {
+                            bits.read_vlc(SELECTOR_RLE_COUNT_CHUNK_BITS) + SELECTOR_RLE_MIN
+                        } else {
+                            run + SELECTOR_RLE_MIN
+                        };
+                        if selector_repeats as usize > blocks_x * blocks_y {
+                            return Err(INVALID);
+                        }
+                        selector_repeats -= 1;
+                        history_start
+                    } else {
+                        symbol
+                    }
+                };
+                let selector_index = if symbol >= history_start {
+                    history.take((symbol - history_start) as usize)?
+                } else {
+                    history.add(symbol as usize);
+                    symbol as usize
+                };
+
+                let endpoint = self.endpoints.get(endpoint_index).ok_or(INVALID)?;
+                let selectors = self.selectors.get(selector_index).ok_or(INVALID)?;
+                for (y, row) in selectors.iter().enumerate() {
+                    for (x, &selector) in row.iter().enumerate() {
+                        let (px, py) = (block_x * 4 + x, block_y * 4 + y);
+                        if px < width as usize && py < height as usize {
+                            let start = (py * width as usize + px) * 4;
+                            write(&mut pixels[start..start + 4], endpoint.color(selector));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Endpoint {
+    /// Color of the pixels with `selector`.
+    fn color(&self, selector: u8) -> [u8; 3] {
+        let modifier = INTENSITIES[self.intensity as usize][selector as usize];
+        let mut color = [0; 3];
+        for (channel, &c5) in color.iter_mut().zip(&self.color5) {
+            let c8 = i32::from(c5 << 3 | c5 >> 2);
+            *channel = (c8 + modifier).max(0).min(255) as u8;
+        }
+        color
+    }
+}
+
+/// Decode the codebook of endpoints, delta coded from the previous one.
+fn decode_endpoints(data: &[u8], count: usize) -> Result<Vec<Endpoint>, TextureError> {
+    let mut bits = Bits::new(data);
+    let color_models = [
+        Huffman::read(&mut bits)?,
+        Huffman::read(&mut bits)?,
+        Huffman::read(&mut bits)?,
+    ];
+    let intensity_model = Huffman::read(&mut bits)?;
+    let grayscale = bits.read(1) == 1;
+    let mut previous = Endpoint {
+        color5: [16; 3],
+        intensity: 0,
+    };
+    let mut endpoints = Vec::with_capacity(count);
+    for _ in 0..count {
+        let intensity = intensity_model.decode(&mut bits)? + u32::from(previous.intensity);
+        let mut endpoint = Endpoint {
+            color5: previous.color5,
+            intensity: (intensity & 7) as u8,
+        };
+        let channels = if grayscale { 1 } else { 3 };
+        for c in 0..channels {
+            let model = match previous.color5[c] {
+                p if p <= COLOR5_MODEL0_PREV_MAX => &color_models[0],
+                p if p <= COLOR5_MODEL1_PREV_MAX => &color_models[1],
+                _ => &color_models[2],
+            };
+            let color5 = model.decode(&mut bits)? + u32::from(previous.color5[c]);
+            endpoint.color5[c] = (color5 & 31) as u8;
+        }
+        if grayscale {
+            endpoint.color5 = [endpoint.color5[0]; 3];
+        }
+        endpoints.push(endpoint);
+        previous = endpoint;
+    }
+    Ok(endpoints)
+}
+
+/// Decode the codebook of selectors, either raw or with each row XORed with the previous entry.
+fn decode_selectors(data: &[u8], count: usize) -> Result<Vec<[[u8; 4]; 4]>, TextureError> {
+    let mut bits = Bits::new(data);
+    if bits.read(1) == 1 || bits.read(1) == 1 {
+        return Err(TextureError::UnsupportedKtx2(
+            "BasisLZ global selector codebook".into(),
+        ));
+    }
+    let unpack = |row: u32| {
+        let mut selectors = [0; 4];
+        for (x, selector) in selectors.iter_mut().enumerate() {
+            *selector = (row >> (x * 2)) as u8 & 3;
+        }
+        selectors
+    };
+    let raw = bits.read(1) == 1;
+    let delta_model = if raw {
+        None
+    } else {
+        Some(Huffman::read(&mut bits)?)
+    };
+    let mut previous = [0u32; 4];
+    let mut selectors = Vec::with_capacity(count);
+    for index in 0..count {
+        let mut rows = [[0; 4]; 4];
+        for (y, row) in rows.iter_mut().enumerate() {
+            let bytes = match delta_model {
+                Some(ref model) if index > 0 => model.decode(&mut bits)? ^ previous[y],
+                _ => bits.read(8),
+            };
+            previous[y] = bytes & 0xFF;
+            *row = unpack(bytes);
+        }
+        selectors.push(rows);
+    }
+    Ok(selectors)
+}
+
+/// Reads a bit stream, least significant bit first. Reading past the end yields zeros.
+struct Bits<'a> {
+    bytes: &'a [u8],
+    buffer: u64,
+    len: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Bits {
+            bytes,
+            buffer: 0,
+            len: 0,
+        }
+    }
+
+    fn read(&mut self, count: u32) -> u32 {
+        while self.len < count {
+            let (&byte, rest) = self.bytes.split_first().unwrap_or((&0, &[]));
+            self.bytes = rest;
+            self.buffer |= u64::from(byte) << self.len;
+            self.len += 8;
+        }
+        let value = (self.buffer & ((1 << count) - 1)) as u32;
+        self.buffer >>= count;
+        self.len -= count;
+        value
+    }
+
+    /// Read a number stored in chunks of `chunk_bits`, each followed by a bit telling whether
+    /// another chunk follows.
+    fn read_vlc(&mut self, chunk_bits: u32) -> u32 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let chunk = self.read(chunk_bits + 1);
+            value |= (chunk & ((1 << chunk_bits) - 1)) << shift;
+            shift += chunk_bits;
+            if chunk >> chunk_bits == 0 || shift >= 32 {
+                return value;
+            }
+        }
+    }
+}
+
+/// Canonical Huffman code, with the bits of each code stored most significant first.
+#[derive(Debug)]
+struct Huffman {
+    /// Number of codes of each size.
+    counts: [u32; MAX_CODE_SIZE + 1],
+    /// Symbols ordered by code.
+    symbols: Vec<u32>,
+}
+
+impl Huffman {
+    /// Code of symbols with code `sizes`, 0 for unused symbols.
+    fn new(sizes: &[u8]) -> Result<Self, TextureError> {
+        let mut counts = [0; MAX_CODE_SIZE + 1];
+        for &size in sizes {
+            if size as usize > MAX_CODE_SIZE {
+                return Err(TextureError::InvalidKtx2("invalid BasisLZ Huffman table"));
+            }
+            counts[size as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut left = 1i64;
+        for &count in &counts[1..] {
+            left = (left << 1) - i64::from(count);
+            if left < 0 {
+                return Err(TextureError::InvalidKtx2("invalid BasisLZ Huffman table"));
+            }
+        }
+        let mut symbols = Vec::new();
+        for size in 1..=MAX_CODE_SIZE as u8 {
+            symbols.extend((0..sizes.len() as u32).filter(|&s| sizes[s as usize] == size));
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    /// Read a table stored as the code sizes of its symbols, themselves Huffman coded.
+    fn read(bits: &mut Bits<'_>) -> Result<Self, TextureError> {
+        const INVALID: TextureError = TextureError::InvalidKtx2("invalid BasisLZ Huffman table");
+
+        let symbol_count = bits.read(MAX_SYMBOLS_LOG2) as usize;
+        if symbol_count == 0 {
+            return Huffman::new(&[]);
+        }
+        let code_length_count = bits.read(5) as usize;
+        if code_length_count == 0 || code_length_count > CODE_LENGTH_CODES {
+            return Err(INVALID);
+        }
+        let mut code_length_sizes = [0; CODE_LENGTH_CODES];
+        for &code in &CODE_LENGTH_ORDER[..code_length_count] {
+            code_length_sizes[code] = bits.read(3) as u8;
+        }
+        let code_lengths = Huffman::new(&code_length_sizes)?;
+
+        let mut sizes = Vec::with_capacity(symbol_count);
+        while sizes.len() < symbol_count {
+            let code = code_lengths.decode(bits)?;
+            let (size, count) = match code {
+                0..=16 => (code as u8, 1),
+                SMALL_ZERO_RUN => (0, bits.read(3) + 3),
+                BIG_ZERO_RUN => (0, bits.read(7) + 11),
+                _ => {
+                    let previous = *sizes.last().filter(|&&size| size > 0).ok_or(INVALID)?;
+                    let count = if code == SMALL_REPEAT {
+                        bits.read(2) + 3
+                    } else {
+                        bits.read(7) + 7
+                    };
+                    (previous, count)
+                }
+            };
+            if sizes.len() + count as usize > symbol_count {
+                return Err(INVALID);
+            }
+            sizes.extend((0..count).map(|_| size));
+        }
+        Huffman::new(&sizes)
+    }
+
+    fn decode(&self, bits: &mut Bits<'_>) -> Result<u32, TextureError> {
+        let mut code = 0;
+        let mut first = 0;
+        let mut index = 0;
+        for &count in &self.counts[1..] {
+            code |= bits.read(1);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(TextureError::InvalidKtx2("invalid BasisLZ Huffman code"))
+    }
+}
+
+/// Recently used selectors, that blocks can reuse by their position in the history.
+struct SelectorHistory {
+    selectors: Vec<usize>,
+    next: usize,
+}
+
+impl SelectorHistory {
+    fn new(size: usize) -> Self {
+        SelectorHistory {
+            selectors: vec![0; size],
+            next: size / 2,
+        }
+    }
+
+    fn add(&mut self, selector: usize) {
+        if self.selectors.is_empty() {
+            return;
+        }
+        self.selectors[self.next] = selector;
+        self.next += 1;
+        if self.next == self.selectors.len() {
+            self.next = self.selectors.len() / 2;
+        }
+    }
+
+    /// The selector at `index`, which moves halfway to the front.
+    fn take(&mut self, index: usize) -> Result<usize, TextureError> {
+        let selector = *self.selectors.get(index).ok_or(TextureError::InvalidKtx2(
+            "invalid BasisLZ selector history",
+        ))?;
+        self.selectors.swap(index / 2, index);
+        Ok(selector)
+    }
+}
@@ -0,0 +1,460 @@
+//! Loading of images with one or two channels, or with 16 bits per channel.
+use crate::{
+    formats::{hdr::f32_to_f16, indexed::load_indexed},
+    types::{TextureData, TextureFallback},
+};
+use amethyst_error::Error;
+use rendy::{
+    hal::{
+        format::{Component, Format, Swizzle},
+        image::{Kind, ViewKind},
+    },
+    texture::{
+        image::{ImageTextureConfig, Repr},
+        TextureBuilder,
+    },
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+pub(crate) const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Channels of the textures loaded by `ImageFormat`.
+///
+/// Textures with less than four channels are sampled as grayscale: the luminance is returned
+/// for red, green and blue, and the second channel, if any, as alpha. 16 bit channels of PNG
+/// files keep their precision, as `R16Unorm`, `Rg16Unorm` or `Rgba16Unorm`, with sRGB colors
+/// converted to linear values. Devices that can't sample a format get the texture converted to
+/// `Rgba8` or `Rgba16Sfloat`, with a warning.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImageChannels {
+    /// Keep the channels of PNG files: grayscale images load as `R8` or `R16`, grayscale
+    /// images with alpha as `Rg8` or `Rg16` and color images as `Rgba8` or `Rgba16`. Other
+    /// files load as `Rgba8`.
+    Auto,
+    /// Luminance only, for masks and heightmaps.
+    R,
+    /// Luminance and alpha.
+    Rg,
+    /// Color and alpha.
+    Rgba,
+    /// The raw palette indices of indexed PNG files, see `ImageFormat::with_indexed`.
+    Indexed,
+}
+
+impl Default for ImageChannels {
+    fn default() -> Self {
+        ImageChannels::Auto
+    }
+}
+
+impl ImageChannels {
+    fn count(self) -> Option<usize> {
+        match self {
+            ImageChannels::Auto | ImageChannels::Indexed => None,
+            ImageChannels::R => Some(1),
+            ImageChannels::Rg => Some(2),
+            ImageChannels::Rgba => Some(4),
+        }
+    }
+}
+
+/// Decoded pixels, with 16 bit samples stored in native order.
+#[derive(Debug, Clone)]
+struct Pixels {
+    width: u32,
+    height: u32,
+    channels: usize,
+    wide: bool,
+    srgb: bool,
+    data: Vec<u8>,
+}
+
+/// Texture data of an image with the requested channels, or `None` if the image is best loaded
+/// as `Rgba8` by rendy.
+pub(crate) fn load_channels(
+    bytes: &[u8],
+    config: &ImageTextureConfig,
+    channels: ImageChannels,
+) -> Result<Option<TextureData>, Error> {
+    if channels == ImageChannels::Indexed {
+        return load_indexed(bytes, config).map(Some);
+    }
+    let pixels = match decode(bytes, config, channels)? {
+        Some(pixels) => pixels,
+        None => return Ok(None),
+    };
+    let format = pixels.format();
+    let fallback_format = pixels.fallback_format();
+
+    // The fallback decodes the file again, instead of keeping a copy of the pixels in memory
+    let bytes: Arc<[u8]> = bytes.into();
+    let fallback_config = config.clone();
+    let fallback = TextureFallback::new(fallback_format, move || {
+        match decode(&bytes, &fallback_config, channels)? {
+            Some(pixels) => Ok(pixels
+                .expand(&fallback_config)
+                .with_required_format(fallback_format)),
+            None => unreachable!("Decoding the same image gives the same channels"),
+        }
+    });
+
+    let data = pixels
+        .texture_data(config)
+        .with_required_format(format)
+        .with_fallback(fallback);
+    Ok(Some(if config.generate_mips {
+        data.with_generated_mips()
+    } else {
+        data
+    }))
+}
+
+/// Approximate size of an image loaded as `Rgba8`, read from the header of PNG and JPEG files.
+pub(crate) fn rgba8_byte_size(bytes: &[u8], config: &ImageTextureConfig) -> Option<u64> {
+    use image::{jpeg::JPEGDecoder, ImageDecoder};
+
+    let (width, height) = if bytes.starts_with(&PNG_SIGNATURE) && bytes.len() >= 24 {
+        let read = |offset: usize| {
+            u64::from(u32::from_be_bytes([
+                bytes[offset],
+                bytes[offset + 1],
+                bytes[offset + 2],
+                bytes[offset + 3],
+            ]))
+        };
+        (read(16), read(20))
+    } else if bytes.starts_with(&[0xFF, 0xD8]) {
+        JPEGDecoder::new(std::io::Cursor::new(bytes))
+            .ok()?
+            .dimensions()
+    } else {
+        return None;
+    };
+    let size = width * height * 4;
+    // The mip chain adds a third to the size of the image
+    Some(if config.generate_mips {
+        size + size / 3
+    } else {
+        size
+    })
+}
+
+fn decode(
+    bytes: &[u8],
+    config: &ImageTextureConfig,
+    channels: ImageChannels,
+) -> Result<Option<Pixels>, Error> {
+    use image::{png::PNGDecoder, ColorType, ImageDecoder};
+
+    let srgb = match config.repr {
+        Repr::Srgb => true,
+        _ => false,
+    };
+
+    let mut source_channels = None;
+    if bytes.starts_with(&PNG_SIGNATURE) {
+        let decoder = PNGDecoder::new(std::io::Cursor::new(bytes))?;
+        let (count, bits) = match decoder.colortype() {
+            ColorType::Gray(bits) => (1, bits),
+            ColorType::GrayA(bits) => (2, bits),
+            ColorType::RGB(bits) => (3, bits),
+            ColorType::RGBA(bits) => (4, bits),
+            _ => (4, 8),
+        };
+        if bits == 16 {
+            let (width, height) = decoder.dimensions();
+            let samples = decoder
+                .read_image()?
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect::<Vec<_>>();
+            let target = channels
+                .count()
+                .unwrap_or(if count == 3 { 4 } else { count });
+            let premultiply = config.premultiply_alpha;
+            return Ok(Some(Pixels {
+                width: width as u32,
+                height: height as u32,
+                channels: target,
+                wide: true,
+                srgb: false,
+                data: convert_wide(&samples, count, target, srgb, premultiply),
+            }));
+        }
+        source_channels = Some(count);
+    }
+
+    let target = match channels.count().or(source_channels) {
+        Some(target) if target < 3 => target,
+        _ => return Ok(None),
+    };
+    let image = image::load_from_memory(bytes)?;
+    let (width, height, mut data) = if target == 1 {
+        let image = image.to_luma();
+        let (width, height) = image.dimensions();
+        (width, height, image.into_raw())
+    } else {
+        let image = image.to_luma_alpha();
+        let (width, height) = image.dimensions();
+        (width, height, image.into_raw())
+    };
+    if target == 2 && config.premultiply_alpha {
+        for pixel in data.chunks_exact_mut(2) {
+            pixel[0] = (u16::from(pixel[0]) * u16::from(pixel[1]) / 255) as u8;
+        }
+    }
+    Ok(Some(Pixels {
+        width,
+        height,
+        channels: target,
+        wide: false,
+        srgb,
+        data,
+    }))
+}
+
+/// Convert 16 bit samples with `source` channels to `target` channels of linear values,
+/// stored in native order.
+fn convert_wide(
+    samples: &[u16],
+    source: usize,
+    target: usize,
+    srgb: bool,
+    premultiply: bool,
+) -> Vec<u8> {
+    let color = |sample: u16| {
+        let value = f32::from(sample) / 65535.0;
+        if srgb {
+            srgb_to_linear(value)
+        } else {
+            value
+        }
+    };
+
+    let mut data = Vec::with_capacity(samples.len() / source * target * 2);
+    for pixel in samples.chunks_exact(source) {
+        let alpha = match source {
+            2 => f32::from(pixel[1]) / 65535.0,
+            4 => f32::from(pixel[3]) / 65535.0,
+            _ => 1.0,
+        };
+        let factor = if premultiply { alpha } else { 1.0 };
+        let rgb = if source >= 3 {
+            [color(pixel[0]), color(pixel[1]), color(pixel[2])]
+        } else {
+            [color(pixel[0]); 3]
+        };
+        let luminance = 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+        let (out, len) = match target {
+            1 => ([luminance, 0.0, 0.0, 0.0], 1),
+            2 => ([luminance * factor, alpha, 0.0, 0.0], 2),
+            _ => (
+                [rgb[0] * factor, rgb[1] * factor, rgb[2] * factor, alpha],
+                4,
+            ),
+        };
+        for &value in &out[..len] {
+            let sample = (value.max(0.0).min(1.0) * 65535.0).round() as u16;
+            data.extend_from_slice(&sample.to_ne_bytes());
+        }
+    }
+    data
+}
+
+impl Pixels {
+    fn format(&self) -> Format {
+        match (self.channels, self.wide, self.srgb) {
+            (1, true, _) => Format::R16Unorm,
+            (2, true, _) => Format::Rg16Unorm,
+            (_, true, _) => Format::Rgba16Unorm,
+            (1, false, true) => Format::R8Srgb,
+            (1, false, false) => Format::R8Unorm,
+            (_, false, true) => Format::Rg8Srgb,
+            (_, false, false) => Format::Rg8Unorm,
+        }
+    }
+
+    /// Format of the pixels expanded to four channels, which every device can sample.
+    fn fallback_format(&self) -> Format {
+        if self.wide {
+            Format::Rgba16Sfloat
+        } else if self.srgb {
+            Format::Rgba8Srgb
+        } else {
+            Format::Rgba8Unorm
+        }
+    }
+
+    /// Texture data sampling the pixels as grayscale if they have less than four channels.
+    fn texture_data(self, config: &ImageTextureConfig) -> TextureData {
+        let swizzle = match self.channels {
+            1 => Swizzle(Component::R, Component::R, Component::R, Component::One),
+            2 => Swizzle(Component::R, Component::R, Component::R, Component::G),
+            _ => Swizzle::NO,
+        };
+        let format = self.format();
+        let byte_size = self.data.len() as u64;
+        let builder = builder(self.width, self.height)
+            .with_swizzle(swizzle)
+            .with_raw_data(self.data, format);
+        TextureData::from(builder)
+            .with_sampler_info(config.sampler_info.clone())
+            .with_byte_size(byte_size)
+    }
+
+    /// Texture data of the pixels expanded to four channels, with 16 bit samples stored as
+    /// half floats.
+    fn expand(self, config: &ImageTextureConfig) -> TextureData {
+        let size = if self.wide { 2 } else { 1 };
+        let sources = match self.channels {
+            1 => [Some(0), Some(0), Some(0), None],
+            2 => [Some(0), Some(0), Some(0), Some(1)],
+            _ => [Some(0), Some(1), Some(2), Some(3)],
+        };
+        let mut data = Vec::with_capacity(self.data.len() / self.channels * 4);
+        for pixel in self.data.chunks_exact(self.channels * size) {
+            for source in &sources {
+                match (self.wide, *source) {
+                    (true, Some(i)) => {
+                        let sample = u16::from_ne_bytes([pixel[i * 2], pixel[i * 2 + 1]]);
+                        let half = f32_to_f16(f32::from(sample) / 65535.0);
+                        data.extend_from_slice(&half.to_ne_bytes());
+                    }
+                    (true, None) => data.extend_from_slice(&f32_to_f16(1.0).to_ne_bytes()),
+                    (false, Some(i)) => data.push(pixel[i]),
+                    (false, None) => data.push(std::u8::MAX),
+                }
+            }
+        }
+        let format = self.fallback_format();
+        let byte_size = data.len() as u64;
+        let builder = builder(self.width, self.height).with_raw_data(data, format);
+        TextureData::from(builder)
+            .with_sampler_info(config.sampler_info.clone())
+            .with_byte_size(byte_size)
+    }
+}
+
+fn builder(width: u32, height: u32) -> TextureBuilder<'static> {
+    TextureBuilder::new()
+        .with_kind(Kind::D2(width, height, 1, 1))
+        .with_view_kind(ViewKind::D2)
+        .with_data_width(width)
+        .with_data_height(height)
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formats::texture::ImageFormat;
+    use image::{png::PNGEncoder, ColorType};
+
+    fn png(data: &[u8], width: u32, height: u32, color: ColorType) -> Vec<u8> {
+        let mut png = Vec::new();
+        PNGEncoder::new(&mut png)
+            .encode(data, width, height, color)
+            .unwrap();
+        png
+    }
+
+    fn linear() -> ImageTextureConfig {
+        ImageFormat::default().with_srgb(false).0
+    }
+
+    #[test]
+    fn heightmap_keeps_precision() {
+        let heights: Vec<u16> = vec![0, 0x1234, 0xABCD, 0xFFFF];
+        let bytes: Vec<u8> = heights
+            .iter()
+            .flat_map(|h| h.to_be_bytes().to_vec())
+            .collect();
+        let png = png(&bytes, 2, 2, ColorType::Gray(16));
+
+        let pixels = decode(&png, &linear(), ImageChannels::Auto)
+            .unwrap()
+            .unwrap();
+        assert_eq!(pixels.format(), Format::R16Unorm);
+        let decoded: Vec<u16> = pixels
+            .data
+            .chunks_exact(2)
+            .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+            .collect();
+        assert_eq!(decoded, heights);
+
+        let data = load_channels(&png, &linear(), ImageChannels::Auto)
+            .unwrap()
+            .unwrap();
+        assert_eq!(data.1.required_format, Some(Format::R16Unorm));
+        let fallback = data.1.fallback.as_ref().unwrap().convert().unwrap();
+        assert_eq!(fallback.1.required_format, Some(Format::Rgba16Sfloat));
+    }
+
+    #[test]
+    fn grayscale_channels() {
+        let gray = png(&[0, 64, 128, 255], 2, 2, ColorType::Gray(8));
+        let pixels = decode(&gray, &linear(), ImageChannels::Auto)
+            .unwrap()
+            .unwrap();
+        assert_eq!(pixels.format(), Format::R8Unorm);
+        assert_eq!(pixels.data, vec![0, 64, 128, 255]);
+        let srgb = decode(&gray, &ImageFormat::default().0, ImageChannels::Auto)
+            .unwrap()
+            .unwrap();
+        assert_eq!(srgb.format(), Format::R8Srgb);
+
+        // Gray and alpha, premultiplied by default
+        let gray_alpha = png(&[200, 255, 200, 0], 2, 1, ColorType::GrayA(8));
+        let pixels = decode(&gray_alpha, &linear(), ImageChannels::Auto)
+            .unwrap()
+            .unwrap();
+        assert_eq!(pixels.format(), Format::Rg8Unorm);
+        assert_eq!(pixels.data, vec![200, 255, 0, 0]);
+
+        assert_eq!(pixels.fallback_format(), Format::Rgba8Unorm);
+
+        // Color images load as `Rgba8` unless fewer channels are requested
+        let color = png(&[255, 0, 0, 0, 255, 0], 2, 1, ColorType::RGB(8));
+        assert!(decode(&color, &linear(), ImageChannels::Auto)
+            .unwrap()
+            .is_none());
+        assert!(decode(&color, &linear(), ImageChannels::Rgba)
+            .unwrap()
+            .is_none());
+        let mask = decode(&color, &linear(), ImageChannels::R)
+            .unwrap()
+            .unwrap();
+        assert_eq!(mask.format(), Format::R8Unorm);
+        assert_eq!(mask.data.len(), 2);
+    }
+
+    #[test]
+    fn wide_colors_are_linear() {
+        let samples = [0xFFFF, 0x8000, 0x0000, 0x8000];
+        let srgb = convert_wide(&samples, 4, 4, true, false);
+        let linear = convert_wide(&samples, 4, 4, false, true);
+        let read = |data: &[u8], i: usize| u16::from_ne_bytes([data[i * 2], data[i * 2 + 1]]);
+
+        assert_eq!(read(&srgb, 0), 0xFFFF);
+        // sRGB mid-gray is about 21.4% of the linear intensity
+        assert!((f32::from(read(&srgb, 1)) / 65535.0 - 0.2140).abs() < 0.001);
+        assert_eq!(read(&srgb, 3), 0x8000);
+
+        // Premultiplied by an alpha of about one half
+        assert_eq!(read(&linear, 0), 0x8000);
+        assert_eq!(read(&linear, 3), 0x8000);
+
+        // A gray PNG with alpha, reduced to luminance only
+        let luminance = convert_wide(&[0x4000, 0x1000], 2, 1, false, true);
+        assert_eq!(luminance.len(), 2);
+        assert_eq!(read(&luminance, 0), 0x4000);
+    }
+}
@@ -0,0 +1,488 @@
+//! DirectDraw Surface (DDS) texture format.
+//!
+//! Both the legacy header and the DX10 extension header are supported, for 2D textures, texture
+//! arrays and cubemaps with any number of mip levels. Volume textures are not supported.
+use crate::{error::TextureError, formats::texture::ImageFormat, types::TextureData};
+use amethyst_assets::Format;
+use amethyst_error::Error;
+use rendy::{
+    hal::{
+        self,
+        image::{Kind, SamplerInfo, ViewKind},
+    },
+    texture::TextureBuilder,
+};
+use serde::{Deserialize, Serialize};
+
+const MAGIC: &[u8; 4] = b"DDS ";
+const HEADER_SIZE: usize = 124;
+const PIXEL_FORMAT_SIZE: u32 = 32;
+const DX10_HEADER_SIZE: usize = 20;
+/// Width and height no device supports exceeding.
+pub(crate) const MAX_SIZE: u32 = 1 << 16;
+
+const DDSD_MIPMAPCOUNT: u32 = 0x2_0000;
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_CUBEMAP_ALL_FACES: u32 = 0xFC00;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+const DIMENSION_TEXTURE2D: u32 = 3;
+const MISC_TEXTURECUBE: u32 = 0x4;
+
+/// Pixel data decoded from a DDS file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DdsImage {
+    /// Width of the largest mip level in pixels.
+    pub width: u32,
+    /// Height of the largest mip level in pixels.
+    pub height: u32,
+    /// Format of the pixel data.
+    pub format: hal::format::Format,
+    /// Number of array layers, six per cubemap.
+    pub layers: u32,
+    /// Number of mip levels of every layer.
+    pub mip_levels: u32,
+    /// Whether the layers are the faces of one or more cubemaps.
+    pub cubemap: bool,
+    data: Vec<u8>,
+}
+
+impl DdsImage {
+    /// Decode a DDS file.
+    ///
+    /// Legacy headers don't tell whether colors are sRGB encoded, `srgb` picks the format for
+    /// them. The DX10 header always names the exact format, which takes precedence.
+    pub fn parse(bytes: &[u8], srgb: bool) -> Result<Self, TextureError> {
+        if bytes.len() < MAGIC.len() + HEADER_SIZE || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(TextureError::InvalidDds("missing DDS header"));
+        }
+        let header = &bytes[MAGIC.len()..MAGIC.len() + HEADER_SIZE];
+        let word = |i: usize| read_u32(header, i * 4);
+        if word(0) as usize != HEADER_SIZE || word(18) != PIXEL_FORMAT_SIZE {
+            return Err(TextureError::InvalidDds("wrong header size"));
+        }
+
+        let (height, width) = (word(2), word(3));
+        if width == 0 || height == 0 {
+            return Err(TextureError::InvalidDds("texture has no pixels"));
+        }
+        if width.max(height) > MAX_SIZE {
+            return Err(TextureError::UnsupportedDds(format!(
+                "{}x{} texture",
+                width, height
+            )));
+        }
+        let mip_levels = if word(1) & DDSD_MIPMAPCOUNT != 0 {
+            word(6).max(1)
+        } else {
+            1
+        };
+        if mip_levels > 32 - width.max(height).leading_zeros() {
+            return Err(TextureError::InvalidDds(
+                "more mip levels than the texture size allows",
+            ));
+        }
+        let caps2 = word(27);
+        if caps2 & DDSCAPS2_VOLUME != 0 {
+            return Err(TextureError::UnsupportedDds("volume texture".into()));
+        }
+
+        let pixel_flags = word(19);
+        let fourcc = [header[80], header[81], header[82], header[83]];
+        let mut offset = MAGIC.len() + HEADER_SIZE;
+        let mut opaque = false;
+        let (format, layers, cubemap) = if pixel_flags & DDPF_FOURCC != 0 && &fourcc == b"DX10" {
+            let dx10 = bytes
+                .get(offset..offset + DX10_HEADER_SIZE)
+                .ok_or(TextureError::InvalidDds("truncated DX10 header"))?;
+            offset += DX10_HEADER_SIZE;
+            if read_u32(dx10, 4) != DIMENSION_TEXTURE2D {
+                return Err(TextureError::UnsupportedDds("texture is not 2D".into()));
+            }
+            let dxgi = read_u32(dx10, 0);
+            let format = dxgi_format(dxgi)
+                .ok_or_else(|| TextureError::UnsupportedDds(format!("DXGI format {}", dxgi)))?;
+            let cubemap = read_u32(dx10, 8) & MISC_TEXTURECUBE != 0;
+            let array_size = read_u32(dx10, 12).max(1);
+            let layers = if cubemap {
+                array_size.checked_mul(6)
+            } else {
+                Some(array_size)
+            };
+            (
+                format,
+                layers.ok_or(TextureError::InvalidDds("too many array layers"))?,
+                cubemap,
+            )
+        } else {
+            let format = if pixel_flags & DDPF_FOURCC != 0 {
+                fourcc_format(fourcc, srgb).ok_or_else(|| {
+                    TextureError::UnsupportedDds(format!(
+                        "FourCC {:?}",
+                        String::from_utf8_lossy(&fourcc)
+                    ))
+                })?
+            } else {
+                let bits = word(21);
+                let masks = [word(22), word(23), word(24), word(25)];
+                opaque = pixel_flags & DDPF_ALPHAPIXELS == 0;
+                mask_format(pixel_flags, bits, masks, srgb).ok_or_else(|| {
+                    TextureError::UnsupportedDds(format!(
+                        "{} bit uncompressed format with masks {:x?}",
+                        bits, masks
+                    ))
+                })?
+            };
+            let cubemap = caps2 & DDSCAPS2_CUBEMAP != 0;
+            if cubemap && caps2 & DDSCAPS2_CUBEMAP_ALL_FACES != DDSCAPS2_CUBEMAP_ALL_FACES {
+                return Err(TextureError::UnsupportedDds(
+                    "cubemap without all six faces".into(),
+                ));
+            }
+            (format, if cubemap { 6 } else { 1 }, cubemap)
+        };
+        if layers > u32::from(std::u16::MAX) {
+            return Err(TextureError::InvalidDds("too many array layers"));
+        }
+
+        let mut image = DdsImage {
+            width,
+            height,
+            format,
+            layers,
+            mip_levels,
+            cubemap,
+            data: Vec::new(),
+        };
+        let size = image.layer_size() * layers as usize;
+        let data = bytes
+            .get(offset..)
+            .filter(|data| data.len() >= size)
+            .ok_or(TextureError::InvalidDds("pixel data is truncated"))?;
+        image.data = data[..size].to_vec();
+        if opaque {
+            // The alpha byte of formats without alpha is undefined, but sampled as RGBA.
+            for pixel in image.data.chunks_exact_mut(4) {
+                pixel[3] = std::u8::MAX;
+            }
+        }
+        Ok(image)
+    }
+
+    /// Size in pixels of a mip level.
+    pub fn level_extent(&self, mip: u32) -> (u32, u32) {
+        ((self.width >> mip).max(1), (self.height >> mip).max(1))
+    }
+
+    /// Pixel data of a mip level of a layer, `None` if either is out of range.
+    ///
+    /// Cubemap faces are ordered +X, -X, +Y, -Y, +Z, -Z.
+    pub fn level(&self, layer: u32, mip: u32) -> Option<&[u8]> {
+        if layer >= self.layers || mip >= self.mip_levels {
+            return None;
+        }
+        let start = layer as usize * self.layer_size()
+            + (0..mip).map(|m| self.level_size(m)).sum::<usize>();
+        Some(&self.data[start..start + self.level_size(mip)])
+    }
+
+    fn level_size(&self, mip: u32) -> usize {
+        let (block_width, block_height, block_size) = block_layout(self.format);
+        let (width, height) = self.level_extent(mip);
+        let blocks_x = (width + block_width - 1) / block_width;
+        let blocks_y = (height + block_height - 1) / block_height;
+        blocks_x as usize * blocks_y as usize * block_size
+    }
+
+    fn layer_size(&self) -> usize {
+        (0..self.mip_levels).map(|mip| self.level_size(mip)).sum()
+    }
+}
+
+/// Loads DDS files as textures, see `DdsImage` for what is supported.
+///
+/// Every mip level of every layer is uploaded. Textures in block compressed formats the device
+/// can't sample fail to load with `TextureError::UnsupportedFormat`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DdsFormat {
+    /// Whether files with a legacy header are sRGB encoded.
+    pub srgb: bool,
+    /// Sampler of the texture.
+    pub sampler_info: SamplerInfo,
+}
+
+impl Default for DdsFormat {
+    fn default() -> Self {
+        DdsFormat {
+            srgb: true,
+            sampler_info: ImageFormat::default().0.sampler_info,
+        }
+    }
+}
+
+impl DdsFormat {
+    /// Create the texture data of a decoded DDS file.
+    pub fn texture_data(&self, image: &DdsImage) -> TextureData {
+        // The file stores the mip chain of each layer in turn, textures take each level with
+        // all of its layers.
+        let mut levels = (0..image.mip_levels).map(|mip| {
+            (0..image.layers)
+                .flat_map(|layer| image.level(layer, mip).unwrap_or(&[]).iter().cloned())
+                .collect::<Vec<u8>>()
+        });
+        let data = levels.next().unwrap_or_default();
+        layered_texture_data(
+            (image.width, image.height),
+            image.layers,
+            image.cubemap,
+            image.format,
+            data,
+            self.sampler_info.clone(),
+        )
+        .with_mip_chain(levels.collect())
+    }
+}
+
+/// Create the texture data of a 2D texture, texture array or cubemap from the pixels of its
+/// layers, in a format that may not be supported by every device.
+pub(crate) fn layered_texture_data(
+    (width, height): (u32, u32),
+    layers: u32,
+    cubemap: bool,
+    format: hal::format::Format,
+    data: Vec<u8>,
+    sampler_info: SamplerInfo,
+) -> TextureData {
+    let (block_width, block_height, _) = block_layout(format);
+    let byte_size = data.len() as u64;
+    let view_kind = match (cubemap, layers) {
+        (true, 6) => ViewKind::Cube,
+        (true, _) => ViewKind::CubeArray,
+        (false, 1) => ViewKind::D2,
+        (false, _) => ViewKind::D2Array,
+    };
+
+    TextureData::from(
+        TextureBuilder::new()
+            .with_kind(Kind::D2(width, height, layers as u16, 1))
+            .with_view_kind(view_kind)
+            .with_data_width(round_up(width, block_width))
+            .with_data_height(round_up(height, block_height))
+            .with_sampler_info(sampler_info)
+            .with_raw_data(data, format),
+    )
+    .with_required_format(format)
+    .with_byte_size(byte_size)
+}
+
+amethyst_assets::register_format!("DDS", DdsFormat as TextureData);
+impl Format<TextureData> for DdsFormat {
+    fn name(&self) -> &'static str {
+        "DDS"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<TextureData, Error> {
+        let image = DdsImage::parse(&bytes, self.srgb)?;
+        Ok(self.texture_data(&image))
+    }
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+pub(crate) fn round_up(value: u32, multiple: u32) -> u32 {
+    (value + multiple - 1) / multiple * multiple
+}
+
+/// Width and height in pixels and size in bytes of the blocks of a format.
+pub(crate) fn block_layout(format: hal::format::Format) -> (u32, u32, usize) {
+    use hal::format::Format::*;
+
+    match format {
+        Bc1RgbaUnorm | Bc1RgbaSrgb | Bc4Unorm => (4, 4, 8),
+        Bc2Unorm | Bc2Srgb | Bc3Unorm | Bc3Srgb | Bc5Unorm | Bc6hUfloat | Bc7Unorm | Bc7Srgb => {
+            (4, 4, 16)
+        }
+        Rgba16Sfloat => (1, 1, 8),
+        Rgba32Sfloat => (1, 1, 16),
+        _ => (1, 1, 4),
+    }
+}
+
+fn pick_srgb(
+    srgb: bool,
+    linear: hal::format::Format,
+    encoded: hal::format::Format,
+) -> hal::format::Format {
+    if srgb {
+        encoded
+    } else {
+        linear
+    }
+}
+
+fn fourcc_format(fourcc: [u8; 4], srgb: bool) -> Option<hal::format::Format> {
+    use hal::format::Format::*;
+
+    Some(match &fourcc {
+        b"DXT1" => pick_srgb(srgb, Bc1RgbaUnorm, Bc1RgbaSrgb),
+        b"DXT2" | b"DXT3" => pick_srgb(srgb, Bc2Unorm, Bc2Srgb),
+        b"DXT4" | b"DXT5" => pick_srgb(srgb, Bc3Unorm, Bc3Srgb),
+        b"ATI1" | b"BC4U" => Bc4Unorm,
+        b"ATI2" | b"BC5U" => Bc5Unorm,
+        // Legacy D3DFMT codes stored in place of a FourCC
+        _ => match u32::from_le_bytes(fourcc) {
+            113 => Rgba16Sfloat,
+            116 => Rgba32Sfloat,
+            _ => return None,
+        },
+    })
+}
+
+fn mask_format(flags: u32, bits: u32, masks: [u32; 4], srgb: bool) -> Option<hal::format::Format> {
+    use hal::format::Format::*;
+
+    let alpha = if flags & DDPF_ALPHAPIXELS != 0 {
+        masks[3]
+    } else {
+        0xff00_0000
+    };
+    if flags & DDPF_RGB == 0 || bits != 32 || alpha != 0xff00_0000 {
+        return None;
+    }
+    match [masks[0], masks[1], masks[2]] {
+        [0xff, 0xff00, 0xff_0000] => Some(pick_srgb(srgb, Rgba8Unorm, Rgba8Srgb)),
+        [0xff_0000, 0xff00, 0xff] => Some(pick_srgb(srgb, Bgra8Unorm, Bgra8Srgb)),
+        _ => None,
+    }
+}
+
+fn dxgi_format(dxgi: u32) -> Option<hal::format::Format> {
+    use hal::format::Format::*;
+
+    Some(match dxgi {
+        2 => Rgba32Sfloat,
+        10 => Rgba16Sfloat,
+        28 => Rgba8Unorm,
+        29 => Rgba8Srgb,
+        71 => Bc1RgbaUnorm,
+        72 => Bc1RgbaSrgb,
+        74 => Bc2Unorm,
+        75 => Bc2Srgb,
+        77 => Bc3Unorm,
+        78 => Bc3Srgb,
+        80 => Bc4Unorm,
+        83 => Bc5Unorm,
+        87 => Bgra8Unorm,
+        91 => Bgra8Srgb,
+        95 => Bc6hUfloat,
+        98 => Bc7Unorm,
+        99 => Bc7Srgb,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hal::format::Format as HalFormat;
+
+    const BC1: &[u8] = include_bytes!("../../tests/fixtures/dds/bc1_mips.dds");
+    const BC3: &[u8] = include_bytes!("../../tests/fixtures/dds/bc3_dx10.dds");
+    const RGBA8: &[u8] = include_bytes!("../../tests/fixtures/dds/rgba8_mips.dds");
+    const CUBE: &[u8] = include_bytes!("../../tests/fixtures/dds/bgra8_cube.dds");
+
+    #[test]
+    fn bc1_mip_chain() {
+        let image = DdsImage::parse(BC1, true).unwrap();
+        assert_eq!(image.format, HalFormat::Bc1RgbaSrgb);
+        assert_eq!((image.width, image.height), (4, 4));
+        assert_eq!((image.layers, image.mip_levels), (1, 3));
+        assert_eq!(image.level_extent(2), (1, 1));
+        assert_eq!(image.level(0, 0), Some(&BC1[128..136]));
+        assert_eq!(image.level(0, 2), Some(&BC1[144..152]));
+        assert_eq!(image.level(0, 3), None);
+
+        let linear = DdsImage::parse(BC1, false).unwrap();
+        assert_eq!(linear.format, HalFormat::Bc1RgbaUnorm);
+    }
+
+    #[test]
+    fn bc3_dx10_header() {
+        let image = DdsImage::parse(BC3, false).unwrap();
+        assert_eq!(image.format, HalFormat::Bc3Unorm);
+        assert_eq!((image.width, image.height), (8, 8));
+        assert_eq!((image.layers, image.mip_levels), (1, 1));
+        assert_eq!(image.level(0, 0).map(<[u8]>::len), Some(64));
+
+        let data = DdsFormat::default().import_simple(BC3.to_vec()).unwrap();
+        assert_eq!(data.1.required_format, Some(HalFormat::Bc3Unorm));
+    }
+
+    #[test]
+    fn rgba8_mip_chain() {
+        let image = DdsImage::parse(RGBA8, true).unwrap();
+        assert_eq!(image.format, HalFormat::Rgba8Srgb);
+        assert_eq!(image.mip_levels, 2);
+        assert_eq!(image.level(0, 0).unwrap()[..4], [255, 0, 0, 255]);
+        assert_eq!(image.level(0, 0).unwrap()[12..], [255, 255, 255, 128]);
+        assert_eq!(image.level(0, 1), Some(&[10, 20, 30, 40][..]));
+    }
+
+    #[test]
+    fn every_mip_level_is_uploaded() {
+        let bc1 = DdsFormat::default().import_simple(BC1.to_vec()).unwrap();
+        assert_eq!(bc1.mip_levels(), 3);
+        assert_eq!(
+            bc1.1.mip_chain,
+            vec![BC1[136..144].to_vec(), BC1[144..152].to_vec()]
+        );
+        assert_eq!(bc1.1.byte_size, Some(24));
+
+        let rgba8 = DdsFormat::default().import_simple(RGBA8.to_vec()).unwrap();
+        assert_eq!(rgba8.mip_levels(), 2);
+        assert_eq!(rgba8.1.mip_chain, vec![vec![10, 20, 30, 40]]);
+
+        let cube = DdsFormat::default().import_simple(CUBE.to_vec()).unwrap();
+        assert_eq!(cube.mip_levels(), 1);
+    }
+
+    #[test]
+    fn cubemap_faces() {
+        let image = DdsImage::parse(CUBE, true).unwrap();
+        assert_eq!(image.format, HalFormat::Bgra8Srgb);
+        assert!(image.cubemap);
+        assert_eq!((image.layers, image.mip_levels), (6, 1));
+        for face in 0..6 {
+            assert_eq!(image.level(face, 0), Some(&[face as u8, 0, 0, 255][..]));
+        }
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert_eq!(
+            DdsImage::parse(&BC1[..100], true),
+            Err(TextureError::InvalidDds("missing DDS header"))
+        );
+        assert_eq!(
+            DdsImage::parse(&BC1[..140], true),
+            Err(TextureError::InvalidDds("pixel data is truncated"))
+        );
+
+        let mut unknown = BC1.to_vec();
+        unknown[84..88].copy_from_slice(b"ATC ");
+        match DdsImage::parse(&unknown, true) {
+            Err(TextureError::UnsupportedDds(what)) => assert!(what.contains("ATC")),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+}
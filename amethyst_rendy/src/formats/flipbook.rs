@@ -0,0 +1,276 @@
+//! Formats loading the frames of animated textures: GIF files and numbered image sequences.
+//!
+//! APNG files are loaded as still images by `ImageFormat`, as the PNG decoder doesn't expose
+//! their frames. Convert them to GIF or to an image sequence to animate them.
+use crate::{
+    error::TextureError,
+    flipbook::{FlipbookData, FlipbookLayout},
+};
+use amethyst_assets::{Format, FormatValue, Source};
+use amethyst_error::Error;
+use gif::{DisposalMethod, SetParameter};
+use rendy::hal::image::{Filter, SamplerInfo, WrapMode};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// GIF image format, loaded as a flipbook of its frames.
+///
+/// Frames are composited onto the full canvas of the image. As in browsers, frames with a delay
+/// below 20 ms are shown for 100 ms.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GifFormat {
+    /// How the frames are stored in the texture.
+    pub layout: FlipbookLayout,
+    /// Sampler of the texture.
+    pub sampler_info: SamplerInfo,
+    /// Premultiply the colors by their alpha, as `ImageFormat` does by default.
+    pub premultiply_alpha: bool,
+}
+
+impl Default for GifFormat {
+    fn default() -> Self {
+        GifFormat {
+            layout: FlipbookLayout::default(),
+            sampler_info: SamplerInfo::new(Filter::Linear, WrapMode::Clamp),
+            premultiply_alpha: true,
+        }
+    }
+}
+
+amethyst_assets::register_format_type!(FlipbookData);
+
+amethyst_assets::register_format!("GIF", GifFormat as FlipbookData);
+impl Format<FlipbookData> for GifFormat {
+    fn name(&self) -> &'static str {
+        "GIF"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<FlipbookData, Error> {
+        let mut decoder = gif::Decoder::new(&bytes[..]);
+        decoder.set(gif::ColorOutput::RGBA);
+        let mut reader = decoder.read_info()?;
+        let size = (u32::from(reader.width()), u32::from(reader.height()));
+        let mut data = FlipbookData::new(size.0, size.1, self.layout, self.sampler_info.clone());
+
+        let mut canvas = vec![0; size.0 as usize * size.1 as usize * 4];
+        while let Some(frame) = reader.read_next_frame()? {
+            let rect = [
+                u32::from(frame.left),
+                u32::from(frame.top),
+                u32::from(frame.width),
+                u32::from(frame.height),
+            ];
+            let previous = match frame.dispose {
+                DisposalMethod::Previous => Some(canvas.clone()),
+                _ => None,
+            };
+            blit(&mut canvas, size, rect, Some(&frame.buffer[..]));
+
+            let mut pixels = canvas.clone();
+            if self.premultiply_alpha {
+                premultiply(&mut pixels);
+            }
+            data.push_frame(&pixels, gif_delay(frame.delay))?;
+
+            match frame.dispose {
+                DisposalMethod::Background => blit(&mut canvas, size, rect, None),
+                DisposalMethod::Previous => canvas = previous.unwrap_or(canvas),
+                _ => {}
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// Format loading a flipbook from numbered images, such as `fire/0.png`, `fire/1.png`, ...
+///
+/// The asset name is the path of the frames, with `{}` in place of the frame number, or `{:0N}`
+/// for numbers padded with zeros to `N` digits, like `fire/{:03}.png` for `fire/000.png`.
+/// Frames are loaded from `first_frame` on until an image is missing. All frames must have the
+/// same size, and are shown for `frame_duration` seconds.
+///
+/// Image sequences are not hot reloaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FrameSequenceFormat {
+    /// How the frames are stored in the texture.
+    pub layout: FlipbookLayout,
+    /// Sampler of the texture.
+    pub sampler_info: SamplerInfo,
+    /// Premultiply the colors by their alpha, as `ImageFormat` does by default.
+    pub premultiply_alpha: bool,
+    /// Number of the first frame.
+    pub first_frame: u32,
+    /// Display duration of every frame, in seconds.
+    pub frame_duration: f32,
+}
+
+impl Default for FrameSequenceFormat {
+    fn default() -> Self {
+        FrameSequenceFormat {
+            layout: FlipbookLayout::default(),
+            sampler_info: SamplerInfo::new(Filter::Linear, WrapMode::Clamp),
+            premultiply_alpha: true,
+            first_frame: 0,
+            frame_duration: 0.1,
+        }
+    }
+}
+
+amethyst_assets::register_format!("FRAME_SEQUENCE", FrameSequenceFormat as FlipbookData);
+impl Format<FlipbookData> for FrameSequenceFormat {
+    fn name(&self) -> &'static str {
+        "FRAME_SEQUENCE"
+    }
+
+    fn import(
+        &self,
+        name: String,
+        source: Arc<dyn Source>,
+        _create_reload: Option<Box<dyn Format<FlipbookData>>>,
+    ) -> Result<FormatValue<FlipbookData>, Error> {
+        let mut data: Option<FlipbookData> = None;
+        let mut number = self.first_frame;
+        loop {
+            let path = frame_path(&name, number).ok_or_else(|| {
+                TextureError::InvalidFlipbook(format!(
+                    "{:?} has no `{{}}` or `{{:0N}}` in place of the frame number",
+                    name
+                ))
+            })?;
+            let bytes = match source.load(&path) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    return match data {
+                        Some(data) => Ok(FormatValue::data(data)),
+                        None => Err(e),
+                    };
+                }
+            };
+            let image = image::load_from_memory(&bytes)?.to_rgba();
+            let (width, height) = image.dimensions();
+            let mut pixels = image.into_raw();
+            if self.premultiply_alpha {
+                premultiply(&mut pixels);
+            }
+
+            let data = data.get_or_insert_with(|| {
+                FlipbookData::new(width, height, self.layout, self.sampler_info.clone())
+            });
+            if (width, height) != (data.width(), data.height()) {
+                return Err(TextureError::InvalidFlipbook(format!(
+                    "frame {} is {}x{}, but the first frame is {}x{}",
+                    path,
+                    width,
+                    height,
+                    data.width(),
+                    data.height()
+                ))
+                .into());
+            }
+            data.push_frame(&pixels, self.frame_duration)?;
+            number += 1;
+        }
+    }
+}
+
+/// Replace the frame number placeholder of `pattern` by `number`.
+fn frame_path(pattern: &str, number: u32) -> Option<String> {
+    let start = pattern.find('{')?;
+    let end = start + pattern[start..].find('}')?;
+    let number = match &pattern[start + 1..end] {
+        "" => number.to_string(),
+        spec if spec.starts_with(":0") => {
+            let width: usize = spec[2..].parse().ok()?;
+            format!("{:0width$}", number, width = width)
+        }
+        _ => return None,
+    };
+    Some(format!(
+        "{}{}{}",
+        &pattern[..start],
+        number,
+        &pattern[end + 1..]
+    ))
+}
+
+/// Display duration of a GIF frame in seconds, from its delay in hundredths of a second.
+fn gif_delay(delay: u16) -> f32 {
+    if delay < 2 {
+        0.1
+    } else {
+        f32::from(delay) / 100.0
+    }
+}
+
+/// Draw the opaque `pixels` of a `[left, top, width, height]` rectangle onto an RGBA canvas of
+/// `size`, or clear the rectangle if `pixels` is `None`. Pixels outside the canvas are ignored.
+fn blit(canvas: &mut [u8], size: (u32, u32), rect: [u32; 4], pixels: Option<&[u8]>) {
+    let [left, top, width, height] = rect;
+    let columns = width.min(size.0.saturating_sub(left)) as usize;
+    let rows = height.min(size.1.saturating_sub(top)) as usize;
+    for row in 0..rows {
+        let start = ((top as usize + row) * size.0 as usize + left as usize) * 4;
+        let target = &mut canvas[start..start + columns * 4];
+        match pixels {
+            Some(pixels) => {
+                let source = &pixels[row * width as usize * 4..][..columns * 4];
+                for (target, source) in target.chunks_exact_mut(4).zip(source.chunks_exact(4)) {
+                    if source[3] != 0 {
+                        target.copy_from_slice(source);
+                    }
+                }
+            }
+            None => {
+                for byte in target {
+                    *byte = 0;
+                }
+            }
+        }
+    }
+}
+
+fn premultiply(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let alpha = u16::from(pixel[3]);
+        for channel in &mut pixel[..3] {
+            *channel = (u16::from(*channel) * alpha / 255) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_paths() {
+        assert_eq!(frame_path("fire/{}.png", 12), Some("fire/12.png".into()));
+        assert_eq!(frame_path("fire_{:03}.png", 7), Some("fire_007.png".into()));
+        assert_eq!(frame_path("fire.png", 0), None);
+        assert_eq!(frame_path("fire_{:x}.png", 0), None);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn gif_frames_are_composited() {
+        let red = [255, 0, 0, 255];
+        let clear = [0, 0, 0, 0];
+        let mut canvas = vec![0; 3 * 2 * 4];
+        // Transparent pixels keep the canvas, the rest of the frame is clipped
+        blit(&mut canvas, (3, 2), [0, 0, 2, 2], Some(&[red; 4].concat()));
+        let frame = [clear, red, clear, red].concat();
+        blit(&mut canvas, (3, 2), [1, 1, 4, 1], Some(&frame));
+        assert_eq!(canvas, [red, red, clear, red, red, red].concat());
+        blit(&mut canvas, (3, 2), [0, 1, 2, 1], None);
+        assert_eq!(canvas, [red, red, clear, clear, clear, red].concat());
+
+        assert_eq!(gif_delay(0), 0.1);
+        assert_eq!(gif_delay(4), 0.04);
+
+        let mut pixels = vec![200, 100, 50, 128];
+        premultiply(&mut pixels);
+        assert_eq!(pixels, vec![100, 50, 25, 128]);
+    }
+}
@@ -0,0 +1,642 @@
+//! High dynamic range image formats: Radiance `.hdr` (RGBE) and OpenEXR.
+//!
+//! Images are decoded one scanline at a time straight into the pixel format of the texture, so
+//! that large environment maps are not held twice in memory. Only the `32-bit_rle_rgbe`
+//! Radiance format with the default `-Y h +X w` orientation, and single part OpenEXR scanline
+//! images without compression are supported.
+use crate::{error::TextureError, types::TextureData};
+use amethyst_assets::Format;
+use amethyst_error::Error;
+use rendy::{
+    hal::{
+        self,
+        image::{Filter, Kind, SamplerInfo, ViewKind, WrapMode},
+    },
+    texture::TextureBuilder,
+};
+use serde::{Deserialize, Serialize};
+
+const EXR_MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const EXR_TILED: u32 = 0x200;
+const EXR_DEEP: u32 = 0x800;
+const EXR_MULTIPART: u32 = 0x1000;
+const EXR_UINT: i32 = 0;
+const EXR_HALF: i32 = 1;
+const EXR_FLOAT: i32 = 2;
+
+/// Precision of the float textures loaded from HDR images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HdrPrecision {
+    /// 16 bit floats, `Rgba16Sfloat`.
+    Half,
+    /// 32 bit floats, `Rgba32Sfloat`.
+    Full,
+}
+
+impl Default for HdrPrecision {
+    fn default() -> Self {
+        HdrPrecision::Half
+    }
+}
+
+impl HdrPrecision {
+    /// Format of the texture.
+    pub fn format(self) -> hal::format::Format {
+        match self {
+            HdrPrecision::Half => hal::format::Format::Rgba16Sfloat,
+            HdrPrecision::Full => hal::format::Format::Rgba32Sfloat,
+        }
+    }
+
+    fn pixel_size(self) -> usize {
+        match self {
+            HdrPrecision::Half => 8,
+            HdrPrecision::Full => 16,
+        }
+    }
+}
+
+/// Float RGBA pixels decoded from an HDR image, with the top row first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrImage {
+    /// Width in pixels.
+    pub width: u32,
+    /// Height in pixels.
+    pub height: u32,
+    /// Precision of the pixel data.
+    pub precision: HdrPrecision,
+    data: Vec<u8>,
+}
+
+impl HdrImage {
+    fn with_capacity(width: u32, height: u32, precision: HdrPrecision) -> Self {
+        HdrImage {
+            width,
+            height,
+            precision,
+            data: Vec::with_capacity(width as usize * height as usize * precision.pixel_size()),
+        }
+    }
+
+    /// Decode a Radiance RGBE image.
+    pub fn parse_radiance(bytes: &[u8], precision: HdrPrecision) -> Result<Self, TextureError> {
+        let mut lines = RadianceHeader { bytes, offset: 0 };
+        if !lines.next_line()?.starts_with(b"#?") {
+            return Err(TextureError::InvalidHdr("missing Radiance header"));
+        }
+        loop {
+            let line = lines.next_line()?;
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with(b"FORMAT=") && line != b"FORMAT=32-bit_rle_rgbe" {
+                return Err(TextureError::UnsupportedHdr(
+                    String::from_utf8_lossy(line).into_owned(),
+                ));
+            }
+        }
+        let resolution = String::from_utf8_lossy(lines.next_line()?).into_owned();
+        let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => match (height.parse::<u32>(), width.parse::<u32>()) {
+                (Ok(height), Ok(width)) if height > 0 && width > 0 => (height, width),
+                _ => return Err(TextureError::InvalidHdr("invalid resolution")),
+            },
+            _ => return Err(TextureError::UnsupportedHdr(resolution)),
+        };
+
+        let mut image = HdrImage::with_capacity(width, height, precision);
+        let mut scanline = vec![0; width as usize * 4];
+        let mut bytes = &bytes[lines.offset..];
+        for _ in 0..height {
+            bytes = read_rgbe_scanline(bytes, &mut scanline)?;
+            for rgbe in scanline.chunks_exact(4) {
+                let scale = if rgbe[3] == 0 {
+                    0.0
+                } else {
+                    2f32.powi(i32::from(rgbe[3]) - 136)
+                };
+                image.push([
+                    f32::from(rgbe[0]) * scale,
+                    f32::from(rgbe[1]) * scale,
+                    f32::from(rgbe[2]) * scale,
+                    1.0,
+                ]);
+            }
+        }
+        Ok(image)
+    }
+
+    /// Decode an OpenEXR image. The `R`, `G`, `B` and `A` channels are loaded, missing color
+    /// channels are zero and a missing alpha channel is one.
+    pub fn parse_exr(bytes: &[u8], precision: HdrPrecision) -> Result<Self, TextureError> {
+        let mut reader = ExrReader { bytes, offset: 0 };
+        if reader.take(4)? != &EXR_MAGIC[..] {
+            return Err(TextureError::InvalidExr("missing OpenEXR header"));
+        }
+        let version = reader.u32()?;
+        if version & 0xff != 2 {
+            return Err(TextureError::UnsupportedExr(format!(
+                "version {}",
+                version & 0xff
+            )));
+        }
+        if version & (EXR_TILED | EXR_DEEP | EXR_MULTIPART) != 0 {
+            return Err(TextureError::UnsupportedExr(
+                "tiled, deep or multi-part image".into(),
+            ));
+        }
+
+        let mut channels = Vec::new();
+        let mut window = None;
+        let mut compression = None;
+        loop {
+            let name = reader.string()?;
+            if name.is_empty() {
+                break;
+            }
+            let kind = reader.string()?;
+            let size = reader.i32()?;
+            if size < 0 {
+                return Err(TextureError::InvalidExr("negative attribute size"));
+            }
+            let mut value = ExrReader {
+                bytes: reader.take(size as usize)?,
+                offset: 0,
+            };
+            match (name, kind) {
+                (b"channels", b"chlist") => loop {
+                    let channel = value.string()?;
+                    if channel.is_empty() {
+                        break;
+                    }
+                    let pixel_type = value.i32()?;
+                    value.take(4)?;
+                    if (value.i32()?, value.i32()?) != (1, 1) {
+                        return Err(TextureError::UnsupportedExr("subsampled channel".into()));
+                    }
+                    let target = match channel {
+                        b"R" => Some(0),
+                        b"G" => Some(1),
+                        b"B" => Some(2),
+                        b"A" => Some(3),
+                        _ => None,
+                    };
+                    channels.push((target, pixel_type));
+                },
+                (b"compression", b"compression") => compression = Some(value.take(1)?[0]),
+                (b"dataWindow", b"box2i") => {
+                    window = Some([value.i32()?, value.i32()?, value.i32()?, value.i32()?]);
+                }
+                _ => {}
+            }
+        }
+
+        match compression {
+            Some(0) => {}
+            Some(compression) => {
+                return Err(TextureError::UnsupportedExr(format!(
+                    "compression {}",
+                    compression
+                )))
+            }
+            None => return Err(TextureError::InvalidExr("missing compression")),
+        }
+        let [min_x, min_y, max_x, max_y] =
+            window.ok_or(TextureError::InvalidExr("missing data window"))?;
+        if max_x < min_x || max_y < min_y {
+            return Err(TextureError::InvalidExr("empty data window"));
+        }
+        let width = (i64::from(max_x) - i64::from(min_x) + 1) as u32;
+        let height = (i64::from(max_y) - i64::from(min_y) + 1) as u32;
+        let mut sample_size = 0;
+        for &(_, pixel_type) in &channels {
+            sample_size += match pixel_type {
+                EXR_UINT | EXR_FLOAT => 4,
+                EXR_HALF => 2,
+                _ => return Err(TextureError::InvalidExr("unknown channel type")),
+            };
+        }
+
+        // The offset table follows the header, one absolute offset per scanline
+        let table = reader.offset;
+        let mut image = HdrImage::with_capacity(width, height, precision);
+        let mut scanline = vec![[0.0, 0.0, 0.0, 1.0]; width as usize];
+        for y in 0..height as usize {
+            reader.offset = table + y * 8;
+            let offset = reader.u64()?;
+            if offset > bytes.len() as u64 {
+                return Err(TextureError::InvalidExr("scanline offset out of bounds"));
+            }
+            let mut line = ExrReader {
+                bytes,
+                offset: offset as usize,
+            };
+            if i64::from(line.i32()?) != i64::from(min_y) + y as i64 {
+                return Err(TextureError::InvalidExr("scanlines out of order"));
+            }
+            if line.i32()? as usize != sample_size * width as usize {
+                return Err(TextureError::InvalidExr("wrong scanline size"));
+            }
+            for &(target, pixel_type) in &channels {
+                for pixel in scanline.iter_mut() {
+                    let value = match pixel_type {
+                        EXR_HALF => f16_to_f32(line.u16()?),
+                        EXR_FLOAT => f32::from_bits(line.u32()?),
+                        _ => line.u32()? as f32,
+                    };
+                    if let Some(target) = target {
+                        pixel[target] = value;
+                    }
+                }
+            }
+            for &pixel in &scanline {
+                image.push(pixel);
+            }
+        }
+        Ok(image)
+    }
+
+    /// Format of the pixel data.
+    pub fn format(&self) -> hal::format::Format {
+        self.precision.format()
+    }
+
+    /// Pixel data, row by row with the top row first.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub(crate) fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// RGBA value of a pixel, `None` if it is out of bounds.
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[f32; 4]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let size = self.precision.pixel_size();
+        let start = (y as usize * self.width as usize + x as usize) * size;
+        let bytes = &self.data[start..start + size];
+        let mut pixel = [0.0; 4];
+        for (channel, value) in pixel.iter_mut().enumerate() {
+            *value = match self.precision {
+                HdrPrecision::Half => f16_to_f32(u16::from_le_bytes([
+                    bytes[channel * 2],
+                    bytes[channel * 2 + 1],
+                ])),
+                HdrPrecision::Full => f32::from_bits(u32::from_le_bytes([
+                    bytes[channel * 4],
+                    bytes[channel * 4 + 1],
+                    bytes[channel * 4 + 2],
+                    bytes[channel * 4 + 3],
+                ])),
+            };
+        }
+        Some(pixel)
+    }
+
+    fn push(&mut self, pixel: [f32; 4]) {
+        for &value in &pixel {
+            match self.precision {
+                HdrPrecision::Half => self
+                    .data
+                    .extend_from_slice(&f32_to_f16(value).to_le_bytes()),
+                HdrPrecision::Full => self.data.extend_from_slice(&value.to_bits().to_le_bytes()),
+            }
+        }
+    }
+
+    /// Create the texture data of the image.
+    pub fn texture_data(self, sampler_info: SamplerInfo) -> TextureData {
+        let format = self.format();
+        let byte_size = self.data.len() as u64;
+        TextureData::from(
+            TextureBuilder::new()
+                .with_kind(Kind::D2(self.width, self.height, 1, 1))
+                .with_view_kind(ViewKind::D2)
+                .with_data_width(self.width)
+                .with_data_height(self.height)
+                .with_raw_data(self.data, format),
+        )
+        .with_sampler_info(sampler_info)
+        .with_byte_size(byte_size)
+    }
+}
+
+struct RadianceHeader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> RadianceHeader<'a> {
+    fn next_line(&mut self) -> Result<&'a [u8], TextureError> {
+        let rest = &self.bytes[self.offset..];
+        let end = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or(TextureError::InvalidHdr("unterminated header"))?;
+        self.offset += end + 1;
+        Ok(&rest[..end])
+    }
+}
+
+/// Read one scanline of `scanline.len() / 4` RGBE pixels, returning the remaining bytes.
+fn read_rgbe_scanline<'a>(bytes: &'a [u8], scanline: &mut [u8]) -> Result<&'a [u8], TextureError> {
+    let width = scanline.len() / 4;
+    let truncated = TextureError::InvalidHdr("truncated pixel data");
+    let run_length = width >= 8
+        && width < 0x8000
+        && bytes.len() >= 4
+        && bytes[0] == 2
+        && bytes[1] == 2
+        && bytes[2] & 0x80 == 0;
+    if !run_length {
+        // Flat scanline of raw RGBE pixels
+        if bytes.len() < scanline.len() {
+            return Err(truncated);
+        }
+        scanline.copy_from_slice(&bytes[..scanline.len()]);
+        return Ok(&bytes[scanline.len()..]);
+    }
+    if (usize::from(bytes[2]) << 8 | usize::from(bytes[3])) != width {
+        return Err(TextureError::InvalidHdr("wrong scanline width"));
+    }
+
+    // Each channel is run length encoded separately
+    let mut offset = 4;
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = *bytes.get(offset).ok_or_else(|| truncated.clone())? as usize;
+            offset += 1;
+            let (count, run) = if count > 128 {
+                (count - 128, true)
+            } else {
+                (count, false)
+            };
+            if count == 0 || x + count > width {
+                return Err(TextureError::InvalidHdr("invalid run length"));
+            }
+            let length = if run { 1 } else { count };
+            let values = bytes
+                .get(offset..offset + length)
+                .ok_or_else(|| truncated.clone())?;
+            offset += length;
+            for i in 0..count {
+                scanline[(x + i) * 4 + channel] = if run { values[0] } else { values[i] };
+            }
+            x += count;
+        }
+    }
+    Ok(&bytes[offset..])
+}
+
+struct ExrReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ExrReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], TextureError> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset.saturating_add(len))
+            .ok_or(TextureError::InvalidExr("unexpected end of file"))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    /// Null terminated string, without the terminator.
+    fn string(&mut self) -> Result<&'a [u8], TextureError> {
+        let rest = &self.bytes[self.offset.min(self.bytes.len())..];
+        let end = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or(TextureError::InvalidExr("unterminated string"))?;
+        self.offset += end + 1;
+        Ok(&rest[..end])
+    }
+
+    fn u16(&mut self) -> Result<u16, TextureError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, TextureError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i32(&mut self) -> Result<i32, TextureError> {
+        self.u32().map(|value| value as i32)
+    }
+
+    fn u64(&mut self) -> Result<u64, TextureError> {
+        Ok(u64::from(self.u32()?) | u64::from(self.u32()?) << 32)
+    }
+}
+
+/// Convert to the nearest half float, overflowing to infinity.
+pub(crate) fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        // Infinity or NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        // Subnormal, with the implicit leading bit made explicit
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = mantissa >> shift;
+        let round = (mantissa >> (shift - 1)) & 1;
+        let sticky = mantissa & ((1 << (shift - 1)) - 1) != 0;
+        let half = half + (round & (u32::from(sticky) | (half & 1)));
+        return sign | half as u16;
+    }
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    let round = (mantissa >> 12) & 1;
+    let sticky = mantissa & 0xfff != 0;
+    // A carry out of the mantissa correctly increments the exponent
+    sign | (half + (round & (u32::from(sticky) | (half & 1)))) as u16
+}
+
+pub(crate) fn f16_to_f32(half: u16) -> f32 {
+    let sign = u32::from(half & 0x8000) << 16;
+    let exponent = u32::from((half >> 10) & 0x1f);
+    let mantissa = u32::from(half & 0x3ff);
+    let bits = match (exponent, mantissa) {
+        (0, 0) => sign,
+        (0, _) => {
+            // Subnormal, normalize the mantissa
+            let shift = mantissa.leading_zeros() - 21;
+            sign | (113 - shift) << 23 | ((mantissa << shift) & 0x3ff) << 13
+        }
+        (0x1f, _) => sign | 0x7f80_0000 | mantissa << 13,
+        _ => sign | (exponent + 112) << 23 | mantissa << 13,
+    };
+    f32::from_bits(bits)
+}
+
+fn default_sampler() -> SamplerInfo {
+    SamplerInfo::new(Filter::Linear, WrapMode::Clamp)
+}
+
+/// Radiance `.hdr` image format, loaded as a float texture.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HdrFormat {
+    /// Precision of the texture.
+    pub precision: HdrPrecision,
+    /// Sampler of the texture.
+    pub sampler_info: SamplerInfo,
+}
+
+impl Default for HdrFormat {
+    fn default() -> Self {
+        HdrFormat {
+            precision: HdrPrecision::default(),
+            sampler_info: default_sampler(),
+        }
+    }
+}
+
+amethyst_assets::register_format!("HDR", HdrFormat as TextureData);
+impl Format<TextureData> for HdrFormat {
+    fn name(&self) -> &'static str {
+        "HDR"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<TextureData, Error> {
+        let image = HdrImage::parse_radiance(&bytes, self.precision)?;
+        Ok(image.texture_data(self.sampler_info.clone()))
+    }
+}
+
+/// OpenEXR image format, loaded as a float texture.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExrFormat {
+    /// Precision of the texture.
+    pub precision: HdrPrecision,
+    /// Sampler of the texture.
+    pub sampler_info: SamplerInfo,
+}
+
+impl Default for ExrFormat {
+    fn default() -> Self {
+        ExrFormat {
+            precision: HdrPrecision::default(),
+            sampler_info: default_sampler(),
+        }
+    }
+}
+
+amethyst_assets::register_format!("EXR", ExrFormat as TextureData);
+impl Format<TextureData> for ExrFormat {
+    fn name(&self) -> &'static str {
+        "EXR"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<TextureData, Error> {
+        let image = HdrImage::parse_exr(&bytes, self.precision)?;
+        Ok(image.texture_data(self.sampler_info.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RLE: &[u8] = include_bytes!("../../tests/fixtures/hdr/rgbe_rle.hdr");
+    const FLAT: &[u8] = include_bytes!("../../tests/fixtures/hdr/rgbe_flat.hdr");
+    const EXR: &[u8] = include_bytes!("../../tests/fixtures/hdr/rgba_half_float.exr");
+
+    #[test]
+    fn radiance_run_length() {
+        for &precision in &[HdrPrecision::Half, HdrPrecision::Full] {
+            let image = HdrImage::parse_radiance(RLE, precision).unwrap();
+            assert_eq!((image.width, image.height), (8, 2));
+            assert_eq!(image.data().len(), 16 * precision.pixel_size());
+            assert_eq!(image.pixel(0, 0), Some([1.0, 0.5, 0.25, 1.0]));
+            assert_eq!(image.pixel(3, 0), Some([0.0, 0.0, 0.0, 1.0]));
+            assert_eq!(image.pixel(7, 0), Some([3.0, 0.0, 0.0, 1.0]));
+            assert_eq!(image.pixel(5, 1), Some([0.5, 0.5, 0.5, 1.0]));
+            assert_eq!(image.pixel(8, 0), None);
+        }
+    }
+
+    #[test]
+    fn radiance_flat() {
+        let image = HdrImage::parse_radiance(FLAT, HdrPrecision::Full).unwrap();
+        assert_eq!(image.format(), hal::format::Format::Rgba32Sfloat);
+        assert_eq!(image.pixel(0, 0), Some([1.0, 0.5, 0.25, 1.0]));
+        assert_eq!(image.pixel(0, 1), Some([0.5, 0.5, 0.5, 1.0]));
+        assert_eq!(image.pixel(1, 1), Some([255.0, 0.0, 0.0, 1.0]));
+    }
+
+    #[test]
+    fn exr_half_and_float_channels() {
+        for &precision in &[HdrPrecision::Half, HdrPrecision::Full] {
+            let image = HdrImage::parse_exr(EXR, precision).unwrap();
+            assert_eq!(image.format(), precision.format());
+            assert_eq!((image.width, image.height), (2, 2));
+            assert_eq!(image.pixel(0, 0), Some([2.0, 0.25, -1.0, 0.5]));
+            assert_eq!(image.pixel(0, 1), Some([1.0, 1.0, 1.0, 1.0]));
+            assert_eq!(image.pixel(1, 1), Some([65504.0, 0.0009765625, 3.5, 0.75]));
+        }
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert_eq!(
+            HdrImage::parse_radiance(&RLE[..RLE.len() - 3], HdrPrecision::Half),
+            Err(TextureError::InvalidHdr("truncated pixel data"))
+        );
+        let flipped = String::from_utf8_lossy(FLAT).replace("-Y 2 +X 2", "+Y 2 +X 2");
+        assert_eq!(
+            HdrImage::parse_radiance(flipped.as_bytes(), HdrPrecision::Half),
+            Err(TextureError::UnsupportedHdr("+Y 2 +X 2".into()))
+        );
+        assert_eq!(
+            HdrImage::parse_exr(&EXR[..EXR.len() - 1], HdrPrecision::Half),
+            Err(TextureError::InvalidExr("unexpected end of file"))
+        );
+        assert_eq!(
+            HdrImage::parse_exr(FLAT, HdrPrecision::Half),
+            Err(TextureError::InvalidExr("missing OpenEXR header"))
+        );
+    }
+
+    #[test]
+    fn half_float_conversion() {
+        for &value in &[
+            0.0,
+            -0.0,
+            1.0,
+            -2.5,
+            65504.0,
+            6.1035156e-5,
+            5.9604645e-8,
+            0.1,
+        ] {
+            let half = f32_to_f16(value);
+            assert_eq!(f32_to_f16(f16_to_f32(half)), half);
+        }
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(5.9604645e-8), 0x0001);
+        assert_eq!(f16_to_f32(0x0001), 5.9604645e-8);
+        assert_eq!(f16_to_f32(0x3555), 0.33325195);
+    }
+}
@@ -0,0 +1,185 @@
+//! Loading of indexed PNG files as their raw palette indices, without expanding them to RGBA.
+use crate::{error::TextureError, formats::channels::PNG_SIGNATURE, types::TextureData};
+use amethyst_error::Error;
+use palette::Srgba;
+use png::HasParameters;
+use rendy::{hal::format::Format, texture::image::ImageTextureConfig};
+
+/// Palette indices of an indexed image, one byte per pixel.
+#[derive(Debug, Clone, PartialEq)]
+struct IndexedImage {
+    width: u32,
+    height: u32,
+    indices: Vec<u8>,
+    palette: Vec<Srgba<u8>>,
+}
+
+/// Texture data of the palette indices of an indexed PNG file, as `R8Unorm` pixels keeping the
+/// palette. The color space, alpha premultiplication and mips of `config` don't apply to
+/// indices and are ignored.
+pub(crate) fn load_indexed(
+    bytes: &[u8],
+    config: &ImageTextureConfig,
+) -> Result<TextureData, Error> {
+    let image = decode_indexed(bytes)?;
+    let data = TextureData::from_pixels(
+        image.width,
+        image.height,
+        Format::R8Unorm,
+        image.indices,
+        config.sampler_info.clone(),
+    )?;
+    Ok(data.with_palette(image.palette))
+}
+
+fn decode_indexed(bytes: &[u8]) -> Result<IndexedImage, Error> {
+    if !bytes.starts_with(&PNG_SIGNATURE) {
+        return Err(TextureError::NotIndexed.into());
+    }
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set(png::Transformations::IDENTITY);
+    let (info, mut reader) = decoder.read_info()?;
+    if info.color_type != png::ColorType::Indexed {
+        return Err(TextureError::NotIndexed.into());
+    }
+    let mut data = vec![0; info.buffer_size()];
+    reader.next_frame(&mut data)?;
+    let indices = unpack(
+        &data,
+        (info.width, info.height),
+        info.line_size,
+        info.bit_depth as u8,
+    );
+
+    let png_info = reader.info();
+    let colors = png_info.palette.as_ref().ok_or(TextureError::NotIndexed)?;
+    let alpha = png_info.trns.as_ref().map_or(&[][..], |trns| &trns[..]);
+    let palette = colors
+        .chunks_exact(3)
+        .enumerate()
+        .map(|(i, rgb)| Srgba::new(rgb[0], rgb[1], rgb[2], alpha.get(i).cloned().unwrap_or(255)))
+        .collect();
+
+    Ok(IndexedImage {
+        width: info.width,
+        height: info.height,
+        indices,
+        palette,
+    })
+}
+
+/// Unpack rows of `line_size` bytes with `bits` per index to one byte per index.
+fn unpack(data: &[u8], (width, height): (u32, u32), line_size: usize, bits: u8) -> Vec<u8> {
+    let bits = usize::from(bits);
+    let per_byte = 8 / bits;
+    let mask = ((1u16 << bits) - 1) as u8;
+    let mut indices = Vec::with_capacity(width as usize * height as usize);
+    for row in data.chunks(line_size).take(height as usize) {
+        for x in 0..width as usize {
+            let shift = 8 - bits * (x % per_byte + 1);
+            indices.push((row[x / per_byte] >> shift) & mask);
+        }
+    }
+    indices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{png::PNGEncoder, ColorType};
+
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in bytes {
+            crc ^= u32::from(byte);
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    /// A 2 bit indexed PNG, with uncompressed image data.
+    fn indexed_png(width: u32, rows: &[&[u8]], palette: &[u8], trns: &[u8]) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        let mut header = Vec::new();
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&(rows.len() as u32).to_be_bytes());
+        header.extend_from_slice(&[2, 3, 0, 0, 0]);
+        chunk(&mut png, b"IHDR", &header);
+        chunk(&mut png, b"PLTE", palette);
+        chunk(&mut png, b"tRNS", trns);
+
+        // Every row starts with filter type 0, the data is a single stored deflate block
+        let raw: Vec<u8> = rows
+            .iter()
+            .flat_map(|row| std::iter::once(0).chain(row.iter().cloned()))
+            .collect();
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in &raw {
+            a = (a + u32::from(byte)) % 65521;
+            b = (b + a) % 65521;
+        }
+        let len = raw.len() as u16;
+        let mut zlib = vec![0x78, 0x01, 0x01];
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(&raw);
+        zlib.extend_from_slice(&((b << 16) | a).to_be_bytes());
+        chunk(&mut png, b"IDAT", &zlib);
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    #[test]
+    fn unpack_indices() {
+        assert_eq!(
+            unpack(&[0b1011_0001], (8, 1), 1, 1),
+            vec![1, 0, 1, 1, 0, 0, 0, 1]
+        );
+        assert_eq!(
+            unpack(&[0x1F, 0xA0, 0xCD, 0xE0], (3, 2), 2, 4),
+            vec![1, 15, 10, 12, 13, 14]
+        );
+        assert_eq!(unpack(&[7, 9, 3, 4], (2, 2), 2, 8), vec![7, 9, 3, 4]);
+    }
+
+    #[test]
+    fn indexed_png_keeps_indices_and_palette() {
+        let palette = [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+        // Indices 0 1 2 and 3 2 1, four per byte
+        let png = indexed_png(3, &[&[0b0001_1000], &[0b1110_0100]], &palette, &[0, 128]);
+        let image = decode_indexed(&png).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+        assert_eq!(image.indices, vec![0, 1, 2, 3, 2, 1]);
+        assert_eq!(
+            image.palette,
+            vec![
+                Srgba::new(255, 0, 0, 0),
+                Srgba::new(0, 255, 0, 128),
+                Srgba::new(0, 0, 255, 255),
+                Srgba::new(255, 255, 255, 255),
+            ]
+        );
+
+        let mut gray = Vec::new();
+        PNGEncoder::new(&mut gray)
+            .encode(&[0, 255], 2, 1, ColorType::Gray(8))
+            .unwrap();
+        assert!(decode_indexed(&gray).is_err());
+        assert!(decode_indexed(b"GIF89a").is_err());
+    }
+}
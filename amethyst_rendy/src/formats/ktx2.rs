@@ -0,0 +1,430 @@
+//! Khronos KTX2 texture format.
+//!
+//! 2D textures, texture arrays and cubemaps with any number of mip levels are supported, in the
+//! uncompressed and BCn formats also supported by `DdsFormat`.
+//!
+//! Basis Universal ETC1S data, supercompressed with BasisLZ, is transcoded to RGBA8 pixels when
+//! the file is loaded, on the loader thread. Basis Universal UASTC data is rejected with a
+//! `TextureError::UnsupportedKtx2`, as well as Zstandard and zlib supercompressed files.
+//! Transcode them offline to a BCn or RGBA8 KTX2 file without supercompression, e.g. with
+//! `ktx transcode` of KTX-Software.
+use crate::{
+    error::TextureError,
+    formats::{
+        basis::BasisLz,
+        dds::{block_layout, layered_texture_data, read_u32, MAX_SIZE},
+        texture::ImageFormat,
+    },
+    types::TextureData,
+};
+use amethyst_assets::Format;
+use amethyst_error::Error;
+use rendy::hal::{self, image::SamplerInfo};
+use serde::{Deserialize, Serialize};
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+const SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+const SUPERCOMPRESSION_ZSTD: u32 = 2;
+const SUPERCOMPRESSION_ZLIB: u32 = 3;
+const KHR_DF_MODEL_ETC1S: u8 = 163;
+const KHR_DF_MODEL_UASTC: u8 = 166;
+const KHR_DF_TRANSFER_SRGB: u8 = 2;
+
+/// Formats that differ only by their sRGB encoding, linear first.
+const SRGB_PAIRS: [(hal::format::Format, hal::format::Format); 6] = {
+    use hal::format::Format::*;
+    [
+        (Rgba8Unorm, Rgba8Srgb),
+        (Bgra8Unorm, Bgra8Srgb),
+        (Bc1RgbaUnorm, Bc1RgbaSrgb),
+        (Bc2Unorm, Bc2Srgb),
+        (Bc3Unorm, Bc3Srgb),
+        (Bc7Unorm, Bc7Srgb),
+    ]
+};
+
+/// Pixel data decoded from a KTX2 file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ktx2Image {
+    /// Width of the largest mip level in pixels.
+    pub width: u32,
+    /// Height of the largest mip level in pixels.
+    pub height: u32,
+    /// Format of the pixel data.
+    pub format: hal::format::Format,
+    /// Number of array layers, six per cubemap.
+    pub layers: u32,
+    /// Number of mip levels of every layer.
+    pub mip_levels: u32,
+    /// Whether the layers are the faces of one or more cubemaps.
+    pub cubemap: bool,
+    levels: Vec<Vec<u8>>,
+}
+
+impl Ktx2Image {
+    /// Decode a KTX2 file.
+    ///
+    /// Whether the colors are sRGB encoded is read from the data format descriptor, unless `srgb`
+    /// overrides it.
+    pub fn parse(bytes: &[u8], srgb: Option<bool>) -> Result<Self, TextureError> {
+        if bytes.len() < HEADER_SIZE || bytes[..IDENTIFIER.len()] != IDENTIFIER {
+            return Err(TextureError::InvalidKtx2("missing KTX2 header"));
+        }
+        let word = |offset: usize| read_u32(bytes, offset);
+
+        let (width, height, depth) = (word(20), word(24), word(28));
+        if width == 0 {
+            return Err(TextureError::InvalidKtx2("texture has no pixels"));
+        }
+        if depth != 0 {
+            return Err(TextureError::UnsupportedKtx2("3D texture".into()));
+        }
+        let height = height.max(1);
+        if width.max(height) > MAX_SIZE {
+            return Err(TextureError::UnsupportedKtx2(format!(
+                "{}x{} texture",
+                width, height
+            )));
+        }
+        let faces = word(36);
+        if faces != 1 && faces != 6 {
+            return Err(TextureError::InvalidKtx2("face count is neither 1 nor 6"));
+        }
+        let layers = word(32)
+            .max(1)
+            .checked_mul(faces)
+            .filter(|&layers| layers <= u32::from(std::u16::MAX))
+            .ok_or(TextureError::InvalidKtx2("too many array layers"))?;
+        let mip_levels = word(40).max(1);
+        if mip_levels > 32 - width.max(height).leading_zeros() {
+            return Err(TextureError::InvalidKtx2(
+                "more mip levels than the texture size allows",
+            ));
+        }
+
+        let (dfd_offset, dfd_length) = (word(48) as usize, word(52) as usize);
+        let dfd = bytes
+            .get(dfd_offset..dfd_offset + dfd_length)
+            .filter(|dfd| dfd.len() >= 16)
+            .ok_or(TextureError::InvalidKtx2("missing data format descriptor"))?;
+        // The basic descriptor block follows the total size of the descriptor
+        let (color_model, transfer) = (dfd[12], dfd[14]);
+
+        let scheme = word(44);
+        let etc1s = scheme == SUPERCOMPRESSION_BASIS_LZ;
+        if etc1s != (color_model == KHR_DF_MODEL_ETC1S) {
+            return Err(TextureError::InvalidKtx2(
+                "ETC1S data must be BasisLZ supercompressed",
+            ));
+        }
+        if color_model == KHR_DF_MODEL_UASTC {
+            return Err(TextureError::UnsupportedKtx2(
+                "Basis Universal UASTC data, transcode it offline to a BCn or RGBA8 format".into(),
+            ));
+        }
+        match scheme {
+            0 | SUPERCOMPRESSION_BASIS_LZ => {}
+            SUPERCOMPRESSION_ZSTD => {
+                return Err(TextureError::UnsupportedKtx2(
+                    "Zstandard supercompression".into(),
+                ))
+            }
+            SUPERCOMPRESSION_ZLIB => {
+                return Err(TextureError::UnsupportedKtx2(
+                    "zlib supercompression".into(),
+                ))
+            }
+            _ => {
+                return Err(TextureError::UnsupportedKtx2(format!(
+                    "supercompression scheme {}",
+                    scheme
+                )))
+            }
+        }
+
+        let vk_format = word(12);
+        let format = if etc1s {
+            hal::format::Format::Rgba8Unorm
+        } else {
+            vk_format_to_format(vk_format)
+                .ok_or_else(|| TextureError::UnsupportedKtx2(format!("vkFormat {}", vk_format)))?
+        };
+        let format = with_srgb(format, srgb.unwrap_or(transfer == KHR_DF_TRANSFER_SRGB));
+        let basis = if etc1s {
+            let (offset, length) = (read_u64(bytes, 64) as usize, read_u64(bytes, 72) as usize);
+            let global = offset
+                .checked_add(length)
+                .and_then(|end| bytes.get(offset..end))
+                .ok_or(TextureError::InvalidKtx2("BasisLZ data is truncated"))?;
+            Some(BasisLz::parse(global, (mip_levels * layers) as usize)?)
+        } else {
+            None
+        };
+
+        let mut image = Ktx2Image {
+            width,
+            height,
+            format,
+            layers,
+            mip_levels,
+            cubemap: faces == 6,
+            levels: Vec::with_capacity(mip_levels as usize),
+        };
+        for mip in 0..mip_levels {
+            let entry = HEADER_SIZE + mip as usize * LEVEL_INDEX_ENTRY_SIZE;
+            let index = bytes
+                .get(entry..entry + LEVEL_INDEX_ENTRY_SIZE)
+                .ok_or(TextureError::InvalidKtx2("truncated level index"))?;
+            let offset = read_u64(index, 0) as usize;
+            let size = image.level_size(mip) * layers as usize;
+            let level = match basis {
+                Some(ref basis) => {
+                    let length = read_u64(index, 8) as usize;
+                    let data = offset
+                        .checked_add(length)
+                        .and_then(|end| bytes.get(offset..end))
+                        .ok_or(TextureError::InvalidKtx2("pixel data is truncated"))?;
+                    let mut level = Vec::with_capacity(size);
+                    for layer in 0..layers {
+                        let image_index = (mip * layers + layer) as usize;
+                        level.extend(basis.decode(image_index, data, image.level_extent(mip))?);
+                    }
+                    level
+                }
+                None => {
+                    if read_u64(index, 8) < size as u64 {
+                        return Err(TextureError::InvalidKtx2("mip level is too small"));
+                    }
+                    let level = bytes
+                        .get(offset..)
+                        .filter(|level| level.len() >= size)
+                        .ok_or(TextureError::InvalidKtx2("pixel data is truncated"))?;
+                    level[..size].to_vec()
+                }
+            };
+            image.levels.push(level);
+        }
+        Ok(image)
+    }
+
+    /// Size in pixels of a mip level.
+    pub fn level_extent(&self, mip: u32) -> (u32, u32) {
+        ((self.width >> mip).max(1), (self.height >> mip).max(1))
+    }
+
+    /// Pixel data of a mip level of a layer, `None` if either is out of range.
+    ///
+    /// Cubemap faces are ordered +X, -X, +Y, -Y, +Z, -Z.
+    pub fn level(&self, layer: u32, mip: u32) -> Option<&[u8]> {
+        if layer >= self.layers || mip >= self.mip_levels {
+            return None;
+        }
+        let size = self.level_size(mip);
+        let start = layer as usize * size;
+        Some(&self.levels[mip as usize][start..start + size])
+    }
+
+    fn level_size(&self, mip: u32) -> usize {
+        let (block_width, block_height, block_size) = block_layout(self.format);
+        let (width, height) = self.level_extent(mip);
+        let blocks_x = (width + block_width - 1) / block_width;
+        let blocks_y = (height + block_height - 1) / block_height;
+        blocks_x as usize * blocks_y as usize * block_size
+    }
+}
+
+/// Loads KTX2 files as textures, see `Ktx2Image` and the module documentation for what is
+/// supported.
+///
+/// Every mip level of every layer is uploaded. Textures in block compressed formats the device
+/// can't sample fail to load with `TextureError::UnsupportedFormat`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ktx2Format {
+    /// Overrides whether the file is sRGB encoded, instead of reading it from the file.
+    pub srgb: Option<bool>,
+    /// Sampler of the texture.
+    pub sampler_info: SamplerInfo,
+}
+
+impl Default for Ktx2Format {
+    fn default() -> Self {
+        Ktx2Format {
+            srgb: None,
+            sampler_info: ImageFormat::default().0.sampler_info,
+        }
+    }
+}
+
+impl Ktx2Format {
+    /// Create the texture data of a decoded KTX2 file.
+    pub fn texture_data(&self, image: &Ktx2Image) -> TextureData {
+        layered_texture_data(
+            (image.width, image.height),
+            image.layers,
+            image.cubemap,
+            image.format,
+            image.levels[0].clone(),
+            self.sampler_info.clone(),
+        )
+        .with_mip_chain(image.levels[1..].to_vec())
+    }
+}
+
+amethyst_assets::register_format!("KTX2", Ktx2Format as TextureData);
+impl Format<TextureData> for Ktx2Format {
+    fn name(&self) -> &'static str {
+        "KTX2"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<TextureData, Error> {
+        let image = Ktx2Image::parse(&bytes, self.srgb)?;
+        Ok(self.texture_data(&image))
+    }
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from(read_u32(bytes, offset)) | u64::from(read_u32(bytes, offset + 4)) << 32
+}
+
+fn with_srgb(format: hal::format::Format, srgb: bool) -> hal::format::Format {
+    SRGB_PAIRS
+        .iter()
+        .find(|&&(linear, encoded)| format == linear || format == encoded)
+        .map_or(
+            format,
+            |&(linear, encoded)| if srgb { encoded } else { linear },
+        )
+}
+
+fn vk_format_to_format(vk_format: u32) -> Option<hal::format::Format> {
+    use hal::format::Format::*;
+
+    Some(match vk_format {
+        37 => Rgba8Unorm,
+        43 => Rgba8Srgb,
+        44 => Bgra8Unorm,
+        50 => Bgra8Srgb,
+        97 => Rgba16Sfloat,
+        109 => Rgba32Sfloat,
+        133 => Bc1RgbaUnorm,
+        134 => Bc1RgbaSrgb,
+        135 => Bc2Unorm,
+        136 => Bc2Srgb,
+        137 => Bc3Unorm,
+        138 => Bc3Srgb,
+        139 => Bc4Unorm,
+        141 => Bc5Unorm,
+        143 => Bc6hUfloat,
+        145 => Bc7Unorm,
+        146 => Bc7Srgb,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hal::format::Format as HalFormat;
+
+    const RGBA8: &[u8] = include_bytes!("../../tests/fixtures/ktx2/rgba8_srgb_mips.ktx2");
+    const BC1: &[u8] = include_bytes!("../../tests/fixtures/ktx2/bc1_linear.ktx2");
+    const CUBE: &[u8] = include_bytes!("../../tests/fixtures/ktx2/rgba8_cube.ktx2");
+    const BASIS: &[u8] = include_bytes!("../../tests/fixtures/ktx2/basis_etc1s.ktx2");
+
+    #[test]
+    fn rgba8_mip_chain() {
+        let image = Ktx2Image::parse(RGBA8, None).unwrap();
+        assert_eq!(image.format, HalFormat::Rgba8Srgb);
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!((image.layers, image.mip_levels), (1, 2));
+        assert_eq!(image.level(0, 0).unwrap()[..4], [255, 0, 0, 255]);
+        assert_eq!(image.level(0, 1), Some(&[10, 20, 30, 40][..]));
+        assert_eq!(image.level(0, 2), None);
+
+        let linear = Ktx2Image::parse(RGBA8, Some(false)).unwrap();
+        assert_eq!(linear.format, HalFormat::Rgba8Unorm);
+    }
+
+    #[test]
+    fn bc1_transfer_function() {
+        let image = Ktx2Image::parse(BC1, None).unwrap();
+        assert_eq!(image.format, HalFormat::Bc1RgbaUnorm);
+        assert_eq!(image.level(0, 0), Some(&[0, 1, 2, 3, 4, 5, 6, 7][..]));
+
+        let data = Ktx2Format {
+            srgb: Some(true),
+            ..Default::default()
+        }
+        .import_simple(BC1.to_vec())
+        .unwrap();
+        assert_eq!(data.1.required_format, Some(HalFormat::Bc1RgbaSrgb));
+    }
+
+    #[test]
+    fn cubemap_faces() {
+        let image = Ktx2Image::parse(CUBE, None).unwrap();
+        assert!(image.cubemap);
+        assert_eq!(image.layers, 6);
+        for face in 0..6 {
+            assert_eq!(image.level(face, 0), Some(&[face as u8, 0, 0, 255][..]));
+        }
+    }
+
+    #[test]
+    fn rgba8_mip_levels_are_uploaded() {
+        let data = Ktx2Format::default().import_simple(RGBA8.to_vec()).unwrap();
+        assert_eq!(data.mip_levels(), 2);
+        assert_eq!(data.1.mip_chain, vec![vec![10, 20, 30, 40]]);
+        assert_eq!(data.1.byte_size, Some(20));
+    }
+
+    #[test]
+    fn basis_etc1s_is_transcoded() {
+        let image = Ktx2Image::parse(BASIS, None).unwrap();
+        assert_eq!(image.format, HalFormat::Rgba8Srgb);
+        assert_eq!((image.width, image.height), (8, 4));
+        assert_eq!((image.layers, image.mip_levels), (1, 1));
+
+        // The left block has a red endpoint and a selector per column, the right block a blue
+        // endpoint of the largest intensity and the lowest selector everywhere.
+        let pixels = image.level(0, 0).unwrap();
+        for row in pixels.chunks(8 * 4) {
+            assert_eq!(
+                row[..16],
+                [247, 0, 0, 255, 253, 0, 0, 255, 255, 2, 2, 255, 255, 8, 8, 255]
+            );
+            for pixel in row[16..].chunks(4) {
+                assert_eq!(pixel, [0, 0, 72, 255]);
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_files() {
+        let mut uastc = BASIS.to_vec();
+        uastc[44] = 0;
+        uastc[0x74] = KHR_DF_MODEL_UASTC;
+        match Ktx2Image::parse(&uastc, None) {
+            Err(TextureError::UnsupportedKtx2(what)) => assert!(what.contains("UASTC")),
+            other => panic!("Unexpected result {:?}", other),
+        }
+        assert_eq!(
+            Ktx2Image::parse(&BASIS[..BASIS.len() - 1], None),
+            Err(TextureError::InvalidKtx2("pixel data is truncated"))
+        );
+        assert_eq!(
+            Ktx2Image::parse(&RGBA8[..RGBA8.len() - 1], None),
+            Err(TextureError::InvalidKtx2("pixel data is truncated"))
+        );
+        assert_eq!(
+            Ktx2Image::parse(&RGBA8[4..], None),
+            Err(TextureError::InvalidKtx2("missing KTX2 header"))
+        );
+    }
+}
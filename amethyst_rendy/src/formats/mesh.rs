@@ -2,18 +2,26 @@
 use crate::{
     shape::{FromShape, ShapePrefab},
     types::{Mesh, MeshData},
+    visibility::{MeshBoundingBoxes, MeshBoundingSpheres},
 };
 use amethyst_assets::{
     AssetPrefab, AssetStorage, Format, Handle, Loader, PrefabData, ProgressCounter,
 };
-use amethyst_core::ecs::{Entity, Read, ReadExpect, WriteStorage};
+use amethyst_core::ecs::{Entity, Read, ReadExpect, Write, WriteStorage};
 use amethyst_error::Error;
 use rendy::mesh::MeshBuilder;
 use serde::{Deserialize, Serialize};
 
 /// 'Obj' mesh format `Format` implementation.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
-pub struct ObjFormat;
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(default)]
+pub struct ObjFormat {
+    /// Generate tangents for normal mapping, see `MeshData::with_generated_tangents`. The file
+    /// must provide normals and texture coordinates.
+    pub generate_tangents: bool,
+}
 
 amethyst_assets::register_format_type!(MeshData);
 
@@ -24,7 +32,7 @@ impl Format<MeshData> for ObjFormat {
     }
 
     fn import_simple(&self, bytes: Vec<u8>) -> Result<MeshData, Error> {
-        rendy::mesh::obj::load_from_obj(&bytes)
+        let data: MeshData = rendy::mesh::obj::load_from_obj(&bytes)
             .map(|mut builders| {
                 let mut iter = builders.drain(..);
                 let builder = iter.next().unwrap();
@@ -33,7 +41,12 @@ impl Format<MeshData> for ObjFormat {
                 }
                 builder.0.into()
             })
-            .map_err(|e| e.compat().into())
+            .map_err(|e| e.compat())?;
+        if self.generate_tangents {
+            Ok(data.with_generated_tangents()?)
+        } else {
+            Ok(data)
+        }
     }
 }
 
@@ -59,9 +72,13 @@ where
     V: FromShape + Into<MeshBuilder<'static>>,
 {
     type SystemData = (
-        ReadExpect<'a, Loader>,
-        WriteStorage<'a, Handle<Mesh>>,
-        Read<'a, AssetStorage<Mesh>>,
+        (
+            ReadExpect<'a, Loader>,
+            WriteStorage<'a, Handle<Mesh>>,
+            Read<'a, AssetStorage<Mesh>>,
+        ),
+        Write<'a, MeshBoundingSpheres>,
+        Write<'a, MeshBoundingBoxes>,
     );
     type Result = ();
 
@@ -74,7 +91,7 @@ where
     ) -> Result<(), Error> {
         match self {
             MeshPrefab::Asset(m) => {
-                m.add_to_entity(entity, system_data, entities, children)?;
+                m.add_to_entity(entity, &mut system_data.0, entities, children)?;
             }
             MeshPrefab::Shape(s) => {
                 s.add_to_entity(entity, system_data, entities, children)?;
@@ -89,7 +106,7 @@ where
         system_data: &mut Self::SystemData,
     ) -> Result<bool, Error> {
         Ok(match self {
-            MeshPrefab::Asset(m) => m.load_sub_assets(progress, system_data)?,
+            MeshPrefab::Asset(m) => m.load_sub_assets(progress, &mut system_data.0)?,
             MeshPrefab::Shape(s) => s.load_sub_assets(progress, system_data)?,
         })
     }
@@ -1,4 +1,11 @@
 //! Pre-defined graphical formats and data provided by amethyst_rendy
+mod basis;
+pub mod channels;
+pub mod dds;
+pub mod flipbook;
+pub mod hdr;
+mod indexed;
+pub mod ktx2;
 pub mod mesh;
 pub mod mtl;
 pub mod texture;
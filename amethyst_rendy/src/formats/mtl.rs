@@ -1,4 +1,11 @@
 //! Material type implementation.
+//!
+//! `MaterialPrefab` is its own file format, written back by editors as they read it. The fields
+//! left out of a file, or set to `None`, inherit from the `MaterialDefaults` when the material is
+//! loaded instead of being baked into the file: texture slots are written as the image path or
+//! inline color they load, and scalars as plain floats. Every file has the `version` of the format
+//! it was written in, files without one being of the first version. New fields are added to a
+//! later version as optional, so files of earlier versions still load.
 
 use crate::{
     formats::texture::TexturePrefab,
@@ -9,44 +16,90 @@ use crate::{
 use amethyst_assets::{AssetStorage, Handle, Loader, PrefabData, ProgressCounter};
 use amethyst_core::ecs::prelude::{Entity, Read, ReadExpect, WriteStorage};
 use amethyst_error::Error;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Version of the format of `MaterialPrefab`, written with every prefab.
+pub const MATERIAL_PREFAB_VERSION: u32 = 1;
 
 /// `PrefabData` for loading `Material`s
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(default, bound = "")]
 pub struct MaterialPrefab {
+    /// Version of the format the prefab was written in, see `MATERIAL_PREFAB_VERSION`.
+    #[serde(deserialize_with = "deserialize_version")]
+    pub version: u32,
     /// Diffuse map.
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "inherit")]
     pub albedo: Option<TexturePrefab>,
     /// Emission map.
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "inherit")]
     pub emission: Option<TexturePrefab>,
     /// Normal map.
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "inherit")]
     pub normal: Option<TexturePrefab>,
     /// Metallic-roughness map. (B channel metallic, G channel roughness)
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "inherit")]
     pub metallic_roughness: Option<TexturePrefab>,
     /// Ambient occlusion map.
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "inherit")]
     pub ambient_occlusion: Option<TexturePrefab>,
     /// Cavity map.
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "inherit")]
     pub cavity: Option<TexturePrefab>,
     /// Texture offset.
-    pub uv_offset: TextureOffset,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "inherit")]
+    pub uv_offset: Option<TextureOffset>,
     /// Set material as `Transparent`
     pub transparent: bool,
     /// Alpha cutoff: the value below which we do not draw the pixel
-    pub alpha_cutoff: f32,
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "inherit")]
+    pub alpha_cutoff: Option<f32>,
+    /// Generate the full mip chain of the textures when they are loaded, defaults to `true`.
+    pub generate_mips: bool,
     /// Clone handle only
     #[serde(skip)]
     handle: Option<Handle<Material>>,
 }
 
+/// Write the value of a field set, as the field isn't written when it inherits its default.
+fn inherit<T: Serialize, S: Serializer>(value: &Option<T>, s: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => value.serialize(s),
+        None => s.serialize_none(),
+    }
+}
+
+/// Fail on versions this build can't read.
+fn deserialize_version<'de, D: Deserializer<'de>>(de: D) -> Result<u32, D::Error> {
+    let version = u32::deserialize(de)?;
+    if version == 0 || version > MATERIAL_PREFAB_VERSION {
+        Err(serde::de::Error::custom(format!(
+            "unsupported material prefab version {}, expected 1 to {}",
+            version, MATERIAL_PREFAB_VERSION
+        )))
+    } else {
+        Ok(version)
+    }
+}
+
 impl MaterialPrefab {
     /// Clone the loaded material prefab to a new instance with an independent transparency value.
     pub fn clone_loaded(&self) -> Self {
         assert!(self.handle.is_some());
 
         Self {
-            handle: self.handle.clone(),
+            version: self.version,
+            albedo: self.albedo.clone(),
+            emission: self.emission.clone(),
+            normal: self.normal.clone(),
+            metallic_roughness: self.metallic_roughness.clone(),
+            ambient_occlusion: self.ambient_occlusion.clone(),
+            cavity: self.cavity.clone(),
+            uv_offset: self.uv_offset.clone(),
             transparent: self.transparent,
-            ..Self::default()
+            alpha_cutoff: self.alpha_cutoff,
+            generate_mips: self.generate_mips,
+            handle: self.handle.clone(),
         }
     }
 }
@@ -54,15 +107,17 @@ impl MaterialPrefab {
 impl Default for MaterialPrefab {
     fn default() -> Self {
         MaterialPrefab {
+            version: MATERIAL_PREFAB_VERSION,
             albedo: None,
             emission: None,
             normal: None,
             metallic_roughness: None,
             ambient_occlusion: None,
             cavity: None,
-            uv_offset: TextureOffset::default(),
+            uv_offset: None,
             transparent: false,
-            alpha_cutoff: std::f32::MIN_POSITIVE,
+            alpha_cutoff: None,
+            generate_mips: true,
             handle: None,
         }
     }
@@ -78,6 +133,21 @@ fn load_handle(prefab: &Option<TexturePrefab>, def: &Handle<Texture>) -> Handle<
         .unwrap_or_else(|| def.clone())
 }
 
+/// The texture `prefab` loads, as the slot of a map storing colors when `srgb` is set.
+fn loading_texture(
+    prefab: &Option<TexturePrefab>,
+    srgb: bool,
+    generate_mips: bool,
+) -> Option<TexturePrefab> {
+    prefab.clone().map(|mut texture| {
+        texture.guess_srgb(srgb);
+        if generate_mips {
+            texture.generate_mips();
+        }
+        texture
+    })
+}
+
 impl<'a> PrefabData<'a> for MaterialPrefab {
     type SystemData = (
         WriteStorage<'a, Handle<Material>>,
@@ -110,61 +180,82 @@ impl<'a> PrefabData<'a> for MaterialPrefab {
         system_data: &mut Self::SystemData,
     ) -> Result<bool, Error> {
         let &mut (_, _, ref mat_default, ref mut tp_data, ref loader, ref storage) = system_data;
-        let mut ret = false;
-        if let Some(ref mut texture) = self.albedo {
-            if texture.load_sub_assets(progress, tp_data)? {
-                ret = true;
-            }
-        }
-        if let Some(ref mut texture) = self.emission {
-            if texture.load_sub_assets(progress, tp_data)? {
-                ret = true;
-            }
-        }
-        if let Some(ref mut texture) = self.normal {
-            if texture.load_sub_assets(progress, tp_data)? {
-                ret = true;
-            }
-        }
-        if let Some(ref mut texture) = self.metallic_roughness {
-            if texture.load_sub_assets(progress, tp_data)? {
-                ret = true;
-            }
-        }
-        if let Some(ref mut texture) = self.ambient_occlusion {
-            if texture.load_sub_assets(progress, tp_data)? {
-                ret = true;
-            }
-        }
-        if let Some(ref mut texture) = self.cavity {
-            if texture.load_sub_assets(progress, tp_data)? {
-                ret = true;
-            }
+        if self.handle.is_some() {
+            return Ok(false);
         }
 
-        if self.handle.is_none() {
-            let mtl = Material {
-                albedo: load_handle(&self.albedo, &mat_default.0.albedo),
-                emission: load_handle(&self.emission, &mat_default.0.emission),
-                normal: load_handle(&self.normal, &mat_default.0.normal),
-                metallic_roughness: load_handle(
-                    &self.metallic_roughness,
-                    &mat_default.0.metallic_roughness,
-                ),
-                ambient_occlusion: load_handle(
-                    &self.ambient_occlusion,
-                    &mat_default.0.ambient_occlusion,
-                ),
-                cavity: load_handle(&self.cavity, &mat_default.0.cavity),
-                uv_offset: self.uv_offset.clone(),
-                alpha_cutoff: self.alpha_cutoff,
-            };
-
-            self.handle
-                .replace(loader.load_from_data(mtl, progress, storage));
-            ret = true;
+        // The textures are loaded from copies of the slots, which keep the form they're written
+        // in. Only the albedo and emission maps store colors, the other maps are linear data
+        let mut textures = [
+            loading_texture(&self.albedo, true, self.generate_mips),
+            loading_texture(&self.emission, true, self.generate_mips),
+            loading_texture(&self.normal, false, self.generate_mips),
+            loading_texture(&self.metallic_roughness, false, self.generate_mips),
+            loading_texture(&self.ambient_occlusion, false, self.generate_mips),
+            loading_texture(&self.cavity, false, self.generate_mips),
+        ];
+        for texture in textures.iter_mut().filter_map(Option::as_mut) {
+            texture.load_sub_assets(progress, tp_data)?;
         }
 
-        Ok(ret)
+        let [albedo, emission, normal, metallic_roughness, ambient_occlusion, cavity] = &textures;
+        let defaults = &mat_default.0;
+        let mtl = Material {
+            albedo: load_handle(albedo, &defaults.albedo),
+            emission: load_handle(emission, &defaults.emission),
+            normal: load_handle(normal, &defaults.normal),
+            metallic_roughness: load_handle(metallic_roughness, &defaults.metallic_roughness),
+            ambient_occlusion: load_handle(ambient_occlusion, &defaults.ambient_occlusion),
+            cavity: load_handle(cavity, &defaults.cavity),
+            uv_offset: self
+                .uv_offset
+                .clone()
+                .unwrap_or_else(|| defaults.uv_offset.clone()),
+            alpha_cutoff: self.alpha_cutoff.unwrap_or(defaults.alpha_cutoff),
+        };
+
+        self.handle
+            .replace(loader.load_from_data(mtl, progress, storage));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PREFAB: &str = r#"(
+    albedo: Image("textures/crate.png", (srgb: None)),
+    emission: Generate(Srgba(1.0, 0.5, 0.0, 1.0)),
+    alpha_cutoff: 0.5,
+)"#;
+
+    #[test]
+    fn prefab_round_trips_with_inherited_fields() {
+        let prefab: MaterialPrefab = ron::de::from_str(PREFAB).unwrap();
+        assert_eq!(prefab.version, MATERIAL_PREFAB_VERSION);
+        assert_eq!(prefab.alpha_cutoff, Some(0.5));
+        assert!(prefab.normal.is_none());
+        assert!(prefab.uv_offset.is_none());
+
+        let ser = ron::ser::to_string(&prefab).unwrap();
+        assert!(!ser.contains("Some"));
+        assert!(!ser.contains("normal"));
+        assert!(!ser.contains("uv_offset"));
+        let de: MaterialPrefab = ron::de::from_str(&ser).unwrap();
+        assert_eq!(de.alpha_cutoff, Some(0.5));
+        assert_eq!(ron::ser::to_string(&de).unwrap(), ser);
+
+        // Slots aren't changed by how their textures are loaded.
+        let texture = loading_texture(&prefab.albedo, true, true).unwrap();
+        assert!(format!("{:?}", texture).contains("GenerateMips"));
+        assert!(format!("{:?}", prefab.albedo).contains("srgb: None"));
+    }
+
+    #[test]
+    fn later_versions_fail_to_deserialize() {
+        assert!(ron::de::from_str::<MaterialPrefab>("(version: 1)").is_ok());
+        assert!(ron::de::from_str::<MaterialPrefab>("(version: 2)").is_err());
+        assert!(ron::de::from_str::<MaterialPrefab>("(version: 0)").is_err());
     }
 }
@@ -1,22 +1,36 @@
 //! Texture formats implementation.
-use crate::types::{Texture, TextureData};
-use amethyst_assets::{AssetStorage, Format, Handle, Loader, PrefabData, ProgressCounter};
+use crate::{
+    error::TextureError,
+    formats::{
+        channels::{load_channels, rgba8_byte_size, ImageChannels},
+        dds::{block_layout, layered_texture_data},
+        hdr::{f16_to_f32, f32_to_f16, HdrImage, HdrPrecision},
+    },
+    shape::Heightmap,
+    types::{Texture, TextureData},
+};
+use amethyst_assets::{
+    AssetStorage, Format, FormatValue, Handle, Loader, PrefabData, ProgressCounter, Reload,
+    SingleFile, Source,
+};
 use amethyst_core::ecs::{Entity, Read, ReadExpect};
 use amethyst_error::Error;
 use rendy::{
     hal::{
         self,
-        image::{Filter, Kind, Size, ViewKind},
+        image::{Anisotropic, Filter, Kind, PackedColor, SamplerInfo, Size, ViewKind, WrapMode},
     },
     texture::{
-        image::{load_from_image, ImageTextureConfig},
+        image::{load_from_image, ImageTextureConfig, Repr},
         pixel::{AsPixel, Rgba8Srgb},
         TextureBuilder,
     },
 };
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
-/// Image format description newtype wrapper for `ImageTextureConfig` from rendy.
+/// Image format description newtype wrapper for `ImageTextureConfig` from rendy, with the
+/// `ImageChannels` of the loaded textures.
 ///
 /// # Example Usage
 /// ```ignore
@@ -45,40 +59,191 @@ use serde::{Deserialize, Serialize};
 ///        })
 ///        .with_raw_data(handle.pixels, Format::Rgba8Unorm);
 ///
-///    let tex: Handle<Texture> = loader.load_from_data(TextureData(texture_builder), (), &texture_storage);
+///    let tex: Handle<Texture> = loader.load_from_data(texture_builder.into(), (), &texture_storage);
 /// ```
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(transparent)]
-pub struct ImageFormat(pub ImageTextureConfig);
+pub struct ImageFormat(pub ImageTextureConfig, #[serde(skip)] pub ImageChannels);
 
 impl Default for ImageFormat {
     fn default() -> Self {
-        use rendy::{
-            hal::image::{Anisotropic, PackedColor, SamplerInfo, WrapMode},
-            texture::image::{Repr, TextureKind},
+        use rendy::texture::image::TextureKind;
+
+        ImageFormat(
+            ImageTextureConfig {
+                format: None,
+                repr: Repr::Srgb,
+                kind: TextureKind::D2,
+                sampler_info: TextureSampler::default().into(),
+                generate_mips: false,
+                premultiply_alpha: true,
+            },
+            ImageChannels::default(),
+        )
+    }
+}
+
+impl ImageFormat {
+    /// Format for textures seen at a distance, like those of 3D materials: the full mip chain is
+    /// generated when the texture is loaded, and sampled with trilinear filtering.
+    pub fn mipmapped() -> Self {
+        let mut format = ImageFormat::default().with_sampler(TextureSampler::trilinear());
+        format.0.generate_mips = true;
+        format
+    }
+
+    /// Format for pixel art: nearest filtering and clamped edges, without mips.
+    pub fn pixel_art() -> Self {
+        ImageFormat::default().with_sampler(TextureSampler::pixel_art())
+    }
+
+    /// Sample the loaded textures with `sampler`.
+    pub fn with_sampler(mut self, sampler: impl Into<SamplerInfo>) -> Self {
+        self.0.sampler_info = sampler.into();
+        self
+    }
+
+    /// Decode the colors of the images as sRGB encoded, or as linear values if `srgb` is
+    /// `false`, as required for normal maps and other textures that don't store colors.
+    pub fn with_srgb(mut self, srgb: bool) -> Self {
+        self.0.repr = if srgb { Repr::Srgb } else { Repr::Unorm };
+        self
+    }
+
+    /// Multiply the colors of the images by their alpha when they are decoded, which is the
+    /// default. Sprite sheets of images loaded with `premultiply` disabled must use
+    /// `AlphaMode::Straight`.
+    pub fn with_premultiplied_alpha(mut self, premultiply: bool) -> Self {
+        self.0.premultiply_alpha = premultiply;
+        self
+    }
+
+    /// Load textures with `channels`, see `ImageChannels`.
+    pub fn with_channels(mut self, channels: ImageChannels) -> Self {
+        self.1 = channels;
+        self
+    }
+
+    /// Load indexed PNG files as `R8Unorm` textures of their raw palette indices instead of
+    /// expanding them to colors, for palette swaps and other retro effects. Sample the index of
+    /// a pixel as `texture(indices, uv).r * 255.0` with nearest filtering.
+    ///
+    /// The palette is kept by the texture, see `Texture::palette`, and can be uploaded as a
+    /// texture of its own with `TextureData::from_palette`. Images that are not indexed PNG
+    /// files fail to load with `TextureError::NotIndexed`.
+    pub fn with_indexed(self, keep_indexed: bool) -> Self {
+        let channels = match (keep_indexed, self.1) {
+            (true, _) => ImageChannels::Indexed,
+            (false, ImageChannels::Indexed) => ImageChannels::default(),
+            (false, channels) => channels,
         };
+        self.with_channels(channels)
+    }
+}
+
+/// Options of an image loaded by `TexturePrefab::Image`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageOptions {
+    /// Whether the image is sRGB encoded. When `None`, this is guessed from how the texture is
+    /// used: color textures, like the albedo and emission maps of a `MaterialPrefab` or sprite
+    /// sheets, are sRGB encoded, and the texture maps storing other data, like normals, are
+    /// linear. Textures loaded without a known usage are sRGB encoded.
+    pub srgb: Option<bool>,
+    /// Whether the colors are multiplied by their alpha when the image is decoded. Defaults to
+    /// `true`, see `AlphaMode`.
+    pub premultiply_alpha: Option<bool>,
+    /// Channels of the loaded texture, detected from the file by default.
+    pub channels: ImageChannels,
+    /// Load the raw palette indices of an indexed PNG file, see `ImageFormat::with_indexed`.
+    pub keep_indexed: bool,
+}
 
-        ImageFormat(ImageTextureConfig {
-            format: None,
-            repr: Repr::Srgb,
-            kind: TextureKind::D2,
-            sampler_info: SamplerInfo {
-                min_filter: Filter::Nearest,
-                mag_filter: Filter::Nearest,
-                mip_filter: Filter::Nearest,
-                wrap_mode: (WrapMode::Tile, WrapMode::Tile, WrapMode::Tile),
-                lod_bias: 0.0.into(),
-                lod_range: std::ops::Range {
-                    start: 0.0.into(),
-                    end: 1000.0.into(),
-                },
-                comparison: None,
-                border: PackedColor(0),
-                anisotropic: Anisotropic::Off,
+impl ImageOptions {
+    /// Format loading images with these options.
+    pub fn format(&self) -> ImageFormat {
+        ImageFormat::default()
+            .with_srgb(self.srgb.unwrap_or(true))
+            .with_premultiplied_alpha(self.premultiply_alpha.unwrap_or(true))
+            .with_channels(self.channels)
+            .with_indexed(self.keep_indexed)
+    }
+}
+
+/// Sampler settings of a texture, defaulting to those of `ImageFormat::default`.
+///
+/// Unlike `SamplerInfo`, every field is optional when deserializing.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TextureSampler {
+    /// Filter used when the texture is minified.
+    pub min_filter: Filter,
+    /// Filter used when the texture is magnified.
+    pub mag_filter: Filter,
+    /// Filter used between mip levels.
+    pub mip_filter: Filter,
+    /// Wrap mode along the u, v and w axes.
+    pub wrap_mode: (WrapMode, WrapMode, WrapMode),
+    /// Anisotropic filtering level, where `1` disables it. Levels beyond the device limit are
+    /// clamped when the texture is uploaded.
+    pub anisotropy: u8,
+    /// Linear RGBA color sampled outside of the texture along axes using `WrapMode::Border`.
+    pub border: [f32; 4],
+}
+
+impl Default for TextureSampler {
+    fn default() -> Self {
+        TextureSampler {
+            min_filter: Filter::Nearest,
+            mag_filter: Filter::Nearest,
+            mip_filter: Filter::Nearest,
+            wrap_mode: (WrapMode::Tile, WrapMode::Tile, WrapMode::Tile),
+            anisotropy: 1,
+            border: [0.0; 4],
+        }
+    }
+}
+
+impl TextureSampler {
+    /// Nearest filtering and clamped edges, for pixel art.
+    pub fn pixel_art() -> Self {
+        TextureSampler {
+            wrap_mode: (WrapMode::Clamp, WrapMode::Clamp, WrapMode::Clamp),
+            ..Default::default()
+        }
+    }
+
+    /// Linear filtering within and between mip levels.
+    pub fn trilinear() -> Self {
+        TextureSampler {
+            min_filter: Filter::Linear,
+            mag_filter: Filter::Linear,
+            mip_filter: Filter::Linear,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<TextureSampler> for SamplerInfo {
+    fn from(sampler: TextureSampler) -> Self {
+        SamplerInfo {
+            min_filter: sampler.min_filter,
+            mag_filter: sampler.mag_filter,
+            mip_filter: sampler.mip_filter,
+            wrap_mode: sampler.wrap_mode,
+            lod_bias: 0.0.into(),
+            lod_range: std::ops::Range {
+                start: 0.0.into(),
+                end: 1000.0.into(),
             },
-            generate_mips: false,
-            premultiply_alpha: true,
-        })
+            comparison: None,
+            border: PackedColor::from(sampler.border),
+            anisotropic: if sampler.anisotropy > 1 {
+                Anisotropic::On(sampler.anisotropy)
+            } else {
+                Anisotropic::Off
+            },
+        }
     }
 }
 
@@ -91,12 +256,415 @@ impl Format<TextureData> for ImageFormat {
     }
 
     fn import_simple(&self, bytes: Vec<u8>) -> Result<TextureData, Error> {
+        if let Some(data) = load_channels(&bytes, &self.0, self.1)? {
+            return Ok(data);
+        }
+        let byte_size = rgba8_byte_size(&bytes, &self.0);
         load_from_image(std::io::Cursor::new(&bytes), self.0.clone())
-            .map(|builder| builder.into())
+            .map(|builder| {
+                let data =
+                    TextureData::from(builder).with_sampler_info(self.0.sampler_info.clone());
+                match byte_size {
+                    Some(bytes) => data.with_byte_size(bytes),
+                    None => data,
+                }
+            })
             .map_err(|e| e.compat().into())
     }
 }
 
+/// Names of the cubemap faces, in the order of the texture layers.
+const CUBE_FACES: [&str; 6] = ["+X", "-X", "+Y", "-Y", "+Z", "-Z"];
+
+/// Layout of the images making up a cubemap. Faces are stored in the +X, -X, +Y, -Y, +Z, -Z
+/// layer order, following the Vulkan cubemap conventions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CubemapLayout {
+    /// Six square images of equal size, one per face. The faces are loaded from these paths,
+    /// the name the cubemap is loaded with is ignored.
+    Faces([String; 6]),
+    /// A single image with 4x3 square faces:
+    ///
+    /// ```text
+    ///     +Y
+    /// -X  +Z  +X  -Z
+    ///     -Y
+    /// ```
+    HorizontalCross,
+    /// A single image with 3x4 square faces, the -Z face upside down:
+    ///
+    /// ```text
+    ///     +Y
+    /// -X  +Z  +X
+    ///     -Y
+    ///     -Z
+    /// ```
+    VerticalCross,
+    /// An equirectangular panorama, resampled to faces of the given size in pixels. The
+    /// center of the image looks towards -Z, and the top row towards +Y.
+    Equirectangular(u32),
+}
+
+/// Format loading cubemaps, for skyboxes and image based lighting. The texture is viewed as a
+/// cube, so it binds to `samplerCube` descriptors.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CubemapFormat {
+    /// Layout of the faces in the loaded images.
+    pub layout: CubemapLayout,
+    /// Whether 8 bit images are sRGB encoded.
+    pub srgb: bool,
+    /// Load Radiance HDR or OpenEXR images with this precision, instead of 8 bit images.
+    pub hdr: Option<HdrPrecision>,
+    /// Sampler of the texture.
+    pub sampler_info: SamplerInfo,
+}
+
+impl Default for CubemapFormat {
+    fn default() -> Self {
+        CubemapFormat::new(CubemapLayout::HorizontalCross)
+    }
+}
+
+impl CubemapFormat {
+    /// Format loading sRGB encoded 8 bit images with the given layout.
+    pub fn new(layout: CubemapLayout) -> Self {
+        CubemapFormat {
+            layout,
+            srgb: true,
+            hdr: None,
+            sampler_info: SamplerInfo::new(Filter::Linear, WrapMode::Clamp),
+        }
+    }
+
+    /// Load Radiance HDR or OpenEXR images as float textures.
+    pub fn with_hdr(mut self, precision: HdrPrecision) -> Self {
+        self.hdr = Some(precision);
+        self
+    }
+
+    /// Build the six faces of the cubemap from the decoded images of the layout.
+    fn faces(&self, images: Vec<CubeImage>) -> Result<CubeImage, TextureError> {
+        let image = &images[0];
+        let (width, height) = (image.width, image.height);
+        let invalid = |layout| {
+            TextureError::InvalidCubemap(format!(
+                "{}x{} image is not a {} of square faces",
+                width, height, layout
+            ))
+        };
+        let (size, squares) = match self.layout {
+            CubemapLayout::Faces(_) => {
+                for (image, face) in images.iter().zip(&CUBE_FACES) {
+                    if image.width != image.height {
+                        return Err(TextureError::InvalidCubemap(format!(
+                            "face {} is {}x{}, faces must be square",
+                            face, image.width, image.height
+                        )));
+                    }
+                    if image.width != width {
+                        return Err(TextureError::InvalidCubemap(format!(
+                            "face {} is {}x{}, but face +X is {}x{}",
+                            face, image.width, image.height, width, height
+                        )));
+                    }
+                }
+                let mut data = Vec::with_capacity(image.data.len() * 6);
+                for image in &images {
+                    data.extend_from_slice(&image.data);
+                }
+                return Ok(CubeImage {
+                    width,
+                    height: width * 6,
+                    format: image.format,
+                    data,
+                });
+            }
+            CubemapLayout::HorizontalCross => {
+                if width == 0 || width % 4 != 0 || width / 4 * 3 != height {
+                    return Err(invalid("horizontal cross"));
+                }
+                (width / 4, [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)])
+            }
+            CubemapLayout::VerticalCross => {
+                if height == 0 || height % 4 != 0 || height / 4 * 3 != width {
+                    return Err(invalid("vertical cross"));
+                }
+                (height / 4, [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (1, 3)])
+            }
+            CubemapLayout::Equirectangular(size) => {
+                if size == 0 || width == 0 || height == 0 {
+                    return Err(TextureError::InvalidCubemap(format!(
+                        "can't resample a {}x{} panorama to {}x{} faces",
+                        width, height, size, size
+                    )));
+                }
+                return Ok(image.equirectangular_faces(size));
+            }
+        };
+
+        let mut data = Vec::with_capacity(image.data.len() / 2);
+        for (face, &(column, row)) in squares.iter().enumerate() {
+            let flip = self.layout == CubemapLayout::VerticalCross && face == 5;
+            image.copy_square(column * size, row * size, size, flip, &mut data);
+        }
+        Ok(CubeImage {
+            width: size,
+            height: size * 6,
+            format: image.format,
+            data,
+        })
+    }
+
+    fn texture_data(&self, images: Vec<CubeImage>) -> Result<TextureData, TextureError> {
+        let faces = self.faces(images)?;
+        Ok(layered_texture_data(
+            (faces.width, faces.width),
+            6,
+            true,
+            faces.format,
+            faces.data,
+            self.sampler_info.clone(),
+        ))
+    }
+}
+
+amethyst_assets::register_format!("CUBEMAP", CubemapFormat as TextureData);
+impl Format<TextureData> for CubemapFormat {
+    fn name(&self) -> &'static str {
+        "CUBEMAP"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<TextureData, Error> {
+        if let CubemapLayout::Faces(_) = self.layout {
+            return Err(TextureError::InvalidCubemap(
+                "six faces can't be loaded from a single image".into(),
+            )
+            .into());
+        }
+        let image = CubeImage::decode(&bytes, self.srgb, self.hdr)?;
+        Ok(self.texture_data(vec![image])?)
+    }
+
+    fn import(
+        &self,
+        name: String,
+        source: Arc<dyn Source>,
+        create_reload: Option<Box<dyn Format<TextureData>>>,
+    ) -> Result<FormatValue<TextureData>, Error> {
+        let paths = match self.layout {
+            CubemapLayout::Faces(ref paths) => paths,
+            _ => {
+                let (bytes, modified) = source.load_with_metadata(&name)?;
+                return Ok(FormatValue {
+                    data: self.import_simple(bytes)?,
+                    reload: create_reload.map(|format| -> Box<dyn Reload<TextureData>> {
+                        Box::new(SingleFile::new(format, modified, name, source))
+                    }),
+                });
+            }
+        };
+        let mut images = Vec::with_capacity(6);
+        for path in paths {
+            images.push(CubeImage::decode(&source.load(path)?, self.srgb, self.hdr)?);
+        }
+        Ok(FormatValue::data(self.texture_data(images)?))
+    }
+}
+
+/// Decoded image with 4 channel pixels, or the faces of a cubemap stacked from top to bottom.
+#[derive(Debug)]
+struct CubeImage {
+    width: u32,
+    height: u32,
+    format: hal::format::Format,
+    data: Vec<u8>,
+}
+
+impl CubeImage {
+    fn decode(bytes: &[u8], srgb: bool, hdr: Option<HdrPrecision>) -> Result<Self, Error> {
+        let precision = match hdr {
+            Some(precision) => precision,
+            None => {
+                let image = image::load_from_memory(bytes)?.to_rgba();
+                return Ok(CubeImage {
+                    width: image.width(),
+                    height: image.height(),
+                    format: if srgb {
+                        hal::format::Format::Rgba8Srgb
+                    } else {
+                        hal::format::Format::Rgba8Unorm
+                    },
+                    data: image.into_raw(),
+                });
+            }
+        };
+        let image = if bytes.starts_with(b"#?") {
+            HdrImage::parse_radiance(bytes, precision)?
+        } else {
+            HdrImage::parse_exr(bytes, precision)?
+        };
+        Ok(CubeImage {
+            width: image.width,
+            height: image.height,
+            format: image.format(),
+            data: image.into_data(),
+        })
+    }
+
+    fn pixel_size(&self) -> usize {
+        block_layout(self.format).2
+    }
+
+    /// Append the `size` pixels square with its top left corner at `x`, `y` to `data`, rotated
+    /// by 180 degrees if `flip` is set.
+    fn copy_square(&self, x: u32, y: u32, size: u32, flip: bool, data: &mut Vec<u8>) {
+        let pixel_size = self.pixel_size();
+        let stride = self.width as usize * pixel_size;
+        for row in 0..size {
+            let row = if flip { size - 1 - row } else { row };
+            let start = (y + row) as usize * stride + x as usize * pixel_size;
+            let line = &self.data[start..start + size as usize * pixel_size];
+            if flip {
+                for pixel in line.chunks_exact(pixel_size).rev() {
+                    data.extend_from_slice(pixel);
+                }
+            } else {
+                data.extend_from_slice(line);
+            }
+        }
+    }
+
+    fn read(&self, x: u32, y: u32) -> [f32; 4] {
+        let pixel_size = self.pixel_size();
+        let start = (y as usize * self.width as usize + x as usize) * pixel_size;
+        let bytes = &self.data[start..start + pixel_size];
+        let mut pixel = [0.0; 4];
+        for (channel, value) in pixel.iter_mut().enumerate() {
+            *value = match pixel_size {
+                4 => f32::from(bytes[channel]) / 255.0,
+                8 => f16_to_f32(u16::from_le_bytes([
+                    bytes[channel * 2],
+                    bytes[channel * 2 + 1],
+                ])),
+                _ => f32::from_bits(u32::from_le_bytes([
+                    bytes[channel * 4],
+                    bytes[channel * 4 + 1],
+                    bytes[channel * 4 + 2],
+                    bytes[channel * 4 + 3],
+                ])),
+            };
+        }
+        pixel
+    }
+
+    fn write(&self, pixel: [f32; 4], data: &mut Vec<u8>) {
+        for &value in &pixel {
+            match self.pixel_size() {
+                4 => data.push((value.max(0.0).min(1.0) * 255.0).round() as u8),
+                8 => data.extend_from_slice(&f32_to_f16(value).to_le_bytes()),
+                _ => data.extend_from_slice(&value.to_bits().to_le_bytes()),
+            }
+        }
+    }
+
+    /// Bilinearly sample the panorama at `u`, `v` in `0..=1`, wrapping around horizontally.
+    fn sample(&self, u: f32, v: f32) -> [f32; 4] {
+        let x = u * self.width as f32 - 0.5;
+        let y = (v * self.height as f32 - 0.5)
+            .max(0.0)
+            .min((self.height - 1) as f32);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let width = i64::from(self.width);
+        let column = |x: f32| ((x as i64 % width + width) % width) as u32;
+        let (x0, x1) = (column(x0), column(x0 + 1.0));
+        let (y0, y1) = (y0 as u32, (y0 as u32 + 1).min(self.height - 1));
+        let (a, b, c, d) = (
+            self.read(x0, y0),
+            self.read(x1, y0),
+            self.read(x0, y1),
+            self.read(x1, y1),
+        );
+        let mut pixel = [0.0; 4];
+        for (i, value) in pixel.iter_mut().enumerate() {
+            let top = a[i] + (b[i] - a[i]) * fx;
+            let bottom = c[i] + (d[i] - c[i]) * fx;
+            *value = top + (bottom - top) * fy;
+        }
+        pixel
+    }
+
+    /// Resample the equirectangular panorama to six `size` pixels faces.
+    fn equirectangular_faces(&self, size: u32) -> CubeImage {
+        use std::f32::consts::PI;
+
+        let mut data = Vec::with_capacity(size as usize * size as usize * 6 * self.pixel_size());
+        for face in 0..6 {
+            for y in 0..size {
+                for x in 0..size {
+                    let s = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                    let t = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                    let [dx, dy, dz] = cube_direction(face, s, t);
+                    let length = (dx * dx + dy * dy + dz * dz).sqrt();
+                    let u = 0.5 + dx.atan2(-dz) / (2.0 * PI);
+                    let v = (dy / length).max(-1.0).min(1.0).acos() / PI;
+                    self.write(self.sample(u, v), &mut data);
+                }
+            }
+        }
+        CubeImage {
+            width: size,
+            height: size * 6,
+            format: self.format,
+            data,
+        }
+    }
+}
+
+/// Direction through the point `s`, `t` in `-1..=1` of a cubemap face, with `s` pointing
+/// right and `t` down in the face image.
+fn cube_direction(face: usize, s: f32, t: f32) -> [f32; 3] {
+    match face {
+        0 => [1.0, -t, -s],
+        1 => [-1.0, -t, s],
+        2 => [s, 1.0, t],
+        3 => [s, -1.0, -t],
+        4 => [s, -t, 1.0],
+        _ => [-s, -t, -1.0],
+    }
+}
+
+/// Load a `Heightmap` from the bytes of a grayscale image, with heights normalized to `0..=1`.
+///
+/// 16 bit grayscale PNG files keep their full precision, any other image supported by the
+/// `image` crate is converted to 8 bit luminance first. The top row of the image becomes the
+/// far (-Z) edge of the terrain.
+pub fn load_heightmap(bytes: &[u8]) -> Result<Heightmap, Error> {
+    use image::{png::PNGDecoder, ColorType, ImageDecoder};
+
+    if let Ok(decoder) = PNGDecoder::new(std::io::Cursor::new(bytes)) {
+        if decoder.colortype() == ColorType::Gray(16) {
+            let (width, depth) = decoder.dimensions();
+            let heights = decoder
+                .read_image()?
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]) as f32 / std::u16::MAX as f32)
+                .collect();
+            return Ok(Heightmap::new(width as usize, depth as usize, heights)?);
+        }
+    }
+
+    let image = image::load_from_memory(bytes)?.to_luma();
+    let (width, depth) = image.dimensions();
+    let heights = image
+        .into_raw()
+        .into_iter()
+        .map(|h| h as f32 / std::u8::MAX as f32)
+        .collect();
+    Ok(Heightmap::new(width as usize, depth as usize, heights)?)
+}
+
 /// `PrefabData` for loading `Texture`s.
 ///
 /// Will not add any `Component`s to the `Entity`, will only return a `Handle`
@@ -110,6 +678,10 @@ pub enum TexturePrefab {
     Generate(TextureGenerator),
     /// Load file with format
     File(String, Box<dyn Format<TextureData>>),
+    /// Load image file with options
+    Image(String, ImageOptions),
+    /// Texture sampled with the given settings
+    Sampled(Box<TexturePrefab>, TextureSampler),
 
     /// Clone handle only
     #[serde(skip)]
@@ -119,6 +691,108 @@ pub enum TexturePrefab {
     Placeholder,
 }
 
+impl TexturePrefab {
+    /// Set whether `Image` textures without an explicit color space are sRGB encoded, from
+    /// how the texture is used.
+    pub fn guess_srgb(&mut self, srgb: bool) {
+        match *self {
+            TexturePrefab::Image(_, ref mut options) => {
+                options.srgb.get_or_insert(srgb);
+            }
+            TexturePrefab::Sampled(ref mut prefab, _) => prefab.guess_srgb(srgb),
+            _ => {}
+        }
+    }
+
+    /// Replace `Image` textures by the file they load.
+    fn resolve_image(self) -> Self {
+        match self {
+            TexturePrefab::Image(name, options) => {
+                TexturePrefab::File(name, Box::new(options.format()))
+            }
+            other => other,
+        }
+    }
+
+    /// Generate the full mip chain of the texture when it is loaded, see
+    /// `TextureData::with_generated_mips`. Generated textures and handles are left unchanged.
+    pub fn generate_mips(&mut self) {
+        *self = match std::mem::replace(self, TexturePrefab::Placeholder).resolve_image() {
+            TexturePrefab::Data(data) => TexturePrefab::Data(data.with_generated_mips()),
+            TexturePrefab::File(name, format) => {
+                TexturePrefab::File(name, Box::new(GenerateMips(format)))
+            }
+            TexturePrefab::Sampled(mut prefab, sampler) => {
+                prefab.generate_mips();
+                TexturePrefab::Sampled(prefab, sampler)
+            }
+            other => other,
+        };
+    }
+
+    /// Sample the texture with `sampler` once it is loaded. Handles are left unchanged.
+    pub fn with_sampler(self, sampler: impl Into<SamplerInfo>) -> Self {
+        let info = sampler.into();
+        match self.resolve_image() {
+            TexturePrefab::Data(data) => TexturePrefab::Data(data.with_sampler_info(info)),
+            TexturePrefab::Generate(generator) => {
+                TexturePrefab::Data(generator.data().with_sampler_info(info))
+            }
+            TexturePrefab::File(name, format) => {
+                TexturePrefab::File(name, Box::new(WithSampler(format, info)))
+            }
+            TexturePrefab::Sampled(prefab, _) => prefab.with_sampler(info),
+            other => other,
+        }
+    }
+}
+
+/// Wraps the format of a `TexturePrefab::File` to replace the sampler of the loaded data.
+#[derive(Debug, Clone)]
+struct WithSampler(Box<dyn Format<TextureData>>, SamplerInfo);
+
+impl Format<TextureData> for WithSampler {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn import(
+        &self,
+        name: String,
+        source: Arc<dyn Source>,
+        create_reload: Option<Box<dyn Format<TextureData>>>,
+    ) -> Result<FormatValue<TextureData>, Error> {
+        let value = self.0.import(name, source, create_reload)?;
+        Ok(FormatValue {
+            data: value.data.with_sampler_info(self.1.clone()),
+            reload: value.reload,
+        })
+    }
+}
+
+/// Wraps the format of a `TexturePrefab::File` to generate mips for the loaded data.
+#[derive(Debug, Clone)]
+struct GenerateMips(Box<dyn Format<TextureData>>);
+
+impl Format<TextureData> for GenerateMips {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn import(
+        &self,
+        name: String,
+        source: Arc<dyn Source>,
+        create_reload: Option<Box<dyn Format<TextureData>>>,
+    ) -> Result<FormatValue<TextureData>, Error> {
+        let value = self.0.import(name, source, create_reload)?;
+        Ok(FormatValue {
+            data: value.data.with_generated_mips(),
+            reload: value.reload,
+        })
+    }
+}
+
 /// Provides enum variant typecasting of texture data.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum TextureGenerator {
@@ -197,7 +871,11 @@ impl<'a> PrefabData<'a> for TexturePrefab {
         progress: &mut ProgressCounter,
         (loader, storage): &mut Self::SystemData,
     ) -> Result<bool, Error> {
-        let (ret, next) = match std::mem::replace(self, TexturePrefab::Placeholder) {
+        let prefab = match std::mem::replace(self, TexturePrefab::Placeholder) {
+            TexturePrefab::Sampled(prefab, sampler) => prefab.with_sampler(sampler),
+            prefab => prefab.resolve_image(),
+        };
+        let (ret, next) = match prefab {
             TexturePrefab::Data(data) => {
                 let handle = loader.load_from_data(data, progress, storage);
                 (true, TexturePrefab::Handle(handle))
@@ -216,3 +894,234 @@ impl<'a> PrefabData<'a> for TexturePrefab {
         Ok(ret)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_mips_wraps_files() {
+        let format = ImageFormat::mipmapped();
+        assert!(format.0.generate_mips);
+        assert_eq!(format.0.sampler_info.mip_filter, Filter::Linear);
+        assert!(!ImageFormat::default().0.generate_mips);
+
+        let mut prefab =
+            TexturePrefab::File("texture.png".into(), Box::new(ImageFormat::default()));
+        prefab.generate_mips();
+        match prefab {
+            TexturePrefab::File(ref name, ref format) => {
+                assert_eq!(name, "texture.png");
+                assert_eq!(format.name(), "IMAGE");
+                assert!(format!("{:?}", format).starts_with("GenerateMips"));
+            }
+            _ => panic!("Expected a file prefab"),
+        }
+    }
+
+    #[test]
+    fn sampler_options() {
+        assert_eq!(
+            ImageFormat::default().0.sampler_info,
+            SamplerInfo::from(TextureSampler::default())
+        );
+
+        let info = ImageFormat::pixel_art().0.sampler_info;
+        assert_eq!(info.min_filter, Filter::Nearest);
+        assert_eq!(info.wrap_mode.0, WrapMode::Clamp);
+
+        let info = SamplerInfo::from(TextureSampler {
+            anisotropy: 16,
+            border: [1.0, 1.0, 1.0, 1.0],
+            ..TextureSampler::trilinear()
+        });
+        assert_eq!(info.anisotropic, Anisotropic::On(16));
+        assert_eq!(info.border, PackedColor(std::u32::MAX));
+
+        let sampler: TextureSampler = ron::de::from_str("(anisotropy: 8)").unwrap();
+        assert_eq!(sampler.anisotropy, 8);
+        assert_eq!(sampler.wrap_mode, TextureSampler::default().wrap_mode);
+    }
+
+    #[test]
+    fn premultiplied_alpha_option() {
+        assert!(ImageFormat::default().0.premultiply_alpha);
+        assert!(
+            !ImageFormat::default()
+                .with_premultiplied_alpha(false)
+                .0
+                .premultiply_alpha
+        );
+
+        let options: ImageOptions = ron::de::from_str("(premultiply_alpha: Some(false))").unwrap();
+        assert!(!options.format().0.premultiply_alpha);
+        assert!(ImageOptions::default().format().0.premultiply_alpha);
+    }
+
+    /// Value the GPU samples from a channel stored as `value`, depending on the texture format.
+    fn sampled(value: u8, data: &TextureData) -> f32 {
+        let value = f32::from(value) / 255.0;
+        if format!("{:?}", data.0).contains("Srgb") {
+            palette::Srgb::new(value, value, value).into_linear().red
+        } else {
+            value
+        }
+    }
+
+    #[test]
+    fn mid_gray_normal_map_color_space() {
+        use image::{png::PNGEncoder, ColorType};
+
+        // Flat normal map, with x and y at mid-gray
+        let pixels: Vec<u8> = [128, 128, 255, 255]
+            .iter()
+            .cycle()
+            .take(16)
+            .cloned()
+            .collect();
+        let mut png = Vec::new();
+        PNGEncoder::new(&mut png)
+            .encode(&pixels, 2, 2, ColorType::RGBA(8))
+            .unwrap();
+
+        let mut prefab = TexturePrefab::Image("normal.png".into(), ImageOptions::default());
+        prefab.guess_srgb(false);
+        let srgb = match prefab {
+            TexturePrefab::Image(_, ref options) => options.srgb,
+            _ => panic!("Expected an image prefab"),
+        };
+        assert_eq!(srgb, Some(false));
+
+        let linear = ImageOptions {
+            srgb,
+            ..ImageOptions::default()
+        }
+        .format()
+        .import_simple(png.clone())
+        .unwrap();
+        assert!((sampled(128, &linear) - 0.502).abs() < 0.001);
+
+        // Decoded as sRGB, the normal would lean far towards -x and -y
+        let encoded = ImageOptions::default().format().import_simple(png).unwrap();
+        assert!((sampled(128, &encoded) - 0.216).abs() < 0.001);
+    }
+
+    fn cross_image(width: u32, height: u32) -> CubeImage {
+        // Every pixel stores its own coordinates
+        let mut data = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                data.extend_from_slice(&[x as u8, y as u8, 0, 255]);
+            }
+        }
+        CubeImage {
+            width,
+            height,
+            format: hal::format::Format::Rgba8Unorm,
+            data,
+        }
+    }
+
+    fn first_pixels(faces: &CubeImage) -> Vec<[u8; 2]> {
+        let face_size = faces.data.len() / 6;
+        (0..6)
+            .map(|face| {
+                [
+                    faces.data[face * face_size],
+                    faces.data[face * face_size + 1],
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cubemap_crosses() {
+        let faces = CubemapFormat::new(CubemapLayout::HorizontalCross)
+            .faces(vec![cross_image(8, 6)])
+            .unwrap();
+        assert_eq!((faces.width, faces.height), (2, 12));
+        assert_eq!(faces.data.len(), 2 * 2 * 6 * 4);
+        assert_eq!(
+            first_pixels(&faces),
+            vec![[4, 2], [0, 2], [2, 0], [2, 4], [2, 2], [6, 2]]
+        );
+
+        // -Z is upside down below -Y, so its first pixel is the bottom right one
+        let faces = CubemapFormat::new(CubemapLayout::VerticalCross)
+            .faces(vec![cross_image(6, 8)])
+            .unwrap();
+        assert_eq!(
+            first_pixels(&faces),
+            vec![[4, 2], [0, 2], [2, 0], [2, 4], [2, 2], [3, 7]]
+        );
+
+        assert_eq!(
+            CubemapFormat::new(CubemapLayout::VerticalCross)
+                .faces(vec![cross_image(8, 6)])
+                .unwrap_err(),
+            TextureError::InvalidCubemap(
+                "8x6 image is not a vertical cross of square faces".into()
+            )
+        );
+    }
+
+    #[test]
+    fn cubemap_faces_must_match() {
+        let format = CubemapFormat::new(CubemapLayout::Faces([
+            "px.png".into(),
+            "nx.png".into(),
+            "py.png".into(),
+            "ny.png".into(),
+            "pz.png".into(),
+            "nz.png".into(),
+        ]));
+        let mut images: Vec<_> = (0..6).map(|_| cross_image(4, 4)).collect();
+        assert_eq!(format.faces(images).unwrap().height, 24);
+
+        images = (0..6).map(|_| cross_image(4, 4)).collect();
+        images[2] = cross_image(4, 2);
+        assert_eq!(
+            format.faces(images).unwrap_err(),
+            TextureError::InvalidCubemap("face +Y is 4x2, faces must be square".into())
+        );
+
+        images = (0..6).map(|_| cross_image(4, 4)).collect();
+        images[5] = cross_image(8, 8);
+        assert_eq!(
+            format.faces(images).unwrap_err(),
+            TextureError::InvalidCubemap("face -Z is 8x8, but face +X is 4x4".into())
+        );
+        assert!(format.import_simple(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn equirectangular_cubemap() {
+        // Bright sky above a dark ground, with the column in green
+        let mut data = Vec::new();
+        for y in 0..4 {
+            for x in 0..8 {
+                let sky = if y < 2 { 1.0 } else { 0.0 };
+                for &value in &[sky, x as f32 / 8.0, 0.0, 1.0f32] {
+                    data.extend_from_slice(&value.to_bits().to_le_bytes());
+                }
+            }
+        }
+        let panorama = CubeImage {
+            width: 8,
+            height: 4,
+            format: hal::format::Format::Rgba32Sfloat,
+            data,
+        };
+
+        let faces = CubemapFormat::new(CubemapLayout::Equirectangular(1))
+            .faces(vec![panorama])
+            .unwrap();
+        assert_eq!(faces.format, hal::format::Format::Rgba32Sfloat);
+        let center = |face| faces.read(0, face);
+        assert!((center(2)[0] - 1.0).abs() < 1e-5);
+        assert!(center(3)[0].abs() < 1e-5);
+        assert!((center(5)[0] - 0.5).abs() < 1e-5);
+        assert!((center(5)[1] - 3.5 / 8.0).abs() < 1e-5);
+        assert!((center(0)[1] - 5.5 / 8.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,240 @@
+//! Comparing rendered frames with golden reference images.
+//!
+//! Frames read back by a `CaptureDesc` node are compared pixel by pixel with a reference PNG.
+//! GPUs and drivers rasterize edges and round colors a little differently, so a pixel matches
+//! when none of its channels differ by more than `GoldenTolerance::channel`, and an image matches
+//! when less than `GoldenTolerance::different_pixels` percent of its pixels don't. The references
+//! of the passes are checked in to `tests/fixtures/golden`, and each pass is compared with its
+//! own tolerance.
+//!
+//! On mismatch, `check_golden` writes the actual frame and an image of the differences next to
+//! each other in the output directory. A missing reference is an error too, unless the
+//! `AMETHYST_UPDATE_GOLDEN` environment variable is set, which writes the actual frames as the
+//! new references, to record them before a change or update them after an intended one.
+//! `RenderGoldenTestBundle` renders the scenes of the golden-image checks with the
+//! `test-support` feature.
+use crate::error::GoldenError;
+use image::{Rgba, RgbaImage};
+use std::path::Path;
+
+/// Environment variable writing the actual frames as the references, when it's set.
+pub const UPDATE_GOLDEN_VAR: &str = "AMETHYST_UPDATE_GOLDEN";
+
+/// How much a frame may differ from its reference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GoldenTolerance {
+    /// Largest difference of a channel of a matching pixel, from 0 to 255.
+    pub channel: u8,
+    /// Largest percentage of the pixels that may not match.
+    pub different_pixels: f32,
+}
+
+impl Default for GoldenTolerance {
+    fn default() -> Self {
+        Self {
+            channel: 2,
+            different_pixels: 0.5,
+        }
+    }
+}
+
+/// Result of the comparison of a frame with its reference.
+#[derive(Debug, Clone)]
+pub struct GoldenDiff {
+    /// Number of pixels differing by more than the tolerance of a channel.
+    pub different_pixels: u32,
+    /// Number of pixels compared.
+    pub pixel_count: u32,
+    /// Largest difference of a channel.
+    pub max_channel_difference: u8,
+    /// The differing pixels in red over the dimmed reference.
+    pub diff: RgbaImage,
+}
+
+impl GoldenDiff {
+    /// Percentage of the pixels that don't match.
+    pub fn different_percent(&self) -> f32 {
+        if self.pixel_count == 0 {
+            0.0
+        } else {
+            self.different_pixels as f32 * 100.0 / self.pixel_count as f32
+        }
+    }
+
+    /// Whether the frame is within `tolerance` of its reference.
+    pub fn matches(&self, tolerance: &GoldenTolerance) -> bool {
+        self.different_percent() <= tolerance.different_pixels
+    }
+}
+
+/// Largest difference of the channels of two pixels.
+fn channel_difference(a: &Rgba<u8>, b: &Rgba<u8>) -> u8 {
+    a.data
+        .iter()
+        .zip(b.data.iter())
+        .map(|(a, b)| if a > b { a - b } else { b - a })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Compare `actual` with `reference` pixel by pixel.
+pub fn compare_golden(
+    actual: &RgbaImage,
+    reference: &RgbaImage,
+    tolerance: &GoldenTolerance,
+) -> Result<GoldenDiff, GoldenError> {
+    if actual.dimensions() != reference.dimensions() {
+        return Err(GoldenError::SizeMismatch {
+            expected: reference.dimensions(),
+            actual: actual.dimensions(),
+        });
+    }
+
+    let (width, height) = actual.dimensions();
+    let mut diff = RgbaImage::new(width, height);
+    let mut different_pixels = 0;
+    let mut max_channel_difference = 0;
+    for ((actual, reference), diff) in actual
+        .pixels()
+        .zip(reference.pixels())
+        .zip(diff.pixels_mut())
+    {
+        let difference = channel_difference(actual, reference);
+        max_channel_difference = max_channel_difference.max(difference);
+        *diff = if difference > tolerance.channel {
+            different_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let dim = |c: u8| c / 4;
+            let [r, g, b, _] = reference.data;
+            Rgba([dim(r), dim(g), dim(b), 255])
+        };
+    }
+
+    Ok(GoldenDiff {
+        different_pixels,
+        pixel_count: width * height,
+        max_channel_difference,
+        diff,
+    })
+}
+
+/// Compare `actual` with the reference PNG `name` of `reference_dir`, writing the actual frame
+/// and the differences as `<name>.actual.png` and `<name>.diff.png` to `output_dir` when they
+/// don't match.
+pub fn check_golden(
+    actual: &RgbaImage,
+    name: &str,
+    reference_dir: &Path,
+    output_dir: &Path,
+    tolerance: &GoldenTolerance,
+) -> Result<GoldenDiff, GoldenError> {
+    let reference_path = reference_dir.join(format!("{}.png", name));
+    let write = |image: &RgbaImage, path: &Path| {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| GoldenError::Io(e.to_string()))?;
+        }
+        image.save(path).map_err(|e| GoldenError::Io(e.to_string()))
+    };
+
+    if std::env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+        write(actual, &reference_path)?;
+        return compare_golden(actual, actual, tolerance);
+    }
+
+    let reference = match image::open(&reference_path) {
+        Ok(reference) => reference.to_rgba(),
+        Err(_) if !reference_path.exists() => {
+            let output = output_dir.join(format!("{}.actual.png", name));
+            write(actual, &output)?;
+            return Err(GoldenError::MissingReference {
+                path: reference_path.display().to_string(),
+                output: output.display().to_string(),
+            });
+        }
+        Err(e) => return Err(GoldenError::Io(e.to_string())),
+    };
+
+    let diff = compare_golden(actual, &reference, tolerance)?;
+    if diff.matches(tolerance) {
+        return Ok(diff);
+    }
+    write(actual, &output_dir.join(format!("{}.actual.png", name)))?;
+    write(&diff.diff, &output_dir.join(format!("{}.diff.png", name)))?;
+    Err(GoldenError::Mismatch {
+        name: name.to_string(),
+        different_pixels: diff.different_pixels,
+        pixel_count: diff.pixel_count,
+        max_channel_difference: diff.max_channel_difference,
+        output: output_dir.display().to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn differences_within_tolerance_match() {
+        let reference = RgbaImage::from_pixel(10, 10, Rgba([100, 100, 100, 255]));
+        let mut actual = reference.clone();
+        actual.put_pixel(0, 0, Rgba([102, 99, 100, 255]));
+        actual.put_pixel(1, 0, Rgba([100, 100, 120, 255]));
+
+        let tolerance = GoldenTolerance {
+            channel: 2,
+            different_pixels: 1.0,
+        };
+        let diff = compare_golden(&actual, &reference, &tolerance).unwrap();
+        assert_eq!(diff.different_pixels, 1);
+        assert_eq!(diff.max_channel_difference, 20);
+        assert_eq!(diff.different_percent(), 1.0);
+        assert!(diff.matches(&tolerance));
+        assert_eq!(*diff.diff.get_pixel(1, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(*diff.diff.get_pixel(0, 0), Rgba([25, 25, 25, 255]));
+        assert!(!diff.matches(&GoldenTolerance {
+            different_pixels: 0.5,
+            ..tolerance
+        }));
+
+        assert_eq!(
+            compare_golden(&RgbaImage::new(4, 4), &reference, &tolerance).unwrap_err(),
+            GoldenError::SizeMismatch {
+                expected: (10, 10),
+                actual: (4, 4),
+            }
+        );
+    }
+
+    #[test]
+    fn mismatches_write_the_frame_and_diff() {
+        if std::env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("amethyst-golden-{}", std::process::id()));
+        let (references, output) = (dir.join("reference"), dir.join("output"));
+        let frame = RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255]));
+        let tolerance = GoldenTolerance::default();
+
+        match check_golden(&frame, "scene", &references, &output, &tolerance) {
+            Err(GoldenError::MissingReference { .. }) => {}
+            other => panic!("expected a missing reference, got {:?}", other),
+        }
+        assert!(output.join("scene.actual.png").exists());
+
+        std::fs::create_dir_all(&references).unwrap();
+        frame.save(references.join("scene.png")).unwrap();
+        let diff = check_golden(&frame, "scene", &references, &output, &tolerance).unwrap();
+        assert_eq!(diff.different_pixels, 0);
+
+        let changed = RgbaImage::from_pixel(4, 4, Rgba([200, 20, 30, 255]));
+        match check_golden(&changed, "scene", &references, &output, &tolerance) {
+            Err(GoldenError::Mismatch {
+                different_pixels, ..
+            }) => assert_eq!(different_pixels, 16),
+            other => panic!("expected a mismatch, got {:?}", other),
+        }
+        assert!(output.join("scene.diff.png").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
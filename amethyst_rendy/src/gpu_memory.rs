@@ -0,0 +1,157 @@
+//! Tracking of the GPU memory used by meshes and textures.
+use crate::types::{GpuAssetStats, Mesh, Texture};
+use amethyst_assets::{AssetStorage, Handle};
+
+/// Resource with the GPU memory used by the renderer, updated once per frame by the
+/// `RenderingSystem`.
+///
+/// Asset sizes are computed from the layout of the uploaded data, see `GpuAssetStats`. The heap
+/// usage is reported by the rendy allocator and includes all allocations of the renderer, like
+/// render targets and uniform buffers, together with the padding and alignment added by it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GpuMemoryStats {
+    /// Approximate size in bytes of all loaded textures.
+    pub texture_bytes: u64,
+    /// Approximate size in bytes of all loaded meshes with a known layout.
+    pub mesh_bytes: u64,
+    /// Highest sum of `texture_bytes` and `mesh_bytes` during this session.
+    pub peak_asset_bytes: u64,
+    /// Usage of each memory heap of the device.
+    pub heaps: Vec<GpuHeapUsage>,
+}
+
+/// Usage of a memory heap of the device.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct GpuHeapUsage {
+    /// Size of the heap in bytes.
+    pub size: u64,
+    /// Bytes of memory allocated from the heap.
+    pub used: u64,
+    /// Bytes of the allocated memory in use by resources.
+    pub effective: u64,
+    /// Highest `used` during this session.
+    pub peak_used: u64,
+}
+
+impl GpuMemoryStats {
+    /// Bytes of memory allocated from all heaps.
+    pub fn heap_bytes(&self) -> u64 {
+        self.heaps.iter().map(|heap| heap.used).sum()
+    }
+
+    /// Update the statistics of a new frame, from the loaded assets and the `(size, used,
+    /// effective)` bytes of each heap.
+    pub(crate) fn update<I>(&mut self, assets: &GpuAssetStats, heaps: I)
+    where
+        I: IntoIterator<Item = (u64, u64, u64)>,
+    {
+        self.texture_bytes = assets.texture_bytes;
+        self.mesh_bytes = assets.mesh_bytes;
+        self.peak_asset_bytes = self.peak_asset_bytes.max(assets.total_bytes());
+
+        let mut count = 0;
+        for (i, (size, used, effective)) in heaps.into_iter().enumerate() {
+            if self.heaps.len() == i {
+                self.heaps.push(GpuHeapUsage::default());
+            }
+            let heap = &mut self.heaps[i];
+            heap.size = size;
+            heap.used = used;
+            heap.effective = effective;
+            heap.peak_used = heap.peak_used.max(used);
+            count += 1;
+        }
+        self.heaps.truncate(count);
+    }
+}
+
+/// A loaded mesh or texture, see `largest_assets`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GpuAsset {
+    /// A mesh.
+    Mesh(Handle<Mesh>),
+    /// A texture.
+    Texture(Handle<Texture>),
+}
+
+/// The `n` largest loaded meshes and textures with their approximate size in bytes, largest
+/// first. Meshes without a known layout aren't included.
+pub fn largest_assets(
+    n: usize,
+    meshes: &AssetStorage<Mesh>,
+    textures: &AssetStorage<Texture>,
+) -> Vec<(GpuAsset, u64)> {
+    let meshes = meshes.iter().filter_map(|(handle, mesh)| {
+        let bytes = mesh.layout()?.byte_size();
+        Some((GpuAsset::Mesh(handle.clone()), bytes))
+    });
+    let textures = textures.iter().map(|(handle, texture)| {
+        let bytes = texture.info().byte_size();
+        (GpuAsset::Texture(handle.clone()), bytes)
+    });
+    largest(n, meshes.chain(textures))
+}
+
+/// Log the `n` largest loaded meshes and textures, see `largest_assets`.
+pub fn log_largest(n: usize, meshes: &AssetStorage<Mesh>, textures: &AssetStorage<Texture>) {
+    let assets = largest_assets(n, meshes, textures);
+    log::info!("{} largest GPU assets:", assets.len());
+    for (asset, bytes) in assets {
+        let (kind, id) = match asset {
+            GpuAsset::Mesh(handle) => ("Mesh", handle.id()),
+            GpuAsset::Texture(handle) => ("Texture", handle.id()),
+        };
+        log::info!(
+            "  {} {}: {:.2} MiB",
+            kind,
+            id,
+            bytes as f64 / (1024.0 * 1024.0)
+        );
+    }
+}
+
+fn largest<T>(n: usize, assets: impl Iterator<Item = (T, u64)>) -> Vec<(T, u64)> {
+    let mut assets: Vec<_> = assets.collect();
+    assets.sort_by(|a, b| b.1.cmp(&a.1));
+    assets.truncate(n);
+    assets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_keeps_peaks() {
+        let mut assets = GpuAssetStats::default();
+        assets.texture_bytes = 300;
+        assets.mesh_bytes = 20;
+        let mut stats = GpuMemoryStats::default();
+        stats.update(&assets, vec![(1000, 400, 350), (100, 10, 10)]);
+        assert_eq!(stats.peak_asset_bytes, 320);
+        assert_eq!(stats.heap_bytes(), 410);
+
+        assets.texture_bytes = 100;
+        stats.update(&assets, vec![(1000, 200, 150)]);
+        assert_eq!(stats.texture_bytes, 100);
+        assert_eq!(stats.peak_asset_bytes, 320);
+        assert_eq!(
+            stats.heaps,
+            vec![GpuHeapUsage {
+                size: 1000,
+                used: 200,
+                effective: 150,
+                peak_used: 400,
+            }]
+        );
+    }
+
+    #[test]
+    fn largest_first() {
+        let assets = vec![("small", 10), ("huge", 300 << 20), ("medium", 1 << 20)];
+        assert_eq!(
+            largest(2, assets.into_iter()),
+            vec![("huge", 300 << 20), ("medium", 1 << 20)]
+        );
+    }
+}
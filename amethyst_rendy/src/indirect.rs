@@ -0,0 +1,395 @@
+//! Draws of batches through indirect buffers.
+//!
+//! Instead of recording the counts of every batch in the command buffer, render groups push the
+//! draw of each batch, its mesh length and instance range, to an `IndirectDraws`. The draws are
+//! written to one slot each of a buffer of the frame, and recorded as `draw_indexed_indirect` or
+//! `draw_indirect` calls reading them. A compute pass can then rewrite the instance counts of
+//! the slots, like the one of the `culling` module does, and recorded command buffers stay valid
+//! while the counts change. Consecutive slots drawn together are merged into one call when the
+//! device supports multi-draw.
+//!
+//! Devices without `DRAW_INDIRECT_FIRST_INSTANCE` can't draw instance ranges starting after the
+//! first instance, their batches are drawn directly with the same counts.
+//!
+//! The draws are counted each frame by the `DrawCallStats` resource, with the instances they
+//! draw. Opaque entities sharing a mesh and a material are drawn as instances of one batch.
+use crate::{
+    culling::DrawIndexedCommand,
+    render_stats::PassStats,
+    rendy::{
+        command::RenderPassEncoder,
+        factory::Factory,
+        hal::{self, adapter::PhysicalDevice, Features},
+        memory::{Dynamic, Write},
+        resource::{Buffer, Escape},
+    },
+    types::Backend,
+    util,
+};
+use std::ops::Range;
+
+/// Size in bytes of a slot of an indirect buffer, large enough for either kind of draw.
+pub const INDIRECT_SLOT_SIZE: u64 = 20;
+
+/// Parameters of a non-indexed indirect draw, laid out as the GPU reads them.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DrawCommand {
+    /// Number of vertices drawn.
+    pub vertex_count: u32,
+    /// Number of instances drawn.
+    pub instance_count: u32,
+    /// First vertex drawn.
+    pub first_vertex: u32,
+    /// First instance drawn.
+    pub first_instance: u32,
+}
+
+/// The draw of a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndirectDraw {
+    /// Draw of an indexed mesh.
+    Indexed(DrawIndexedCommand),
+    /// Draw of a mesh without indices.
+    Vertices(DrawCommand),
+}
+
+impl IndirectDraw {
+    /// Draw the `len` indices of an indexed mesh, or its `len` vertices, for `instances`.
+    pub fn new(len: u32, indexed: bool, instances: Range<u32>) -> Self {
+        let instance_count = instances.end.saturating_sub(instances.start);
+        if indexed {
+            IndirectDraw::Indexed(DrawIndexedCommand {
+                index_count: len,
+                instance_count,
+                first_index: 0,
+                vertex_offset: 0,
+                first_instance: instances.start,
+            })
+        } else {
+            IndirectDraw::Vertices(DrawCommand {
+                vertex_count: len,
+                instance_count,
+                first_vertex: 0,
+                first_instance: instances.start,
+            })
+        }
+    }
+
+    /// A draw drawing nothing, for the slot of a batch whose mesh isn't loaded.
+    pub fn empty() -> Self {
+        IndirectDraw::Vertices(DrawCommand::default())
+    }
+
+    /// Number of instances drawn.
+    pub fn instance_count(&self) -> u32 {
+        match self {
+            IndirectDraw::Indexed(c) => c.instance_count,
+            IndirectDraw::Vertices(c) => c.instance_count,
+        }
+    }
+
+    /// Number of triangles drawn by all instances, assuming a triangle list.
+    pub fn triangles(&self) -> u64 {
+        let (len, instances) = match self {
+            IndirectDraw::Indexed(c) => (c.index_count, c.instance_count),
+            IndirectDraw::Vertices(c) => (c.vertex_count, c.instance_count),
+        };
+        u64::from(len / 3) * u64::from(instances)
+    }
+
+    /// Whether the mesh drawn is indexed.
+    pub fn indexed(&self) -> bool {
+        match self {
+            IndirectDraw::Indexed(_) => true,
+            IndirectDraw::Vertices(_) => false,
+        }
+    }
+
+    /// The words of the slot of the draw.
+    pub(crate) fn slot(&self) -> [u32; 5] {
+        match *self {
+            IndirectDraw::Indexed(c) => [
+                c.index_count,
+                c.instance_count,
+                c.first_index,
+                c.vertex_offset as u32,
+                c.first_instance,
+            ],
+            IndirectDraw::Vertices(c) => [
+                c.vertex_count,
+                c.instance_count,
+                c.first_vertex,
+                c.first_instance,
+                0,
+            ],
+        }
+    }
+
+    unsafe fn draw_direct<B: Backend>(&self, encoder: &mut RenderPassEncoder<'_, B>) {
+        match *self {
+            IndirectDraw::Indexed(c) => encoder.draw_indexed(
+                c.first_index..c.first_index + c.index_count,
+                c.vertex_offset,
+                c.first_instance..c.first_instance + c.instance_count,
+            ),
+            IndirectDraw::Vertices(c) => encoder.draw(
+                c.first_vertex..c.first_vertex + c.vertex_count,
+                c.first_instance..c.first_instance + c.instance_count,
+            ),
+        }
+    }
+}
+
+/// Indirect draws supported by a device.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IndirectSupport {
+    /// Batches can be drawn indirectly.
+    pub indirect: bool,
+    /// Consecutive slots can be drawn in one call.
+    pub multi_draw: bool,
+}
+
+impl IndirectSupport {
+    /// The support of the device of `factory`.
+    pub fn new<B: Backend>(factory: &Factory<B>) -> Self {
+        let features = factory.physical().features();
+        let indirect = features.contains(Features::DRAW_INDIRECT_FIRST_INSTANCE);
+        Self {
+            indirect,
+            multi_draw: indirect && features.contains(Features::MULTI_DRAW_INDIRECT),
+        }
+    }
+
+    /// The calls drawing `draws`, as ranges of them.
+    fn calls(self, draws: &[IndirectDraw]) -> Vec<Range<u32>> {
+        let mut calls: Vec<Range<u32>> = Vec::new();
+        for (i, draw) in draws.iter().enumerate() {
+            let i = i as u32;
+            match calls.last_mut() {
+                Some(call)
+                    if self.multi_draw
+                        && draws[call.start as usize].indexed() == draw.indexed() =>
+                {
+                    call.end = i + 1
+                }
+                _ => calls.push(i..i + 1),
+            }
+        }
+        calls
+    }
+}
+
+/// Resource counting the draws of the render groups drawing through `IndirectDraws`, reset
+/// every frame.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DrawCallStats {
+    /// Batches drawn, which is the number of draw calls without merging.
+    pub batches: u32,
+    /// Instances drawn by the batches, one per entity.
+    pub instances: u32,
+    /// Draw calls recorded for the batches.
+    pub draw_calls: u32,
+    /// Batches drawn indirectly.
+    pub indirect: u32,
+}
+
+impl DrawCallStats {
+    /// Count `batches` of `instances` drawn with `draw_calls` calls, indirectly or not.
+    pub fn record(&mut self, batches: u32, instances: u32, draw_calls: u32, indirect: bool) {
+        self.batches += batches;
+        self.instances += instances;
+        self.draw_calls += draw_calls;
+        if indirect {
+            self.indirect += batches;
+        }
+    }
+
+    /// Average number of instances drawn by a batch, 1 when nothing is instanced, or 0 when
+    /// nothing is drawn.
+    pub fn instancing_ratio(&self) -> f32 {
+        if self.batches == 0 {
+            0.
+        } else {
+            self.instances as f32 / self.batches as f32
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// The draws of the batches of a render group, written to an indirect buffer of each frame.
+#[derive(Debug)]
+pub struct IndirectDraws<B: Backend> {
+    support: IndirectSupport,
+    draws: Vec<IndirectDraw>,
+    per_image: Vec<Option<Escape<Buffer<B>>>>,
+}
+
+impl<B: Backend> IndirectDraws<B> {
+    /// Draws supported by the device of `factory`.
+    pub fn new(factory: &Factory<B>) -> Self {
+        Self {
+            support: IndirectSupport::new(factory),
+            draws: Vec::new(),
+            per_image: Vec::new(),
+        }
+    }
+
+    /// The support of the device.
+    pub fn support(&self) -> IndirectSupport {
+        self.support
+    }
+
+    /// Remove the draws of the previous frame.
+    pub fn clear(&mut self) {
+        self.draws.clear();
+    }
+
+    /// Add the draw of a batch, returning its slot.
+    pub fn push(&mut self, draw: IndirectDraw) -> u32 {
+        self.draws.push(draw);
+        self.draws.len() as u32 - 1
+    }
+
+    /// Number of draws pushed.
+    pub fn len(&self) -> u32 {
+        self.draws.len() as u32
+    }
+
+    /// Whether no draw was pushed.
+    pub fn is_empty(&self) -> bool {
+        self.draws.is_empty()
+    }
+
+    /// Number of instances drawn by all the draws pushed.
+    pub fn instances(&self) -> u32 {
+        self.draws.iter().map(IndirectDraw::instance_count).sum()
+    }
+
+    /// Number of calls drawing the slots `slots`.
+    pub fn calls(&self, slots: Range<u32>) -> u32 {
+        if !self.support.indirect {
+            return slots.end - slots.start;
+        }
+        self.support
+            .calls(&self.draws[slots.start as usize..slots.end as usize])
+            .len() as u32
+    }
+
+    /// Add the calls, batches, instances and triangles of the slots `slots` to `stats`.
+    pub fn count(&self, slots: Range<u32>, stats: &mut PassStats) {
+        stats.draw_calls += self.calls(slots.clone());
+        for draw in &self.draws[slots.start as usize..slots.end as usize] {
+            stats.batches += 1;
+            stats.instances += draw.instance_count();
+            stats.triangles += draw.triangles();
+        }
+    }
+
+    /// Write the draws to the buffer of the frame `index`. Returns whether the buffer was
+    /// created, so command buffers reading the previous one must be recorded again.
+    pub fn write(&mut self, factory: &Factory<B>, index: usize) -> bool {
+        if !self.support.indirect || self.draws.is_empty() {
+            return false;
+        }
+        while self.per_image.len() <= index {
+            self.per_image.push(None);
+        }
+        let buffer = &mut self.per_image[index];
+        let size = u64::from(self.len()) * INDIRECT_SLOT_SIZE;
+        let allocated =
+            util::ensure_buffer(factory, buffer, hal::buffer::Usage::INDIRECT, Dynamic, size)
+                .unwrap_or_else(|e| {
+                    log::error!("Failed to allocate an indirect buffer: {}", e);
+                    false
+                });
+        if let Some(buffer) = buffer {
+            let slots: Vec<[u32; 5]> = self.draws.iter().map(IndirectDraw::slot).collect();
+            let bytes = util::slice_as_bytes(&slots);
+            let mut mapped = buffer.map(factory.device(), 0..size).unwrap();
+            unsafe {
+                let mut writer = mapped.write::<u8>(factory.device(), 0..size).unwrap();
+                writer.slice().copy_from_slice(bytes);
+            }
+        }
+        allocated
+    }
+
+    /// Draw the slots `slots` of the frame `index`, with the mesh they draw bound.
+    pub fn draw(&self, index: usize, slots: Range<u32>, encoder: &mut RenderPassEncoder<'_, B>) {
+        let draws = &self.draws[slots.start as usize..slots.end as usize];
+        let buffer = match self.per_image.get(index) {
+            Some(Some(buffer)) if self.support.indirect => buffer,
+            _ => {
+                for draw in draws {
+                    unsafe {
+                        draw.draw_direct(encoder);
+                    }
+                }
+                return;
+            }
+        };
+        for call in self.support.calls(draws) {
+            let offset = u64::from(slots.start + call.start) * INDIRECT_SLOT_SIZE;
+            let count = call.end - call.start;
+            unsafe {
+                if draws[call.start as usize].indexed() {
+                    encoder.draw_indexed_indirect(
+                        buffer.raw(),
+                        offset,
+                        count,
+                        INDIRECT_SLOT_SIZE as u32,
+                    );
+                } else {
+                    encoder.draw_indirect(buffer.raw(), offset, count, INDIRECT_SLOT_SIZE as u32);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_and_calls() {
+        let indexed = IndirectDraw::new(36, true, 4..10);
+        assert_eq!(indexed.slot(), [36, 6, 0, 0, 4]);
+        let vertices = IndirectDraw::new(6, false, 0..2);
+        assert_eq!(vertices.slot(), [6, 2, 0, 0, 0]);
+        assert_eq!(IndirectDraw::empty().slot(), [0; 5]);
+        assert_eq!(indexed.triangles(), 72);
+        assert_eq!(vertices.triangles(), 4);
+
+        let draws = [indexed, indexed, vertices, indexed];
+        let single = IndirectSupport {
+            indirect: true,
+            multi_draw: false,
+        };
+        assert_eq!(single.calls(&draws).len(), 4);
+        let multi = IndirectSupport {
+            indirect: true,
+            multi_draw: true,
+        };
+        assert_eq!(multi.calls(&draws), vec![0..2, 2..3, 3..4]);
+
+        let mut stats = DrawCallStats::default();
+        stats.record(4, 12, 3, true);
+        stats.record(2, 2, 2, false);
+        assert_eq!(
+            stats,
+            DrawCallStats {
+                batches: 6,
+                instances: 14,
+                draw_calls: 5,
+                indirect: 4,
+            }
+        );
+        assert_eq!(stats.instancing_ratio(), 14. / 6.);
+        assert_eq!(DrawCallStats::default().instancing_ratio(), 0.);
+    }
+}
@@ -0,0 +1,94 @@
+//! Render layers for selecting which entities are seen by which camera.
+use amethyst_core::ecs::prelude::{Component, DenseVecStorage};
+use serde::{Deserialize, Serialize};
+
+/// Bit mask of the layers an entity is rendered on, and of the layers a `Camera` sees.
+///
+/// An entity is visible to a camera when the layers of the entity and of the camera share at
+/// least one bit. Entities without a `RenderLayers` component and cameras without explicit
+/// layers use `RenderLayers::ALL`, so they see and are seen by everything.
+///
+/// ```
+/// # use amethyst_rendy::layers::RenderLayers;
+/// const MAP: u8 = 1;
+///
+/// let map_entity = RenderLayers::layer(MAP);
+/// let minimap_camera = RenderLayers::layer(MAP);
+/// let main_camera = RenderLayers::ALL.without(MAP);
+///
+/// assert!(map_entity.intersects(minimap_camera));
+/// assert!(!map_entity.intersects(main_camera));
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RenderLayers(u32);
+
+impl RenderLayers {
+    /// All 32 layers.
+    pub const ALL: RenderLayers = RenderLayers(!0);
+    /// No layers, an entity with these layers is never drawn.
+    pub const NONE: RenderLayers = RenderLayers(0);
+
+    /// Create layers from raw bits.
+    pub const fn from_bits(bits: u32) -> Self {
+        RenderLayers(bits)
+    }
+
+    /// Only the given layer, which must be less than 32.
+    pub fn layer(layer: u8) -> Self {
+        RenderLayers::NONE.with(layer)
+    }
+
+    /// Raw bits of the layers.
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Add a layer, which must be less than 32.
+    pub fn with(self, layer: u8) -> Self {
+        debug_assert!(layer < 32, "Render layer {} is out of range", layer);
+        RenderLayers(self.0 | (1 << u32::from(layer)))
+    }
+
+    /// Remove a layer, which must be less than 32.
+    pub fn without(self, layer: u8) -> Self {
+        debug_assert!(layer < 32, "Render layer {} is out of range", layer);
+        RenderLayers(self.0 & !(1 << u32::from(layer)))
+    }
+
+    /// Returns `true` if the given layer is set.
+    pub fn contains(self, layer: u8) -> bool {
+        layer < 32 && self.0 & (1 << u32::from(layer)) != 0
+    }
+
+    /// Returns `true` if both masks share at least one layer.
+    pub fn intersects(self, other: RenderLayers) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        RenderLayers::ALL
+    }
+}
+
+impl Component for RenderLayers {
+    type Storage = DenseVecStorage<Self>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layer_masks() {
+        let layers = RenderLayers::layer(3).with(5);
+        assert_eq!(layers.bits(), 0b101000);
+        assert!(layers.contains(3) && layers.contains(5) && !layers.contains(4));
+        assert!(!layers.without(3).contains(3));
+        assert!(layers.intersects(RenderLayers::default()));
+        assert!(!layers.intersects(RenderLayers::NONE));
+        assert!(!layers.intersects(RenderLayers::layer(4)));
+    }
+}
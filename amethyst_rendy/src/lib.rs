@@ -16,24 +16,113 @@
 //! * [`DrawShadedDesc`](crate::pass::shaded::DrawShadedDesc)
 //! * [`DrawSkyboxDesc`](crate::pass::skybox::DrawSkyboxDesc)
 //! * [`DrawDebugLinesDesc`](crate::pass::debug_lines::DrawDebugLinesDesc)
+//! * [`DrawScreenDebugLinesDesc`](crate::pass::debug_lines::DrawScreenDebugLinesDesc)
+//! * [`CaptureDesc`](crate::capture::CaptureDesc)
+//! * [`ViewDesc`](crate::view::ViewDesc)
+//! * [`GpuTimingDesc`](crate::timing::GpuTimingDesc)
+//! * [`TimedGroupDesc`](crate::timing::TimedGroupDesc)
+//! * [`ComputeNodeDesc`](crate::compute::ComputeNodeDesc)
+//! * [`LabeledGroupDesc`](crate::validation::LabeledGroupDesc)
+//!
+//! ## Bundles and plugins
+//!
+//! * [`RenderingBundle`](crate::bundle::RenderingBundle)
+//! * [`RuntimeRenderingBundle`](crate::backend::RuntimeRenderingBundle)
+//! * [`RenderToWindow`](crate::bundle::RenderToWindow)
+//! * [`RenderToImage`](crate::bundle::RenderToImage)
+//! * [`RenderToSecondaryWindow`](crate::bundle::RenderToSecondaryWindow)
+//! * [`RenderFlat2D`](crate::bundle::RenderFlat2D)
+//! * [`RenderPbr3D`](crate::bundle::RenderPbr3D)
+//! * [`RenderShaded3D`](crate::bundle::RenderShaded3D)
+//! * [`RenderFlat3D`](crate::bundle::RenderFlat3D)
+//! * [`RenderSkybox`](crate::bundle::RenderSkybox)
+//! * [`RenderDebugLines`](crate::bundle::RenderDebugLines)
+//! * [`RenderAutoExposure`](crate::bundle::RenderAutoExposure)
+//! * [`DepthImage`](crate::depth::DepthImage)
+//! * [`ColorImage`](crate::bundle::ColorImage)
 //!
 //! ## Systems
 //!
 //! * [`RenderingSystem`](crate::system::RenderingSystem)
 //! * [`VisibilitySortingSystem`](crate::visibility::VisibilitySortingSystem)
 //! * [`SpriteVisibilitySortingSystem`](crate::sprite_visibility::SpriteVisibilitySortingSystem)
+//! * [`MeshBoundingSphereSystem`](crate::visibility::MeshBoundingSphereSystem)
+//! * [`MeshBoundingBoxSystem`](crate::visibility::MeshBoundingBoxSystem)
+//! * [`MeshLodSystem`](crate::lod::MeshLodSystem)
+//! * [`RibbonSystem`](crate::ribbon::RibbonSystem)
+//! * [`DebugLinesSystem`](crate::debug_drawing::DebugLinesSystem)
+//! * [`MorphSystem`](crate::morph::MorphSystem)
+//! * [`CpuSkinningSystem`](crate::skinning::CpuSkinningSystem)
+//! * [`SkinnedBoundsSystem`](crate::skinning::SkinnedBoundsSystem)
+//! * [`AnimatedTextureSystem`](crate::flipbook::AnimatedTextureSystem)
+//! * [`AssetReadinessSystem`](crate::fade_in::AssetReadinessSystem)
+//! * [`StaticInstancesSystem`](crate::static_instances::StaticInstancesSystem)
+//! * [`GpuTimingLogSystem`](crate::timing::GpuTimingLogSystem)
+//! * `ShaderReloadSystem`, with the `shader-hot-reload` feature
 //!
 //! ## Components
 //!
 //! * [`Camera`](camera::Camera)
 //! * [`SpriteVisibility`](sprite_visibility::SpriteVisibility)
 //! * [`Visibility`](visibility::Visibility)
+//! * [`VisibilityStats`](visibility::VisibilityStats)
+//! * [`RenderLayers`](layers::RenderLayers)
 //! * [`BoundingSphere`](visibility::BoundingSphere)
+//! * [`BoundingBox`](visibility::BoundingBox)
+//! * [`DrawDistance`](visibility::DrawDistance)
+//! * [`NoCull`](visibility::NoCull)
+//! * [`ViewModel`](view_model::ViewModel)
+//! * [`ViewModelProjection`](view_model::ViewModelProjection)
+//! * [`Occluder`](occlusion::Occluder)
+//! * [`MeshLod`](lod::MeshLod)
+//! * [`Ribbon`](ribbon::Ribbon)
+//! * [`MorphTargets`](morph::MorphTargets)
+//! * [`MorphWeights`](morph::MorphWeights)
 //! * [`DebugLinesComponent`](debug_drawing::DebugLinesComponent)
+//! * [`DebugLinesStats`](debug_drawing::DebugLinesStats)
 //! * [`Light`](light::Light)
 //! * [`Tint`](resources::Tint)
+//! * [`TintGradient3D`](resources::TintGradient3D)
+//! * [`Dissolve`](dissolve::Dissolve)
+//! * [`DissolveNoise`](dissolve::DissolveNoise)
 //! * [`JointTransforms`](skinning::JointTransforms)
+//! * [`CpuSkinnedMesh`](skinning::CpuSkinnedMesh)
+//! * [`SkinningPath`](skinning::SkinningPath)
 //! * [`SpriteRender`](sprite::SpriteRender)
+//! * [`SpriteSheetPaths`](sprite::persist::SpriteSheetPaths)
+//! * [`AnimatedTexture`](flipbook::AnimatedTexture)
+//! * [`TextureUploads`](texture_upload::TextureUploads)
+//! * [`TextureUploadStats`](texture_upload::TextureUploadStats)
+//! * [`GpuMemoryStats`](gpu_memory::GpuMemoryStats)
+//! * [`FrameCapture`](capture::FrameCapture)
+//! * [`PresentationConfig`](presentation::PresentationConfig)
+//! * [`WindowCameras`](view::WindowCameras)
+//! * [`RenderView`](view::RenderView)
+//! * [`GpuTimingStats`](timing::GpuTimingStats)
+//! * [`PipelineCache`](pipeline_cache::PipelineCache)
+//! * [`ShaderWatch`](shader_reload::ShaderWatch)
+//! * [`Adapters`](adapter::Adapters)
+//! * [`RenderRecovery`](recovery::RenderRecovery)
+//! * [`RenderControl`](control::RenderControl)
+//! * [`RenderEvent`](events::RenderEvent)
+//! * [`ClearConfig`](clear::ClearConfig)
+//! * [`GpuCullingInput`](culling::GpuCullingInput)
+//! * [`ValidationConfig`](validation::ValidationConfig)
+//! * [`DrawCallStats`](indirect::DrawCallStats)
+//! * [`ValidationReport`](validation::ValidationReport)
+//! * [`VertexBufferStats`](submodules::VertexBufferStats)
+//! * [`UniformRingStats`](submodules::UniformRingStats)
+//! * [`RenderStats3D`](render_stats::RenderStats3D)
+//! * [`ChangeDetectionSet`](util::ChangeDetectionSet)
+//! * [`ContentErrors`](content_errors::ContentErrors)
+//! * [`Deterministic`](deterministic::Deterministic)
+//! * [`FallbackAssets`](fallback::FallbackAssets)
+//! * [`Exposure`](exposure::Exposure)
+//! * [`SceneLuminance`](exposure::SceneLuminance)
+//! * [`FadeInOnLoad`](fade_in::FadeInOnLoad)
+//! * [`AssetReadiness`](fade_in::AssetReadiness)
+//! * [`StaticInstances`](static_instances::StaticInstances)
+//! * [`StaticInstanceSet`](static_instances::StaticInstanceSet)
 
 #![allow(dead_code)]
 #![allow(unused_variables)]
@@ -56,49 +145,135 @@ pub use rendy;
 
 pub mod pass;
 
+pub mod adapter;
+pub mod backend;
 pub mod batch;
+pub mod bundle;
 pub mod camera;
+pub mod capture;
+pub mod clear;
+pub mod compute;
+pub mod content_errors;
+pub mod control;
+pub mod culling;
 pub mod debug_drawing;
+pub mod depth;
+pub mod deterministic;
+pub mod dissolve;
+pub mod dynamic_mesh;
+pub mod dynamic_texture;
 pub mod error;
+pub mod events;
+pub mod exposure;
+pub mod fade_in;
+pub mod fallback;
+pub mod flipbook;
 pub mod formats;
+pub mod golden;
+pub mod gpu_memory;
+pub mod indirect;
+pub mod layers;
 pub mod light;
+pub mod lod;
+pub mod mesh_util;
+pub mod morph;
 pub mod mtl;
+pub mod occlusion;
 pub mod pipeline;
+pub mod pipeline_cache;
+pub mod presentation;
+pub mod recovery;
+pub mod render_stats;
 pub mod resources;
+pub mod ribbon;
 pub mod serde_shim;
+pub mod shader_reload;
 pub mod shape;
 pub mod skinning;
 pub mod sprite;
 pub mod sprite_visibility;
+pub mod static_instances;
 pub mod submodules;
 pub mod system;
+pub mod texture_upload;
+pub mod timing;
 pub mod transparent;
 pub mod types;
+pub mod validation;
+pub mod view;
+pub mod view_model;
 pub mod visibility;
 
 pub mod pod;
 pub mod util;
 
+mod debug_font;
+mod mesh_reader;
+mod spatial;
+
 #[cfg(feature = "test-support")]
 mod render_test_bundle;
 
 #[doc(inline)]
 pub use crate::{
-    camera::{ActiveCamera, Camera},
+    adapter::{AdapterDescription, AdapterKind, AdapterSelection, Adapters},
+    backend::{select_backend, BackendSelection, ConfigureRendering, RuntimeRenderingBundle},
+    bundle::{
+        ColorImage, RenderAutoExposure, RenderDebugLines, RenderFlat2D, RenderFlat3D, RenderOrder,
+        RenderPbr3D, RenderPlan, RenderPlugin, RenderShaded3D, RenderSkybox, RenderToImage,
+        RenderToSecondaryWindow, RenderToWindow, RenderingBundle,
+    },
+    camera::{ActiveCamera, Camera, CullingCamera},
+    capture::{CaptureDesc, CapturedFrame, CapturedImage, FrameCapture},
+    clear::{ClearConfig, ClearDesc},
+    compute::{ComputeAccess, ComputeBinding, ComputeNodeDesc},
+    content_errors::ContentErrors,
+    control::{RenderControl, RenderControlEvent, RenderMode, RenderState},
+    culling::{CulledDraw, DrawIndexedCommand, GpuCullingInput},
+    depth::DepthImage,
+    deterministic::Deterministic,
+    dissolve::{Dissolve, DissolveNoise},
+    events::RenderEvent,
+    exposure::{AutoExposureSystem, Exposure, SceneLuminance},
+    fade_in::{AssetReadiness, AssetReadinessSystem, FadeInOnLoad},
+    fallback::FallbackAssets,
+    flipbook::{AnimatedTexture, AnimatedTextureSystem, Flipbook},
     formats::{
+        channels::ImageChannels,
+        dds::DdsFormat,
+        flipbook::{FrameSequenceFormat, GifFormat},
+        hdr::{ExrFormat, HdrFormat},
+        ktx2::Ktx2Format,
         mesh::MeshPrefab,
-        texture::{ImageFormat, TexturePrefab},
+        texture::{CubemapFormat, ImageFormat, TexturePrefab},
     },
+    indirect::DrawCallStats,
     mtl::{Material, MaterialDefaults},
+    pipeline_cache::PipelineCache,
+    presentation::{PresentMode, PresentationConfig},
+    recovery::RenderRecovery,
+    render_stats::RenderStats3D,
     sprite::{Sprite, SpriteRender, SpriteSheet, SpriteSheetFormat},
+    static_instances::{StaticCulling, StaticInstanceSet, StaticInstances, StaticInstancesSystem},
+    submodules::{UniformRingStats, VertexBufferStats},
     system::{GraphCreator, RenderingSystem},
-    transparent::Transparent,
-    types::{Backend, Mesh, Texture},
-    util::{simple_shader_set, ChangeDetection},
+    timing::{GpuTimingLogSystem, GpuTimingStats, GpuTimingStatus},
+    transparent::{BlendMode, NoTintTransparency, TintTransparency, Transparent},
+    types::{Backend, BackendVariant, BackendVisitor, Mesh, Texture},
+    util::{simple_shader_set, ChangeDetection, ChangeDetectionSet, ChangeWatch},
+    validation::{ValidationConfig, ValidationReport},
+    view::{RenderView, ViewDesc, WindowCameras},
+    view_model::{ViewModel, ViewModelProjection},
 };
 
+#[cfg(feature = "shader-hot-reload")]
+pub use crate::shader_reload::{ShaderReload, ShaderReloadSystem};
+
 #[cfg(feature = "test-support")]
-pub use render_test_bundle::{RenderEmptyBundle, RenderTestBundle};
+pub use render_test_bundle::{
+    capture_frame, capture_scene_frames, render_golden_scene, GoldenScene, RenderEmptyBundle,
+    RenderGoldenTestBundle, RenderHeadlessTestBundle, RenderTestBundle,
+};
 
 pub use rendy::{
     factory::Factory,
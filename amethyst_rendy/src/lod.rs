@@ -0,0 +1,347 @@
+//! Level of detail selection for meshes.
+use crate::{
+    camera::{ActiveCamera, Camera, CullingCamera},
+    types::Mesh,
+    view::WindowCameras,
+    visibility::BoundingSphere,
+};
+use amethyst_assets::Handle;
+use amethyst_core::{
+    ecs::prelude::{
+        Component, DenseVecStorage, Entities, Join, Read, ReadExpect, ReadStorage, System,
+        WriteStorage,
+    },
+    math::{distance, Point3},
+    Transform,
+};
+use amethyst_window::{ScreenDimensions, SecondaryWindows};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Metric used by `MeshLod` to pick a level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LodSelection {
+    /// Switch levels based on the distance of the bounding sphere center to the camera.
+    /// Level thresholds are distances, in ascending order.
+    Distance,
+    /// Switch levels based on the estimated height in pixels covered by the bounding sphere.
+    /// Level thresholds are pixel sizes, in descending order.
+    ScreenSize,
+}
+
+impl Default for LodSelection {
+    fn default() -> Self {
+        LodSelection::Distance
+    }
+}
+
+/// A single level of a `MeshLod`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LodLevel {
+    /// Mesh drawn for this level.
+    pub mesh: Handle<Mesh>,
+    /// Threshold at which the next, less detailed level takes over.
+    ///
+    /// For `LodSelection::Distance` this is the distance beyond which the next level is used, for
+    /// `LodSelection::ScreenSize` the pixel size below which the next level is used. Ignored for
+    /// the last level.
+    pub threshold: f32,
+}
+
+/// Component selecting the `Handle<Mesh>` of an entity from a list of levels ordered from most to
+/// least detailed, see `MeshLodSystem`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshLod {
+    /// Levels ordered from most to least detailed.
+    pub levels: Vec<LodLevel>,
+    /// Metric used to select a level.
+    pub selection: LodSelection,
+    /// Fraction of a threshold that has to be crossed before switching to another level, used
+    /// to prevent popping when the entity sits at a boundary. `0.0` disables hysteresis.
+    pub hysteresis: f32,
+    current: usize,
+}
+
+impl Component for MeshLod {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl MeshLod {
+    /// Create a distance based `MeshLod` from `(mesh, threshold)` pairs ordered from most to least
+    /// detailed.
+    pub fn new(levels: impl IntoIterator<Item = (Handle<Mesh>, f32)>) -> Self {
+        Self {
+            levels: levels
+                .into_iter()
+                .map(|(mesh, threshold)| LodLevel { mesh, threshold })
+                .collect(),
+            selection: LodSelection::Distance,
+            hysteresis: 0.0,
+            current: 0,
+        }
+    }
+
+    /// Set the selection metric.
+    pub fn with_selection(mut self, selection: LodSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Set the hysteresis fraction.
+    pub fn with_hysteresis(mut self, hysteresis: f32) -> Self {
+        self.hysteresis = hysteresis;
+        self
+    }
+
+    /// Index of the currently selected level.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// Mesh of the currently selected level, if there are any levels.
+    pub fn current_mesh(&self) -> Option<&Handle<Mesh>> {
+        self.levels.get(self.current).map(|l| &l.mesh)
+    }
+
+    /// Update the selected level from a distance or pixel size, depending on `selection`, and
+    /// return the new level index.
+    pub fn select(&mut self, metric: f32) -> usize {
+        let boundaries = self.levels.len().saturating_sub(1);
+        self.current = select_level(
+            self.levels[..boundaries].iter().map(|l| l.threshold),
+            self.selection,
+            self.hysteresis,
+            self.current,
+            metric,
+        );
+        self.current
+    }
+}
+
+fn select_level(
+    thresholds: impl Iterator<Item = f32>,
+    selection: LodSelection,
+    hysteresis: f32,
+    current: usize,
+    metric: f32,
+) -> usize {
+    // Map both metrics onto a value that grows as less detail is required
+    let (value, sign) = match selection {
+        LodSelection::Distance => (metric, 1.0),
+        LodSelection::ScreenSize => (-metric, -1.0),
+    };
+    thresholds
+        .enumerate()
+        .filter(|(i, threshold)| {
+            let threshold = threshold * sign;
+            let band = threshold.abs() * hysteresis;
+            if *i < current {
+                value > threshold - band
+            } else {
+                value > threshold + band
+            }
+        })
+        .count()
+}
+
+/// Estimate the height in pixels covered by a sphere at the given distance, using the vertical
+/// scale of the projection matrix.
+pub fn screen_size(radius: f32, distance: f32, projection_scale: f32, screen_height: f32) -> f32 {
+    if distance <= radius {
+        return std::f32::INFINITY;
+    }
+    radius * projection_scale * screen_height / distance
+}
+
+/// Selects the level of every `MeshLod` and writes the selected mesh into the entity's
+/// `Handle<Mesh>`, so that it is gathered and batched like any other mesh.
+///
+/// Levels are selected for the camera the visibility systems cull for, the one of
+/// `CullingCamera` if set, of `ActiveCamera` otherwise, and for the cameras of `WindowCameras`.
+/// As all views draw the same mesh, the most detailed level required by any of them is used.
+///
+/// Entities without a `BoundingSphere` use a unit sphere at their origin. The sphere radius is
+/// scaled by the largest axis scale of the transform, like in `VisibilitySortingSystem`.
+///
+/// Note that this should run after `Transform` has been updated for the current frame, and
+/// before rendering occurs.
+#[derive(Default, Debug)]
+pub struct MeshLodSystem;
+
+impl MeshLodSystem {
+    /// Create new level of detail system
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for MeshLodSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, ActiveCamera>,
+        Read<'a, CullingCamera>,
+        Read<'a, WindowCameras>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, BoundingSphere>,
+        WriteStorage<'a, MeshLod>,
+        WriteStorage<'a, Handle<Mesh>>,
+        ReadExpect<'a, ScreenDimensions>,
+        Option<Read<'a, SecondaryWindows>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            active,
+            culling,
+            window_cameras,
+            camera,
+            transform,
+            bound,
+            mut lods,
+            mut meshes,
+            dimensions,
+            secondary_windows,
+        ): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("mesh_lod_system");
+
+        let origin = Point3::origin();
+        let mut camera_join = (&camera, &transform).join();
+        let main = match culling
+            .entity
+            .or(active.entity)
+            .and_then(|a| camera_join.get(a, &entities))
+            .or_else(|| camera_join.next())
+        {
+            Some(camera) => (camera, dimensions.height()),
+            None => return,
+        };
+        // Windows without dimensions are not open yet, their camera is not drawn.
+        let windows = window_cameras.iter().filter_map(|(window, entity)| {
+            let height = secondary_windows
+                .as_ref()
+                .and_then(|windows| windows.dimensions(window))?
+                .height();
+            Some((camera_join.get(entity, &entities)?, height))
+        });
+        let views = Some(main)
+            .into_iter()
+            .chain(windows)
+            .map(|((camera, camera_transform), height)| {
+                (
+                    camera_transform.global_matrix().transform_point(&origin),
+                    camera.as_matrix()[(1, 1)].abs(),
+                    height,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        for (entity, lod, transform, sphere) in
+            (&entities, &mut lods, &transform, bound.maybe()).join()
+        {
+            let matrix = transform.global_matrix();
+            let center = matrix.transform_point(sphere.map_or(&origin, |s| &s.center));
+            let radius = sphere.map_or(1.0, |s| s.radius.as_f32())
+                * matrix[(0, 0)]
+                    .max(matrix[(1, 1)])
+                    .max(matrix[(2, 2)])
+                    .as_f32();
+            let metrics = views
+                .iter()
+                .map(|(camera_centroid, projection_scale, height)| {
+                    let dist = distance(&center, camera_centroid).as_f32();
+                    match lod.selection {
+                        LodSelection::Distance => dist,
+                        LodSelection::ScreenSize => {
+                            screen_size(radius, dist, *projection_scale, *height)
+                        }
+                    }
+                });
+            // The closest, or largest, view requires the most detail.
+            let metric = match lod.selection {
+                LodSelection::Distance => metrics.fold(std::f32::INFINITY, f32::min),
+                LodSelection::ScreenSize => metrics.fold(0.0, f32::max),
+            };
+
+            let previous = lod.current();
+            let current = lod.select(metric);
+            let mesh = match lod.current_mesh() {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            if current != previous || meshes.get(entity) != Some(mesh) {
+                if let Err(e) = meshes.insert(entity, mesh.clone()) {
+                    log::error!("Failed to insert selected level of detail mesh: {}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DISTANCES: [f32; 2] = [10.0, 50.0];
+    const PIXELS: [f32; 2] = [200.0, 40.0];
+
+    fn distance_level(current: usize, metric: f32, hysteresis: f32) -> usize {
+        select_level(
+            DISTANCES.iter().cloned(),
+            LodSelection::Distance,
+            hysteresis,
+            current,
+            metric,
+        )
+    }
+
+    #[test]
+    fn distance_selection() {
+        assert_eq!(distance_level(0, 5.0, 0.0), 0);
+        assert_eq!(distance_level(0, 20.0, 0.0), 1);
+        assert_eq!(distance_level(0, 200.0, 0.0), 2);
+        assert_eq!(distance_level(2, 5.0, 0.0), 0);
+    }
+
+    #[test]
+    fn distance_hysteresis() {
+        // Moving away, the switch happens past the threshold plus the band
+        assert_eq!(distance_level(0, 10.5, 0.1), 0);
+        assert_eq!(distance_level(0, 11.5, 0.1), 1);
+        // Moving closer, the switch happens before the threshold minus the band
+        assert_eq!(distance_level(1, 9.5, 0.1), 1);
+        assert_eq!(distance_level(1, 8.5, 0.1), 0);
+        assert_eq!(distance_level(1, 54.0, 0.1), 1);
+        assert_eq!(distance_level(1, 56.0, 0.1), 2);
+    }
+
+    #[test]
+    fn screen_size_selection() {
+        let level = |current, pixels| {
+            select_level(
+                PIXELS.iter().cloned(),
+                LodSelection::ScreenSize,
+                0.1,
+                current,
+                pixels,
+            )
+        };
+        assert_eq!(level(0, 500.0), 0);
+        assert_eq!(level(0, 190.0), 0);
+        assert_eq!(level(0, 170.0), 1);
+        assert_eq!(level(1, 210.0), 1);
+        assert_eq!(level(1, 230.0), 0);
+        assert_eq!(level(1, 10.0), 2);
+    }
+
+    #[test]
+    fn screen_size_estimate() {
+        assert_eq!(screen_size(1.0, 10.0, 1.0, 1000.0), 100.0);
+        assert_eq!(screen_size(1.0, 0.5, 1.0, 1000.0), std::f32::INFINITY);
+    }
+}
@@ -0,0 +1,479 @@
+//! Reading the vertex and index buffers back from a rendy `MeshBuilder`.
+//!
+//! The builder keeps its buffers private, but serializes them along with their vertex formats.
+//! `read` walks the serialized form, keeping every byte buffer together with the format or index
+//! type that follows it.
+
+use crate::types::MeshLayout;
+use rendy::{
+    hal::{IndexType, Primitive},
+    mesh::{
+        AsVertex, Color, MeshBuilder, Normal, PosColor, PosColorNorm, PosNormTangTex, PosNormTex,
+        PosTex, Position, Tangent, TexCoord, VertexFormat,
+    },
+};
+use serde::{
+    de::DeserializeOwned,
+    ser::{self, Serialize},
+};
+use std::{error, fmt};
+
+/// Vertex attribute stored in a buffer whose format is known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Attribute {
+    Position,
+    Normal,
+    Tangent,
+    TexCoord,
+    Color,
+}
+
+/// Vertex and index buffers of a `MeshBuilder`.
+#[derive(Debug, Default)]
+pub(crate) struct MeshBuffers {
+    /// Vertex buffers with their format.
+    pub vertices: Vec<(VertexFormat, Vec<u8>)>,
+    /// Index buffer with its index type.
+    pub indices: Option<(IndexType, Vec<u8>)>,
+    /// Primitive topology, if the builder tells it.
+    pub primitive: Option<Primitive>,
+}
+
+impl MeshBuffers {
+    /// Number of vertices, taken from the first vertex buffer.
+    pub fn vertex_count(&self) -> usize {
+        self.vertices
+            .first()
+            .map_or(0, |(format, bytes)| match format.stride {
+                0 => 0,
+                stride => bytes.len() / stride as usize,
+            })
+    }
+
+    /// Number of indices, `0` without index buffer.
+    pub fn index_count(&self) -> usize {
+        self.indices.as_ref().map_or(0, |(index_type, bytes)| {
+            bytes.len() / index_size(*index_type)
+        })
+    }
+
+    /// Layout of the buffers.
+    pub fn layout(&self) -> MeshLayout {
+        MeshLayout {
+            vertex_count: self.vertex_count() as u32,
+            index_count: self.index_count() as u32,
+            index_type: self.indices.as_ref().map(|(index_type, _)| *index_type),
+            vertex_formats: self
+                .vertices
+                .iter()
+                .map(|(format, _)| format.clone())
+                .collect(),
+        }
+    }
+
+    /// Values of `attribute` in the first buffer holding it, `A` being an array of as many
+    /// floats as the attribute has components.
+    pub fn attribute<A>(&self, attribute: Attribute) -> Option<Vec<A>>
+    where
+        A: Default + AsMut<[f32]>,
+    {
+        self.vertices.iter().find_map(|(format, bytes)| {
+            attributes(format)?
+                .iter()
+                .find(|(a, _)| *a == attribute)
+                .map(|(_, offset)| read_floats(bytes, format.stride as usize, *offset))
+        })
+    }
+
+    /// Indices widened to 32 bit.
+    pub fn indices_u32(&self) -> Option<Vec<u32>> {
+        self.indices.as_ref().map(|(index_type, bytes)| {
+            let size = index_size(*index_type);
+            bytes
+                .chunks_exact(size)
+                .map(|c| match index_type {
+                    IndexType::U16 => u32::from(u16::from_ne_bytes([c[0], c[1]])),
+                    IndexType::U32 => u32::from_ne_bytes([c[0], c[1], c[2], c[3]]),
+                })
+                .collect()
+        })
+    }
+}
+
+fn index_size(index_type: IndexType) -> usize {
+    match index_type {
+        IndexType::U16 => 2,
+        IndexType::U32 => 4,
+    }
+}
+
+/// Attributes and their byte offsets for the vertex formats of rendy whose layout is known,
+/// `None` for other formats.
+pub(crate) fn attributes(format: &VertexFormat) -> Option<&'static [(Attribute, usize)]> {
+    use self::Attribute as A;
+
+    let known: [(VertexFormat, &'static [(Attribute, usize)]); 10] = [
+        (Position::vertex(), &[(A::Position, 0)]),
+        (Normal::vertex(), &[(A::Normal, 0)]),
+        (Tangent::vertex(), &[(A::Tangent, 0)]),
+        (TexCoord::vertex(), &[(A::TexCoord, 0)]),
+        (Color::vertex(), &[(A::Color, 0)]),
+        (PosTex::vertex(), &[(A::Position, 0), (A::TexCoord, 12)]),
+        (
+            PosNormTex::vertex(),
+            &[(A::Position, 0), (A::Normal, 12), (A::TexCoord, 24)],
+        ),
+        (
+            PosNormTangTex::vertex(),
+            &[
+                (A::Position, 0),
+                (A::Normal, 12),
+                (A::Tangent, 24),
+                (A::TexCoord, 40),
+            ],
+        ),
+        (PosColor::vertex(), &[(A::Position, 0), (A::Color, 12)]),
+        (
+            PosColorNorm::vertex(),
+            &[(A::Position, 0), (A::Color, 12), (A::Normal, 28)],
+        ),
+    ];
+    known
+        .iter()
+        .find(|(known, _)| known == format)
+        .map(|(_, attributes)| *attributes)
+}
+
+fn read_floats<A: Default + AsMut<[f32]>>(bytes: &[u8], stride: usize, offset: usize) -> Vec<A> {
+    if stride == 0 {
+        return Vec::new();
+    }
+    bytes
+        .chunks_exact(stride)
+        .map(|vertex| {
+            let mut value = A::default();
+            for (i, float) in value.as_mut().iter_mut().enumerate() {
+                let at = offset + i * 4;
+                *float = f32::from_bits(u32::from_ne_bytes([
+                    vertex[at],
+                    vertex[at + 1],
+                    vertex[at + 2],
+                    vertex[at + 3],
+                ]));
+            }
+            value
+        })
+        .collect()
+}
+
+/// Read the buffers of `builder`, `None` if its serialized form can't be understood.
+pub(crate) fn read(builder: &MeshBuilder<'_>) -> Option<MeshBuffers> {
+    let mut reader = Reader::default();
+    match builder.serialize(&mut reader) {
+        Ok(()) => Some(reader.buffers),
+        Err(e) => {
+            log::debug!("Failed to read back mesh builder: {}", e);
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ReadError(String);
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.write_str(&self.0)
+    }
+}
+
+impl error::Error for ReadError {}
+
+impl ser::Error for ReadError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ReadError(msg.to_string())
+    }
+}
+
+/// Convert a serialized value to `T` by going through its RON representation.
+fn convert<T: Serialize + ?Sized, U: DeserializeOwned>(value: &T) -> Result<U, ReadError> {
+    let ron = ron::ser::to_string(value).map_err(|e| ReadError(e.to_string()))?;
+    ron::de::from_str(&ron).map_err(|e| ReadError(e.to_string()))
+}
+
+/// Serializer keeping the byte buffers it is given, and pairing them with the vertex format
+/// or index type serialized right after them.
+#[derive(Default)]
+struct Reader {
+    bytes: Option<Vec<u8>>,
+    buffers: MeshBuffers,
+}
+
+impl Reader {
+    fn take_bytes(&mut self, field: &str) -> Result<Vec<u8>, ReadError> {
+        self.bytes
+            .take()
+            .ok_or_else(|| ReadError(format!("`{}` without a buffer before it", field)))
+    }
+}
+
+macro_rules! ignore_values {
+    ($($method:ident: $type:ty,)*) => {
+        $(
+            fn $method(self, _: $type) -> Result<(), ReadError> {
+                Ok(())
+            }
+        )*
+    };
+}
+
+impl<'a> ser::Serializer for &'a mut Reader {
+    type Ok = ();
+    type Error = ReadError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Self;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Self;
+
+    ignore_values! {
+        serialize_bool: bool,
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str,
+        serialize_unit_struct: &'static str,
+    }
+
+    // Byte buffers serialized as sequences are collected as well.
+    fn serialize_u8(self, byte: u8) -> Result<(), ReadError> {
+        self.bytes.get_or_insert_with(Vec::new).push(byte);
+        Ok(())
+    }
+
+    fn serialize_bytes(self, bytes: &[u8]) -> Result<(), ReadError> {
+        self.bytes = Some(bytes.to_vec());
+        Ok(())
+    }
+
+    fn serialize_none(self) -> Result<(), ReadError> {
+        Ok(())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), ReadError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), ReadError> {
+        Ok(())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<(), ReadError> {
+        Ok(())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<(), ReadError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        value: &T,
+    ) -> Result<(), ReadError> {
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self, ReadError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self, ReadError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self, ReadError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self, ReadError> {
+        Ok(self)
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self, ReadError> {
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self, ReadError> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self, ReadError> {
+        Ok(self)
+    }
+}
+
+impl<'a> ser::SerializeStruct for &'a mut Reader {
+    type Ok = ();
+    type Error = ReadError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ReadError> {
+        match key {
+            "format" => {
+                let format = convert(value)?;
+                let bytes = self.take_bytes(key)?;
+                self.buffers.vertices.push((format, bytes));
+                Ok(())
+            }
+            "index_type" => {
+                let index_type = convert(value)?;
+                let bytes = self.take_bytes(key)?;
+                self.buffers.indices = Some((index_type, bytes));
+                Ok(())
+            }
+            "prim" => {
+                self.buffers.primitive = Some(convert(value)?);
+                Ok(())
+            }
+            _ => value.serialize(&mut **self),
+        }
+    }
+
+    fn end(self) -> Result<(), ReadError> {
+        Ok(())
+    }
+}
+
+macro_rules! forward_compound {
+    ($($trait:ident::$method:ident),*) => {
+        $(
+            impl<'a> ser::$trait for &'a mut Reader {
+                type Ok = ();
+                type Error = ReadError;
+
+                fn $method<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ReadError> {
+                    value.serialize(&mut **self)
+                }
+
+                fn end(self) -> Result<(), ReadError> {
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+forward_compound!(
+    SerializeSeq::serialize_element,
+    SerializeTuple::serialize_element,
+    SerializeTupleStruct::serialize_field,
+    SerializeTupleVariant::serialize_field
+);
+
+impl<'a> ser::SerializeMap for &'a mut Reader {
+    type Ok = ();
+    type Error = ReadError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), ReadError> {
+        key.serialize(&mut **self)
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ReadError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), ReadError> {
+        Ok(())
+    }
+}
+
+impl<'a> ser::SerializeStructVariant for &'a mut Reader {
+    type Ok = ();
+    type Error = ReadError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<(), ReadError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), ReadError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_separate_and_interleaved() {
+        let mut builder = MeshBuilder::new();
+        builder.add_vertices(vec![
+            PosNormTex {
+                position: [1.0, 2.0, 3.0].into(),
+                normal: [0.0, 1.0, 0.0].into(),
+                tex_coord: [0.5, 0.25].into(),
+            };
+            3
+        ]);
+        builder.add_vertices(vec![Color([0.1, 0.2, 0.3, 1.0]); 3]);
+        builder.set_indices(vec![0u16, 2, 1]);
+
+        let buffers = read(&builder).unwrap();
+        assert_eq!(buffers.vertex_count(), 3);
+        assert_eq!(buffers.index_count(), 3);
+        assert_eq!(buffers.indices_u32(), Some(vec![0, 2, 1]));
+        assert_eq!(
+            buffers.attribute::<[f32; 3]>(Attribute::Position),
+            Some(vec![[1.0, 2.0, 3.0]; 3])
+        );
+        assert_eq!(
+            buffers.attribute::<[f32; 2]>(Attribute::TexCoord),
+            Some(vec![[0.5, 0.25]; 3])
+        );
+        assert_eq!(
+            buffers.attribute::<[f32; 4]>(Attribute::Color),
+            Some(vec![[0.1, 0.2, 0.3, 1.0]; 3])
+        );
+        assert_eq!(buffers.attribute::<[f32; 4]>(Attribute::Tangent), None);
+    }
+}
@@ -0,0 +1,1388 @@
+//! Utilities for processing raw mesh vertex data before upload.
+use crate::{
+    error::MeshError,
+    mesh_reader::{self, Attribute},
+    mtl::Material,
+    types::{Mesh, MeshData, MeshLayout},
+    util,
+    visibility::{BoundingBox, BoundingSphere},
+};
+use amethyst_assets::{AssetStorage, Handle, Loader};
+use amethyst_core::{
+    ecs::prelude::{Entities, Entity, Read, ReadExpect, ReadStorage, WriteStorage},
+    math::{convert, zero, Matrix3, Matrix4, Point3, Vector2, Vector3, U3},
+    Hidden, Transform,
+};
+use fnv::FnvHashMap;
+use rendy::{
+    hal::{IndexType, Primitive},
+    mesh::{AsVertex, Color, MeshBuilder, Normal, Position, Tangent, TexCoord},
+};
+use std::borrow::Cow;
+
+const EPSILON: f32 = 1e-12;
+
+fn vertex_index(indices: Option<&[u32]>, face: usize, vert: usize) -> usize {
+    match indices {
+        Some(indices) => indices[face * 3 + vert] as usize,
+        None => face * 3 + vert,
+    }
+}
+
+fn perpendicular(normal: &Vector3<f32>) -> Vector3<f32> {
+    let axis = if normal.x.abs() < 0.9 {
+        Vector3::x()
+    } else {
+        Vector3::y()
+    };
+    (axis - normal * normal.dot(&axis)).normalize()
+}
+
+/// Smoothing mode used by `recompute_normals`.
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum NormalSmoothing {
+    /// Every vertex gets the area weighted normal of the triangles referencing it. Vertices are
+    /// not welded, so faces only get hard edges where their vertices are not shared, see
+    /// `ProceduralMesh::unweld`.
+    Flat,
+    /// Vertices at the same position are welded and get the angle weighted average normal of the
+    /// surrounding triangles.
+    AngleWeighted {
+        /// Triangles whose normal deviates more than this angle, in radians, from the normal of
+        /// a triangle referencing the vertex do not contribute to it, preserving hard edges.
+        #[serde(default)]
+        hard_edge_angle: Option<f32>,
+    },
+}
+
+/// Recompute per-vertex normals of a triangle list from its positions.
+///
+/// The vertex count stays the same, which allows reusing this on every update of a deforming
+/// mesh. Vertices without any non-degenerate triangle get a +Y normal.
+///
+/// ### Parameters:
+///
+/// - `positions`: Vertex positions
+/// - `indices`: Triangle list indices, or `None` if every three vertices form a triangle
+/// - `smoothing`: How the normals of neighbouring triangles are combined
+pub fn recompute_normals(
+    positions: &[Position],
+    indices: Option<&[u32]>,
+    smoothing: NormalSmoothing,
+) -> Vec<Normal> {
+    let num_faces = indices.map_or(positions.len(), |i| i.len()) / 3;
+    let faces = (0..num_faces)
+        .map(|face| {
+            let idx = [
+                vertex_index(indices, face, 0),
+                vertex_index(indices, face, 1),
+                vertex_index(indices, face, 2),
+            ];
+            let p = [
+                Vector3::from(positions[idx[0]].0),
+                Vector3::from(positions[idx[1]].0),
+                Vector3::from(positions[idx[2]].0),
+            ];
+            // Length of the cross product is twice the area, used as weight in flat mode
+            let cross = (p[1] - p[0]).cross(&(p[2] - p[0]));
+            (idx, p, cross)
+        })
+        .filter(|(_, _, cross)| cross.norm_squared() > EPSILON)
+        .collect::<Vec<_>>();
+
+    let mut normals = vec![zero::<Vector3<f32>>(); positions.len()];
+    match smoothing {
+        NormalSmoothing::Flat => {
+            for (idx, _, cross) in &faces {
+                for &i in idx {
+                    normals[i] += cross;
+                }
+            }
+        }
+        NormalSmoothing::AngleWeighted { hard_edge_angle } => {
+            // Weld vertices by position, collecting the faces around every welded position
+            let key = |p: &Position| {
+                let [x, y, z] = p.0;
+                (
+                    (x * 1e5).round() as i64,
+                    (y * 1e5).round() as i64,
+                    (z * 1e5).round() as i64,
+                )
+            };
+            let mut welded = FnvHashMap::<_, Vec<(usize, usize)>>::default();
+            for (f, (idx, _, _)) in faces.iter().enumerate() {
+                for (corner, &i) in idx.iter().enumerate() {
+                    welded
+                        .entry(key(&positions[i]))
+                        .or_default()
+                        .push((f, corner));
+                }
+            }
+            let cos_limit = hard_edge_angle.map(f32::cos);
+
+            for (f, (idx, _, cross)) in faces.iter().enumerate() {
+                let own = cross.normalize();
+                for &i in idx {
+                    let mut normal = zero::<Vector3<f32>>();
+                    for &(other, corner) in &welded[&key(&positions[i])] {
+                        let (_, p, other_cross) = &faces[other];
+                        let other_normal = other_cross.normalize();
+                        if other != f {
+                            if let Some(cos_limit) = cos_limit {
+                                if own.dot(&other_normal) < cos_limit {
+                                    continue;
+                                }
+                            }
+                        }
+                        let a = p[(corner + 1) % 3] - p[corner];
+                        let b = p[(corner + 2) % 3] - p[corner];
+                        normal += other_normal * a.angle(&b);
+                    }
+                    // Shared vertices get contributions from each referencing face, which all
+                    // agree unless hard edges split the neighbourhood.
+                    normals[i] += normal;
+                }
+            }
+        }
+    }
+
+    normals
+        .into_iter()
+        .map(|n| {
+            if n.norm_squared() > EPSILON {
+                Normal(n.normalize().into())
+            } else {
+                Normal([0.0, 1.0, 0.0])
+            }
+        })
+        .collect()
+}
+
+/// Generate per-vertex tangents for a triangle list.
+///
+/// Follows the MikkTSpace conventions: face tangents are normalized and weighted by the corner
+/// angle of each vertex, then orthogonalized against the vertex normal. The `w` component holds
+/// the handedness of the tangent basis, such that `bitangent = cross(normal, tangent) * w`, which
+/// is what the builtin shaders expect.
+///
+/// Triangles with zero area or degenerate texture coordinates do not contribute. Vertices
+/// without any contributing triangle get an arbitrary tangent perpendicular to their normal.
+///
+/// Unlike a full MikkTSpace implementation vertices are never split, so a vertex shared between
+/// triangles with mirrored texture coordinates receives the averaged basis of the majority.
+///
+/// ### Parameters:
+///
+/// - `positions`, `normals`, `tex_coords`: Vertex attributes, all of the same length
+/// - `indices`: Triangle list indices, or `None` if every three vertices form a triangle
+pub fn generate_tangents(
+    positions: &[Position],
+    normals: &[Normal],
+    tex_coords: &[TexCoord],
+    indices: Option<&[u32]>,
+) -> Vec<Tangent> {
+    debug_assert_eq!(positions.len(), normals.len());
+    debug_assert_eq!(positions.len(), tex_coords.len());
+
+    let mut tangents = vec![zero::<Vector3<f32>>(); positions.len()];
+    let mut bitangents = vec![zero::<Vector3<f32>>(); positions.len()];
+    let num_faces = indices.map_or(positions.len(), |i| i.len()) / 3;
+
+    for face in 0..num_faces {
+        let idx = [
+            vertex_index(indices, face, 0),
+            vertex_index(indices, face, 1),
+            vertex_index(indices, face, 2),
+        ];
+        let p = [
+            Vector3::from(positions[idx[0]].0),
+            Vector3::from(positions[idx[1]].0),
+            Vector3::from(positions[idx[2]].0),
+        ];
+        let uv = [
+            Vector2::from(tex_coords[idx[0]].0),
+            Vector2::from(tex_coords[idx[1]].0),
+            Vector2::from(tex_coords[idx[2]].0),
+        ];
+
+        let e1 = p[1] - p[0];
+        let e2 = p[2] - p[0];
+        let d1 = uv[1] - uv[0];
+        let d2 = uv[2] - uv[0];
+        let det = d1.x * d2.y - d2.x * d1.y;
+        if det.abs() < EPSILON || e1.cross(&e2).norm_squared() < EPSILON {
+            continue;
+        }
+
+        let tangent = ((e1 * d2.y - e2 * d1.y) / det).normalize();
+        let bitangent = ((e2 * d1.x - e1 * d2.x) / det).normalize();
+
+        for corner in 0..3 {
+            let a = p[(corner + 1) % 3] - p[corner];
+            let b = p[(corner + 2) % 3] - p[corner];
+            let angle = a.angle(&b);
+            tangents[idx[corner]] += tangent * angle;
+            bitangents[idx[corner]] += bitangent * angle;
+        }
+    }
+
+    tangents
+        .into_iter()
+        .zip(bitangents)
+        .zip(normals)
+        .map(|((tangent, bitangent), normal)| {
+            let normal = Vector3::from(normal.0);
+            let tangent = tangent - normal * normal.dot(&tangent);
+            if tangent.norm_squared() < EPSILON {
+                let t = perpendicular(&normal);
+                Tangent([t.x, t.y, t.z, 1.0])
+            } else {
+                let t = tangent.normalize();
+                let w = if normal.cross(&t).dot(&bitangent) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                Tangent([t.x, t.y, t.z, w])
+            }
+        })
+        .collect()
+}
+
+/// Compute the axis aligned bounding box of all positions. Returns `None` if there are no
+/// positions.
+pub fn bounding_box<'a>(positions: impl IntoIterator<Item = &'a [f32; 3]>) -> Option<BoundingBox> {
+    let mut positions = positions.into_iter().map(|p| Vector3::from(*p));
+    let first = positions.next()?;
+    let (min, max) = positions.fold((first, first), |(min, max), p| (min.inf(&p), max.sup(&p)));
+    Some(BoundingBox::new(
+        Point3::new(min.x.into(), min.y.into(), min.z.into()),
+        Point3::new(max.x.into(), max.y.into(), max.z.into()),
+    ))
+}
+
+/// Compute a bounding sphere enclosing all positions, using Ritter's algorithm.
+///
+/// The result is not minimal, but within a few percent of the optimal sphere for typical
+/// meshes. Returns `None` if there are no positions.
+pub fn bounding_sphere<'a>(
+    positions: impl IntoIterator<Item = &'a [f32; 3]>,
+) -> Option<BoundingSphere> {
+    let points = positions
+        .into_iter()
+        .map(|p| Vector3::from(*p))
+        .collect::<Vec<Vector3<f32>>>();
+    let first = *points.first()?;
+
+    let farthest = |from: Vector3<f32>| {
+        points
+            .iter()
+            .cloned()
+            .max_by(|a, b| {
+                (a - from)
+                    .norm_squared()
+                    .partial_cmp(&(b - from).norm_squared())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or(from)
+    };
+    let x = farthest(first);
+    let y = farthest(x);
+    let mut center = (x + y) * 0.5;
+    let mut radius = (y - x).norm() * 0.5;
+
+    for p in &points {
+        let d = (p - center).norm();
+        if d > radius {
+            let new_radius = (radius + d) * 0.5;
+            center += (p - center) * ((new_radius - radius) / d);
+            radius = new_radius;
+        }
+    }
+
+    Some(BoundingSphere::new(
+        Point3::new(center.x.into(), center.y.into(), center.z.into()),
+        radius,
+    ))
+}
+
+/// Smallest index type able to address `vertex_count` vertices.
+pub fn index_type_for(vertex_count: usize) -> IndexType {
+    if vertex_count <= u16::max_value() as usize + 1 {
+        IndexType::U16
+    } else {
+        IndexType::U32
+    }
+}
+
+/// Index buffer of a `ProceduralMesh`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshIndices {
+    /// 16-bit indices.
+    U16(Vec<u16>),
+    /// 32-bit indices.
+    U32(Vec<u32>),
+}
+
+impl MeshIndices {
+    /// Number of indices.
+    pub fn len(&self) -> usize {
+        match self {
+            MeshIndices::U16(vec) => vec.len(),
+            MeshIndices::U32(vec) => vec.len(),
+        }
+    }
+
+    /// Returns `true` if there are no indices.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Largest index, if any.
+    pub fn max(&self) -> Option<u32> {
+        match self {
+            MeshIndices::U16(vec) => vec.iter().max().map(|i| u32::from(*i)),
+            MeshIndices::U32(vec) => vec.iter().max().cloned(),
+        }
+    }
+
+    /// Index type of the stored indices.
+    pub fn index_type(&self) -> IndexType {
+        match self {
+            MeshIndices::U16(_) => IndexType::U16,
+            MeshIndices::U32(_) => IndexType::U32,
+        }
+    }
+
+    /// Convert to the smallest index type able to address `vertex_count` vertices,
+    /// see `index_type_for`.
+    pub fn compact(self, vertex_count: usize) -> Self {
+        match (self, index_type_for(vertex_count)) {
+            (MeshIndices::U32(vec), IndexType::U16) => {
+                MeshIndices::U16(vec.into_iter().map(|i| i as u16).collect())
+            }
+            (indices, _) => indices,
+        }
+    }
+
+    /// Set the indices on a rendy `MeshBuilder`.
+    pub fn set_on(self, builder: &mut MeshBuilder<'static>) {
+        match self {
+            MeshIndices::U16(indices) => {
+                builder.set_indices(indices);
+            }
+            MeshIndices::U32(indices) => {
+                builder.set_indices(indices);
+            }
+        }
+    }
+
+    /// Indices widened to 32 bit.
+    pub fn to_u32(&self) -> Vec<u32> {
+        match self {
+            MeshIndices::U16(vec) => vec.iter().map(|i| u32::from(*i)).collect(),
+            MeshIndices::U32(vec) => vec.clone(),
+        }
+    }
+
+    /// Set the index at `at`, which must fit the stored index type.
+    pub(crate) fn set(&mut self, at: usize, index: u32) {
+        match self {
+            MeshIndices::U16(vec) => vec[at] = index as u16,
+            MeshIndices::U32(vec) => vec[at] = index,
+        }
+    }
+}
+
+/// Attributes and indices of a `ProceduralMesh`, to write them in place.
+pub(crate) struct VerticesMut<'a> {
+    pub positions: &'a mut [[f32; 3]],
+    pub normals: Option<&'a mut [[f32; 3]]>,
+    pub tex_coords: Option<&'a mut [[f32; 2]]>,
+    pub tangents: Option<&'a mut [[f32; 4]]>,
+    pub colors: Option<&'a mut [[f32; 4]]>,
+    pub indices: Option<&'a mut MeshIndices>,
+}
+
+impl From<Vec<u16>> for MeshIndices {
+    fn from(indices: Vec<u16>) -> Self {
+        MeshIndices::U16(indices)
+    }
+}
+
+impl From<Vec<u32>> for MeshIndices {
+    fn from(indices: Vec<u32>) -> Self {
+        MeshIndices::U32(indices)
+    }
+}
+
+/// Builder for meshes generated at runtime, such as voxel chunks or trails.
+///
+/// Takes plain vertex attribute arrays, validates them and produces `MeshData` which can be
+/// passed to `Loader::load_from_data` like any other mesh asset. Every provided attribute is
+/// stored in its own vertex buffer, so the mesh can be drawn by any pass whose vertex format is
+/// a subset of the provided attributes. Indices describe a triangle list.
+///
+/// ### Example:
+///
+/// ```rust,ignore
+/// let data = ProceduralMesh::new()
+///     .with_positions(vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]])
+///     .with_normals(vec![[0.0, 0.0, 1.0]; 3])
+///     .with_tex_coords(vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]])
+///     .with_generated_tangents()
+///     .with_indices(vec![0u16, 1, 2])
+///     .build()?;
+/// let handle = loader.load_from_data(data, (), &mesh_storage);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ProceduralMesh {
+    positions: Vec<[f32; 3]>,
+    normals: Option<Vec<[f32; 3]>>,
+    tex_coords: Option<Vec<[f32; 2]>>,
+    tangents: Option<Vec<[f32; 4]>>,
+    colors: Option<Vec<[f32; 4]>>,
+    indices: Option<MeshIndices>,
+    generate_tangents: bool,
+    recompute_normals: Option<NormalSmoothing>,
+}
+
+impl ProceduralMesh {
+    /// Create an empty builder.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Read the attributes back from a rendy `MeshBuilder`, for example one loaded from a file.
+    ///
+    /// Every vertex buffer must have the format of one of the rendy vertex types made of
+    /// positions, normals, tangents, texture coordinates and colors, either separate or
+    /// interleaved, and the builder must describe a triangle list. Otherwise
+    /// `MeshError::UnreadableBuilder` is returned.
+    pub fn from_builder(builder: &MeshBuilder<'_>) -> Result<Self, MeshError> {
+        let buffers = mesh_reader::read(builder).ok_or(MeshError::UnreadableBuilder)?;
+        let triangles = buffers
+            .primitive
+            .map_or(true, |p| p == Primitive::TriangleList);
+        if !triangles
+            || buffers
+                .vertices
+                .iter()
+                .any(|(format, _)| mesh_reader::attributes(format).is_none())
+        {
+            return Err(MeshError::UnreadableBuilder);
+        }
+        Ok(ProceduralMesh {
+            positions: buffers
+                .attribute(Attribute::Position)
+                .ok_or(MeshError::MissingAttribute("positions"))?,
+            normals: buffers.attribute(Attribute::Normal),
+            tex_coords: buffers.attribute(Attribute::TexCoord),
+            tangents: buffers.attribute(Attribute::Tangent),
+            colors: buffers.attribute(Attribute::Color),
+            indices: buffers.indices_u32().map(MeshIndices::U32),
+            ..Self::default()
+        })
+    }
+
+    /// Set the vertex positions, which determine the number of vertices.
+    pub fn with_positions(mut self, positions: Vec<[f32; 3]>) -> Self {
+        self.positions = positions;
+        self
+    }
+
+    /// Set the vertex normals.
+    pub fn with_normals(mut self, normals: Vec<[f32; 3]>) -> Self {
+        self.normals = Some(normals);
+        self
+    }
+
+    /// Set the vertex texture coordinates.
+    pub fn with_tex_coords(mut self, tex_coords: Vec<[f32; 2]>) -> Self {
+        self.tex_coords = Some(tex_coords);
+        self
+    }
+
+    /// Set the vertex tangents, with handedness in `w`.
+    pub fn with_tangents(mut self, tangents: Vec<[f32; 4]>) -> Self {
+        self.tangents = Some(tangents);
+        self
+    }
+
+    /// Set the linear RGBA vertex colors.
+    pub fn with_colors(mut self, colors: Vec<[f32; 4]>) -> Self {
+        self.colors = Some(colors);
+        self
+    }
+
+    /// Set the triangle list indices, either `Vec<u16>` or `Vec<u32>`.
+    ///
+    /// 32-bit indices are stored as 16-bit when the vertex count allows it.
+    pub fn with_indices(mut self, indices: impl Into<MeshIndices>) -> Self {
+        self.indices = Some(indices.into());
+        self
+    }
+
+    /// Generate tangents with `generate_tangents` if none are provided.
+    /// Requires normals and texture coordinates.
+    pub fn with_generated_tangents(mut self) -> Self {
+        self.generate_tangents = true;
+        self
+    }
+
+    /// Replace the normals with ones computed by `recompute_normals` when building. Normals
+    /// are recomputed on every build, so this also applies to updates through `DynamicMeshes`.
+    pub fn with_recomputed_normals(mut self, smoothing: NormalSmoothing) -> Self {
+        self.recompute_normals = Some(smoothing);
+        self
+    }
+
+    /// Split shared vertices so that every triangle references its own three vertices, which
+    /// gives hard edges everywhere when combined with `NormalSmoothing::Flat`.
+    pub fn unweld(mut self) -> Self {
+        let indices = match self.indices.take() {
+            Some(indices) => indices.to_u32(),
+            None => return self,
+        };
+        fn expand<T: Copy>(data: &mut Option<Vec<T>>, indices: &[u32]) {
+            if let Some(data) = data {
+                *data = indices.iter().map(|i| data[*i as usize]).collect();
+            }
+        }
+        self.positions = indices
+            .iter()
+            .map(|i| self.positions[*i as usize])
+            .collect();
+        expand(&mut self.normals, &indices);
+        expand(&mut self.tex_coords, &indices);
+        expand(&mut self.tangents, &indices);
+        expand(&mut self.colors, &indices);
+        self
+    }
+
+    /// Number of vertices.
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Vertex positions.
+    pub fn positions(&self) -> &[[f32; 3]] {
+        &self.positions
+    }
+
+    /// Vertex normals, if provided.
+    pub fn normals(&self) -> Option<&[[f32; 3]]> {
+        self.normals.as_ref().map(Vec::as_slice)
+    }
+
+    /// Vertex tangents, if provided.
+    pub fn tangents(&self) -> Option<&[[f32; 4]]> {
+        self.tangents.as_ref().map(Vec::as_slice)
+    }
+
+    /// Vertex texture coordinates, if provided.
+    pub fn tex_coords(&self) -> Option<&[[f32; 2]]> {
+        self.tex_coords.as_ref().map(Vec::as_slice)
+    }
+
+    /// Index buffer, if the mesh is indexed.
+    pub fn indices(&self) -> Option<&MeshIndices> {
+        self.indices.as_ref()
+    }
+
+    /// Bounding sphere of the vertex positions, see `bounding_sphere`.
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        bounding_sphere(&self.positions)
+    }
+
+    /// Bounding box of the vertex positions, see `bounding_box`.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        bounding_box(&self.positions)
+    }
+
+    /// Returns `true` if both builders provide the same set of vertex attributes.
+    pub fn same_attributes(&self, other: &ProceduralMesh) -> bool {
+        self.normals.is_some() == other.normals.is_some()
+            && self.tex_coords.is_some() == other.tex_coords.is_some()
+            && self.tangents.is_some() == other.tangents.is_some()
+            && self.colors.is_some() == other.colors.is_some()
+    }
+
+    /// Returns `true` if both builders provide the same set of vertex attributes and indices.
+    pub fn same_layout(&self, other: &ProceduralMesh) -> bool {
+        (self.normals.is_some() || self.recompute_normals.is_some())
+            == (other.normals.is_some() || other.recompute_normals.is_some())
+            && self.tex_coords.is_some() == other.tex_coords.is_some()
+            && (self.tangents.is_some() || self.generate_tangents)
+                == (other.tangents.is_some() || other.generate_tangents)
+            && self.colors.is_some() == other.colors.is_some()
+            && self.indices.as_ref().map(MeshIndices::len)
+                == other.indices.as_ref().map(MeshIndices::len)
+    }
+
+    /// The attributes and indices, to write them in place without changing their counts.
+    pub(crate) fn vertices_mut(&mut self) -> VerticesMut<'_> {
+        VerticesMut {
+            positions: &mut self.positions,
+            normals: self.normals.as_mut().map(Vec::as_mut_slice),
+            tex_coords: self.tex_coords.as_mut().map(Vec::as_mut_slice),
+            tangents: self.tangents.as_mut().map(Vec::as_mut_slice),
+            colors: self.colors.as_mut().map(Vec::as_mut_slice),
+            indices: self.indices.as_mut(),
+        }
+    }
+
+    /// Replace the vertex positions, keeping all other attributes.
+    pub(crate) fn set_positions(&mut self, positions: Vec<[f32; 3]>) {
+        self.positions = positions;
+    }
+
+    /// Replace the vertex normals, keeping all other attributes.
+    pub(crate) fn set_normals(&mut self, normals: Vec<[f32; 3]>) {
+        self.normals = Some(normals);
+    }
+
+    /// Validate the attributes and produce `MeshData` ready for upload.
+    pub fn build(self) -> Result<MeshData, MeshError> {
+        let layout = self.layout();
+        Ok(MeshData::from(self.into_builder()?).with_layout(layout))
+    }
+
+    /// Layout of the mesh built by `into_builder`.
+    pub fn layout(&self) -> MeshLayout {
+        let count = self.vertex_count();
+        let mut vertex_formats = vec![Position::vertex()];
+        if self.normals.is_some() || self.recompute_normals.is_some() {
+            vertex_formats.push(Normal::vertex());
+        }
+        if self.tangents.is_some() || self.generate_tangents {
+            vertex_formats.push(Tangent::vertex());
+        }
+        if self.tex_coords.is_some() {
+            vertex_formats.push(TexCoord::vertex());
+        }
+        if self.colors.is_some() {
+            vertex_formats.push(Color::vertex());
+        }
+        MeshLayout {
+            vertex_count: count as u32,
+            index_count: self.indices.as_ref().map_or(0, |i| i.len() as u32),
+            index_type: self.indices.as_ref().map(|i| match i {
+                MeshIndices::U16(_) => IndexType::U16,
+                MeshIndices::U32(_) => index_type_for(count),
+            }),
+            vertex_formats,
+        }
+    }
+
+    /// Validate the attributes and produce a rendy `MeshBuilder`.
+    pub fn into_builder(self) -> Result<MeshBuilder<'static>, MeshError> {
+        self.validate()?;
+        let count = self.positions.len();
+        let positions = self.typed_positions();
+        let normals = self.computed_normals(&positions).or_else(|| {
+            self.normals
+                .as_ref()
+                .map(|n| n.iter().cloned().map(Normal).collect::<Vec<_>>())
+        });
+        let tangents = match self.computed_tangents(&positions, normals.as_ref())? {
+            Some(tangents) => Some(tangents),
+            None => self
+                .tangents
+                .as_ref()
+                .map(|t| t.iter().cloned().map(Tangent).collect::<Vec<_>>()),
+        };
+        let tex_coords = self
+            .tex_coords
+            .map(|t| t.into_iter().map(TexCoord).collect::<Vec<_>>());
+
+        let mut builder = MeshBuilder::new();
+        builder.add_vertices(positions);
+        normals.map(|v| builder.add_vertices(v));
+        tangents.map(|v| builder.add_vertices(v));
+        tex_coords.map(|v| builder.add_vertices(v));
+        self.colors
+            .map(|v| builder.add_vertices(v.into_iter().map(Color).collect::<Vec<_>>()));
+        if let Some(indices) = self.indices {
+            indices.compact(count).set_on(&mut builder);
+        }
+        Ok(builder)
+    }
+
+    /// Validate the attributes and produce the contents of the vertex buffers of the mesh built
+    /// by `into_builder`, one per format of `layout`.
+    ///
+    /// With `positions_only`, only the positions and the attributes computed from them are
+    /// produced, the other buffers are `None`.
+    pub(crate) fn vertex_data(
+        &self,
+        positions_only: bool,
+    ) -> Result<Vec<Option<Cow<'_, [u8]>>>, MeshError> {
+        fn provided<T>(data: &Option<Vec<T>>, skip: bool) -> Option<Cow<'_, [u8]>> {
+            match data {
+                Some(data) if !skip => Some(Cow::Borrowed(util::slice_as_bytes(data))),
+                _ => None,
+            }
+        }
+
+        self.validate()?;
+        let positions = self.typed_positions();
+        let computed_normals = self.computed_normals(&positions);
+        let mut buffers = vec![Some(Cow::Borrowed(util::slice_as_bytes(&self.positions)))];
+        if self.normals.is_some() || self.recompute_normals.is_some() {
+            buffers.push(match &computed_normals {
+                Some(normals) => Some(Cow::Owned(util::slice_as_bytes(normals).to_vec())),
+                None => provided(&self.normals, positions_only),
+            });
+        }
+        if self.tangents.is_some() || self.generate_tangents {
+            let normals = computed_normals.or_else(|| {
+                self.normals
+                    .as_ref()
+                    .filter(|_| self.generate_tangents && self.tangents.is_none())
+                    .map(|n| n.iter().cloned().map(Normal).collect::<Vec<_>>())
+            });
+            buffers.push(
+                match self.computed_tangents(&positions, normals.as_ref())? {
+                    Some(tangents) => Some(Cow::Owned(util::slice_as_bytes(&tangents).to_vec())),
+                    None => provided(&self.tangents, positions_only),
+                },
+            );
+        }
+        if self.tex_coords.is_some() {
+            buffers.push(provided(&self.tex_coords, positions_only));
+        }
+        if self.colors.is_some() {
+            buffers.push(provided(&self.colors, positions_only));
+        }
+        Ok(buffers)
+    }
+
+    /// Contents of the index buffer of the mesh built by `into_builder`, if it is indexed.
+    pub(crate) fn index_data(&self) -> Option<(IndexType, Cow<'_, [u8]>)> {
+        let count = self.vertex_count();
+        match self.indices.as_ref()? {
+            MeshIndices::U16(indices) => {
+                Some((IndexType::U16, Cow::Borrowed(util::slice_as_bytes(indices))))
+            }
+            MeshIndices::U32(indices) if index_type_for(count) == IndexType::U32 => {
+                Some((IndexType::U32, Cow::Borrowed(util::slice_as_bytes(indices))))
+            }
+            indices => {
+                let compact = indices.clone().compact(count);
+                let bytes = match &compact {
+                    MeshIndices::U16(indices) => util::slice_as_bytes(indices).to_vec(),
+                    MeshIndices::U32(indices) => util::slice_as_bytes(indices).to_vec(),
+                };
+                Some((compact.index_type(), Cow::Owned(bytes)))
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<(), MeshError> {
+        let count = self.positions.len();
+        check_len("normals", count, self.normals.as_ref().map(Vec::len))?;
+        check_len("tex_coords", count, self.tex_coords.as_ref().map(Vec::len))?;
+        check_len("tangents", count, self.tangents.as_ref().map(Vec::len))?;
+        check_len("colors", count, self.colors.as_ref().map(Vec::len))?;
+
+        if let Some(indices) = &self.indices {
+            if indices.len() % 3 != 0 {
+                return Err(MeshError::InvalidIndexCount(indices.len()));
+            }
+            if let Some(max) = indices.max() {
+                if max as usize >= count {
+                    return Err(MeshError::IndexOutOfBounds {
+                        index: max,
+                        vertex_count: count,
+                    });
+                }
+            }
+        } else if count % 3 != 0 {
+            return Err(MeshError::InvalidIndexCount(count));
+        }
+        Ok(())
+    }
+
+    fn typed_positions(&self) -> Vec<Position> {
+        self.positions.iter().cloned().map(Position).collect()
+    }
+
+    /// Normals computed with `recompute_normals`, if requested.
+    fn computed_normals(&self, positions: &[Position]) -> Option<Vec<Normal>> {
+        let smoothing = self.recompute_normals?;
+        let indices = self.indices.as_ref().map(MeshIndices::to_u32);
+        Some(recompute_normals(
+            positions,
+            indices.as_ref().map(Vec::as_slice),
+            smoothing,
+        ))
+    }
+
+    /// Tangents computed with `generate_tangents`, if requested and none are provided.
+    fn computed_tangents(
+        &self,
+        positions: &[Position],
+        normals: Option<&Vec<Normal>>,
+    ) -> Result<Option<Vec<Tangent>>, MeshError> {
+        if !self.generate_tangents || self.tangents.is_some() {
+            return Ok(None);
+        }
+        let normals = normals.ok_or(MeshError::MissingAttribute("normals"))?;
+        let tex_coords = self
+            .tex_coords
+            .as_ref()
+            .ok_or(MeshError::MissingAttribute("tex_coords"))?
+            .iter()
+            .cloned()
+            .map(TexCoord)
+            .collect::<Vec<_>>();
+        let indices = self.indices.as_ref().map(MeshIndices::to_u32);
+        Ok(Some(generate_tangents(
+            positions,
+            normals,
+            &tex_coords,
+            indices.as_ref().map(Vec::as_slice),
+        )))
+    }
+}
+
+/// Result of `merge_meshes`.
+#[derive(Debug, Clone)]
+pub struct MergedMesh {
+    /// Combined mesh data in the space of the input transforms.
+    pub mesh: ProceduralMesh,
+    /// Bounding sphere of the combined mesh, if it has any vertices.
+    pub bounding_sphere: Option<BoundingSphere>,
+}
+
+/// Merge meshes into a single mesh, baking each transform into the vertex data.
+///
+/// Positions are transformed as points, normals by the inverse transpose and tangents by the
+/// upper 3x3 of the matrix. Transforms that mirror the geometry flip the triangle winding and
+/// the tangent handedness. Indices are concatenated and stored as 16-bit when the combined
+/// vertex count allows it, unindexed inputs receive sequential indices.
+///
+/// All inputs must provide the same vertex attributes as the first, otherwise
+/// `MeshError::LayoutMismatch` is returned.
+pub fn merge_meshes<'a>(
+    meshes: impl IntoIterator<Item = (&'a ProceduralMesh, Matrix4<f32>)>,
+) -> Result<MergedMesh, MeshError> {
+    let mut merged: Option<ProceduralMesh> = None;
+    let mut indices = Vec::new();
+
+    for (index, (mesh, matrix)) in meshes.into_iter().enumerate() {
+        let out = merged.get_or_insert_with(|| ProceduralMesh {
+            positions: Vec::new(),
+            normals: mesh.normals.as_ref().map(|_| Vec::new()),
+            tex_coords: mesh.tex_coords.as_ref().map(|_| Vec::new()),
+            tangents: mesh.tangents.as_ref().map(|_| Vec::new()),
+            colors: mesh.colors.as_ref().map(|_| Vec::new()),
+            indices: None,
+            generate_tangents: mesh.generate_tangents,
+            recompute_normals: mesh.recompute_normals,
+        });
+        if !out.same_attributes(mesh) {
+            return Err(MeshError::LayoutMismatch { index });
+        }
+
+        let base = out.positions.len() as u32;
+        let linear: Matrix3<f32> = matrix.fixed_slice::<U3, U3>(0, 0).into_owned();
+        let normal_matrix = linear.try_inverse().unwrap_or(linear).transpose();
+        let mirrored = linear.determinant() < 0.0;
+
+        out.positions.extend(mesh.positions.iter().map(|p| {
+            let p = matrix.transform_point(&Point3::new(p[0], p[1], p[2]));
+            [p.x, p.y, p.z]
+        }));
+        if let (Some(dst), Some(src)) = (out.normals.as_mut(), mesh.normals.as_ref()) {
+            dst.extend(src.iter().map(|n| {
+                let n = (normal_matrix * Vector3::from(*n)).normalize();
+                [n.x, n.y, n.z]
+            }));
+        }
+        if let (Some(dst), Some(src)) = (out.tangents.as_mut(), mesh.tangents.as_ref()) {
+            dst.extend(src.iter().map(|t| {
+                let v = (linear * Vector3::new(t[0], t[1], t[2])).normalize();
+                let w = if mirrored { -t[3] } else { t[3] };
+                [v.x, v.y, v.z, w]
+            }));
+        }
+        if let (Some(dst), Some(src)) = (out.tex_coords.as_mut(), mesh.tex_coords.as_ref()) {
+            dst.extend_from_slice(src);
+        }
+        if let (Some(dst), Some(src)) = (out.colors.as_mut(), mesh.colors.as_ref()) {
+            dst.extend_from_slice(src);
+        }
+
+        let start = indices.len();
+        match &mesh.indices {
+            Some(src) => indices.extend(src.to_u32().into_iter().map(|i| i + base)),
+            None => indices.extend(base..base + mesh.positions.len() as u32),
+        }
+        if mirrored {
+            for tri in indices[start..].chunks_mut(3) {
+                if tri.len() == 3 {
+                    tri.swap(1, 2);
+                }
+            }
+        }
+    }
+
+    let mut mesh = merged.unwrap_or_default();
+    let count = mesh.positions.len();
+    mesh.indices = Some(MeshIndices::U32(indices).compact(count));
+    Ok(MergedMesh {
+        bounding_sphere: mesh.bounding_sphere(),
+        mesh,
+    })
+}
+
+/// `SystemData` needed by `merge_static_entities`.
+#[derive(SystemData)]
+#[allow(missing_debug_implementations)]
+pub struct MergeStaticData<'a> {
+    entities: Entities<'a>,
+    loader: ReadExpect<'a, Loader>,
+    mesh_storage: Read<'a, AssetStorage<Mesh>>,
+    materials: WriteStorage<'a, Handle<Material>>,
+    meshes: WriteStorage<'a, Handle<Mesh>>,
+    transforms: WriteStorage<'a, Transform>,
+    bounds: WriteStorage<'a, BoundingSphere>,
+    hidden: WriteStorage<'a, Hidden>,
+}
+
+/// Merge the meshes of static entities sharing one `Material` into a new entity, and hide the
+/// originals.
+///
+/// Each entity is given with the mesh data its `Handle<Mesh>` was built from, since mesh
+/// assets do not keep their vertex data once uploaded. The global matrix of every entity's
+/// `Transform` is baked into the merged mesh, so the new entity gets an identity `Transform`
+/// and must not be parented. It also gets the combined `BoundingSphere`.
+///
+/// Returns `MeshError::MaterialMismatch` if the entities do not share a single material.
+pub fn merge_static_entities<'a>(
+    data: &mut MergeStaticData<'_>,
+    parts: impl IntoIterator<Item = (Entity, &'a ProceduralMesh)>,
+) -> Result<Entity, MeshError> {
+    let parts = parts.into_iter().collect::<Vec<_>>();
+    let material = match parts.first().and_then(|(e, _)| data.materials.get(*e)) {
+        Some(material) => material.clone(),
+        None => return Err(MeshError::MaterialMismatch),
+    };
+    if parts
+        .iter()
+        .any(|(e, _)| data.materials.get(*e) != Some(&material))
+    {
+        return Err(MeshError::MaterialMismatch);
+    }
+
+    let identity = Transform::default();
+    let merged = merge_meshes(parts.iter().map(|(entity, mesh)| {
+        let transform = data.transforms.get(*entity).unwrap_or(&identity);
+        (
+            *mesh,
+            convert::<_, Matrix4<f32>>(*transform.global_matrix()),
+        )
+    }))?;
+    let mesh = data
+        .loader
+        .load_from_data(merged.mesh.build()?, (), &data.mesh_storage);
+
+    let entity = data.entities.create();
+    // Inserting into storages of a freshly created entity can not fail
+    data.meshes.insert(entity, mesh).unwrap();
+    data.materials.insert(entity, material).unwrap();
+    data.transforms
+        .insert(entity, Transform::default())
+        .unwrap();
+    if let Some(sphere) = merged.bounding_sphere {
+        data.bounds.insert(entity, sphere).unwrap();
+    }
+    for (original, _) in parts {
+        data.hidden.insert(original, Hidden).unwrap();
+    }
+    Ok(entity)
+}
+
+fn check_len(
+    attribute: &'static str,
+    expected: usize,
+    found: Option<usize>,
+) -> Result<(), MeshError> {
+    match found {
+        Some(found) if found != expected => Err(MeshError::AttributeCountMismatch {
+            attribute,
+            expected,
+            found,
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unit cube faces as (origin corner, u axis, v axis), normal = u x v.
+    const FACES: [([f32; 3], [f32; 3], [f32; 3]); 6] = [
+        ([-1.0, -1.0, 1.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([1.0, -1.0, -1.0], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0]),
+        ([1.0, -1.0, 1.0], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, -1.0, -1.0], [0.0, 0.0, 1.0], [0.0, 1.0, 0.0]),
+        ([-1.0, 1.0, 1.0], [1.0, 0.0, 0.0], [0.0, 0.0, -1.0]),
+        ([-1.0, -1.0, -1.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+    ];
+
+    fn cube(mirror_u: bool) -> (Vec<Position>, Vec<Normal>, Vec<TexCoord>, Vec<u32>) {
+        let mut positions = Vec::new();
+        let mut normals = Vec::new();
+        let mut tex_coords = Vec::new();
+        let mut indices = Vec::new();
+        for (origin, u, v) in FACES.iter() {
+            let origin = Vector3::from(*origin);
+            let u = Vector3::from(*u);
+            let v = Vector3::from(*v);
+            let normal = u.cross(&v);
+            let base = positions.len() as u32;
+            for &(s, t) in &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                positions.push(Position((origin + u * s * 2.0 + v * t * 2.0).into()));
+                normals.push(Normal(normal.into()));
+                let s = if mirror_u { 1.0 - s } else { s };
+                tex_coords.push(TexCoord([s, t]));
+            }
+            indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+        (positions, normals, tex_coords, indices)
+    }
+
+    fn assert_tangents(tangents: &[Tangent], sign: f32) {
+        for (face, (_, u, _)) in FACES.iter().enumerate() {
+            for vert in 0..4 {
+                let Tangent([x, y, z, w]) = tangents[face * 4 + vert];
+                let expected = Vector3::from(*u) * sign;
+                assert!((Vector3::new(x, y, z) - expected).norm() < 1e-5);
+                assert_eq!(w, sign);
+            }
+        }
+    }
+
+    #[test]
+    fn cube_tangents() {
+        let (positions, normals, tex_coords, indices) = cube(false);
+        let tangents = generate_tangents(&positions, &normals, &tex_coords, Some(&indices));
+        assert_tangents(&tangents, 1.0);
+    }
+
+    #[test]
+    fn mirrored_cube_tangents() {
+        let (positions, normals, tex_coords, indices) = cube(true);
+        let tangents = generate_tangents(&positions, &normals, &tex_coords, Some(&indices));
+        assert_tangents(&tangents, -1.0);
+    }
+
+    #[test]
+    fn unindexed_tangents() {
+        let (positions, normals, tex_coords, indices) = cube(false);
+        let flatten = |i: &u32| *i as usize;
+        let positions: Vec<_> = indices.iter().map(flatten).map(|i| positions[i]).collect();
+        let normals: Vec<_> = indices.iter().map(flatten).map(|i| normals[i]).collect();
+        let tex_coords: Vec<_> = indices.iter().map(flatten).map(|i| tex_coords[i]).collect();
+        let tangents = generate_tangents(&positions, &normals, &tex_coords, None);
+        for (tangent, index) in tangents.iter().zip(&indices) {
+            let Tangent([x, y, z, w]) = *tangent;
+            let (_, u, _) = FACES[*index as usize / 4];
+            assert!((Vector3::new(x, y, z) - Vector3::from(u)).norm() < 1e-5);
+            assert_eq!(w, 1.0);
+        }
+    }
+
+    #[test]
+    fn degenerate_triangles() {
+        let positions = [
+            Position([0.0, 0.0, 0.0]),
+            Position([1.0, 0.0, 0.0]),
+            Position([2.0, 0.0, 0.0]),
+        ];
+        let normals = [Normal([0.0, 0.0, 1.0]); 3];
+        let tex_coords = [
+            TexCoord([0.0, 0.0]),
+            TexCoord([1.0, 0.0]),
+            TexCoord([0.0, 1.0]),
+        ];
+        let tangents = generate_tangents(&positions, &normals, &tex_coords, None);
+
+        // Collinear positions produce no contribution, so a perpendicular fallback is used.
+        for Tangent([x, y, z, w]) in tangents {
+            let t = Vector3::new(x, y, z);
+            assert!((t.norm() - 1.0).abs() < 1e-5);
+            assert!(t.z.abs() < 1e-5);
+            assert_eq!(w, 1.0);
+        }
+
+        let positions = [
+            Position([0.0, 0.0, 0.0]),
+            Position([1.0, 0.0, 0.0]),
+            Position([0.0, 1.0, 0.0]),
+        ];
+        let tex_coords = [TexCoord([0.5, 0.5]); 3];
+        let tangents = generate_tangents(&positions, &normals, &tex_coords, None);
+        for Tangent([x, y, z, _]) in tangents {
+            assert!(x.is_finite() && y.is_finite() && z.is_finite());
+        }
+    }
+
+    fn triangle() -> ProceduralMesh {
+        ProceduralMesh::new()
+            .with_positions(vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]])
+            .with_normals(vec![[0.0, 0.0, 1.0]; 3])
+            .with_tex_coords(vec![[0.0, 0.0], [1.0, 0.0], [0.0, 1.0]])
+    }
+
+    #[test]
+    fn procedural_mesh_builds() {
+        assert!(triangle().with_indices(vec![0u16, 1, 2]).build().is_ok());
+        assert!(triangle().with_indices(vec![0u32, 1, 2]).build().is_ok());
+        assert!(triangle().with_generated_tangents().build().is_ok());
+    }
+
+    #[test]
+    fn procedural_mesh_layout() {
+        let layout = triangle()
+            .with_indices(vec![0u32, 1, 2])
+            .with_generated_tangents()
+            .layout();
+        assert_eq!(layout.vertex_count, 3);
+        assert_eq!(layout.index_count, 3);
+        assert_eq!(layout.index_type, Some(IndexType::U16));
+        assert_eq!(layout.vertex_formats.len(), 4);
+        // Position, normal, tangent and texture coordinate, plus 16-bit indices
+        assert_eq!(layout.byte_size(), 3 * (12 + 12 + 16 + 8) + 3 * 2);
+        assert_eq!(triangle().layout().index_type, None);
+    }
+
+    #[test]
+    fn procedural_mesh_from_builder() {
+        let builder = triangle()
+            .with_indices(vec![0u16, 2, 1])
+            .into_builder()
+            .unwrap();
+        let mesh = ProceduralMesh::from_builder(&builder).unwrap();
+        assert_eq!(mesh.positions(), triangle().positions());
+        assert_eq!(mesh.normals(), Some(&[[0.0, 0.0, 1.0]; 3][..]));
+        assert_eq!(mesh.tangents(), None);
+        assert_eq!(mesh.indices().map(MeshIndices::to_u32), Some(vec![0, 2, 1]));
+
+        let tangents = MeshData::from(builder)
+            .with_generated_tangents()
+            .map(|data| ProceduralMesh::from_builder(&data.0).unwrap())
+            .unwrap();
+        assert_eq!(tangents.tangents().map(<[_]>::len), Some(3));
+
+        let mut builder = MeshBuilder::new();
+        builder.add_vertices(vec![Position([0.0; 3]); 3]);
+        builder.add_vertices(vec![
+            crate::skinning::JointCombined::new(
+                [0u16; 4],
+                [1.0, 0.0, 0.0, 0.0]
+            );
+            3
+        ]);
+        assert_eq!(
+            ProceduralMesh::from_builder(&builder).unwrap_err(),
+            MeshError::UnreadableBuilder
+        );
+    }
+
+    #[test]
+    fn flat_cube_normals() {
+        let (positions, _, _, indices) = cube(false);
+        for &smoothing in &[
+            NormalSmoothing::Flat,
+            NormalSmoothing::AngleWeighted {
+                hard_edge_angle: Some(std::f32::consts::FRAC_PI_4),
+            },
+        ] {
+            let normals = recompute_normals(&positions, Some(&indices), smoothing);
+            for (face, (_, u, v)) in FACES.iter().enumerate() {
+                let expected = Vector3::from(*u).cross(&Vector3::from(*v));
+                for vert in 0..4 {
+                    let normal = Vector3::from(normals[face * 4 + vert].0);
+                    assert!((normal - expected).norm() < 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_cube_normals() {
+        let (positions, _, _, indices) = cube(false);
+        let normals = recompute_normals(
+            &positions,
+            Some(&indices),
+            NormalSmoothing::AngleWeighted {
+                hard_edge_angle: None,
+            },
+        );
+        // Every corner has three faces meeting at right angles, so the normals point along
+        // the diagonals.
+        for (position, normal) in positions.iter().zip(&normals) {
+            let expected = Vector3::from(position.0).normalize();
+            assert!((Vector3::from(normal.0) - expected).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn smooth_sphere_normals() {
+        let shape = crate::shape::Shape::IcoSphere(Some(3))
+            .generate_internal(None)
+            .unwrap();
+        let positions = shape.positions();
+        let normals = recompute_normals(
+            &positions,
+            shape.indices(),
+            NormalSmoothing::AngleWeighted {
+                hard_edge_angle: None,
+            },
+        );
+        for (position, normal) in positions.iter().zip(&normals) {
+            let expected = Vector3::from(position.0).normalize();
+            assert!((Vector3::from(normal.0) - expected).norm() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn cube_bounding_sphere() {
+        let (positions, _, _, _) = cube(false);
+        let positions = positions.iter().map(|p| p.0).collect::<Vec<_>>();
+        let sphere = bounding_sphere(&positions).unwrap();
+        let center = Vector3::new(
+            sphere.center.x.as_f32(),
+            sphere.center.y.as_f32(),
+            sphere.center.z.as_f32(),
+        );
+        let radius = sphere.radius.as_f32();
+        assert!(center.norm() < 1e-5);
+        assert!((radius - 3f32.sqrt()).abs() < 1e-5);
+        assert!(bounding_sphere(&[]).is_none());
+    }
+
+    #[test]
+    fn cube_bounding_box() {
+        let (positions, _, _, _) = cube(false);
+        let positions = positions.iter().map(|p| p.0).collect::<Vec<_>>();
+        let aabb = bounding_box(&positions).unwrap();
+        assert_eq!(aabb, BoundingBox::default());
+        assert!(bounding_box(&[]).is_none());
+    }
+
+    #[test]
+    fn bounding_sphere_contains_all_points() {
+        let positions = (0..100)
+            .map(|i| {
+                let f = i as f32;
+                [(f * 0.37).sin() * 3.0, (f * 1.3).cos(), f * 0.01 - 0.5]
+            })
+            .collect::<Vec<_>>();
+        let sphere = bounding_sphere(&positions).unwrap();
+        let center = Vector3::new(
+            sphere.center.x.as_f32(),
+            sphere.center.y.as_f32(),
+            sphere.center.z.as_f32(),
+        );
+        for p in &positions {
+            assert!((Vector3::from(*p) - center).norm() <= sphere.radius.as_f32() + 1e-5);
+        }
+    }
+
+    #[test]
+    fn merge_transforms_and_offsets() {
+        let translated = Matrix4::new_translation(&Vector3::new(10.0, 0.0, 0.0));
+        let mirrored = Matrix4::new_nonuniform_scaling(&Vector3::new(-1.0, 1.0, 1.0));
+        let merged = merge_meshes(vec![
+            (&triangle(), Matrix4::identity()),
+            (&triangle().with_indices(vec![0u16, 1, 2]), translated),
+            (&triangle(), mirrored),
+        ])
+        .unwrap();
+
+        assert_eq!(merged.mesh.vertex_count(), 9);
+        assert_eq!(merged.mesh.positions[4], [11.0, 0.0, 0.0]);
+        assert_eq!(merged.mesh.positions[7], [-1.0, 0.0, 0.0]);
+        assert_eq!(
+            merged.mesh.indices,
+            Some(MeshIndices::U16(vec![0, 1, 2, 3, 4, 5, 6, 8, 7]))
+        );
+        assert!(merged.bounding_sphere.is_some());
+    }
+
+    #[test]
+    fn merge_layout_mismatch() {
+        let result = merge_meshes(vec![
+            (&triangle(), Matrix4::identity()),
+            (
+                &triangle().with_colors(vec![[1.0; 4]; 3]),
+                Matrix4::identity(),
+            ),
+        ]);
+        assert_eq!(result.unwrap_err(), MeshError::LayoutMismatch { index: 1 });
+    }
+
+    #[test]
+    fn index_type_selection() {
+        assert_eq!(index_type_for(65536), IndexType::U16);
+        assert_eq!(index_type_for(65537), IndexType::U32);
+        assert_eq!(
+            MeshIndices::from(vec![0u32, 1, 2]).compact(3),
+            MeshIndices::U16(vec![0, 1, 2])
+        );
+        assert_eq!(
+            MeshIndices::from(vec![0u32, 1, 70000]).compact(70001),
+            MeshIndices::U32(vec![0, 1, 70000])
+        );
+        assert_eq!(
+            MeshIndices::from(vec![0u16, 1, 2]).compact(70001),
+            MeshIndices::U16(vec![0, 1, 2])
+        );
+    }
+
+    #[test]
+    fn procedural_mesh_errors() {
+        assert_eq!(
+            triangle()
+                .with_colors(vec![[1.0; 4]; 2])
+                .into_builder()
+                .unwrap_err(),
+            MeshError::AttributeCountMismatch {
+                attribute: "colors",
+                expected: 3,
+                found: 2,
+            }
+        );
+        assert_eq!(
+            triangle()
+                .with_indices(vec![0u16, 1])
+                .into_builder()
+                .unwrap_err(),
+            MeshError::InvalidIndexCount(2)
+        );
+        assert_eq!(
+            triangle()
+                .with_indices(vec![0u32, 1, 3])
+                .into_builder()
+                .unwrap_err(),
+            MeshError::IndexOutOfBounds {
+                index: 3,
+                vertex_count: 3,
+            }
+        );
+        assert_eq!(
+            ProceduralMesh::new()
+                .with_positions(vec![[0.0; 3]; 3])
+                .with_generated_tangents()
+                .into_builder()
+                .unwrap_err(),
+            MeshError::MissingAttribute("normals")
+        );
+    }
+}
@@ -0,0 +1,485 @@
+//! Morph targets, also known as blend shapes, for facial animation and other deformations.
+use crate::{
+    dynamic_mesh::DynamicMeshes,
+    error::MeshError,
+    mesh_util::ProceduralMesh,
+    skinning::{CpuSkinnedMesh, SkinningPath},
+    types::Mesh,
+};
+use amethyst_assets::{AssetStorage, Handle, Loader};
+use amethyst_core::ecs::prelude::{
+    Component, DenseVecStorage, Entities, Join, Read, ReadExpect, ReadStorage, System, Write,
+    WriteStorage,
+};
+use std::{cmp::Ordering, sync::Arc};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Maximum number of morph targets blended into a mesh at once. When more targets have a non
+/// zero weight, only the ones with the largest weights are applied.
+pub const MAX_ACTIVE_MORPH_TARGETS: usize = 8;
+
+/// Per vertex offsets from the base mesh, added to it in proportion to the target's weight.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MorphTarget {
+    position_deltas: Vec<[f32; 3]>,
+    normal_deltas: Option<Vec<[f32; 3]>>,
+}
+
+impl MorphTarget {
+    /// Create a morph target moving the vertices of the base mesh by `position_deltas`.
+    pub fn new(position_deltas: Vec<[f32; 3]>) -> Self {
+        MorphTarget {
+            position_deltas,
+            normal_deltas: None,
+        }
+    }
+
+    /// Set the offsets of the vertex normals. Normals are renormalized after blending.
+    pub fn with_normal_deltas(mut self, normal_deltas: Vec<[f32; 3]>) -> Self {
+        self.normal_deltas = Some(normal_deltas);
+        self
+    }
+
+    /// Offsets of the vertex positions.
+    pub fn position_deltas(&self) -> &[[f32; 3]] {
+        &self.position_deltas
+    }
+
+    /// Offsets of the vertex normals, if the target changes them.
+    pub fn normal_deltas(&self) -> Option<&[[f32; 3]]> {
+        self.normal_deltas.as_ref().map(Vec::as_slice)
+    }
+}
+
+/// Component holding the base mesh of an entity and the morph targets it can blend to, see
+/// `MorphSystem`.
+///
+/// The targets are shared between clones, so that many entities can use the same set of
+/// targets cheaply. Each entity still gets its own mesh, as the blended vertices depend on its
+/// `MorphWeights`.
+#[derive(Debug, Clone)]
+pub struct MorphTargets {
+    base: ProceduralMesh,
+    targets: Arc<[MorphTarget]>,
+}
+
+impl Component for MorphTargets {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl MorphTargets {
+    /// Create morph targets for `base`. Every target needs one delta per vertex of the base
+    /// mesh, and normal deltas are only allowed if the base mesh provides normals.
+    pub fn new(base: ProceduralMesh, targets: Vec<MorphTarget>) -> Result<Self, MeshError> {
+        let count = base.vertex_count();
+        for target in &targets {
+            if target.position_deltas.len() != count {
+                return Err(MeshError::AttributeCountMismatch {
+                    attribute: "position_deltas",
+                    expected: count,
+                    found: target.position_deltas.len(),
+                });
+            }
+            if let Some(normal_deltas) = &target.normal_deltas {
+                if base.normals().is_none() {
+                    return Err(MeshError::MissingAttribute("normals"));
+                }
+                if normal_deltas.len() != count {
+                    return Err(MeshError::AttributeCountMismatch {
+                        attribute: "normal_deltas",
+                        expected: count,
+                        found: normal_deltas.len(),
+                    });
+                }
+            }
+        }
+        Ok(MorphTargets {
+            base,
+            targets: targets.into(),
+        })
+    }
+
+    /// Mesh the targets are applied to.
+    pub fn base(&self) -> &ProceduralMesh {
+        &self.base
+    }
+
+    /// The morph targets.
+    pub fn targets(&self) -> &[MorphTarget] {
+        &self.targets
+    }
+
+    /// Returns the base mesh with the targets blended in according to `weights`, the weight of
+    /// each target being clamped to `0.0..=1.0`. Targets without a weight are not applied.
+    pub fn blend(&self, weights: &[f32]) -> ProceduralMesh {
+        let active = active_targets(weights);
+        let mut mesh = self.base.clone();
+        if active.is_empty() {
+            return mesh;
+        }
+
+        let mut positions = mesh.positions().to_vec();
+        for &(index, weight) in &active {
+            add_scaled(&mut positions, &self.targets[index].position_deltas, weight);
+        }
+        mesh.set_positions(positions);
+
+        if let Some(normals) = mesh.normals() {
+            let mut normals = normals.to_vec();
+            let mut changed = false;
+            for &(index, weight) in &active {
+                if let Some(deltas) = &self.targets[index].normal_deltas {
+                    add_scaled(&mut normals, deltas, weight);
+                    changed = true;
+                }
+            }
+            if changed {
+                for normal in &mut normals {
+                    let length = normal.iter().map(|c| c * c).sum::<f32>().sqrt();
+                    if length > std::f32::EPSILON {
+                        normal.iter_mut().for_each(|c| *c /= length);
+                    }
+                }
+                mesh.set_normals(normals);
+            }
+        }
+        mesh
+    }
+}
+
+/// Component holding the weight of each morph target of the entity's `MorphTargets`, from
+/// `0.0` for the base mesh to `1.0` for the full target.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MorphWeights {
+    weights: Vec<f32>,
+    dirty: bool,
+}
+
+impl Component for MorphWeights {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl MorphWeights {
+    /// Create weights for `count` targets, all at zero.
+    pub fn new(count: usize) -> Self {
+        MorphWeights {
+            weights: vec![0.0; count],
+            dirty: true,
+        }
+    }
+
+    /// The weights, one per target.
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+    /// Set the weight of target `index`, clamped to `0.0..=1.0`. The weights grow to hold
+    /// `index` if needed.
+    ///
+    /// Setting a weight schedules the mesh of the entity to be blended again, so avoid setting
+    /// weights that did not change.
+    pub fn set(&mut self, index: usize, weight: f32) {
+        if self.weights.len() <= index {
+            self.weights.resize(index + 1, 0.0);
+        }
+        self.weights[index] = weight.max(0.0).min(1.0);
+        self.dirty = true;
+    }
+
+    /// Replace all weights, each clamped to `0.0..=1.0`.
+    pub fn set_all(&mut self, weights: &[f32]) {
+        self.weights.clear();
+        self.weights
+            .extend(weights.iter().map(|w| w.max(0.0).min(1.0)));
+        self.dirty = true;
+    }
+}
+
+/// Returns the targets with the largest non zero weights, at most `MAX_ACTIVE_MORPH_TARGETS`.
+fn active_targets(weights: &[f32]) -> Vec<(usize, f32)> {
+    let mut active = weights
+        .iter()
+        .map(|w| w.max(0.0).min(1.0))
+        .enumerate()
+        .filter(|(_, w)| *w > 0.0)
+        .collect::<Vec<_>>();
+    active.sort_by(|a, b| {
+        b.1.partial_cmp(&a.1)
+            .unwrap_or(Ordering::Equal)
+            .then(a.0.cmp(&b.0))
+    });
+    active.truncate(MAX_ACTIVE_MORPH_TARGETS);
+    active
+}
+
+fn add_scaled(values: &mut [[f32; 3]], deltas: &[[f32; 3]], weight: f32) {
+    for (value, delta) in values.iter_mut().zip(deltas) {
+        for (v, d) in value.iter_mut().zip(delta) {
+            *v += d * weight;
+        }
+    }
+}
+
+/// Blends the `MorphTargets` of entities whose `MorphWeights` changed on the CPU, and keeps the
+/// result in the entity's `Handle<Mesh>`.
+///
+/// A mesh is created from the blended base mesh and registered with `DynamicMeshes` the first
+/// time an entity is updated, later updates go through `DynamicMeshes::update`, so they become
+/// visible with at most one frame of delay. Entities whose weights did not change are not
+/// uploaded again.
+///
+/// Deltas are added in mesh space, before the entity's `Transform` is applied.
+///
+/// Skinned entities are morphed first, then skinned: the blended mesh of an entity with a
+/// `CpuSkinnedMesh` replaces its bind pose with `CpuSkinnedMesh::set_base`, and is posed and
+/// uploaded by the `CpuSkinningSystem`, which must run after this system. The base of the
+/// `MorphTargets` is then the bind pose of the `CpuSkinnedMesh`. This needs
+/// `SkinningPath::Cpu`, as the skinned vertex shaders don't blend morph targets: with
+/// `SkinningPath::Gpu`, such entities are drawn in their unmorphed mesh, with a warning.
+#[derive(Default, Debug)]
+pub struct MorphSystem {
+    warned: bool,
+}
+
+impl MorphSystem {
+    /// Create new morph system
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for MorphSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, MorphTargets>,
+        WriteStorage<'a, MorphWeights>,
+        WriteStorage<'a, CpuSkinnedMesh>,
+        WriteStorage<'a, Handle<Mesh>>,
+        Write<'a, DynamicMeshes>,
+        Read<'a, SkinningPath>,
+        ReadExpect<'a, Loader>,
+        Read<'a, AssetStorage<Mesh>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            targets,
+            mut weights,
+            mut skinned,
+            mut meshes,
+            mut dynamic,
+            path,
+            loader,
+            mesh_storage,
+        ): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("morph_system");
+
+        for (entity, targets, weights) in (&entities, &targets, &mut weights).join() {
+            if let Some(skinned) = skinned.get_mut(entity) {
+                if *path == SkinningPath::Gpu && !self.warned {
+                    log::warn!(
+                        "Skinned meshes are only morphed with `SkinningPath::Cpu`, drawing them \
+                         unmorphed"
+                    );
+                    self.warned = true;
+                }
+                if weights.dirty {
+                    weights.dirty = false;
+                    if let Err(e) = skinned.set_base(targets.blend(&weights.weights)) {
+                        log::error!("Failed to morph skinned mesh: {}", e);
+                    }
+                }
+                continue;
+            }
+
+            let handle = meshes.get(entity).filter(|h| dynamic.contains(h)).cloned();
+            if !weights.dirty && handle.is_some() {
+                continue;
+            }
+            weights.dirty = false;
+            let data = targets.blend(&weights.weights);
+
+            match handle {
+                Some(handle) => {
+                    if let Err(e) = dynamic.update(&handle, data) {
+                        log::error!("Failed to update morphed mesh: {}", e);
+                    }
+                }
+                None => {
+                    let mesh_data = match data.clone().build() {
+                        Ok(mesh_data) => mesh_data,
+                        Err(e) => {
+                            log::error!("Failed to build morphed mesh: {}", e);
+                            continue;
+                        }
+                    };
+                    let handle = loader.load_from_data(mesh_data, (), &mesh_storage);
+                    dynamic.register(&handle, data);
+                    if let Err(e) = meshes.insert(entity, handle) {
+                        log::error!("Failed to insert morphed mesh: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::skinning::{CpuSkinningSystem, JointCombined, JointTransforms};
+    use amethyst_core::{
+        ecs::{Builder, RunNow, World},
+        math::{Matrix4, Point3, Vector3},
+    };
+    use rayon::ThreadPoolBuilder;
+
+    fn two_targets() -> MorphTargets {
+        let base = ProceduralMesh::new()
+            .with_positions(vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]])
+            .with_normals(vec![[0.0, 0.0, 1.0]; 3]);
+        let raise =
+            MorphTarget::new(vec![[0.0, 0.0, 1.0]; 3])
+                .with_normal_deltas(vec![[1.0, 0.0, -1.0]; 3]);
+        let widen = MorphTarget::new(vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 0.0]]);
+        MorphTargets::new(base, vec![raise, widen]).unwrap()
+    }
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-5)
+    }
+
+    #[test]
+    fn blends_weighted_targets() {
+        let targets = two_targets();
+        let mesh = targets.blend(&[0.5, 1.0]);
+        assert!(close(mesh.positions()[0], [0.0, 0.0, 0.5]));
+        assert!(close(mesh.positions()[1], [2.0, 0.0, 0.5]));
+        assert!(close(mesh.positions()[2], [0.0, 1.0, 0.5]));
+        let half = 0.5f32.sqrt();
+        assert!(close(mesh.normals().unwrap()[0], [half, 0.0, half]));
+
+        // Weights are clamped, missing weights leave targets out.
+        let mesh = targets.blend(&[2.0]);
+        assert!(close(mesh.positions()[1], [1.0, 0.0, 1.0]));
+        let mesh = targets.blend(&[]);
+        assert_eq!(mesh.positions(), targets.base().positions());
+    }
+
+    #[test]
+    fn deltas_are_in_mesh_space() {
+        let targets = two_targets();
+        let mesh = targets.blend(&[0.0, 1.0]);
+        let transform = Matrix4::new_nonuniform_scaling(&Vector3::new(1.0, 2.0, 1.0))
+            * Matrix4::new_rotation(Vector3::z() * std::f32::consts::FRAC_PI_2);
+
+        // The delta is added in mesh space, then rotated and scaled like the vertex.
+        let moved = transform.transform_point(&Point3::from(Vector3::from(mesh.positions()[1])));
+        assert!(close([moved.x, moved.y, moved.z], [0.0, 4.0, 0.0]));
+    }
+
+    #[test]
+    fn skinned_meshes_are_morphed_then_skinned() {
+        let loader = Loader::new(".", Arc::new(ThreadPoolBuilder::new().build().unwrap()));
+        let mut world = World::new();
+        world.add_resource(loader);
+        world.add_resource(SkinningPath::Cpu);
+        let mut morph = MorphSystem::new();
+        let mut skinning = CpuSkinningSystem::new();
+        RunNow::setup(&mut morph, &mut world.res);
+        RunNow::setup(&mut skinning, &mut world.res);
+
+        // The second vertex follows a joint doubling X, the others stay in place.
+        let targets = two_targets();
+        let still = JointCombined::new([0, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]);
+        let scaled = JointCombined::new([1, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]);
+        let skinned =
+            CpuSkinnedMesh::new(targets.base().clone(), vec![still, scaled, still]).unwrap();
+        let mut weights = MorphWeights::new(2);
+        weights.set(1, 1.0);
+        let skin = world.create_entity().build();
+        world
+            .create_entity()
+            .with(targets)
+            .with(weights)
+            .with(skinned)
+            .with(JointTransforms {
+                skin,
+                matrices: vec![
+                    Matrix4::identity(),
+                    Matrix4::new_nonuniform_scaling(&Vector3::new(2.0, 1.0, 1.0)),
+                ],
+            })
+            .build();
+
+        let mut posed = || {
+            morph.run_now(&world.res);
+            skinning.run_now(&world.res);
+            let mut posed = Vec::new();
+            world
+                .write_resource::<DynamicMeshes>()
+                .upload_pending(|_, data, _| {
+                    posed.push(data.clone());
+                    true
+                });
+            assert_eq!(posed.len(), 1);
+            posed.remove(0)
+        };
+
+        // Widened to 2 then doubled to 4, where skinning first would give 3.
+        let mesh = posed();
+        assert!(close(mesh.positions()[0], [0.0, 0.0, 0.0]));
+        assert!(close(mesh.positions()[1], [4.0, 0.0, 0.0]));
+
+        // New weights alone pose the mesh again.
+        for weights in (&mut world.write_storage::<MorphWeights>()).join() {
+            weights.set(1, 0.5);
+        }
+        let mesh = posed();
+        assert!(close(mesh.positions()[1], [3.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn keeps_largest_weights() {
+        let mut weights = vec![0.1; MAX_ACTIVE_MORPH_TARGETS + 2];
+        weights[3] = 0.9;
+        weights[0] = 0.0;
+        let active = active_targets(&weights);
+        assert_eq!(active.len(), MAX_ACTIVE_MORPH_TARGETS);
+        assert_eq!(active[0].0, 3);
+        assert!(active.iter().all(|(i, _)| *i != 0));
+    }
+
+    #[test]
+    fn rejects_mismatched_targets() {
+        let base = ProceduralMesh::new().with_positions(vec![[0.0; 3]; 3]);
+        assert_eq!(
+            MorphTargets::new(base.clone(), vec![MorphTarget::new(vec![[0.0; 3]; 2])]).unwrap_err(),
+            MeshError::AttributeCountMismatch {
+                attribute: "position_deltas",
+                expected: 3,
+                found: 2,
+            }
+        );
+        let target = MorphTarget::new(vec![[0.0; 3]; 3]).with_normal_deltas(vec![[0.0; 3]; 3]);
+        assert_eq!(
+            MorphTargets::new(base, vec![target]).unwrap_err(),
+            MeshError::MissingAttribute("normals")
+        );
+    }
+
+    #[test]
+    fn weights_track_changes() {
+        let mut weights = MorphWeights::new(2);
+        weights.dirty = false;
+        weights.set(3, 1.5);
+        assert!(weights.dirty);
+        assert_eq!(weights.weights(), &[0.0, 0.0, 0.0, 1.0]);
+    }
+}
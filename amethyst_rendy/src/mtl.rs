@@ -46,7 +46,7 @@ pub struct Material {
 impl Asset for Material {
     const NAME: &'static str = "renderer::Material";
     type Data = Self;
-    type HandleStorage = DenseVecStorage<Handle<Self>>;
+    type HandleStorage = FlaggedStorage<Handle<Self>, DenseVecStorage<Handle<Self>>>;
 }
 
 /// A resource providing default textures for `Material`.
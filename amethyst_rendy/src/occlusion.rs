@@ -0,0 +1,354 @@
+//! Software occlusion culling against designated occluder meshes.
+use crate::{error::MeshError, mesh_util::ProceduralMesh, visibility::BoundingBox};
+use amethyst_core::{
+    ecs::prelude::{Component, DenseVecStorage},
+    math::{Matrix4, Point3, Vector4},
+};
+
+/// Smallest clip space `w` of a projected vertex, vertices closer to the camera plane make the
+/// geometry they belong to ignored for occlusion.
+const MIN_W: f32 = 1e-5;
+
+fn corner(aabb: &BoundingBox, i: usize) -> Point3<f32> {
+    let pick = |axis: usize| {
+        if i & (1 << axis) == 0 {
+            aabb.min[axis].as_f32()
+        } else {
+            aabb.max[axis].as_f32()
+        }
+    };
+    Point3::new(pick(0), pick(1), pick(2))
+}
+
+/// Simplified geometry of an entity hiding the entities behind it, used by the occlusion culling
+/// of `VisibilitySortingSystem`.
+///
+/// Occluders are drawn into a small depth buffer on the CPU each frame, and entities with a
+/// `BoundingSphere` or `BoundingBox` are culled when their bounds are entirely behind them.
+/// Occluders should be a handful of triangles lying inside the rendered mesh, like the walls of
+/// a building: an occluder bigger than its mesh hides entities that are actually visible.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Occluder {
+    positions: Vec<Point3<f32>>,
+    indices: Vec<u32>,
+}
+
+impl Occluder {
+    /// Create an occluder from triangles in local space, three indices per triangle.
+    pub fn new(positions: Vec<[f32; 3]>, indices: Vec<u32>) -> Result<Self, MeshError> {
+        if indices.len() % 3 != 0 {
+            return Err(MeshError::InvalidIndexCount(indices.len()));
+        }
+        if let Some(index) = indices.iter().find(|i| **i as usize >= positions.len()) {
+            return Err(MeshError::IndexOutOfBounds {
+                index: *index,
+                vertex_count: positions.len(),
+            });
+        }
+        Ok(Self {
+            positions: positions
+                .into_iter()
+                .map(|p| Point3::new(p[0], p[1], p[2]))
+                .collect(),
+            indices,
+        })
+    }
+
+    /// Create an occluder covering the faces of a box in local space.
+    pub fn from_box(aabb: &BoundingBox) -> Self {
+        let positions = (0..8).map(|i| corner(aabb, i)).collect();
+        let indices = vec![
+            0, 2, 1, 1, 2, 3, // -z
+            4, 5, 6, 5, 7, 6, // +z
+            0, 1, 4, 1, 5, 4, // -y
+            2, 6, 3, 3, 6, 7, // +y
+            0, 4, 2, 2, 4, 6, // -x
+            1, 3, 5, 3, 7, 5, // +x
+        ];
+        Self { positions, indices }
+    }
+
+    /// Create an occluder from the triangles of a mesh, usually a simplified version of the
+    /// rendered one.
+    pub fn from_mesh(mesh: &ProceduralMesh) -> Result<Self, MeshError> {
+        let indices = match mesh.indices() {
+            Some(indices) => indices.to_u32(),
+            None => (0..mesh.vertex_count() as u32).collect(),
+        };
+        Self::new(mesh.positions().to_vec(), indices)
+    }
+
+    /// Vertex positions in local space.
+    pub fn positions(&self) -> &[Point3<f32>] {
+        &self.positions
+    }
+
+    /// Triangle indices, three per triangle.
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+}
+
+impl Component for Occluder {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Low resolution depth buffer holding the occluders of a frame, and its max depth pyramid.
+///
+/// Depth is the normalized device depth of the camera projection, zero at the near plane and one
+/// at the far plane. Both the occluder rasterization and the occludee tests err on the side of
+/// visibility: texels only take the depth of the farthest occluder point over them, occludees
+/// are tested with their screen rectangle grown by a texel, and anything crossing the camera
+/// plane is considered visible.
+#[derive(Debug)]
+pub(crate) struct OcclusionBuffer {
+    width: usize,
+    height: usize,
+    /// Level 0 is the full resolution depth, each next level the max of 2x2 texels of the
+    /// previous one.
+    levels: Vec<Vec<f32>>,
+}
+
+impl OcclusionBuffer {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        assert!(
+            width > 0 && height > 0,
+            "Occlusion buffer must not be empty"
+        );
+        let (width, height) = (width as usize, height as usize);
+        let mut levels = Vec::new();
+        let (mut w, mut h) = (width, height);
+        loop {
+            levels.push(vec![1.0; w * h]);
+            if w == 1 && h == 1 {
+                break;
+            }
+            w = (w + 1) / 2;
+            h = (h + 1) / 2;
+        }
+        Self {
+            width,
+            height,
+            levels,
+        }
+    }
+
+    fn level_size(&self, level: usize) -> (usize, usize) {
+        let mut size = (self.width, self.height);
+        for _ in 0..level {
+            size = ((size.0 + 1) / 2, (size.1 + 1) / 2);
+        }
+        size
+    }
+
+    /// Remove all occluders.
+    pub(crate) fn clear(&mut self) {
+        for depth in &mut self.levels[0] {
+            *depth = 1.0;
+        }
+    }
+
+    /// Project a point from world to screen space, in level 0 texels, with its depth.
+    fn project(&self, view_proj: &Matrix4<f32>, point: &Point3<f32>) -> Option<(f32, f32, f32)> {
+        let clip = view_proj * Vector4::new(point.x, point.y, point.z, 1.0);
+        if clip.w < MIN_W {
+            return None;
+        }
+        let ndc = clip.xyz() / clip.w;
+        Some((
+            (ndc.x * 0.5 + 0.5) * self.width as f32,
+            (ndc.y * 0.5 + 0.5) * self.height as f32,
+            ndc.z,
+        ))
+    }
+
+    /// Draw the triangles of an occluder transformed by `model_view_proj`.
+    pub(crate) fn rasterize(&mut self, model_view_proj: &Matrix4<f32>, occluder: &Occluder) {
+        for triangle in occluder.indices.chunks(3) {
+            let mut vertices = [(0.0, 0.0, 0.0); 3];
+            let mut projected = true;
+            for (vertex, index) in vertices.iter_mut().zip(triangle) {
+                let position = &occluder.positions[*index as usize];
+                match self.project(model_view_proj, position) {
+                    Some(v) if v.2 >= 0.0 => *vertex = v,
+                    _ => projected = false,
+                }
+            }
+            // Clipping the triangle is not worth it, it just doesn't occlude anything
+            if projected {
+                self.rasterize_triangle(vertices);
+            }
+        }
+    }
+
+    fn rasterize_triangle(&mut self, [a, b, c]: [(f32, f32, f32); 3]) {
+        let area = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+        if area.abs() < std::f32::EPSILON {
+            return;
+        }
+        // Depth is affine in screen space, as z = z0 + dx * x + dy * y
+        let dx = ((b.2 - a.2) * (c.1 - a.1) - (b.1 - a.1) * (c.2 - a.2)) / area;
+        let dy = ((b.0 - a.0) * (c.2 - a.2) - (b.2 - a.2) * (c.0 - a.0)) / area;
+        let z0 = a.2 - dx * a.0 - dy * a.1;
+        let z_min = a.2.min(b.2).min(c.2);
+        let z_max = a.2.max(b.2).max(c.2);
+
+        let x0 = a.0.min(b.0).min(c.0).floor().max(0.0) as usize;
+        let y0 = a.1.min(b.1).min(c.1).floor().max(0.0) as usize;
+        let x1 = (a.0.max(b.0).max(c.0).ceil() as usize).min(self.width);
+        let y1 = (a.1.max(b.1).max(c.1).ceil() as usize).min(self.height);
+
+        let sign = area.signum();
+        let edge = |p: (f32, f32, f32), q: (f32, f32, f32), x: f32, y: f32| {
+            ((q.0 - p.0) * (y - p.1) - (q.1 - p.1) * (x - p.0)) * sign
+        };
+
+        let width = self.width;
+        let depth = &mut self.levels[0];
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                if edge(a, b, px, py) < 0.0 || edge(b, c, px, py) < 0.0 || edge(c, a, px, py) < 0.0
+                {
+                    continue;
+                }
+                // Farthest point of the triangle's plane over the texel
+                let (fx, fy) = (x as f32, y as f32);
+                let far = z0
+                    + dx.max(0.0) * (fx + 1.0)
+                    + dx.min(0.0) * fx
+                    + dy.max(0.0) * (fy + 1.0)
+                    + dy.min(0.0) * fy;
+                let texel = &mut depth[y * width + x];
+                *texel = texel.min(far.max(z_min).min(z_max));
+            }
+        }
+    }
+
+    /// Update the depth pyramid after rasterizing the occluders of the frame.
+    pub(crate) fn build_pyramid(&mut self) {
+        for level in 1..self.levels.len() {
+            let (src_w, src_h) = self.level_size(level - 1);
+            let (dst_w, dst_h) = self.level_size(level);
+            let (done, rest) = self.levels.split_at_mut(level);
+            let (src, dst) = (&done[level - 1], &mut rest[0]);
+            for y in 0..dst_h {
+                for x in 0..dst_w {
+                    let (x0, y0) = (x * 2, y * 2);
+                    let (x1, y1) = ((x0 + 1).min(src_w - 1), (y0 + 1).min(src_h - 1));
+                    dst[y * dst_w + x] = src[y0 * src_w + x0]
+                        .max(src[y0 * src_w + x1])
+                        .max(src[y1 * src_w + x0])
+                        .max(src[y1 * src_w + x1]);
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if the world space box is entirely behind the occluders.
+    pub(crate) fn is_occluded(&self, view_proj: &Matrix4<f32>, aabb: &BoundingBox) -> bool {
+        let (mut x_min, mut y_min, mut z_min) = (std::f32::MAX, std::f32::MAX, std::f32::MAX);
+        let (mut x_max, mut y_max) = (std::f32::MIN, std::f32::MIN);
+        for i in 0..8 {
+            let (x, y, z) = match self.project(view_proj, &corner(aabb, i)) {
+                Some(projected) => projected,
+                None => return false,
+            };
+            x_min = x_min.min(x);
+            x_max = x_max.max(x);
+            y_min = y_min.min(y);
+            y_max = y_max.max(y);
+            z_min = z_min.min(z);
+        }
+        if z_min < 0.0 {
+            return false;
+        }
+
+        // Grow the rectangle by a texel, as texels are only partly covered along the edges
+        let texel = |v: f32, size: usize| v.floor().max(-2.0).min(size as f32 + 1.0) as isize;
+        let (x0, y0) = (texel(x_min, self.width) - 1, texel(y_min, self.height) - 1);
+        let (x1, y1) = (texel(x_max, self.width) + 1, texel(y_max, self.height) + 1);
+        if x1 < 0 || y1 < 0 || x0 >= self.width as isize || y0 >= self.height as isize {
+            // Off screen, left to frustum culling
+            return false;
+        }
+        let (mut x0, mut y0) = (x0.max(0) as usize, y0.max(0) as usize);
+        let mut x1 = (x1 as usize).min(self.width - 1);
+        let mut y1 = (y1 as usize).min(self.height - 1);
+
+        // Pick the level where the rectangle spans a few texels
+        let mut level = 0;
+        while level + 1 < self.levels.len() && (x1 - x0 > 3 || y1 - y0 > 3) {
+            level += 1;
+            x0 /= 2;
+            y0 /= 2;
+            x1 /= 2;
+            y1 /= 2;
+        }
+
+        let (level_width, _) = self.level_size(level);
+        let depth = &self.levels[level];
+        (y0..=y1).all(|y| (x0..=x1).all(|x| depth[y * level_width + x] < z_min))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst_core::math::Vector3;
+
+    fn aabb(min: [f32; 3], max: [f32; 3]) -> BoundingBox {
+        BoundingBox::new(
+            Point3::new(min[0].into(), min[1].into(), min[2].into()),
+            Point3::new(max[0].into(), max[1].into(), max[2].into()),
+        )
+    }
+
+    // Orthographic projection of -10..10 along x and y, with depth going from 0 at z = 0 to 1 at
+    // z = -100
+    fn view_proj() -> Matrix4<f32> {
+        Matrix4::new_nonuniform_scaling(&Vector3::new(0.1, 0.1, -0.01))
+    }
+
+    #[test]
+    fn wall_hides_boxes_behind_it() {
+        let mut buffer = OcclusionBuffer::new(64, 32);
+        let wall = Occluder::from_box(&aabb([-5.0, -5.0, -11.0], [5.0, 5.0, -10.0]));
+        buffer.rasterize(&view_proj(), &wall);
+        buffer.build_pyramid();
+
+        let view_proj = view_proj();
+        // Behind the wall
+        assert!(buffer.is_occluded(&view_proj, &aabb([-1.0, -1.0, -30.0], [1.0, 1.0, -20.0])));
+        assert!(buffer.is_occluded(&view_proj, &aabb([-4.0, -4.0, -90.0], [4.0, 4.0, -12.0])));
+        // In front of the wall, or reaching into it
+        assert!(!buffer.is_occluded(&view_proj, &aabb([-1.0, -1.0, -5.0], [1.0, 1.0, -2.0])));
+        assert!(!buffer.is_occluded(&view_proj, &aabb([-1.0, -1.0, -20.0], [1.0, 1.0, -9.0])));
+        // Inside the wall, behind its front face
+        assert!(buffer.is_occluded(&view_proj, &aabb([-1.0, -1.0, -10.8], [1.0, 1.0, -10.5])));
+        // Behind the wall, but sticking out on one side
+        assert!(!buffer.is_occluded(&view_proj, &aabb([3.0, -1.0, -30.0], [6.0, 1.0, -20.0])));
+        // The wall itself
+        let wall_box = aabb([-5.0, -5.0, -11.0], [5.0, 5.0, -10.0]);
+        assert!(!buffer.is_occluded(&view_proj, &wall_box));
+
+        buffer.clear();
+        buffer.build_pyramid();
+        assert!(!buffer.is_occluded(&view_proj, &aabb([-1.0, -1.0, -30.0], [1.0, 1.0, -20.0])));
+    }
+
+    #[test]
+    fn invalid_occluders() {
+        assert_eq!(
+            Occluder::new(vec![[0.0; 3]; 3], vec![0, 1]),
+            Err(MeshError::InvalidIndexCount(2))
+        );
+        assert_eq!(
+            Occluder::new(vec![[0.0; 3]; 3], vec![0, 1, 3]),
+            Err(MeshError::IndexOutOfBounds {
+                index: 3,
+                vertex_count: 3
+            })
+        );
+    }
+}
@@ -1,14 +1,27 @@
 use crate::{
     batch::{GroupIterator, OrderedTwoLevelBatch, TwoLevelBatch},
+    clear::{ClearConfig, DEFAULT_CLEAR_DEPTH},
+    compute::{indirect_draw_access, vertex_buffer_access},
+    content_errors, deterministic,
+    dissolve::{Dissolve, DissolveNoise},
+    error::RenderError,
+    indirect::{DrawCallStats, IndirectDraw, IndirectDraws, INDIRECT_SLOT_SIZE},
     mtl::{FullTextureSet, Material, StaticTextureSet},
-    pipeline::{PipelineDescBuilder, PipelinesBuilder},
-    pod::{SkinnedVertexArgs, VertexArgs},
-    resources::Tint,
-    skinning::JointTransforms,
-    submodules::{DynamicVertexBuffer, EnvironmentSub, MaterialId, MaterialSub, SkinningSub},
-    transparent::Transparent,
+    pipeline::{self, PipelineDescBuilder, PipelinesBuilder},
+    pod::{DissolveVertexArgs, GradientVertexArgs, SkinnedVertexArgs, VertexArgs},
+    render_stats::{GroupStats, Pass3D},
+    resources::{Tint, TintGradient3D},
+    shader_reload::{self, shader, ShaderWatch},
+    skinning::{JointTransforms, SkinningPath},
+    static_instances::{StaticCulling, StaticInstanceSet},
+    submodules::{
+        DynamicVertexBuffer, EnvironmentSub, JointBuffer, MaterialId, MaterialSub, SkinningSub,
+        TextureId, TextureSub,
+    },
+    transparent::{BlendMode, Transparent},
     types::{Backend, Mesh},
     util,
+    view_model::ViewModel,
     visibility::Visibility,
 };
 use amethyst_assets::{AssetStorage, Handle};
@@ -18,19 +31,26 @@ use amethyst_core::{
     Hidden, HiddenPropagate,
 };
 use derivative::Derivative;
+use hibitset::BitSetNot;
 use rendy::{
     command::{QueueId, RenderPassEncoder},
     factory::Factory,
     graph::{
         render::{PrepareResult, RenderGroup, RenderGroupDesc},
-        GraphContext, NodeBuffer, NodeImage,
+        BufferAccess, GraphContext, NodeBuffer, NodeImage,
+    },
+    hal::{
+        self,
+        command::{AttachmentClear, RawCommandBuffer},
+        device::Device,
+        pso,
     },
-    hal::{self, device::Device, pso},
     mesh::{AsVertex, VertexFormat},
-    shader::{Shader, SpirvShader},
+    resource::{Buffer, Handle as RendyHandle},
+    shader::SpirvShader,
 };
 use smallvec::SmallVec;
-use std::marker::PhantomData;
+use std::{marker::PhantomData, ops::Range};
 
 macro_rules! profile_scope_impl {
     ($string:expr) => {
@@ -58,21 +78,53 @@ pub trait Base3DPassDef<B: Backend>: 'static + std::fmt::Debug + Send + Sync {
     /// Returns the vertex `SpirvShader` which will be used for this pass on skinned meshes
     fn vertex_skinned_shader() -> &'static SpirvShader;
 
+    /// Returns the vertex `SpirvShader` which will be used for this pass on skinned meshes when
+    /// the `SkinningSub` binds the joints as a uniform buffer, see `JointBuffer`. Passes without
+    /// one don't draw skinned meshes on such devices. It isn't reloaded by the `shader_reload`
+    /// module.
+    fn vertex_skinned_uniform_shader() -> Option<&'static SpirvShader> {
+        None
+    }
+
     /// Returns the fragment `SpirvShader` which will be used for this pass
     fn fragment_shader() -> &'static SpirvShader;
 
+    /// Returns the vertex `SpirvShader` which will be used for this pass on meshes with a
+    /// `TintGradient3D`, taking `GradientVertexArgs`. Passes without one ignore the gradients.
+    /// It isn't reloaded by the `shader_reload` module.
+    fn vertex_gradient_shader() -> Option<&'static SpirvShader> {
+        None
+    }
+
+    /// Returns the vertex and fragment `SpirvShader`s which will be used for this pass on opaque
+    /// meshes with a `Dissolve`, taking `DissolveVertexArgs` and the noise texture at set 3.
+    /// Passes without them ignore the dissolves. They aren't reloaded by the `shader_reload`
+    /// module.
+    fn dissolve_shaders() -> Option<(&'static SpirvShader, &'static SpirvShader)> {
+        None
+    }
+
     /// Returns the `VertexFormat` of this pass
     fn base_format() -> Vec<VertexFormat>;
 
     /// Returns the `VertexFormat` of this pass for skinned meshes
     fn skinned_format() -> Vec<VertexFormat>;
+
+    /// Returns the names of the vertex, skinned vertex and fragment shaders for reloading them
+    /// with the `shader_reload` module, like `vertex/pos_tex.vert`. Passes without names always
+    /// use the shaders above.
+    fn shader_names() -> Option<[&'static str; 3]> {
+        None
+    }
 }
 
 /// Draw opaque 3d meshes with specified shaders and texture set
-#[derive(Clone, Derivative)]
-#[derivative(Debug(bound = ""), Default(bound = ""))]
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""), Default(bound = ""))]
 pub struct DrawBase3DDesc<B: Backend, T: Base3DPassDef<B>> {
     skinning: bool,
+    view_models: bool,
+    static_instances: Option<StaticCulling>,
     marker: PhantomData<(B, T)>,
 }
 
@@ -81,6 +133,8 @@ impl<B: Backend, T: Base3DPassDef<B>> DrawBase3DDesc<B, T> {
     pub fn new() -> Self {
         Self {
             skinning: false,
+            view_models: false,
+            static_instances: None,
             marker: PhantomData,
         }
     }
@@ -89,65 +143,134 @@ impl<B: Backend, T: Base3DPassDef<B>> DrawBase3DDesc<B, T> {
     pub fn skinned() -> Self {
         Self {
             skinning: true,
+            view_models: false,
+            static_instances: None,
             marker: PhantomData,
         }
     }
+
+    /// Also draw the `StaticInstanceSet` culled on the GPU, with the instances and the indirect
+    /// draws written by the node of `static_culling_node` to the two buffers the group is added
+    /// with, see the `static_instances` module.
+    pub fn with_static_instances(mut self, culling: StaticCulling) -> Self {
+        self.static_instances = Some(culling);
+        self
+    }
+
+    /// Draw only the `ViewModel` entities, over the depth cleared after the world is drawn, with
+    /// the `ViewModelProjection`.
+    pub fn for_view_models(mut self) -> Self {
+        self.view_models = true;
+        self
+    }
 }
 
 impl<B: Backend, T: Base3DPassDef<B>> RenderGroupDesc<B, Resources> for DrawBase3DDesc<B, T> {
+    fn buffers(&self) -> Vec<BufferAccess> {
+        match self.static_instances {
+            Some(_) => vec![vertex_buffer_access(), indirect_draw_access()],
+            None => Vec::new(),
+        }
+    }
+
     fn build(
         self,
-        _ctx: &GraphContext<B>,
+        ctx: &GraphContext<B>,
         factory: &mut Factory<B>,
         _queue: QueueId,
-        _aux: &Resources,
+        aux: &Resources,
         framebuffer_width: u32,
         framebuffer_height: u32,
         subpass: hal::pass::Subpass<'_, B>,
-        _buffers: Vec<NodeBuffer>,
+        buffers: Vec<NodeBuffer>,
         _images: Vec<NodeImage>,
     ) -> Result<Box<dyn RenderGroup<B, Resources>>, failure::Error> {
         profile_scope_impl!("build");
 
-        let env = EnvironmentSub::new(factory)?;
+        let static_instances = match self.static_instances {
+            Some(culling) => {
+                let buffer = |index: usize| {
+                    buffers
+                        .get(index)
+                        .and_then(|node_buffer| ctx.get_buffer(node_buffer.id))
+                        .cloned()
+                        .ok_or_else(|| {
+                            failure::format_err!("A buffer of the static instances is missing")
+                        })
+                };
+                Some(StaticDraws {
+                    culling,
+                    instances: buffer(0)?,
+                    commands: buffer(1)?,
+                    draws: Vec::new(),
+                })
+            }
+            None => None,
+        };
+
+        let env = if self.view_models {
+            EnvironmentSub::view_models(factory)?
+        } else {
+            EnvironmentSub::new(factory)?
+        };
         let materials = MaterialSub::new(factory)?;
-        let skinning = SkinningSub::new(factory)?;
+        let noise = TextureSub::new(factory)?;
+        let skinning = aux.fetch::<SkinningSub<B>>();
 
         let mut vertex_format_base = T::base_format();
         let mut vertex_format_skinned = T::skinned_format();
 
         let (mut pipelines, pipeline_layout) = build_pipelines::<B, T>(
             factory,
+            aux,
             subpass,
             framebuffer_width,
             framebuffer_height,
             &vertex_format_base,
             &vertex_format_skinned,
             self.skinning,
-            false,
+            &[None],
             vec![
                 env.raw_layout(),
                 materials.raw_layout(),
                 skinning.raw_layout(),
+                noise.raw_layout(),
             ],
         )?;
+        let reload =
+            PipelineReload::new::<B, T>(aux, framebuffer_width, framebuffer_height, self.skinning);
 
         vertex_format_base.sort();
         vertex_format_skinned.sort();
 
         Ok(Box::new(DrawBase3D::<B, T> {
-            pipeline_basic: pipelines.remove(0),
-            pipeline_skinned: pipelines.pop(),
+            pipelines: pipelines.remove(0),
             pipeline_layout,
             static_batches: Default::default(),
             skinned_batches: Default::default(),
+            gradient_batches: Default::default(),
+            dissolve_batches: Default::default(),
             vertex_format_base,
             vertex_format_skinned,
             env,
             materials,
-            skinning,
+            noise,
             models: DynamicVertexBuffer::new(),
             skinned_models: DynamicVertexBuffer::new(),
+            gradient_models: DynamicVertexBuffer::new(),
+            dissolve_models: DynamicVertexBuffer::new(),
+            indirect: IndirectDraws::new(factory),
+            stats: GroupStats::new(),
+            reload,
+            view_models: self.view_models,
+            static_instances,
+            clear_depth: false,
+            rect: pso::Rect {
+                x: 0,
+                y: 0,
+                w: framebuffer_width as i16,
+                h: framebuffer_height as i16,
+            },
             marker: PhantomData,
         }))
     }
@@ -158,32 +281,70 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroupDesc<B, Resources> for DrawBase
 #[derive(Derivative)]
 #[derivative(Debug(bound = ""))]
 pub struct DrawBase3D<B: Backend, T: Base3DPassDef<B>> {
-    pipeline_basic: B::GraphicsPipeline,
-    pipeline_skinned: Option<B::GraphicsPipeline>,
+    pipelines: ModePipelines<B>,
     pipeline_layout: B::PipelineLayout,
     static_batches: TwoLevelBatch<MaterialId, u32, SmallVec<[VertexArgs; 4]>>,
     skinned_batches: TwoLevelBatch<MaterialId, u32, SmallVec<[SkinnedVertexArgs; 4]>>,
+    gradient_batches: TwoLevelBatch<MaterialId, u32, SmallVec<[GradientVertexArgs; 4]>>,
+    dissolve_batches:
+        TwoLevelBatch<(MaterialId, TextureId), u32, SmallVec<[DissolveVertexArgs; 4]>>,
     vertex_format_base: Vec<VertexFormat>,
     vertex_format_skinned: Vec<VertexFormat>,
     env: EnvironmentSub<B>,
     materials: MaterialSub<B, T::TextureSet>,
-    skinning: SkinningSub<B>,
+    noise: TextureSub<B>,
     models: DynamicVertexBuffer<B, VertexArgs>,
     skinned_models: DynamicVertexBuffer<B, SkinnedVertexArgs>,
+    gradient_models: DynamicVertexBuffer<B, GradientVertexArgs>,
+    dissolve_models: DynamicVertexBuffer<B, DissolveVertexArgs>,
+    indirect: IndirectDraws<B>,
+    stats: GroupStats,
+    reload: PipelineReload,
+    view_models: bool,
+    static_instances: Option<StaticDraws<B>>,
+    clear_depth: bool,
+    rect: pso::Rect,
     marker: PhantomData<T>,
 }
 
+/// The buffers of the static instances culled on the GPU, and the batches drawn this frame, as
+/// their material, their mesh and their slot in the indirect draws.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+struct StaticDraws<B: Backend> {
+    culling: StaticCulling,
+    instances: RendyHandle<Buffer<B>>,
+    commands: RendyHandle<Buffer<B>>,
+    draws: Vec<(MaterialId, u32, u32)>,
+}
+
 impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3D<B, T> {
     fn prepare(
         &mut self,
         factory: &Factory<B>,
         _queue: QueueId,
         index: usize,
-        _subpass: hal::pass::Subpass<'_, B>,
+        subpass: hal::pass::Subpass<'_, B>,
         resources: &Resources,
     ) -> PrepareResult {
         profile_scope_impl!("prepare opaque");
 
+        reload_pipelines::<B, T>(
+            factory,
+            resources,
+            subpass,
+            &mut self.reload,
+            &[None],
+            vec![
+                self.env.raw_layout(),
+                self.materials.raw_layout(),
+                resources.fetch::<SkinningSub<B>>().raw_layout(),
+                self.noise.raw_layout(),
+            ],
+            std::slice::from_mut(&mut self.pipelines),
+            &mut self.pipeline_layout,
+        );
+
         let (
             mesh_storage,
             visibility,
@@ -195,6 +356,11 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3D<B
             transforms,
             joints,
             tints,
+            gradients,
+            dissolves,
+            dissolve_noise,
+            view_models,
+            static_set,
         ) = <(
             Read<AssetStorage<Mesh>>,
             Option<Read<Visibility>>,
@@ -206,31 +372,118 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3D<B
             ReadStorage<Transform>,
             ReadStorage<JointTransforms>,
             ReadStorage<Tint>,
+            ReadStorage<TintGradient3D>,
+            ReadStorage<Dissolve>,
+            Read<DissolveNoise>,
+            ReadStorage<ViewModel>,
+            Read<StaticInstanceSet>,
         )>::fetch(resources);
+        // The view models aren't culled.
+        let view_mode = self.view_models;
+        let visibility = visibility.filter(|_| !view_mode);
 
         // Prepare environment
         self.env.process(factory, index, resources);
-        self.materials.maintain();
+        self.materials.maintain(factory, resources);
+        self.noise.maintain(factory, resources);
 
-        self.static_batches.clear_inner();
-        self.skinned_batches.clear_inner();
+        let deterministic = deterministic::is_deterministic(resources);
+        self.static_batches.reset(deterministic);
+        self.skinned_batches.reset(deterministic);
+        self.gradient_batches.reset(deterministic);
+        self.dissolve_batches.reset(deterministic);
 
         let materials_ref = &mut self.materials;
-        let skinning_ref = &mut self.skinning;
+        let cpu_skinning = resources
+            .try_fetch::<SkinningPath>()
+            .map_or(false, |path| *path == SkinningPath::Cpu);
+        let gpu_skinning = self.pipelines.skinned.is_some() && !cpu_skinning;
+        let mut skinning = resources.fetch_mut::<SkinningSub<B>>();
+        if gpu_skinning {
+            skinning.prepare(factory, index, (&joints).join(), resources);
+        }
+        let skinning_ref = &*skinning;
         let statics_ref = &mut self.static_batches;
         let skinned_ref = &mut self.skinned_batches;
+        let gradients_ref = &mut self.gradient_batches;
+        let gradient_path = self.pipelines.gradient.is_some();
+        let noise_ref = &mut self.noise;
+        let dissolves_ref = &mut self.dissolve_batches;
+        let dissolve_path = self.pipelines.dissolve.is_some();
+
+        let static_input = || {
+            (
+                (&materials, &meshes, &transforms, tints.maybe()),
+                (joints.maybe(), gradients.maybe(), dissolves.maybe()),
+            )
+        };
 
-        let static_input = || ((&materials, &meshes, &transforms, tints.maybe()), !&joints);
+        // Meshes with a gradient or a dissolve are drawn by the gradient or dissolve pipeline,
+        // when the pass has one. Dissolving meshes with a gradient are drawn dissolving.
+        let is_dissolving = |dissolve: Option<&Dissolve>| dissolve_path && dissolve.is_some();
+        let is_static = |joints: Option<&JointTransforms>,
+                         gradient: Option<&TintGradient3D>,
+                         dissolve: Option<&Dissolve>| {
+            (cpu_skinning || joints.is_none())
+                && !(gradient_path && gradient.is_some())
+                && !is_dissolving(dissolve)
+        };
 
         let skinned_input = || (&materials, &meshes, &transforms, tints.maybe(), &joints);
 
+        let gradient_input = || {
+            (
+                (&materials, &meshes, &transforms, tints.maybe(), &gradients),
+                (joints.maybe(), dissolves.maybe()),
+            )
+        };
+
+        let dissolve_input = || {
+            (
+                (&materials, &meshes, &transforms, tints.maybe(), &dissolves),
+                joints.maybe(),
+            )
+        };
+
+        // The noise of a dissolving mesh depends on its material, so they're batched together.
+        let noise_layout = hal::image::Layout::ShaderReadOnlyOptimal;
+
+        // The view models are drawn opaque by their own group, the other meshes by the main one.
+        let in_group = |transparent: Option<&Transparent>, view_model: Option<&ViewModel>| {
+            if view_mode {
+                view_model.is_some()
+            } else {
+                view_model.is_none() && transparent.is_none()
+            }
+        };
+
         match &visibility {
             None => {
                 profile_scope_impl!("gather_novisibility");
 
-                (static_input(), (!&hiddens, !&hiddens_prop, !&transparent))
+                // The static instances culled on the GPU are drawn from its buffers.
+                (
+                    static_input(),
+                    (
+                        !&hiddens,
+                        !&hiddens_prop,
+                        transparent.maybe(),
+                        view_models.maybe(),
+                    ),
+                    BitSetNot(static_set.members()),
+                )
                     .join()
-                    .map(|(((mat, mesh, tform, tint), _), _)| {
+                    .filter(
+                        |(
+                            (_, (joints, gradient, dissolve)),
+                            (_, _, transparent, view_model),
+                            _,
+                        )| {
+                            in_group(*transparent, *view_model)
+                                && is_static(*joints, *gradient, *dissolve)
+                        },
+                    )
+                    .map(|(((mat, mesh, tform, tint), _), _, _)| {
                         ((mat, mesh.id()), VertexArgs::from_object_data(tform, tint))
                     })
                     .for_each_group(|(mat, mesh_id), data| {
@@ -241,20 +494,24 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3D<B
                         }
                     });
 
-                if self.pipeline_skinned.is_some() {
+                if gpu_skinning {
                     profile_scope_impl!("gather_novisibility_skinning");
 
-                    (skinned_input(), (!&hiddens, !&hiddens_prop))
+                    (
+                        skinned_input(),
+                        (!&hiddens, !&hiddens_prop, view_models.maybe()),
+                    )
                         .join()
-                        .map(|((mat, mesh, tform, tint, joints), _)| {
-                            (
+                        .filter(|(_, (_, _, view_model))| in_group(None, *view_model))
+                        .filter_map(|((mat, mesh, tform, tint, joints), _)| {
+                            Some((
                                 (mat, mesh.id()),
                                 SkinnedVertexArgs::from_object_data(
                                     tform,
                                     tint,
-                                    skinning_ref.insert(joints),
+                                    skinning_ref.offset(joints)?,
                                 ),
-                            )
+                            ))
                         })
                         .for_each_group(|(mat, mesh_id), data| {
                             if mesh_storage.contains_id(mesh_id) {
@@ -266,12 +523,89 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3D<B
                             }
                         });
                 }
+
+                if gradient_path {
+                    profile_scope_impl!("gather_novisibility_gradient");
+
+                    (
+                        gradient_input(),
+                        (
+                            !&hiddens,
+                            !&hiddens_prop,
+                            transparent.maybe(),
+                            view_models.maybe(),
+                        ),
+                    )
+                        .join()
+                        .filter(
+                            |((_, (joints, dissolve)), (_, _, transparent, view_model))| {
+                                in_group(*transparent, *view_model)
+                                    && (cpu_skinning || joints.is_none())
+                                    && !is_dissolving(*dissolve)
+                            },
+                        )
+                        .map(|(((mat, mesh, tform, tint, gradient), _), _)| {
+                            (
+                                (mat, mesh.id()),
+                                GradientVertexArgs::from_object_data(tform, tint, gradient),
+                            )
+                        })
+                        .for_each_group(|(mat, mesh_id), data| {
+                            if mesh_storage.contains_id(mesh_id) {
+                                if let Some((mat, _)) =
+                                    materials_ref.insert(factory, resources, mat)
+                                {
+                                    gradients_ref.insert(mat, mesh_id, data.drain(..));
+                                }
+                            }
+                        });
+                }
+
+                if dissolve_path {
+                    profile_scope_impl!("gather_novisibility_dissolve");
+
+                    (
+                        dissolve_input(),
+                        (
+                            !&hiddens,
+                            !&hiddens_prop,
+                            transparent.maybe(),
+                            view_models.maybe(),
+                        ),
+                    )
+                        .join()
+                        .filter(|((_, joints), (_, _, transparent, view_model))| {
+                            in_group(*transparent, *view_model)
+                                && (cpu_skinning || joints.is_none())
+                        })
+                        .map(|(((mat, mesh, tform, tint, dissolve), _), _)| {
+                            (
+                                (mat, mesh.id()),
+                                DissolveVertexArgs::from_object_data(tform, tint, dissolve),
+                            )
+                        })
+                        .for_each_group(|(mat, mesh_id), data| {
+                            if mesh_storage.contains_id(mesh_id) {
+                                let noise = dissolve_noise.noise_for(mat).and_then(|noise| {
+                                    noise_ref.insert(factory, resources, noise, noise_layout)
+                                });
+                                if let (Some((mat, _)), Some((noise, _))) =
+                                    (materials_ref.insert(factory, resources, mat), noise)
+                                {
+                                    dissolves_ref.insert((mat, noise), mesh_id, data.drain(..));
+                                }
+                            }
+                        });
+                }
             }
             Some(visibility) => {
                 profile_scope_impl!("prepare_visibility");
 
                 (static_input(), &visibility.visible_unordered)
                     .join()
+                    .filter(|((_, (joints, gradient, dissolve)), _)| {
+                        is_static(*joints, *gradient, *dissolve)
+                    })
                     .map(|(((mat, mesh, tform, tint), _), _)| {
                         ((mat, mesh.id()), VertexArgs::from_object_data(tform, tint))
                     })
@@ -283,20 +617,20 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3D<B
                         }
                     });
 
-                if self.pipeline_skinned.is_some() {
+                if gpu_skinning {
                     profile_scope_impl!("prepare_visibility_skinning");
 
                     (skinned_input(), &visibility.visible_unordered)
                         .join()
-                        .map(|((mat, mesh, tform, tint, joints), _)| {
-                            (
+                        .filter_map(|((mat, mesh, tform, tint, joints), _)| {
+                            Some((
                                 (mat, mesh.id()),
                                 SkinnedVertexArgs::from_object_data(
                                     tform,
                                     tint,
-                                    skinning_ref.insert(joints),
+                                    skinning_ref.offset(joints)?,
                                 ),
-                            )
+                            ))
                         })
                         .for_each_group(|(mat, mesh_id), data| {
                             if mesh_storage.contains_id(mesh_id) {
@@ -308,6 +642,57 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3D<B
                             }
                         });
                 }
+
+                if gradient_path {
+                    profile_scope_impl!("prepare_visibility_gradient");
+
+                    (gradient_input(), &visibility.visible_unordered)
+                        .join()
+                        .filter(|((_, (joints, dissolve)), _)| {
+                            (cpu_skinning || joints.is_none()) && !is_dissolving(*dissolve)
+                        })
+                        .map(|(((mat, mesh, tform, tint, gradient), _), _)| {
+                            (
+                                (mat, mesh.id()),
+                                GradientVertexArgs::from_object_data(tform, tint, gradient),
+                            )
+                        })
+                        .for_each_group(|(mat, mesh_id), data| {
+                            if mesh_storage.contains_id(mesh_id) {
+                                if let Some((mat, _)) =
+                                    materials_ref.insert(factory, resources, mat)
+                                {
+                                    gradients_ref.insert(mat, mesh_id, data.drain(..));
+                                }
+                            }
+                        });
+                }
+
+                if dissolve_path {
+                    profile_scope_impl!("prepare_visibility_dissolve");
+
+                    (dissolve_input(), &visibility.visible_unordered)
+                        .join()
+                        .filter(|((_, joints), _)| cpu_skinning || joints.is_none())
+                        .map(|(((mat, mesh, tform, tint, dissolve), _), _)| {
+                            (
+                                (mat, mesh.id()),
+                                DissolveVertexArgs::from_object_data(tform, tint, dissolve),
+                            )
+                        })
+                        .for_each_group(|(mat, mesh_id), data| {
+                            if mesh_storage.contains_id(mesh_id) {
+                                let noise = dissolve_noise.noise_for(mat).and_then(|noise| {
+                                    noise_ref.insert(factory, resources, noise, noise_layout)
+                                });
+                                if let (Some((mat, _)), Some((noise, _))) =
+                                    (materials_ref.insert(factory, resources, mat), noise)
+                                {
+                                    dissolves_ref.insert((mat, noise), mesh_id, data.drain(..));
+                                }
+                            }
+                        });
+                }
             }
         };
 
@@ -316,6 +701,8 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3D<B
 
             self.static_batches.prune();
             self.skinned_batches.prune();
+            self.gradient_batches.prune();
+            self.dissolve_batches.prune();
 
             self.models.write(
                 factory,
@@ -330,8 +717,98 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3D<B
                 self.skinned_batches.count() as u64,
                 self.skinned_batches.data(),
             );
-            self.skinning.commit(factory, index);
+
+            self.gradient_models.write(
+                factory,
+                index,
+                self.gradient_batches.count() as u64,
+                self.gradient_batches.data(),
+            );
+
+            self.dissolve_models.write(
+                factory,
+                index,
+                self.dissolve_batches.count() as u64,
+                self.dissolve_batches.data(),
+            );
+
+            self.indirect.clear();
+            push_draws(
+                &mut self.indirect,
+                &mesh_storage,
+                consecutive_instances(
+                    self.static_batches
+                        .iter()
+                        .flat_map(|(_, b)| b)
+                        .map(|(mesh, data)| (*mesh, data.len() as u32)),
+                ),
+            );
+            push_draws(
+                &mut self.indirect,
+                &mesh_storage,
+                consecutive_instances(
+                    self.skinned_batches
+                        .iter()
+                        .flat_map(|(_, b)| b)
+                        .map(|(mesh, data)| (*mesh, data.len() as u32)),
+                ),
+            );
+            push_draws(
+                &mut self.indirect,
+                &mesh_storage,
+                consecutive_instances(
+                    self.gradient_batches
+                        .iter()
+                        .flat_map(|(_, b)| b)
+                        .map(|(mesh, data)| (*mesh, data.len() as u32)),
+                ),
+            );
+            push_draws(
+                &mut self.indirect,
+                &mesh_storage,
+                consecutive_instances(
+                    self.dissolve_batches
+                        .iter()
+                        .flat_map(|(_, b)| b)
+                        .map(|(mesh, data)| (*mesh, data.len() as u32)),
+                ),
+            );
+            self.indirect.write(factory, index);
+            record_draws(resources, &self.indirect);
+            self.models.report(index, resources);
+            self.skinned_models.report(index, resources);
+            self.gradient_models.report(index, resources);
+            self.dissolve_models.report(index, resources);
+        }
+
+        if let Some(statics) = self.static_instances.as_mut() {
+            profile_scope_impl!("static_instances");
+
+            statics.draws.clear();
+            if static_set.is_gpu_culled() {
+                for (slot, batch) in static_set.culled(&statics.culling).iter().enumerate() {
+                    if !mesh_storage.contains(&batch.mesh) {
+                        continue;
+                    }
+                    if let Some((mat, _)) =
+                        self.materials.insert(factory, resources, &batch.material)
+                    {
+                        statics.draws.push((mat, batch.mesh.id(), slot as u32));
+                    }
+                }
+            }
+            // The instances drawn are only counted on the GPU.
+            if let Some(mut stats) = resources.try_fetch_mut::<DrawCallStats>() {
+                let batches = statics.draws.len() as u32;
+                stats.record(batches, 0, batches, true);
+            }
         }
+        self.clear_depth = view_mode
+            && self.static_batches.count()
+                + self.skinned_batches.count()
+                + self.gradient_batches.count()
+                + self.dissolve_batches.count()
+                > 0;
         PrepareResult::DrawRecord
     }
 
@@ -348,79 +825,246 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3D<B
         let models_loc = self.vertex_format_base.len() as u32;
         let skin_models_loc = self.vertex_format_skinned.len() as u32;
 
-        encoder.bind_graphics_pipeline(&self.pipeline_basic);
+        // The depth of the world is cleared only when there are view models to draw over it.
+        if self.clear_depth {
+            let depth = resources
+                .try_fetch::<ClearConfig>()
+                .map_or(DEFAULT_CLEAR_DEPTH, |config| config.depth);
+            unsafe {
+                encoder.raw().clear_attachments(
+                    Some(AttachmentClear::DepthStencil {
+                        depth: Some(depth),
+                        stencil: None,
+                    }),
+                    Some(pso::ClearRect {
+                        rect: self.rect,
+                        layers: 0..1,
+                    }),
+                );
+            }
+        }
+
+        self.stats.begin(resources);
+        encoder.bind_graphics_pipeline(&self.pipelines.basic);
         self.env.bind(index, &self.pipeline_layout, 0, &mut encoder);
+        self.stats.pipeline_bind(Pass3D::Opaque);
+        self.stats.set_binds(Pass3D::Opaque, 1);
 
+        // The slots of the skinned batches follow the ones of the static batches.
+        let mut slot = 0;
         if self.models.bind(index, models_loc, 0, &mut encoder) {
-            let mut instances_drawn = 0;
             for (&mat_id, batches) in self.static_batches.iter() {
-                if self.materials.loaded(mat_id) {
+                if !self.materials.loaded(mat_id) {
+                    slot += batches.count() as u32;
+                    continue;
+                }
+                self.materials
+                    .bind(&self.pipeline_layout, 1, mat_id, &mut encoder);
+                self.stats.set_binds(Pass3D::Opaque, 1);
+                for (mesh_id, _) in batches {
+                    debug_assert!(mesh_storage.contains_id(*mesh_id));
+                    if let Some(mesh) =
+                        B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(*mesh_id) })
+                    {
+                        if mesh.bind(0, &self.vertex_format_base, &mut encoder).is_ok() {
+                            self.indirect.draw(index, slot..slot + 1, &mut encoder);
+                            self.stats
+                                .draws(Pass3D::Opaque, &self.indirect, slot..slot + 1);
+                        } else {
+                            report_vertex_layout(resources, *mesh_id, "DrawBase3D");
+                        }
+                    }
+                    slot += 1;
+                }
+            }
+        } else {
+            slot = self
+                .static_batches
+                .iter()
+                .map(|(_, b)| b.count() as u32)
+                .sum();
+        }
+
+        if let Some(statics) = self
+            .static_instances
+            .as_ref()
+            .filter(|s| !s.draws.is_empty())
+        {
+            unsafe {
+                encoder.bind_vertex_buffers(models_loc, Some((statics.instances.raw(), 0)));
+            }
+            for &(mat_id, mesh_id, batch) in &statics.draws {
+                if !self.materials.loaded(mat_id) {
+                    continue;
+                }
+                self.materials
+                    .bind(&self.pipeline_layout, 1, mat_id, &mut encoder);
+                self.stats.set_binds(Pass3D::Opaque, 1);
+                debug_assert!(mesh_storage.contains_id(mesh_id));
+                if let Some(mesh) =
+                    B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(mesh_id) })
+                {
+                    if mesh.bind(0, &self.vertex_format_base, &mut encoder).is_ok() {
+                        let offset = u64::from(batch) * INDIRECT_SLOT_SIZE;
+                        let stride = INDIRECT_SLOT_SIZE as u32;
+                        unsafe {
+                            if mesh.index_type().is_some() {
+                                encoder.draw_indexed_indirect(
+                                    statics.commands.raw(),
+                                    offset,
+                                    1,
+                                    stride,
+                                );
+                            } else {
+                                encoder.draw_indirect(statics.commands.raw(), offset, 1, stride);
+                            }
+                        }
+                    } else {
+                        report_vertex_layout(resources, mesh_id, "DrawBase3D");
+                    }
+                }
+            }
+        }
+
+        if let Some(pipeline_skinned) = self.pipelines.skinned.as_ref() {
+            encoder.bind_graphics_pipeline(pipeline_skinned);
+            self.stats.pipeline_bind(Pass3D::Skinned);
+
+            if self
+                .skinned_models
+                .bind(index, skin_models_loc, 0, &mut encoder)
+            {
+                resources.fetch::<SkinningSub<B>>().bind(
+                    index,
+                    &self.pipeline_layout,
+                    2,
+                    &mut encoder,
+                );
+                self.stats.set_binds(Pass3D::Skinned, 1);
+
+                for (&mat_id, batches) in self.skinned_batches.iter() {
+                    if !self.materials.loaded(mat_id) {
+                        slot += batches.count() as u32;
+                        continue;
+                    }
                     self.materials
                         .bind(&self.pipeline_layout, 1, mat_id, &mut encoder);
-                    for (mesh_id, batch_data) in batches {
+                    self.stats.set_binds(Pass3D::Skinned, 1);
+                    for (mesh_id, _) in batches {
                         debug_assert!(mesh_storage.contains_id(*mesh_id));
                         if let Some(mesh) =
                             B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(*mesh_id) })
                         {
-                            mesh.bind_and_draw(
-                                0,
-                                &self.vertex_format_base,
-                                instances_drawn..instances_drawn + batch_data.len() as u32,
-                                &mut encoder,
-                            )
-                            .unwrap();
+                            if mesh
+                                .bind(0, &self.vertex_format_skinned, &mut encoder)
+                                .is_ok()
+                            {
+                                self.indirect.draw(index, slot..slot + 1, &mut encoder);
+                                self.stats
+                                    .draws(Pass3D::Skinned, &self.indirect, slot..slot + 1);
+                            } else {
+                                report_vertex_layout(resources, *mesh_id, "DrawBase3D");
+                            }
                         }
-                        instances_drawn += batch_data.len() as u32;
+                        slot += 1;
                     }
                 }
             }
         }
 
-        if let Some(pipeline_skinned) = self.pipeline_skinned.as_ref() {
-            encoder.bind_graphics_pipeline(pipeline_skinned);
+        if let Some(pipeline_gradient) = self.pipelines.gradient.as_ref() {
+            // The slots of the gradient batches follow the ones of the skinned batches.
+            slot = self
+                .static_batches
+                .iter()
+                .map(|(_, b)| b.count() as u32)
+                .chain(self.skinned_batches.iter().map(|(_, b)| b.count() as u32))
+                .sum();
+            encoder.bind_graphics_pipeline(pipeline_gradient);
+            self.stats.pipeline_bind(Pass3D::Opaque);
 
             if self
-                .skinned_models
-                .bind(index, skin_models_loc, 0, &mut encoder)
+                .gradient_models
+                .bind(index, models_loc, 0, &mut encoder)
             {
-                self.skinning
-                    .bind(index, &self.pipeline_layout, 2, &mut encoder);
+                for (&mat_id, batches) in self.gradient_batches.iter() {
+                    if !self.materials.loaded(mat_id) {
+                        slot += batches.count() as u32;
+                        continue;
+                    }
+                    self.materials
+                        .bind(&self.pipeline_layout, 1, mat_id, &mut encoder);
+                    self.stats.set_binds(Pass3D::Opaque, 1);
+                    for (mesh_id, _) in batches {
+                        debug_assert!(mesh_storage.contains_id(*mesh_id));
+                        if let Some(mesh) =
+                            B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(*mesh_id) })
+                        {
+                            if mesh.bind(0, &self.vertex_format_base, &mut encoder).is_ok() {
+                                self.indirect.draw(index, slot..slot + 1, &mut encoder);
+                                self.stats
+                                    .draws(Pass3D::Opaque, &self.indirect, slot..slot + 1);
+                            } else {
+                                report_vertex_layout(resources, *mesh_id, "DrawBase3D");
+                            }
+                        }
+                        slot += 1;
+                    }
+                }
+            }
+        }
 
-                let mut instances_drawn = 0;
-                for (&mat_id, batches) in self.skinned_batches.iter() {
-                    if self.materials.loaded(mat_id) {
-                        self.materials
-                            .bind(&self.pipeline_layout, 1, mat_id, &mut encoder);
-                        for (mesh_id, batch_data) in batches {
-                            debug_assert!(mesh_storage.contains_id(*mesh_id));
-                            if let Some(mesh) = B::unwrap_mesh(unsafe {
-                                mesh_storage.get_by_id_unchecked(*mesh_id)
-                            }) {
-                                mesh.bind_and_draw(
-                                    0,
-                                    &self.vertex_format_skinned,
-                                    instances_drawn..instances_drawn + batch_data.len() as u32,
-                                    &mut encoder,
-                                )
-                                .unwrap();
+        if let Some(pipeline_dissolve) = self.pipelines.dissolve.as_ref() {
+            // The slots of the dissolve batches follow the ones of the gradient batches.
+            slot = self
+                .static_batches
+                .iter()
+                .map(|(_, b)| b.count() as u32)
+                .chain(self.skinned_batches.iter().map(|(_, b)| b.count() as u32))
+                .chain(self.gradient_batches.iter().map(|(_, b)| b.count() as u32))
+                .sum();
+            encoder.bind_graphics_pipeline(pipeline_dissolve);
+            self.stats.pipeline_bind(Pass3D::Opaque);
+
+            if self
+                .dissolve_models
+                .bind(index, models_loc, 0, &mut encoder)
+            {
+                for (&(mat_id, noise_id), batches) in self.dissolve_batches.iter() {
+                    if !self.materials.loaded(mat_id) || !self.noise.loaded(noise_id) {
+                        slot += batches.count() as u32;
+                        continue;
+                    }
+                    self.materials
+                        .bind(&self.pipeline_layout, 1, mat_id, &mut encoder);
+                    self.noise
+                        .bind(&self.pipeline_layout, 3, noise_id, &mut encoder);
+                    self.stats.set_binds(Pass3D::Opaque, 2);
+                    for (mesh_id, _) in batches {
+                        debug_assert!(mesh_storage.contains_id(*mesh_id));
+                        if let Some(mesh) =
+                            B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(*mesh_id) })
+                        {
+                            if mesh.bind(0, &self.vertex_format_base, &mut encoder).is_ok() {
+                                self.indirect.draw(index, slot..slot + 1, &mut encoder);
+                                self.stats
+                                    .draws(Pass3D::Opaque, &self.indirect, slot..slot + 1);
+                            } else {
+                                report_vertex_layout(resources, *mesh_id, "DrawBase3D");
                             }
-                            instances_drawn += batch_data.len() as u32;
                         }
+                        slot += 1;
                     }
                 }
             }
         }
+        self.stats.finish(index, resources);
     }
 
-    fn dispose(mut self: Box<Self>, factory: &mut Factory<B>, _aux: &Resources) {
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, _aux: &Resources) {
         profile_scope_impl!("dispose");
         unsafe {
-            factory
-                .device()
-                .destroy_graphics_pipeline(self.pipeline_basic);
-            if let Some(pipeline) = self.pipeline_skinned.take() {
-                factory.device().destroy_graphics_pipeline(pipeline);
-            }
+            self.pipelines.destroy(factory);
             factory
                 .device()
                 .destroy_pipeline_layout(self.pipeline_layout);
@@ -429,8 +1073,8 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3D<B
 }
 
 /// Draw transparent mesh with physically based lighting
-#[derive(Clone, Derivative)]
-#[derivative(Debug(bound = ""), Default(bound = ""))]
+#[derive(Derivative)]
+#[derivative(Clone(bound = ""), Debug(bound = ""), Default(bound = ""))]
 pub struct DrawBase3DTransparentDesc<B: Backend, T: Base3DPassDef<B>> {
     skinning: bool,
     marker: PhantomData<(B, T)>,
@@ -462,7 +1106,7 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroupDesc<B, Resources>
         _ctx: &GraphContext<B>,
         factory: &mut Factory<B>,
         _queue: QueueId,
-        _aux: &Resources,
+        aux: &Resources,
         framebuffer_width: u32,
         framebuffer_height: u32,
         subpass: hal::pass::Subpass<'_, B>,
@@ -471,44 +1115,50 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroupDesc<B, Resources>
     ) -> Result<Box<dyn RenderGroup<B, Resources>>, failure::Error> {
         let env = EnvironmentSub::new(factory)?;
         let materials = MaterialSub::new(factory)?;
-        let skinning = SkinningSub::new(factory)?;
+        let skinning = aux.fetch::<SkinningSub<B>>();
 
         let mut vertex_format_base = T::base_format();
         let mut vertex_format_skinned = T::skinned_format();
 
-        let (mut pipelines, pipeline_layout) = build_pipelines::<B, T>(
+        let (pipelines, pipeline_layout) = build_pipelines::<B, T>(
             factory,
+            aux,
             subpass,
             framebuffer_width,
             framebuffer_height,
             &vertex_format_base,
             &vertex_format_skinned,
             self.skinning,
-            true,
+            &super::transparent_modes(),
             vec![
                 env.raw_layout(),
                 materials.raw_layout(),
                 skinning.raw_layout(),
             ],
         )?;
+        let reload =
+            PipelineReload::new::<B, T>(aux, framebuffer_width, framebuffer_height, self.skinning);
 
         vertex_format_base.sort();
         vertex_format_skinned.sort();
 
         Ok(Box::new(DrawBase3DTransparent::<B, T> {
-            pipeline_basic: pipelines.remove(0),
-            pipeline_skinned: pipelines.pop(),
+            pipelines,
             pipeline_layout,
             static_batches: Default::default(),
             skinned_batches: Default::default(),
+            gradient_batches: Default::default(),
             vertex_format_base,
             vertex_format_skinned,
             env,
             materials,
-            skinning,
             models: DynamicVertexBuffer::new(),
             skinned_models: DynamicVertexBuffer::new(),
+            gradient_models: DynamicVertexBuffer::new(),
+            indirect: IndirectDraws::new(factory),
+            stats: GroupStats::new(),
             change: Default::default(),
+            reload,
             marker: PhantomData,
         }))
     }
@@ -518,19 +1168,22 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroupDesc<B, Resources>
 #[derive(Derivative)]
 #[derivative(Debug(bound = ""))]
 pub struct DrawBase3DTransparent<B: Backend, T: Base3DPassDef<B>> {
-    pipeline_basic: B::GraphicsPipeline,
-    pipeline_skinned: Option<B::GraphicsPipeline>,
+    pipelines: Vec<ModePipelines<B>>,
     pipeline_layout: B::PipelineLayout,
-    static_batches: OrderedTwoLevelBatch<MaterialId, u32, VertexArgs>,
-    skinned_batches: OrderedTwoLevelBatch<MaterialId, u32, SkinnedVertexArgs>,
+    static_batches: OrderedTwoLevelBatch<(BlendMode, MaterialId), u32, VertexArgs>,
+    skinned_batches: OrderedTwoLevelBatch<(BlendMode, MaterialId), u32, SkinnedVertexArgs>,
+    gradient_batches: OrderedTwoLevelBatch<(BlendMode, MaterialId), u32, GradientVertexArgs>,
     vertex_format_base: Vec<VertexFormat>,
     vertex_format_skinned: Vec<VertexFormat>,
     env: EnvironmentSub<B>,
     materials: MaterialSub<B, FullTextureSet>,
-    skinning: SkinningSub<B>,
     models: DynamicVertexBuffer<B, VertexArgs>,
     skinned_models: DynamicVertexBuffer<B, SkinnedVertexArgs>,
+    gradient_models: DynamicVertexBuffer<B, GradientVertexArgs>,
+    indirect: IndirectDraws<B>,
+    stats: GroupStats,
     change: util::ChangeDetection,
+    reload: PipelineReload,
     marker: PhantomData<(T)>,
 }
 
@@ -540,102 +1193,240 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3DTr
         factory: &Factory<B>,
         _queue: QueueId,
         index: usize,
-        _subpass: hal::pass::Subpass<'_, B>,
+        subpass: hal::pass::Subpass<'_, B>,
         resources: &Resources,
     ) -> PrepareResult {
         profile_scope_impl!("prepare transparent");
 
-        let (mesh_storage, visibility, meshes, materials, transforms, joints, tints) =
-            <(
-                Read<AssetStorage<Mesh>>,
-                ReadExpect<Visibility>,
-                ReadStorage<Handle<Mesh>>,
-                ReadStorage<Handle<Material>>,
-                ReadStorage<Transform>,
-                ReadStorage<JointTransforms>,
-                ReadStorage<Tint>,
-            )>::fetch(resources);
+        let reloaded = reload_pipelines::<B, T>(
+            factory,
+            resources,
+            subpass,
+            &mut self.reload,
+            &super::transparent_modes(),
+            vec![
+                self.env.raw_layout(),
+                self.materials.raw_layout(),
+                resources.fetch::<SkinningSub<B>>().raw_layout(),
+            ],
+            &mut self.pipelines,
+            &mut self.pipeline_layout,
+        );
+
+        let (
+            mesh_storage,
+            visibility,
+            meshes,
+            materials,
+            transforms,
+            joints,
+            tints,
+            blend_modes,
+            gradients,
+        ) = <(
+            Read<AssetStorage<Mesh>>,
+            ReadExpect<Visibility>,
+            ReadStorage<Handle<Mesh>>,
+            ReadStorage<Handle<Material>>,
+            ReadStorage<Transform>,
+            ReadStorage<JointTransforms>,
+            ReadStorage<Tint>,
+            ReadStorage<BlendMode>,
+            ReadStorage<TintGradient3D>,
+        )>::fetch(resources);
 
         // Prepare environment
         self.env.process(factory, index, resources);
-        self.materials.maintain();
+        let materials_changed = self.materials.maintain(factory, resources);
 
         self.static_batches.swap_clear();
         self.skinned_batches.swap_clear();
+        self.gradient_batches.swap_clear();
 
         let materials_ref = &mut self.materials;
-        let skinning_ref = &mut self.skinning;
+        let cpu_skinning = resources
+            .try_fetch::<SkinningPath>()
+            .map_or(false, |path| *path == SkinningPath::Cpu);
+        let gpu_skinning = self.pipelines[BlendMode::Premultiplied.index()]
+            .skinned
+            .is_some()
+            && !cpu_skinning;
+        let mut skinning = resources.fetch_mut::<SkinningSub<B>>();
+        if gpu_skinning {
+            skinning.prepare(factory, index, (&joints).join(), resources);
+        }
+        let skinning_ref = &*skinning;
         let statics_ref = &mut self.static_batches;
         let skinned_ref = &mut self.skinned_batches;
-        let mut changed = false;
-
-        let mut joined = ((&materials, &meshes, &transforms, tints.maybe()), !&joints).join();
+        let gradients_ref = &mut self.gradient_batches;
+        let gradient_path = self.pipelines[BlendMode::Premultiplied.index()]
+            .gradient
+            .is_some();
+        let mut changed = materials_changed || reloaded;
+
+        // Entities without a `BlendMode` blend as premultiplied, like before there were modes.
+        let blend_mode =
+            |mode: Option<&BlendMode>| mode.cloned().unwrap_or(BlendMode::Premultiplied);
+
+        let mut joined = (
+            (
+                &materials,
+                &meshes,
+                &transforms,
+                tints.maybe(),
+                blend_modes.maybe(),
+            ),
+            (joints.maybe(), gradients.maybe()),
+        )
+            .join();
+        // Meshes with a gradient are drawn by the gradient pipelines, when the pass has them.
         visibility
             .visible_ordered
             .iter()
-            .filter_map(|e| joined.get_unchecked(e.id()))
-            .map(|((mat, mesh, tform, tint), _)| {
-                ((mat, mesh.id()), VertexArgs::from_object_data(tform, tint))
+            .filter_map(|e| joined.get_unchecked(e.id()).map(|data| (*e, data)))
+            .filter(|(_, (_, (joints, gradient)))| {
+                (cpu_skinning || joints.is_none()) && !(gradient_path && gradient.is_some())
             })
-            .for_each_group(|(mat, mesh_id), data| {
+            .map(|(e, ((mat, mesh, tform, tint, mode), _))| {
+                let tint = Tint::faded(tint, visibility.fade(e));
+                (
+                    (blend_mode(mode), mat, mesh.id()),
+                    VertexArgs::from_object_data(tform, tint.as_ref()),
+                )
+            })
+            .for_each_group(|(mode, mat, mesh_id), data| {
                 if mesh_storage.contains_id(mesh_id) {
                     if let Some((mat, this_changed)) = materials_ref.insert(factory, resources, mat)
                     {
                         changed = changed || this_changed;
-                        statics_ref.insert(mat, mesh_id, data.drain(..));
+                        statics_ref.insert((mode, mat), mesh_id, data.drain(..));
                     }
                 }
             });
 
-        if self.pipeline_skinned.is_some() {
-            let mut joined = (&materials, &meshes, &transforms, tints.maybe(), &joints).join();
+        if gpu_skinning {
+            let mut joined = (
+                &materials,
+                &meshes,
+                &transforms,
+                tints.maybe(),
+                blend_modes.maybe(),
+                &joints,
+            )
+                .join();
 
             visibility
                 .visible_ordered
                 .iter()
-                .filter_map(|e| joined.get_unchecked(e.id()))
-                .map(|(mat, mesh, tform, tint, joints)| {
-                    (
-                        (mat, mesh.id()),
+                .filter_map(|e| joined.get_unchecked(e.id()).map(|data| (*e, data)))
+                .filter_map(|(e, (mat, mesh, tform, tint, mode, joints))| {
+                    let tint = Tint::faded(tint, visibility.fade(e));
+                    Some((
+                        (blend_mode(mode), mat, mesh.id()),
                         SkinnedVertexArgs::from_object_data(
                             tform,
-                            tint,
-                            skinning_ref.insert(joints),
+                            tint.as_ref(),
+                            skinning_ref.offset(joints)?,
                         ),
+                    ))
+                })
+                .for_each_group(|(mode, mat, mesh_id), data| {
+                    if mesh_storage.contains_id(mesh_id) {
+                        if let Some((mat, this_changed)) =
+                            materials_ref.insert(factory, resources, mat)
+                        {
+                            changed = changed || this_changed;
+                            skinned_ref.insert((mode, mat), mesh_id, data.drain(..));
+                        }
+                    }
+                });
+        }
+
+        if gradient_path {
+            let mut joined = (
+                (
+                    &materials,
+                    &meshes,
+                    &transforms,
+                    tints.maybe(),
+                    blend_modes.maybe(),
+                    &gradients,
+                ),
+                joints.maybe(),
+            )
+                .join();
+
+            visibility
+                .visible_ordered
+                .iter()
+                .filter_map(|e| joined.get_unchecked(e.id()).map(|data| (*e, data)))
+                .filter(|(_, (_, joints))| cpu_skinning || joints.is_none())
+                .map(|(e, ((mat, mesh, tform, tint, mode, gradient), _))| {
+                    let tint = Tint::faded(tint, visibility.fade(e));
+                    (
+                        (blend_mode(mode), mat, mesh.id()),
+                        GradientVertexArgs::from_object_data(tform, tint.as_ref(), gradient),
                     )
                 })
-                .for_each_group(|(mat, mesh_id), data| {
+                .for_each_group(|(mode, mat, mesh_id), data| {
                     if mesh_storage.contains_id(mesh_id) {
                         if let Some((mat, this_changed)) =
                             materials_ref.insert(factory, resources, mat)
                         {
                             changed = changed || this_changed;
-                            skinned_ref.insert(mat, mesh_id, data.drain(..));
+                            gradients_ref.insert((mode, mat), mesh_id, data.drain(..));
                         }
                     }
                 });
         }
 
-        self.models.write(
+        changed = self.models.write(
             factory,
             index,
             self.static_batches.count() as u64,
             Some(self.static_batches.data()),
-        );
+        ) || changed;
 
-        self.skinned_models.write(
+        changed = self.skinned_models.write(
             factory,
             index,
             self.skinned_batches.count() as u64,
             Some(self.skinned_batches.data()),
-        );
+        ) || changed;
 
-        self.skinning.commit(factory, index);
+        changed = self.gradient_models.write(
+            factory,
+            index,
+            self.gradient_batches.count() as u64,
+            Some(self.gradient_batches.data()),
+        ) || changed;
+        self.models.report(index, resources);
+        self.skinned_models.report(index, resources);
+        self.gradient_models.report(index, resources);
 
         changed = changed || self.static_batches.changed();
         changed = changed || self.skinned_batches.changed();
+        changed = changed || self.gradient_batches.changed();
+
+        self.indirect.clear();
+        push_draws(
+            &mut self.indirect,
+            &mesh_storage,
+            self.static_batches
+                .iter()
+                .chain(self.skinned_batches.iter())
+                .chain(self.gradient_batches.iter())
+                .flat_map(|(_, b)| b.iter())
+                .map(|(mesh, range)| (*mesh, range.clone())),
+        );
+        changed = self.indirect.write(factory, index) || changed;
+        record_draws(resources, &self.indirect);
 
-        self.change.prepare_result(index, changed)
+        let result = self.change.prepare_result(index, changed);
+        if let PrepareResult::DrawReuse = result {
+            self.stats.reuse(index, resources);
+        }
+        result
     }
 
     fn draw_inline(
@@ -654,61 +1445,151 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3DTr
         let models_loc = self.vertex_format_base.len() as u32;
         let skin_models_loc = self.vertex_format_skinned.len() as u32;
 
-        encoder.bind_graphics_pipeline(&self.pipeline_basic);
+        self.stats.begin(resources);
+        let mut bound_mode = BlendMode::Premultiplied;
+        encoder.bind_graphics_pipeline(&self.pipelines[bound_mode.index()].basic);
         self.env.bind(index, layout, 0, encoder);
+        self.stats.pipeline_bind(Pass3D::Transparent);
+        self.stats.set_binds(Pass3D::Transparent, 1);
 
+        // The slots of the skinned batches follow the ones of the static batches. The pipelines
+        // of the modes share the layout, so bound descriptor sets stay valid when switching.
+        let mut slot = 0;
         if self.models.bind(index, models_loc, 0, encoder) {
-            for (&mat, batches) in self.static_batches.iter() {
-                if self.materials.loaded(mat) {
-                    self.materials.bind(layout, 1, mat, encoder);
-                    for (mesh, range) in batches {
-                        debug_assert!(mesh_storage.contains_id(*mesh));
-                        if let Some(mesh) =
-                            B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(*mesh) })
-                        {
-                            mesh.bind_and_draw(0, &self.vertex_format_base, range.clone(), encoder)
-                                .unwrap();
+            for (&(mode, mat), batches) in self.static_batches.iter() {
+                if !self.materials.loaded(mat) {
+                    slot += batches.len() as u32;
+                    continue;
+                }
+                if mode != bound_mode {
+                    encoder.bind_graphics_pipeline(&self.pipelines[mode.index()].basic);
+                    self.stats.pipeline_bind(Pass3D::Transparent);
+                    bound_mode = mode;
+                }
+                self.materials.bind(layout, 1, mat, encoder);
+                self.stats.set_binds(Pass3D::Transparent, 1);
+                for (mesh_id, _) in batches {
+                    debug_assert!(mesh_storage.contains_id(*mesh_id));
+                    if let Some(mesh) =
+                        B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(*mesh_id) })
+                    {
+                        if mesh.bind(0, &self.vertex_format_base, encoder).is_ok() {
+                            self.indirect.draw(index, slot..slot + 1, encoder);
+                            self.stats
+                                .draws(Pass3D::Transparent, &self.indirect, slot..slot + 1);
+                        } else {
+                            report_vertex_layout(resources, *mesh_id, "DrawBase3DTransparent");
                         }
                     }
+                    slot += 1;
                 }
             }
+        } else {
+            slot = self
+                .static_batches
+                .iter()
+                .map(|(_, b)| b.len() as u32)
+                .sum();
         }
 
-        if let Some(pipeline_skinned) = self.pipeline_skinned.as_ref() {
+        let mut bound_mode = BlendMode::Premultiplied;
+        if let Some(pipeline_skinned) = self.pipelines[bound_mode.index()].skinned.as_ref() {
             encoder.bind_graphics_pipeline(pipeline_skinned);
+            self.stats.pipeline_bind(Pass3D::Skinned);
 
             if self.skinned_models.bind(index, skin_models_loc, 0, encoder) {
-                self.skinning.bind(index, layout, 2, encoder);
-                for (&mat, batches) in self.skinned_batches.iter() {
-                    if self.materials.loaded(mat) {
-                        self.materials.bind(layout, 1, mat, encoder);
-                        for (mesh, range) in batches {
-                            debug_assert!(mesh_storage.contains_id(*mesh));
-                            if let Some(mesh) =
-                                B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(*mesh) })
-                            {
-                                mesh.bind_and_draw(
-                                    0,
-                                    &self.vertex_format_skinned,
-                                    range.clone(),
-                                    encoder,
-                                )
-                                .unwrap();
+                resources
+                    .fetch::<SkinningSub<B>>()
+                    .bind(index, layout, 2, encoder);
+                self.stats.set_binds(Pass3D::Skinned, 1);
+                for (&(mode, mat), batches) in self.skinned_batches.iter() {
+                    if !self.materials.loaded(mat) {
+                        slot += batches.len() as u32;
+                        continue;
+                    }
+                    if mode != bound_mode {
+                        if let Some(pipeline) = &self.pipelines[mode.index()].skinned {
+                            encoder.bind_graphics_pipeline(pipeline);
+                            self.stats.pipeline_bind(Pass3D::Skinned);
+                        }
+                        bound_mode = mode;
+                    }
+                    self.materials.bind(layout, 1, mat, encoder);
+                    self.stats.set_binds(Pass3D::Skinned, 1);
+                    for (mesh_id, _) in batches {
+                        debug_assert!(mesh_storage.contains_id(*mesh_id));
+                        if let Some(mesh) =
+                            B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(*mesh_id) })
+                        {
+                            if mesh.bind(0, &self.vertex_format_skinned, encoder).is_ok() {
+                                self.indirect.draw(index, slot..slot + 1, encoder);
+                                self.stats
+                                    .draws(Pass3D::Skinned, &self.indirect, slot..slot + 1);
+                            } else {
+                                report_vertex_layout(resources, *mesh_id, "DrawBase3DTransparent");
                             }
                         }
+                        slot += 1;
                     }
                 }
             }
         }
+
+        let mut bound_mode = BlendMode::Premultiplied;
+        if let Some(pipeline_gradient) = self.pipelines[bound_mode.index()].gradient.as_ref() {
+            // The slots of the gradient batches follow the ones of the skinned batches.
+            slot = self
+                .static_batches
+                .iter()
+                .chain(self.skinned_batches.iter())
+                .map(|(_, b)| b.len() as u32)
+                .sum();
+            encoder.bind_graphics_pipeline(pipeline_gradient);
+            self.stats.pipeline_bind(Pass3D::Transparent);
+
+            if self.gradient_models.bind(index, models_loc, 0, encoder) {
+                for (&(mode, mat), batches) in self.gradient_batches.iter() {
+                    if !self.materials.loaded(mat) {
+                        slot += batches.len() as u32;
+                        continue;
+                    }
+                    if mode != bound_mode {
+                        if let Some(pipeline) = &self.pipelines[mode.index()].gradient {
+                            encoder.bind_graphics_pipeline(pipeline);
+                            self.stats.pipeline_bind(Pass3D::Transparent);
+                        }
+                        bound_mode = mode;
+                    }
+                    self.materials.bind(layout, 1, mat, encoder);
+                    self.stats.set_binds(Pass3D::Transparent, 1);
+                    for (mesh_id, _) in batches {
+                        debug_assert!(mesh_storage.contains_id(*mesh_id));
+                        if let Some(mesh) =
+                            B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(*mesh_id) })
+                        {
+                            if mesh.bind(0, &self.vertex_format_base, encoder).is_ok() {
+                                self.indirect.draw(index, slot..slot + 1, encoder);
+                                self.stats.draws(
+                                    Pass3D::Transparent,
+                                    &self.indirect,
+                                    slot..slot + 1,
+                                );
+                            } else {
+                                report_vertex_layout(resources, *mesh_id, "DrawBase3DTransparent");
+                            }
+                        }
+                        slot += 1;
+                    }
+                }
+            }
+        }
+        self.stats.finish(index, resources);
     }
 
-    fn dispose(mut self: Box<Self>, factory: &mut Factory<B>, _aux: &Resources) {
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, _aux: &Resources) {
         unsafe {
-            factory
-                .device()
-                .destroy_graphics_pipeline(self.pipeline_basic);
-            if let Some(pipeline) = self.pipeline_skinned.take() {
-                factory.device().destroy_graphics_pipeline(pipeline);
+            for pipelines in self.pipelines {
+                pipelines.destroy(factory);
             }
             factory
                 .device()
@@ -717,17 +1598,115 @@ impl<B: Backend, T: Base3DPassDef<B>> RenderGroup<B, Resources> for DrawBase3DTr
     }
 }
 
+/// Report a mesh `group` can't bind to the `ContentErrors` resource, its draw being skipped.
+fn report_vertex_layout(resources: &Resources, mesh: u32, group: &'static str) {
+    content_errors::report(
+        resources,
+        RenderError::UnsupportedVertexLayout { mesh, group },
+    );
+}
+
+/// The ranges of the instances of batches given as mesh ids and instance counts, when the
+/// instances of each batch follow the ones of the previous batch in the instance buffer.
+fn consecutive_instances(
+    batches: impl Iterator<Item = (u32, u32)>,
+) -> impl Iterator<Item = (u32, Range<u32>)> {
+    let mut first = 0;
+    batches.map(move |(mesh, count)| {
+        let instances = first..first + count;
+        first = instances.end;
+        (mesh, instances)
+    })
+}
+
+/// Push the draws of batches of loaded meshes to `indirect`, one slot per batch.
+fn push_draws<B: Backend>(
+    indirect: &mut IndirectDraws<B>,
+    mesh_storage: &AssetStorage<Mesh>,
+    batches: impl Iterator<Item = (u32, Range<u32>)>,
+) {
+    for (mesh_id, instances) in batches {
+        let mesh = B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(mesh_id) });
+        indirect.push(mesh.map_or_else(IndirectDraw::empty, |mesh| {
+            IndirectDraw::new(mesh.len(), mesh.index_type().is_some(), instances)
+        }));
+    }
+}
+
+/// Count the draws of a group in the `DrawCallStats`, one call per batch.
+fn record_draws<B: Backend>(resources: &Resources, indirect: &IndirectDraws<B>) {
+    if let Some(mut stats) = resources.try_fetch_mut::<DrawCallStats>() {
+        let batches = indirect.len();
+        stats.record(
+            batches,
+            indirect.instances(),
+            batches,
+            indirect.support().indirect,
+        );
+    }
+}
+
+/// The pipelines of a 3D group drawing a blend mode, for static, skinned, gradient and
+/// dissolving meshes.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+struct ModePipelines<B: Backend> {
+    basic: B::GraphicsPipeline,
+    skinned: Option<B::GraphicsPipeline>,
+    gradient: Option<B::GraphicsPipeline>,
+    dissolve: Option<B::GraphicsPipeline>,
+}
+
+impl<B: Backend> ModePipelines<B> {
+    /// Group the pipelines built for each mode, the basic one followed by the skinned one when
+    /// `skinning`, the gradient one when `gradient`, and the dissolve one when `dissolve`.
+    fn split(
+        pipelines: Vec<B::GraphicsPipeline>,
+        skinning: bool,
+        gradient: bool,
+        dissolve: bool,
+    ) -> Vec<Self> {
+        let mut pipelines = pipelines.into_iter();
+        let mut modes = Vec::new();
+        while let Some(basic) = pipelines.next() {
+            modes.push(Self {
+                basic,
+                skinned: if skinning { pipelines.next() } else { None },
+                gradient: if gradient { pipelines.next() } else { None },
+                dissolve: if dissolve { pipelines.next() } else { None },
+            });
+        }
+        modes
+    }
+
+    unsafe fn destroy(self, factory: &Factory<B>) {
+        factory.device().destroy_graphics_pipeline(self.basic);
+        for pipeline in self
+            .skinned
+            .into_iter()
+            .chain(self.gradient)
+            .chain(self.dissolve)
+        {
+            factory.device().destroy_graphics_pipeline(pipeline);
+        }
+    }
+}
+
+/// Build the pipelines of each blend mode, sharing a single layout. Meshes are opaque when the
+/// mode is `None`, and only opaque meshes dissolve, with the noise at the set 3 of `layouts`.
+#[allow(clippy::too_many_arguments)]
 fn build_pipelines<B: Backend, T: Base3DPassDef<B>>(
     factory: &Factory<B>,
+    aux: &Resources,
     subpass: hal::pass::Subpass<'_, B>,
     framebuffer_width: u32,
     framebuffer_height: u32,
     vertex_format_base: &[VertexFormat],
     vertex_format_skinned: &[VertexFormat],
     skinning: bool,
-    transparent: bool,
+    modes: &[Option<BlendMode>],
     layouts: Vec<&B::DescriptorSetLayout>,
-) -> Result<(Vec<B::GraphicsPipeline>, B::PipelineLayout), failure::Error> {
+) -> Result<(Vec<ModePipelines<B>>, B::PipelineLayout), failure::Error> {
     let pipeline_layout = unsafe {
         factory
             .device()
@@ -743,10 +1722,19 @@ fn build_pipelines<B: Backend, T: Base3DPassDef<B>>(
         )))
         .collect::<Vec<_>>();
 
-    let shader_vertex_basic = unsafe { T::vertex_shader().module(factory).unwrap() };
-    let shader_fragment = unsafe { T::fragment_shader().module(factory).unwrap() };
+    let opaque = modes.iter().all(Option::is_none);
+    let group = if opaque {
+        "DrawBase3D"
+    } else {
+        "DrawBase3DTransparent"
+    };
+    let vertex_basic = pass_shader::<B, T>(aux, 0, T::vertex_shader());
+    let fragment = pass_shader::<B, T>(aux, 2, T::fragment_shader());
+    let shader_vertex_basic = unsafe { pipeline::shader_module(factory, group, &vertex_basic) }?;
+    let shader_fragment = unsafe { pipeline::shader_module(factory, group, &fragment) }?;
     let pipe_desc = PipelineDescBuilder::new()
         .with_vertex_desc(&vertex_desc)
+        .with_vertex_inputs(pipeline::vertex_inputs(&vertex_basic))
         .with_shaders(util::simple_shader_set(
             &shader_vertex_basic,
             Some(&shader_fragment),
@@ -754,59 +1742,154 @@ fn build_pipelines<B: Backend, T: Base3DPassDef<B>>(
         .with_layout(&pipeline_layout)
         .with_subpass(subpass)
         .with_framebuffer_size(framebuffer_width, framebuffer_height)
-        .with_face_culling(pso::Face::BACK)
-        .with_depth_test(pso::DepthTest::On {
-            fun: pso::Comparison::Less,
-            write: !transparent,
-        })
-        .with_blend_targets(vec![pso::ColorBlendDesc(
-            pso::ColorMask::ALL,
-            if transparent {
-                pso::BlendState::PREMULTIPLIED_ALPHA
-            } else {
-                pso::BlendState::Off
-            },
-        )]);
-
-    let pipelines = if skinning {
-        let shader_vertex_skinned = unsafe { T::vertex_skinned_shader().module(factory).unwrap() };
+        .with_face_culling(pso::Face::BACK);
 
-        let vertex_desc = vertex_format_skinned
-            .iter()
-            .map(|f| (f.clone(), pso::VertexInputRate::Vertex))
-            .chain(Some((
-                SkinnedVertexArgs::vertex(),
-                pso::VertexInputRate::Instance(1),
-            )))
-            .collect::<Vec<_>>();
-
-        let pipe = PipelinesBuilder::new()
-            .with_pipeline(pipe_desc.clone())
-            .with_child_pipeline(
-                0,
-                pipe_desc
-                    .with_vertex_desc(&vertex_desc)
-                    .with_shaders(util::simple_shader_set(
-                        &shader_vertex_skinned,
-                        Some(&shader_fragment),
-                    )),
+    let vertex_skinned = match aux.fetch::<SkinningSub<B>>().joint_buffer() {
+        JointBuffer::Storage => Some(pass_shader::<B, T>(aux, 1, T::vertex_skinned_shader())),
+        JointBuffer::Uniform => T::vertex_skinned_uniform_shader().cloned(),
+    };
+    if skinning && vertex_skinned.is_none() {
+        log::warn!(
+            "The {} pass has no shader reading joints from a uniform buffer, skinned meshes \
+             won't be drawn",
+            T::NAME
+        );
+    }
+    let shader_vertex_skinned = match vertex_skinned.as_ref() {
+        Some(vertex_skinned) if skinning => {
+            Some(unsafe { pipeline::shader_module(factory, group, vertex_skinned) }?)
+        }
+        _ => None,
+    };
+    let vertex_desc_skinned = vertex_format_skinned
+        .iter()
+        .map(|f| (f.clone(), pso::VertexInputRate::Vertex))
+        .chain(Some((
+            SkinnedVertexArgs::vertex(),
+            pso::VertexInputRate::Instance(1),
+        )))
+        .collect::<Vec<_>>();
+    let skinned_desc = shader_vertex_skinned.as_ref().map(|shader_vertex_skinned| {
+        pipe_desc
+            .clone()
+            .with_vertex_desc(&vertex_desc_skinned)
+            .with_vertex_inputs(
+                vertex_skinned
+                    .as_ref()
+                    .map(pipeline::vertex_inputs)
+                    .unwrap_or_default(),
             )
-            .build(factory, None);
-
-        unsafe {
-            factory.destroy_shader_module(shader_vertex_skinned);
+            .with_shaders(util::simple_shader_set(
+                shader_vertex_skinned,
+                Some(&shader_fragment),
+            ))
+    });
+    let skinned = skinned_desc.is_some();
+
+    let vertex_gradient = T::vertex_gradient_shader();
+    let shader_vertex_gradient = match vertex_gradient {
+        Some(vertex_gradient) => {
+            Some(unsafe { pipeline::shader_module(factory, group, vertex_gradient) }?)
         }
-
-        pipe
-    } else {
-        PipelinesBuilder::new()
-            .with_pipeline(pipe_desc)
-            .build(factory, None)
+        None => None,
     };
+    let vertex_desc_gradient = vertex_format_base
+        .iter()
+        .map(|f| (f.clone(), pso::VertexInputRate::Vertex))
+        .chain(Some((
+            GradientVertexArgs::vertex(),
+            pso::VertexInputRate::Instance(1),
+        )))
+        .collect::<Vec<_>>();
+    let gradient_desc = shader_vertex_gradient
+        .as_ref()
+        .map(|shader_vertex_gradient| {
+            pipe_desc
+                .clone()
+                .with_vertex_desc(&vertex_desc_gradient)
+                .with_vertex_inputs(
+                    vertex_gradient
+                        .map(pipeline::vertex_inputs)
+                        .unwrap_or_default(),
+                )
+                .with_shaders(util::simple_shader_set(
+                    shader_vertex_gradient,
+                    Some(&shader_fragment),
+                ))
+        });
+    let gradient = gradient_desc.is_some();
+
+    let dissolve_shaders = T::dissolve_shaders().filter(|_| opaque);
+    let shader_dissolve = match dissolve_shaders {
+        Some((vertex, fragment)) => Some((
+            unsafe { pipeline::shader_module(factory, group, vertex) }?,
+            unsafe { pipeline::shader_module(factory, group, fragment) }?,
+        )),
+        None => None,
+    };
+    let vertex_desc_dissolve = vertex_format_base
+        .iter()
+        .map(|f| (f.clone(), pso::VertexInputRate::Vertex))
+        .chain(Some((
+            DissolveVertexArgs::vertex(),
+            pso::VertexInputRate::Instance(1),
+        )))
+        .collect::<Vec<_>>();
+    let dissolve_desc = shader_dissolve
+        .as_ref()
+        .map(|(shader_vertex, shader_fragment)| {
+            pipe_desc
+                .clone()
+                .with_vertex_desc(&vertex_desc_dissolve)
+                .with_vertex_inputs(
+                    dissolve_shaders
+                        .map(|(vertex, _)| pipeline::vertex_inputs(vertex))
+                        .unwrap_or_default(),
+                )
+                .with_shaders(util::simple_shader_set(
+                    shader_vertex,
+                    Some(shader_fragment),
+                ))
+        });
+    let dissolve = dissolve_desc.is_some();
+
+    // The basic, skinned, gradient and dissolve pipelines of each mode, children of the first one
+    let pipelines = modes
+        .iter()
+        .flat_map(|&mode| {
+            Some(&pipe_desc)
+                .into_iter()
+                .chain(skinned_desc.as_ref())
+                .chain(gradient_desc.as_ref())
+                .chain(dissolve_desc.as_ref())
+                .map(move |desc| super::with_blend_mode(desc.clone(), mode))
+        })
+        .enumerate()
+        .fold(
+            PipelinesBuilder::new().with_group(group),
+            |builder, (i, desc)| {
+                if i == 0 {
+                    builder.with_pipeline(desc)
+                } else {
+                    builder.with_child_pipeline(0, desc)
+                }
+            },
+        )
+        .build_cached(factory, aux);
 
     unsafe {
         factory.destroy_shader_module(shader_vertex_basic);
         factory.destroy_shader_module(shader_fragment);
+        if let Some(shader_vertex_skinned) = shader_vertex_skinned {
+            factory.destroy_shader_module(shader_vertex_skinned);
+        }
+        if let Some(shader_vertex_gradient) = shader_vertex_gradient {
+            factory.destroy_shader_module(shader_vertex_gradient);
+        }
+        if let Some((shader_vertex, shader_fragment)) = shader_dissolve {
+            factory.destroy_shader_module(shader_vertex);
+            factory.destroy_shader_module(shader_fragment);
+        }
     }
 
     match pipelines {
@@ -816,6 +1899,90 @@ fn build_pipelines<B: Backend, T: Base3DPassDef<B>>(
             }
             Err(e)
         }
-        Ok(pipelines) => Ok((pipelines, pipeline_layout)),
+        Ok(pipelines) => Ok((
+            ModePipelines::split(pipelines, skinned, gradient, dissolve),
+            pipeline_layout,
+        )),
+    }
+}
+
+/// Shader `index` of the `shader_names` of a pass, reloaded if it has names.
+fn pass_shader<B: Backend, T: Base3DPassDef<B>>(
+    aux: &Resources,
+    index: usize,
+    builtin: &SpirvShader,
+) -> SpirvShader {
+    match T::shader_names() {
+        Some(names) => shader(aux, names[index], builtin),
+        None => builtin.clone(),
+    }
+}
+
+/// What a 3D group needs to rebuild its pipelines when its shaders are reloaded.
+#[derive(Debug)]
+struct PipelineReload {
+    shaders: ShaderWatch,
+    framebuffer_size: (u32, u32),
+    skinning: bool,
+}
+
+impl PipelineReload {
+    fn new<B: Backend, T: Base3DPassDef<B>>(
+        aux: &Resources,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        skinning: bool,
+    ) -> Self {
+        let names = T::shader_names().map_or_else(Vec::new, |names| names.to_vec());
+        Self {
+            shaders: ShaderWatch::new(aux, &names),
+            framebuffer_size: (framebuffer_width, framebuffer_height),
+            skinning,
+        }
+    }
+}
+
+/// Rebuild the pipelines of a 3D group if its shaders were reloaded, keeping the previous ones
+/// if that fails. Returns whether the pipelines were replaced.
+#[allow(clippy::too_many_arguments)]
+fn reload_pipelines<B: Backend, T: Base3DPassDef<B>>(
+    factory: &Factory<B>,
+    aux: &Resources,
+    subpass: hal::pass::Subpass<'_, B>,
+    reload: &mut PipelineReload,
+    modes: &[Option<BlendMode>],
+    layouts: Vec<&B::DescriptorSetLayout>,
+    pipelines: &mut [ModePipelines<B>],
+    pipeline_layout: &mut B::PipelineLayout,
+) -> bool {
+    if !reload.shaders.changed(aux) {
+        return false;
+    }
+    let (framebuffer_width, framebuffer_height) = reload.framebuffer_size;
+    let built = build_pipelines::<B, T>(
+        factory,
+        aux,
+        subpass,
+        framebuffer_width,
+        framebuffer_height,
+        &T::base_format(),
+        &T::skinned_format(),
+        reload.skinning,
+        modes,
+        layouts,
+    );
+    match shader_reload::rebuilt(factory, T::NAME, built) {
+        Some((new_pipelines, layout)) => {
+            unsafe {
+                for (pipelines, new_pipelines) in pipelines.iter_mut().zip(new_pipelines) {
+                    std::mem::replace(pipelines, new_pipelines).destroy(factory);
+                }
+                factory
+                    .device()
+                    .destroy_pipeline_layout(std::mem::replace(pipeline_layout, layout));
+            }
+            true
+        }
+        None => false,
     }
 }
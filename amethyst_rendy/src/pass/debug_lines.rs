@@ -1,12 +1,20 @@
 use crate::{
-    debug_drawing::{DebugLine, DebugLines, DebugLinesComponent, DebugLinesParams},
-    pipeline::{PipelineDescBuilder, PipelinesBuilder},
+    debug_drawing::{
+        ClearPolicy, DebugLine, DebugLines, DebugLinesComponent, DebugLinesParams, DebugLinesStats,
+        ScreenDebugLines,
+    },
+    layers::RenderLayers,
+    pipeline::{self, PipelineDescBuilder, PipelinesBuilder},
     pod::ViewArgs,
-    submodules::{gather::CameraGatherer, DynamicUniform, DynamicVertexBuffer},
+    shader_reload::{self, shader, ShaderWatch},
+    submodules::{gather::CameraGatherer, DynamicUniform, DynamicVertexBuffer, PushConstants},
     types::Backend,
     util,
 };
-use amethyst_core::ecs::{Join, Read, Resources, SystemData, Write, WriteStorage};
+use amethyst_core::{
+    ecs::{Join, Read, ReadStorage, Resources, SystemData, Write, WriteStorage},
+    math::Matrix4,
+};
 use derivative::Derivative;
 use glsl_layout::*;
 use rendy::{
@@ -18,12 +26,14 @@ use rendy::{
     },
     hal::{self, device::Device, pso},
     mesh::AsVertex,
-    shader::Shader,
 };
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
 
+/// `screen_space_thickness` is half the size of a pixel in clip space, the shader expanding the
+/// lines to quads multiplies it with the width of each line. Passed as push constants, with the
+/// shaders declaring them, or as the uniform of the set 1 where they aren't supported.
 #[derive(Debug, Clone, AsStd140)]
 struct DebugLinesArgs {
     screen_space_thickness: vec2,
@@ -32,13 +42,22 @@ struct DebugLinesArgs {
 /// Draw opaque sprites without lighting.
 #[derive(Clone, Debug, PartialEq, Derivative)]
 #[derivative(Default(bound = ""))]
-pub struct DrawDebugLinesDesc;
+pub struct DrawDebugLinesDesc {
+    honor_layers: bool,
+}
 
 impl DrawDebugLinesDesc {
     /// Create instance of `DrawDebugLines` render group
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Only draw the `DebugLinesComponent`s whose `RenderLayers` are seen by the active camera.
+    /// Lines of the `DebugLines` resource are always drawn.
+    pub fn with_render_layers(mut self) -> Self {
+        self.honor_layers = true;
+        self
+    }
 }
 
 impl<B: Backend> RenderGroupDesc<B, Resources> for DrawDebugLinesDesc {
@@ -47,7 +66,7 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawDebugLinesDesc {
         _ctx: &GraphContext<B>,
         factory: &mut Factory<B>,
         _queue: QueueId,
-        _aux: &Resources,
+        aux: &Resources,
         framebuffer_width: u32,
         framebuffer_height: u32,
         subpass: hal::pass::Subpass<'_, B>,
@@ -57,20 +76,26 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawDebugLinesDesc {
         #[cfg(feature = "profiler")]
         profile_scope!("build");
 
+        let geometry = geometry_lines(factory);
         let env = DynamicUniform::new(factory, pso::ShaderStageFlags::VERTEX)?;
-        let args = DynamicUniform::new(factory, pso::ShaderStageFlags::VERTEX)?;
+        let args = PushConstants::new(factory, args_stages(geometry))?;
         let vertex = DynamicVertexBuffer::new();
 
-        let (pipeline, pipeline_layout) = build_lines_pipeline(
+        let (pipeline, thin_pipeline, pipeline_layout) = build_lines_pipeline(
             factory,
+            aux,
             subpass,
             framebuffer_width,
             framebuffer_height,
-            vec![env.raw_layout(), args.raw_layout()],
+            env.raw_layout(),
+            &args,
+            true,
+            geometry,
         )?;
 
         Ok(Box::new(DrawDebugLines::<B> {
             pipeline,
+            thin_pipeline,
             pipeline_layout,
             env,
             args,
@@ -78,7 +103,12 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawDebugLinesDesc {
             framebuffer_width: framebuffer_width as f32,
             framebuffer_height: framebuffer_height as f32,
             lines: Vec::new(),
+            scratch: Vec::new(),
+            thin_count: 0,
+            geometry,
             change: Default::default(),
+            honor_layers: self.honor_layers,
+            shaders: ShaderWatch::new(aux, &SHADER_NAMES),
         }))
     }
 }
@@ -87,14 +117,20 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawDebugLinesDesc {
 #[derive(Debug)]
 pub struct DrawDebugLines<B: Backend> {
     pipeline: B::GraphicsPipeline,
+    thin_pipeline: B::GraphicsPipeline,
     pipeline_layout: B::PipelineLayout,
     env: DynamicUniform<B, ViewArgs>,
-    args: DynamicUniform<B, DebugLinesArgs>,
+    args: PushConstants<B, DebugLinesArgs>,
     vertex: DynamicVertexBuffer<B, DebugLine>,
     framebuffer_width: f32,
     framebuffer_height: f32,
     lines: Vec<DebugLine>,
+    scratch: Vec<DebugLine>,
+    thin_count: usize,
+    geometry: bool,
     change: util::ChangeDetection,
+    honor_layers: bool,
+    shaders: ShaderWatch,
 }
 
 impl<B: Backend> RenderGroup<B, Resources> for DrawDebugLines<B> {
@@ -103,55 +139,87 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawDebugLines<B> {
         factory: &Factory<B>,
         _queue: QueueId,
         index: usize,
-        _subpass: hal::pass::Subpass<'_, B>,
+        subpass: hal::pass::Subpass<'_, B>,
         resources: &Resources,
     ) -> PrepareResult {
         #[cfg(feature = "profiler")]
         profile_scope!("prepare");
 
-        let (lines_comps, lines_res, line_params) = <(
+        let (mut lines_comps, layers, lines_res, line_params, stats) = <(
             WriteStorage<DebugLinesComponent>,
+            ReadStorage<RenderLayers>,
             Option<Write<DebugLines>>,
             Option<Read<DebugLinesParams>>,
+            Option<Write<DebugLinesStats>>,
         )>::fetch(resources);
 
-        let old_len = self.lines.len();
+        let cam = CameraGatherer::gather(resources);
+
+        let (old_len, old_thin_count) = (self.lines.len(), self.thin_count);
         self.lines.clear();
-        for lines_component in (&lines_comps).join() {
+        for (lines_component, layers) in (&lines_comps, layers.maybe()).join() {
+            if self.honor_layers && !layers.map_or(true, |l| l.intersects(cam.layers)) {
+                continue;
+            }
             self.lines.extend_from_slice(lines_component.lines());
+            lines_component.text_lines(cam.camera_right, cam.camera_up, &mut self.lines);
+        }
+        for lines_component in (&mut lines_comps).join() {
+            if lines_component.clear_policy() == ClearPolicy::EveryFrame {
+                lines_component.clear();
+            }
         }
 
         if let Some(mut lines_res) = lines_res {
             self.lines.extend(lines_res.drain());
+            lines_res.drain_text(cam.camera_right, cam.camera_up, &mut self.lines);
+            self.lines.extend_from_slice(lines_res.timed_lines());
         };
-
-        let cam = CameraGatherer::gather(resources);
-        let line_width = line_params
-            .map(|p| p.line_width)
-            .unwrap_or(DebugLinesParams::default().line_width);
-
         self.env.write(factory, index, cam.projview);
-        self.args.write(
+        let args_changed = self.args.write(
             factory,
             index,
-            DebugLinesArgs {
-                screen_space_thickness: [
-                    (line_width * 2.0) / self.framebuffer_width,
-                    (line_width * 2.0) / self.framebuffer_height,
-                ]
-                .into(),
-            }
-            .std140(),
+            lines_args(self.framebuffer_width, self.framebuffer_height),
         );
+        let default_width =
+            line_params.map_or(DebugLinesParams::default().line_width, |p| p.line_width);
+        self.thin_count = partition_lines(&mut self.lines, &mut self.scratch, default_width);
 
-        {
+        let reallocated = {
             #[cfg(feature = "profiler")]
             profile_scope!("write");
             self.vertex
-                .write(factory, index, self.lines.len() as u64, Some(&self.lines));
+                .write(factory, index, self.lines.len() as u64, Some(&self.lines))
+        };
+        self.vertex.report(index, resources);
+
+        if let Some(mut stats) = stats {
+            stats.lines = self.lines.len();
+            stats.buffer_bytes = self.vertex.capacity(index);
         }
 
-        let changed = old_len != self.lines.len();
+        let reloaded = self.shaders.changed(resources)
+            && reload_lines_pipelines(
+                factory,
+                resources,
+                subpass,
+                (
+                    self.framebuffer_width as u32,
+                    self.framebuffer_height as u32,
+                ),
+                self.env.raw_layout(),
+                &self.args,
+                true,
+                self.geometry,
+                &mut self.pipeline,
+                &mut self.thin_pipeline,
+                &mut self.pipeline_layout,
+            );
+        let changed = old_len != self.lines.len()
+            || old_thin_count != self.thin_count
+            || args_changed
+            || reloaded
+            || reallocated;
         self.change.prepare_result(index, changed)
     }
 
@@ -169,19 +237,224 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawDebugLines<B> {
             return;
         }
 
-        let layout = &self.pipeline_layout;
-        encoder.bind_graphics_pipeline(&self.pipeline);
-        self.env.bind(index, layout, 0, &mut encoder);
-        self.args.bind(index, layout, 1, &mut encoder);
-        self.vertex.bind(index, 0, 0, &mut encoder);
+        draw_lines(
+            &mut encoder,
+            index,
+            &self.pipeline,
+            &self.thin_pipeline,
+            &self.pipeline_layout,
+            &self.env,
+            &self.args,
+            &self.vertex,
+            self.thin_count as u32,
+            self.lines.len() as u32,
+            self.geometry,
+        );
+    }
+
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, _aux: &Resources) {
         unsafe {
-            encoder.draw(0..4, 0..self.lines.len() as u32);
+            factory.device().destroy_graphics_pipeline(self.pipeline);
+            factory
+                .device()
+                .destroy_graphics_pipeline(self.thin_pipeline);
+            factory
+                .device()
+                .destroy_pipeline_layout(self.pipeline_layout);
+        }
+    }
+}
+
+/// Draw the lines of the `ScreenDebugLines` resource, given in framebuffer pixels.
+///
+/// The lines are drawn without depth test, so this should be the last group of the subpass.
+#[derive(Clone, Debug, PartialEq, Derivative)]
+#[derivative(Default(bound = ""))]
+pub struct DrawScreenDebugLinesDesc;
+
+impl DrawScreenDebugLinesDesc {
+    /// Create instance of `DrawScreenDebugLines` render group
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<B: Backend> RenderGroupDesc<B, Resources> for DrawScreenDebugLinesDesc {
+    fn build(
+        self,
+        _ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        aux: &Resources,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, Resources>>, failure::Error> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("build");
+
+        let geometry = geometry_lines(factory);
+        let env = DynamicUniform::new(factory, pso::ShaderStageFlags::VERTEX)?;
+        let args = PushConstants::new(factory, args_stages(geometry))?;
+        let vertex = DynamicVertexBuffer::new();
+
+        let (pipeline, thin_pipeline, pipeline_layout) = build_lines_pipeline(
+            factory,
+            aux,
+            subpass,
+            framebuffer_width,
+            framebuffer_height,
+            env.raw_layout(),
+            &args,
+            false,
+            geometry,
+        )?;
+
+        Ok(Box::new(DrawScreenDebugLines::<B> {
+            pipeline,
+            thin_pipeline,
+            pipeline_layout,
+            env,
+            args,
+            vertex,
+            framebuffer_width: framebuffer_width as f32,
+            framebuffer_height: framebuffer_height as f32,
+            lines: Vec::new(),
+            scratch: Vec::new(),
+            thin_count: 0,
+            geometry,
+            change: Default::default(),
+            shaders: ShaderWatch::new(aux, &SHADER_NAMES),
+        }))
+    }
+}
+
+/// Draws screen space debug lines
+#[derive(Debug)]
+pub struct DrawScreenDebugLines<B: Backend> {
+    pipeline: B::GraphicsPipeline,
+    thin_pipeline: B::GraphicsPipeline,
+    pipeline_layout: B::PipelineLayout,
+    env: DynamicUniform<B, ViewArgs>,
+    args: PushConstants<B, DebugLinesArgs>,
+    vertex: DynamicVertexBuffer<B, DebugLine>,
+    framebuffer_width: f32,
+    framebuffer_height: f32,
+    lines: Vec<DebugLine>,
+    scratch: Vec<DebugLine>,
+    thin_count: usize,
+    geometry: bool,
+    change: util::ChangeDetection,
+    shaders: ShaderWatch,
+}
+
+impl<B: Backend> RenderGroup<B, Resources> for DrawScreenDebugLines<B> {
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        _queue: QueueId,
+        index: usize,
+        subpass: hal::pass::Subpass<'_, B>,
+        resources: &Resources,
+    ) -> PrepareResult {
+        #[cfg(feature = "profiler")]
+        profile_scope!("prepare");
+
+        let (lines_res, line_params) = <(
+            Option<Write<ScreenDebugLines>>,
+            Option<Read<DebugLinesParams>>,
+        )>::fetch(resources);
+
+        let (old_len, old_thin_count) = (self.lines.len(), self.thin_count);
+        self.lines.clear();
+        if let Some(mut lines_res) = lines_res {
+            self.lines.extend(lines_res.drain());
+        };
+
+        self.env.write(
+            factory,
+            index,
+            pixel_view_args(self.framebuffer_width, self.framebuffer_height),
+        );
+        let args_changed = self.args.write(
+            factory,
+            index,
+            lines_args(self.framebuffer_width, self.framebuffer_height),
+        );
+        let default_width =
+            line_params.map_or(DebugLinesParams::default().line_width, |p| p.line_width);
+        self.thin_count = partition_lines(&mut self.lines, &mut self.scratch, default_width);
+
+        let reallocated = {
+            #[cfg(feature = "profiler")]
+            profile_scope!("write");
+            self.vertex
+                .write(factory, index, self.lines.len() as u64, Some(&self.lines))
+        };
+        self.vertex.report(index, resources);
+
+        let reloaded = self.shaders.changed(resources)
+            && reload_lines_pipelines(
+                factory,
+                resources,
+                subpass,
+                (
+                    self.framebuffer_width as u32,
+                    self.framebuffer_height as u32,
+                ),
+                self.env.raw_layout(),
+                &self.args,
+                false,
+                self.geometry,
+                &mut self.pipeline,
+                &mut self.thin_pipeline,
+                &mut self.pipeline_layout,
+            );
+        let changed = old_len != self.lines.len()
+            || old_thin_count != self.thin_count
+            || args_changed
+            || reloaded
+            || reallocated;
+        self.change.prepare_result(index, changed)
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        _resources: &Resources,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("draw");
+
+        if self.lines.is_empty() {
+            return;
         }
+
+        draw_lines(
+            &mut encoder,
+            index,
+            &self.pipeline,
+            &self.thin_pipeline,
+            &self.pipeline_layout,
+            &self.env,
+            &self.args,
+            &self.vertex,
+            self.thin_count as u32,
+            self.lines.len() as u32,
+            self.geometry,
+        );
     }
 
     fn dispose(self: Box<Self>, factory: &mut Factory<B>, _aux: &Resources) {
         unsafe {
             factory.device().destroy_graphics_pipeline(self.pipeline);
+            factory
+                .device()
+                .destroy_graphics_pipeline(self.thin_pipeline);
             factory
                 .device()
                 .destroy_pipeline_layout(self.pipeline_layout);
@@ -189,31 +462,258 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawDebugLines<B> {
     }
 }
 
+fn lines_args(
+    framebuffer_width: f32,
+    framebuffer_height: f32,
+) -> <DebugLinesArgs as AsStd140>::Std140 {
+    DebugLinesArgs {
+        screen_space_thickness: [1.0 / framebuffer_width, 1.0 / framebuffer_height].into(),
+    }
+    .std140()
+}
+
+/// Gives the lines without a width of their own `default_width`, and moves the lines at most
+/// one pixel wide to the front, returning their count.
+fn partition_lines(
+    lines: &mut Vec<DebugLine>,
+    scratch: &mut Vec<DebugLine>,
+    default_width: f32,
+) -> usize {
+    scratch.clear();
+    let mut thin_count = 0;
+    for i in 0..lines.len() {
+        let mut line = lines[i];
+        if line.width() <= 0.0 {
+            line.set_width(default_width);
+        }
+        if line.width() <= 1.0 {
+            lines[thin_count] = line;
+            thin_count += 1;
+        } else {
+            scratch.push(line);
+        }
+    }
+    lines.truncate(thin_count);
+    lines.extend_from_slice(scratch);
+    thin_count
+}
+
+/// Draws the first `thin_count` lines as plain lines, and the others as quads, expanded by the
+/// geometry shader from two vertices per line if `geometry` is set.
+#[allow(clippy::too_many_arguments)]
+fn draw_lines<B: Backend>(
+    encoder: &mut RenderPassEncoder<'_, B>,
+    index: usize,
+    pipeline: &B::GraphicsPipeline,
+    thin_pipeline: &B::GraphicsPipeline,
+    layout: &B::PipelineLayout,
+    env: &DynamicUniform<B, ViewArgs>,
+    args: &PushConstants<B, DebugLinesArgs>,
+    vertex: &DynamicVertexBuffer<B, DebugLine>,
+    thin_count: u32,
+    count: u32,
+    geometry: bool,
+) {
+    // Both pipelines share the layout, so the bindings stay valid when switching
+    if thin_count > 0 {
+        encoder.bind_graphics_pipeline(thin_pipeline);
+    } else {
+        encoder.bind_graphics_pipeline(pipeline);
+    }
+    env.bind(index, layout, 0, encoder);
+    args.bind(index, layout, 1, encoder);
+    vertex.bind(index, 0, 0, encoder);
+    unsafe {
+        if thin_count > 0 {
+            encoder.draw(0..2, 0..thin_count);
+            if thin_count < count {
+                encoder.bind_graphics_pipeline(pipeline);
+            }
+        }
+        if thin_count < count {
+            encoder.draw(0..if geometry { 2 } else { 4 }, thin_count..count);
+        }
+    }
+}
+
+/// Projection from framebuffer pixels, with the origin at the top left corner, to clip space.
+fn pixel_projection(framebuffer_width: f32, framebuffer_height: f32) -> Matrix4<f32> {
+    Matrix4::new(
+        2.0 / framebuffer_width,
+        0.0,
+        0.0,
+        -1.0,
+        0.0,
+        2.0 / framebuffer_height,
+        0.0,
+        -1.0,
+        0.0,
+        0.0,
+        1.0,
+        0.0,
+        0.0,
+        0.0,
+        0.0,
+        1.0,
+    )
+}
+
+fn pixel_view_args(
+    framebuffer_width: f32,
+    framebuffer_height: f32,
+) -> <ViewArgs as AsStd140>::Std140 {
+    let proj: [[f32; 4]; 4] = pixel_projection(framebuffer_width, framebuffer_height).into();
+    let view: [[f32; 4]; 4] = Matrix4::<f32>::identity().into();
+    ViewArgs {
+        proj: proj.into(),
+        view: view.into(),
+    }
+    .std140()
+}
+
+const SHADER_NAMES: [&str; 6] = [
+    "vertex/debug_lines.vert",
+    "fragment/debug_lines.frag",
+    "vertex/debug_lines_geom.vert",
+    "geometry/debug_lines.geom",
+    "vertex/debug_lines_push.vert",
+    "geometry/debug_lines_push.geom",
+];
+
+/// Whether wide lines are expanded to quads by a geometry shader, drawing two vertices per line,
+/// rather than by the vertex shader drawing four, which devices without geometry shaders do.
+fn geometry_lines<B: Backend>(factory: &Factory<B>) -> bool {
+    util::check_shader_stages(factory, pso::ShaderStageFlags::GEOMETRY).is_ok()
+}
+
+/// Stages reading the `DebugLinesArgs`.
+fn args_stages(geometry: bool) -> pso::ShaderStageFlags {
+    if geometry {
+        pso::ShaderStageFlags::VERTEX | pso::ShaderStageFlags::GEOMETRY
+    } else {
+        pso::ShaderStageFlags::VERTEX
+    }
+}
+
+/// Rebuilds the pipelines of a lines group after its shaders were reloaded, keeping the previous
+/// ones if that fails. Returns whether the pipelines were replaced.
+#[allow(clippy::too_many_arguments)]
+fn reload_lines_pipelines<B: Backend>(
+    factory: &Factory<B>,
+    aux: &Resources,
+    subpass: hal::pass::Subpass<'_, B>,
+    (framebuffer_width, framebuffer_height): (u32, u32),
+    env: &B::DescriptorSetLayout,
+    args: &PushConstants<B, DebugLinesArgs>,
+    depth_test: bool,
+    geometry: bool,
+    pipeline: &mut B::GraphicsPipeline,
+    thin_pipeline: &mut B::GraphicsPipeline,
+    pipeline_layout: &mut B::PipelineLayout,
+) -> bool {
+    let built = build_lines_pipeline(
+        factory,
+        aux,
+        subpass,
+        framebuffer_width,
+        framebuffer_height,
+        env,
+        args,
+        depth_test,
+        geometry,
+    );
+    match shader_reload::rebuilt(factory, "DrawDebugLines", built) {
+        Some((new_pipeline, new_thin_pipeline, new_layout)) => {
+            unsafe {
+                let device = factory.device();
+                device.destroy_graphics_pipeline(std::mem::replace(pipeline, new_pipeline));
+                device
+                    .destroy_graphics_pipeline(std::mem::replace(thin_pipeline, new_thin_pipeline));
+                device.destroy_pipeline_layout(std::mem::replace(pipeline_layout, new_layout));
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Builds the pipelines drawing lines as quads and as plain lines. The quads are expanded by a
+/// geometry shader if `geometry` is set. The shaders read the `args` as push constants, or from
+/// the set after the `env` if they aren't pushed.
+#[allow(clippy::too_many_arguments)]
 fn build_lines_pipeline<B: Backend>(
     factory: &Factory<B>,
+    aux: &Resources,
     subpass: hal::pass::Subpass<'_, B>,
     framebuffer_width: u32,
     framebuffer_height: u32,
-    layouts: Vec<&B::DescriptorSetLayout>,
-) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
+    env: &B::DescriptorSetLayout,
+    args: &PushConstants<B, DebugLinesArgs>,
+    depth_test: bool,
+    geometry: bool,
+) -> Result<(B::GraphicsPipeline, B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
     let pipeline_layout = unsafe {
         factory
             .device()
-            .create_pipeline_layout(layouts, None as Option<(_, _)>)
+            .create_pipeline_layout(std::iter::once(env).chain(args.raw_layout()), args.range())
     }?;
 
-    let shader_vertex = unsafe { super::DEBUG_LINES_VERTEX.module(factory).unwrap() };
-    let shader_fragment = unsafe { super::DEBUG_LINES_FRAGMENT.module(factory).unwrap() };
+    // The shaders reading the args, declared as push constants or as a uniform
+    let (args_vertex, args_geometry) = if args.pushed() {
+        (
+            (SHADER_NAMES[4], &*super::DEBUG_LINES_PUSH_VERTEX),
+            (SHADER_NAMES[5], &*super::DEBUG_LINES_PUSH_GEOMETRY),
+        )
+    } else {
+        (
+            (SHADER_NAMES[0], &*super::DEBUG_LINES_VERTEX),
+            (SHADER_NAMES[3], &*super::DEBUG_LINES_GEOMETRY),
+        )
+    };
+
+    let vertex = shader(aux, args_vertex.0, args_vertex.1);
+    let fragment = shader(aux, SHADER_NAMES[1], &super::DEBUG_LINES_FRAGMENT);
+    let shader_vertex = unsafe { pipeline::shader_module(factory, "DrawDebugLines", &vertex) }?;
+    let shader_fragment = unsafe { pipeline::shader_module(factory, "DrawDebugLines", &fragment) }?;
+    let geom_vertex = shader(aux, SHADER_NAMES[2], &super::DEBUG_LINES_GEOM_VERTEX);
+    let geometry_shaders = if geometry {
+        unsafe {
+            Some((
+                pipeline::shader_module(factory, "DrawDebugLines", &geom_vertex)?,
+                pipeline::shader_module(
+                    factory,
+                    "DrawDebugLines",
+                    &shader(aux, args_geometry.0, args_geometry.1),
+                )?,
+            ))
+        }
+    } else {
+        None
+    };
+    let wide_vertex = if geometry { &geom_vertex } else { &vertex };
+
+    let (wide_shaders, wide_primitive) = match &geometry_shaders {
+        Some((vertex, geometry)) => (
+            util::shader_set_with_geometry(factory, vertex, geometry, Some(&shader_fragment)),
+            hal::Primitive::LineList,
+        ),
+        None => (
+            Ok(util::simple_shader_set(
+                &shader_vertex,
+                Some(&shader_fragment),
+            )),
+            hal::Primitive::TriangleStrip,
+        ),
+    };
 
-    let pipes = PipelinesBuilder::new()
-        .with_pipeline(
-            PipelineDescBuilder::new()
+    let pipes = wide_shaders
+        .map_err(failure::Error::from)
+        .and_then(|wide_shaders| {
+            let pipe_desc = PipelineDescBuilder::new()
                 .with_vertex_desc(&[(DebugLine::vertex(), pso::VertexInputRate::Instance(1))])
-                .with_input_assembler(pso::InputAssemblerDesc::new(hal::Primitive::TriangleStrip))
-                .with_shaders(util::simple_shader_set(
-                    &shader_vertex,
-                    Some(&shader_fragment),
-                ))
+                .with_vertex_inputs(pipeline::vertex_inputs(wide_vertex))
+                .with_input_assembler(pso::InputAssemblerDesc::new(wide_primitive))
+                .with_shaders(wide_shaders)
                 .with_layout(&pipeline_layout)
                 .with_subpass(subpass)
                 .with_framebuffer_size(framebuffer_width, framebuffer_height)
@@ -221,16 +721,40 @@ fn build_lines_pipeline<B: Backend>(
                     pso::ColorMask::ALL,
                     pso::BlendState::ALPHA,
                 )])
-                .with_depth_test(pso::DepthTest::On {
-                    fun: pso::Comparison::LessEqual,
-                    write: true,
-                }),
-        )
-        .build(factory, None);
+                .with_depth_test(if depth_test {
+                    pso::DepthTest::On {
+                        fun: pso::Comparison::LessEqual,
+                        write: true,
+                    }
+                } else {
+                    pso::DepthTest::Off
+                });
+
+            PipelinesBuilder::new()
+                .with_group("DrawDebugLines")
+                .with_pipeline(pipe_desc.clone())
+                .with_child_pipeline(
+                    0,
+                    pipe_desc
+                        .with_input_assembler(pso::InputAssemblerDesc::new(
+                            hal::Primitive::LineList,
+                        ))
+                        .with_vertex_inputs(pipeline::vertex_inputs(&vertex))
+                        .with_shaders(util::simple_shader_set(
+                            &shader_vertex,
+                            Some(&shader_fragment),
+                        )),
+                )
+                .build_cached(factory, aux)
+        });
 
     unsafe {
         factory.destroy_shader_module(shader_vertex);
         factory.destroy_shader_module(shader_fragment);
+        if let Some((vertex, geometry)) = geometry_shaders {
+            factory.destroy_shader_module(vertex);
+            factory.destroy_shader_module(geometry);
+        }
     }
 
     match pipes {
@@ -240,6 +764,50 @@ fn build_lines_pipeline<B: Backend>(
             }
             Err(e)
         }
-        Ok(mut pipes) => Ok((pipes.remove(0), pipeline_layout)),
+        Ok(mut pipes) => {
+            let thin = pipes.remove(1);
+            Ok((pipes.remove(0), thin, pipeline_layout))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst_core::math::Point3;
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn thin_lines_first() {
+        let mut lines = DebugLinesComponent::new();
+        let (start, end) = (Point3::origin(), Point3::new(1.0, 0.0, 0.0));
+        lines.add_line(start, end, Default::default());
+        lines.set_line_width(4.0);
+        lines.add_line(start, end, Default::default());
+        lines.set_line_width(1.0);
+        lines.add_line(start, end, Default::default());
+
+        let mut lines = lines.lines().to_vec();
+        let mut scratch = Vec::new();
+        assert_eq!(partition_lines(&mut lines, &mut scratch, 1.0), 2);
+        let widths: Vec<_> = lines.iter().map(DebugLine::width).collect();
+        assert_eq!(widths, vec![1.0, 1.0, 4.0]);
+
+        assert_eq!(partition_lines(&mut lines, &mut scratch, 3.0), 2);
+        let mut lines = lines[..1].to_vec();
+        lines[0].set_width(0.0);
+        assert_eq!(partition_lines(&mut lines, &mut scratch, 3.0), 0);
+        assert_eq!(lines[0].width(), 3.0);
+    }
+
+    #[test]
+    fn pixels_to_clip_space() {
+        let proj = pixel_projection(800.0, 600.0);
+        let top_left = proj.transform_point(&Point3::new(0.0, 0.0, 0.0));
+        let bottom_right = proj.transform_point(&Point3::new(800.0, 600.0, 0.0));
+        let center = proj.transform_point(&Point3::new(400.0, 300.0, 0.0));
+        assert!((top_left - Point3::new(-1.0, -1.0, 0.0)).norm() < 1e-6);
+        assert!((bottom_right - Point3::new(1.0, 1.0, 0.0)).norm() < 1e-6);
+        assert!(center.coords.norm() < 1e-6);
     }
 }
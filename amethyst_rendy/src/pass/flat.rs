@@ -17,9 +17,21 @@ impl<B: Backend> Base3DPassDef<B> for FlatPassDef {
     fn vertex_skinned_shader() -> &'static SpirvShader {
         &super::POS_TEX_SKIN_VERTEX
     }
+    fn vertex_skinned_uniform_shader() -> Option<&'static SpirvShader> {
+        Some(&super::POS_TEX_SKIN_UNIFORM_VERTEX)
+    }
+    fn vertex_gradient_shader() -> Option<&'static SpirvShader> {
+        Some(&super::POS_TEX_GRADIENT_VERTEX)
+    }
     fn fragment_shader() -> &'static SpirvShader {
         &super::FLAT_FRAGMENT
     }
+    fn dissolve_shaders() -> Option<(&'static SpirvShader, &'static SpirvShader)> {
+        Some((
+            &*super::POS_TEX_DISSOLVE_VERTEX,
+            &*super::FLAT_DISSOLVE_FRAGMENT,
+        ))
+    }
     fn base_format() -> Vec<VertexFormat> {
         vec![Position::vertex(), TexCoord::vertex()]
     }
@@ -30,6 +42,13 @@ impl<B: Backend> Base3DPassDef<B> for FlatPassDef {
             JointCombined::vertex(),
         ]
     }
+    fn shader_names() -> Option<[&'static str; 3]> {
+        Some([
+            "vertex/pos_tex.vert",
+            "vertex/pos_tex_skin.vert",
+            "fragment/flat.frag",
+        ])
+    }
 }
 
 /// Describes a Flat 3D pass
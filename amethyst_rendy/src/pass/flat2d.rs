@@ -1,17 +1,20 @@
 use crate::{
     batch::{GroupIterator, OneLevelBatch, OrderedOneLevelBatch},
-    pipeline::{PipelineDescBuilder, PipelinesBuilder},
+    content_errors, deterministic,
+    pipeline::{self, PipelineDescBuilder, PipelinesBuilder},
     pod::SpriteArgs,
     resources::Tint,
+    shader_reload::{self, shader, ShaderWatch},
     sprite::{SpriteRender, SpriteSheet},
     sprite_visibility::SpriteVisibility,
     submodules::{DynamicVertexBuffer, FlatEnvironmentSub, TextureId, TextureSub},
+    transparent::BlendMode,
     types::{Backend, Texture},
     util,
 };
-use amethyst_assets::AssetStorage;
+use amethyst_assets::{AssetStorage, Handle};
 use amethyst_core::{
-    ecs::{Join, Read, ReadExpect, ReadStorage, Resources, SystemData},
+    ecs::{Entities, Entity, Join, Read, ReadExpect, ReadStorage, Resources, SystemData},
     transform::Transform,
     Hidden, HiddenPropagate,
 };
@@ -25,12 +28,31 @@ use rendy::{
     },
     hal::{self, device::Device, pso},
     mesh::AsVertex,
-    shader::Shader,
 };
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
 
+const SHADER_NAMES: [&str; 2] = ["vertex/sprite.vert", "fragment/sprite.frag"];
+
+/// The vertex data of the sprite of `entity`, reporting sprites out of the range of their sheet
+/// to the `ContentErrors` resource.
+fn sprite_args<'a>(
+    resources: &Resources,
+    entity: Entity,
+    tex_storage: &AssetStorage<Texture>,
+    sprite_storage: &'a AssetStorage<SpriteSheet>,
+    sprite_render: &SpriteRender,
+    transform: &Transform,
+    tint: Option<&Tint>,
+) -> Option<(SpriteArgs, &'a Handle<Texture>)> {
+    SpriteArgs::try_from_data(tex_storage, sprite_storage, sprite_render, transform, tint)
+        .unwrap_or_else(|e| {
+            content_errors::report(resources, e.with_entity(entity));
+            None
+        })
+}
+
 /// Draw opaque sprites without lighting.
 #[derive(Clone, Debug, PartialEq, Derivative)]
 #[derivative(Default(bound = ""))]
@@ -49,7 +71,7 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawFlat2DDesc {
         _ctx: &GraphContext<B>,
         factory: &mut Factory<B>,
         _queue: QueueId,
-        _aux: &Resources,
+        aux: &Resources,
         framebuffer_width: u32,
         framebuffer_height: u32,
         subpass: hal::pass::Subpass<'_, B>,
@@ -63,14 +85,16 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawFlat2DDesc {
         let textures = TextureSub::new(factory)?;
         let vertex = DynamicVertexBuffer::new();
 
-        let (pipeline, pipeline_layout) = build_sprite_pipeline(
+        let (mut pipelines, pipeline_layout) = build_sprite_pipelines(
             factory,
+            aux,
             subpass,
             framebuffer_width,
             framebuffer_height,
-            false,
+            &[None],
             vec![env.raw_layout(), textures.raw_layout()],
         )?;
+        let pipeline = pipelines.remove(0);
 
         Ok(Box::new(DrawFlat2D::<B> {
             pipeline,
@@ -79,6 +103,8 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawFlat2DDesc {
             textures,
             vertex,
             sprites: Default::default(),
+            framebuffer_size: (framebuffer_width, framebuffer_height),
+            shaders: ShaderWatch::new(aux, &SHADER_NAMES),
         }))
     }
 }
@@ -92,6 +118,8 @@ pub struct DrawFlat2D<B: Backend> {
     textures: TextureSub<B>,
     vertex: DynamicVertexBuffer<B, SpriteArgs>,
     sprites: OneLevelBatch<TextureId, SpriteArgs>,
+    framebuffer_size: (u32, u32),
+    shaders: ShaderWatch,
 }
 
 impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2D<B> {
@@ -100,13 +128,14 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2D<B> {
         factory: &Factory<B>,
         _queue: QueueId,
         index: usize,
-        _subpass: hal::pass::Subpass<'_, B>,
+        subpass: hal::pass::Subpass<'_, B>,
         resources: &Resources,
     ) -> PrepareResult {
         #[cfg(feature = "profiler")]
         profile_scope!("prepare opaque");
 
         let (
+            entities,
             sprite_sheet_storage,
             tex_storage,
             visibilities,
@@ -116,6 +145,7 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2D<B> {
             transforms,
             tints,
         ) = <(
+            Entities<'_>,
             Read<'_, AssetStorage<SpriteSheet>>,
             Read<'_, AssetStorage<Texture>>,
             Option<Read<'_, SpriteVisibility>>,
@@ -131,7 +161,7 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2D<B> {
         let sprites_ref = &mut self.sprites;
         let textures_ref = &mut self.textures;
 
-        sprites_ref.clear_inner();
+        sprites_ref.reset(deterministic::is_deterministic(resources));
 
         match visibilities {
             None => {
@@ -139,6 +169,7 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2D<B> {
                 profile_scope!("gather_novisibility");
 
                 (
+                    &entities,
                     &sprite_renders,
                     &transforms,
                     tints.maybe(),
@@ -146,8 +177,10 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2D<B> {
                     !&hidden_props,
                 )
                     .join()
-                    .filter_map(|(sprite_render, global, tint, _, _)| {
-                        let (batch_data, texture) = SpriteArgs::from_data(
+                    .filter_map(|(entity, sprite_render, global, tint, _, _)| {
+                        let (batch_data, texture) = sprite_args(
+                            resources,
+                            entity,
                             &tex_storage,
                             &sprite_sheet_storage,
                             &sprite_render,
@@ -171,14 +204,17 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2D<B> {
                 profile_scope!("gather_visibility");
 
                 (
+                    &entities,
                     &sprite_renders,
                     &transforms,
                     &visibility.visible_unordered,
                     tints.maybe(),
                 )
                     .join()
-                    .filter_map(|(sprite_render, global, _, tint)| {
-                        let (batch_data, texture) = SpriteArgs::from_data(
+                    .filter_map(|(entity, sprite_render, global, _, tint)| {
+                        let (batch_data, texture) = sprite_args(
+                            resources,
+                            entity,
                             &tex_storage,
                             &sprite_sheet_storage,
                             &sprite_render,
@@ -212,6 +248,21 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2D<B> {
                 self.sprites.count() as u64,
                 self.sprites.data(),
             );
+            self.vertex.report(index, resources);
+        }
+
+        if self.shaders.changed(resources) {
+            let layouts = vec![self.env.raw_layout(), self.textures.raw_layout()];
+            reload_sprite_pipelines(
+                factory,
+                resources,
+                subpass,
+                self.framebuffer_size,
+                &[None],
+                layouts,
+                &mut [&mut self.pipeline],
+                &mut self.pipeline_layout,
+            );
         }
 
         PrepareResult::DrawRecord
@@ -232,8 +283,7 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2D<B> {
         self.env.bind(index, layout, 0, &mut encoder);
         self.vertex.bind(index, 0, 0, &mut encoder);
         for (&tex, range) in self.sprites.iter() {
-            if self.textures.loaded(tex) {
-                self.textures.bind(layout, 1, tex, &mut encoder);
+            if self.textures.bind(layout, 1, tex, &mut encoder) {
                 unsafe {
                     encoder.draw(0..4, range);
                 }
@@ -268,7 +318,7 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawFlat2DTransparentDesc {
         _ctx: &GraphContext<B>,
         factory: &mut Factory<B>,
         _queue: QueueId,
-        _aux: &Resources,
+        aux: &Resources,
         framebuffer_width: u32,
         framebuffer_height: u32,
         subpass: hal::pass::Subpass<'_, B>,
@@ -282,37 +332,46 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawFlat2DTransparentDesc {
         let textures = TextureSub::new(factory)?;
         let vertex = DynamicVertexBuffer::new();
 
-        let (pipeline, pipeline_layout) = build_sprite_pipeline(
+        let (pipelines, pipeline_layout) = build_sprite_pipelines(
             factory,
+            aux,
             subpass,
             framebuffer_width,
             framebuffer_height,
-            true,
+            &super::transparent_modes(),
             vec![env.raw_layout(), textures.raw_layout()],
         )?;
 
         Ok(Box::new(DrawFlat2DTransparent::<B> {
-            pipeline,
+            pipelines,
             pipeline_layout,
             env,
             textures,
             vertex,
             sprites: Default::default(),
             change: Default::default(),
+            framebuffer_size: (framebuffer_width, framebuffer_height),
+            shaders: ShaderWatch::new(aux, &SHADER_NAMES),
         }))
     }
 }
 
 /// Draws transparent sprites without lighting.
+///
+/// Sprites are blended according to their `BlendMode`, or to the `AlphaMode` of their sprite
+/// sheet without one. Consecutive sprites sharing a texture and blend mode are drawn in a single
+/// batch.
 #[derive(Debug)]
 pub struct DrawFlat2DTransparent<B: Backend> {
-    pipeline: B::GraphicsPipeline,
+    pipelines: Vec<B::GraphicsPipeline>,
     pipeline_layout: B::PipelineLayout,
     env: FlatEnvironmentSub<B>,
     textures: TextureSub<B>,
     vertex: DynamicVertexBuffer<B, SpriteArgs>,
-    sprites: OrderedOneLevelBatch<TextureId, SpriteArgs>,
+    sprites: OrderedOneLevelBatch<(TextureId, BlendMode), SpriteArgs>,
     change: util::ChangeDetection,
+    framebuffer_size: (u32, u32),
+    shaders: ShaderWatch,
 }
 
 impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2DTransparent<B> {
@@ -321,21 +380,29 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2DTransparent<B> {
         factory: &Factory<B>,
         _queue: QueueId,
         index: usize,
-        _subpass: hal::pass::Subpass<'_, B>,
+        subpass: hal::pass::Subpass<'_, B>,
         resources: &Resources,
     ) -> PrepareResult {
         #[cfg(feature = "profiler")]
         profile_scope!("prepare transparent");
 
-        let (sprite_sheet_storage, tex_storage, visibility, sprite_renders, transforms, tints) =
-            <(
-                Read<'_, AssetStorage<SpriteSheet>>,
-                Read<'_, AssetStorage<Texture>>,
-                ReadExpect<'_, SpriteVisibility>,
-                ReadStorage<'_, SpriteRender>,
-                ReadStorage<'_, Transform>,
-                ReadStorage<'_, Tint>,
-            )>::fetch(resources);
+        let (
+            sprite_sheet_storage,
+            tex_storage,
+            visibility,
+            sprite_renders,
+            transforms,
+            tints,
+            blend_modes,
+        ) = <(
+            Read<'_, AssetStorage<SpriteSheet>>,
+            Read<'_, AssetStorage<Texture>>,
+            ReadExpect<'_, SpriteVisibility>,
+            ReadStorage<'_, SpriteRender>,
+            ReadStorage<'_, Transform>,
+            ReadStorage<'_, Tint>,
+            ReadStorage<'_, BlendMode>,
+        )>::fetch(resources);
 
         self.env.process(factory, index, resources);
         self.sprites.swap_clear();
@@ -348,18 +415,33 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2DTransparent<B> {
             #[cfg(feature = "profiler")]
             profile_scope!("gather_sprites_trans");
 
-            let mut joined = (&sprite_renders, &transforms, tints.maybe()).join();
+            let mut joined = (
+                &sprite_renders,
+                &transforms,
+                tints.maybe(),
+                blend_modes.maybe(),
+            )
+                .join();
             visibility
                 .visible_ordered
                 .iter()
-                .filter_map(|e| joined.get_unchecked(e.id()))
-                .filter_map(|(sprite_render, global, tint)| {
-                    let (batch_data, texture) = SpriteArgs::from_data(
+                .filter_map(|e| joined.get_unchecked(e.id()).map(|data| (*e, data)))
+                .filter_map(|(e, (sprite_render, global, tint, blend_mode))| {
+                    let alpha_mode = sprite_sheet_storage
+                        .get(&sprite_render.sprite_sheet)?
+                        .alpha_mode;
+                    let blend_mode = blend_mode
+                        .cloned()
+                        .unwrap_or_else(|| alpha_mode.blend_mode());
+                    let tint = alpha_mode.tint(Tint::faded(tint, visibility.fade(e)).as_ref());
+                    let (batch_data, texture) = sprite_args(
+                        resources,
+                        e,
                         &tex_storage,
                         &sprite_sheet_storage,
                         &sprite_render,
                         &global,
-                        tint,
+                        tint.as_ref(),
                     )?;
                     let (tex_id, this_changed) = textures_ref.insert(
                         factory,
@@ -368,28 +450,40 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2DTransparent<B> {
                         hal::image::Layout::ShaderReadOnlyOptimal,
                     )?;
                     changed = changed || this_changed;
-                    Some((tex_id, batch_data))
+                    Some(((tex_id, blend_mode), batch_data))
                 })
-                .for_each_group(|tex_id, batch_data| {
-                    sprites_ref.insert(tex_id, batch_data.drain(..));
+                .for_each_group(|key, batch_data| {
+                    sprites_ref.insert(key, batch_data.drain(..));
                 });
         }
-        self.textures.maintain(factory, resources);
+        changed = self.textures.maintain(factory, resources) || changed;
         changed = changed || self.sprites.changed();
 
         {
             #[cfg(feature = "profiler")]
             profile_scope!("write");
 
-            self.vertex.write(
+            changed = self.vertex.write(
                 factory,
                 index,
                 self.sprites.count() as u64,
                 Some(self.sprites.data()),
-            );
+            ) || changed;
+            self.vertex.report(index, resources);
         }
 
-        self.change.prepare_result(index, changed)
+        let reloaded = self.shaders.changed(resources)
+            && reload_sprite_pipelines(
+                factory,
+                resources,
+                subpass,
+                self.framebuffer_size,
+                &super::transparent_modes(),
+                vec![self.env.raw_layout(), self.textures.raw_layout()],
+                &mut self.pipelines.iter_mut().collect::<Vec<_>>(),
+                &mut self.pipeline_layout,
+            );
+        self.change.prepare_result(index, changed || reloaded)
     }
 
     fn draw_inline(
@@ -403,11 +497,17 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2DTransparent<B> {
         profile_scope!("draw transparent");
 
         let layout = &self.pipeline_layout;
-        encoder.bind_graphics_pipeline(&self.pipeline);
+        let mut bound_mode = BlendMode::Premultiplied;
+        encoder.bind_graphics_pipeline(&self.pipelines[bound_mode.index()]);
         self.env.bind(index, layout, 0, &mut encoder);
         self.vertex.bind(index, 0, 0, &mut encoder);
-        for (&tex, range) in self.sprites.iter() {
+        for (&(tex, blend_mode), range) in self.sprites.iter() {
             if self.textures.loaded(tex) {
+                // The pipelines share the layout, so bound descriptor sets stay valid
+                if blend_mode != bound_mode {
+                    encoder.bind_graphics_pipeline(&self.pipelines[blend_mode.index()]);
+                    bound_mode = blend_mode;
+                }
                 self.textures.bind(layout, 1, tex, &mut encoder);
                 unsafe {
                     encoder.draw(0..4, range);
@@ -418,7 +518,9 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2DTransparent<B> {
 
     fn dispose(self: Box<Self>, factory: &mut Factory<B>, _aux: &Resources) {
         unsafe {
-            factory.device().destroy_graphics_pipeline(self.pipeline);
+            for pipeline in self.pipelines {
+                factory.device().destroy_graphics_pipeline(pipeline);
+            }
             factory
                 .device()
                 .destroy_pipeline_layout(self.pipeline_layout);
@@ -426,49 +528,88 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawFlat2DTransparent<B> {
     }
 }
 
-fn build_sprite_pipeline<B: Backend>(
+/// Rebuilds the sprite pipelines of `modes` after their shaders were reloaded, keeping the
+/// previous ones if that fails. Returns whether the pipelines were replaced.
+#[allow(clippy::too_many_arguments)]
+fn reload_sprite_pipelines<B: Backend>(
+    factory: &Factory<B>,
+    aux: &Resources,
+    subpass: hal::pass::Subpass<'_, B>,
+    (framebuffer_width, framebuffer_height): (u32, u32),
+    modes: &[Option<BlendMode>],
+    layouts: Vec<&B::DescriptorSetLayout>,
+    pipelines: &mut [&mut B::GraphicsPipeline],
+    pipeline_layout: &mut B::PipelineLayout,
+) -> bool {
+    let built = build_sprite_pipelines(
+        factory,
+        aux,
+        subpass,
+        framebuffer_width,
+        framebuffer_height,
+        modes,
+        layouts,
+    );
+    match shader_reload::rebuilt(factory, "DrawFlat2D", built) {
+        Some((new_pipelines, new_layout)) => {
+            unsafe {
+                let device = factory.device();
+                for (pipeline, new_pipeline) in pipelines.iter_mut().zip(new_pipelines) {
+                    device.destroy_graphics_pipeline(std::mem::replace(*pipeline, new_pipeline));
+                }
+                device.destroy_pipeline_layout(std::mem::replace(pipeline_layout, new_layout));
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// Build a sprite pipeline for each blend mode, sharing a single layout. Sprites are opaque
+/// when the mode is `None`.
+fn build_sprite_pipelines<B: Backend>(
     factory: &Factory<B>,
+    aux: &Resources,
     subpass: hal::pass::Subpass<'_, B>,
     framebuffer_width: u32,
     framebuffer_height: u32,
-    transparent: bool,
+    modes: &[Option<BlendMode>],
     layouts: Vec<&B::DescriptorSetLayout>,
-) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
+) -> Result<(Vec<B::GraphicsPipeline>, B::PipelineLayout), failure::Error> {
     let pipeline_layout = unsafe {
         factory
             .device()
             .create_pipeline_layout(layouts, None as Option<(_, _)>)
     }?;
 
-    let shader_vertex = unsafe { super::SPRITE_VERTEX.module(factory).unwrap() };
-    let shader_fragment = unsafe { super::SPRITE_FRAGMENT.module(factory).unwrap() };
-
-    let pipes = PipelinesBuilder::new()
-        .with_pipeline(
-            PipelineDescBuilder::new()
-                .with_vertex_desc(&[(SpriteArgs::vertex(), pso::VertexInputRate::Instance(1))])
-                .with_input_assembler(pso::InputAssemblerDesc::new(hal::Primitive::TriangleStrip))
-                .with_shaders(util::simple_shader_set(
-                    &shader_vertex,
-                    Some(&shader_fragment),
-                ))
-                .with_layout(&pipeline_layout)
-                .with_subpass(subpass)
-                .with_framebuffer_size(framebuffer_width, framebuffer_height)
-                .with_blend_targets(vec![pso::ColorBlendDesc(
-                    pso::ColorMask::ALL,
-                    if transparent {
-                        pso::BlendState::PREMULTIPLIED_ALPHA
-                    } else {
-                        pso::BlendState::Off
-                    },
-                )])
-                .with_depth_test(pso::DepthTest::On {
-                    fun: pso::Comparison::Less,
-                    write: !transparent,
-                }),
+    let vertex = shader(aux, SHADER_NAMES[0], &super::SPRITE_VERTEX);
+    let fragment = shader(aux, SHADER_NAMES[1], &super::SPRITE_FRAGMENT);
+    let shader_vertex = unsafe { pipeline::shader_module(factory, "DrawFlat2D", &vertex) }?;
+    let shader_fragment = unsafe { pipeline::shader_module(factory, "DrawFlat2D", &fragment) }?;
+
+    let pipe_desc = PipelineDescBuilder::new()
+        .with_vertex_desc(&[(SpriteArgs::vertex(), pso::VertexInputRate::Instance(1))])
+        .with_vertex_inputs(pipeline::vertex_inputs(&vertex))
+        .with_input_assembler(pso::InputAssemblerDesc::new(hal::Primitive::TriangleStrip))
+        .with_shaders(util::simple_shader_set(
+            &shader_vertex,
+            Some(&shader_fragment),
+        ))
+        .with_layout(&pipeline_layout)
+        .with_subpass(subpass)
+        .with_framebuffer_size(framebuffer_width, framebuffer_height);
+
+    let pipes = modes[1..]
+        .iter()
+        .fold(
+            PipelinesBuilder::new()
+                .with_group("DrawFlat2D")
+                .with_pipeline(super::with_blend_mode(pipe_desc.clone(), modes[0])),
+            |builder, &mode| {
+                builder.with_child_pipeline(0, super::with_blend_mode(pipe_desc.clone(), mode))
+            },
         )
-        .build(factory, None);
+        .build_cached(factory, aux);
 
     unsafe {
         factory.destroy_shader_module(shader_vertex);
@@ -482,6 +623,6 @@ fn build_sprite_pipeline<B: Backend>(
             }
             Err(e)
         }
-        Ok(mut pipes) => Ok((pipes.remove(0), pipeline_layout)),
+        Ok(pipes) => Ok((pipes, pipeline_layout)),
     }
 }
@@ -10,7 +10,31 @@ mod skybox;
 
 pub use self::{base_3d::*, debug_lines::*, flat::*, flat2d::*, pbr::*, shaded::*, skybox::*};
 
-use rendy::{hal::pso::ShaderStageFlags, shader::SpirvShader};
+use crate::{pipeline::PipelineDescBuilder, transparent::BlendMode, types::Backend};
+use rendy::{
+    hal::pso::{self, ShaderStageFlags},
+    shader::SpirvShader,
+};
+
+/// The blend modes of the pipelines of the transparent groups, by `BlendMode::index`.
+fn transparent_modes() -> Vec<Option<BlendMode>> {
+    BlendMode::ALL.iter().cloned().map(Some).collect()
+}
+
+/// Blend a pipeline with `mode`, drawing opaque objects writing depth when it's `None`.
+fn with_blend_mode<B: Backend>(
+    desc: PipelineDescBuilder<'_, B>,
+    mode: Option<BlendMode>,
+) -> PipelineDescBuilder<'_, B> {
+    desc.with_blend_targets(vec![pso::ColorBlendDesc(
+        pso::ColorMask::ALL,
+        mode.map_or(pso::BlendState::Off, BlendMode::blend_state),
+    )])
+    .with_depth_test(pso::DepthTest::On {
+        fun: pso::Comparison::Less,
+        write: mode.is_none(),
+    })
+}
 
 lazy_static::lazy_static! {
     static ref POS_TEX_VERTEX: SpirvShader = SpirvShader::new(
@@ -25,6 +49,24 @@ lazy_static::lazy_static! {
         "main",
     );
 
+    static ref POS_TEX_SKIN_UNIFORM_VERTEX: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/vertex/pos_tex_skin_uniform.vert.spv").to_vec(),
+        ShaderStageFlags::VERTEX,
+        "main",
+    );
+
+    static ref POS_TEX_GRADIENT_VERTEX: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/vertex/pos_tex_gradient.vert.spv").to_vec(),
+        ShaderStageFlags::VERTEX,
+        "main",
+    );
+
+    static ref POS_TEX_DISSOLVE_VERTEX: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/vertex/pos_tex_dissolve.vert.spv").to_vec(),
+        ShaderStageFlags::VERTEX,
+        "main",
+    );
+
     static ref POS_NORM_TEX_VERTEX: SpirvShader = SpirvShader::new(
         include_bytes!("../../compiled/vertex/pos_norm_tex.vert.spv").to_vec(),
         ShaderStageFlags::VERTEX,
@@ -37,6 +79,18 @@ lazy_static::lazy_static! {
         "main",
     );
 
+    static ref POS_NORM_TEX_SKIN_UNIFORM_VERTEX: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/vertex/pos_norm_tex_skin_uniform.vert.spv").to_vec(),
+        ShaderStageFlags::VERTEX,
+        "main",
+    );
+
+    static ref POS_NORM_TEX_GRADIENT_VERTEX: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/vertex/pos_norm_tex_gradient.vert.spv").to_vec(),
+        ShaderStageFlags::VERTEX,
+        "main",
+    );
+
     static ref POS_NORM_TANG_TEX_VERTEX: SpirvShader = SpirvShader::new(
         include_bytes!("../../compiled/vertex/pos_norm_tang_tex.vert.spv").to_vec(),
         ShaderStageFlags::VERTEX,
@@ -49,12 +103,36 @@ lazy_static::lazy_static! {
         "main",
     );
 
+    static ref POS_NORM_TANG_TEX_SKIN_UNIFORM_VERTEX: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/vertex/pos_norm_tang_tex_skin_uniform.vert.spv").to_vec(),
+        ShaderStageFlags::VERTEX,
+        "main",
+    );
+
+    static ref POS_NORM_TANG_TEX_GRADIENT_VERTEX: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/vertex/pos_norm_tang_tex_gradient.vert.spv").to_vec(),
+        ShaderStageFlags::VERTEX,
+        "main",
+    );
+
+    static ref POS_NORM_TANG_TEX_DISSOLVE_VERTEX: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/vertex/pos_norm_tang_tex_dissolve.vert.spv").to_vec(),
+        ShaderStageFlags::VERTEX,
+        "main",
+    );
+
     static ref FLAT_FRAGMENT: SpirvShader = SpirvShader::new(
         include_bytes!("../../compiled/fragment/flat.frag.spv").to_vec(),
         ShaderStageFlags::FRAGMENT,
         "main",
     );
 
+    static ref FLAT_DISSOLVE_FRAGMENT: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/fragment/flat_dissolve.frag.spv").to_vec(),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    );
+
     static ref SHADED_FRAGMENT: SpirvShader = SpirvShader::new(
         include_bytes!("../../compiled/fragment/shaded.frag.spv").to_vec(),
         ShaderStageFlags::FRAGMENT,
@@ -67,6 +145,12 @@ lazy_static::lazy_static! {
         "main",
     );
 
+    static ref PBR_DISSOLVE_FRAGMENT: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/fragment/pbr_dissolve.frag.spv").to_vec(),
+        ShaderStageFlags::FRAGMENT,
+        "main",
+    );
+
     static ref SPRITE_VERTEX: SpirvShader = SpirvShader::new(
         include_bytes!("../../compiled/vertex/sprite.vert.spv").to_vec(),
         ShaderStageFlags::VERTEX,
@@ -97,9 +181,33 @@ lazy_static::lazy_static! {
         "main",
     );
 
+    static ref DEBUG_LINES_PUSH_VERTEX: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/vertex/debug_lines_push.vert.spv").to_vec(),
+        ShaderStageFlags::VERTEX,
+        "main",
+    );
+
     static ref DEBUG_LINES_FRAGMENT: SpirvShader = SpirvShader::new(
         include_bytes!("../../compiled/fragment/debug_lines.frag.spv").to_vec(),
         ShaderStageFlags::FRAGMENT,
         "main",
     );
+
+    static ref DEBUG_LINES_GEOM_VERTEX: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/vertex/debug_lines_geom.vert.spv").to_vec(),
+        ShaderStageFlags::VERTEX,
+        "main",
+    );
+
+    static ref DEBUG_LINES_GEOMETRY: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/geometry/debug_lines.geom.spv").to_vec(),
+        ShaderStageFlags::GEOMETRY,
+        "main",
+    );
+
+    static ref DEBUG_LINES_PUSH_GEOMETRY: SpirvShader = SpirvShader::new(
+        include_bytes!("../../compiled/geometry/debug_lines_push.geom.spv").to_vec(),
+        ShaderStageFlags::GEOMETRY,
+        "main",
+    );
 }
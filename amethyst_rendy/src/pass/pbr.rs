@@ -17,9 +17,21 @@ impl<B: Backend> Base3DPassDef<B> for PbrPassDef {
     fn vertex_skinned_shader() -> &'static SpirvShader {
         &super::POS_NORM_TANG_TEX_SKIN_VERTEX
     }
+    fn vertex_skinned_uniform_shader() -> Option<&'static SpirvShader> {
+        Some(&super::POS_NORM_TANG_TEX_SKIN_UNIFORM_VERTEX)
+    }
+    fn vertex_gradient_shader() -> Option<&'static SpirvShader> {
+        Some(&super::POS_NORM_TANG_TEX_GRADIENT_VERTEX)
+    }
     fn fragment_shader() -> &'static SpirvShader {
         &super::PBR_FRAGMENT
     }
+    fn dissolve_shaders() -> Option<(&'static SpirvShader, &'static SpirvShader)> {
+        Some((
+            &*super::POS_NORM_TANG_TEX_DISSOLVE_VERTEX,
+            &*super::PBR_DISSOLVE_FRAGMENT,
+        ))
+    }
     fn base_format() -> Vec<VertexFormat> {
         vec![
             Position::vertex(),
@@ -37,6 +49,13 @@ impl<B: Backend> Base3DPassDef<B> for PbrPassDef {
             JointCombined::vertex(),
         ]
     }
+    fn shader_names() -> Option<[&'static str; 3]> {
+        Some([
+            "vertex/pos_norm_tang_tex.vert",
+            "vertex/pos_norm_tang_tex_skin.vert",
+            "fragment/pbr.frag",
+        ])
+    }
 }
 
 /// Describes a Physically-based (PBR) 3d Pass with lighting
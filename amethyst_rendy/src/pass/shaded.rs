@@ -21,6 +21,12 @@ impl<B: Backend> Base3DPassDef<B> for ShadedPassDef {
     fn vertex_skinned_shader() -> &'static SpirvShader {
         &super::POS_NORM_TEX_SKIN_VERTEX
     }
+    fn vertex_skinned_uniform_shader() -> Option<&'static SpirvShader> {
+        Some(&super::POS_NORM_TEX_SKIN_UNIFORM_VERTEX)
+    }
+    fn vertex_gradient_shader() -> Option<&'static SpirvShader> {
+        Some(&super::POS_NORM_TEX_GRADIENT_VERTEX)
+    }
     fn fragment_shader() -> &'static SpirvShader {
         &super::SHADED_FRAGMENT
     }
@@ -35,6 +41,13 @@ impl<B: Backend> Base3DPassDef<B> for ShadedPassDef {
             JointCombined::vertex(),
         ]
     }
+    fn shader_names() -> Option<[&'static str; 3]> {
+        Some([
+            "vertex/pos_norm_tex.vert",
+            "vertex/pos_norm_tex_skin.vert",
+            "fragment/shaded.frag",
+        ])
+    }
 }
 
 /// Describes a simple shaded 3D pass.
@@ -1,7 +1,8 @@
 use crate::{
     palette::Srgb,
-    pipeline::{PipelineDescBuilder, PipelinesBuilder},
+    pipeline::{self, PipelineDescBuilder, PipelinesBuilder},
     pod::IntoPod,
+    shader_reload::{self, shader, ShaderWatch},
     shape::Shape,
     submodules::{DynamicUniform, FlatEnvironmentSub},
     types::Backend,
@@ -19,7 +20,6 @@ use rendy::{
     },
     hal::{self, device::Device, pso},
     mesh::{AsVertex, Mesh, PosTex},
-    shader::Shader,
 };
 
 #[cfg(feature = "profiler")]
@@ -86,7 +86,7 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawSkyboxDesc {
         _ctx: &GraphContext<B>,
         factory: &mut Factory<B>,
         queue: QueueId,
-        _resources: &Resources,
+        resources: &Resources,
         framebuffer_width: u32,
         framebuffer_height: u32,
         subpass: hal::pass::Subpass<'_, B>,
@@ -104,6 +104,7 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawSkyboxDesc {
 
         let (pipeline, pipeline_layout) = build_skybox_pipeline(
             factory,
+            resources,
             subpass,
             framebuffer_width,
             framebuffer_height,
@@ -117,6 +118,8 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawSkyboxDesc {
             colors,
             mesh,
             default_settings: self.default_settings,
+            framebuffer_size: (framebuffer_width, framebuffer_height),
+            shaders: ShaderWatch::new(resources, &SHADER_NAMES),
         }))
     }
 }
@@ -130,20 +133,25 @@ pub struct DrawSkybox<B: Backend> {
     colors: DynamicUniform<B, SkyboxUniform>,
     mesh: Mesh<B>,
     default_settings: SkyboxSettings,
+    framebuffer_size: (u32, u32),
+    shaders: ShaderWatch,
 }
 
+const SHADER_NAMES: [&str; 2] = ["vertex/skybox.vert", "fragment/skybox.frag"];
+
 impl<B: Backend> RenderGroup<B, Resources> for DrawSkybox<B> {
     fn prepare(
         &mut self,
         factory: &Factory<B>,
         _queue: QueueId,
         index: usize,
-        _subpass: hal::pass::Subpass<'_, B>,
+        subpass: hal::pass::Subpass<'_, B>,
         resources: &Resources,
     ) -> PrepareResult {
         #[cfg(feature = "profiler")]
         profile_scope!("prepare");
 
+        let reloaded = self.shaders.changed(resources) && self.reload(factory, resources, subpass);
         let settings = <(Option<Read<'_, SkyboxSettings>>)>::fetch(resources)
             .map(|s| s.uniform())
             .unwrap_or_else(|| self.default_settings.uniform());
@@ -151,7 +159,7 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawSkybox<B> {
         self.env.process(factory, index, resources);
         let changed = self.colors.write(factory, index, settings);
 
-        if changed {
+        if changed || reloaded {
             PrepareResult::DrawRecord
         } else {
             PrepareResult::DrawReuse
@@ -172,11 +180,8 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawSkybox<B> {
         self.colors
             .bind(index, &self.pipeline_layout, 1, &mut encoder);
         self.mesh
-            .bind(0, &[PosTex::vertex()], &mut encoder)
+            .bind_and_draw(0, &[PosTex::vertex()], 0..1, &mut encoder)
             .unwrap();
-        unsafe {
-            encoder.draw(0..self.mesh.len(), 0..1);
-        }
     }
 
     fn dispose(self: Box<Self>, factory: &mut Factory<B>, _aux: &Resources) {
@@ -189,8 +194,43 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawSkybox<B> {
     }
 }
 
+impl<B: Backend> DrawSkybox<B> {
+    /// Rebuild the pipeline after the shaders were reloaded, returns whether it was replaced.
+    fn reload(
+        &mut self,
+        factory: &Factory<B>,
+        resources: &Resources,
+        subpass: hal::pass::Subpass<'_, B>,
+    ) -> bool {
+        let built = build_skybox_pipeline(
+            factory,
+            resources,
+            subpass,
+            self.framebuffer_size.0,
+            self.framebuffer_size.1,
+            vec![self.env.raw_layout(), self.colors.raw_layout()],
+        );
+        match shader_reload::rebuilt(factory, "DrawSkybox", built) {
+            Some((pipeline, pipeline_layout)) => {
+                unsafe {
+                    let device = factory.device();
+                    device
+                        .destroy_graphics_pipeline(std::mem::replace(&mut self.pipeline, pipeline));
+                    device.destroy_pipeline_layout(std::mem::replace(
+                        &mut self.pipeline_layout,
+                        pipeline_layout,
+                    ));
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
 fn build_skybox_pipeline<B: Backend>(
     factory: &Factory<B>,
+    aux: &Resources,
     subpass: hal::pass::Subpass<'_, B>,
     framebuffer_width: u32,
     framebuffer_height: u32,
@@ -202,13 +242,17 @@ fn build_skybox_pipeline<B: Backend>(
             .create_pipeline_layout(layouts, None as Option<(_, _)>)
     }?;
 
-    let shader_vertex = unsafe { super::SKYBOX_VERTEX.module(factory).unwrap() };
-    let shader_fragment = unsafe { super::SKYBOX_FRAGMENT.module(factory).unwrap() };
+    let vertex = shader(aux, SHADER_NAMES[0], &super::SKYBOX_VERTEX);
+    let fragment = shader(aux, SHADER_NAMES[1], &super::SKYBOX_FRAGMENT);
+    let shader_vertex = unsafe { pipeline::shader_module(factory, "DrawSkybox", &vertex) }?;
+    let shader_fragment = unsafe { pipeline::shader_module(factory, "DrawSkybox", &fragment) }?;
 
     let pipes = PipelinesBuilder::new()
+        .with_group("DrawSkybox")
         .with_pipeline(
             PipelineDescBuilder::new()
                 .with_vertex_desc(&[(PosTex::vertex(), pso::VertexInputRate::Vertex)])
+                .with_vertex_inputs(pipeline::vertex_inputs(&vertex))
                 .with_shaders(util::simple_shader_set(
                     &shader_vertex,
                     Some(&shader_fragment),
@@ -225,7 +269,7 @@ fn build_skybox_pipeline<B: Backend>(
                     pso::BlendState::Off,
                 )]),
         )
-        .build(factory, None);
+        .build_cached(factory, aux);
 
     unsafe {
         factory.destroy_shader_module(shader_vertex);
@@ -1,10 +1,16 @@
 //! Graphics pipeline abstraction
-use crate::{types::Backend, util};
+//!
+//! Failures to create the shader modules and pipelines of a render group are reported as
+//! `PipelineError`s naming the group, with the vertex attributes of the pipeline and the ones
+//! its vertex shader reads when the builder was given them with `with_vertex_inputs`.
+use crate::{error::PipelineError, pipeline_cache::PipelineCache, types::Backend, util};
+use amethyst_core::ecs::Resources;
 use derivative::Derivative;
 use rendy::{
     factory::Factory,
     hal::{
         device::Device,
+        format::Format,
         pass::Subpass,
         pso::{
             AttributeDesc, BakedStates, BasePipeline, BlendDesc, ColorBlendDesc, DepthStencilDesc,
@@ -15,7 +21,9 @@ use rendy::{
         Primitive,
     },
     mesh::VertexFormat,
+    shader::{Shader, SpirvShader},
 };
+use std::time::Instant;
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
@@ -46,6 +54,7 @@ pub struct PipelineDescBuilder<'a, B: Backend> {
     subpass: Option<Subpass<'a, B>>,
     flags: PipelineCreationFlags,
     parent: LocalBasePipeline<'a, B::GraphicsPipeline>,
+    vertex_inputs: Option<Vec<u32>>,
 }
 
 impl<'a, B: Backend> PipelineDescBuilder<'a, B> {
@@ -65,6 +74,7 @@ impl<'a, B: Backend> PipelineDescBuilder<'a, B> {
             subpass: None,
             flags: PipelineCreationFlags::empty(),
             parent: LocalBasePipeline::None,
+            vertex_inputs: None,
         }
     }
 
@@ -246,6 +256,35 @@ impl<'a, B: Backend> PipelineDescBuilder<'a, B> {
         self.rasterizer.cull_face = cull_face;
     }
 
+    /// Build checking that the vertex attributes provide the locations the vertex shader reads,
+    /// as given by `vertex_inputs`.
+    pub fn with_vertex_inputs(mut self, locations: Vec<u32>) -> Self {
+        self.set_vertex_inputs(locations);
+        self
+    }
+    /// Set the locations the vertex shader reads, checked against the vertex attributes.
+    pub fn set_vertex_inputs(&mut self, locations: Vec<u32>) {
+        self.vertex_inputs = Some(locations);
+    }
+
+    /// The locations and formats of the vertex attributes.
+    fn declared_attributes(&self) -> Vec<(u32, Format)> {
+        self.attributes
+            .iter()
+            .map(|a| (a.location, a.element.format))
+            .collect()
+    }
+
+    /// Locations read by the vertex shader without an attribute.
+    fn missing_attributes(&self) -> Vec<u32> {
+        self.vertex_inputs
+            .iter()
+            .flatten()
+            .cloned()
+            .filter(|location| !self.attributes.iter().any(|a| a.location == *location))
+            .collect()
+    }
+
     /// Build with the provided vertex description.
     pub fn with_vertex_desc(mut self, desc: &[(VertexFormat, VertexInputRate)]) -> Self {
         self.set_vertex_desc(desc);
@@ -295,6 +334,7 @@ impl<'a, B: Backend> PipelineDescBuilder<'a, B> {
 #[derive(Default, Debug, Clone)]
 pub struct PipelinesBuilder<'a, B: Backend> {
     builders: Vec<PipelineDescBuilder<'a, B>>,
+    group: Option<&'a str>,
 }
 
 impl<'a, B: Backend> PipelinesBuilder<'a, B> {
@@ -302,9 +342,16 @@ impl<'a, B: Backend> PipelinesBuilder<'a, B> {
     pub fn new() -> Self {
         Self {
             builders: Vec::new(),
+            group: None,
         }
     }
 
+    /// Build naming the render group of the pipelines in their errors.
+    pub fn with_group(mut self, group: &'a str) -> Self {
+        self.group = Some(group);
+        self
+    }
+
     /// Build with an additional `PipelineDescBuilder` instance.
     pub fn with_pipeline(mut self, builder: PipelineDescBuilder<'a, B>) -> Self {
         self.add_pipeline(builder);
@@ -333,7 +380,9 @@ impl<'a, B: Backend> PipelinesBuilder<'a, B> {
             .push(builder.with_parent(BasePipeline::Index(index)));
     }
 
-    /// Finalize and construct the `GraphicsPipeline`
+    /// Finalize and construct the `GraphicsPipeline`. Fails with a `PipelineError` when a
+    /// vertex shader reads attributes its pipeline doesn't provide, or the device fails to create
+    /// a pipeline.
     pub fn build(
         self,
         factory: &Factory<B>,
@@ -342,21 +391,198 @@ impl<'a, B: Backend> PipelinesBuilder<'a, B> {
         #[cfg(feature = "profiler")]
         profile_scope!("create_pipelines");
 
+        let group = self.group.unwrap_or("a render group").to_string();
+        for (pipeline, builder) in self.builders.iter().enumerate() {
+            let missing = builder.missing_attributes();
+            if !missing.is_empty() {
+                return Err(PipelineError::MissingVertexAttributes {
+                    group,
+                    pipeline,
+                    missing,
+                    reflected: builder.vertex_inputs.clone().unwrap_or_default(),
+                    declared: builder.declared_attributes(),
+                }
+                .into());
+            }
+        }
+        let declared: Vec<_> = self
+            .builders
+            .iter()
+            .map(PipelineDescBuilder::declared_attributes)
+            .collect();
+
         let mut pipelines = unsafe {
             factory
                 .device()
                 .create_graphics_pipelines(self.builders.into_iter().map(|b| b.build()), cache)
         };
 
-        if let Some(err) = pipelines.iter().find_map(|p| p.as_ref().err().cloned()) {
+        if let Some((pipeline, err)) = pipelines
+            .iter()
+            .enumerate()
+            .find_map(|(i, p)| p.as_ref().err().map(|e| (i, e.to_string())))
+        {
             for p in pipelines.drain(..).filter_map(Result::ok) {
                 unsafe {
                     factory.destroy_graphics_pipeline(p);
                 }
             }
-            failure::bail!(err);
+            return Err(PipelineError::Creation {
+                group,
+                pipeline,
+                reason: err,
+                declared: declared.into_iter().nth(pipeline).unwrap_or_default(),
+            }
+            .into());
         }
 
         Ok(pipelines.into_iter().map(|p| p.unwrap()).collect())
     }
+
+    /// Finalize and construct the `GraphicsPipeline`s with the `PipelineCache` resource of `res`,
+    /// if there is one.
+    pub fn build_cached(
+        self,
+        factory: &Factory<B>,
+        res: &Resources,
+    ) -> Result<Vec<B::GraphicsPipeline>, failure::Error> {
+        let cache = res.try_fetch::<PipelineCache<B>>();
+        let count = self.builders.len();
+        let start = Instant::now();
+        let pipelines = self.build(factory, cache.as_ref().map(|cache| cache.raw()));
+        if let Some(cache) = &cache {
+            cache.record(count, start.elapsed());
+        }
+        pipelines
+    }
+}
+
+/// Create the module of `shader` for the render group `group`, failing with a
+/// `PipelineError::ShaderModule` naming them.
+///
+/// # Safety
+///
+/// The SPIR-V of the shader must be valid, as for `Shader::module`.
+pub unsafe fn shader_module<B: Backend>(
+    factory: &Factory<B>,
+    group: &str,
+    shader: &SpirvShader,
+) -> Result<B::ShaderModule, failure::Error> {
+    shader.module(factory).map_err(|e| {
+        PipelineError::ShaderModule {
+            group: group.to_string(),
+            stage: shader.stage(),
+            reason: e.to_string(),
+        }
+        .into()
+    })
+}
+
+/// Locations of the inputs of the vertex shader `shader`, for
+/// `PipelineDescBuilder::with_vertex_inputs`. Empty when its SPIR-V can't be read.
+pub fn vertex_inputs(shader: &SpirvShader) -> Vec<u32> {
+    shader
+        .spirv()
+        .map(|spirv| input_locations(&spirv))
+        .unwrap_or_default()
+}
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const OP_DECORATE: u32 = 71;
+const OP_VARIABLE: u32 = 59;
+const DECORATION_LOCATION: u32 = 30;
+const STORAGE_CLASS_INPUT: u32 = 1;
+
+/// The sorted locations of the input variables of a SPIR-V module, skipping built-ins, which
+/// have none.
+fn input_locations(spirv: &[u8]) -> Vec<u32> {
+    let words: Vec<u32> = spirv
+        .chunks_exact(4)
+        .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+        .collect();
+    if words.len() < 5 || words[0] != SPIRV_MAGIC {
+        return Vec::new();
+    }
+
+    let mut locations = Vec::new();
+    let mut inputs = Vec::new();
+    let mut i = 5;
+    while i < words.len() {
+        let count = (words[i] >> 16) as usize;
+        if count == 0 || i + count > words.len() {
+            break;
+        }
+        let operands = &words[i + 1..i + count];
+        match words[i] & 0xffff {
+            OP_DECORATE if operands.len() >= 3 && operands[1] == DECORATION_LOCATION => {
+                locations.push((operands[0], operands[2]));
+            }
+            OP_VARIABLE if operands.len() >= 3 && operands[2] == STORAGE_CLASS_INPUT => {
+                inputs.push(operands[1]);
+            }
+            _ => {}
+        }
+        i += count;
+    }
+
+    let mut reflected: Vec<u32> = locations
+        .into_iter()
+        .filter(|(id, _)| inputs.contains(id))
+        .map(|(_, location)| location)
+        .collect();
+    reflected.sort();
+    reflected.dedup();
+    reflected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_locations_are_reflected() {
+        let words = [
+            SPIRV_MAGIC,
+            0x0001_0000,
+            0,
+            10,
+            0,
+            // OpDecorate %5 Location 2, OpDecorate %4 Location 0, OpDecorate %6 Location 1
+            (4 << 16) | OP_DECORATE,
+            5,
+            DECORATION_LOCATION,
+            2,
+            (4 << 16) | OP_DECORATE,
+            4,
+            DECORATION_LOCATION,
+            0,
+            (4 << 16) | OP_DECORATE,
+            6,
+            DECORATION_LOCATION,
+            1,
+            // %4 and %5 are inputs, %6 is an output, %7 a built-in input
+            (4 << 16) | OP_VARIABLE,
+            3,
+            4,
+            STORAGE_CLASS_INPUT,
+            (4 << 16) | OP_VARIABLE,
+            3,
+            5,
+            STORAGE_CLASS_INPUT,
+            (4 << 16) | OP_VARIABLE,
+            3,
+            6,
+            3,
+            (4 << 16) | OP_VARIABLE,
+            3,
+            7,
+            STORAGE_CLASS_INPUT,
+        ];
+        let bytes: Vec<u8> = words
+            .iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        assert_eq!(input_locations(&bytes), vec![0, 2]);
+        assert!(input_locations(&bytes[4..]).is_empty());
+    }
 }
@@ -0,0 +1,220 @@
+//! Persistence of compiled pipelines between runs.
+//!
+//! The `RenderingSystem` creates a `PipelineCache` resource at startup, which every pass builds
+//! its pipelines with through `PipelinesBuilder::build_cached`. With a path set by
+//! `RenderingSystem::with_pipeline_cache`, the cache is loaded from that file at startup and
+//! written back to it on shutdown, so the driver doesn't compile the same pipelines on the next
+//! run.
+//!
+//! The file starts with the data of an empty cache of the device, which identifies the driver
+//! and device on most backends. Files written for another device, by another version of the
+//! format or cut short are ignored.
+use crate::types::Backend;
+use rendy::{factory::Factory, hal::device::Device};
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+const MAGIC: &[u8; 8] = b"AMETHPSO";
+const FORMAT_VERSION: u32 = 1;
+
+/// Resource with the pipeline cache of the device, see the module documentation.
+#[derive(Debug)]
+pub struct PipelineCache<B: Backend> {
+    cache: B::PipelineCache,
+    path: Option<PathBuf>,
+    device_header: Vec<u8>,
+    loaded_bytes: usize,
+    created: Mutex<(usize, Duration)>,
+}
+
+impl<B: Backend> PipelineCache<B> {
+    /// Create the cache, with the data of the file at `path` if it was written for this device.
+    pub(crate) fn load(
+        factory: &Factory<B>,
+        path: Option<PathBuf>,
+    ) -> Result<Self, failure::Error> {
+        let device_header = unsafe {
+            let empty = factory.device().create_pipeline_cache(None)?;
+            let header = factory.device().get_pipeline_cache_data(&empty);
+            factory.device().destroy_pipeline_cache(empty);
+            header?
+        };
+
+        let file = path.as_ref().and_then(|path| std::fs::read(path).ok());
+        let data = file
+            .as_ref()
+            .and_then(|file| decode(file, &device_header))
+            .filter(|data| !data.is_empty());
+        match (&path, &file, data) {
+            (Some(path), Some(_), None) => log::debug!(
+                "Ignoring pipeline cache {}, it is invalid or for another device",
+                path.display()
+            ),
+            (Some(path), _, Some(data)) => log::debug!(
+                "Loaded pipeline cache of {} bytes from {}",
+                data.len(),
+                path.display()
+            ),
+            _ => {}
+        }
+
+        let cache = unsafe {
+            match data {
+                // The driver may still reject the data, start over with an empty cache then.
+                Some(data) => factory
+                    .device()
+                    .create_pipeline_cache(Some(data))
+                    .or_else(|_| factory.device().create_pipeline_cache(None))?,
+                None => factory.device().create_pipeline_cache(None)?,
+            }
+        };
+
+        Ok(Self {
+            cache,
+            path,
+            device_header,
+            loaded_bytes: data.map_or(0, |data| data.len()),
+            created: Mutex::new((0, Duration::default())),
+        })
+    }
+
+    /// The raw cache, to create pipelines with.
+    pub fn raw(&self) -> &B::PipelineCache {
+        &self.cache
+    }
+
+    /// The file the cache is loaded from and saved to.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_ref().map(PathBuf::as_path)
+    }
+
+    /// Number of pipelines created with the cache and the time spent creating them.
+    pub fn created(&self) -> (usize, Duration) {
+        *self.created.lock().unwrap()
+    }
+
+    /// Count `pipelines` created with the cache in `time`.
+    pub(crate) fn record(&self, pipelines: usize, time: Duration) {
+        let mut created = self.created.lock().unwrap();
+        created.0 += pipelines;
+        created.1 += time;
+    }
+
+    /// Destroy the cache without writing it, as its device was lost.
+    pub(crate) fn destroy(self, factory: &Factory<B>) {
+        unsafe {
+            factory.device().destroy_pipeline_cache(self.cache);
+        }
+    }
+
+    /// Write the cache to its file if it has one, and destroy it.
+    pub(crate) fn save(self, factory: &Factory<B>) {
+        let data = unsafe { factory.device().get_pipeline_cache_data(&self.cache) };
+        unsafe {
+            factory.device().destroy_pipeline_cache(self.cache);
+        }
+
+        let (pipelines, time) = *self.created.lock().unwrap();
+        let path = match self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let data = match data {
+            Ok(data) => data,
+            Err(e) => {
+                log::warn!("Failed to get the pipeline cache data: {}", e);
+                return;
+            }
+        };
+
+        // The cache only grows with the pipelines that weren't in it yet, the share of it that
+        // was already loaded tells how many pipelines were found.
+        let loaded = if data.is_empty() {
+            0.0
+        } else {
+            self.loaded_bytes.min(data.len()) as f64 * 100.0 / data.len() as f64
+        };
+        log::info!(
+            "Pipeline cache {}: {} pipelines created in {:.1} ms, {:.0}% of the {} bytes saved were loaded at startup",
+            path.display(),
+            pipelines,
+            time.as_micros() as f64 / 1000.0,
+            loaded,
+            data.len(),
+        );
+        if let Err(e) = std::fs::write(&path, encode(&data, &self.device_header)) {
+            log::warn!(
+                "Failed to write the pipeline cache to {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Cache file of `data`, for the device of `device_header`.
+fn encode(data: &[u8], device_header: &[u8]) -> Vec<u8> {
+    let mut file = Vec::with_capacity(MAGIC.len() + 12 + device_header.len() + data.len());
+    file.extend_from_slice(MAGIC);
+    file.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    file.extend_from_slice(&(device_header.len() as u32).to_le_bytes());
+    file.extend_from_slice(device_header);
+    file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    file.extend_from_slice(data);
+    file
+}
+
+/// The cache data of `file`, if it was written by this version for the device of
+/// `device_header`.
+fn decode<'a>(file: &'a [u8], device_header: &[u8]) -> Option<&'a [u8]> {
+    fn read_u32(bytes: &[u8]) -> Option<(u32, &[u8])> {
+        if bytes.len() < 4 {
+            return None;
+        }
+        let mut value = [0; 4];
+        value.copy_from_slice(&bytes[..4]);
+        Some((u32::from_le_bytes(value), &bytes[4..]))
+    }
+
+    if !file.starts_with(MAGIC) {
+        return None;
+    }
+    let (version, rest) = read_u32(&file[MAGIC.len()..])?;
+    if version != FORMAT_VERSION {
+        return None;
+    }
+    let (header_len, rest) = read_u32(rest)?;
+    if rest.get(..header_len as usize)? != device_header {
+        return None;
+    }
+    let (data_len, data) = read_u32(&rest[header_len as usize..])?;
+    if data.len() != data_len as usize {
+        return None;
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_files_round_trip() {
+        let file = encode(b"pipelines", b"device 1");
+        assert_eq!(decode(&file, b"device 1"), Some(&b"pipelines"[..]));
+        assert_eq!(decode(&file, b"device 2"), None);
+        assert_eq!(decode(&file, b"device"), None);
+        assert_eq!(decode(&file[..file.len() - 1], b"device 1"), None);
+        assert_eq!(decode(b"AMETH", b"device 1"), None);
+
+        let mut newer = file.clone();
+        newer[MAGIC.len()] = 2;
+        assert_eq!(decode(&newer, b"device 1"), None);
+
+        let empty_device = encode(b"data", b"");
+        assert_eq!(decode(&empty_device, b""), Some(&b"data"[..]));
+    }
+}
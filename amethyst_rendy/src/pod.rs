@@ -1,7 +1,9 @@
 //! GPU POD data types.
 use crate::{
+    dissolve::Dissolve,
+    error::RenderError,
     mtl,
-    resources::Tint as TintComponent,
+    resources::{Tint as TintComponent, TintGradient3D},
     sprite::{SpriteRender, SpriteSheet},
     types::Texture,
 };
@@ -96,10 +98,7 @@ impl VertexArgs {
         let model: [[f32; 4]; 4] = convert::<_, Matrix4<f32>>(*transform.global_matrix()).into();
         VertexArgs {
             model: model.into(),
-            tint: tint.map_or([1.0; 4].into(), |t| {
-                let (r, g, b, a) = t.0.into_components();
-                [r, g, b, a].into()
-            }),
+            tint: tint.map_or([1.0; 4].into(), |t| t.0.into_pod()),
         }
     }
 }
@@ -110,6 +109,191 @@ impl AsVertex for VertexArgs {
     }
 }
 
+/// Gradient start color
+/// ```glsl,ignore
+/// vec4 gradient_start;
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(16))]
+pub struct GradientStart {
+    /// Color as `Rgba32Sfloat`
+    pub gradient_start: vec4,
+}
+
+impl AsAttribute for GradientStart {
+    const NAME: &'static str = "gradient_start";
+    const FORMAT: Format = Format::Rgba32Sfloat;
+}
+
+/// Gradient end color
+/// ```glsl,ignore
+/// vec4 gradient_end;
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(16))]
+pub struct GradientEnd {
+    /// Color as `Rgba32Sfloat`
+    pub gradient_end: vec4,
+}
+
+impl AsAttribute for GradientEnd {
+    const NAME: &'static str = "gradient_end";
+    const FORMAT: Format = Format::Rgba32Sfloat;
+}
+
+/// Gradient axis, over the length of the gradient, and offset of its start
+/// ```glsl,ignore
+/// vec4 gradient_axis;
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(16))]
+pub struct GradientAxis {
+    /// Axis and offset as `Rgba32Sfloat`
+    pub gradient_axis: vec4,
+}
+
+impl AsAttribute for GradientAxis {
+    const NAME: &'static str = "gradient_axis";
+    const FORMAT: Format = Format::Rgba32Sfloat;
+}
+
+/// Instance-rate vertex arguments of meshes with a `TintGradient3D`
+/// ```glsl,ignore
+///  mat4 model;
+///  vec4 tint;
+///  vec4 gradient_start;
+///  vec4 gradient_end;
+///  vec4 gradient_axis;
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[repr(C, align(16))]
+pub struct GradientVertexArgs {
+    /// Instance-rate model matrix
+    pub model: mat4,
+    /// Instance-rate model `Tint`
+    pub tint: vec4,
+    /// Instance-rate gradient start color
+    pub gradient_start: vec4,
+    /// Instance-rate gradient end color
+    pub gradient_end: vec4,
+    /// Instance-rate gradient axis and offset
+    pub gradient_axis: vec4,
+}
+
+impl GradientVertexArgs {
+    /// Populates a `GradientVertexArgs` instance-rate structure with the information from a
+    /// `Transform`, `TintComponent` and `TintGradient3D` components.
+    #[inline]
+    pub fn from_object_data(
+        transform: &Transform,
+        tint: Option<&TintComponent>,
+        gradient: &TintGradient3D,
+    ) -> Self {
+        let args = VertexArgs::from_object_data(transform, tint);
+        GradientVertexArgs {
+            model: args.model,
+            tint: args.tint,
+            gradient_start: gradient.start_color.into_pod(),
+            gradient_end: gradient.end_color.into_pod(),
+            gradient_axis: gradient.axis_offset().into(),
+        }
+    }
+}
+
+impl AsVertex for GradientVertexArgs {
+    fn vertex() -> VertexFormat {
+        VertexFormat::new((
+            Model::vertex(),
+            Tint::vertex(),
+            GradientStart::vertex(),
+            GradientEnd::vertex(),
+            GradientAxis::vertex(),
+        ))
+    }
+}
+
+/// Dissolve edge color
+/// ```glsl,ignore
+/// vec4 dissolve_edge_color;
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(16))]
+pub struct DissolveEdgeColor {
+    /// Color as `Rgba32Sfloat`
+    pub dissolve_edge_color: vec4,
+}
+
+impl AsAttribute for DissolveEdgeColor {
+    const NAME: &'static str = "dissolve_edge_color";
+    const FORMAT: Format = Format::Rgba32Sfloat;
+}
+
+/// Dissolve amount and edge width
+/// ```glsl,ignore
+/// vec2 dissolve;
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(8))]
+pub struct DissolveAmount {
+    /// Amount and edge width as `Rg32Sfloat`
+    pub dissolve: vec2,
+}
+
+impl AsAttribute for DissolveAmount {
+    const NAME: &'static str = "dissolve";
+    const FORMAT: Format = Format::Rg32Sfloat;
+}
+
+/// Instance-rate vertex arguments of meshes with a `Dissolve`
+/// ```glsl,ignore
+///  mat4 model;
+///  vec4 tint;
+///  vec4 dissolve_edge_color;
+///  vec2 dissolve;
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+#[repr(C, packed)]
+pub struct DissolveVertexArgs {
+    /// Instance-rate model matrix
+    pub model: mat4,
+    /// Instance-rate model `Tint`
+    pub tint: vec4,
+    /// Instance-rate dissolve edge color
+    pub dissolve_edge_color: vec4,
+    /// Instance-rate dissolve amount and edge width
+    pub dissolve: vec2,
+}
+
+impl DissolveVertexArgs {
+    /// Populates a `DissolveVertexArgs` instance-rate structure with the information from a
+    /// `Transform`, `TintComponent` and `Dissolve` components.
+    #[inline]
+    pub fn from_object_data(
+        transform: &Transform,
+        tint: Option<&TintComponent>,
+        dissolve: &Dissolve,
+    ) -> Self {
+        let args = VertexArgs::from_object_data(transform, tint);
+        DissolveVertexArgs {
+            model: args.model,
+            tint: args.tint,
+            dissolve_edge_color: dissolve.edge_color.into_pod(),
+            dissolve: dissolve.amount_edge().into(),
+        }
+    }
+}
+
+impl AsVertex for DissolveVertexArgs {
+    fn vertex() -> VertexFormat {
+        VertexFormat::new((
+            Model::vertex(),
+            Tint::vertex(),
+            DissolveEdgeColor::vertex(),
+            DissolveAmount::vertex(),
+        ))
+    }
+}
+
 /// Instance-rate joints offset
 /// ```glsl,ignore
 ///  uint joints_offset;
@@ -160,10 +344,7 @@ impl SkinnedVertexArgs {
         let model: [[f32; 4]; 4] = convert::<_, Matrix4<f32>>(*transform.global_matrix()).into();
         SkinnedVertexArgs {
             model: model.into(),
-            tint: tint.map_or([1.0; 4].into(), |t| {
-                let (r, g, b, a) = t.0.into_components();
-                [r, g, b, a].into()
-            }),
+            tint: tint.map_or([1.0; 4].into(), |t| t.0.into_pod()),
             joints_offset,
         }
     }
@@ -336,6 +517,8 @@ impl SpriteArgs {
     /// * `sprite_storage` - `SpriteSheet` Storage
     /// * `sprite_render` - `SpriteRender` component reference
     /// * `transform` - 'Transform' component reference
+    ///
+    /// Sprites out of the range of their sheet give `None`, see `try_from_data`.
     pub fn from_data<'a>(
         tex_storage: &AssetStorage<Texture>,
         sprite_storage: &'a AssetStorage<SpriteSheet>,
@@ -343,19 +526,44 @@ impl SpriteArgs {
         transform: &Transform,
         tint: Option<&TintComponent>,
     ) -> Option<(Self, &'a Handle<Texture>)> {
-        let sprite_sheet = sprite_storage.get(&sprite_render.sprite_sheet)?;
+        Self::try_from_data(tex_storage, sprite_storage, sprite_render, transform, tint)
+            .unwrap_or(None)
+    }
+
+    /// Extracts POD vertex data like `from_data`, with a `RenderError::SpriteOutOfRange` for
+    /// sprites out of the range of their sheet. Gives `Ok(None)` while the sheet or its texture
+    /// isn't loaded.
+    pub fn try_from_data<'a>(
+        tex_storage: &AssetStorage<Texture>,
+        sprite_storage: &'a AssetStorage<SpriteSheet>,
+        sprite_render: &SpriteRender,
+        transform: &Transform,
+        tint: Option<&TintComponent>,
+    ) -> Result<Option<(Self, &'a Handle<Texture>)>, RenderError> {
+        let sprite_sheet = match sprite_storage.get(&sprite_render.sprite_sheet) {
+            Some(sprite_sheet) => sprite_sheet,
+            None => return Ok(None),
+        };
         if !tex_storage.contains(&sprite_sheet.texture) {
-            return None;
+            return Ok(None);
         }
 
-        let sprite = &sprite_sheet.sprites[sprite_render.sprite_number];
+        let sprite = sprite_sheet
+            .sprites
+            .get(sprite_render.sprite_number)
+            .ok_or_else(|| RenderError::SpriteOutOfRange {
+                entity: None,
+                sprite_sheet: sprite_render.sprite_sheet.id(),
+                sprite_number: sprite_render.sprite_number,
+                sprite_count: sprite_sheet.sprites.len(),
+            })?;
 
         let transform = convert::<_, Matrix4<f32>>(*transform.global_matrix());
         let dir_x = transform.column(0) * sprite.width;
         let dir_y = transform.column(1) * -sprite.height;
         let pos = transform * Vector4::new(-sprite.offsets[0], -sprite.offsets[1], 0.0, 1.0);
 
-        Some((
+        Ok(Some((
             SpriteArgs {
                 dir_x: dir_x.xy().into_pod(),
                 dir_y: dir_y.xy().into_pod(),
@@ -363,13 +571,10 @@ impl SpriteArgs {
                 u_offset: [sprite.tex_coords.left, sprite.tex_coords.right].into(),
                 v_offset: [sprite.tex_coords.top, sprite.tex_coords.bottom].into(),
                 depth: pos.z,
-                tint: tint.map_or([1.0; 4].into(), |t| {
-                    let (r, g, b, a) = t.0.into_components();
-                    [r, g, b, a].into()
-                }),
+                tint: tint.map_or([1.0; 4].into(), |t| t.0.into_pod()),
             },
             &sprite_sheet.texture,
-        ))
+        )))
     }
 }
 
@@ -393,30 +598,133 @@ impl IntoPod<vec3> for amethyst_core::math::Vector3<f32> {
     }
 }
 
-impl IntoPod<vec3> for palette::Srgb {
-    fn into_pod(self) -> vec3 {
-        let (r, g, b) = self.into_components();
-        [r, g, b].into()
+/// Decodes an sRGB encoded color component to linear. The shaders work with linear colors,
+/// as sampled from sRGB textures and written to sRGB framebuffers.
+pub fn srgb_to_linear(component: f32) -> f32 {
+    if component <= 0.040_45 {
+        component / 12.92
+    } else {
+        ((component + 0.055) / 1.055).powf(2.4)
     }
 }
 
+/// Encodes a linear color component to sRGB, inverting `srgb_to_linear`.
+pub fn linear_to_srgb(component: f32) -> f32 {
+    if component <= 0.003_130_8 {
+        component * 12.92
+    } else {
+        1.055 * component.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The linear components of an sRGB color, as the `pod` structs store them.
 impl IntoPod<[f32; 3]> for palette::Srgb {
     fn into_pod(self) -> [f32; 3] {
         let (r, g, b) = self.into_components();
-        [r, g, b]
+        [srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b)]
+    }
+}
+
+impl IntoPod<vec3> for palette::Srgb {
+    fn into_pod(self) -> vec3 {
+        let arr: [f32; 3] = self.into_pod();
+        arr.into()
+    }
+}
+
+/// The linear components of an sRGB color, with its alpha as is.
+impl IntoPod<[f32; 4]> for palette::Srgba {
+    fn into_pod(self) -> [f32; 4] {
+        let [r, g, b]: [f32; 3] = self.color.into_pod();
+        [r, g, b, self.alpha]
     }
 }
 
 impl IntoPod<vec4> for palette::Srgba {
     fn into_pod(self) -> vec4 {
-        let (r, g, b, a) = self.into_components();
-        [r, g, b, a].into()
+        let arr: [f32; 4] = self.into_pod();
+        arr.into()
     }
 }
 
-impl IntoPod<[f32; 4]> for palette::Srgba {
+impl IntoPod<[f32; 3]> for palette::LinSrgb {
+    fn into_pod(self) -> [f32; 3] {
+        let (r, g, b) = self.into_components();
+        [r, g, b]
+    }
+}
+
+impl IntoPod<vec3> for palette::LinSrgb {
+    fn into_pod(self) -> vec3 {
+        let arr: [f32; 3] = self.into_pod();
+        arr.into()
+    }
+}
+
+impl IntoPod<[f32; 4]> for palette::LinSrgba {
     fn into_pod(self) -> [f32; 4] {
         let (r, g, b, a) = self.into_components();
         [r, g, b, a]
     }
 }
+
+impl IntoPod<vec4> for palette::LinSrgba {
+    fn into_pod(self) -> vec4 {
+        let arr: [f32; 4] = self.into_pod();
+        arr.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use palette::{LinSrgba, Srgb, Srgba};
+
+    /// 50% sRGB gray is about 21.4% of the light of white.
+    const GRAY_LINEAR: f32 = 0.214_041_14;
+
+    #[test]
+    fn srgb_colors_are_uploaded_linear() {
+        let [r, g, b, a]: [f32; 4] = Srgba::new(0.5, 0.5, 0.5, 0.5).into_pod();
+        for c in &[r, g, b] {
+            assert!((c - GRAY_LINEAR).abs() < 1e-6);
+        }
+        assert!((a - 0.5).abs() < 1e-6);
+        let rgb: [f32; 3] = Srgb::new(0.5, 0.5, 0.5).into_pod();
+        assert!((rgb[1] - GRAY_LINEAR).abs() < 1e-6);
+        let linear: [f32; 4] = LinSrgba::new(0.5, 0.5, 0.5, 0.5).into_pod();
+        assert_eq!(linear, [0.5; 4]);
+
+        let tint = TintComponent(Srgba::new(0.5, 0.5, 0.5, 0.5));
+        let args = VertexArgs::from_object_data(&Transform::default(), Some(&tint));
+        assert_eq!(args.tint, vec4::from([r, g, b, a]));
+
+        for &c in &[0.0, 0.02, 0.5, 0.9, 1.0] {
+            assert!((linear_to_srgb(srgb_to_linear(c)) - c).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn gradient_axis_spans_start_to_end() {
+        let mut gradient = TintGradient3D::vertical(
+            Srgba::new(0.0, 0.0, 0.0, 1.0),
+            Srgba::new(1.0, 1.0, 1.0, 1.0),
+        );
+        gradient.axis = [0.0, 2.0, 0.0].into();
+        gradient.start = 1.0;
+        gradient.end = 3.0;
+        let [x, y, z, offset] = gradient.axis_offset();
+        let at = |height: f32| x * 0.0 + y * height + z * 0.0 + offset;
+        assert!(at(1.0).abs() < 1e-6);
+        assert!((at(2.0) - 0.5).abs() < 1e-6);
+        assert!((at(3.0) - 1.0).abs() < 1e-6);
+
+        let args = GradientVertexArgs::from_object_data(&Transform::default(), None, &gradient);
+        assert_eq!(args.gradient_axis, vec4::from(gradient.axis_offset()));
+        assert_eq!(args.gradient_end, vec4::from([1.0; 4]));
+        assert_eq!(
+            std::mem::size_of::<GradientVertexArgs>() as u32,
+            GradientVertexArgs::vertex().stride
+        );
+    }
+}
@@ -0,0 +1,297 @@
+//! Runtime configuration of how frames are presented to the window.
+//!
+//! Fewer swapchain images and frames in flight lower the latency between input and display, at
+//! the cost of stalls when the CPU or GPU can't keep up. The number of images is clamped to the
+//! capabilities of the surface, and the number of frames in flight to the number of images.
+use crate::{
+    rendy::{
+        factory::Factory,
+        graph::{
+            present::{PresentBuilder, PresentNode},
+            ImageId,
+        },
+        hal,
+        wsi::Surface,
+    },
+    types::Backend,
+};
+use amethyst_core::ecs::Resources;
+use serde::{Deserialize, Serialize};
+use std::{
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+/// Swapchain images of a window when no count is given.
+pub const DEFAULT_IMAGE_COUNT: u32 = 3;
+
+/// How frames are queued for presentation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PresentMode {
+    /// Wait for the vertical blank, queueing frames. Vsync, supported by every surface.
+    Fifo,
+    /// Wait for the vertical blank, replacing the queued frame by newer ones. Vsync with lower
+    /// latency. Falls back to `Fifo`.
+    Mailbox,
+    /// Present right away, which may tear. No vsync. Falls back to `Mailbox`, then `Fifo`.
+    Immediate,
+}
+
+impl Default for PresentMode {
+    fn default() -> Self {
+        PresentMode::Fifo
+    }
+}
+
+impl PresentMode {
+    /// This mode and the modes it falls back to, in order.
+    pub fn fallbacks(self) -> &'static [PresentMode] {
+        match self {
+            PresentMode::Fifo => &[PresentMode::Fifo],
+            PresentMode::Mailbox => &[PresentMode::Mailbox, PresentMode::Fifo],
+            PresentMode::Immediate => &[
+                PresentMode::Immediate,
+                PresentMode::Mailbox,
+                PresentMode::Fifo,
+            ],
+        }
+    }
+
+    fn to_hal(self) -> hal::PresentMode {
+        match self {
+            PresentMode::Fifo => hal::PresentMode::Fifo,
+            PresentMode::Mailbox => hal::PresentMode::Mailbox,
+            PresentMode::Immediate => hal::PresentMode::Immediate,
+        }
+    }
+
+    /// The first mode of `self` and its fallbacks accepted by `supported`.
+    fn select(self, supported: impl Fn(PresentMode) -> bool) -> PresentMode {
+        self.fallbacks()
+            .iter()
+            .cloned()
+            .find(|&mode| supported(mode))
+            .unwrap_or(PresentMode::Fifo)
+    }
+}
+
+/// Resource with the desired presentation of frames, watched by the `RenderingSystem`.
+///
+/// Changing `present_mode`, `image_count` or `frames_in_flight` rebuilds the render graph on the
+/// next frame. The values used by the graph, after falling back from unsupported ones, are
+/// reported by `active_mode`, `active_image_count` and `active_frames_in_flight`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresentationConfig {
+    /// Desired present mode.
+    pub present_mode: PresentMode,
+    /// Maximum number of frames rendered per second, if any.
+    pub max_fps: Option<u32>,
+    /// Desired number of swapchain images, or `None` for `DEFAULT_IMAGE_COUNT`.
+    pub image_count: Option<u32>,
+    /// Desired number of frames recorded while the GPU renders earlier ones, or `None` for the
+    /// default of the render graph.
+    pub frames_in_flight: Option<u32>,
+    #[serde(skip)]
+    active: Option<PresentMode>,
+    #[serde(skip)]
+    active_image_count: Option<u32>,
+    #[serde(skip)]
+    active_frames_in_flight: Option<u32>,
+}
+
+impl PresentationConfig {
+    /// Present with the given mode.
+    pub fn with_present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    /// Render at most `max_fps` frames per second.
+    pub fn with_max_fps(mut self, max_fps: u32) -> Self {
+        self.max_fps = Some(max_fps);
+        self
+    }
+
+    /// Present with `image_count` swapchain images.
+    pub fn with_image_count(mut self, image_count: u32) -> Self {
+        self.image_count = Some(image_count);
+        self
+    }
+
+    /// Keep at most `frames_in_flight` frames in flight.
+    pub fn with_frames_in_flight(mut self, frames_in_flight: u32) -> Self {
+        self.frames_in_flight = Some(frames_in_flight);
+        self
+    }
+
+    /// The present mode of the window surface, once the render graph is built.
+    pub fn active_mode(&self) -> Option<PresentMode> {
+        self.active
+    }
+
+    /// The number of swapchain images of the window surface, once the render graph is built.
+    pub fn active_image_count(&self) -> Option<u32> {
+        self.active_image_count
+    }
+
+    /// The number of frames in flight of the render graph, once it is built.
+    pub fn active_frames_in_flight(&self) -> Option<u32> {
+        self.active_frames_in_flight
+    }
+
+    /// The frames in flight to build the render graph with, at least one and no more than the
+    /// swapchain images.
+    pub(crate) fn clamped_frames_in_flight(&self) -> Option<u32> {
+        self.frames_in_flight.map(|frames| {
+            frames
+                .min(self.active_image_count.unwrap_or(u32::max_value()))
+                .max(1)
+        })
+    }
+
+    pub(crate) fn set_active_frames_in_flight(&mut self, frames_in_flight: u32) {
+        self.active_frames_in_flight = Some(frames_in_flight);
+    }
+
+    /// Shortest time between the start of two frames.
+    pub(crate) fn frame_time(&self) -> Option<Duration> {
+        self.max_fps
+            .filter(|&fps| fps > 0)
+            .map(|fps| Duration::from_secs(1) / fps)
+    }
+}
+
+/// The count of images in `supported` closest to `desired`. The end of `supported` is inclusive.
+fn clamp_image_count(desired: u32, supported: &Range<u32>) -> u32 {
+    desired.min(supported.end).max(supported.start)
+}
+
+/// Build a `PresentNode` for `surface`, presenting with the mode and image count of the
+/// `PresentationConfig` resource or their closest supported values, and report them to the
+/// resource.
+///
+/// Graph creators should use this instead of `PresentNode::builder` to follow the configuration.
+pub fn present_builder<B: Backend>(
+    factory: &Factory<B>,
+    surface: Surface<B>,
+    image: ImageId,
+    res: &Resources,
+) -> PresentBuilder<B> {
+    let (caps, _, supported) = factory.get_surface_compatibility(&surface);
+    let (desired, desired_count) = res
+        .try_fetch::<PresentationConfig>()
+        .map(|config| (config.present_mode, config.image_count))
+        .unwrap_or_default();
+    let mode = desired.select(|mode| supported.contains(&mode.to_hal()));
+    if mode != desired {
+        log::warn!(
+            "Present mode {:?} is not supported by the surface, using {:?}",
+            desired,
+            mode
+        );
+    }
+    let desired_count = desired_count.unwrap_or(DEFAULT_IMAGE_COUNT);
+    let image_count = clamp_image_count(desired_count, &caps.image_count);
+    if image_count != desired_count {
+        log::warn!(
+            "{} swapchain images are not supported by the surface, using {}",
+            desired_count,
+            image_count
+        );
+    }
+    if let Some(mut config) = res.try_fetch_mut::<PresentationConfig>() {
+        config.active = Some(mode);
+        config.active_image_count = Some(image_count);
+    }
+
+    let selected = mode.to_hal();
+    PresentNode::builder(factory, surface, image)
+        .with_image_count(image_count)
+        .with_present_modes_priority(move |mode| if mode == selected { Some(0) } else { None })
+}
+
+/// Tracks changes of the `PresentationConfig` and caps the frame rate, for the `RenderingSystem`.
+#[derive(Debug, Default)]
+pub(crate) struct PresentationWatch {
+    desired: Option<(PresentMode, Option<u32>, Option<u32>)>,
+    frame_start: Option<Instant>,
+}
+
+impl PresentationWatch {
+    /// Whether the present mode, image count or frames in flight changed since the last call.
+    pub(crate) fn changed(&mut self, config: &PresentationConfig) -> bool {
+        let desired = (
+            config.present_mode,
+            config.image_count,
+            config.frames_in_flight,
+        );
+        let previous = self.desired.replace(desired);
+        previous.map_or(false, |previous| previous != desired)
+    }
+
+    /// Sleep until the frame started last time `frame_time` ago, and start a new frame.
+    pub(crate) fn limit(&mut self, frame_time: Option<Duration>) {
+        if let (Some(frame_time), Some(start)) = (frame_time, self.frame_start) {
+            let elapsed = start.elapsed();
+            if elapsed < frame_time {
+                std::thread::sleep(frame_time - elapsed);
+            }
+        }
+        self.frame_start = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_modes_fall_back() {
+        let mailbox_only = |mode| mode != PresentMode::Immediate;
+        assert_eq!(
+            PresentMode::Immediate.select(mailbox_only),
+            PresentMode::Mailbox
+        );
+        let fifo_only = |mode| mode == PresentMode::Fifo;
+        assert_eq!(PresentMode::Immediate.select(fifo_only), PresentMode::Fifo);
+        assert_eq!(PresentMode::Mailbox.select(fifo_only), PresentMode::Fifo);
+        assert_eq!(PresentMode::Mailbox.select(|_| true), PresentMode::Mailbox);
+    }
+
+    #[test]
+    fn present_mode_changes() {
+        let mut watch = PresentationWatch::default();
+        let mut config = PresentationConfig::default();
+        assert!(!watch.changed(&config));
+        assert!(!watch.changed(&config));
+        config.present_mode = PresentMode::Immediate;
+        assert!(watch.changed(&config));
+        assert!(!watch.changed(&config));
+        config.frames_in_flight = Some(1);
+        assert!(watch.changed(&config));
+        assert!(!watch.changed(&config));
+
+        assert_eq!(config.frame_time(), None);
+        assert_eq!(
+            config.with_max_fps(50).frame_time(),
+            Some(Duration::from_millis(20))
+        );
+    }
+
+    #[test]
+    fn latency_is_clamped() {
+        assert_eq!(clamp_image_count(3, &(2..8)), 3);
+        assert_eq!(clamp_image_count(1, &(2..8)), 2);
+        assert_eq!(clamp_image_count(3, &(1..2)), 2);
+
+        let mut config = PresentationConfig::default().with_frames_in_flight(3);
+        assert_eq!(config.clamped_frames_in_flight(), Some(3));
+        config.active_image_count = Some(2);
+        assert_eq!(config.clamped_frames_in_flight(), Some(2));
+        config.frames_in_flight = Some(0);
+        assert_eq!(config.clamped_frames_in_flight(), Some(1));
+        config.frames_in_flight = None;
+        assert_eq!(config.clamped_frames_in_flight(), None);
+    }
+}
@@ -0,0 +1,148 @@
+//! Recovery of the `RenderingSystem` from lost devices and surfaces.
+//!
+//! When running the graph fails, the rendering system checks whether the device is still
+//! usable. If it was lost, the graph, the GPU copies of the assets and the factory are disposed
+//! of and created again: meshes and textures loaded from files are reloaded from their sources,
+//! and those loaded from data, the dynamic meshes and textures included, are uploaded again from
+//! their data. If the device is fine but the failure tells a surface was lost or out of date,
+//! the graph is rebuilt, which recreates the surfaces. Any other failure is resumed right away.
+//!
+//! Each recovery is announced with a `RenderRecovery` event, for the game to tell the player
+//! about it. Failing again on the frame right after a recovery gives up and resumes the panic.
+use crate::{
+    rendy::{factory::Factory, hal::device::Device},
+    types::Backend,
+};
+use amethyst_core::{ecs::Resources, shrev::EventChannel};
+
+/// Event written to the `EventChannel<RenderRecovery>` resource when the renderer recovered
+/// from a failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderRecovery {
+    /// The device was lost. The device, the graph and the GPU copies of the assets were
+    /// created again.
+    DeviceLost,
+    /// The graph failed with a working device, as when a surface was lost, and was rebuilt.
+    GraphRebuilt,
+}
+
+/// Tells which recovery follows the failures of the graph.
+#[derive(Debug, Default)]
+pub(crate) struct RecoveryWatch {
+    recovered: bool,
+}
+
+impl RecoveryWatch {
+    /// Go on with `recovery` after a failure of the graph, or `None` to give up as the last
+    /// frame failed too.
+    pub(crate) fn failed(&mut self, recovery: RenderRecovery) -> Option<RenderRecovery> {
+        if std::mem::replace(&mut self.recovered, true) {
+            None
+        } else {
+            Some(recovery)
+        }
+    }
+
+    /// The graph ran fine.
+    pub(crate) fn succeeded(&mut self) {
+        self.recovered = false;
+    }
+}
+
+/// Whether the device of `factory` was lost.
+pub(crate) fn device_lost<B: Backend>(factory: &Factory<B>) -> bool {
+    use crate::rendy::hal::error::HostExecutionError;
+
+    match factory.device().wait_idle() {
+        Err(HostExecutionError::DeviceLost) => true,
+        _ => false,
+    }
+}
+
+/// The recovery from a failure of the graph with `message`, or `None` if the failure isn't
+/// caused by a lost device or surface.
+pub(crate) fn recovery_for(device_lost: bool, message: &str) -> Option<RenderRecovery> {
+    if device_lost {
+        return Some(RenderRecovery::DeviceLost);
+    }
+    // Matches both the variant names, like `SurfaceLost`, and the messages of the errors.
+    let message: String = message
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    if message.contains("surfacelost") || message.contains("outofdate") {
+        Some(RenderRecovery::GraphRebuilt)
+    } else {
+        None
+    }
+}
+
+/// Announce a recovery.
+pub(crate) fn announce(res: &Resources, recovery: RenderRecovery) {
+    log::warn!("Recovered the renderer: {:?}", recovery);
+    if let Some(mut events) = res.try_fetch_mut::<EventChannel<RenderRecovery>>() {
+        events.single_write(recovery);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gives_up_on_repeated_failures() {
+        let mut watch = RecoveryWatch::default();
+        assert_eq!(
+            watch.failed(RenderRecovery::DeviceLost),
+            Some(RenderRecovery::DeviceLost)
+        );
+        assert_eq!(watch.failed(RenderRecovery::GraphRebuilt), None);
+        watch.succeeded();
+        assert_eq!(
+            watch.failed(RenderRecovery::GraphRebuilt),
+            Some(RenderRecovery::GraphRebuilt)
+        );
+    }
+
+    #[test]
+    fn recovers_again_after_a_frame_rendered() {
+        let mut watch = RecoveryWatch::default();
+        assert_eq!(
+            watch.failed(RenderRecovery::DeviceLost),
+            Some(RenderRecovery::DeviceLost)
+        );
+        watch.succeeded();
+        assert_eq!(
+            watch.failed(RenderRecovery::DeviceLost),
+            Some(RenderRecovery::DeviceLost)
+        );
+        watch.succeeded();
+        watch.succeeded();
+        assert_eq!(
+            watch.failed(RenderRecovery::DeviceLost),
+            Some(RenderRecovery::DeviceLost)
+        );
+    }
+
+    #[test]
+    fn recovers_only_from_lost_devices_and_surfaces() {
+        assert_eq!(
+            recovery_for(true, "index out of bounds"),
+            Some(RenderRecovery::DeviceLost)
+        );
+        assert_eq!(
+            recovery_for(false, "Failed to acquire image: OutOfDate"),
+            Some(RenderRecovery::GraphRebuilt)
+        );
+        assert_eq!(
+            recovery_for(false, "Surface lost"),
+            Some(RenderRecovery::GraphRebuilt)
+        );
+        assert_eq!(
+            recovery_for(false, "called `Option::unwrap()` on a `None` value"),
+            None
+        );
+        assert_eq!(recovery_for(false, ""), None);
+    }
+}
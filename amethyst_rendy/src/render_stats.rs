@@ -0,0 +1,227 @@
+//! Statistics of the draws of the 3D render groups, for profiling the renderer itself.
+//!
+//! When the `enabled` flag of the `RenderStats3D` resource is set, the 3D groups count their
+//! draw calls, batches, instances, triangles and binds while recording their command buffers,
+//! and add the counts to the resource every frame the command buffers are submitted, also when
+//! a group reuses the ones of a previous frame. The `RenderingSystem` resets the counts every
+//! frame. Triangles are estimated from the index or vertex counts of the draws, as triangle
+//! lists.
+//!
+//! Counting starts when `enabled` is set on the resource, or when `RenderStats3D::enabled()` is
+//! inserted before the `RenderingSystem` is set up.
+use crate::{indirect::IndirectDraws, types::Backend};
+use amethyst_core::ecs::Resources;
+use std::ops::Range;
+
+/// The passes of the 3D render groups counted by `RenderStats3D`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Pass3D {
+    /// Opaque meshes without skinning.
+    Opaque = 0,
+    /// Transparent meshes without skinning.
+    Transparent = 1,
+    /// Skinned meshes, opaque or transparent.
+    Skinned = 2,
+}
+
+/// Counts of the draws of a pass.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PassStats {
+    /// Draw calls recorded.
+    pub draw_calls: u32,
+    /// Batches of instances of the same mesh and material drawn.
+    pub batches: u32,
+    /// Instances drawn.
+    pub instances: u32,
+    /// Triangles submitted, estimated from the index or vertex counts.
+    pub triangles: u64,
+    /// Graphics pipelines bound.
+    pub pipeline_binds: u32,
+    /// Descriptor sets bound.
+    pub descriptor_set_binds: u32,
+}
+
+impl PassStats {
+    /// Add the counts of `other`.
+    pub fn add(&mut self, other: &PassStats) {
+        self.draw_calls += other.draw_calls;
+        self.batches += other.batches;
+        self.instances += other.instances;
+        self.triangles += other.triangles;
+        self.pipeline_binds += other.pipeline_binds;
+        self.descriptor_set_binds += other.descriptor_set_binds;
+    }
+}
+
+/// Resource with the draws of the 3D render groups of the last frame, counted only while
+/// `enabled` is set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats3D {
+    /// Whether the groups count their draws.
+    pub enabled: bool,
+    /// Draws of the opaque meshes without skinning.
+    pub opaque: PassStats,
+    /// Draws of the transparent meshes without skinning.
+    pub transparent: PassStats,
+    /// Draws of the skinned meshes.
+    pub skinned: PassStats,
+}
+
+impl RenderStats3D {
+    /// Statistics counted from the first frame.
+    pub fn enabled() -> Self {
+        Self {
+            enabled: true,
+            ..Self::default()
+        }
+    }
+
+    /// The counts of `pass`.
+    pub fn pass(&self, pass: Pass3D) -> &PassStats {
+        match pass {
+            Pass3D::Opaque => &self.opaque,
+            Pass3D::Transparent => &self.transparent,
+            Pass3D::Skinned => &self.skinned,
+        }
+    }
+
+    /// The counts of `pass`, mutably.
+    pub fn pass_mut(&mut self, pass: Pass3D) -> &mut PassStats {
+        match pass {
+            Pass3D::Opaque => &mut self.opaque,
+            Pass3D::Transparent => &mut self.transparent,
+            Pass3D::Skinned => &mut self.skinned,
+        }
+    }
+
+    /// The counts of all passes.
+    pub fn total(&self) -> PassStats {
+        let mut total = self.opaque;
+        total.add(&self.transparent);
+        total.add(&self.skinned);
+        total
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self {
+            enabled: self.enabled,
+            ..Self::default()
+        };
+    }
+}
+
+const PASSES: [Pass3D; 3] = [Pass3D::Opaque, Pass3D::Transparent, Pass3D::Skinned];
+
+/// Counts of the command buffers of a render group, one per frame index, for the
+/// `RenderStats3D` resource. Counting does nothing while the resource is disabled.
+#[derive(Debug, Default)]
+pub struct GroupStats {
+    enabled: bool,
+    recording: [PassStats; 3],
+    per_image: Vec<[PassStats; 3]>,
+}
+
+impl GroupStats {
+    /// Create an empty `GroupStats`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start counting the command buffer being recorded, if the resource is enabled.
+    pub fn begin(&mut self, resources: &Resources) {
+        self.enabled = resources
+            .try_fetch::<RenderStats3D>()
+            .map_or(false, |stats| stats.enabled);
+        self.recording = Default::default();
+    }
+
+    /// Count a bind of a graphics pipeline.
+    pub fn pipeline_bind(&mut self, pass: Pass3D) {
+        if self.enabled {
+            self.recording[pass as usize].pipeline_binds += 1;
+        }
+    }
+
+    /// Count `count` binds of descriptor sets.
+    pub fn set_binds(&mut self, pass: Pass3D, count: u32) {
+        if self.enabled {
+            self.recording[pass as usize].descriptor_set_binds += count;
+        }
+    }
+
+    /// Count the draws of the slots `slots` of `indirect`.
+    pub fn draws<B: Backend>(
+        &mut self,
+        pass: Pass3D,
+        indirect: &IndirectDraws<B>,
+        slots: Range<u32>,
+    ) {
+        if self.enabled {
+            indirect.count(slots, &mut self.recording[pass as usize]);
+        }
+    }
+
+    /// Keep the counts of the command buffer of the frame `index`, and add them to the resource.
+    pub fn finish(&mut self, index: usize, resources: &Resources) {
+        if !self.enabled {
+            return;
+        }
+        while self.per_image.len() <= index {
+            self.per_image.push(Default::default());
+        }
+        self.per_image[index] = self.recording;
+        self.reuse(index, resources);
+    }
+
+    /// Add the counts of the command buffer of the frame `index`, when it's submitted again.
+    pub fn reuse(&self, index: usize, resources: &Resources) {
+        let counts = match self.per_image.get(index) {
+            Some(counts) => counts,
+            None => return,
+        };
+        if let Some(mut stats) = resources.try_fetch_mut::<RenderStats3D>() {
+            if stats.enabled {
+                for (pass, counts) in PASSES.iter().zip(counts) {
+                    stats.pass_mut(*pass).add(counts);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuse_adds_recorded_counts() {
+        let mut resources = Resources::new();
+        resources.insert(RenderStats3D::enabled());
+
+        let mut group = GroupStats::new();
+        group.begin(&resources);
+        group.pipeline_bind(Pass3D::Opaque);
+        group.set_binds(Pass3D::Opaque, 2);
+        group.pipeline_bind(Pass3D::Skinned);
+        group.finish(1, &resources);
+        group.reuse(0, &resources);
+        group.reuse(1, &resources);
+
+        let stats = *resources.fetch::<RenderStats3D>();
+        assert_eq!(stats.opaque.pipeline_binds, 2);
+        assert_eq!(stats.opaque.descriptor_set_binds, 4);
+        assert_eq!(stats.skinned.pipeline_binds, 2);
+        assert_eq!(stats.total().pipeline_binds, 4);
+
+        resources.fetch_mut::<RenderStats3D>().reset();
+        resources.fetch_mut::<RenderStats3D>().enabled = false;
+        group.begin(&resources);
+        group.pipeline_bind(Pass3D::Opaque);
+        group.finish(0, &resources);
+        group.reuse(1, &resources);
+        assert_eq!(
+            *resources.fetch::<RenderStats3D>(),
+            RenderStats3D::default()
+        );
+    }
+}
@@ -1,35 +1,53 @@
-use std::{marker::PhantomData, ops::Deref, sync::Arc};
+use std::{marker::PhantomData, sync::Arc};
 
-use amethyst_assets::Processor;
+use amethyst_assets::{AssetStorage, Handle, Loader, Processor, ProgressCounter};
 use amethyst_core::{
     bundle::SystemBundle,
-    ecs::{DispatcherBuilder, ReadExpect, Resources, SystemData},
+    ecs::{Builder, Dispatcher, DispatcherBuilder, ReadExpect, Resources, SystemData, World},
+    math::{Point3, Vector3},
+    Transform, TransformBundle,
 };
 use amethyst_error::Error;
 use amethyst_window::{ScreenDimensions, Window};
 use derive_new::new;
+use palette::{Srgb, Srgba};
 
 use crate::{
-    pass::{DrawFlat2DDesc, DrawFlat2DTransparentDesc},
+    camera::Camera,
+    capture::{CaptureDesc, CapturedFrame, FrameCapture},
+    debug_drawing::DebugLinesComponent,
+    error::GoldenError,
+    light::{DirectionalLight, Light, PointLight},
+    mtl::{Material, MaterialDefaults},
+    pass::{
+        DrawDebugLinesDesc, DrawFlat2DDesc, DrawFlat2DTransparentDesc, DrawFlatDesc, DrawPbrDesc,
+        DrawShadedDesc,
+    },
+    presentation::present_builder,
     rendy::{
         factory::Factory,
         graph::{
-            present::PresentNode,
             render::{RenderGroupDesc, SubpassBuilder},
-            GraphBuilder,
+            GraphBuilder, NodeDesc,
         },
         hal::{
             command::{ClearDepthStencil, ClearValue},
             format::Format,
             image::Kind,
         },
+        mesh::{Normal, Position, Tangent, TexCoord},
+        texture::palette::load_from_srgba,
     },
-    sprite::SpriteSheet,
+    shape::Shape,
+    sprite::{Sprite, SpriteRender, SpriteSheet},
     sprite_visibility::SpriteVisibilitySortingSystem,
-    types::Backend,
+    types::{Backend, Mesh, Texture},
     GraphCreator, RenderingSystem,
 };
 
+/// Frames rendered at most by `render_golden_scene` before it gives up on its capture.
+pub const GOLDEN_MAX_FRAMES: u32 = 600;
+
 /// Adds sprite systems and a basic rendering system to the dispatcher.
 ///
 /// This test bundle requires the user to also add the `TransformBundle` and `WindowBundle` to the
@@ -62,12 +80,55 @@ where
     }
 }
 
+/// Adds sprite systems and a headless rendering system to the dispatcher.
+///
+/// Sprites are rendered to an offscreen image of the given size instead of a window, so this
+/// runs on machines with a GPU but no display. No window or surface is created. Frames are read
+/// back with the `FrameCapture` resource, for example to compare them with golden images.
+///
+/// This test bundle requires the user to also add the `TransformBundle` to the dispatcher.
+///
+/// This is only meant for testing and only provides very basic rendering. You need to enable the
+/// `test-support` flag to use this.
+#[derive(Debug, new)]
+pub struct RenderHeadlessTestBundle<B> {
+    width: u32,
+    height: u32,
+    #[new(default)]
+    backend: PhantomData<B>,
+}
+
+impl<'a, 'b, B> SystemBundle<'a, 'b> for RenderHeadlessTestBundle<B>
+where
+    B: Backend,
+{
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(
+            Processor::<SpriteSheet>::new(),
+            "sprite_sheet_processor",
+            &[],
+        );
+        builder.add(
+            SpriteVisibilitySortingSystem::new(),
+            "sprite_visibility_system",
+            &["transform_system"],
+        );
+
+        builder.add_thread_local(
+            RenderingSystem::<B, _>::new(HeadlessRenderGraph::<B>::new(self.width, self.height))
+                .with_deterministic(),
+        );
+
+        Ok(())
+    }
+}
+
 /// Adds sprite systems and a basic rendering system to the dispatcher.
 ///
 /// This test bundle requires the user to also add the `TransformBundle` to the dispatcher.
 ///
-/// This is only meant for automated testing and doesn't render anything. You need to enable the
-///// `test-support` flag to use this.
+/// This is only meant for automated testing and doesn't render anything, its `RenderingSystem` is
+/// headless. You need to enable the `test-support` flag to use this.
 #[derive(Debug, new)]
 pub struct RenderEmptyBundle<B>(PhantomData<B>);
 
@@ -87,21 +148,19 @@ where
             &["transform_system"],
         );
 
-        builder.add_thread_local(RenderingSystem::<B, _>::new(EmptyGraph::<B>::new()));
+        builder
+            .add_thread_local(RenderingSystem::<B, _>::new(EmptyGraph::<B>::new()).with_headless());
 
         Ok(())
     }
 }
 
-/// Render graph that renders sprites to a Window.
+/// Render graph that renders sprites to a Window. The `RenderingSystem` rebuilds it when the
+/// window is resized.
 #[derive(Default, new)]
 pub struct RenderGraph<B> {
-    #[new(default)]
-    dimensions: Option<ScreenDimensions>,
     #[new(default)]
     surface_format: Option<Format>,
-    #[new(default)]
-    dirty: bool,
     backend: PhantomData<B>,
 }
 
@@ -109,27 +168,18 @@ impl<B> GraphCreator<B> for RenderGraph<B>
 where
     B: Backend,
 {
-    fn rebuild(&mut self, res: &Resources) -> bool {
-        // Rebuild when dimensions change, but wait until at least two frames have the same.
-        let new_dimensions = res.try_fetch::<ScreenDimensions>();
-        if self.dimensions.as_ref() != new_dimensions.as_ref().map(|d| d.deref()) {
-            self.dirty = true;
-            self.dimensions = new_dimensions.map(|d| d.clone());
-            return false;
-        }
-        return self.dirty;
+    fn rebuild(&mut self, _res: &Resources) -> bool {
+        false
     }
 
     fn builder(&mut self, factory: &mut Factory<B>, res: &Resources) -> GraphBuilder<B, Resources> {
-        self.dirty = false;
-
-        let window = <ReadExpect<'_, Arc<Window>>>::fetch(res);
+        let window = <ReadExpect<'_, Window>>::fetch(res);
         let surface = factory.create_surface(&window);
         // cache surface format to speed things up
         let surface_format = *self
             .surface_format
             .get_or_insert_with(|| factory.get_surface_format(&surface));
-        let dimensions = self.dimensions.as_ref().unwrap();
+        let dimensions = <ReadExpect<'_, ScreenDimensions>>::fetch(res);
         let window_kind = Kind::D2(dimensions.width() as u32, dimensions.height() as u32, 1, 1);
 
         let mut graph_builder = GraphBuilder::new();
@@ -164,7 +214,75 @@ where
         );
 
         let _present = graph_builder.add_node(
-            PresentNode::builder(factory, surface, colour)
+            present_builder(factory, surface, colour, res)
+                .with_dependency(sprite_trans)
+                .with_dependency(sprite),
+        );
+
+        graph_builder
+    }
+}
+
+/// Render graph that renders sprites to an offscreen color image, read back by a `CaptureDesc`
+/// node. It needs no window or surface.
+#[derive(Debug, new)]
+pub struct HeadlessRenderGraph<B> {
+    width: u32,
+    height: u32,
+    #[new(default)]
+    backend: PhantomData<B>,
+}
+
+impl<B> GraphCreator<B> for HeadlessRenderGraph<B>
+where
+    B: Backend,
+{
+    fn rebuild(&mut self, _res: &Resources) -> bool {
+        false
+    }
+
+    fn builder(
+        &mut self,
+        _factory: &mut Factory<B>,
+        _res: &Resources,
+    ) -> GraphBuilder<B, Resources> {
+        let kind = Kind::D2(self.width, self.height, 1, 1);
+
+        let mut graph_builder = GraphBuilder::new();
+        let colour = graph_builder.create_image(
+            kind,
+            1,
+            Format::Rgba8Srgb,
+            Some(ClearValue::Color([0., 0., 0., 1.].into())),
+        );
+
+        // Depth stencil must be 1. for the background to be drawn.
+        let depth = graph_builder.create_image(
+            kind,
+            1,
+            Format::D32Sfloat,
+            Some(ClearValue::DepthStencil(ClearDepthStencil(1., 0))),
+        );
+
+        let sprite = graph_builder.add_node(
+            SubpassBuilder::new()
+                .with_group(DrawFlat2DDesc::new().builder())
+                .with_color(colour)
+                .with_depth_stencil(depth)
+                .into_pass(),
+        );
+        let sprite_trans = graph_builder.add_node(
+            SubpassBuilder::new()
+                .with_group(DrawFlat2DTransparentDesc::new().builder())
+                .with_color(colour)
+                .with_depth_stencil(depth)
+                .into_pass(),
+        );
+
+        let _capture = graph_builder.add_node(
+            CaptureDesc::default()
+                .builder()
+                .with_image(colour)
                 .with_dependency(sprite_trans)
                 .with_dependency(sprite),
         );
@@ -193,3 +311,534 @@ where
         GraphBuilder::new()
     }
 }
+
+/// Deterministic scenes rendered by the golden-image tests, each drawn by one pass.
+///
+/// The 3D scenes are a sphere and a cube made from `Shape`, in front of a fixed camera, lit by
+/// a directional and a point light for the passes with lighting. The 2D scene is made of sprites
+/// of solid colors, and the debug lines scene of the edges of boxes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GoldenScene {
+    /// Sprites drawn by `DrawFlat2DDesc`.
+    Flat2D,
+    /// Meshes drawn by `DrawFlatDesc`.
+    Flat,
+    /// Meshes drawn by `DrawShadedDesc`.
+    Shaded,
+    /// Meshes drawn by `DrawPbrDesc`.
+    Pbr,
+    /// Lines drawn by `DrawDebugLinesDesc`.
+    DebugLines,
+}
+
+impl GoldenScene {
+    /// Name of the reference image of the scene.
+    pub fn name(self) -> &'static str {
+        match self {
+            GoldenScene::Flat2D => "flat2d",
+            GoldenScene::Flat => "flat",
+            GoldenScene::Shaded => "shaded",
+            GoldenScene::Pbr => "pbr",
+            GoldenScene::DebugLines => "debug_lines",
+        }
+    }
+
+    /// Create the entities and assets of the scene, once the `RenderingSystem` is set up. The
+    /// assets are tracked by `progress`.
+    pub fn create(
+        self,
+        world: &mut World,
+        width: u32,
+        height: u32,
+        progress: &mut ProgressCounter,
+    ) {
+        match self {
+            GoldenScene::Flat2D => create_sprites(world, width, height, progress),
+            GoldenScene::DebugLines => {
+                create_camera_3d(world, width, height);
+                create_debug_lines(world);
+            }
+            GoldenScene::Flat | GoldenScene::Shaded | GoldenScene::Pbr => {
+                create_camera_3d(world, width, height);
+                create_meshes(world, progress);
+                create_lights(world);
+            }
+        }
+    }
+}
+
+fn create_camera_3d(world: &mut World, width: u32, height: u32) {
+    let mut transform = Transform::default();
+    transform.set_translation_xyz(0.0, 0.5, 5.0);
+    world
+        .create_entity()
+        .with(Camera::standard_3d(width as f32, height as f32))
+        .with(transform)
+        .build();
+}
+
+fn create_sprites(world: &mut World, width: u32, height: u32, progress: &mut ProgressCounter) {
+    let mut camera = Transform::default();
+    camera.set_translation_z(10.0);
+    world
+        .create_entity()
+        .with(Camera::standard_2d(width as f32, height as f32))
+        .with(camera)
+        .build();
+
+    let colors = [
+        Srgba::new(0.9, 0.2, 0.1, 1.0),
+        Srgba::new(0.1, 0.7, 0.3, 1.0),
+        Srgba::new(0.2, 0.3, 0.9, 1.0),
+    ];
+    for (i, color) in colors.iter().enumerate() {
+        let sheet = {
+            let loader = world.read_resource::<Loader>();
+            let texture = loader.load_from_data(
+                load_from_srgba(*color).into(),
+                &mut *progress,
+                &world.read_resource::<AssetStorage<Texture>>(),
+            );
+            let sheet = SpriteSheet {
+                texture,
+                sprites: vec![Sprite::from_pixel_values(
+                    1, 1, 1, 1, 0, 0, [0.0; 2], false, false,
+                )],
+                alpha_mode: Default::default(),
+            };
+            loader.load_from_data(
+                sheet,
+                &mut *progress,
+                &world.read_resource::<AssetStorage<SpriteSheet>>(),
+            )
+        };
+        let mut transform = Transform::default();
+        transform.set_translation_xyz((i as f32 - 1.0) * width as f32 / 4.0, 0.0, i as f32);
+        transform.set_scale(Vector3::new(width as f32 / 5.0, height as f32 / 3.0, 1.0));
+        transform.set_rotation_2d(i as f32 * 0.3);
+        world
+            .create_entity()
+            .with(SpriteRender {
+                sprite_sheet: sheet,
+                sprite_number: 0,
+            })
+            .with(transform)
+            .build();
+    }
+}
+
+fn create_meshes(world: &mut World, progress: &mut ProgressCounter) {
+    let defaults = world.read_resource::<MaterialDefaults>().0.clone();
+    let shapes = [
+        (Shape::Sphere(32, 32), Srgba::new(0.8, 0.3, 0.2, 1.0), -1.2),
+        (Shape::Cube, Srgba::new(0.2, 0.4, 0.8, 1.0), 1.2),
+    ];
+    for (shape, color, x) in shapes.iter() {
+        let (mesh, material): (Handle<Mesh>, Handle<Material>) = {
+            let loader = world.read_resource::<Loader>();
+            let mesh = loader.load_from_data(
+                shape
+                    .generate::<(Vec<Position>, Vec<Normal>, Vec<Tangent>, Vec<TexCoord>)>(None)
+                    .into(),
+                &mut *progress,
+                &world.read_resource::<AssetStorage<Mesh>>(),
+            );
+            let albedo = loader.load_from_data(
+                load_from_srgba(*color).into(),
+                &mut *progress,
+                &world.read_resource::<AssetStorage<Texture>>(),
+            );
+            let material = loader.load_from_data(
+                Material {
+                    albedo,
+                    ..defaults.clone()
+                },
+                &mut *progress,
+                &world.read_resource::<AssetStorage<Material>>(),
+            );
+            (mesh, material)
+        };
+        let mut transform = Transform::default();
+        transform.set_translation_xyz(*x, 0.0, 0.0);
+        transform.set_rotation_euler(0.4, 0.6, 0.0);
+        world
+            .create_entity()
+            .with(mesh)
+            .with(material)
+            .with(transform)
+            .build();
+    }
+}
+
+fn create_lights(world: &mut World) {
+    let directional: Light = DirectionalLight {
+        color: Srgb::new(1.0, 0.95, 0.9),
+        intensity: 1.0,
+        direction: Vector3::new(-1.0, -1.0, -1.0).normalize(),
+    }
+    .into();
+    world
+        .create_entity()
+        .with(directional)
+        .with(Transform::default())
+        .build();
+
+    let point: Light = PointLight {
+        intensity: 5.0,
+        color: Srgb::new(0.6, 0.7, 1.0),
+        ..PointLight::default()
+    }
+    .into();
+    let mut transform = Transform::default();
+    transform.set_translation_xyz(2.0, 2.0, 3.0);
+    world.create_entity().with(point).with(transform).build();
+}
+
+fn create_debug_lines(world: &mut World) {
+    let mut lines = DebugLinesComponent::new();
+    lines.add_box(
+        Point3::new(-2.0, -1.0, -1.0),
+        Point3::new(-0.5, 0.5, 0.5),
+        Srgba::new(1.0, 0.2, 0.2, 1.0),
+    );
+    lines.add_box(
+        Point3::new(0.5, -0.5, -0.5),
+        Point3::new(1.5, 1.5, 0.5),
+        Srgba::new(0.2, 1.0, 0.2, 1.0),
+    );
+    lines.add_line(
+        Point3::new(-3.0, -1.5, 0.0),
+        Point3::new(3.0, 2.0, 0.0),
+        Srgba::new(0.2, 0.4, 1.0, 1.0),
+    );
+    world
+        .create_entity()
+        .with(lines)
+        .with(Transform::default())
+        .build();
+}
+
+/// Adds the systems rendering a `GoldenScene` offscreen to the dispatcher, read back with the
+/// `FrameCapture` resource.
+///
+/// This test bundle requires the user to also add the `TransformBundle` to the dispatcher. See
+/// `render_golden_scene` to render a scene and read the frame back at once.
+///
+/// This is only meant for testing. You need to enable the `test-support` flag to use this.
+#[derive(Debug, new)]
+pub struct RenderGoldenTestBundle<B> {
+    scene: GoldenScene,
+    width: u32,
+    height: u32,
+    #[new(default)]
+    backend: PhantomData<B>,
+}
+
+impl<'a, 'b, B> SystemBundle<'a, 'b> for RenderGoldenTestBundle<B>
+where
+    B: Backend,
+{
+    fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(
+            Processor::<SpriteSheet>::new(),
+            "sprite_sheet_processor",
+            &[],
+        );
+        builder.add(
+            SpriteVisibilitySortingSystem::new(),
+            "sprite_visibility_system",
+            &["transform_system"],
+        );
+
+        builder.add_thread_local(
+            RenderingSystem::<B, _>::new(GoldenRenderGraph::<B>::new(
+                self.scene,
+                self.width,
+                self.height,
+            ))
+            .with_deterministic(),
+        );
+
+        Ok(())
+    }
+}
+
+/// Render `scene` offscreen at `width` x `height`, and read the frame back once its assets are
+/// loaded. The frame is deterministic on a given GPU and driver, and compared with a reference
+/// recorded on the same machine by `golden::check_golden`.
+pub fn render_golden_scene<B: Backend>(
+    scene: GoldenScene,
+    width: u32,
+    height: u32,
+) -> Result<image::RgbaImage, GoldenError> {
+    render_offscreen(
+        RenderGoldenTestBundle::<B>::new(scene, width, height),
+        1,
+        |world, progress| scene.create(world, width, height, progress),
+    )
+}
+
+/// Render the sprites created by `setup` offscreen at `width` x `height` for `frames` frames,
+/// and read the last one back.
+///
+/// Frames are rendered until the assets tracked by the `ProgressCounter` of `setup` are loaded,
+/// so the frame read back may come later. The `World` has the resources of the dispatcher of a
+/// `RenderHeadlessTestBundle` and the `TransformBundle`, and its `RenderingSystem` is set up
+/// before `setup` is called.
+///
+/// The empty backend renders nothing, so with it the frames are only dispatched and the frame
+/// returned is a stub, made of transparent black pixels, whose `is_stub` is set. Tests asserting
+/// colors skip their checks on stubs, and run everywhere.
+pub fn capture_frame<B, F>(
+    width: u32,
+    height: u32,
+    frames: u32,
+    setup: F,
+) -> Result<CapturedFrame, Error>
+where
+    B: Backend,
+    F: FnOnce(&mut World, &mut ProgressCounter),
+{
+    if !B::variant().renders() {
+        let (mut world, mut dispatcher) =
+            headless_world(RenderEmptyBundle::<B>::new()).map_err(Error::new)?;
+        let mut progress = ProgressCounter::new();
+        setup(&mut world, &mut progress);
+        for _ in 0..frames {
+            dispatcher.dispatch(&world.res);
+            world.maintain();
+        }
+        return Ok(CapturedFrame::stub(width, height));
+    }
+
+    render_offscreen(
+        RenderHeadlessTestBundle::<B>::new(width, height),
+        frames,
+        setup,
+    )
+    .map(CapturedFrame::from_rgba)
+    .map_err(Error::new)
+}
+
+/// Render `scene` offscreen at `width` x `height` like `render_golden_scene`, and read back
+/// `captures` frames, to test what changes from one frame to the next.
+///
+/// `setup` is called after the scene is created, and its assets are tracked with those of the
+/// scene. The first frame is read back once they are loaded. After each frame read back but the
+/// last, `update` is called with the `World` and the value returned by `setup`, and the frame
+/// rendered right after it is read back next.
+///
+/// The empty backend renders nothing, so with it every frame returned is a stub, like with
+/// `capture_frame`.
+pub fn capture_scene_frames<B, T, F, U>(
+    scene: GoldenScene,
+    width: u32,
+    height: u32,
+    captures: usize,
+    setup: F,
+    mut update: U,
+) -> Result<Vec<CapturedFrame>, Error>
+where
+    B: Backend,
+    F: FnOnce(&mut World, &mut ProgressCounter) -> T,
+    U: FnMut(&mut World, &mut T),
+{
+    let setup_scene = |world: &mut World, progress: &mut ProgressCounter| {
+        scene.create(world, width, height, progress);
+        setup(world, progress)
+    };
+    if !B::variant().renders() {
+        let (mut world, mut dispatcher) =
+            headless_world(RenderEmptyBundle::<B>::new()).map_err(Error::new)?;
+        let mut progress = ProgressCounter::new();
+        let mut state = setup_scene(&mut world, &mut progress);
+        let mut frames = Vec::with_capacity(captures);
+        for _ in 0..GOLDEN_MAX_FRAMES {
+            dispatcher.dispatch(&world.res);
+            world.maintain();
+            if progress.is_complete() {
+                frames.push(CapturedFrame::stub(width, height));
+                if frames.len() >= captures {
+                    return Ok(frames);
+                }
+                update(&mut world, &mut state);
+            }
+        }
+        return Err(Error::new(GoldenError::Render(format!(
+            "the assets of the scene weren't loaded after {} frames",
+            GOLDEN_MAX_FRAMES
+        ))));
+    }
+
+    render_offscreen_frames(
+        RenderGoldenTestBundle::<B>::new(scene, width, height),
+        1,
+        captures,
+        setup_scene,
+        update,
+    )
+    .map(|images| images.into_iter().map(CapturedFrame::from_rgba).collect())
+    .map_err(Error::new)
+}
+
+/// A `World` with a `Loader` and a dispatcher of the `TransformBundle` and `bundle`, set up.
+fn headless_world<T>(bundle: T) -> Result<(World, Dispatcher<'static, 'static>), GoldenError>
+where
+    T: SystemBundle<'static, 'static>,
+{
+    let render_error = |e: &dyn std::fmt::Display| GoldenError::Render(e.to_string());
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .map_err(|e| render_error(&e))?;
+    let mut world = World::new();
+    world.add_resource(Loader::new(".", Arc::new(pool)));
+
+    let mut builder = DispatcherBuilder::new();
+    TransformBundle::new()
+        .build(&mut builder)
+        .map_err(|e| render_error(&e))?;
+    bundle.build(&mut builder).map_err(|e| render_error(&e))?;
+    let mut dispatcher = builder.build();
+    dispatcher.setup(&mut world.res);
+    Ok((world, dispatcher))
+}
+
+/// Render the frames of the dispatcher of `bundle`, and read back the first one from the
+/// `frames`th on rendered once the assets tracked by `setup` are loaded.
+fn render_offscreen<T, F>(bundle: T, frames: u32, setup: F) -> Result<image::RgbaImage, GoldenError>
+where
+    T: SystemBundle<'static, 'static>,
+    F: FnOnce(&mut World, &mut ProgressCounter),
+{
+    render_offscreen_frames(bundle, frames, 1, setup, |_, _| {}).map(|mut images| images.remove(0))
+}
+
+/// Render the frames of the dispatcher of `bundle` like `render_offscreen`, and read back
+/// `captures` frames. After each frame read back but the last, `update` is called with the value
+/// returned by `setup`, and the next frame rendered is read back.
+fn render_offscreen_frames<T, S, F, U>(
+    bundle: T,
+    frames: u32,
+    captures: usize,
+    setup: F,
+    mut update: U,
+) -> Result<Vec<image::RgbaImage>, GoldenError>
+where
+    T: SystemBundle<'static, 'static>,
+    F: FnOnce(&mut World, &mut ProgressCounter) -> S,
+    U: FnMut(&mut World, &mut S),
+{
+    let (mut world, mut dispatcher) = headless_world(bundle)?;
+    let mut progress = ProgressCounter::new();
+    let mut state = setup(&mut world, &mut progress);
+
+    // Frames are captured once the assets are loaded, as unloaded ones aren't drawn.
+    let mut images = Vec::with_capacity(captures);
+    let mut max_frames = frames.saturating_add(GOLDEN_MAX_FRAMES);
+    let mut requested = false;
+    let mut frame = 0;
+    while frame < max_frames {
+        // The capture is requested before the frame read back is rendered.
+        if !requested && (!images.is_empty() || frame + 1 >= frames && progress.is_complete()) {
+            world.write_resource::<FrameCapture>().request();
+            requested = true;
+        }
+
+        dispatcher.dispatch(&world.res);
+        world.maintain();
+        frame += 1;
+
+        let captured = world.write_resource::<FrameCapture>().take();
+        if let Some(captured) = captured {
+            images.push(captured.to_rgba().ok_or_else(|| {
+                GoldenError::Render(format!("unsupported capture format {:?}", captured.format))
+            })?);
+            if images.len() >= captures {
+                return Ok(images);
+            }
+            update(&mut world, &mut state);
+            requested = false;
+            max_frames = frame.saturating_add(GOLDEN_MAX_FRAMES);
+        }
+        if progress.num_failed() > 0 {
+            return Err(GoldenError::Render(format!(
+                "failed to load the assets of the scene: {:?}",
+                progress.errors()
+            )));
+        }
+    }
+    Err(GoldenError::Render(format!(
+        "no frame was captured after {} frames",
+        max_frames
+    )))
+}
+
+/// Render graph that draws a `GoldenScene` with its pass to an offscreen color image, read back
+/// by a `CaptureDesc` node.
+#[derive(Debug, new)]
+pub struct GoldenRenderGraph<B> {
+    scene: GoldenScene,
+    width: u32,
+    height: u32,
+    #[new(default)]
+    backend: PhantomData<B>,
+}
+
+impl<B> GraphCreator<B> for GoldenRenderGraph<B>
+where
+    B: Backend,
+{
+    fn rebuild(&mut self, _res: &Resources) -> bool {
+        false
+    }
+
+    fn builder(
+        &mut self,
+        _factory: &mut Factory<B>,
+        _res: &Resources,
+    ) -> GraphBuilder<B, Resources> {
+        let kind = Kind::D2(self.width, self.height, 1, 1);
+
+        let mut graph_builder = GraphBuilder::new();
+        let colour = graph_builder.create_image(
+            kind,
+            1,
+            Format::Rgba8Srgb,
+            Some(ClearValue::Color([0.1, 0.1, 0.1, 1.].into())),
+        );
+
+        // Depth stencil must be 1. for the background to be drawn.
+        let depth = graph_builder.create_image(
+            kind,
+            1,
+            Format::D32Sfloat,
+            Some(ClearValue::DepthStencil(ClearDepthStencil(1., 0))),
+        );
+
+        let subpass = SubpassBuilder::new();
+        let subpass = match self.scene {
+            GoldenScene::Flat2D => subpass.with_group(DrawFlat2DDesc::new().builder()),
+            GoldenScene::Flat => subpass.with_group(DrawFlatDesc::<B>::new().builder()),
+            GoldenScene::Shaded => subpass.with_group(DrawShadedDesc::<B>::new().builder()),
+            GoldenScene::Pbr => subpass.with_group(DrawPbrDesc::<B>::new().builder()),
+            GoldenScene::DebugLines => subpass.with_group(DrawDebugLinesDesc::new().builder()),
+        };
+        let pass = graph_builder.add_node(
+            subpass
+                .with_color(colour)
+                .with_depth_stencil(depth)
+                .into_pass(),
+        );
+
+        let _capture = graph_builder.add_node(
+            CaptureDesc::default()
+                .builder()
+                .with_image(colour)
+                .with_dependency(pass),
+        );
+
+        graph_builder
+    }
+}
@@ -1,8 +1,12 @@
 //! `amethyst` rendering ecs resources
 //!
 
+use crate::pod::IntoPod;
 use amethyst_assets::PrefabData;
-use amethyst_core::ecs::{Component, DenseVecStorage, Entity, Write};
+use amethyst_core::{
+    ecs::{Component, DenseVecStorage, Entity, FlaggedStorage, Write},
+    math::Vector3,
+};
 use amethyst_error::Error;
 
 /// The ambient color of a scene
@@ -35,13 +39,91 @@ impl<'a> PrefabData<'a> for AmbientColor {
 #[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
 pub struct Tint(#[serde(with = "crate::serde_shim::srgba")] pub palette::Srgba);
 
+impl Tint {
+    /// Returns `tint` with its alpha multiplied by `fade`, starting from white without a tint.
+    pub(crate) fn faded(tint: Option<&Tint>, fade: f32) -> Option<Tint> {
+        if fade >= 1.0 {
+            return tint.cloned();
+        }
+        let mut color = tint.map_or(palette::Srgba::new(1.0, 1.0, 1.0, 1.0), |t| t.0);
+        color.alpha *= fade;
+        Some(Tint(color))
+    }
+}
+
 impl Component for Tint {
-    type Storage = DenseVecStorage<Self>;
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
 }
 
+/// The linear components of the tint, as it's uploaded.
 impl Into<[f32; 4]> for Tint {
     fn into(self) -> [f32; 4] {
-        let (r, g, b, a) = self.0.into_components();
-        [r, g, b, a]
+        self.0.into_pod()
+    }
+}
+
+/// A tint interpolated between two colors along a local-space axis of a mesh, multiplying its
+/// `Tint`. The mesh passes draw it from the vertex positions, like for a fake top light or
+/// ambient occlusion on flat shaded meshes, without vertex colors.
+///
+/// Vertices before `start` along the axis take `start_color`, the ones after `end` take
+/// `end_color`. Skinned meshes drawn by the GPU ignore it.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct TintGradient3D {
+    /// Color at `start`
+    #[serde(with = "crate::serde_shim::srgba")]
+    pub start_color: palette::Srgba,
+    /// Color at `end`
+    #[serde(with = "crate::serde_shim::srgba")]
+    pub end_color: palette::Srgba,
+    /// Local-space direction of the gradient
+    pub axis: Vector3<f32>,
+    /// Offset along the axis where the gradient starts
+    pub start: f32,
+    /// Offset along the axis where the gradient ends
+    pub end: f32,
+}
+
+impl Default for TintGradient3D {
+    /// A white gradient from -1 to 1 along the Y axis, as the bounds of the default shapes.
+    fn default() -> Self {
+        Self::vertical(
+            palette::Srgba::new(1.0, 1.0, 1.0, 1.0),
+            palette::Srgba::new(1.0, 1.0, 1.0, 1.0),
+        )
     }
 }
+
+impl TintGradient3D {
+    /// A gradient from `bottom` to `top` along the Y axis, from -1 to 1.
+    pub fn vertical(bottom: palette::Srgba, top: palette::Srgba) -> Self {
+        Self {
+            start_color: bottom,
+            end_color: top,
+            axis: Vector3::y(),
+            start: -1.0,
+            end: 1.0,
+        }
+    }
+
+    /// The axis over the length of the gradient and the offset of its start, so the gradient
+    /// of a local position is `dot(position, axis) + offset`, from 0 to 1.
+    pub(crate) fn axis_offset(&self) -> [f32; 4] {
+        let length = self.end - self.start;
+        let length = if length.abs() < std::f32::EPSILON {
+            std::f32::EPSILON
+        } else {
+            length
+        };
+        let axis = self
+            .axis
+            .try_normalize(std::f32::EPSILON)
+            .unwrap_or_else(Vector3::y)
+            / length;
+        [axis.x, axis.y, axis.z, -self.start / length]
+    }
+}
+
+impl Component for TintGradient3D {
+    type Storage = DenseVecStorage<Self>;
+}
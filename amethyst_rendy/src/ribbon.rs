@@ -0,0 +1,832 @@
+//! Ribbon meshes following a polyline, for trails, ropes and path visualizations.
+use crate::{
+    camera::{ActiveCamera, Camera},
+    dynamic_mesh::{DynamicMeshes, Pending},
+    mesh_util::{index_type_for, MeshIndices, ProceduralMesh, VerticesMut},
+    types::Mesh,
+};
+use amethyst_assets::{AssetStorage, Handle, Loader};
+use amethyst_core::{
+    ecs::prelude::{
+        Component, DenseVecStorage, Entities, Join, Read, ReadExpect, ReadStorage, System, Write,
+        WriteStorage,
+    },
+    math::{convert, Matrix4, Point3, Vector3},
+    Transform,
+};
+use rendy::hal::IndexType;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Default maximum length of a miter, as a multiple of the half width of the ribbon.
+pub const DEFAULT_MITER_LIMIT: f32 = 4.0;
+
+const EPSILON: f32 = 1e-12;
+
+/// Orientation of a ribbon generated by `ribbon_from_points`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RibbonMode {
+    /// The ribbon is turned around its polyline to face a viewer at the given position.
+    FacePoint(Point3<f32>),
+    /// The ribbon lies flat, facing along the given axis, e.g. `Vector3::y()` for a path drawn on
+    /// the ground.
+    Axis(Vector3<f32>),
+}
+
+/// A point of a `Ribbon`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RibbonPoint {
+    /// Position of the point.
+    pub position: Point3<f32>,
+    /// Width of the ribbon at this point.
+    pub width: f32,
+    /// Linear RGBA color of the ribbon at this point.
+    pub color: [f32; 4],
+}
+
+impl RibbonPoint {
+    /// Create a white ribbon point.
+    pub fn new(position: Point3<f32>, width: f32) -> Self {
+        RibbonPoint {
+            position,
+            width,
+            color: [1.0; 4],
+        }
+    }
+
+    /// Set the color of the point.
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+}
+
+/// Reusable vertex buffers of a ribbon, filled by `RibbonGeometry::fill`.
+///
+/// Two vertices are generated per point, the left edge at even and the right edge at odd
+/// indices. `u` runs from 0 at the first point to 1 at the last point along the length of the
+/// polyline, `v` from 0 on the left to 1 on the right edge.
+#[derive(Debug, Clone, Default)]
+pub struct RibbonGeometry {
+    positions: Vec<[f32; 3]>,
+    normals: Vec<[f32; 3]>,
+    tex_coords: Vec<[f32; 2]>,
+    tangents: Vec<[f32; 4]>,
+    colors: Vec<[f32; 4]>,
+    indices: Vec<u32>,
+}
+
+impl RibbonGeometry {
+    /// Regenerate the geometry for `points`, reusing the allocated buffers.
+    ///
+    /// The geometry always has `capacity` points worth of vertices and indices, so that it
+    /// keeps the same vertex layout while points are added or removed. Points beyond
+    /// `points.len()` collapse onto the last point and produce degenerate triangles. Points
+    /// beyond `capacity` are ignored.
+    ///
+    /// Corners are joined with miters, which are clamped to `miter_limit` times the half width
+    /// to avoid spikes at sharp corners.
+    pub fn fill(
+        &mut self,
+        points: &[RibbonPoint],
+        mode: RibbonMode,
+        miter_limit: f32,
+        capacity: usize,
+    ) {
+        let points = &points[..points.len().min(capacity)];
+        self.positions.clear();
+        self.normals.clear();
+        self.tex_coords.clear();
+        self.tangents.clear();
+        self.colors.clear();
+
+        let total_length: f32 = points
+            .windows(2)
+            .map(|w| (w[1].position - w[0].position).norm())
+            .sum();
+        let mut length = 0.0;
+
+        for (i, point) in points.iter().enumerate() {
+            if i > 0 {
+                length += (point.position - points[i - 1].position).norm();
+            }
+            let frame = PointFrame::new(points, i, mode, miter_limit);
+            let u = if total_length > EPSILON {
+                length / total_length
+            } else {
+                0.0
+            };
+            for (position, v) in frame.edges(point).iter().cloned() {
+                self.positions.push(position);
+                self.normals.push(frame.normal);
+                self.tex_coords.push([u, v]);
+                self.tangents.push(frame.tangent);
+                self.colors.push(point.color);
+            }
+        }
+
+        let vertex_count = capacity * 2;
+        pad(&mut self.positions, vertex_count);
+        pad(&mut self.normals, vertex_count);
+        pad(&mut self.tex_coords, vertex_count);
+        pad(&mut self.tangents, vertex_count);
+        pad(&mut self.colors, vertex_count);
+
+        let index_count = capacity.saturating_sub(1) * 6;
+        if self.indices.len() != index_count {
+            self.indices.clear();
+            for segment in 0..capacity.saturating_sub(1) as u32 {
+                let (a, b, c, d) = (
+                    segment * 2,
+                    segment * 2 + 1,
+                    segment * 2 + 2,
+                    segment * 2 + 3,
+                );
+                self.indices.extend_from_slice(&[a, c, b, b, c, d]);
+            }
+        }
+    }
+
+    /// Number of vertices of the geometry.
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Create a `ProceduralMesh` with positions, normals, texture coordinates, tangents and
+    /// colors of the geometry.
+    pub fn to_mesh(&self) -> ProceduralMesh {
+        ProceduralMesh::new()
+            .with_positions(self.positions.clone())
+            .with_normals(self.normals.clone())
+            .with_tex_coords(self.tex_coords.clone())
+            .with_tangents(self.tangents.clone())
+            .with_colors(self.colors.clone())
+            .with_indices(self.indices.clone())
+    }
+}
+
+/// Edge offset, normal and tangent of the ribbon at a point.
+struct PointFrame {
+    offset: Vector3<f32>,
+    normal: [f32; 3],
+    tangent: [f32; 4],
+}
+
+impl PointFrame {
+    fn new(points: &[RibbonPoint], i: usize, mode: RibbonMode, miter_limit: f32) -> Self {
+        let point = &points[i];
+        let incoming = i
+            .checked_sub(1)
+            .and_then(|prev| direction(&points[prev].position, &point.position));
+        let outgoing = points
+            .get(i + 1)
+            .and_then(|next| direction(&point.position, &next.position));
+        let tangent = match (incoming, outgoing) {
+            (Some(a), Some(b)) => (a + b).try_normalize(EPSILON).unwrap_or(b),
+            (Some(t), None) | (None, Some(t)) => t,
+            (None, None) => Vector3::x(),
+        };
+
+        let normal = match mode {
+            RibbonMode::FacePoint(eye) => (eye - point.position)
+                .try_normalize(EPSILON)
+                .unwrap_or_else(Vector3::z),
+            RibbonMode::Axis(axis) => axis.try_normalize(EPSILON).unwrap_or_else(Vector3::y),
+        };
+        let side = |t: &Vector3<f32>| t.cross(&normal).try_normalize(EPSILON);
+
+        let half_width = point.width * 0.5;
+        let offset = match (
+            incoming.and_then(|t| side(&t)),
+            outgoing.and_then(|t| side(&t)),
+        ) {
+            (Some(a), Some(b)) => match (a + b).try_normalize(EPSILON) {
+                Some(miter) => {
+                    let scale = (1.0 / miter.dot(&b).max(EPSILON)).min(miter_limit);
+                    miter * half_width * scale
+                }
+                // The polyline turns back on itself
+                None => b * half_width,
+            },
+            (Some(s), None) | (None, Some(s)) => s * half_width,
+            (None, None) => side(&tangent).unwrap_or_else(Vector3::y) * half_width,
+        };
+
+        PointFrame {
+            offset,
+            normal: normal.into(),
+            tangent: [tangent.x, tangent.y, tangent.z, 1.0],
+        }
+    }
+
+    /// Positions and `v` texture coordinates of the left and right edge vertices.
+    fn edges(&self, point: &RibbonPoint) -> [([f32; 3], f32); 2] {
+        [
+            ((point.position + self.offset).coords.into(), 0.0),
+            ((point.position - self.offset).coords.into(), 1.0),
+        ]
+    }
+}
+
+fn pad<T: Copy + Default>(buffer: &mut Vec<T>, len: usize) {
+    let last = buffer.last().cloned().unwrap_or_default();
+    buffer.resize(len, last);
+}
+
+fn direction(from: &Point3<f32>, to: &Point3<f32>) -> Option<Vector3<f32>> {
+    (to - from).try_normalize(EPSILON)
+}
+
+/// Generate a ribbon of constant `width` following `points`.
+///
+/// See `RibbonGeometry` for the generated attributes, and `Ribbon` for a component that
+/// updates its ribbon mesh every frame.
+pub fn ribbon_from_points(points: &[Point3<f32>], width: f32, mode: RibbonMode) -> ProceduralMesh {
+    let points = points
+        .iter()
+        .map(|p| RibbonPoint::new(*p, width))
+        .collect::<Vec<_>>();
+    let mut geometry = RibbonGeometry::default();
+    geometry.fill(&points, mode, DEFAULT_MITER_LIMIT, points.len());
+    geometry.to_mesh()
+}
+
+/// Orientation of a `Ribbon` component.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RibbonFacing {
+    /// Face the active camera.
+    Camera,
+    /// Face along a fixed axis in the local space of the entity.
+    Axis(Vector3<f32>),
+}
+
+/// Component generating a ribbon mesh from a list of points in the local space of the entity,
+/// see `RibbonSystem`.
+///
+/// The mesh has a fixed ring of `capacity` slots of two vertices each, so that it keeps the
+/// same vertex layout and is written in place through `DynamicMeshes` while points are pushed
+/// and removed. Pushing a point to a full ribbon drops the oldest one and reuses its slot,
+/// which makes a trail out of the ribbon.
+///
+/// By default `u` runs from 0 to 1 along the whole ribbon, so every change rewrites all
+/// vertices. With `uv_length`, `u` grows by 1 every `uv_length` units along the trail instead,
+/// and pushing points only writes the slots of the points whose vertices changed.
+///
+/// The entity also needs a `Material` and a `Transform` to be drawn. Point colors are stored in
+/// the `Color` vertex attribute, which is available to passes that request it.
+#[derive(Debug, Clone)]
+pub struct Ribbon {
+    points: Vec<RibbonPoint>,
+    capacity: usize,
+    /// Orientation of the ribbon.
+    pub facing: RibbonFacing,
+    /// Maximum length of a miter, as a multiple of the half width.
+    pub miter_limit: f32,
+    /// Length of the ribbon covered by the texture once, see `Ribbon`.
+    pub uv_length: Option<f32>,
+    /// Slot of the first point in the ring.
+    head: usize,
+    /// Points pushed since the last write.
+    pushed: usize,
+    /// Whether points were dropped since the last write.
+    dropped: bool,
+    /// Whether all slots have to be written again.
+    rewrite: bool,
+    /// Distance along the trail of the first and of the last point.
+    start_distance: f32,
+    end_distance: f32,
+    /// Mode, miter limit and uv length of the last write.
+    written: Option<(RibbonMode, f32, Option<f32>)>,
+}
+
+impl Component for Ribbon {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl Ribbon {
+    /// Create an empty camera facing ribbon holding up to `capacity` points.
+    pub fn new(capacity: usize) -> Self {
+        Ribbon {
+            points: Vec::with_capacity(capacity + 1),
+            capacity,
+            facing: RibbonFacing::Camera,
+            miter_limit: DEFAULT_MITER_LIMIT,
+            uv_length: None,
+            head: 0,
+            pushed: 0,
+            dropped: false,
+            rewrite: true,
+            start_distance: 0.0,
+            end_distance: 0.0,
+            written: None,
+        }
+    }
+
+    /// Set the orientation of the ribbon.
+    pub fn with_facing(mut self, facing: RibbonFacing) -> Self {
+        self.facing = facing;
+        self
+    }
+
+    /// Repeat the texture every `length` units along the ribbon.
+    pub fn with_uv_length(mut self, length: f32) -> Self {
+        self.uv_length = Some(length);
+        self
+    }
+
+    /// Maximum number of points of the ribbon.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Points of the ribbon, from oldest to newest.
+    pub fn points(&self) -> &[RibbonPoint] {
+        &self.points
+    }
+
+    /// Mutable points of the ribbon. Points beyond `capacity` are ignored.
+    ///
+    /// The whole ribbon is written again after changes through this.
+    pub fn points_mut(&mut self) -> &mut Vec<RibbonPoint> {
+        self.rewrite = true;
+        &mut self.points
+    }
+
+    /// Add a point to the end of the ribbon, dropping the oldest point if the ribbon is full.
+    pub fn push(&mut self, point: RibbonPoint) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(last) = self.points.last() {
+            self.end_distance += (point.position - last.position).norm();
+        } else {
+            self.start_distance = self.end_distance;
+        }
+        self.points.push(point);
+        if self.points.len() > self.capacity {
+            let excess = self.points.len() - self.capacity;
+            self.start_distance += self.points[..=excess]
+                .windows(2)
+                .map(|w| (w[1].position - w[0].position).norm())
+                .sum::<f32>();
+            self.points.drain(..excess);
+            self.head = (self.head + excess) % self.capacity;
+            self.dropped = true;
+        }
+        self.pushed += 1;
+    }
+
+    /// Remove all points.
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.start_distance = 0.0;
+        self.end_distance = 0.0;
+        self.rewrite = true;
+    }
+
+    fn needs_write(&self, mode: RibbonMode) -> bool {
+        self.rewrite
+            || self.pushed > 0
+            || self.written != Some((mode, self.miter_limit, self.uv_length))
+    }
+
+    /// Write the changed slots into `mesh`, adding them to `pending`.
+    fn write(&mut self, mesh: &mut ProceduralMesh, mode: RibbonMode, pending: &mut Pending) {
+        let capacity = self.capacity;
+        if mesh.vertex_count() != capacity * 2
+            || mesh.indices().map(MeshIndices::len) != Some(capacity * 6)
+        {
+            *mesh = ring_mesh(capacity);
+            *pending = Pending::All;
+            self.rewrite = true;
+        }
+        let uv_length = self.uv_length.filter(|length| *length > EPSILON);
+        let partial = !self.rewrite
+            && uv_length.is_some()
+            && self.written == Some((mode, self.miter_limit, self.uv_length));
+
+        let len = self.points.len().min(capacity);
+        let mut ring = Ring {
+            vertices: mesh.vertices_mut(),
+            points: &self.points[..len],
+            head: self.head,
+            capacity,
+            mode,
+            miter_limit: self.miter_limit,
+        };
+
+        match uv_length {
+            Some(uv_length) if partial => {
+                // Only the new points and the ends they changed move.
+                let first = len - (self.pushed + 1).min(len);
+                let mut distance = self.end_distance;
+                for i in (first..len).rev() {
+                    if i + 1 < len {
+                        distance -= (ring.points[i + 1].position - ring.points[i].position).norm();
+                    }
+                    let slot = ring.write_point(i, distance / uv_length);
+                    add_slot(pending, slot);
+                }
+                if self.dropped && first > 0 {
+                    let slot = ring.write_point(0, self.start_distance / uv_length);
+                    add_slot(pending, slot);
+                }
+            }
+            _ => {
+                ring.head = 0;
+                let total_length: f32 = ring
+                    .points
+                    .windows(2)
+                    .map(|w| (w[1].position - w[0].position).norm())
+                    .sum();
+                let mut length = 0.0;
+                for i in 0..len {
+                    if i > 0 {
+                        length += (ring.points[i].position - ring.points[i - 1].position).norm();
+                    }
+                    let u = match uv_length {
+                        Some(uv_length) => (self.start_distance + length) / uv_length,
+                        None if total_length > EPSILON => length / total_length,
+                        None => 0.0,
+                    };
+                    ring.write_point(i, u);
+                }
+                for slot in len..capacity {
+                    ring.collapse(slot, len.checked_sub(1));
+                }
+                self.head = 0;
+                self.end_distance = self.start_distance + total_length;
+                pending.ranges(0..capacity * 2, 0..capacity * 6);
+            }
+        }
+
+        self.pushed = 0;
+        self.dropped = false;
+        self.rewrite = false;
+        self.written = Some((mode, self.miter_limit, self.uv_length));
+    }
+}
+
+fn add_slot(pending: &mut Pending, slot: usize) {
+    pending.ranges(slot * 2..slot * 2 + 2, slot * 6..slot * 6 + 6);
+}
+
+/// An empty ring of `capacity` slots, with the smallest index type that fits.
+fn ring_mesh(capacity: usize) -> ProceduralMesh {
+    let vertex_count = capacity * 2;
+    let indices = match index_type_for(vertex_count) {
+        IndexType::U16 => MeshIndices::U16(vec![0; capacity * 6]),
+        IndexType::U32 => MeshIndices::U32(vec![0; capacity * 6]),
+    };
+    ProceduralMesh::new()
+        .with_positions(vec![[0.0; 3]; vertex_count])
+        .with_normals(vec![[0.0; 3]; vertex_count])
+        .with_tex_coords(vec![[0.0; 2]; vertex_count])
+        .with_tangents(vec![[0.0; 4]; vertex_count])
+        .with_colors(vec![[0.0; 4]; vertex_count])
+        .with_indices(indices)
+}
+
+/// Writer of the slots of a ribbon mesh.
+///
+/// Slot `s` holds vertices `2 * s` and `2 * s + 1`, and the six indices at `6 * s` of the
+/// segment joining it to the next slot. The segment of the newest point is degenerate.
+struct Ring<'a> {
+    vertices: VerticesMut<'a>,
+    points: &'a [RibbonPoint],
+    head: usize,
+    capacity: usize,
+    mode: RibbonMode,
+    miter_limit: f32,
+}
+
+impl<'a> Ring<'a> {
+    /// Write the vertices and the segment of point `i`, returning its slot.
+    fn write_point(&mut self, i: usize, u: f32) -> usize {
+        let slot = (self.head + i) % self.capacity;
+        let point = &self.points[i];
+        let frame = PointFrame::new(self.points, i, self.mode, self.miter_limit);
+        let vertices = &mut self.vertices;
+        for (edge, (position, v)) in frame.edges(point).iter().cloned().enumerate() {
+            let vertex = slot * 2 + edge;
+            vertices.positions[vertex] = position;
+            if let Some(normals) = vertices.normals.as_mut() {
+                normals[vertex] = frame.normal;
+            }
+            if let Some(tex_coords) = vertices.tex_coords.as_mut() {
+                tex_coords[vertex] = [u, v];
+            }
+            if let Some(tangents) = vertices.tangents.as_mut() {
+                tangents[vertex] = frame.tangent;
+            }
+            if let Some(colors) = vertices.colors.as_mut() {
+                colors[vertex] = point.color;
+            }
+        }
+
+        let next = if i + 1 < self.points.len() {
+            Some((slot + 1) % self.capacity)
+        } else {
+            None
+        };
+        self.segment(slot, next);
+        slot
+    }
+
+    /// Collapse an unused slot onto the slot of point `onto`.
+    fn collapse(&mut self, slot: usize, onto: Option<usize>) {
+        let from = onto.map_or(slot, |i| (self.head + i) % self.capacity);
+        let vertices = &mut self.vertices;
+        for edge in 0..2 {
+            let (vertex, from) = (slot * 2 + edge, from * 2 + edge);
+            vertices.positions[vertex] = vertices.positions[from];
+            if let Some(normals) = vertices.normals.as_mut() {
+                normals[vertex] = normals[from];
+            }
+            if let Some(tex_coords) = vertices.tex_coords.as_mut() {
+                tex_coords[vertex] = tex_coords[from];
+            }
+            if let Some(tangents) = vertices.tangents.as_mut() {
+                tangents[vertex] = tangents[from];
+            }
+            if let Some(colors) = vertices.colors.as_mut() {
+                colors[vertex] = colors[from];
+            }
+        }
+        self.segment(slot, None);
+    }
+
+    fn segment(&mut self, slot: usize, next: Option<usize>) {
+        let (a, b) = (slot as u32 * 2, slot as u32 * 2 + 1);
+        let segment = match next {
+            Some(next) => {
+                let (c, d) = (next as u32 * 2, next as u32 * 2 + 1);
+                [a, c, b, b, c, d]
+            }
+            None => [a; 6],
+        };
+        if let Some(indices) = self.vertices.indices.as_mut() {
+            for (k, index) in segment.iter().enumerate() {
+                indices.set(slot * 6 + k, *index);
+            }
+        }
+    }
+}
+
+/// Writes the meshes of `Ribbon` components whose points changed, or which face a moving
+/// camera, and keeps them in the entity's `Handle<Mesh>`.
+///
+/// A mesh is created and registered with `DynamicMeshes` the first time a ribbon is updated.
+/// Later updates write the changed slots in place through `DynamicMeshes`, and only those are
+/// uploaded into the persistent buffers of the mesh, so they become visible with at most one
+/// frame of delay.
+///
+/// Note that this should run after `Transform` has been updated for the current frame, and
+/// before rendering occurs.
+#[derive(Default, Debug)]
+pub struct RibbonSystem;
+
+impl RibbonSystem {
+    /// Create new ribbon system
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for RibbonSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, ActiveCamera>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, Transform>,
+        WriteStorage<'a, Ribbon>,
+        WriteStorage<'a, Handle<Mesh>>,
+        Write<'a, DynamicMeshes>,
+        ReadExpect<'a, Loader>,
+        Read<'a, AssetStorage<Mesh>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            active,
+            camera,
+            transforms,
+            mut ribbons,
+            mut meshes,
+            mut dynamic,
+            loader,
+            mesh_storage,
+        ): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("ribbon_system");
+
+        let origin = Point3::origin();
+        let mut camera_join = (&camera, &transforms).join();
+        let eye = active
+            .entity
+            .and_then(|a| camera_join.get(a, &entities))
+            .or_else(|| camera_join.next())
+            .map(|(_, t)| convert::<_, Matrix4<f32>>(*t.global_matrix()).transform_point(&origin));
+
+        for (entity, ribbon, transform) in (&entities, &mut ribbons, transforms.maybe()).join() {
+            if ribbon.capacity == 0 {
+                continue;
+            }
+            let mode = match ribbon.facing {
+                RibbonFacing::Axis(axis) => RibbonMode::Axis(axis),
+                RibbonFacing::Camera => {
+                    let inverse = transform
+                        .and_then(|t| convert::<_, Matrix4<f32>>(*t.global_matrix()).try_inverse())
+                        .unwrap_or_else(Matrix4::identity);
+                    match eye {
+                        Some(eye) => RibbonMode::FacePoint(inverse.transform_point(&eye)),
+                        None => continue,
+                    }
+                }
+            };
+            let handle = meshes.get(entity).filter(|h| dynamic.contains(h)).cloned();
+
+            match handle {
+                Some(handle) => {
+                    if !ribbon.needs_write(mode) {
+                        continue;
+                    }
+                    if let Err(e) = dynamic
+                        .write_in_place(&handle, |data, pending| ribbon.write(data, mode, pending))
+                    {
+                        log::error!("Failed to update ribbon mesh: {}", e);
+                    }
+                }
+                None => {
+                    let mut data = ring_mesh(ribbon.capacity);
+                    ribbon.rewrite = true;
+                    ribbon.write(&mut data, mode, &mut Pending::All);
+                    let mesh_data = match data.clone().build() {
+                        Ok(mesh_data) => mesh_data,
+                        Err(e) => {
+                            log::error!("Failed to build ribbon mesh: {}", e);
+                            continue;
+                        }
+                    };
+                    let handle = loader.load_from_data(mesh_data, (), &mesh_storage);
+                    dynamic.register(&handle, data);
+                    if let Err(e) = meshes.insert(entity, handle) {
+                        log::error!("Failed to insert ribbon mesh: {}", e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(mesh: &RibbonGeometry, index: usize) -> Vector3<f32> {
+        Vector3::from(mesh.positions[index])
+    }
+
+    fn fill(points: &[Point3<f32>], capacity: usize) -> RibbonGeometry {
+        let points = points
+            .iter()
+            .map(|p| RibbonPoint::new(*p, 2.0))
+            .collect::<Vec<_>>();
+        let mut geometry = RibbonGeometry::default();
+        geometry.fill(&points, RibbonMode::Axis(Vector3::y()), 4.0, capacity);
+        geometry
+    }
+
+    #[test]
+    fn straight_ribbon() {
+        let geometry = fill(&[Point3::new(0.0, 0.0, 0.0), Point3::new(4.0, 0.0, 0.0)], 2);
+        assert_eq!(geometry.vertex_count(), 4);
+        assert_eq!(geometry.indices.len(), 6);
+        assert_eq!(vertex(&geometry, 0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(vertex(&geometry, 1), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(geometry.tex_coords[3], [1.0, 1.0]);
+
+        // Triangles face along the ribbon normal
+        for tri in geometry.indices.chunks(3) {
+            let (a, b, c) = (
+                vertex(&geometry, tri[0] as usize),
+                vertex(&geometry, tri[1] as usize),
+                vertex(&geometry, tri[2] as usize),
+            );
+            assert!((b - a).cross(&(c - a)).dot(&Vector3::y()) > 0.0);
+        }
+    }
+
+    #[test]
+    fn miter_corners() {
+        let corner = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(4.0, 0.0, 0.0),
+            Point3::new(4.0, 0.0, -4.0),
+        ];
+        let geometry = fill(&corner, 3);
+        // The outer corner of a right angle is pushed out to keep the width constant
+        let outer = vertex(&geometry, 2);
+        assert!((outer - Vector3::new(5.0, 0.0, 1.0)).norm() < 1e-5);
+
+        // A nearly reversed polyline is clamped to the miter limit
+        let spike = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(4.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, 0.1),
+        ];
+        let geometry = fill(&spike, 3);
+        let offset = vertex(&geometry, 2) - Vector3::new(4.0, 0.0, 0.0);
+        assert!(offset.norm() <= 4.0 + 1e-5);
+    }
+
+    #[test]
+    fn capacity_keeps_layout() {
+        let points = [Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 0.0, 0.0)];
+        let geometry = fill(&points, 8);
+        assert_eq!(geometry.vertex_count(), 16);
+        assert_eq!(geometry.indices.len(), 42);
+        assert_eq!(geometry.positions[15], geometry.positions[3]);
+
+        let mut ribbon = Ribbon::new(2);
+        for x in 0..3 {
+            ribbon.push(RibbonPoint::new(Point3::new(x as f32, 0.0, 0.0), 1.0));
+        }
+        assert_eq!(ribbon.points().len(), 2);
+        assert_eq!(ribbon.points()[0].position, Point3::new(1.0, 0.0, 0.0));
+    }
+
+    type Triangle = [([f32; 3], [f32; 2]); 3];
+
+    fn triangles(mesh: &ProceduralMesh) -> Vec<Triangle> {
+        let indices = mesh.indices().unwrap().to_u32();
+        let tex_coords = mesh.tex_coords().unwrap();
+        indices
+            .chunks(3)
+            .filter(|tri| tri[0] != tri[1] && tri[1] != tri[2] && tri[0] != tri[2])
+            .map(|tri| {
+                let vertex = |i: u32| (mesh.positions()[i as usize], tex_coords[i as usize]);
+                [vertex(tri[0]), vertex(tri[1]), vertex(tri[2])]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ring_writes_match_full_writes() {
+        let mode = RibbonMode::Axis(Vector3::y());
+        let mut ribbon = Ribbon::new(4)
+            .with_facing(RibbonFacing::Axis(Vector3::y()))
+            .with_uv_length(2.0);
+        let mut mesh = ring_mesh(4);
+        ribbon.write(&mut mesh, mode, &mut Pending::All);
+
+        // Unit steps around corners keep the distances exact.
+        let path = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 1.0),
+            Point3::new(2.0, 0.0, 1.0),
+            Point3::new(3.0, 0.0, 1.0),
+            Point3::new(3.0, 0.0, 0.0),
+            Point3::new(4.0, 0.0, 0.0),
+        ];
+        for (step, position) in path.iter().enumerate() {
+            ribbon.push(RibbonPoint::new(*position, 0.5));
+            let mut pending = Pending::None;
+            ribbon.write(&mut mesh, mode, &mut pending);
+            if step > 0 {
+                // The new point and the previous newest, and the oldest once points drop.
+                let written = match &pending {
+                    Pending::Ranges { vertices, .. } => {
+                        vertices.iter().map(|r| r.end - r.start).sum::<usize>()
+                    }
+                    other => panic!("Expected ranges, got {:?}", other),
+                };
+                assert_eq!(written, if step >= 4 { 6 } else { 4 });
+            }
+
+            let mut full = ribbon.clone();
+            full.points_mut();
+            let mut full_mesh = ring_mesh(4);
+            full.write(&mut full_mesh, mode, &mut Pending::All);
+
+            let mut partial = triangles(&mesh);
+            let expected = triangles(&full_mesh);
+            assert_eq!(expected.len(), ribbon.points().len().saturating_sub(1) * 2);
+            assert_eq!(partial.len(), expected.len());
+            for triangle in &expected {
+                let found = partial
+                    .iter()
+                    .position(|t| t == triangle)
+                    .expect("Missing triangle");
+                partial.swap_remove(found);
+            }
+        }
+        assert_eq!(ribbon.points()[0].position, Point3::new(2.0, 0.0, 1.0));
+        assert_eq!(mesh.indices().unwrap().index_type(), IndexType::U16);
+    }
+}
@@ -2,9 +2,29 @@
 //! It is required due to buggy default implementation of those types.
 //! See this issue for more details: https://github.com/Ogeon/palette/issues/130
 //! When above issue will be resolved, this can probably be removed.
+//!
+//! It also contains stable formats of the render components saved with scenes, which don't
+//! change with their fields: `camera` as projection parameters rather than a matrix, `light`,
+//! `tint`, `transparent` and `bounding_sphere`. Each value is written with the
+//! `FORMAT_VERSION` it's in, and values of a later version fail to deserialize.
 
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+/// Version of the formats of the component shims, written with every value.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Fail on versions this build can't read.
+fn check_version<E: serde::de::Error>(version: u32) -> Result<(), E> {
+    if version == 0 || version > FORMAT_VERSION {
+        Err(E::custom(format!(
+            "unsupported format version {}, expected 1 to {}",
+            version, FORMAT_VERSION
+        )))
+    } else {
+        Ok(())
+    }
+}
+
 /// Srgb serialization shim.
 /// ```
 /// # use serde::{Serialize, Deserialize};
@@ -59,8 +79,388 @@ pub mod srgba {
     }
 }
 
+/// `Camera` serialization shim, as the parameters of its projection and its layers.
+/// ```
+/// # use serde::{Serialize, Deserialize};
+/// #[derive(Serialize, Deserialize)]
+/// struct MyType(
+///     #[serde(with="amethyst_rendy::serde_shim::camera")]
+///     pub amethyst_rendy::Camera
+/// );
+/// ```
+pub mod camera {
+    use super::*;
+    use crate::{camera::Projection as CameraProjection, layers::RenderLayers};
+
+    #[derive(Serialize, Deserialize)]
+    enum Projection {
+        Orthographic {
+            left: f32,
+            right: f32,
+            bottom: f32,
+            top: f32,
+            znear: f32,
+            zfar: f32,
+        },
+        Perspective {
+            aspect: f32,
+            fovy: f32,
+            znear: f32,
+            zfar: f32,
+        },
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Camera {
+        version: u32,
+        projection: Projection,
+        layers: RenderLayers,
+    }
+
+    /// Serialize Camera type as its projection parameters, with `fovy` in radians
+    pub fn serialize<S: Serializer>(x: &crate::camera::Camera, s: S) -> Result<S::Ok, S::Error> {
+        let projection = match x.projection() {
+            CameraProjection::Orthographic(p) => Projection::Orthographic {
+                left: p.left(),
+                right: p.right(),
+                bottom: p.bottom(),
+                top: p.top(),
+                znear: p.near(),
+                zfar: p.far(),
+            },
+            CameraProjection::Perspective(p) => Projection::Perspective {
+                aspect: p.aspect(),
+                fovy: p.fovy(),
+                znear: p.near(),
+                zfar: p.far(),
+            },
+        };
+        Camera {
+            version: FORMAT_VERSION,
+            projection,
+            layers: x.layers(),
+        }
+        .serialize(s)
+    }
+
+    /// Deserialize Camera type from its projection parameters
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        de: D,
+    ) -> Result<crate::camera::Camera, D::Error> {
+        let t = Camera::deserialize(de)?;
+        check_version(t.version)?;
+        let projection = match t.projection {
+            Projection::Orthographic {
+                left,
+                right,
+                bottom,
+                top,
+                znear,
+                zfar,
+            } => CameraProjection::orthographic(left, right, bottom, top, znear, zfar),
+            Projection::Perspective {
+                aspect,
+                fovy,
+                znear,
+                zfar,
+            } => CameraProjection::perspective(aspect, fovy, znear, zfar),
+        };
+        Ok(crate::camera::Camera::from(projection).with_layers(t.layers))
+    }
+}
+
+/// `Light` serialization shim, of all its variants.
+/// ```
+/// # use serde::{Serialize, Deserialize};
+/// #[derive(Serialize, Deserialize)]
+/// struct MyType(
+///     #[serde(with="amethyst_rendy::serde_shim::light")]
+///     pub amethyst_rendy::light::Light
+/// );
+/// ```
+pub mod light {
+    use super::*;
+    use crate::light::{DirectionalLight, PointLight, SpotLight, SunLight};
+    use amethyst_core::math::Vector3;
+
+    #[derive(Serialize, Deserialize)]
+    enum Source {
+        Area,
+        Directional {
+            #[serde(with = "super::srgb")]
+            color: palette::Srgb,
+            intensity: f32,
+            direction: [f32; 3],
+        },
+        Point {
+            #[serde(with = "super::srgb")]
+            color: palette::Srgb,
+            intensity: f32,
+            radius: f32,
+            smoothness: f32,
+        },
+        Spot {
+            angle: f32,
+            #[serde(with = "super::srgb")]
+            color: palette::Srgb,
+            direction: [f32; 3],
+            intensity: f32,
+            range: f32,
+            smoothness: f32,
+        },
+        Sun {
+            angle: f32,
+            #[serde(with = "super::srgb")]
+            color: palette::Srgb,
+            direction: [f32; 3],
+            intensity: f32,
+        },
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Light {
+        version: u32,
+        light: Source,
+    }
+
+    fn array(v: &Vector3<f32>) -> [f32; 3] {
+        [v.x, v.y, v.z]
+    }
+
+    /// Serialize Light type as its variant with its fields, with directions as three floats
+    pub fn serialize<S: Serializer>(x: &crate::light::Light, s: S) -> Result<S::Ok, S::Error> {
+        use crate::light::Light as L;
+        let light = match x {
+            L::Area => Source::Area,
+            L::Directional(l) => Source::Directional {
+                color: l.color,
+                intensity: l.intensity,
+                direction: array(&l.direction),
+            },
+            L::Point(l) => Source::Point {
+                color: l.color,
+                intensity: l.intensity,
+                radius: l.radius,
+                smoothness: l.smoothness,
+            },
+            L::Spot(l) => Source::Spot {
+                angle: l.angle,
+                color: l.color,
+                direction: array(&l.direction),
+                intensity: l.intensity,
+                range: l.range,
+                smoothness: l.smoothness,
+            },
+            L::Sun(l) => Source::Sun {
+                angle: l.angle,
+                color: l.color,
+                direction: array(&l.direction),
+                intensity: l.intensity,
+            },
+        };
+        Light {
+            version: FORMAT_VERSION,
+            light,
+        }
+        .serialize(s)
+    }
+
+    /// Deserialize Light type from its variant with its fields
+    pub fn deserialize<'de, D: Deserializer<'de>>(de: D) -> Result<crate::light::Light, D::Error> {
+        use crate::light::Light as L;
+        let t = Light::deserialize(de)?;
+        check_version(t.version)?;
+        Ok(match t.light {
+            Source::Area => L::Area,
+            Source::Directional {
+                color,
+                intensity,
+                direction,
+            } => L::Directional(DirectionalLight {
+                color,
+                intensity,
+                direction: direction.into(),
+            }),
+            Source::Point {
+                color,
+                intensity,
+                radius,
+                smoothness,
+            } => L::Point(PointLight {
+                color,
+                intensity,
+                radius,
+                smoothness,
+            }),
+            Source::Spot {
+                angle,
+                color,
+                direction,
+                intensity,
+                range,
+                smoothness,
+            } => L::Spot(SpotLight {
+                angle,
+                color,
+                direction: direction.into(),
+                intensity,
+                range,
+                smoothness,
+            }),
+            Source::Sun {
+                angle,
+                color,
+                direction,
+                intensity,
+            } => L::Sun(SunLight {
+                angle,
+                color,
+                direction: direction.into(),
+                intensity,
+            }),
+        })
+    }
+}
+
+/// `Tint` serialization shim, as four sRGB floats with alpha.
+/// ```
+/// # use serde::{Serialize, Deserialize};
+/// #[derive(Serialize, Deserialize)]
+/// struct MyType(
+///     #[serde(with="amethyst_rendy::serde_shim::tint")]
+///     pub amethyst_rendy::resources::Tint
+/// );
+/// ```
+pub mod tint {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Tint {
+        version: u32,
+        #[serde(with = "super::srgba")]
+        srgba: palette::Srgba,
+    }
+
+    /// Serialize Tint type as its color
+    pub fn serialize<S: Serializer>(x: &crate::resources::Tint, s: S) -> Result<S::Ok, S::Error> {
+        Tint {
+            version: FORMAT_VERSION,
+            srgba: x.0,
+        }
+        .serialize(s)
+    }
+
+    /// Deserialize Tint type from its color
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        de: D,
+    ) -> Result<crate::resources::Tint, D::Error> {
+        let t = Tint::deserialize(de)?;
+        check_version(t.version)?;
+        Ok(crate::resources::Tint(t.srgba))
+    }
+}
+
+/// `Transparent` serialization shim, of the version only.
+/// ```
+/// # use serde::{Serialize, Deserialize};
+/// #[derive(Serialize, Deserialize)]
+/// struct MyType(
+///     #[serde(with="amethyst_rendy::serde_shim::transparent")]
+///     pub amethyst_rendy::Transparent
+/// );
+/// ```
+pub mod transparent {
+    use super::*;
+
+    #[derive(Serialize, Deserialize)]
+    struct Transparent {
+        version: u32,
+    }
+
+    /// Serialize Transparent type as its version
+    pub fn serialize<S: Serializer>(
+        _: &crate::transparent::Transparent,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        Transparent {
+            version: FORMAT_VERSION,
+        }
+        .serialize(s)
+    }
+
+    /// Deserialize Transparent type from its version
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        de: D,
+    ) -> Result<crate::transparent::Transparent, D::Error> {
+        let t = Transparent::deserialize(de)?;
+        check_version(t.version)?;
+        Ok(crate::transparent::Transparent)
+    }
+}
+
+/// `BoundingSphere` serialization shim, as a center of three floats and a radius.
+/// ```
+/// # use serde::{Serialize, Deserialize};
+/// #[derive(Serialize, Deserialize)]
+/// struct MyType(
+///     #[serde(with="amethyst_rendy::serde_shim::bounding_sphere")]
+///     pub amethyst_rendy::visibility::BoundingSphere
+/// );
+/// ```
+pub mod bounding_sphere {
+    use super::*;
+    use amethyst_core::math::Point3;
+
+    #[derive(Serialize, Deserialize)]
+    struct BoundingSphere {
+        version: u32,
+        center: [f32; 3],
+        radius: f32,
+    }
+
+    /// Serialize BoundingSphere type as floats, losing precision with the `float64` feature
+    pub fn serialize<S: Serializer>(
+        x: &crate::visibility::BoundingSphere,
+        s: S,
+    ) -> Result<S::Ok, S::Error> {
+        BoundingSphere {
+            version: FORMAT_VERSION,
+            center: [
+                x.center.x.as_f32(),
+                x.center.y.as_f32(),
+                x.center.z.as_f32(),
+            ],
+            radius: x.radius.as_f32(),
+        }
+        .serialize(s)
+    }
+
+    /// Deserialize BoundingSphere type from floats
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        de: D,
+    ) -> Result<crate::visibility::BoundingSphere, D::Error> {
+        let t = BoundingSphere::deserialize(de)?;
+        check_version(t.version)?;
+        let [x, y, z] = t.center;
+        Ok(crate::visibility::BoundingSphere::new(
+            Point3::new(x.into(), y.into(), z.into()),
+            t.radius,
+        ))
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use crate::{
+        camera::Camera,
+        layers::RenderLayers,
+        light::{DirectionalLight, Light, PointLight, SpotLight, SunLight},
+        resources::Tint,
+        transparent::Transparent,
+        visibility::BoundingSphere,
+    };
+    use amethyst_core::math::Point3;
+    use approx::assert_relative_eq;
     use serde::{Deserialize, Serialize};
 
     #[derive(Serialize, Deserialize)]
@@ -96,4 +496,118 @@ mod test {
             "((0.1, 0.2, 0.3, 0.4))"
         )
     }
+
+    const COMPONENTS: &str = include_str!("../tests/fixtures/serde_shim/components_v1.ron");
+
+    #[derive(Serialize, Deserialize)]
+    struct Components {
+        #[serde(with = "crate::serde_shim::camera")]
+        perspective: Camera,
+        #[serde(with = "crate::serde_shim::camera")]
+        orthographic: Camera,
+        #[serde(with = "crate::serde_shim::light")]
+        area: Light,
+        #[serde(with = "crate::serde_shim::light")]
+        directional: Light,
+        #[serde(with = "crate::serde_shim::light")]
+        point: Light,
+        #[serde(with = "crate::serde_shim::light")]
+        spot: Light,
+        #[serde(with = "crate::serde_shim::light")]
+        sun: Light,
+        #[serde(with = "crate::serde_shim::tint")]
+        tint: Tint,
+        #[serde(with = "crate::serde_shim::transparent")]
+        transparent: Transparent,
+        #[serde(with = "crate::serde_shim::bounding_sphere")]
+        bounding_sphere: BoundingSphere,
+    }
+
+    impl Components {
+        fn new() -> Self {
+            Components {
+                perspective: Camera::standard_3d(1920.0, 1280.0),
+                orthographic: Camera::standard_2d(640.0, 360.0).with_layers(RenderLayers::layer(1)),
+                area: Light::Area,
+                directional: DirectionalLight {
+                    color: palette::Srgb::new(1.0, 0.9, 0.8),
+                    intensity: 2.0,
+                    direction: [-1.0, -1.0, -1.0].into(),
+                }
+                .into(),
+                point: PointLight {
+                    color: palette::Srgb::new(1.0, 0.5, 0.25),
+                    intensity: 10.0,
+                    radius: 5.0,
+                    smoothness: 4.0,
+                }
+                .into(),
+                spot: SpotLight {
+                    angle: 0.5,
+                    color: palette::Srgb::new(0.25, 0.5, 1.0),
+                    direction: [0.0, -1.0, 0.0].into(),
+                    intensity: 20.0,
+                    range: 15.0,
+                    smoothness: 2.0,
+                }
+                .into(),
+                sun: SunLight {
+                    angle: 0.01,
+                    color: palette::Srgb::new(1.0, 1.0, 0.9),
+                    direction: [0.0, -1.0, -1.0].into(),
+                    intensity: 64_000.0,
+                }
+                .into(),
+                tint: Tint(palette::Srgba::new(1.0, 0.5, 0.25, 0.75)),
+                transparent: Transparent,
+                bounding_sphere: BoundingSphere::new(
+                    Point3::new(1.0f32.into(), 2.0f32.into(), (-3.0f32).into()),
+                    4.5,
+                ),
+            }
+        }
+
+        /// Assert the components are equal, the projections up to the precision of the
+        /// parameters extracted from their matrices.
+        fn assert_eq(&self, other: &Components) {
+            for (a, b) in &[
+                (&self.perspective, &other.perspective),
+                (&self.orthographic, &other.orthographic),
+            ] {
+                for (a, b) in a.as_matrix().iter().zip(b.as_matrix().iter()) {
+                    assert_relative_eq!(*a, *b, max_relative = 1e-4);
+                }
+                assert_eq!(a.layers(), b.layers());
+            }
+            assert_eq!(self.area, other.area);
+            assert_eq!(self.directional, other.directional);
+            assert_eq!(self.point, other.point);
+            assert_eq!(self.spot, other.spot);
+            assert_eq!(self.sun, other.sun);
+            assert_eq!(self.tint.0, other.tint.0);
+            assert_eq!(self.bounding_sphere, other.bounding_sphere);
+        }
+    }
+
+    #[test]
+    fn components_round_trip() {
+        let components = Components::new();
+        let ser = ron::ser::to_string_pretty(&components, Default::default()).unwrap();
+        let de: Components = ron::de::from_str(&ser).unwrap();
+        components.assert_eq(&de);
+    }
+
+    #[test]
+    fn components_fixture_deserializes() {
+        let de: Components = ron::de::from_str(COMPONENTS).unwrap();
+        Components::new().assert_eq(&de);
+    }
+
+    #[test]
+    fn later_versions_fail() {
+        #[derive(Debug, Deserialize)]
+        struct MyTransparentWrapper(#[serde(with = "crate::serde_shim::transparent")] Transparent);
+        assert!(ron::de::from_str::<MyTransparentWrapper>("((version: 1))").is_ok());
+        assert!(ron::de::from_str::<MyTransparentWrapper>("((version: 2))").is_err());
+    }
 }
@@ -0,0 +1,377 @@
+//! Reloading of the shaders of the built-in passes while the game runs, for development.
+//!
+//! With the `shader-hot-reload` feature, the `ShaderReloadSystem` watches a directory laid out
+//! like the `shaders` directory of this crate, with files like `vertex/sprite.vert` and
+//! `fragment/sprite.frag`. Passes look their shaders up with `shader`, which prefers a SPIR-V
+//! file of the shader name with a `.spv` extension in that directory, then the GLSL source of
+//! that name if the `shader-compiler` feature compiles it at runtime, and falls back to the
+//! shader built into the crate. When a file changes, the passes using it rebuild their pipelines
+//! on the next frame, as told by their `ShaderWatch`, and the rest of the graph is kept.
+//!
+//! Shaders failing to load or compile are reported in `ShaderReload::errors` and keep their
+//! previous version, as do the pipelines of a pass failing to build.
+//!
+//! Without the feature, `shader` always returns the built-in shader and a `ShaderWatch` never
+//! reports changes.
+use crate::types::Backend;
+use amethyst_core::ecs::Resources;
+use rendy::{factory::Factory, hal::device::Device, shader::SpirvShader};
+
+#[cfg(feature = "shader-hot-reload")]
+pub use self::reload::{ShaderReload, ShaderReloadSystem};
+
+/// The shader `name` of a built-in pass, like `vertex/sprite.vert`, or its version of the
+/// `ShaderReload` directory if there is one.
+#[cfg_attr(not(feature = "shader-hot-reload"), allow(unused_variables))]
+pub fn shader(res: &Resources, name: &str, builtin: &SpirvShader) -> SpirvShader {
+    #[cfg(feature = "shader-hot-reload")]
+    {
+        let reloaded = res
+            .try_fetch_mut::<ShaderReload>()
+            .and_then(|mut reload| reload.get(name));
+        if let Some(shader) = reloaded {
+            return shader;
+        }
+    }
+    builtin.clone()
+}
+
+#[cfg_attr(not(feature = "shader-hot-reload"), allow(unused_variables))]
+fn generation(res: &Resources, name: &str) -> u64 {
+    #[cfg(feature = "shader-hot-reload")]
+    {
+        if let Some(reload) = res.try_fetch::<ShaderReload>() {
+            return reload.generation(name);
+        }
+    }
+    0
+}
+
+/// Tells a render group when the shaders its pipelines were built with are reloaded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShaderWatch {
+    generations: Vec<(String, u64)>,
+}
+
+impl ShaderWatch {
+    /// Watch the shaders of the given names, as they are now.
+    pub fn new(res: &Resources, names: &[&str]) -> Self {
+        Self {
+            generations: names
+                .iter()
+                .map(|&name| (name.to_string(), generation(res, name)))
+                .collect(),
+        }
+    }
+
+    /// Whether any of the shaders was reloaded since the last call.
+    pub fn changed(&mut self, res: &Resources) -> bool {
+        let mut changed = false;
+        for (name, seen) in &mut self.generations {
+            let current = generation(res, name);
+            if current != *seen {
+                *seen = current;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// The pipelines rebuilt by `group` after its shaders changed, once the device finished the
+/// frames still using the previous ones, which can then be destroyed. Errors are logged and
+/// give `None`, for the group to keep its previous pipelines.
+pub fn rebuilt<B: Backend, T>(
+    factory: &Factory<B>,
+    group: &str,
+    rebuilt: Result<T, failure::Error>,
+) -> Option<T> {
+    match rebuilt {
+        Ok(pipelines) => {
+            if let Err(e) = factory.device().wait_idle() {
+                log::error!("Failed to wait for the device to rebuild {}: {}", group, e);
+            }
+            log::info!("Rebuilt the pipelines of {}", group);
+            Some(pipelines)
+        }
+        Err(e) => {
+            log::error!(
+                "Failed to rebuild the pipelines of {}, keeping the previous ones: {}",
+                group,
+                e
+            );
+            None
+        }
+    }
+}
+
+#[cfg(feature = "shader-hot-reload")]
+mod reload {
+    use crate::error::ShaderError;
+    use amethyst_core::ecs::{Resources, System, SystemData, Write};
+    use fnv::FnvHashMap;
+    use rendy::{hal::pso::ShaderStageFlags, shader::SpirvShader};
+    use std::{
+        path::{Path, PathBuf},
+        time::{Duration, Instant, SystemTime},
+    };
+
+    const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+    /// Resource with the directory shaders are reloaded from, see the `shader_reload` module.
+    #[derive(Debug)]
+    pub struct ShaderReload {
+        directory: PathBuf,
+        shaders: FnvHashMap<String, WatchedShader>,
+        errors: Vec<ShaderError>,
+    }
+
+    #[derive(Debug, Default)]
+    struct WatchedShader {
+        file: Option<(PathBuf, Option<SystemTime>)>,
+        shader: Option<SpirvShader>,
+        generation: u64,
+    }
+
+    impl ShaderReload {
+        /// Reload shaders from `directory`.
+        pub fn new(directory: impl Into<PathBuf>) -> Self {
+            Self {
+                directory: directory.into(),
+                shaders: FnvHashMap::default(),
+                errors: Vec::new(),
+            }
+        }
+
+        /// The directory shaders are reloaded from.
+        pub fn directory(&self) -> &Path {
+            &self.directory
+        }
+
+        /// Errors of the shaders that failed to load since the last `take_errors`.
+        pub fn errors(&self) -> &[ShaderError] {
+            &self.errors
+        }
+
+        /// Take the errors of the shaders that failed to load.
+        pub fn take_errors(&mut self) -> Vec<ShaderError> {
+            std::mem::replace(&mut self.errors, Vec::new())
+        }
+
+        /// Number of times the shader `name` was loaded from the directory.
+        pub fn generation(&self, name: &str) -> u64 {
+            self.shaders.get(name).map_or(0, |shader| shader.generation)
+        }
+
+        /// The shader `name` of the directory, watching it from now on.
+        pub(crate) fn get(&mut self, name: &str) -> Option<SpirvShader> {
+            if !self.shaders.contains_key(name) {
+                self.shaders
+                    .insert(name.to_string(), WatchedShader::default());
+                self.update(name);
+            }
+            self.shaders[name].shader.clone()
+        }
+
+        /// Load the shaders whose files changed since they were last loaded.
+        pub fn poll(&mut self) {
+            let names: Vec<_> = self.shaders.keys().cloned().collect();
+            for name in names {
+                self.update(&name);
+            }
+        }
+
+        fn update(&mut self, name: &str) {
+            let file = find(&self.directory, name).map(|path| {
+                let modified = path.metadata().and_then(|meta| meta.modified()).ok();
+                (path, modified)
+            });
+            let watched = self.shaders.get_mut(name).expect("Shader is not watched");
+            if file.is_none() || file == watched.file {
+                return;
+            }
+            watched.file = file.clone();
+
+            let (path, _) = file.unwrap();
+            match load(&path, name) {
+                Ok(shader) => {
+                    if watched.generation > 0 {
+                        log::info!("Reloaded shader {} from {}", name, path.display());
+                    }
+                    watched.shader = Some(shader);
+                    watched.generation += 1;
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    self.errors.push(e);
+                }
+            }
+        }
+    }
+
+    /// The SPIR-V or GLSL file of the shader `name` in `directory`.
+    fn find(directory: &Path, name: &str) -> Option<PathBuf> {
+        let spirv = directory.join(format!("{}.spv", name));
+        if spirv.is_file() {
+            return Some(spirv);
+        }
+        let source = directory.join(name);
+        if cfg!(feature = "shader-compiler") && source.is_file() {
+            return Some(source);
+        }
+        None
+    }
+
+    /// Stage of the shader `name`, from its extension.
+    fn stage(name: &str) -> Result<ShaderStageFlags, ShaderError> {
+        match Path::new(name).extension().and_then(|ext| ext.to_str()) {
+            Some("vert") => Ok(ShaderStageFlags::VERTEX),
+            Some("frag") => Ok(ShaderStageFlags::FRAGMENT),
+            Some("geom") => Ok(ShaderStageFlags::GEOMETRY),
+            Some("tesc") => Ok(ShaderStageFlags::HULL),
+            Some("tese") => Ok(ShaderStageFlags::DOMAIN),
+            Some("comp") => Ok(ShaderStageFlags::COMPUTE),
+            _ => Err(ShaderError::UnknownStage(name.to_string())),
+        }
+    }
+
+    fn load(path: &Path, name: &str) -> Result<SpirvShader, ShaderError> {
+        let stage = stage(name)?;
+        let bytes = std::fs::read(path).map_err(|e| ShaderError::Read {
+            name: name.to_string(),
+            reason: e.to_string(),
+        })?;
+        if path.extension().map_or(false, |ext| ext == "spv") {
+            check_spirv(&bytes, name)?;
+            return Ok(SpirvShader::new(bytes, stage, "main"));
+        }
+        compile(bytes, path, name, stage)
+    }
+
+    fn check_spirv(bytes: &[u8], name: &str) -> Result<(), ShaderError> {
+        let mut magic = [0; 4];
+        if bytes.len() < 20 || bytes.len() % 4 != 0 {
+            return Err(ShaderError::InvalidSpirv(name.to_string()));
+        }
+        magic.copy_from_slice(&bytes[..4]);
+        if u32::from_le_bytes(magic) != SPIRV_MAGIC {
+            return Err(ShaderError::InvalidSpirv(name.to_string()));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "shader-compiler")]
+    fn compile(
+        bytes: Vec<u8>,
+        path: &Path,
+        name: &str,
+        stage: ShaderStageFlags,
+    ) -> Result<SpirvShader, ShaderError> {
+        use rendy::shader::{ShaderKind, SourceLanguage, SourceShaderInfo};
+
+        let source = String::from_utf8(bytes).map_err(|e| ShaderError::Read {
+            name: name.to_string(),
+            reason: e.to_string(),
+        })?;
+        let kind = match stage {
+            ShaderStageFlags::VERTEX => ShaderKind::Vertex,
+            ShaderStageFlags::FRAGMENT => ShaderKind::Fragment,
+            ShaderStageFlags::GEOMETRY => ShaderKind::Geometry,
+            ShaderStageFlags::HULL => ShaderKind::TessControl,
+            ShaderStageFlags::DOMAIN => ShaderKind::TessEvaluation,
+            _ => ShaderKind::Compute,
+        };
+        SourceShaderInfo::new(
+            source,
+            path.to_string_lossy(),
+            kind,
+            SourceLanguage::GLSL,
+            "main",
+        )
+        .precompile()
+        .map_err(|e| ShaderError::Compile {
+            name: name.to_string(),
+            message: e.to_string(),
+        })
+    }
+
+    #[cfg(not(feature = "shader-compiler"))]
+    fn compile(
+        _bytes: Vec<u8>,
+        _path: &Path,
+        name: &str,
+        _stage: ShaderStageFlags,
+    ) -> Result<SpirvShader, ShaderError> {
+        Err(ShaderError::Compile {
+            name: name.to_string(),
+            message: "GLSL sources need the `shader-compiler` feature".to_string(),
+        })
+    }
+
+    /// Inserts a `ShaderReload` resource for its directory and polls it at a fixed interval.
+    #[derive(Debug)]
+    pub struct ShaderReloadSystem {
+        directory: PathBuf,
+        interval: Duration,
+        last: Instant,
+    }
+
+    impl ShaderReloadSystem {
+        /// Reload shaders from `directory`, checking the files every `interval`.
+        pub fn new(directory: impl Into<PathBuf>, interval: Duration) -> Self {
+            Self {
+                directory: directory.into(),
+                interval,
+                last: Instant::now(),
+            }
+        }
+    }
+
+    impl<'a> System<'a> for ShaderReloadSystem {
+        type SystemData = Option<Write<'a, ShaderReload>>;
+
+        fn run(&mut self, reload: Self::SystemData) {
+            if self.last.elapsed() < self.interval {
+                return;
+            }
+            self.last = Instant::now();
+            if let Some(mut reload) = reload {
+                reload.poll();
+            }
+        }
+
+        fn setup(&mut self, res: &mut Resources) {
+            Self::SystemData::setup(res);
+            res.insert(ShaderReload::new(self.directory.clone()));
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn stages_from_names() {
+            assert_eq!(stage("vertex/sprite.vert"), Ok(ShaderStageFlags::VERTEX));
+            assert_eq!(stage("fragment/pbr.frag"), Ok(ShaderStageFlags::FRAGMENT));
+            assert_eq!(
+                stage("geometry/debug_lines.geom"),
+                Ok(ShaderStageFlags::GEOMETRY)
+            );
+            assert_eq!(stage("terrain.tese"), Ok(ShaderStageFlags::DOMAIN));
+            assert_eq!(
+                stage("sprite"),
+                Err(ShaderError::UnknownStage("sprite".to_string()))
+            );
+        }
+
+        #[test]
+        fn spirv_is_checked() {
+            let mut spirv = SPIRV_MAGIC.to_le_bytes().to_vec();
+            spirv.extend_from_slice(&[0; 16]);
+            assert!(check_spirv(&spirv, "a.vert").is_ok());
+            assert!(check_spirv(&spirv[..18], "a.vert").is_err());
+            assert!(check_spirv(b"#version 450\nvoid main", "a.vert").is_err());
+        }
+    }
+}
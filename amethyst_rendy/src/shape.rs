@@ -1,22 +1,31 @@
 //! Basic shape prefabs.
-use crate::types::Mesh;
+use crate::{
+    error::MeshError,
+    mesh_util::{self, MeshIndices, NormalSmoothing},
+    types::{Mesh, MeshData, MeshLayout},
+    visibility::{BoundingBox, BoundingSphere, MeshBoundingBoxes, MeshBoundingSpheres},
+};
 use amethyst_assets::{AssetStorage, Handle, Loader, PrefabData, Progress, ProgressCounter};
 use amethyst_core::{
-    ecs::prelude::{Entity, Read, ReadExpect, WriteStorage},
+    ecs::prelude::{Entity, Read, ReadExpect, Write, WriteStorage},
     math::Vector3,
 };
 use amethyst_error::Error;
+use fnv::{FnvHashMap, FnvHashSet};
 use genmesh::{
     generators::{
-        Circle, Cone, Cube, Cylinder, IcoSphere, IndexedPolygon, Plane, SharedVertex, SphereUv,
-        Torus,
+        Circle, Cone, Cube, Cylinder, IndexedPolygon, Plane, SharedVertex, SphereUv, Torus,
     },
     EmitTriangles, MapVertex, Triangulate, Vertex, Vertices,
 };
 use rendy::mesh::{
-    MeshBuilder, Normal, PosNormTangTex, PosNormTex, PosTex, Position, Tangent, TexCoord,
+    AsVertex, MeshBuilder, Normal, PosNormTangTex, PosNormTex, PosTex, Position, Tangent, TexCoord,
+    VertexFormat,
 };
-use std::marker::PhantomData;
+use std::{f32::consts::PI, marker::PhantomData};
+
+/// Maximum number of subdivisions supported by `Shape::IcoSphere`.
+pub const MAX_ICOSPHERE_SUBDIVISIONS: usize = 5;
 
 fn option_none<T>() -> Option<T> {
     None
@@ -31,6 +40,10 @@ fn option_none<T>() -> Option<T> {
 ///     * `Vec<PosNormTex>`
 ///     * `Vec<PosNormTangTex>`
 ///     * `ComboMeshCreator`
+///
+/// Set `generate_tangents` to replace the approximated tangents of the basic shapes with ones
+/// computed from the texture coordinates, which is needed for correct normal mapping. Set
+/// `recompute_normals` to replace the generated normals, for example with flat shading.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(bound = "")]
 pub struct ShapePrefab<V> {
@@ -40,6 +53,10 @@ pub struct ShapePrefab<V> {
     shape: Shape,
     #[serde(default)]
     shape_scale: Option<(f32, f32, f32)>,
+    #[serde(default)]
+    generate_tangents: bool,
+    #[serde(default)]
+    recompute_normals: Option<NormalSmoothing>,
     #[serde(skip)]
     _m: PhantomData<V>,
 }
@@ -49,9 +66,13 @@ where
     V: FromShape + Into<MeshBuilder<'static>>,
 {
     type SystemData = (
-        ReadExpect<'a, Loader>,
-        WriteStorage<'a, Handle<Mesh>>,
-        Read<'a, AssetStorage<Mesh>>,
+        (
+            ReadExpect<'a, Loader>,
+            WriteStorage<'a, Handle<Mesh>>,
+            Read<'a, AssetStorage<Mesh>>,
+        ),
+        Write<'a, MeshBoundingSpheres>,
+        Write<'a, MeshBoundingBoxes>,
     );
     type Result = ();
 
@@ -62,7 +83,7 @@ where
         _: &[Entity],
         _: &[Entity],
     ) -> Result<(), Error> {
-        let (_, ref mut meshes, _) = system_data;
+        let ((_, ref mut meshes, _), _, _) = system_data;
         let self_handle = self.handle.as_ref().expect(
             "`ShapePrefab::load_sub_assets` was not called before `ShapePrefab::add_to_entity`",
         );
@@ -75,12 +96,24 @@ where
         progress: &mut ProgressCounter,
         system_data: &mut <Self as PrefabData<'_>>::SystemData,
     ) -> Result<bool, Error> {
-        let (loader, _, mesh_storage) = system_data;
-        self.handle = Some(loader.load_from_data(
-            self.shape.generate::<V>(self.shape_scale).into(),
-            progress,
-            &mesh_storage,
-        ));
+        let ((loader, _, mesh_storage), spheres, boxes) = system_data;
+        let mut internal = self.shape.generate_internal(self.shape_scale)?;
+        if let Some(smoothing) = self.recompute_normals {
+            internal = internal.with_recomputed_normals(smoothing);
+        }
+        if self.generate_tangents {
+            internal = internal.with_generated_tangents();
+        }
+        let sphere = internal.bounding_sphere();
+        let aabb = internal.bounding_box();
+        let handle = loader.load_from_data(build_mesh_data::<V>(internal), progress, &mesh_storage);
+        if let Some(sphere) = sphere {
+            spheres.insert(&handle, sphere);
+        }
+        if let Some(aabb) = aabb {
+            boxes.insert(&handle, aabb);
+        }
+        self.handle = Some(handle);
         Ok(true)
     }
 }
@@ -99,12 +132,340 @@ pub enum Shape {
     /// Torus, radius from origin to center of tubular, tubular radius from toridal to surface,
     /// number of tube segments >= 3, number of segments around the tube
     Torus(f32, f32, usize, usize),
-    /// Icosahedral sphere, number of subdivisions > 0 if given
+    /// Icosahedral sphere, number of subdivisions if given, at most `MAX_ICOSPHERE_SUBDIVISIONS`.
+    ///
+    /// Vertices are shared between faces using an index buffer, except along the UV seam and at
+    /// the poles where faces get their own copies. With `n` subdivisions the mesh has
+    /// `60 * 4^n` indices and the following number of vertices:
+    ///
+    /// | subdivisions | vertices | indices |
+    /// |--------------|----------|---------|
+    /// | 0            | 15       | 60      |
+    /// | 1            | 57       | 240     |
+    /// | 2            | 183      | 960     |
+    /// | 3            | 675      | 3840    |
+    /// | 4            | 2619     | 15360   |
+    /// | 5            | 10347    | 61440   |
+    ///
+    /// The UV unwrap is a spherical projection with a seam along the -X axis. Faces crossing the
+    /// seam have texture coordinates slightly outside `0..1`, which need a repeating sampler.
     IcoSphere(Option<usize>),
     /// Plane, located in the XY plane, number of subdivisions along x and y axis if given
     Plane(Option<(usize, usize)>),
     /// Circle, located in the XY plane, number of points around the circle
     Circle(usize),
+    /// Subdivided plane spanning -1..1 along both of its axes, with vertices shared between
+    /// quads. See `PlaneGrid` for generating a grid whose positions can be edited before upload.
+    Grid {
+        /// Number of quads along the first and second axis of the plane, each at least 1
+        subdivisions: (usize, usize),
+        /// Number of times the texture repeats along each axis
+        #[serde(default = "default_uv_tiling")]
+        uv_tiling: (f32, f32),
+        /// Plane the grid is located in
+        #[serde(default)]
+        orientation: PlaneOrientation,
+    },
+}
+
+fn default_uv_tiling() -> (f32, f32) {
+    (1.0, 1.0)
+}
+
+/// Orientation of a generated plane.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PlaneOrientation {
+    /// Ground plane, facing +Y. The second axis of the grid runs along -Z.
+    XZ,
+    /// Billboard plane, facing +Z. The second axis of the grid runs along +Y.
+    XY,
+}
+
+impl Default for PlaneOrientation {
+    fn default() -> Self {
+        PlaneOrientation::XZ
+    }
+}
+
+/// A subdivided plane whose vertex positions can be modified before generating the mesh,
+/// for example to displace heights for terrain or water.
+///
+/// Positions are stored row by row, `columns + 1` vertices per row and `rows + 1` rows.
+/// Normals and tangents are computed from the final positions when the mesh is generated.
+///
+/// ### Example:
+///
+/// ```rust,ignore
+/// let mut grid = PlaneGrid::new((64, 64), (8.0, 8.0), PlaneOrientation::XZ);
+/// for position in grid.positions_mut() {
+///     position.y = (position.x * 4.0).sin() * 0.1;
+/// }
+/// let mesh = grid.generate::<Vec<PosNormTangTex>>();
+/// ```
+#[derive(Clone, Debug)]
+pub struct PlaneGrid {
+    columns: usize,
+    rows: usize,
+    positions: Vec<Vector3<f32>>,
+    tex_coords: Vec<[f32; 2]>,
+}
+
+impl PlaneGrid {
+    /// Create a flat grid spanning -1..1 along both axes of the plane.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `subdivisions`: Number of quads along the first and second axis, clamped to at least 1
+    /// - `uv_tiling`: Number of times the texture repeats along each axis
+    /// - `orientation`: Plane the grid is located in
+    pub fn new(
+        subdivisions: (usize, usize),
+        uv_tiling: (f32, f32),
+        orientation: PlaneOrientation,
+    ) -> Self {
+        let columns = subdivisions.0.max(1);
+        let rows = subdivisions.1.max(1);
+        let mut positions = Vec::with_capacity((columns + 1) * (rows + 1));
+        let mut tex_coords = Vec::with_capacity((columns + 1) * (rows + 1));
+        for j in 0..=rows {
+            let v = j as f32 / rows as f32;
+            for i in 0..=columns {
+                let u = i as f32 / columns as f32;
+                let a = u * 2.0 - 1.0;
+                let b = v * 2.0 - 1.0;
+                positions.push(match orientation {
+                    PlaneOrientation::XZ => Vector3::new(a, 0.0, -b),
+                    PlaneOrientation::XY => Vector3::new(a, b, 0.0),
+                });
+                tex_coords.push([u * uv_tiling.0, v * uv_tiling.1]);
+            }
+        }
+        PlaneGrid {
+            columns,
+            rows,
+            positions,
+            tex_coords,
+        }
+    }
+
+    /// Number of quads along the first axis.
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// Number of quads along the second axis.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Vertex positions, row by row.
+    pub fn positions(&self) -> &[Vector3<f32>] {
+        &self.positions
+    }
+
+    /// Mutable vertex positions, row by row.
+    pub fn positions_mut(&mut self) -> &mut [Vector3<f32>] {
+        &mut self.positions
+    }
+
+    /// Mutable position of the vertex at `column`, `row`.
+    ///
+    /// ### Panics:
+    ///
+    /// Panics if `column > columns()` or `row > rows()`.
+    pub fn position_mut(&mut self, column: usize, row: usize) -> &mut Vector3<f32> {
+        assert!(column <= self.columns && row <= self.rows);
+        &mut self.positions[row * (self.columns + 1) + column]
+    }
+
+    /// Generate `MeshBuilder` for the grid, in vertex format `V`.
+    ///
+    /// See `Shape::generate` for the supported vertex formats.
+    pub fn generate<V>(&self) -> MeshBuilder<'static>
+    where
+        V: FromShape + Into<MeshBuilder<'static>>,
+    {
+        build_mesh::<V>(self.generate_internal())
+    }
+
+    /// Generate the `InternalShape` for the grid.
+    pub fn generate_internal(&self) -> InternalShape {
+        let stride = self.columns + 1;
+        let at = |i: usize, j: usize| self.positions[j * stride + i];
+
+        let vertices = (0..=self.rows)
+            .flat_map(|j| (0..=self.columns).map(move |i| (i, j)))
+            .map(|(i, j)| {
+                let du = at((i + 1).min(self.columns), j) - at(i.saturating_sub(1), j);
+                let dv = at(i, (j + 1).min(self.rows)) - at(i, j.saturating_sub(1));
+                let normal = du.cross(&dv).normalize();
+                let tangent = (du - normal * normal.dot(&du)).normalize();
+                (
+                    at(i, j).into(),
+                    normal.into(),
+                    self.tex_coords[j * stride + i],
+                    [tangent.x, tangent.y, tangent.z, 1.0],
+                )
+            })
+            .collect();
+
+        let mut indices = Vec::with_capacity(self.columns * self.rows * 6);
+        for j in 0..self.rows {
+            for i in 0..self.columns {
+                let a = (j * stride + i) as u32;
+                let b = a + 1;
+                let c = b + stride as u32;
+                let d = a + stride as u32;
+                indices.extend_from_slice(&[a, b, c, a, c, d]);
+            }
+        }
+
+        InternalShape {
+            vertices,
+            indices: Some(indices),
+        }
+    }
+}
+
+/// Grid of height samples for generating terrain meshes.
+///
+/// Samples are stored row by row, `width` samples per row along +X and `depth` rows along +Z,
+/// so the first row is the far edge of the terrain when viewed from +Z. This matches the
+/// layout of an image whose top row is north. See `formats::texture::load_heightmap` for
+/// loading heights from a grayscale image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heightmap {
+    width: usize,
+    depth: usize,
+    heights: Vec<f32>,
+}
+
+/// A chunk of a terrain generated by `Heightmap::generate_chunks`.
+#[derive(Debug)]
+pub struct HeightmapChunk {
+    /// Index of the chunk along the x and z axes.
+    pub coordinates: (usize, usize),
+    /// Vertex data of the chunk, in the same space as the whole terrain.
+    pub shape: InternalShape,
+    /// Bounding sphere of the chunk, for culling.
+    pub bounding_sphere: Option<BoundingSphere>,
+}
+
+impl Heightmap {
+    /// Create a heightmap from `width * depth` samples.
+    pub fn new(width: usize, depth: usize, heights: Vec<f32>) -> Result<Self, MeshError> {
+        if width < 2 || depth < 2 {
+            return Err(MeshError::InvalidGridSize { width, depth });
+        }
+        if heights.len() != width * depth {
+            return Err(MeshError::AttributeCountMismatch {
+                attribute: "heights",
+                expected: width * depth,
+                found: heights.len(),
+            });
+        }
+        Ok(Heightmap {
+            width,
+            depth,
+            heights,
+        })
+    }
+
+    /// Number of samples along the x axis.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Number of samples along the z axis.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Height samples, row by row.
+    pub fn heights(&self) -> &[f32] {
+        &self.heights
+    }
+
+    /// Generate the terrain as a single grid mesh, centered on the origin.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `world_scale`: Size of the terrain along x, multiplier for the heights and size of the
+    ///    terrain along z
+    /// - `uv_tiling`: Number of times the texture repeats along x and z
+    pub fn generate_internal(
+        &self,
+        world_scale: (f32, f32, f32),
+        uv_tiling: (f32, f32),
+    ) -> InternalShape {
+        let (columns, rows) = (self.width - 1, self.depth - 1);
+        let mut grid = PlaneGrid::new((columns, rows), uv_tiling, PlaneOrientation::XZ);
+        for j in 0..=rows {
+            for i in 0..=columns {
+                // Grid rows run along -Z, heightmap rows along +Z
+                let height = self.heights[(rows - j) * self.width + i];
+                let p = grid.position_mut(i, j);
+                *p = Vector3::new(
+                    p.x * world_scale.0 * 0.5,
+                    height * world_scale.1,
+                    p.z * world_scale.2 * 0.5,
+                );
+            }
+        }
+        grid.generate_internal()
+    }
+
+    /// Generate the terrain split into chunks of at most `chunk_size` quads along each axis,
+    /// each with its own bounding sphere. Normals are computed on the whole terrain, so there
+    /// are no lighting seams between chunks.
+    ///
+    /// See `generate_internal` for the parameters.
+    pub fn generate_chunks(
+        &self,
+        chunk_size: usize,
+        world_scale: (f32, f32, f32),
+        uv_tiling: (f32, f32),
+    ) -> Vec<HeightmapChunk> {
+        let chunk_size = chunk_size.max(1);
+        let whole = self.generate_internal(world_scale, uv_tiling);
+        let (columns, rows) = (self.width - 1, self.depth - 1);
+        let stride = self.width;
+
+        let mut chunks = Vec::new();
+        for (cz, j0) in (0..rows).step_by(chunk_size).enumerate() {
+            for (cx, i0) in (0..columns).step_by(chunk_size).enumerate() {
+                let i1 = (i0 + chunk_size).min(columns);
+                let j1 = (j0 + chunk_size).min(rows);
+                let chunk_stride = i1 - i0 + 1;
+
+                let vertices = (j0..=j1)
+                    .flat_map(|j| (i0..=i1).map(move |i| j * stride + i))
+                    .map(|index| whole.vertices[index])
+                    .collect::<Vec<_>>();
+                let mut indices = Vec::with_capacity((i1 - i0) * (j1 - j0) * 6);
+                for j in 0..j1 - j0 {
+                    for i in 0..i1 - i0 {
+                        let a = (j * chunk_stride + i) as u32;
+                        let b = a + 1;
+                        let c = b + chunk_stride as u32;
+                        let d = a + chunk_stride as u32;
+                        indices.extend_from_slice(&[a, b, c, a, c, d]);
+                    }
+                }
+
+                let shape = InternalShape {
+                    vertices,
+                    indices: Some(indices),
+                };
+                chunks.push(HeightmapChunk {
+                    coordinates: (cx, cz),
+                    bounding_sphere: shape.bounding_sphere(),
+                    shape,
+                });
+            }
+        }
+        chunks
+    }
 }
 
 /// `SystemData` needed to upload a `Shape` directly to create a `Handle<Mesh>`
@@ -113,18 +474,100 @@ pub enum Shape {
 pub struct ShapeUpload<'a> {
     loader: ReadExpect<'a, Loader>,
     storage: Read<'a, AssetStorage<Mesh>>,
+    spheres: Write<'a, MeshBoundingSpheres>,
+    boxes: Write<'a, MeshBoundingBoxes>,
 }
 
-/// Vertex data for a basic shape.
-pub type InternalVertexData = ([f32; 3], [f32; 3], [f32; 2], [f32; 3]);
+/// Vertex data for a basic shape: position, normal, texture coordinate and tangent with
+/// handedness in `w`.
+pub type InternalVertexData = ([f32; 3], [f32; 3], [f32; 2], [f32; 4]);
 
 /// Internal Shape, used for transformation from `genmesh` to `MeshBuilder`
 #[derive(Debug)]
-pub struct InternalShape(Vec<InternalVertexData>);
+pub struct InternalShape {
+    vertices: Vec<InternalVertexData>,
+    indices: Option<Vec<u32>>,
+}
 
 impl InternalShape {
     fn map_into<T, F: FnMut(&InternalVertexData) -> T>(&self, f: F) -> Vec<T> {
-        self.0.iter().map(f).collect()
+        self.vertices.iter().map(f).collect()
+    }
+
+    /// Generate `MeshBuilder` for this shape, in vertex format `V`.
+    ///
+    /// See `Shape::generate` for the supported vertex formats.
+    pub fn mesh_builder<V>(self) -> MeshBuilder<'static>
+    where
+        V: FromShape + Into<MeshBuilder<'static>>,
+    {
+        build_mesh::<V>(self)
+    }
+
+    /// Vertex positions of this shape.
+    pub fn positions(&self) -> Vec<Position> {
+        self.map_into(Position::from_internal)
+    }
+
+    /// Replace the normals of this shape with ones computed from its positions, see
+    /// `mesh_util::recompute_normals`.
+    pub fn with_recomputed_normals(mut self, smoothing: NormalSmoothing) -> Self {
+        let normals = mesh_util::recompute_normals(
+            &self.positions(),
+            self.indices.as_ref().map(Vec::as_slice),
+            smoothing,
+        );
+        for (vertex, normal) in self.vertices.iter_mut().zip(normals) {
+            vertex.1 = normal.0;
+        }
+        self
+    }
+
+    /// Bounding sphere of the vertex positions, see `mesh_util::bounding_sphere`.
+    pub fn bounding_sphere(&self) -> Option<BoundingSphere> {
+        mesh_util::bounding_sphere(self.vertices.iter().map(|v| &v.0))
+    }
+
+    /// Bounding box of the vertex positions, see `mesh_util::bounding_box`.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        mesh_util::bounding_box(self.vertices.iter().map(|v| &v.0))
+    }
+
+    /// Expand the shared vertices of an indexed shape into a plain triangle list, one vertex
+    /// per index.
+    fn unindexed(self) -> Self {
+        match self.indices {
+            Some(indices) => InternalShape {
+                vertices: indices.iter().map(|&i| self.vertices[i as usize]).collect(),
+                indices: None,
+            },
+            None => self,
+        }
+    }
+
+    /// Returns the index buffer of this shape, if its vertices are shared between triangles.
+    ///
+    /// Shapes without indices are emitted as a plain triangle list.
+    pub fn indices(&self) -> Option<&[u32]> {
+        self.indices.as_ref().map(Vec::as_slice)
+    }
+
+    /// Replace the tangents of this shape with ones generated from its positions, normals and
+    /// texture coordinates, see `mesh_util::generate_tangents`.
+    pub fn with_generated_tangents(mut self) -> Self {
+        let positions = self.map_into(Position::from_internal);
+        let normals = self.map_into(Normal::from_internal);
+        let tex_coords = self.map_into(TexCoord::from_internal);
+        let tangents = mesh_util::generate_tangents(
+            &positions,
+            &normals,
+            &tex_coords,
+            self.indices.as_ref().map(Vec::as_slice),
+        );
+        for (vertex, tangent) in self.vertices.iter_mut().zip(tangents) {
+            vertex.3 = tangent.0;
+        }
+        self
     }
 }
 
@@ -132,6 +575,13 @@ impl InternalShape {
 pub trait FromShape {
     /// Convert from a shape to `Self` type.
     fn from(shape: &InternalShape) -> Self;
+
+    /// Formats of the vertex buffers produced by `from`, one per buffer.
+    ///
+    /// By default the formats are read back from the generated mesh data.
+    fn vertex_formats() -> Vec<VertexFormat> {
+        Vec::new()
+    }
 }
 
 /// Internal trait for converting from vertex data to a shape type.
@@ -140,10 +590,14 @@ pub trait FromInternalVertex {
     fn from_internal(v: &InternalVertexData) -> Self;
 }
 
-impl<T: FromInternalVertex> FromShape for Vec<T> {
+impl<T: FromInternalVertex + AsVertex> FromShape for Vec<T> {
     fn from(shape: &InternalShape) -> Self {
         shape.map_into(T::from_internal)
     }
+
+    fn vertex_formats() -> Vec<VertexFormat> {
+        vec![T::vertex()]
+    }
 }
 
 impl Shape {
@@ -163,19 +617,36 @@ impl Shape {
     ///     * `Vec<PosNormTangTex>`
     ///     * `ComboMeshCreator`
     /// `P`: Progress tracker type
+    ///
+    /// ### Panics:
+    ///
+    /// Panics if the shape parameters are out of range, see `try_generate`.
     pub fn upload<V, P>(
         &self,
         scale: Option<(f32, f32, f32)>,
-        upload: ShapeUpload<'_>,
+        mut upload: ShapeUpload<'_>,
         progress: P,
     ) -> Handle<Mesh>
     where
         V: FromShape + Into<MeshBuilder<'static>>,
         P: Progress,
     {
-        upload
-            .loader
-            .load_from_data(self.generate::<V>(scale).into(), progress, &upload.storage)
+        let internal = self
+            .generate_internal(scale)
+            .unwrap_or_else(|e| panic!("Failed to generate {:?}: {}", self, e));
+        let sphere = internal.bounding_sphere();
+        let aabb = internal.bounding_box();
+        let handle =
+            upload
+                .loader
+                .load_from_data(build_mesh_data::<V>(internal), progress, &upload.storage);
+        if let Some(sphere) = sphere {
+            upload.spheres.insert(&handle, sphere);
+        }
+        if let Some(aabb) = aabb {
+            upload.boxes.insert(&handle, aabb);
+        }
+        handle
     }
 
     /// Generate `MeshBuilder` for the `Shape`
@@ -191,11 +662,40 @@ impl Shape {
     ///     * `Vec<PosNormTex>`
     ///     * `Vec<PosNormTangTex>`
     ///     * `ComboMeshCreator`
+    ///
+    /// ### Panics:
+    ///
+    /// Panics if the shape parameters are out of range, see `try_generate`.
     pub fn generate<V>(&self, scale: Option<(f32, f32, f32)>) -> MeshBuilder<'static>
     where
         V: FromShape + Into<MeshBuilder<'static>>,
     {
-        V::from(&self.generate_internal(scale)).into()
+        self.try_generate::<V>(scale)
+            .unwrap_or_else(|e| panic!("Failed to generate {:?}: {}", self, e))
+    }
+
+    /// Generate `MeshBuilder` for the `Shape`, returning an error if the shape parameters are
+    /// out of range.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `scale`: Scale the shape by the given amounts along the x, y, z axes
+    ///
+    /// ### Type parameters:
+    ///
+    /// `V`: Vertex format to use, must to be one of:
+    ///     * `Vec<PosTex>`
+    ///     * `Vec<PosNormTex>`
+    ///     * `Vec<PosNormTangTex>`
+    ///     * `ComboMeshCreator`
+    pub fn try_generate<V>(
+        &self,
+        scale: Option<(f32, f32, f32)>,
+    ) -> Result<MeshBuilder<'static>, MeshError>
+    where
+        V: FromShape + Into<MeshBuilder<'static>>,
+    {
+        self.generate_internal(scale).map(build_mesh::<V>)
     }
 
     /// Generate vertices for the `Shape`, in format `V`
@@ -211,14 +711,36 @@ impl Shape {
     ///     * `Vec<PosNormTex>`
     ///     * `Vec<PosNormTangTex>`
     ///     * `ComboMeshCreator`
+    ///
+    /// ### Panics:
+    ///
+    /// Panics if the shape parameters are out of range, see `try_generate`.
+    ///
+    /// The vertices form a plain triangle list: indexed shapes such as `IcoSphere` have their
+    /// shared vertices expanded, one per index. Use `generate_internal` and
+    /// `InternalShape::indices` to keep the vertices shared.
     pub fn generate_vertices<V>(&self, scale: Option<(f32, f32, f32)>) -> V
     where
         V: FromShape,
     {
-        V::from(&self.generate_internal(scale))
+        V::from(
+            &self
+                .generate_internal(scale)
+                .unwrap_or_else(|e| panic!("Failed to generate {:?}: {}", self, e))
+                .unindexed(),
+        )
     }
 
-    fn generate_internal(&self, scale: Option<(f32, f32, f32)>) -> InternalShape {
+    /// Generate the `InternalShape` for the `Shape`, holding all vertex attributes and the
+    /// optional index buffer.
+    ///
+    /// ### Parameters:
+    ///
+    /// - `scale`: Scale the shape by the given amounts along the x, y, z axes
+    pub fn generate_internal(
+        &self,
+        scale: Option<(f32, f32, f32)>,
+    ) -> Result<InternalShape, MeshError> {
         let vertices = match *self {
             Shape::Cube => generate_vertices(Cube::new(), scale),
             Shape::Sphere(u, v) => generate_vertices(SphereUv::new(u, v), scale),
@@ -228,12 +750,9 @@ impl Shape {
                     .unwrap_or_else(|| Cylinder::new(u)),
                 scale,
             ),
-            Shape::IcoSphere(divide) => generate_vertices(
-                divide
-                    .map(IcoSphere::subdivide)
-                    .unwrap_or_else(IcoSphere::new),
-                scale,
-            ),
+            Shape::IcoSphere(divide) => {
+                return generate_icosphere(divide.unwrap_or(0), scale);
+            }
             Shape::Torus(radius, tube_radius, radial_segments, tubular_segments) => {
                 generate_vertices(
                     Torus::new(radius, tube_radius, radial_segments, tubular_segments),
@@ -247,11 +766,231 @@ impl Shape {
                 scale,
             ),
             Shape::Circle(u) => generate_vertices(Circle::new(u), scale),
+            Shape::Grid {
+                subdivisions,
+                uv_tiling,
+                orientation,
+            } => {
+                let mut grid = PlaneGrid::new(subdivisions, uv_tiling, orientation);
+                if let Some((x, y, z)) = scale {
+                    for p in grid.positions_mut() {
+                        *p = Vector3::new(p.x * x, p.y * y, p.z * z);
+                    }
+                }
+                return Ok(grid.generate_internal());
+            }
         };
-        InternalShape(vertices)
+        Ok(InternalShape {
+            vertices,
+            indices: None,
+        })
     }
 }
 
+fn build_mesh<V>(internal: InternalShape) -> MeshBuilder<'static>
+where
+    V: FromShape + Into<MeshBuilder<'static>>,
+{
+    let mut builder: MeshBuilder<'static> = V::from(&internal).into();
+    if let Some(indices) = internal.indices {
+        MeshIndices::U32(indices)
+            .compact(internal.vertices.len())
+            .set_on(&mut builder);
+    }
+    builder
+}
+
+fn build_mesh_data<V>(internal: InternalShape) -> MeshData
+where
+    V: FromShape + Into<MeshBuilder<'static>>,
+{
+    let vertex_formats = V::vertex_formats();
+    if vertex_formats.is_empty() {
+        let data = MeshData::from(build_mesh::<V>(internal));
+        let layout = data.layout();
+        return MeshData(data.0, layout);
+    }
+    let vertex_count = internal.vertices.len();
+    let layout = MeshLayout {
+        vertex_count: vertex_count as u32,
+        index_count: internal.indices.as_ref().map_or(0, |i| i.len() as u32),
+        index_type: internal
+            .indices
+            .as_ref()
+            .map(|_| mesh_util::index_type_for(vertex_count)),
+        vertex_formats,
+    };
+    MeshData::from(build_mesh::<V>(internal)).with_layout(layout)
+}
+
+fn generate_icosphere(
+    subdivisions: usize,
+    scale: Option<(f32, f32, f32)>,
+) -> Result<InternalShape, MeshError> {
+    if subdivisions > MAX_ICOSPHERE_SUBDIVISIONS {
+        return Err(MeshError::TooManySubdivisions {
+            requested: subdivisions,
+            max: MAX_ICOSPHERE_SUBDIVISIONS,
+        });
+    }
+
+    let t = (1.0 + 5f32.sqrt()) / 2.0;
+    let mut positions = [
+        [-1.0, t, 0.0],
+        [1.0, t, 0.0],
+        [-1.0, -t, 0.0],
+        [1.0, -t, 0.0],
+        [0.0, -1.0, t],
+        [0.0, 1.0, t],
+        [0.0, -1.0, -t],
+        [0.0, 1.0, -t],
+        [t, 0.0, -1.0],
+        [t, 0.0, 1.0],
+        [-t, 0.0, -1.0],
+        [-t, 0.0, 1.0],
+    ]
+    .iter()
+    .map(|p| Vector3::from(*p).normalize())
+    .collect::<Vec<Vector3<f32>>>();
+
+    let mut faces: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints = FnvHashMap::default();
+        let mut next = Vec::with_capacity(faces.len() * 4);
+        for face in &faces {
+            let (a, b, c) = (face[0], face[1], face[2]);
+            let ab = icosphere_midpoint(&mut positions, &mut midpoints, a, b);
+            let bc = icosphere_midpoint(&mut positions, &mut midpoints, b, c);
+            let ca = icosphere_midpoint(&mut positions, &mut midpoints, c, a);
+            next.push([a, ab, ca]);
+            next.push([b, bc, ab]);
+            next.push([c, ca, bc]);
+            next.push([ab, bc, ca]);
+        }
+        faces = next;
+    }
+
+    let mut vertices = positions
+        .iter()
+        .map(|p| icosphere_vertex(p, icosphere_u(p), scale))
+        .collect::<Vec<_>>();
+
+    // Each face takes the texture coordinates closest to the U of its centroid, so faces crossing
+    // the seam get a copy of their vertices shifted by a full turn instead of interpolating across
+    // the whole texture. The poles have no U of their own and get a copy per face.
+    let mut shifted = FnvHashMap::default();
+    let mut poles = FnvHashSet::default();
+    let mut indices = Vec::with_capacity(faces.len() * 3);
+    for face in &faces {
+        let centroid = face.iter().fold(Vector3::<f32>::zeros(), |sum, &i| {
+            sum + positions[i as usize]
+        });
+        let face_u = icosphere_u(&centroid);
+        for &index in face {
+            let p = &positions[index as usize];
+            if p.x * p.x + p.z * p.z < 1e-6 {
+                let vertex = icosphere_vertex(p, face_u, scale);
+                if poles.insert(index) {
+                    vertices[index as usize] = vertex;
+                    indices.push(index);
+                } else {
+                    vertices.push(vertex);
+                    indices.push((vertices.len() - 1) as u32);
+                }
+                continue;
+            }
+            let u = vertices[index as usize].2[0];
+            let turns = (face_u - u).round() as i32;
+            if turns == 0 {
+                indices.push(index);
+                continue;
+            }
+            let copy = *shifted.entry((index, turns)).or_insert_with(|| {
+                let mut vertex = vertices[index as usize];
+                vertex.2[0] = u + turns as f32;
+                vertices.push(vertex);
+                (vertices.len() - 1) as u32
+            });
+            indices.push(copy);
+        }
+    }
+
+    Ok(InternalShape {
+        vertices,
+        indices: Some(indices),
+    })
+}
+
+/// U coordinate of the spherical projection of the direction `p`, with the seam along -X.
+fn icosphere_u(p: &Vector3<f32>) -> f32 {
+    0.5 + p.z.atan2(p.x) / (2.0 * PI)
+}
+
+fn icosphere_vertex(
+    p: &Vector3<f32>,
+    u: f32,
+    scale: Option<(f32, f32, f32)>,
+) -> InternalVertexData {
+    let tex_coord = [u, 0.5 - p.y.max(-1.0).min(1.0).asin() / PI];
+    let pos = scale
+        .map(|(x, y, z)| Vector3::new(p.x * x, p.y * y, p.z * z))
+        .unwrap_or(*p);
+    let normal = scale
+        .map(|(x, y, z)| Vector3::new(p.x * x, p.y * y, p.z * z).normalize())
+        .unwrap_or(*p);
+    // Tangent follows the direction of increasing U around the Y axis, which at the poles is
+    // the one of the U given to the face.
+    let angle = (u - 0.5) * 2.0 * PI;
+    let tangent = Vector3::new(-normal.z, 0.0, normal.x);
+    let tangent = if tangent.norm_squared() > std::f32::EPSILON {
+        tangent.normalize()
+    } else {
+        Vector3::new(-angle.sin(), 0.0, angle.cos())
+    };
+    (
+        pos.into(),
+        normal.into(),
+        tex_coord,
+        [tangent.x, tangent.y, tangent.z, 1.0],
+    )
+}
+
+fn icosphere_midpoint(
+    positions: &mut Vec<Vector3<f32>>,
+    midpoints: &mut FnvHashMap<(u32, u32), u32>,
+    a: u32,
+    b: u32,
+) -> u32 {
+    let key = if a < b { (a, b) } else { (b, a) };
+    *midpoints.entry(key).or_insert_with(|| {
+        let mid = (positions[a as usize] + positions[b as usize]).normalize();
+        positions.push(mid);
+        (positions.len() - 1) as u32
+    })
+}
+
 fn generate_vertices<F, P, G>(
     generator: G,
     scale: Option<(f32, f32, f32)>,
@@ -283,7 +1022,7 @@ where
                     pos.into(),
                     normal.into(),
                     [(v.pos.x + 1.) / 2., (v.pos.y + 1.) / 2.],
-                    tangent.into(),
+                    [tangent.x, tangent.y, tangent.z, 1.0],
                 )
             })
         })
@@ -311,7 +1050,7 @@ impl FromInternalVertex for Normal {
 
 impl FromInternalVertex for Tangent {
     fn from_internal(v: &InternalVertexData) -> Self {
-        Tangent([v.3[0], v.3[1], v.3[2], 1.0])
+        Tangent(v.3)
     }
 }
 
@@ -342,6 +1081,12 @@ macro_rules! impl_nested_from {
             fn from(shape: &InternalShape) -> Self {
                 ($($from::from(shape),)*)
             }
+
+            fn vertex_formats() -> Vec<VertexFormat> {
+                let mut formats = Vec::new();
+                $(formats.extend($from::vertex_formats());)*
+                formats
+            }
         }
     }
 }
@@ -374,4 +1119,251 @@ mod tests {
             Shape::Plane(None).generate::<Vec<PosNormTangTex>>(None)
         );
     }
+
+    #[test]
+    fn icosphere_counts() {
+        let vertices = [15, 57, 183, 675, 2619, 10347];
+        for subdivisions in 0..=MAX_ICOSPHERE_SUBDIVISIONS {
+            let shape = Shape::IcoSphere(Some(subdivisions))
+                .generate_internal(None)
+                .unwrap();
+            let factor = 4usize.pow(subdivisions as u32);
+            assert_eq!(shape.vertices.len(), vertices[subdivisions]);
+            assert_eq!(shape.indices().unwrap().len(), 60 * factor);
+        }
+    }
+
+    #[test]
+    fn icosphere_vertices_unindexed() {
+        let shape = Shape::IcoSphere(Some(1));
+        let internal = shape.generate_internal(None).unwrap();
+        let vertices = shape.generate_vertices::<Vec<Position>>(None);
+        let indices = internal.indices().unwrap();
+        assert_eq!(vertices.len(), indices.len());
+        for (vertex, &index) in vertices.iter().zip(indices) {
+            assert_eq!(vertex.0, internal.vertices[index as usize].0);
+        }
+    }
+
+    #[test]
+    fn icosphere_too_many_subdivisions() {
+        assert_eq!(
+            Shape::IcoSphere(Some(MAX_ICOSPHERE_SUBDIVISIONS + 1))
+                .generate_internal(None)
+                .unwrap_err(),
+            MeshError::TooManySubdivisions {
+                requested: MAX_ICOSPHERE_SUBDIVISIONS + 1,
+                max: MAX_ICOSPHERE_SUBDIVISIONS,
+            }
+        );
+    }
+
+    #[test]
+    fn icosphere_unit_normals() {
+        let shape = Shape::IcoSphere(Some(3)).generate_internal(None).unwrap();
+        for (_, normal, _, _) in &shape.vertices {
+            assert!((Vector3::from(*normal).norm() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn icosphere_watertight() {
+        let shape = Shape::IcoSphere(Some(2)).generate_internal(None).unwrap();
+        let indices = shape.indices().unwrap();
+
+        // Seam and pole vertices are copies, so weld the vertices by position first.
+        let mut welded = FnvHashMap::default();
+        let key = |i: u32| {
+            let p = shape.vertices[i as usize].0;
+            let q = |c: f32| (c * 1e4).round() as i32;
+            (q(p[0]), q(p[1]), q(p[2]))
+        };
+        let ids = indices
+            .iter()
+            .map(|&i| {
+                let next = welded.len();
+                *welded.entry(key(i)).or_insert(next)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(welded.len(), 162);
+
+        // Every directed edge must appear exactly once, and its reverse exactly once, for a
+        // closed and consistently wound surface.
+        let mut edges = FnvHashMap::default();
+        for tri in ids.chunks(3) {
+            for &(a, b) in &[(tri[0], tri[1]), (tri[1], tri[2]), (tri[2], tri[0])] {
+                *edges.entry((a, b)).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+
+        // Faces are wound counter-clockwise when seen from outside.
+        for tri in indices.chunks(3) {
+            let p = |i: u32| Vector3::from(shape.vertices[i as usize].0);
+            let normal = (p(tri[1]) - p(tri[0])).cross(&(p(tri[2]) - p(tri[0])));
+            let centroid = p(tri[0]) + p(tri[1]) + p(tri[2]);
+            assert!(normal.dot(&centroid) > 0.0);
+        }
+    }
+
+    #[test]
+    fn icosphere_faces_do_not_span_the_seam() {
+        let shape = Shape::IcoSphere(Some(2)).generate_internal(None).unwrap();
+        for tri in shape.indices().unwrap().chunks(3) {
+            let u = |i: u32| shape.vertices[i as usize].2[0];
+            let (a, b, c) = (u(tri[0]), u(tri[1]), u(tri[2]));
+            assert!(a.max(b).max(c) - a.min(b).min(c) < 0.25);
+        }
+    }
+
+    #[test]
+    fn grid_layout() {
+        for &orientation in &[PlaneOrientation::XZ, PlaneOrientation::XY] {
+            let shape = Shape::Grid {
+                subdivisions: (4, 3),
+                uv_tiling: (2.0, 3.0),
+                orientation,
+            }
+            .generate_internal(None)
+            .unwrap();
+            assert_eq!(shape.vertices.len(), 5 * 4);
+            assert_eq!(shape.indices().unwrap().len(), 4 * 3 * 6);
+
+            let up = match orientation {
+                PlaneOrientation::XZ => Vector3::y(),
+                PlaneOrientation::XY => Vector3::z(),
+            };
+            for (_, normal, _, tangent) in &shape.vertices {
+                assert!((Vector3::from(*normal) - up).norm() < 1e-5);
+                let tangent = Vector3::new(tangent[0], tangent[1], tangent[2]);
+                assert!((tangent - Vector3::x()).norm() < 1e-5);
+            }
+            assert_eq!(shape.vertices.last().unwrap().2, [2.0, 3.0]);
+
+            // Triangles are wound counter-clockwise around the normal.
+            for tri in shape.indices().unwrap().chunks(3) {
+                let p = |i: u32| Vector3::from(shape.vertices[i as usize].0);
+                let normal = (p(tri[1]) - p(tri[0])).cross(&(p(tri[2]) - p(tri[0])));
+                assert!(normal.dot(&up) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn large_grid_uses_u32_indices() {
+        use rendy::hal::IndexType;
+
+        let shape = Shape::Grid {
+            subdivisions: (316, 316),
+            uv_tiling: (1.0, 1.0),
+            orientation: PlaneOrientation::XZ,
+        }
+        .generate_internal(None)
+        .unwrap();
+        assert_eq!(shape.vertices.len(), 317 * 317);
+
+        let indices = MeshIndices::U32(shape.indices().unwrap().to_vec());
+        assert_eq!(indices.max(), Some(317 * 317 - 1));
+        let indices = indices.compact(shape.vertices.len());
+        assert_eq!(indices.index_type(), IndexType::U32);
+        assert_eq!(indices.max(), Some(317 * 317 - 1));
+
+        // The built mesh keeps them as 32 bit, both in its index buffer and its layout.
+        let data = build_mesh_data::<Vec<PosNormTex>>(shape);
+        assert_eq!(data.1.as_ref().unwrap().index_type, Some(IndexType::U32));
+        let buffers = crate::mesh_reader::read(&data.0).unwrap();
+        assert_eq!(buffers.indices.unwrap().0, IndexType::U32);
+        assert_eq!(buffers.vertex_count(), 317 * 317);
+
+        let small = Shape::Grid {
+            subdivisions: (16, 16),
+            uv_tiling: (1.0, 1.0),
+            orientation: PlaneOrientation::XZ,
+        }
+        .generate_internal(None)
+        .unwrap();
+        let indices = MeshIndices::U32(small.indices().unwrap().to_vec());
+        assert_eq!(
+            indices.compact(small.vertices.len()).index_type(),
+            IndexType::U16
+        );
+        let data = build_mesh_data::<Vec<PosNormTex>>(small);
+        let buffers = crate::mesh_reader::read(&data.0).unwrap();
+        assert_eq!(buffers.indices.unwrap().0, IndexType::U16);
+    }
+
+    #[test]
+    fn grid_displaced_normals() {
+        let mut grid = PlaneGrid::new((2, 2), (1.0, 1.0), PlaneOrientation::XZ);
+        for p in grid.positions_mut() {
+            p.y = p.x;
+        }
+        let shape = grid.generate_internal();
+        let expected = Vector3::new(-1.0, 1.0, 0.0).normalize();
+        for (_, normal, _, _) in &shape.vertices {
+            assert!((Vector3::from(*normal) - expected).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn heightmap_terrain() {
+        assert_eq!(
+            Heightmap::new(1, 4, vec![0.0; 4]).unwrap_err(),
+            MeshError::InvalidGridSize { width: 1, depth: 4 }
+        );
+        assert!(Heightmap::new(3, 3, vec![0.0; 8]).is_err());
+
+        // A ramp rising along +X
+        let heights = (0..5 * 4).map(|i| (i % 5) as f32).collect::<Vec<_>>();
+        let heightmap = Heightmap::new(5, 4, heights).unwrap();
+        let shape = heightmap.generate_internal((8.0, 0.5, 6.0), (2.0, 2.0));
+        assert_eq!(shape.vertices.len(), 20);
+        assert_eq!(shape.indices().unwrap().len(), 4 * 3 * 6);
+
+        // Height rises by 0.5 every 2 units along x
+        let expected = Vector3::new(-1.0, 4.0, 0.0).normalize();
+        for (position, normal, _, _) in &shape.vertices {
+            assert!(position[0].abs() <= 4.0 && position[2].abs() <= 3.0);
+            assert!((Vector3::from(*normal) - expected).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn heightmap_chunks() {
+        let heights = (0..9 * 9)
+            .map(|i| (i as f32 * 0.1).sin())
+            .collect::<Vec<_>>();
+        let heightmap = Heightmap::new(9, 9, heights).unwrap();
+        let whole = heightmap.generate_internal((8.0, 1.0, 8.0), (1.0, 1.0));
+        let chunks = heightmap.generate_chunks(3, (8.0, 1.0, 8.0), (1.0, 1.0));
+
+        // 8 quads split into chunks of 3, 3 and 2 along both axes
+        assert_eq!(chunks.len(), 9);
+        let quads: usize = chunks
+            .iter()
+            .map(|c| c.shape.indices().unwrap().len() / 6)
+            .sum();
+        assert_eq!(quads, 64);
+
+        for chunk in &chunks {
+            let sphere = chunk.bounding_sphere.as_ref().unwrap();
+            for (position, normal, _, _) in &chunk.shape.vertices {
+                let center = Vector3::new(
+                    sphere.center.x.as_f32(),
+                    sphere.center.y.as_f32(),
+                    sphere.center.z.as_f32(),
+                );
+                let offset = Vector3::from(*position) - center;
+                assert!(offset.norm() <= sphere.radius.as_f32() + 1e-5);
+                // Chunk vertices share the normals of the whole terrain
+                assert!(whole
+                    .vertices
+                    .iter()
+                    .any(|v| v.0 == *position && v.1 == *normal));
+            }
+        }
+    }
 }
@@ -1,8 +1,20 @@
 //! Skinned mesh and bone implementation for renderer.
-use amethyst_assets::PrefabData;
+use crate::{
+    dynamic_mesh::DynamicMeshes,
+    error::MeshError,
+    mesh_util::ProceduralMesh,
+    types::Mesh,
+    visibility::{BoundingBox, BoundingSphere, MeshBoundingBoxes, MeshBoundingSpheres},
+};
+use amethyst_assets::{AssetStorage, Handle, Loader, PrefabData};
 use amethyst_core::{
-    ecs::prelude::{Component, DenseVecStorage, Entity, FlaggedStorage, WriteStorage},
-    math::Matrix4,
+    ecs::prelude::{
+        BitSet, Component, ComponentEvent, DenseVecStorage, Entities, Entity, FlaggedStorage, Join,
+        Read, ReadExpect, ReadStorage, ReaderId, Resources, System, SystemData, Write,
+        WriteStorage,
+    },
+    math::{convert, Matrix4, Point3, Vector3},
+    Float,
 };
 use amethyst_error::Error;
 use rendy::{
@@ -11,6 +23,9 @@ use rendy::{
 };
 use std::result::Result as StdResult;
 
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
 /// Type for joint weights attribute of vertex
 #[repr(C)]
 #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
@@ -120,3 +135,504 @@ impl<'a> PrefabData<'a> for JointTransformsPrefab {
         Ok(())
     }
 }
+
+/// Resource selecting where vertex skinning is performed.
+///
+/// With `SkinningPath::Cpu`, the mesh passes draw entities with `JointTransforms` like rigid
+/// meshes, and `CpuSkinningSystem` bakes the pose of entities with a `CpuSkinnedMesh` into their
+/// mesh instead. Entities without a `CpuSkinnedMesh` are then drawn in bind pose.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkinningPath {
+    /// Skin in the vertex shader, reading joints from the buffer of the `SkinningSub`.
+    Gpu,
+    /// Skin on the CPU, uploading the posed vertices through `DynamicMeshes`.
+    Cpu,
+}
+
+impl Default for SkinningPath {
+    fn default() -> Self {
+        SkinningPath::Gpu
+    }
+}
+
+/// Bind pose vertex data of a mesh skinned on the CPU, see `CpuSkinningSystem`.
+#[derive(Debug, Clone)]
+pub struct CpuSkinnedMesh {
+    base: ProceduralMesh,
+    joints: Vec<JointCombined>,
+    gpu_mesh: Option<Handle<Mesh>>,
+    posed: bool,
+    rebased: bool,
+}
+
+impl Component for CpuSkinnedMesh {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl CpuSkinnedMesh {
+    /// Create CPU skinning data for `base`, with the joints influencing each vertex.
+    pub fn new(base: ProceduralMesh, joints: Vec<JointCombined>) -> Result<Self, MeshError> {
+        if joints.len() != base.vertex_count() {
+            return Err(MeshError::AttributeCountMismatch {
+                attribute: "joints",
+                expected: base.vertex_count(),
+                found: joints.len(),
+            });
+        }
+        Ok(CpuSkinnedMesh {
+            base,
+            joints,
+            gpu_mesh: None,
+            posed: false,
+            rebased: false,
+        })
+    }
+
+    /// Replace the bind pose vertices, keeping the joints influencing each of them, so that the
+    /// next run of the `CpuSkinningSystem` poses the new vertices. The `MorphSystem` gives the
+    /// blended vertices of entities with `MorphTargets` this way.
+    pub fn set_base(&mut self, base: ProceduralMesh) -> Result<(), MeshError> {
+        if base.vertex_count() != self.joints.len() {
+            return Err(MeshError::AttributeCountMismatch {
+                attribute: "joints",
+                expected: base.vertex_count(),
+                found: self.joints.len(),
+            });
+        }
+        self.base = base;
+        self.rebased = true;
+        Ok(())
+    }
+
+    /// Returns the base mesh with its positions and normals moved by the joint `matrices`, like
+    /// the skinned vertex shaders do. Weights of joints missing from `matrices` are ignored.
+    ///
+    /// Tangents are kept as they are, unless the base mesh generates them.
+    pub fn pose(&self, matrices: &[Matrix4<f32>]) -> ProceduralMesh {
+        let blend = |joints: &JointCombined| {
+            let ids = joints.joint_ids.0.iter();
+            let weights = joints.joint_weights.0.iter();
+            ids.zip(weights)
+                .filter_map(|(id, weight)| matrices.get(*id as usize).map(|m| m * *weight))
+                .fold(Matrix4::zeros(), |sum, m| sum + m)
+        };
+
+        let mut mesh = self.base.clone();
+        let mut positions = Vec::with_capacity(self.joints.len());
+        let mut normals = mesh
+            .normals()
+            .map(|_| Vec::with_capacity(self.joints.len()));
+        for (i, joints) in self.joints.iter().enumerate() {
+            let matrix = blend(joints);
+            let position = Point3::from(Vector3::from(self.base.positions()[i]));
+            let position = matrix.transform_point(&position);
+            positions.push([position.x, position.y, position.z]);
+            if let (Some(normals), Some(base)) = (&mut normals, self.base.normals()) {
+                let normal = matrix.transform_vector(&Vector3::from(base[i]));
+                let normal = normal.try_normalize(std::f32::EPSILON).unwrap_or(normal);
+                normals.push([normal.x, normal.y, normal.z]);
+            }
+        }
+        mesh.set_positions(positions);
+        if let Some(normals) = normals {
+            mesh.set_normals(normals);
+        }
+        mesh
+    }
+}
+
+/// Bakes the pose of entities with a `CpuSkinnedMesh` into their `Handle<Mesh>` while the
+/// `SkinningPath` resource is `SkinningPath::Cpu`.
+///
+/// The first time an entity is posed, its mesh is replaced by a dynamic mesh registered with
+/// `DynamicMeshes`, and later poses go through `DynamicMeshes::update` whenever its
+/// `JointTransforms` change, or its bind pose is replaced by `CpuSkinnedMesh::set_base`. The
+/// `BoundingSphere` and `BoundingBox` of the entity are set from the posed vertices. The original
+/// mesh is given back when switching to `SkinningPath::Gpu`.
+///
+/// Note that this should run after the joint matrices have been updated for the current frame,
+/// and before rendering occurs.
+#[derive(Default, Debug)]
+pub struct CpuSkinningSystem {
+    updated: BitSet,
+    updated_id: Option<ReaderId<ComponentEvent>>,
+}
+
+impl CpuSkinningSystem {
+    /// Create new CPU skinning system
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for CpuSkinningSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, SkinningPath>,
+        ReadStorage<'a, JointTransforms>,
+        WriteStorage<'a, CpuSkinnedMesh>,
+        WriteStorage<'a, Handle<Mesh>>,
+        WriteStorage<'a, BoundingSphere>,
+        WriteStorage<'a, BoundingBox>,
+        Write<'a, DynamicMeshes>,
+        ReadExpect<'a, Loader>,
+        Read<'a, AssetStorage<Mesh>>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            path,
+            joint_transforms,
+            mut skinned,
+            mut meshes,
+            mut spheres,
+            mut boxes,
+            mut dynamic,
+            loader,
+            mesh_storage,
+        ): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("cpu_skinning_system");
+
+        self.updated.clear();
+        joint_transforms
+            .channel()
+            .read(self.updated_id.as_mut().expect(
+                "`CpuSkinningSystem::setup` was not called before `CpuSkinningSystem::run`",
+            ))
+            .for_each(|event| match event {
+                ComponentEvent::Inserted(id) | ComponentEvent::Modified(id) => {
+                    self.updated.add(*id);
+                }
+                ComponentEvent::Removed(_id) => {}
+            });
+
+        for (entity, joints, skinned) in (&entities, &joint_transforms, &mut skinned).join() {
+            if *path == SkinningPath::Gpu {
+                if skinned.posed {
+                    skinned.posed = false;
+                    match skinned.gpu_mesh.take() {
+                        Some(handle) => {
+                            if let Err(e) = meshes.insert(entity, handle) {
+                                log::error!("Failed to restore skinned mesh: {}", e);
+                            }
+                        }
+                        None => {
+                            meshes.remove(entity);
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let handle = meshes
+                .get(entity)
+                .filter(|h| skinned.posed && dynamic.contains(h))
+                .cloned();
+            if handle.is_some() && !self.updated.contains(entity.id()) && !skinned.rebased {
+                continue;
+            }
+            skinned.rebased = false;
+
+            let data = skinned.pose(&joints.matrices);
+            if let Some(sphere) = data.bounding_sphere() {
+                let _ = spheres.insert(entity, sphere);
+            }
+            if let Some(aabb) = data.bounding_box() {
+                let _ = boxes.insert(entity, aabb);
+            }
+
+            match handle {
+                Some(handle) => {
+                    if let Err(e) = dynamic.update(&handle, data) {
+                        log::error!("Failed to update skinned mesh: {}", e);
+                    }
+                }
+                None => {
+                    let mesh_data = match data.clone().build() {
+                        Ok(mesh_data) => mesh_data,
+                        Err(e) => {
+                            log::error!("Failed to build skinned mesh: {}", e);
+                            continue;
+                        }
+                    };
+                    let handle = loader.load_from_data(mesh_data, (), &mesh_storage);
+                    dynamic.register(&handle, data);
+                    match meshes.insert(entity, handle) {
+                        Ok(previous) => {
+                            if !skinned.posed {
+                                skinned.gpu_mesh = previous;
+                                skinned.posed = true;
+                            }
+                        }
+                        Err(e) => log::error!("Failed to insert skinned mesh: {}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        let mut joint_transforms = WriteStorage::<JointTransforms>::fetch(res);
+        self.updated_id = Some(joint_transforms.register_reader());
+    }
+}
+
+/// Returns a sphere enclosing every vertex of a mesh inside `bind` once skinned by `matrices`.
+///
+/// A skinned vertex is a weighted average of the vertex moved by each of its joints, so it lies
+/// within the union of `bind` moved by every joint.
+pub fn skinned_bounding_sphere(bind: &BoundingSphere, matrices: &[Matrix4<f32>]) -> BoundingSphere {
+    let center = Point3::new(
+        bind.center.x.as_f32(),
+        bind.center.y.as_f32(),
+        bind.center.z.as_f32(),
+    );
+    let radius = bind.radius.as_f32();
+    let spheres = matrices
+        .iter()
+        .map(|m| {
+            let scale = (0..3)
+                .map(|i| m.column(i).xyz().norm())
+                .fold(0.0f32, f32::max);
+            (m.transform_point(&center), radius * scale)
+        })
+        .collect::<Vec<_>>();
+    let first = match spheres.first() {
+        Some(first) => first.0.coords,
+        None => return bind.clone(),
+    };
+
+    let (min, max) = spheres.iter().fold((first, first), |(min, max), (c, _)| {
+        (min.inf(&c.coords), max.sup(&c.coords))
+    });
+    let union_center = (min + max) * 0.5;
+    let union_radius = spheres
+        .iter()
+        .map(|(c, r)| (c.coords - union_center).norm() + r)
+        .fold(0.0f32, f32::max);
+    BoundingSphere::new(
+        Point3::new(
+            union_center.x.into(),
+            union_center.y.into(),
+            union_center.z.into(),
+        ),
+        union_radius,
+    )
+}
+
+/// Returns a box enclosing every vertex of a mesh inside `bind` once skinned by `matrices`, see
+/// `skinned_bounding_sphere`.
+pub fn skinned_bounding_box(bind: &BoundingBox, matrices: &[Matrix4<f32>]) -> BoundingBox {
+    matrices
+        .iter()
+        .map(|m| bind.transform(&convert::<_, Matrix4<Float>>(*m)))
+        .fold(None, |union: Option<BoundingBox>, aabb| {
+            Some(match union {
+                Some(union) => union.union(&aabb),
+                None => aabb,
+            })
+        })
+        .unwrap_or_else(|| bind.clone())
+}
+
+/// Sets the `BoundingSphere` and `BoundingBox` of meshes skinned on the GPU from their current
+/// pose, so that they are not culled when moving away from their bind pose bounds.
+///
+/// The bind pose bounds are taken from `MeshBoundingSpheres` and `MeshBoundingBoxes`, and moved
+/// by every joint of the entity's `JointTransforms`, see `skinned_bounding_sphere`. Entities
+/// skinned by the `CpuSkinningSystem` get their bounds from their posed vertices instead.
+///
+/// Note that this should run after the joint matrices have been updated for the current frame,
+/// and before `VisibilitySortingSystem`.
+#[derive(Default, Debug)]
+pub struct SkinnedBoundsSystem;
+
+impl SkinnedBoundsSystem {
+    /// Create new skinned bounds system
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for SkinnedBoundsSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, SkinningPath>,
+        Read<'a, MeshBoundingSpheres>,
+        Read<'a, MeshBoundingBoxes>,
+        ReadStorage<'a, Handle<Mesh>>,
+        ReadStorage<'a, JointTransforms>,
+        ReadStorage<'a, CpuSkinnedMesh>,
+        WriteStorage<'a, BoundingSphere>,
+        WriteStorage<'a, BoundingBox>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            path,
+            mesh_spheres,
+            mesh_boxes,
+            meshes,
+            joint_transforms,
+            cpu_skinned,
+            mut spheres,
+            mut boxes,
+        ): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("skinned_bounds_system");
+
+        for (entity, mesh, joints) in (&entities, &meshes, &joint_transforms).join() {
+            if *path == SkinningPath::Cpu && cpu_skinned.contains(entity) {
+                continue;
+            }
+            if let Some(bind) = mesh_spheres.get(mesh) {
+                let _ = spheres.insert(entity, skinned_bounding_sphere(bind, &joints.matrices));
+            }
+            if let Some(bind) = mesh_boxes.get(mesh) {
+                let _ = boxes.insert(entity, skinned_bounding_box(bind, &joints.matrices));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{MeshBounds, MeshData};
+    use amethyst_core::{
+        ecs::{Builder, RunNow, World},
+        math::Rotation3,
+    };
+    use rendy::mesh::{MeshBuilder, PosNormTex};
+
+    fn two_joints() -> Vec<Matrix4<f32>> {
+        vec![
+            Matrix4::identity(),
+            Matrix4::new_translation(&Vector3::new(0.0, 0.0, 4.0))
+                * Rotation3::from_axis_angle(&Vector3::y_axis(), std::f32::consts::FRAC_PI_2)
+                    .to_homogeneous(),
+        ]
+    }
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-5)
+    }
+
+    #[test]
+    fn pose_blends_joints() {
+        let base = ProceduralMesh::new()
+            .with_positions(vec![[1.0, 0.0, 0.0], [1.0, 1.0, 0.0]])
+            .with_normals(vec![[1.0, 0.0, 0.0]; 2]);
+        let skinned = CpuSkinnedMesh::new(
+            base,
+            vec![
+                JointCombined::new([1, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]),
+                JointCombined::new([0, 1, 9, 0], [0.5, 0.5, 1.0, 0.0]),
+            ],
+        )
+        .unwrap();
+        let posed = skinned.pose(&two_joints());
+
+        assert!(close(posed.positions()[0], [0.0, 0.0, 3.0]));
+        assert!(close(posed.positions()[1], [0.5, 1.0, 1.5]));
+        assert!(close(posed.normals().unwrap()[0], [0.0, 0.0, -1.0]));
+        let half = 0.5f32.sqrt();
+        assert!(close(posed.normals().unwrap()[1], [half, 0.0, -half]));
+    }
+
+    #[test]
+    fn rejects_missing_joints() {
+        let base = ProceduralMesh::new().with_positions(vec![[0.0; 3]; 2]);
+        assert!(CpuSkinnedMesh::new(base, vec![]).is_err());
+    }
+
+    #[test]
+    fn skinned_bounds_enclose_pose() {
+        let base = ProceduralMesh::new().with_positions(vec![
+            [1.0, 0.0, 0.0],
+            [-1.0, 0.5, 0.0],
+            [0.0, -0.5, 1.0],
+        ]);
+        let bind_sphere = base.bounding_sphere().unwrap();
+        let bind_box = base.bounding_box().unwrap();
+        let skinned = CpuSkinnedMesh::new(
+            base,
+            vec![
+                JointCombined::new([1, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]),
+                JointCombined::new([0, 1, 0, 0], [0.3, 0.7, 0.0, 0.0]),
+                JointCombined::new([0, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]),
+            ],
+        )
+        .unwrap();
+        let matrices = two_joints();
+        let sphere = skinned_bounding_sphere(&bind_sphere, &matrices);
+        let aabb = skinned_bounding_box(&bind_box, &matrices);
+
+        for p in skinned.pose(&matrices).positions() {
+            let p: Point3<Float> = Point3::new(p[0].into(), p[1].into(), p[2].into());
+            assert!((p - sphere.center).norm().as_f32() <= sphere.radius.as_f32() + 1e-5);
+            for i in 0..3 {
+                assert!(p[i].as_f32() >= aabb.min[i].as_f32() - 1e-5);
+                assert!(p[i].as_f32() <= aabb.max[i].as_f32() + 1e-5);
+            }
+        }
+        assert_eq!(skinned_bounding_sphere(&bind_sphere, &[]), bind_sphere);
+    }
+
+    #[test]
+    fn loaded_mesh_bounds_are_skinned() {
+        // Laid out like the meshes of the glTF loader, with the joints next to the vertices.
+        let positions = [[1.0, 0.0, 0.0], [-1.0, 0.5, 0.0], [0.0, -0.5, 1.0]];
+        let mut builder = MeshBuilder::new();
+        builder.add_vertices(
+            positions
+                .iter()
+                .map(|&p| PosNormTex {
+                    position: p.into(),
+                    normal: [0.0, 1.0, 0.0].into(),
+                    tex_coord: [0.0, 0.0].into(),
+                })
+                .collect::<Vec<_>>(),
+        );
+        builder.add_vertices(vec![
+            JointCombined::new([1, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]);
+            3
+        ]);
+        let mut storage = AssetStorage::<Mesh>::default();
+        let mesh = storage.insert(Mesh::stub(&MeshData::from(builder)));
+
+        let mut world = World::new();
+        let mut system = SkinnedBoundsSystem::new();
+        RunNow::setup(&mut system, &mut world.res);
+        world.write_resource::<MeshBoundingSpheres>().sync(&storage);
+        world.write_resource::<MeshBoundingBoxes>().sync(&storage);
+        let skin = world.create_entity().build();
+        let entity = world
+            .create_entity()
+            .with(mesh)
+            .with(JointTransforms {
+                skin,
+                matrices: two_joints(),
+            })
+            .build();
+        system.run_now(&world.res);
+
+        let bind = MeshBounds::of(&positions).unwrap();
+        assert_eq!(
+            world.read_storage::<BoundingSphere>().get(entity),
+            Some(&skinned_bounding_sphere(&bind.sphere, &two_joints()))
+        );
+        assert_eq!(
+            world.read_storage::<BoundingBox>().get(entity),
+            Some(&skinned_bounding_box(&bind.aabb, &two_joints()))
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! Loose grid over entities with static transforms, used to speed up frustum culling.
+use crate::visibility::{BoundingBox, BoundingSphere, Frustum, WorldBounds};
+use amethyst_core::{
+    ecs::prelude::{
+        BitSet, ComponentEvent, Entities, Entity, Join, ReadStorage, ReaderId, Resources,
+        SystemData, WriteStorage,
+    },
+    math::Point3,
+    shrev::EventChannel,
+    Float, Transform,
+};
+use derivative::Derivative;
+use fnv::FnvHashMap;
+
+/// Entities are bucketed by the cell containing their centroid. Each cell keeps the box enclosing
+/// the bounds of all of its entities, so a cell is skipped as a whole when that box is outside
+/// of the frustum.
+///
+/// An entity is moved into the grid once its `Transform`, `BoundingSphere` and `BoundingBox`
+/// went unmodified for `settle_frames` frames, and taken out of it as soon as any of them is
+/// inserted, modified or removed. Entities outside of the grid are reported by `dynamic`.
+#[derive(Derivative)]
+#[derivative(Debug)]
+pub(crate) struct StaticGrid {
+    cell_size: Float,
+    settle_frames: u32,
+    cells: FnvHashMap<[i32; 3], Cell>,
+    locations: FnvHashMap<u32, [i32; 3]>,
+    dynamic: BitSet,
+    quiet: FnvHashMap<u32, u32>,
+    initialized: bool,
+    #[derivative(Debug = "ignore")]
+    transform_events: Option<ReaderId<ComponentEvent>>,
+    #[derivative(Debug = "ignore")]
+    sphere_events: Option<ReaderId<ComponentEvent>>,
+    #[derivative(Debug = "ignore")]
+    box_events: Option<ReaderId<ComponentEvent>>,
+}
+
+#[derive(Debug)]
+struct Cell {
+    bounds: BoundingBox,
+    entries: Vec<(Entity, WorldBounds)>,
+}
+
+impl Cell {
+    fn update_bounds(&mut self) {
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|(_, bounds)| bounds.enclosing_box());
+        if let Some(first) = entries.next() {
+            self.bounds = entries.fold(first, |acc, b| acc.union(&b));
+        }
+    }
+}
+
+fn read_changes(
+    channel: &EventChannel<ComponentEvent>,
+    reader: &mut ReaderId<ComponentEvent>,
+    changed: &mut BitSet,
+) {
+    for event in channel.read(reader) {
+        match event {
+            ComponentEvent::Inserted(id)
+            | ComponentEvent::Modified(id)
+            | ComponentEvent::Removed(id) => {
+                changed.add(*id);
+            }
+        }
+    }
+}
+
+impl StaticGrid {
+    pub(crate) fn new(cell_size: f32, settle_frames: u32) -> Self {
+        assert!(cell_size > 0.0, "Grid cell size must be positive");
+        Self {
+            cell_size: cell_size.into(),
+            settle_frames,
+            cells: FnvHashMap::default(),
+            locations: FnvHashMap::default(),
+            dynamic: BitSet::new(),
+            quiet: FnvHashMap::default(),
+            initialized: false,
+            transform_events: None,
+            sphere_events: None,
+            box_events: None,
+        }
+    }
+
+    pub(crate) fn setup(&mut self, res: &mut Resources) {
+        self.transform_events = Some(WriteStorage::<Transform>::fetch(res).register_reader());
+        self.sphere_events = Some(WriteStorage::<BoundingSphere>::fetch(res).register_reader());
+        self.box_events = Some(WriteStorage::<BoundingBox>::fetch(res).register_reader());
+    }
+
+    /// Entities with a `Transform` that are not in the grid.
+    pub(crate) fn dynamic(&self) -> &BitSet {
+        &self.dynamic
+    }
+
+    /// Number of entities in the grid.
+    pub(crate) fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    /// Apply the changes since the last call, moving entities in and out of the grid.
+    pub(crate) fn maintain(
+        &mut self,
+        entities: &Entities<'_>,
+        transforms: &ReadStorage<'_, Transform>,
+        spheres: &ReadStorage<'_, BoundingSphere>,
+        boxes: &ReadStorage<'_, BoundingBox>,
+    ) {
+        const MISSING_SETUP: &str =
+            "`VisibilitySortingSystem::setup` was not called before `VisibilitySortingSystem::run`";
+
+        for quiet in self.quiet.values_mut() {
+            *quiet += 1;
+        }
+
+        let mut changed = BitSet::new();
+        read_changes(
+            transforms.channel(),
+            self.transform_events.as_mut().expect(MISSING_SETUP),
+            &mut changed,
+        );
+        read_changes(
+            spheres.channel(),
+            self.sphere_events.as_mut().expect(MISSING_SETUP),
+            &mut changed,
+        );
+        read_changes(
+            boxes.channel(),
+            self.box_events.as_mut().expect(MISSING_SETUP),
+            &mut changed,
+        );
+        if !self.initialized {
+            // Entities created before `setup` sent no events
+            for (entity, _) in (&**entities, transforms).join() {
+                changed.add(entity.id());
+            }
+            self.initialized = true;
+        }
+        for id in (&changed).join() {
+            self.remove(id);
+            self.dynamic.add(id);
+            self.quiet.insert(id, 0);
+        }
+
+        let mut settled = Vec::new();
+        for id in (&self.dynamic).join() {
+            let entity = entities.entity(id);
+            if !entities.is_alive(entity) || !transforms.contains(entity) {
+                settled.push((id, None));
+            } else if self
+                .quiet
+                .get(&id)
+                .map_or(true, |q| *q >= self.settle_frames)
+            {
+                settled.push((id, Some(entity)));
+            }
+        }
+        for (id, entity) in settled {
+            self.dynamic.remove(id);
+            self.quiet.remove(&id);
+            if let Some(entity) = entity {
+                let bounds = WorldBounds::new(
+                    transforms.get(entity).expect("Checked above"),
+                    spheres.get(entity),
+                    boxes.get(entity),
+                );
+                self.insert(entity, bounds);
+            }
+        }
+    }
+
+    /// Call `f` with every entity in the grid whose bounds are within `frustum`.
+    pub(crate) fn query(&self, frustum: &Frustum, mut f: impl FnMut(Entity, &WorldBounds)) {
+        for cell in self.cells.values() {
+            if !frustum.check_box(&cell.bounds.min, &cell.bounds.max) {
+                continue;
+            }
+            for (entity, bounds) in &cell.entries {
+                if bounds.check(frustum) {
+                    f(*entity, bounds);
+                }
+            }
+        }
+    }
+
+    fn cell_of(&self, point: &Point3<Float>) -> [i32; 3] {
+        let cell = |v: Float| (v / self.cell_size).as_f32().floor() as i32;
+        [cell(point.x), cell(point.y), cell(point.z)]
+    }
+
+    fn insert(&mut self, entity: Entity, bounds: WorldBounds) {
+        let key = self.cell_of(&bounds.centroid());
+        let enclosing = bounds.enclosing_box();
+        self.locations.insert(entity.id(), key);
+        let cell = self.cells.entry(key).or_insert_with(|| Cell {
+            bounds: enclosing.clone(),
+            entries: Vec::new(),
+        });
+        cell.bounds = cell.bounds.union(&enclosing);
+        cell.entries.push((entity, bounds));
+    }
+
+    fn remove(&mut self, id: u32) {
+        let key = match self.locations.remove(&id) {
+            Some(key) => key,
+            None => return,
+        };
+        let empty = match self.cells.get_mut(&key) {
+            Some(cell) => {
+                cell.entries.retain(|(entity, _)| entity.id() != id);
+                cell.update_bounds();
+                cell.entries.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            self.cells.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst_core::{
+        ecs::prelude::{Builder, World},
+        math::Matrix4,
+    };
+
+    fn maintain(grid: &mut StaticGrid, world: &World) {
+        grid.maintain(
+            &world.entities(),
+            &world.read_storage(),
+            &world.read_storage(),
+            &world.read_storage(),
+        );
+    }
+
+    #[test]
+    fn entities_settle_into_grid() {
+        let mut world = World::new();
+        world.register::<Transform>();
+        world.register::<BoundingSphere>();
+        world.register::<BoundingBox>();
+        let mut grid = StaticGrid::new(10.0, 2);
+        grid.setup(&mut world.res);
+
+        let a = world.create_entity().with(Transform::default()).build();
+        let b = world.create_entity().with(Transform::default()).build();
+        for _ in 0..2 {
+            maintain(&mut grid, &world);
+            assert_eq!(grid.len(), 0);
+        }
+        maintain(&mut grid, &world);
+        assert_eq!(grid.len(), 2);
+        assert_eq!(grid.dynamic().join().count(), 0);
+
+        let mut found = Vec::new();
+        grid.query(&Frustum::new(Matrix4::identity()), |entity, _| {
+            found.push(entity)
+        });
+        found.sort();
+        assert_eq!(found, vec![a, b]);
+
+        world
+            .write_storage::<Transform>()
+            .get_mut(a)
+            .unwrap()
+            .set_translation_x(5.0);
+        maintain(&mut grid, &world);
+        assert_eq!(grid.len(), 1);
+        assert!(grid.dynamic().contains(a.id()));
+
+        world.delete_entity(b).unwrap();
+        world.maintain();
+        maintain(&mut grid, &world);
+        assert_eq!(grid.len(), 0);
+        assert!(!grid.dynamic().contains(b.id()));
+    }
+}
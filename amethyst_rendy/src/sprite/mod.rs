@@ -2,11 +2,19 @@
 use ron::de::from_bytes as from_ron_bytes;
 use serde::{Deserialize, Serialize};
 
-use crate::{error, types::Texture};
+use crate::{
+    error,
+    pod::{linear_to_srgb, srgb_to_linear},
+    resources::Tint,
+    transparent::BlendMode,
+    types::Texture,
+};
 use amethyst_assets::{Asset, Format, Handle};
 use amethyst_core::ecs::prelude::{Component, DenseVecStorage};
 use amethyst_error::Error;
+use rendy::hal::pso;
 
+pub mod persist;
 pub mod prefab;
 
 /// An asset handle to sprite sheet metadata.
@@ -21,6 +29,61 @@ pub struct SpriteSheet {
     pub texture: Handle<Texture>,
     /// A list of sprites in this sprite sheet.
     pub sprites: Vec<Sprite>,
+    /// How the colors of the texture are stored, which selects how transparent sprites blend.
+    pub alpha_mode: AlphaMode,
+}
+
+/// How the colors of a sprite sheet texture relate to their alpha.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AlphaMode {
+    /// Colors are multiplied by their alpha, which `ImageFormat` does by default when images
+    /// are decoded. Blends with `(One, OneMinusSrcAlpha)`, so filtering can't bleed the color of
+    /// transparent texels into the edges of sprites.
+    Premultiplied,
+    /// Colors are stored independently of their alpha, as loaded with `premultiply_alpha`
+    /// disabled. Blends with `(SrcAlpha, OneMinusSrcAlpha)`.
+    Straight,
+}
+
+impl Default for AlphaMode {
+    fn default() -> Self {
+        AlphaMode::Premultiplied
+    }
+}
+
+impl AlphaMode {
+    /// The `BlendMode` of transparent sprites of this mode without one.
+    pub fn blend_mode(self) -> BlendMode {
+        match self {
+            AlphaMode::Premultiplied => BlendMode::Premultiplied,
+            AlphaMode::Straight => BlendMode::Alpha,
+        }
+    }
+
+    /// Blend state of transparent sprites.
+    pub fn blend_state(self) -> pso::BlendState {
+        match self {
+            AlphaMode::Premultiplied => pso::BlendState::PREMULTIPLIED_ALPHA,
+            AlphaMode::Straight => pso::BlendState::ALPHA,
+        }
+    }
+
+    /// The `Tint` multiplying texels of this mode. A premultiplied texel blends correctly with
+    /// a tint only if the tint is premultiplied as well, in linear space like the texels.
+    pub fn tint(self, tint: Option<&Tint>) -> Option<Tint> {
+        match self {
+            AlphaMode::Premultiplied => tint.map(|tint| {
+                let mut color = tint.0;
+                let alpha = color.alpha;
+                let premultiply = |c: f32| linear_to_srgb(srgb_to_linear(c) * alpha);
+                color.red = premultiply(color.red);
+                color.green = premultiply(color.green);
+                color.blue = premultiply(color.blue);
+                Tint(color)
+            }),
+            AlphaMode::Straight => tint.cloned(),
+        }
+    }
 }
 
 impl Asset for SpriteSheet {
@@ -449,7 +512,7 @@ impl SpriteGrid {
 /// #   let texture_storage = world.read_resource::<AssetStorage<Texture>>();
 /// let texture_handle = loader.load(
 ///     "my_texture.png",
-///     ImageFormat(Default::default()),
+///     ImageFormat::default(),
 ///     (),
 ///     &texture_storage,
 /// );
@@ -476,13 +539,90 @@ impl Format<SpriteSheet> for SpriteSheetFormat {
         Ok(SpriteSheet {
             texture: self.0.clone(),
             sprites: sprite_list.build_sprites(),
+            alpha_mode: AlphaMode::default(),
         })
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{Sprite, TextureCoordinates};
+    use super::{AlphaMode, Sprite, TextureCoordinates};
+    use crate::resources::Tint;
+    use palette::Srgba;
+
+    /// Color written by the blend state of `mode` when drawing `src` over an opaque `dst`.
+    fn blend(mode: AlphaMode, src: [f32; 4], dst: f32) -> f32 {
+        let src_factor = match mode {
+            AlphaMode::Premultiplied => 1.0,
+            AlphaMode::Straight => src[3],
+        };
+        src[0] * src_factor + dst * (1.0 - src[3])
+    }
+
+    /// Color sampled halfway between two texels with linear filtering, multiplied by a tint.
+    fn sample(mode: AlphaMode, a: [f32; 4], b: [f32; 4], tint: Option<&Tint>) -> [f32; 4] {
+        let tint: [f32; 4] = mode.tint(tint).map_or([1.0; 4], Into::into);
+        let mut color = [0.0; 4];
+        for (i, c) in color.iter_mut().enumerate() {
+            *c = (a[i] + b[i]) / 2.0 * tint[i];
+        }
+        color
+    }
+
+    #[test]
+    fn premultiplied_edges_do_not_darken() {
+        // A white sprite on a transparent background, exported with black transparent pixels
+        let opaque = [1.0; 4];
+        let transparent = [0.0; 4];
+
+        // Linear filtering bleeds the black into the edge of straight alpha sprites
+        let straight = sample(AlphaMode::Straight, opaque, transparent, None);
+        assert!((blend(AlphaMode::Straight, straight, 1.0) - 0.75).abs() < 1e-6);
+
+        let premultiplied = sample(AlphaMode::Premultiplied, opaque, transparent, None);
+        assert!((blend(AlphaMode::Premultiplied, premultiplied, 1.0) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn premultiplied_tint_matches_straight_tint() {
+        let tint = Tint(Srgba::new(1.0, 0.5, 0.25, 0.5));
+        let premultiplied: [f32; 4] = AlphaMode::Premultiplied.tint(Some(&tint)).unwrap().into();
+        let straight: [f32; 4] = AlphaMode::Straight.tint(Some(&tint)).unwrap().into();
+        // The tints are uploaded linear, 50% sRGB gray being about 21.4%
+        assert!((straight[1] - 0.214_041_14).abs() < 1e-6);
+        for (p, s) in premultiplied.iter().zip(&straight).take(3) {
+            assert!((p - s * 0.5).abs() < 1e-5);
+        }
+        assert!((premultiplied[3] - 0.5).abs() < 1e-6);
+        assert!(AlphaMode::Premultiplied.tint(None).is_none());
+
+        // An opaque, faded texel blends the same in both modes
+        let texel = [0.8, 0.8, 0.8, 1.0];
+        for &dst in &[0.0, 0.3, 1.0] {
+            let straight = sample(AlphaMode::Straight, texel, texel, Some(&tint));
+            let premultiplied = sample(AlphaMode::Premultiplied, texel, texel, Some(&tint));
+            assert!(
+                (blend(AlphaMode::Straight, straight, dst)
+                    - blend(AlphaMode::Premultiplied, premultiplied, dst))
+                .abs()
+                    < 1e-6
+            );
+        }
+    }
+
+    #[test]
+    fn alpha_mode_defaults_to_premultiplied() {
+        use rendy::hal::pso::BlendState;
+
+        assert_eq!(AlphaMode::default(), AlphaMode::Premultiplied);
+        assert_eq!(
+            AlphaMode::Premultiplied.blend_state(),
+            BlendState::PREMULTIPLIED_ALPHA
+        );
+        assert_eq!(AlphaMode::Straight.blend_state(), BlendState::ALPHA);
+        let mode: AlphaMode = ron::de::from_str("Straight").unwrap();
+        assert_eq!(mode, AlphaMode::Straight);
+    }
 
     #[test]
     fn texture_coordinates_from_tuple_maps_fields_correctly() {
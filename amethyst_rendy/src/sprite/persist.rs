@@ -0,0 +1,236 @@
+//! Serializable sprite renders, referring to their sheet by asset path.
+//!
+//! The handle of a `SpriteRender` only means something to the running game, so save games and
+//! replication store a `SpriteRenderSerde` in its place, with the asset paths of its sheet and of
+//! the texture of the sheet. They're known from the `SpriteSheetPaths` resource, where sheets are
+//! registered when they're loaded. Resolving a `SpriteRenderSerde` gives the handle of the sheet
+//! registered at its path, or loads the sheet and registers it first.
+//!
+//! Sheets that failed to load and sprite numbers out of the range of a loaded sheet are returned
+//! as a `SpriteSheetPathError` with the path of the sheet, instead of being drawn with the
+//! fallback texture or skipped by the passes. Loading is asynchronous, so check the sheets again
+//! with `SpriteSheetPaths::check` once the `ProgressCounter` completed.
+use crate::{
+    error::SpriteSheetPathError,
+    formats::texture::ImageFormat,
+    sprite::{SpriteRender, SpriteSheet, SpriteSheetFormat},
+    types::Texture,
+};
+use amethyst_assets::{AssetStorage, Handle, Loader, ProgressCounter};
+use fnv::FnvHashMap;
+use serde::{Deserialize, Serialize};
+
+/// Serializable mirror of a `SpriteRender`, with its sheet by asset path.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SpriteRenderSerde {
+    /// Asset path of the sprite sheet, in the format of `SpriteSheetFormat`
+    pub sheet: String,
+    /// Asset path of the texture of the sprite sheet, loaded with the default `ImageFormat`
+    pub texture: String,
+    /// Index of the sprite on the sprite sheet
+    pub sprite_number: usize,
+}
+
+impl SpriteRenderSerde {
+    /// The mirror of `render`, whose sheet must be registered in `paths`.
+    pub fn from_sprite_render(
+        render: &SpriteRender,
+        paths: &SpriteSheetPaths,
+    ) -> Result<Self, SpriteSheetPathError> {
+        let registered = paths.paths.get(&render.sprite_sheet.id()).ok_or_else(|| {
+            SpriteSheetPathError::Unregistered {
+                sprite_sheet: render.sprite_sheet.id(),
+            }
+        })?;
+        Ok(Self {
+            sheet: registered.sheet.clone(),
+            texture: registered.texture.clone(),
+            sprite_number: render.sprite_number,
+        })
+    }
+
+    /// The `SpriteRender` of the sheet registered at `sheet`, loading and registering the sheet
+    /// if there's none. Fails if the sheet failed to load, or if the sprite number is out of its
+    /// range once it's loaded.
+    pub fn resolve(
+        &self,
+        paths: &mut SpriteSheetPaths,
+        loader: &Loader,
+        progress: &mut ProgressCounter,
+        textures: &AssetStorage<Texture>,
+        sheets: &AssetStorage<SpriteSheet>,
+    ) -> Result<SpriteRender, SpriteSheetPathError> {
+        let render = SpriteRender {
+            sprite_sheet: paths.load(
+                &self.sheet,
+                &self.texture,
+                loader,
+                progress,
+                textures,
+                sheets,
+            ),
+            sprite_number: self.sprite_number,
+        };
+        paths.check(&render, sheets)?;
+        Ok(render)
+    }
+}
+
+/// The asset paths of a registered sheet.
+#[derive(Clone, Debug)]
+struct RegisteredPaths {
+    sheet: String,
+    texture: String,
+}
+
+/// Resource with the asset paths of the sprite sheets, to save and resolve `SpriteRenderSerde`s.
+#[derive(Debug, Default)]
+pub struct SpriteSheetPaths {
+    handles: FnvHashMap<String, Handle<SpriteSheet>>,
+    paths: FnvHashMap<u32, RegisteredPaths>,
+}
+
+impl SpriteSheetPaths {
+    /// Register `handle` as the sheet loaded from `sheet`, with its texture loaded from `texture`.
+    pub fn insert(
+        &mut self,
+        sheet: impl Into<String>,
+        texture: impl Into<String>,
+        handle: Handle<SpriteSheet>,
+    ) {
+        let sheet = sheet.into();
+        if let Some(old) = self.handles.insert(sheet.clone(), handle.clone()) {
+            self.paths.remove(&old.id());
+        }
+        self.paths.insert(
+            handle.id(),
+            RegisteredPaths {
+                sheet,
+                texture: texture.into(),
+            },
+        );
+    }
+
+    /// The handle of the sheet registered at `sheet`, after loading it with its texture at
+    /// `texture` if there's none.
+    pub fn load(
+        &mut self,
+        sheet: &str,
+        texture: &str,
+        loader: &Loader,
+        progress: &mut ProgressCounter,
+        textures: &AssetStorage<Texture>,
+        sheets: &AssetStorage<SpriteSheet>,
+    ) -> Handle<SpriteSheet> {
+        if let Some(handle) = self.handles.get(sheet) {
+            return handle.clone();
+        }
+        let texture_handle = loader.load(texture, ImageFormat::default(), &mut *progress, textures);
+        let handle = loader.load(sheet, SpriteSheetFormat(texture_handle), progress, sheets);
+        self.insert(sheet, texture, handle.clone());
+        handle
+    }
+
+    /// The handle of the sheet registered at `sheet`.
+    pub fn handle(&self, sheet: &str) -> Option<&Handle<SpriteSheet>> {
+        self.handles.get(sheet)
+    }
+
+    /// The asset path of the sheet of `handle`, if it's registered.
+    pub fn path(&self, handle: &Handle<SpriteSheet>) -> Option<&str> {
+        self.paths
+            .get(&handle.id())
+            .map(|paths| paths.sheet.as_str())
+    }
+
+    /// Check that the sheet of `render` didn't fail to load, and that its sprite number is in
+    /// its range if it's loaded.
+    pub fn check(
+        &self,
+        render: &SpriteRender,
+        sheets: &AssetStorage<SpriteSheet>,
+    ) -> Result<(), SpriteSheetPathError> {
+        let path =
+            self.path(&render.sprite_sheet)
+                .ok_or_else(|| SpriteSheetPathError::Unregistered {
+                    sprite_sheet: render.sprite_sheet.id(),
+                })?;
+        check_sprite(
+            path,
+            sheets.failed(&render.sprite_sheet).is_some(),
+            sheets.get(&render.sprite_sheet).map(|s| s.sprites.len()),
+            render.sprite_number,
+        )
+    }
+
+    /// The registered sheets that failed to load, with their paths.
+    pub fn failures(&self, sheets: &AssetStorage<SpriteSheet>) -> Vec<SpriteSheetPathError> {
+        let mut failures: Vec<_> = self
+            .handles
+            .iter()
+            .filter(|(_, handle)| sheets.failed(handle).is_some())
+            .map(|(path, _)| SpriteSheetPathError::Missing { path: path.clone() })
+            .collect();
+        failures.sort_by(|a, b| a.to_string().cmp(&b.to_string()));
+        failures
+    }
+}
+
+/// Check a sprite of the sheet at `path`, which has `sprite_count` sprites once it's loaded.
+fn check_sprite(
+    path: &str,
+    failed: bool,
+    sprite_count: Option<usize>,
+    sprite_number: usize,
+) -> Result<(), SpriteSheetPathError> {
+    if failed {
+        return Err(SpriteSheetPathError::Missing {
+            path: path.to_string(),
+        });
+    }
+    match sprite_count {
+        Some(sprite_count) if sprite_number >= sprite_count => {
+            Err(SpriteSheetPathError::SpriteOutOfRange {
+                path: path.to_string(),
+                sprite_number,
+                sprite_count,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprites_are_checked_with_their_path() {
+        let render = SpriteRenderSerde {
+            sheet: "sprites/hero.ron".into(),
+            texture: "sprites/hero.png".into(),
+            sprite_number: 3,
+        };
+        let ser = ron::ser::to_string(&render).unwrap();
+        assert_eq!(
+            ron::de::from_str::<SpriteRenderSerde>(&ser).unwrap(),
+            render
+        );
+
+        assert_eq!(check_sprite(&render.sheet, false, None, 3), Ok(()));
+        assert_eq!(check_sprite(&render.sheet, false, Some(4), 3), Ok(()));
+        assert_eq!(
+            check_sprite(&render.sheet, false, Some(3), 3),
+            Err(SpriteSheetPathError::SpriteOutOfRange {
+                path: "sprites/hero.ron".into(),
+                sprite_number: 3,
+                sprite_count: 3,
+            })
+        );
+        let missing = check_sprite(&render.sheet, true, None, 3).unwrap_err();
+        assert_eq!(
+            missing.to_string(),
+            "Sprite sheet \"sprites/hero.ron\" failed to load"
+        );
+    }
+}
@@ -1,7 +1,7 @@
 //! 2D Sprite specific prefabs.
 use crate::{
     formats::texture::TexturePrefab,
-    sprite::{SpriteRender, SpriteSheet, Sprites},
+    sprite::{AlphaMode, SpriteRender, SpriteSheet, Sprites},
 };
 use amethyst_assets::{AssetStorage, Handle, Loader, PrefabData, ProgressCounter};
 use amethyst_core::{
@@ -31,6 +31,9 @@ pub enum SpriteSheetPrefab {
         sprites: Vec<Sprites>,
         /// The name of the spritesheet to refer to it
         name: Option<String>,
+        /// How the colors of the texture are stored
+        #[serde(default)]
+        alpha_mode: AlphaMode,
     },
 }
 
@@ -67,6 +70,7 @@ impl<'a> PrefabData<'a> for SpriteSheetPrefab {
             texture,
             sprites,
             name,
+            alpha_mode,
         } = self
         {
             texture.load_sub_assets(progress, tex_data)?;
@@ -78,6 +82,7 @@ impl<'a> PrefabData<'a> for SpriteSheetPrefab {
             let spritesheet = SpriteSheet {
                 texture: texture_handle,
                 sprites,
+                alpha_mode: *alpha_mode,
             };
 
             let handle = loader.load_from_data(spritesheet, progress, &storage);
@@ -290,6 +295,7 @@ mod tests {
                 SpriteSheet {
                     texture,
                     sprites: vec![],
+                    alpha_mode: AlphaMode::default(),
                 },
                 (),
                 &data.1,
@@ -352,6 +358,7 @@ mod tests {
             })],
             texture: TexturePrefab::Handle(texture.clone()),
             name: None,
+            alpha_mode: AlphaMode::default(),
         };
         prefab
             .load_sub_assets(&mut ProgressCounter::default(), &mut world.system_data())
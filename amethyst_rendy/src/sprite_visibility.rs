@@ -1,7 +1,13 @@
 //! Transparency, visibility sorting and camera centroid culling for 2D Sprites.
 use crate::{
-    camera::{ActiveCamera, Camera},
-    transparent::Transparent,
+    camera::{ActiveCamera, Camera, CullingCamera},
+    fade_in::AssetReadiness,
+    layers::RenderLayers,
+    resources::Tint,
+    transparent::{NoTintTransparency, TintTransparency, TransparencySortKey, Transparent},
+    visibility::{
+        apply_draw_distance, tint_alpha, DrawDistance, NoCull, VisibilityCounts, VisibilityStats,
+    },
 };
 use amethyst_core::{
     ecs::prelude::{Entities, Entity, Join, Read, ReadStorage, System, Write},
@@ -9,8 +15,9 @@ use amethyst_core::{
     Float, Hidden, HiddenPropagate, Transform,
 };
 use derivative::Derivative;
+use fnv::FnvHashMap;
 use hibitset::BitSet;
-use std::cmp::Ordering;
+use std::{cmp::Ordering, time::Instant};
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
@@ -23,11 +30,25 @@ pub struct SpriteVisibility {
     pub visible_unordered: BitSet,
     /// Visible entities that need to be drawn in the given order
     pub visible_ordered: Vec<Entity>,
+    /// Alpha factor of entities fading out near their `DrawDistance` or fading in with
+    /// `FadeInOnLoad`, always below one.
+    ///
+    /// Fading entities are drawn ordered, even when they are not `Transparent`.
+    pub fade: FnvHashMap<Entity, f32>,
+}
+
+impl SpriteVisibility {
+    /// Alpha factor the tint of `entity` is multiplied with, one unless it is fading.
+    pub fn fade(&self, entity: Entity) -> f32 {
+        self.fade.get(&entity).cloned().unwrap_or(1.0)
+    }
 }
 
 /// Determines what entities to be drawn. Will also sort transparent entities back to front based on
 /// position on the Z axis.
 ///
+/// The camera is the one of `CullingCamera` if set, of `ActiveCamera` otherwise.
+///
 /// The sprite render pass should draw all sprites without semi-transparent pixels, then draw the
 /// sprites with semi-transparent pixels from far to near.
 ///
@@ -38,6 +59,8 @@ pub struct SpriteVisibility {
 pub struct SpriteVisibilitySortingSystem {
     centroids: Vec<Internals>,
     transparent: Vec<Internals>,
+    culled: BitSet,
+    tint_transparency: Option<TintTransparency>,
 }
 
 #[derive(Debug, Clone)]
@@ -47,6 +70,8 @@ struct Internals {
     centroid: Point3<Float>,
     camera_distance: Float,
     from_camera: Vector3<Float>,
+    fade: f32,
+    sort_key: i32,
 }
 
 impl SpriteVisibilitySortingSystem {
@@ -54,6 +79,12 @@ impl SpriteVisibilitySortingSystem {
     pub fn new() -> Self {
         Default::default()
     }
+
+    /// Draw sprites with a translucent `Tint` as transparent, as if they were `Transparent`.
+    pub fn with_tint_transparency(mut self, tint_transparency: TintTransparency) -> Self {
+        self.tint_transparency = Some(tint_transparency);
+        self
+    }
 }
 
 impl<'a> System<'a> for SpriteVisibilitySortingSystem {
@@ -63,24 +94,56 @@ impl<'a> System<'a> for SpriteVisibilitySortingSystem {
         ReadStorage<'a, Hidden>,
         ReadStorage<'a, HiddenPropagate>,
         Read<'a, ActiveCamera>,
+        Read<'a, CullingCamera>,
         ReadStorage<'a, Camera>,
         ReadStorage<'a, Transparent>,
+        ReadStorage<'a, TransparencySortKey>,
         ReadStorage<'a, Transform>,
+        ReadStorage<'a, RenderLayers>,
+        ReadStorage<'a, DrawDistance>,
+        ReadStorage<'a, NoCull>,
+        ReadStorage<'a, Tint>,
+        ReadStorage<'a, NoTintTransparency>,
+        Option<Read<'a, AssetReadiness>>,
+        Option<Write<'a, VisibilityStats>>,
     );
 
     fn run(
         &mut self,
-        (entities, mut visibility, hidden, hidden_prop, active, camera, transparent, transform): Self::SystemData,
+        (
+            entities,
+            mut visibility,
+            hidden,
+            hidden_prop,
+            active,
+            culling,
+            camera,
+            transparent,
+            sort_keys,
+            transform,
+            layers,
+            draw_distances,
+            no_cull,
+            tints,
+            no_tint_transparency,
+            readiness,
+            stats,
+        ): Self::SystemData,
     ) {
         #[cfg(feature = "profiler")]
         profile_scope!("sprite_visibility_sorting_system");
 
+        let start = stats.as_ref().map(|_| Instant::now());
         let origin = Point3::origin();
 
         // The camera position is used to determine culling, but the sprites are ordered based on
         // the Z coordinate
-        let camera: Option<&Transform> = active
-            .entity
+        let culling = culling.entity.or(active.entity);
+        let camera_layers = culling
+            .and_then(|a| camera.get(a))
+            .or_else(|| (&camera, &transform).join().map(|ct| ct.0).next())
+            .map_or(RenderLayers::ALL, Camera::layers);
+        let camera: Option<&Transform> = culling
             .and_then(|a| transform.get(a))
             .or_else(|| (&camera, &transform).join().map(|ct| ct.1).next());
         let camera_backward = camera
@@ -90,19 +153,62 @@ impl<'a> System<'a> for SpriteVisibilitySortingSystem {
             .map(|t| t.global_matrix().transform_point(&origin))
             .unwrap_or_else(|| origin);
 
+        let (mut considered, mut after_frustum, mut after_layers) = (0, 0, 0);
+        let culled = &mut self.culled;
+        let mut tint_transparency = self.tint_transparency.as_mut();
+        if let Some(tint_transparency) = tint_transparency.as_mut() {
+            tint_transparency.begin();
+        }
         self.centroids.clear();
         self.centroids.extend(
-            (&*entities, &transform, !&hidden, !&hidden_prop)
+            (
+                &*entities,
+                &transform,
+                layers.maybe(),
+                draw_distances.maybe(),
+                !&hidden,
+                !&hidden_prop,
+            )
                 .join()
-                .map(|(e, t, _, _)| (e, t.global_matrix().transform_point(&origin)))
+                .inspect(|_| considered += 1)
+                .map(|(e, t, l, d, _, _)| (e, t.global_matrix().transform_point(&origin), l, d))
                 // filter entities behind the camera
-                .filter(|(_, c)| (c - camera_centroid).dot(&camera_backward) < na::zero())
-                .map(|(entity, centroid)| Internals {
-                    entity,
-                    transparent: transparent.contains(entity),
-                    centroid,
-                    camera_distance: (centroid.z - camera_centroid.z).abs(),
-                    from_camera: centroid - camera_centroid,
+                .filter(|(e, c, _, _)| {
+                    no_cull.contains(*e) || (c - camera_centroid).dot(&camera_backward) < na::zero()
+                })
+                .inspect(|_| after_frustum += 1)
+                .filter(|(_, _, layers, _)| layers.map_or(true, |l| l.intersects(camera_layers)))
+                .inspect(|_| after_layers += 1)
+                .filter_map(|(entity, centroid, _, draw_distance)| {
+                    let from_camera = centroid - camera_centroid;
+                    let fade = if no_cull.contains(entity) {
+                        1.0
+                    } else {
+                        apply_draw_distance(
+                            culled,
+                            entity,
+                            draw_distance,
+                            from_camera.norm().as_f32(),
+                        )?
+                    };
+                    let fade = fade * readiness.as_ref().map_or(1.0, |r| r.fade(entity));
+                    let tinted = tint_transparency
+                        .as_mut()
+                        .map_or(false, |tint_transparency| {
+                            tint_transparency.is_transparent(
+                                entity,
+                                tint_alpha(&tints, &no_tint_transparency, entity),
+                            )
+                        });
+                    Some(Internals {
+                        entity,
+                        transparent: transparent.contains(entity) || fade < 1.0 || tinted,
+                        centroid,
+                        camera_distance: (centroid.z - camera_centroid.z).abs(),
+                        from_camera,
+                        fade,
+                        sort_key: sort_keys.get(entity).map_or(0, |k| k.0),
+                    })
                 }),
         );
 
@@ -114,6 +220,7 @@ impl<'a> System<'a> for SpriteVisibilitySortingSystem {
                 .map(|c| c.entity.id()),
         );
 
+        let visible = self.centroids.len();
         self.transparent.clear();
         self.transparent
             .extend(self.centroids.drain(..).filter(|c| c.transparent));
@@ -121,14 +228,40 @@ impl<'a> System<'a> for SpriteVisibilitySortingSystem {
         // Note: Smaller Z values are placed first, so that semi-transparent sprite colors blend
         // correctly.
         self.transparent.sort_by(|a, b| {
-            b.camera_distance
-                .partial_cmp(&a.camera_distance)
-                .unwrap_or(Ordering::Equal)
+            a.sort_key
+                .cmp(&b.sort_key)
+                .then_with(|| {
+                    b.camera_distance
+                        .partial_cmp(&a.camera_distance)
+                        .unwrap_or(Ordering::Equal)
+                })
+                .then_with(|| a.entity.id().cmp(&b.entity.id()))
         });
 
         visibility.visible_ordered.clear();
         visibility
             .visible_ordered
             .extend(self.transparent.iter().map(|c| c.entity));
+
+        visibility.fade.clear();
+        visibility.fade.extend(
+            self.transparent
+                .iter()
+                .filter(|c| c.fade < 1.0)
+                .map(|c| (c.entity, c.fade)),
+        );
+
+        if let (Some(mut stats), Some(start)) = (stats, start) {
+            stats.sprites = VisibilityCounts {
+                considered,
+                culled_by_frustum: considered - after_frustum,
+                culled_by_layers: after_frustum - after_layers,
+                culled_by_occlusion: 0,
+                culled_by_distance: after_layers - visible,
+                visible_opaque: visible - self.transparent.len(),
+                visible_transparent: self.transparent.len(),
+                duration: start.elapsed(),
+            };
+        }
     }
 }
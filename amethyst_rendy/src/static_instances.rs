@@ -0,0 +1,757 @@
+//! Static instances culled on the GPU, for very large numbers of instances like grass or debris.
+//!
+//! Entities with a `StaticInstances` are gathered by the `StaticInstancesSystem` into the
+//! `StaticInstanceSet` resource, batched by material and mesh, with the `VertexArgs` and the
+//! world bounding sphere of each instance. Instances drawn by the other paths of the 3D passes,
+//! because they're `Transparent`, a `ViewModel`, skinned, or have a `TintGradient3D` or a
+//! `Dissolve`, are left out of the set, as well as hidden ones. The set is only rebuilt when the
+//! entities it is gathered from change, or when the `Transform`, the `BoundingSphere`, the
+//! mesh, the material or the `Tint` of one of them changes.
+//!
+//! The batches that don't fit in the capacity of the `StaticCulling` are left out of the set
+//! with a warning, their entities are culled and drawn like the others.
+//!
+//! With `RenderBase3D::with_static_instances`, the node of `static_culling_node` uploads the set
+//! to retained storage buffers once per frame in flight when it changes. Each frame, it tests the
+//! instances against the frustum of the culling camera, copies the ones inside to the start of
+//! the range of their batch in a buffer of the graph, and writes the indirect draw of each batch
+//! with the number copied. The opaque group of the pass binds that buffer as its instance buffer
+//! and draws the batches with the indirect draws. The instances are neither culled nor sorted by
+//! the `VisibilitySortingSystem`, and `DrawDistance`, `RenderLayers` and occlusion culling don't
+//! apply to them. The order of the instances of a batch varies between frames.
+//!
+//! The GPU path needs compute shaders and indirect draws starting after the first instance.
+//! Without them, the set stays empty and the entities with a `StaticInstances` are culled and
+//! drawn like the others.
+use crate::{
+    compute::{ComputeAccess, ComputeBinding, ComputeNodeDesc},
+    dissolve::Dissolve,
+    indirect::IndirectDraw,
+    mtl::Material,
+    pod::VertexArgs,
+    rendy::{graph::BufferId, hal::pso::ShaderStageFlags, shader::SpirvShader},
+    resources::{Tint, TintGradient3D},
+    skinning::JointTransforms,
+    transparent::Transparent,
+    types::{Backend, Mesh},
+    util,
+    view_model::ViewModel,
+    visibility::{BoundingSphere, Frustum, WorldBounds},
+};
+use amethyst_assets::{AssetStorage, Handle};
+use amethyst_core::{
+    ecs::{
+        storage::{ComponentEvent, NullStorage},
+        BitSet, Component, Entities, Entity, FlaggedStorage, Join, ReadStorage, ReaderId,
+        Resources, System, SystemData, Write, WriteStorage,
+    },
+    math::{convert, Vector4},
+    transform::Transform,
+    Hidden, HiddenPropagate,
+};
+use hibitset::{BitSetAnd, BitSetLike, BitSetNot, BitSetOr};
+use std::{collections::BTreeMap, ops::Range, sync::Arc};
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Size in bytes of the `VertexArgs` of an instance.
+pub const INSTANCE_SIZE: u64 = std::mem::size_of::<VertexArgs>() as u64;
+
+/// Words of a batch read by the culling shader: its indirect draw, its first instance and its
+/// number of instances, padded.
+const BATCH_WORDS: usize = 8;
+
+lazy_static::lazy_static! {
+    static ref STATIC_CULL_COMPUTE: SpirvShader = SpirvShader::new(
+        include_bytes!("../compiled/compute/static_cull.comp.spv").to_vec(),
+        ShaderStageFlags::COMPUTE,
+        "main",
+    );
+}
+
+/// Marks an entity drawn as a static instance culled on the GPU, see the module documentation.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct StaticInstances;
+
+impl Component for StaticInstances {
+    type Storage = FlaggedStorage<Self, NullStorage<Self>>;
+}
+
+/// Capacity of the buffers of the GPU culling of the static instances. The batches past
+/// `max_batches`, or whose instances don't all fit in `max_instances`, are culled on the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaticCulling {
+    /// Instances culled.
+    pub max_instances: u32,
+    /// Batches drawn.
+    pub max_batches: u32,
+}
+
+impl Default for StaticCulling {
+    fn default() -> Self {
+        Self {
+            max_instances: 1 << 16,
+            max_batches: 256,
+        }
+    }
+}
+
+impl StaticCulling {
+    /// Size in bytes of the buffer of the graph the instances inside the frustum are copied to.
+    pub fn instances_size(&self) -> u64 {
+        u64::from(self.max_instances.max(1)) * INSTANCE_SIZE
+    }
+
+    /// Size in bytes of the buffer of the graph the indirect draws of the batches are written to.
+    pub fn commands_size(&self) -> u64 {
+        u64::from(self.max_batches.max(1)) * crate::indirect::INDIRECT_SLOT_SIZE
+    }
+}
+
+/// Static instances sharing a material and a mesh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaticBatch {
+    /// The material of the instances.
+    pub material: Handle<Material>,
+    /// The mesh of the instances.
+    pub mesh: Handle<Mesh>,
+    /// The range of the instances in the set.
+    pub instances: Range<u32>,
+}
+
+/// Resource with the static instances culled on the GPU, see the module documentation.
+#[derive(Debug, Default)]
+pub struct StaticInstanceSet {
+    culling: Option<StaticCulling>,
+    dirty: bool,
+    version: u64,
+    members: BitSet,
+    batches: Vec<StaticBatch>,
+    args: Vec<VertexArgs>,
+    spheres: Vec<[f32; 4]>,
+    planes: [[f32; 4]; 6],
+}
+
+impl StaticInstanceSet {
+    /// Whether the static instances are culled on the GPU, which is decided when the render graph
+    /// is planned.
+    pub fn is_gpu_culled(&self) -> bool {
+        self.culling.is_some()
+    }
+
+    /// Set the capacity of the GPU culling, `None` without it.
+    pub(crate) fn set_culling(&mut self, culling: Option<StaticCulling>) {
+        if self.culling != culling {
+            self.culling = culling;
+            self.dirty = true;
+        }
+    }
+
+    /// Whether `entity` is drawn as a static instance culled on the GPU.
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.members.contains(entity.id())
+    }
+
+    /// The entities drawn as static instances culled on the GPU.
+    pub fn members(&self) -> &BitSet {
+        &self.members
+    }
+
+    /// The batches of the instances, ordered by material and mesh.
+    pub fn batches(&self) -> &[StaticBatch] {
+        &self.batches
+    }
+
+    /// Number of instances in the set.
+    pub fn len(&self) -> u32 {
+        self.args.len() as u32
+    }
+
+    /// Whether the set has no instances.
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    /// Changes every time the set is rebuilt.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Cull against `frustum`, set every frame by the `VisibilitySortingSystem`.
+    pub fn set_frustum(&mut self, frustum: &Frustum) {
+        for (plane, frustum_plane) in self.planes.iter_mut().zip(&frustum.planes) {
+            let frustum_plane: Vector4<f32> = convert(*frustum_plane);
+            *plane = frustum_plane.into();
+        }
+    }
+
+    /// The batches drawn with the capacity of `culling`.
+    pub fn culled(&self, culling: &StaticCulling) -> &[StaticBatch] {
+        let len = self
+            .batches
+            .iter()
+            .take(culling.max_batches as usize)
+            .take_while(|batch| batch.instances.end <= culling.max_instances)
+            .count();
+        &self.batches[..len]
+    }
+
+    /// Number of instances of the batches drawn with the capacity of `culling`.
+    fn culled_instances(&self, culling: &StaticCulling) -> usize {
+        self.culled(culling)
+            .last()
+            .map_or(0, |batch| batch.instances.end as usize)
+    }
+
+    /// The words of the batches drawn with the capacity of `culling` read by the shader, with
+    /// the draws given by `draw`.
+    fn batch_words(
+        &self,
+        culling: &StaticCulling,
+        draw: impl Fn(&StaticBatch) -> IndirectDraw,
+    ) -> Vec<[u32; BATCH_WORDS]> {
+        self.culled(culling)
+            .iter()
+            .map(|batch| {
+                let draw = draw(batch);
+                let mut words = [0; BATCH_WORDS];
+                words[..5].copy_from_slice(&draw.slot());
+                words[5] = batch.instances.start;
+                words[6] = batch.instances.end - batch.instances.start;
+                words
+            })
+            .collect()
+    }
+
+    fn rebuild(
+        &mut self,
+        culling: StaticCulling,
+        instances: impl Iterator<Item = (Entity, Handle<Material>, Handle<Mesh>, VertexArgs, [f32; 4])>,
+    ) {
+        let mut groups = BTreeMap::new();
+        for (entity, material, mesh, args, sphere) in instances {
+            groups
+                .entry((material.id(), mesh.id()))
+                .or_insert_with(|| (material, mesh, Vec::new()))
+                .2
+                .push((entity, args, sphere));
+        }
+
+        self.members.clear();
+        self.batches.clear();
+        self.args.clear();
+        self.spheres.clear();
+        let mut overflow = 0;
+        for (_, (material, mesh, instances)) in groups {
+            let start = self.args.len() as u32;
+            // Batches that don't fit are left to the CPU, smaller ones after them may still fit.
+            if self.batches.len() >= culling.max_batches as usize
+                || u64::from(start) + instances.len() as u64 > u64::from(culling.max_instances)
+            {
+                overflow += instances.len();
+                continue;
+            }
+            for (entity, args, sphere) in instances {
+                self.members.add(entity.id());
+                self.args.push(args);
+                self.spheres.push(sphere);
+            }
+            self.batches.push(StaticBatch {
+                material,
+                mesh,
+                instances: start..self.args.len() as u32,
+            });
+        }
+        if overflow > 0 {
+            log::warn!(
+                "{} static instances don't fit in the GPU culling capacity of {} instances in {} \
+                 batches, they are culled on the CPU",
+                overflow,
+                culling.max_instances,
+                culling.max_batches,
+            );
+        }
+        self.version += 1;
+        self.dirty = false;
+    }
+}
+
+/// The world bounding sphere of an instance, as a `vec4` with the radius last.
+fn world_sphere(transform: &Transform, sphere: Option<&BoundingSphere>) -> [f32; 4] {
+    match WorldBounds::new(transform, sphere, None) {
+        WorldBounds::Sphere { center, radius } => [
+            center.x.as_f32(),
+            center.y.as_f32(),
+            center.z.as_f32(),
+            radius.as_f32(),
+        ],
+        WorldBounds::Box(_) => unreachable!("Bounds without a box are a sphere"),
+    }
+}
+
+/// Rebuilds the `StaticInstanceSet` when the static instances change, see the module
+/// documentation.
+///
+/// The entities the set is gathered from are compared every frame with the ones of the last
+/// rebuild, which catches the components added and removed, like `Hidden`. Changed values are
+/// caught with the events of the flagged `Transform`, `BoundingSphere`, `Handle<Mesh>`,
+/// `Handle<Material>` and `Tint` storages.
+///
+/// Added by `RenderBase3D::with_static_instances`.
+#[derive(Debug, Default)]
+pub struct StaticInstancesSystem {
+    gathered: BitSet,
+    gathered_count: usize,
+    transforms_id: Option<ReaderId<ComponentEvent>>,
+    spheres_id: Option<ReaderId<ComponentEvent>>,
+    meshes_id: Option<ReaderId<ComponentEvent>>,
+    materials_id: Option<ReaderId<ComponentEvent>>,
+    tints_id: Option<ReaderId<ComponentEvent>>,
+}
+
+impl StaticInstancesSystem {
+    /// Create a new static instances system.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for StaticInstancesSystem {
+    type SystemData = (
+        Entities<'a>,
+        Write<'a, StaticInstanceSet>,
+        ReadStorage<'a, StaticInstances>,
+        ReadStorage<'a, Handle<Material>>,
+        ReadStorage<'a, Handle<Mesh>>,
+        ReadStorage<'a, Transform>,
+        ReadStorage<'a, Tint>,
+        ReadStorage<'a, BoundingSphere>,
+        ReadStorage<'a, Hidden>,
+        ReadStorage<'a, HiddenPropagate>,
+        ReadStorage<'a, Transparent>,
+        ReadStorage<'a, ViewModel>,
+        ReadStorage<'a, JointTransforms>,
+        ReadStorage<'a, TintGradient3D>,
+        ReadStorage<'a, Dissolve>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut set,
+            statics,
+            materials,
+            meshes,
+            transforms,
+            tints,
+            spheres,
+            hidden,
+            hidden_prop,
+            transparent,
+            view_models,
+            joints,
+            gradients,
+            dissolves,
+        ): Self::SystemData,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("static_instances_system");
+
+        let set = &mut *set;
+        // Entities gaining or losing a component the set depends on.
+        let excluded = BitSetOr(
+            BitSetOr(
+                BitSetOr(hidden.mask(), hidden_prop.mask()),
+                BitSetOr(transparent.mask(), view_models.mask()),
+            ),
+            BitSetOr(BitSetOr(joints.mask(), gradients.mask()), dissolves.mask()),
+        );
+        let gathered = BitSetAnd(
+            BitSetAnd(
+                BitSetAnd(statics.mask(), materials.mask()),
+                BitSetAnd(meshes.mask(), transforms.mask()),
+            ),
+            BitSetNot(excluded),
+        );
+        let mut count = 0;
+        let mut changed = false;
+        for id in (&gathered).iter() {
+            count += 1;
+            changed = changed || !self.gathered.contains(id);
+        }
+        if changed || count != self.gathered_count {
+            self.gathered.clear();
+            for id in (&gathered).iter() {
+                self.gathered.add(id);
+            }
+            self.gathered_count = count;
+            set.dirty = true;
+        }
+
+        // A moved instance, or a new mesh, material or tint of an instance.
+        let members = &set.members;
+        let changed = transforms
+            .channel()
+            .read(self.transforms_id.as_mut().expect(
+                "`StaticInstancesSystem::setup` was not called before `StaticInstancesSystem::run`",
+            ))
+            .chain(spheres.channel().read(self.spheres_id.as_mut().unwrap()))
+            .chain(meshes.channel().read(self.meshes_id.as_mut().unwrap()))
+            .chain(
+                materials
+                    .channel()
+                    .read(self.materials_id.as_mut().unwrap()),
+            )
+            .chain(tints.channel().read(self.tints_id.as_mut().unwrap()))
+            .any(|event| match event {
+                ComponentEvent::Inserted(id)
+                | ComponentEvent::Modified(id)
+                | ComponentEvent::Removed(id) => {
+                    members.contains(*id) || statics.mask().contains(*id)
+                }
+            });
+        if changed {
+            set.dirty = true;
+        }
+        let culling = match set.culling {
+            Some(culling) => culling,
+            None => {
+                // The entities are drawn like the others, rebuild once they're culled on the GPU.
+                if !set.members.is_empty() || !set.batches.is_empty() {
+                    set.members.clear();
+                    set.batches.clear();
+                    set.args.clear();
+                    set.spheres.clear();
+                    set.version += 1;
+                }
+                set.dirty = true;
+                return;
+            }
+        };
+        if !set.dirty {
+            return;
+        }
+
+        set.rebuild(
+            culling,
+            (
+                (&*entities, &statics, &materials, &meshes, &transforms),
+                (tints.maybe(), spheres.maybe()),
+                (!&hidden, !&hidden_prop, !&transparent, !&view_models),
+                (!&joints, !&gradients, !&dissolves),
+            )
+                .join()
+                .map(
+                    |((entity, _, material, mesh, transform), (tint, sphere), _, _)| {
+                        (
+                            entity,
+                            material.clone(),
+                            mesh.clone(),
+                            VertexArgs::from_object_data(transform, tint),
+                            world_sphere(transform, sphere),
+                        )
+                    },
+                ),
+        );
+    }
+
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        self.transforms_id = Some(WriteStorage::<Transform>::fetch(res).register_reader());
+        self.spheres_id = Some(WriteStorage::<BoundingSphere>::fetch(res).register_reader());
+        self.meshes_id = Some(WriteStorage::<Handle<Mesh>>::fetch(res).register_reader());
+        self.materials_id = Some(WriteStorage::<Handle<Material>>::fetch(res).register_reader());
+        self.tints_id = Some(WriteStorage::<Tint>::fetch(res).register_reader());
+    }
+}
+
+fn fill<T>(target: &mut [u8], items: &[T]) {
+    let bytes = util::slice_as_bytes(items);
+    target[..bytes.len()].copy_from_slice(bytes);
+}
+
+/// Compute node culling the `StaticInstanceSet` with the capacity of `culling`, copying the
+/// instances inside the frustum into `instances` and writing the indirect draws of the batches
+/// into `commands`, buffers of the graph of `StaticCulling::instances_size` and
+/// `StaticCulling::commands_size` bytes.
+pub fn static_culling_node<B: Backend>(
+    culling: StaticCulling,
+    instances: BufferId,
+    commands: BufferId,
+) -> ComputeNodeDesc {
+    let version = Arc::new(|res: &Resources| {
+        res.try_fetch::<StaticInstanceSet>()
+            .map_or(0, |set| set.version())
+    });
+    ComputeNodeDesc::new(STATIC_CULL_COMPUTE.clone())
+        .with_binding(ComputeBinding::Retained(
+            u64::from(culling.max_instances.max(1)) * 16,
+            version.clone(),
+            Arc::new(move |res: &Resources, target: &mut [u8]| {
+                if let Some(set) = res.try_fetch::<StaticInstanceSet>() {
+                    fill(target, &set.spheres[..set.culled_instances(&culling)]);
+                }
+            }),
+        ))
+        .with_binding(ComputeBinding::Retained(
+            culling.instances_size(),
+            version,
+            Arc::new(move |res: &Resources, target: &mut [u8]| {
+                if let Some(set) = res.try_fetch::<StaticInstanceSet>() {
+                    fill(target, &set.args[..set.culled_instances(&culling)]);
+                }
+            }),
+        ))
+        .with_binding(ComputeBinding::Host(
+            u64::from(culling.max_batches.max(1)) * BATCH_WORDS as u64 * 4,
+            Arc::new(move |res: &Resources, target: &mut [u8]| {
+                if let (Some(set), Some(mesh_storage)) = (
+                    res.try_fetch::<StaticInstanceSet>(),
+                    res.try_fetch::<AssetStorage<Mesh>>(),
+                ) {
+                    // The draws of the meshes not loaded draw nothing.
+                    let words = set.batch_words(&culling, |batch| {
+                        mesh_storage
+                            .get(&batch.mesh)
+                            .and_then(B::unwrap_mesh)
+                            .map_or_else(IndirectDraw::empty, |mesh| {
+                                IndirectDraw::new(
+                                    mesh.len(),
+                                    mesh.index_type().is_some(),
+                                    batch.instances.clone(),
+                                )
+                            })
+                    });
+                    fill(target, &words);
+                }
+            }),
+        ))
+        .with_binding(ComputeBinding::Buffer(instances, ComputeAccess::Write))
+        .with_binding(ComputeBinding::Buffer(commands, ComputeAccess::Write))
+        .with_push_constants(24)
+        .with_dispatch(move |res, constants| {
+            let batches = match res.try_fetch::<StaticInstanceSet>() {
+                Some(set) if set.is_gpu_culled() => {
+                    for (constant, value) in constants.iter_mut().zip(set.planes.iter().flatten()) {
+                        *constant = value.to_bits();
+                    }
+                    set.culled(&culling).len() as u32
+                }
+                _ => 0,
+            };
+            [batches, 1, 1]
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mtl::TextureOffset,
+        types::{MeshData, Texture, TextureData},
+    };
+    use amethyst_assets::Loader;
+    use amethyst_core::ecs::{Builder, RunNow, World};
+    use rayon::ThreadPoolBuilder;
+    use rendy::{hal::format::Format, texture::TextureBuilder};
+
+    #[test]
+    fn static_instances_are_batched_and_rebuilt_on_changes() {
+        let loader = Loader::new(".", Arc::new(ThreadPoolBuilder::new().build().unwrap()));
+        let texture = loader.load_from_data(
+            TextureData::from(TextureBuilder::new().with_raw_data(vec![], Format::R8Unorm)),
+            (),
+            &AssetStorage::<Texture>::default(),
+        );
+        let material = loader.load_from_data(
+            Material {
+                alpha_cutoff: 0.01,
+                albedo: texture.clone(),
+                emission: texture.clone(),
+                normal: texture.clone(),
+                metallic_roughness: texture.clone(),
+                ambient_occlusion: texture.clone(),
+                cavity: texture,
+                uv_offset: TextureOffset::default(),
+            },
+            (),
+            &AssetStorage::<Material>::default(),
+        );
+        let mesh_storage = AssetStorage::<Mesh>::default();
+        let grass = loader.load_from_data(
+            MeshData::from(rendy::mesh::MeshBuilder::new()),
+            (),
+            &mesh_storage,
+        );
+        let rock = loader.load_from_data(
+            MeshData::from(rendy::mesh::MeshBuilder::new()),
+            (),
+            &mesh_storage,
+        );
+
+        let mut world = World::new();
+        let mut system = StaticInstancesSystem::new();
+        RunNow::setup(&mut system, &mut world.res);
+        let mut instance = |mesh: &Handle<Mesh>| {
+            world
+                .create_entity()
+                .with(StaticInstances)
+                .with(material.clone())
+                .with(mesh.clone())
+                .with(Transform::default())
+                .build()
+        };
+        let blade = instance(&grass);
+        let stone = instance(&rock);
+        instance(&grass);
+        let glass = instance(&grass);
+        world
+            .write_storage::<Transparent>()
+            .insert(glass, Transparent)
+            .unwrap();
+
+        // Nothing is gathered until the instances are culled on the GPU.
+        system.run_now(&world.res);
+        assert!(world.read_resource::<StaticInstanceSet>().is_empty());
+        world
+            .write_resource::<StaticInstanceSet>()
+            .set_culling(Some(StaticCulling::default()));
+        system.run_now(&world.res);
+        {
+            let set = world.read_resource::<StaticInstanceSet>();
+            assert_eq!(set.len(), 3);
+            assert!(set.contains(blade) && !set.contains(glass));
+            let ranges: Vec<_> = set.batches().iter().map(|b| b.instances.clone()).collect();
+            assert_eq!(ranges, vec![0..2, 2..3]);
+            assert_eq!(set.batches()[0].mesh, grass);
+            assert_eq!(set.spheres[0], [0., 0., 0., 1.]);
+
+            let culling = StaticCulling {
+                max_instances: 2,
+                max_batches: 8,
+            };
+            assert_eq!(set.culled(&culling).len(), 1);
+            let words = set.batch_words(&culling, |b| {
+                IndirectDraw::new(36, true, b.instances.clone())
+            });
+            assert_eq!(words, vec![[36, 2, 0, 0, 0, 0, 2, 0]]);
+        }
+
+        let version = world.read_resource::<StaticInstanceSet>().version();
+        system.run_now(&world.res);
+        assert_eq!(
+            world.read_resource::<StaticInstanceSet>().version(),
+            version
+        );
+        world
+            .write_storage::<Transform>()
+            .get_mut(blade)
+            .unwrap()
+            .set_translation_x(4.0);
+        system.run_now(&world.res);
+        assert_eq!(
+            world.read_resource::<StaticInstanceSet>().version(),
+            version + 1
+        );
+
+        // Hiding an instance or changing its mesh or tint rebuilds the set.
+        world
+            .write_storage::<Hidden>()
+            .insert(blade, Hidden)
+            .unwrap();
+        system.run_now(&world.res);
+        {
+            let set = world.read_resource::<StaticInstanceSet>();
+            assert_eq!(set.version(), version + 2);
+            assert!(!set.contains(blade));
+        }
+        world
+            .write_storage::<Handle<Mesh>>()
+            .insert(stone, grass.clone())
+            .unwrap();
+        system.run_now(&world.res);
+        assert_eq!(
+            world.read_resource::<StaticInstanceSet>().batches().len(),
+            1
+        );
+        world
+            .write_storage::<Tint>()
+            .insert(stone, Tint(palette::Srgba::new(1.0, 0.0, 0.0, 1.0)))
+            .unwrap();
+        system.run_now(&world.res);
+        assert_eq!(
+            world.read_resource::<StaticInstanceSet>().version(),
+            version + 4
+        );
+    }
+
+    #[test]
+    fn batches_over_capacity_are_culled_on_the_cpu() {
+        let loader = Loader::new(".", Arc::new(ThreadPoolBuilder::new().build().unwrap()));
+        let mesh_storage = AssetStorage::<Mesh>::default();
+        let material_storage = AssetStorage::<Material>::default();
+        let texture = loader.load_from_data(
+            TextureData::from(TextureBuilder::new().with_raw_data(vec![], Format::R8Unorm)),
+            (),
+            &AssetStorage::<Texture>::default(),
+        );
+        let material = loader.load_from_data(
+            Material {
+                alpha_cutoff: 0.01,
+                albedo: texture.clone(),
+                emission: texture.clone(),
+                normal: texture.clone(),
+                metallic_roughness: texture.clone(),
+                ambient_occlusion: texture.clone(),
+                cavity: texture,
+                uv_offset: TextureOffset::default(),
+            },
+            (),
+            &material_storage,
+        );
+        let meshes = (0..3)
+            .map(|_| {
+                loader.load_from_data(
+                    MeshData::from(rendy::mesh::MeshBuilder::new()),
+                    (),
+                    &mesh_storage,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let mut world = World::new();
+        let mut system = StaticInstancesSystem::new();
+        RunNow::setup(&mut system, &mut world.res);
+        // Two instances of the first mesh, three of the second and one of the third.
+        let entities = [0, 0, 1, 1, 1, 2]
+            .iter()
+            .map(|mesh: &usize| {
+                world
+                    .create_entity()
+                    .with(StaticInstances)
+                    .with(material.clone())
+                    .with(meshes[*mesh].clone())
+                    .with(Transform::default())
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        world
+            .write_resource::<StaticInstanceSet>()
+            .set_culling(Some(StaticCulling {
+                max_instances: 3,
+                max_batches: 8,
+            }));
+        system.run_now(&world.res);
+
+        // The second batch doesn't fit, the third one still does.
+        let set = world.read_resource::<StaticInstanceSet>();
+        assert_eq!(set.len(), 3);
+        let ranges: Vec<_> = set.batches().iter().map(|b| b.instances.clone()).collect();
+        assert_eq!(ranges, vec![0..2, 2..3]);
+        let members: Vec<_> = entities.iter().map(|e| set.contains(*e)).collect();
+        assert_eq!(members, vec![true, true, false, false, false, true]);
+        assert_eq!(set.culled(&set.culling.unwrap()), set.batches());
+    }
+}
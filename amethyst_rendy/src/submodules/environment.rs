@@ -1,25 +1,30 @@
 //! Environment submodule for shared environmental descriptor set data.
 //! Fetches and sets projection and lighting descriptor set information.
 use crate::{
+    exposure::pre_exposure,
     light::Light,
     pod::{self, IntoPod},
     rendy::{
         command::RenderPassEncoder,
         factory::Factory,
-        hal::{self, adapter::PhysicalDevice, device::Device, pso::Descriptor},
-        memory::Write as _,
-        resource::{Buffer, DescriptorSet, DescriptorSetLayout, Escape, Handle as RendyHandle},
+        hal::{self, pso::Descriptor},
+        resource::{DescriptorSet, DescriptorSetLayout, Escape, Handle as RendyHandle},
+    },
+    submodules::{
+        gather::{AmbientGatherer, CameraGatherer},
+        DynamicUniformRing,
     },
-    submodules::gather::{AmbientGatherer, CameraGatherer},
     types::Backend,
-    util::{self, TapCountIter},
+    util::{self, ChangeDetectionSet, ChangeWatch, TapCountIter},
+    view_model::ViewModelProjection,
 };
 use amethyst_core::{
-    ecs::{Join, ReadStorage, Resources, SystemData},
+    ecs::{Join, Read, ReadStorage, Resources, SystemData},
     math::{convert, Vector3},
     transform::Transform,
 };
 use glsl_layout::*;
+use std::ops::Range;
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
@@ -31,10 +36,32 @@ const MAX_SPOT_LIGHTS: usize = 128;
 /// Submodule for loading and binding descriptor sets for a 3D, lit environment.
 /// This also abstracts away the need for handling multiple images in flight, as it provides
 /// per-image submissions.
+///
+/// The environment is gathered every frame, and the `ENVIRONMENT` and `LIGHTS` channels of the
+/// `ChangeDetectionSet` are marked when the camera and ambient color or the lights differ from
+/// the previous frame. It's pushed to a `DynamicUniformRing`, whose buffer of each image is only
+/// written for the channels changed since it was last written.
+///
+/// The ambient color and the intensities of the lights are pre-exposed with the multiplier of
+/// the `Exposure` resource when it exists, see the `exposure` module.
 #[derive(Debug)]
 pub struct EnvironmentSub<B: Backend> {
     layout: RendyHandle<DescriptorSetLayout<B>>,
+    ranges: EnvironmentRanges,
+    ring: DynamicUniformRing<B>,
+    previous: Vec<u8>,
     per_image: Vec<PerImageEnvironmentSub<B>>,
+    view_models: bool,
+}
+
+/// Ranges of the parts of the environment buffer.
+#[derive(Debug, Clone)]
+struct EnvironmentRanges {
+    projview: Range<u64>,
+    env: Range<u64>,
+    plight: Range<u64>,
+    dlight: Range<u64>,
+    slight: Range<u64>,
 }
 
 /// Submodule for loading and binding descriptor sets for a 3D, lit environment.
@@ -42,16 +69,31 @@ pub struct EnvironmentSub<B: Backend> {
 /// for each image in flight.
 #[derive(Debug)]
 struct PerImageEnvironmentSub<B: Backend> {
-    buffer: Option<Escape<Buffer<B>>>,
     set: Escape<DescriptorSet<B>>,
+    watch: ChangeWatch,
 }
 
 impl<B: Backend> EnvironmentSub<B> {
     /// Create and allocate a new `EnvironmentSub` with the provided rendy `Factory`
     pub fn new(factory: &Factory<B>) -> Result<Self, failure::Error> {
+        let mut ring = DynamicUniformRing::new(factory, hal::buffer::Usage::UNIFORM);
+        let ranges = EnvironmentRanges::reserve(&mut ring);
+        ring.clear();
         Ok(Self {
             layout: set_layout! {factory, [1] UniformBuffer VERTEX, [4] UniformBuffer FRAGMENT},
+            ranges,
+            ring,
+            previous: Vec::new(),
             per_image: Vec::new(),
+            view_models: false,
+        })
+    }
+
+    /// Create an environment projecting with the `ViewModelProjection`, for the view models.
+    pub fn view_models(factory: &Factory<B>) -> Result<Self, failure::Error> {
+        Ok(Self {
+            view_models: true,
+            ..Self::new(factory)?
         })
     }
 
@@ -65,14 +107,23 @@ impl<B: Backend> EnvironmentSub<B> {
         #[cfg(feature = "profiler")]
         profile_scope!("process");
 
-        let this_image = {
-            while self.per_image.len() <= index {
-                self.per_image
-                    .push(PerImageEnvironmentSub::new(factory, &self.layout));
+        self.stage(res);
+        while self.per_image.len() <= index {
+            self.per_image
+                .push(PerImageEnvironmentSub::new(factory, &self.layout));
+        }
+        let new_buffer = self.ring.ensure(factory, index);
+        let this_image = &mut self.per_image[index];
+        if new_buffer {
+            if let Some(buffer) = self.ring.buffer(index) {
+                this_image.write_descriptors(factory, buffer, &self.ranges);
             }
-            &mut self.per_image[index]
-        };
-        this_image.process(factory, res)
+        }
+        for range in this_image.changed_ranges(&self.ranges, new_buffer, res) {
+            self.ring.write(factory, index, range);
+        }
+        self.ring.report(index, res);
+        new_buffer
     }
 
     /// Binds this environment set for all images.
@@ -86,13 +137,176 @@ impl<B: Backend> EnvironmentSub<B> {
     ) {
         self.per_image[index].bind(pipeline_layout, set_id, encoder);
     }
+
+    /// Gather the environment of this frame as it's laid out in the buffers, and mark the
+    /// channels of what changed since the previous frame.
+    fn stage(&mut self, res: &Resources) {
+        self.previous.clear();
+        self.previous.extend_from_slice(self.ring.data());
+        self.ring.clear();
+        self.ranges = EnvironmentRanges::reserve(&mut self.ring);
+        let ranges = &self.ranges;
+        let dst_slice = self.ring.data_mut();
+
+        let CameraGatherer {
+            camera_position,
+            projview,
+            ..
+        } = if self.view_models {
+            let projection = <Read<'_, ViewModelProjection>>::fetch(res);
+            CameraGatherer::gather_projected(res, |camera| projection.matrix(camera))
+        } else {
+            CameraGatherer::gather(res)
+        };
+
+        let mut env = pod::Environment {
+            ambient_color: AmbientGatherer::gather_exposed(res),
+            camera_position,
+            point_light_count: 0,
+            directional_light_count: 0,
+            spot_light_count: 0,
+        }
+        .std140();
+
+        let exposure = pre_exposure(res);
+        let (lights, transforms) =
+            <(ReadStorage<'_, Light>, ReadStorage<'_, Transform>)>::fetch(res);
+
+        let point_lights = (&lights, &transforms)
+            .join()
+            .filter_map(|(light, transform)| match light {
+                Light::Point(light) => Some(
+                    pod::PointLight {
+                        position: convert::<_, Vector3<f32>>(
+                            transform.global_matrix().column(3).xyz(),
+                        )
+                        .into_pod(),
+                        color: light.color.into_pod(),
+                        intensity: light.intensity * exposure,
+                    }
+                    .std140(),
+                ),
+                _ => None,
+            })
+            .take(MAX_POINT_LIGHTS);
+
+        let dir_lights = lights
+            .join()
+            .filter_map(|light| match light {
+                Light::Directional(ref light) => Some(
+                    pod::DirectionalLight {
+                        color: light.color.into_pod(),
+                        intensity: light.intensity * exposure,
+                        direction: light.direction.into_pod(),
+                    }
+                    .std140(),
+                ),
+                _ => None,
+            })
+            .take(MAX_DIR_LIGHTS);
+
+        let spot_lights = (&lights, &transforms)
+            .join()
+            .filter_map(|(light, transform)| {
+                if let Light::Spot(ref light) = *light {
+                    Some(
+                        pod::SpotLight {
+                            position: convert::<_, Vector3<f32>>(
+                                transform.global_matrix().column(3).xyz(),
+                            )
+                            .into_pod(),
+                            color: light.color.into_pod(),
+                            direction: light.direction.into_pod(),
+                            angle: light.angle.cos(),
+                            intensity: light.intensity * exposure,
+                            range: light.range,
+                            smoothness: light.smoothness,
+                        }
+                        .std140(),
+                    )
+                } else {
+                    None
+                }
+            })
+            .take(MAX_SPOT_LIGHTS);
+
+        use util::{usize_range, write_into_slice};
+        write_into_slice(
+            &mut dst_slice[usize_range(ranges.plight.clone())],
+            point_lights.tap_count(&mut env.point_light_count),
+        );
+        write_into_slice(
+            &mut dst_slice[usize_range(ranges.dlight.clone())],
+            dir_lights.tap_count(&mut env.directional_light_count),
+        );
+        write_into_slice(
+            &mut dst_slice[usize_range(ranges.slight.clone())],
+            spot_lights.tap_count(&mut env.spot_light_count),
+        );
+        write_into_slice(
+            &mut dst_slice[usize_range(ranges.projview.clone())],
+            Some(projview),
+        );
+        write_into_slice(&mut dst_slice[usize_range(ranges.env.clone())], Some(env));
+
+        // The buffers are written whole when they're created, there's nothing to compare the
+        // first frame with.
+        let staged = self.ring.data();
+        if self.previous.len() != staged.len() {
+            return;
+        }
+        if let Some(mut changes) = res.try_fetch_mut::<ChangeDetectionSet>() {
+            for (channel, range) in &[
+                (ChangeDetectionSet::ENVIRONMENT, ranges.environment()),
+                (ChangeDetectionSet::LIGHTS, ranges.lights()),
+            ] {
+                let range = usize_range(range.clone());
+                if staged[range.clone()] != self.previous[range] {
+                    changes.mark(*channel);
+                }
+            }
+        }
+    }
+}
+
+impl EnvironmentRanges {
+    /// Reserve the parts of the environment in `ring`, the light arrays with room for their
+    /// maximum counts.
+    fn reserve<B: Backend>(ring: &mut DynamicUniformRing<B>) -> Self {
+        fn size<T: AsStd140>(array_len: usize) -> u64
+        where
+            T::Std140: Sized,
+        {
+            (std::mem::size_of::<T::Std140>() * array_len) as u64
+        }
+
+        Self {
+            projview: ring.reserve(size::<pod::ViewArgs>(1)),
+            env: ring.reserve(size::<pod::Environment>(1)),
+            plight: ring.reserve(size::<pod::PointLight>(MAX_POINT_LIGHTS)),
+            dlight: ring.reserve(size::<pod::DirectionalLight>(MAX_DIR_LIGHTS)),
+            slight: ring.reserve(size::<pod::SpotLight>(MAX_SPOT_LIGHTS)),
+        }
+    }
+
+    /// The camera and the ambient color, with the light counts.
+    fn environment(&self) -> Range<u64> {
+        0..self.env.end
+    }
+
+    fn lights(&self) -> Range<u64> {
+        self.env.end..self.slight.end
+    }
 }
 
 impl<B: Backend> PerImageEnvironmentSub<B> {
     fn new(factory: &Factory<B>, layout: &RendyHandle<DescriptorSetLayout<B>>) -> Self {
         Self {
-            buffer: None,
             set: factory.create_descriptor_set(layout.clone()).unwrap(),
+            watch: ChangeWatch::new(vec![
+                ChangeDetectionSet::ENVIRONMENT,
+                ChangeDetectionSet::LIGHTS,
+            ]),
         }
     }
 
@@ -113,153 +327,62 @@ impl<B: Backend> PerImageEnvironmentSub<B> {
         }
     }
 
-    fn process(&mut self, factory: &Factory<B>, res: &Resources) -> bool {
-        let align = factory
-            .physical()
-            .limits()
-            .min_uniform_buffer_offset_alignment;
-
-        let projview_size = util::align_size::<pod::ViewArgs>(align, 1);
-        let env_buf_size = util::align_size::<pod::Environment>(align, 1);
-        let plight_buf_size = util::align_size::<pod::PointLight>(align, MAX_POINT_LIGHTS);
-        let dlight_buf_size = util::align_size::<pod::DirectionalLight>(align, MAX_DIR_LIGHTS);
-        let slight_buf_size = util::align_size::<pod::SpotLight>(align, MAX_SPOT_LIGHTS);
-
-        let projview_range = 0..projview_size;
-        let env_range = util::next_range(&projview_range, env_buf_size);
-        let plight_range = util::next_range(&env_range, plight_buf_size);
-        let dlight_range = util::next_range(&plight_range, dlight_buf_size);
-        let slight_range = util::next_range(&dlight_range, slight_buf_size);
-
-        let whole_range = 0..slight_range.end;
-
-        let new_buffer = util::ensure_buffer(
-            &factory,
-            &mut self.buffer,
-            hal::buffer::Usage::UNIFORM,
-            rendy::memory::Dynamic,
-            whole_range.end,
-        )
-        .unwrap();
-        if let Some(buffer) = self.buffer.as_mut() {
-            if new_buffer {
-                use util::{desc_write, opt_range};
-                let buffer = buffer.raw();
-                let env_set = self.set.raw();
-
-                let desc_projview = Descriptor::Buffer(buffer, opt_range(projview_range.clone()));
-                let desc_env = Descriptor::Buffer(buffer, opt_range(env_range.clone()));
-                let desc_plight = Descriptor::Buffer(buffer, opt_range(plight_range.clone()));
-                let desc_dlight = Descriptor::Buffer(buffer, opt_range(dlight_range.clone()));
-                let desc_slight = Descriptor::Buffer(buffer, opt_range(slight_range.clone()));
-
-                unsafe {
-                    factory.write_descriptor_sets(vec![
-                        desc_write(env_set, 0, desc_projview),
-                        desc_write(env_set, 1, desc_env),
-                        desc_write(env_set, 2, desc_plight),
-                        desc_write(env_set, 3, desc_dlight),
-                        desc_write(env_set, 4, desc_slight),
-                    ]);
-                }
-            }
-
-            let CameraGatherer {
-                camera_position,
-                projview,
-            } = CameraGatherer::gather(res);
-
-            let mut mapped = buffer.map(factory, whole_range.clone()).unwrap();
-            let mut writer = unsafe { mapped.write::<u8>(factory, whole_range.clone()).unwrap() };
-            let dst_slice = unsafe { writer.slice() };
-
-            let mut env = pod::Environment {
-                ambient_color: AmbientGatherer::gather(res),
-                camera_position,
-                point_light_count: 0,
-                directional_light_count: 0,
-                spot_light_count: 0,
-            }
-            .std140();
-
-            let (lights, transforms) =
-                <(ReadStorage<'_, Light>, ReadStorage<'_, Transform>)>::fetch(res);
-
-            let point_lights = (&lights, &transforms)
-                .join()
-                .filter_map(|(light, transform)| match light {
-                    Light::Point(light) => Some(
-                        pod::PointLight {
-                            position: convert::<_, Vector3<f32>>(
-                                transform.global_matrix().column(3).xyz(),
-                            )
-                            .into_pod(),
-                            color: light.color.into_pod(),
-                            intensity: light.intensity,
-                        }
-                        .std140(),
-                    ),
-                    _ => None,
-                })
-                .take(MAX_POINT_LIGHTS);
-
-            let dir_lights = lights
-                .join()
-                .filter_map(|light| match light {
-                    Light::Directional(ref light) => Some(
-                        pod::DirectionalLight {
-                            color: light.color.into_pod(),
-                            intensity: light.intensity,
-                            direction: light.direction.into_pod(),
-                        }
-                        .std140(),
-                    ),
-                    _ => None,
-                })
-                .take(MAX_DIR_LIGHTS);
+    fn write_descriptors(
+        &self,
+        factory: &Factory<B>,
+        buffer: &B::Buffer,
+        ranges: &EnvironmentRanges,
+    ) {
+        use util::{desc_write, opt_range};
+        let env_set = self.set.raw();
 
-            let spot_lights = (&lights, &transforms)
-                .join()
-                .filter_map(|(light, transform)| {
-                    if let Light::Spot(ref light) = *light {
-                        Some(
-                            pod::SpotLight {
-                                position: convert::<_, Vector3<f32>>(
-                                    transform.global_matrix().column(3).xyz(),
-                                )
-                                .into_pod(),
-                                color: light.color.into_pod(),
-                                direction: light.direction.into_pod(),
-                                angle: light.angle.cos(),
-                                intensity: light.intensity,
-                                range: light.range,
-                                smoothness: light.smoothness,
-                            }
-                            .std140(),
-                        )
-                    } else {
-                        None
-                    }
-                })
-                .take(MAX_SPOT_LIGHTS);
+        let desc_projview = Descriptor::Buffer(buffer, opt_range(ranges.projview.clone()));
+        let desc_env = Descriptor::Buffer(buffer, opt_range(ranges.env.clone()));
+        let desc_plight = Descriptor::Buffer(buffer, opt_range(ranges.plight.clone()));
+        let desc_dlight = Descriptor::Buffer(buffer, opt_range(ranges.dlight.clone()));
+        let desc_slight = Descriptor::Buffer(buffer, opt_range(ranges.slight.clone()));
 
-            use util::{usize_range, write_into_slice};
-            write_into_slice(
-                &mut dst_slice[usize_range(plight_range)],
-                point_lights.tap_count(&mut env.point_light_count),
-            );
-            write_into_slice(
-                &mut dst_slice[usize_range(dlight_range)],
-                dir_lights.tap_count(&mut env.directional_light_count),
-            );
-            write_into_slice(
-                &mut dst_slice[usize_range(slight_range)],
-                spot_lights.tap_count(&mut env.spot_light_count),
-            );
-            write_into_slice(&mut dst_slice[usize_range(projview_range)], Some(projview));
-            write_into_slice(&mut dst_slice[usize_range(env_range)], Some(env));
+        unsafe {
+            factory.write_descriptor_sets(vec![
+                desc_write(env_set, 0, desc_projview),
+                desc_write(env_set, 1, desc_env),
+                desc_write(env_set, 2, desc_plight),
+                desc_write(env_set, 3, desc_dlight),
+                desc_write(env_set, 4, desc_slight),
+            ]);
         }
+    }
 
-        new_buffer
+    /// The ranges to write to the buffer of this image, of the channels changed since it was
+    /// last written, or all of them in a new buffer.
+    fn changed_ranges(
+        &mut self,
+        ranges: &EnvironmentRanges,
+        new_buffer: bool,
+        res: &Resources,
+    ) -> Vec<Range<u64>> {
+        // Without the channels, everything is written every frame.
+        let changes = res.try_fetch::<ChangeDetectionSet>();
+        let changed = |channel| {
+            new_buffer
+                || changes
+                    .as_ref()
+                    .map_or(true, |changes| self.watch.changed_channel(changes, channel))
+        };
+        let writes = [
+            (
+                changed(ChangeDetectionSet::ENVIRONMENT),
+                ranges.environment(),
+            ),
+            (changed(ChangeDetectionSet::LIGHTS), ranges.lights()),
+        ];
+        if let Some(changes) = &changes {
+            self.watch.update(changes);
+        }
+        writes
+            .iter()
+            .filter(|(write, _)| *write)
+            .map(|(_, range)| range.clone())
+            .collect()
     }
 }
@@ -1,11 +1,14 @@
 //! Helper gatherer structures for collecting information about the world.
 use crate::{
     camera::{ActiveCamera, Camera},
+    exposure::pre_exposure,
+    layers::RenderLayers,
     pod::{self, IntoPod},
     resources::AmbientColor,
+    view::RenderView,
 };
 use amethyst_core::{
-    ecs::{Join, Read, ReadExpect, ReadStorage, Resources, SystemData},
+    ecs::{Join, Read, ReadStorage, Resources, SystemData},
     math::{convert, Matrix4, Vector3},
     transform::Transform,
 };
@@ -24,30 +27,53 @@ pub struct CameraGatherer {
     pub camera_position: vec3,
     /// Fetched camera projection matrix.
     pub projview: Std140<pod::ViewArgs>,
+    /// Render layers seen by the camera.
+    pub layers: RenderLayers,
+    /// Fetched camera right direction in world space, of unit length.
+    pub camera_right: Vector3<f32>,
+    /// Fetched camera up direction in world space, of unit length.
+    pub camera_up: Vector3<f32>,
 }
 
 impl CameraGatherer {
     /// Collect `ActiveCamera` and `Camera` instances from the provided resource storage and selects
     /// the appropriate camera to use for projection, and returns the camera position and extracted
-    /// projection matrix.
+    /// projection matrix. The camera and dimensions of the `RenderView` take precedence.
     ///
     /// The matrix returned is the camera's `Projection` matrix and the camera `Transform::global_view_matrix`
     pub fn gather(res: &Resources) -> Self {
+        Self::gather_projected(res, |camera| *camera.as_matrix())
+    }
+
+    /// Collect the camera like `gather`, projecting with the matrix `projection` gives for it
+    /// instead of its own.
+    pub fn gather_projected(
+        res: &Resources,
+        projection: impl FnOnce(&Camera) -> Matrix4<f32>,
+    ) -> Self {
         #[cfg(feature = "profiler")]
         profile_scope!("gather_cameras");
 
-        let (active_camera, cameras, transforms, dimensions) = <(
+        let (active_camera, view, cameras, transforms, dimensions) = <(
             Read<'_, ActiveCamera>,
+            Option<Read<'_, RenderView>>,
             ReadStorage<'_, Camera>,
             ReadStorage<'_, Transform>,
-            ReadExpect<'_, ScreenDimensions>,
+            Option<Read<'_, ScreenDimensions>>,
         )>::fetch(res);
 
-        let defcam = Camera::standard_2d(dimensions.width(), dimensions.height());
+        let view = view.as_ref().map(|view| &**view);
+        // Rendering offscreen has no screen dimensions
+        let (width, height) = view
+            .and_then(|view| view.dimensions.as_ref())
+            .or_else(|| dimensions.as_ref().map(|d| &**d))
+            .map_or((1.0, 1.0), |d| (d.width(), d.height()));
+        let defcam = Camera::standard_2d(width, height);
         let identity = Transform::default();
 
-        let (camera, transform) = active_camera
-            .entity
+        let (camera, transform) = view
+            .and_then(|view| view.camera)
+            .or(active_camera.entity)
             .as_ref()
             .and_then(|ac| {
                 cameras
@@ -64,7 +90,7 @@ impl CameraGatherer {
         let camera_position =
             convert::<_, Vector3<f32>>(transform.global_matrix().column(3).xyz()).into_pod();
 
-        let proj: [[f32; 4]; 4] = (*camera.as_matrix()).into();
+        let proj: [[f32; 4]; 4] = projection(camera).into();
         let view: [[f32; 4]; 4] = convert::<_, Matrix4<f32>>(transform.global_view_matrix()).into();
 
         let projview = pod::ViewArgs {
@@ -73,9 +99,21 @@ impl CameraGatherer {
         }
         .std140();
 
+        let world: Matrix4<f32> = convert(*transform.global_matrix());
+        let axis = |i: usize| {
+            world
+                .column(i)
+                .xyz()
+                .try_normalize(std::f32::EPSILON)
+                .unwrap_or_else(|| Vector3::ith(i, 1.0))
+        };
+
         Self {
             camera_position,
             projview,
+            layers: camera.layers(),
+            camera_right: axis(0),
+            camera_up: axis(1),
         }
     }
 }
@@ -87,9 +125,15 @@ impl AmbientGatherer {
     /// If an `AmbientColor` exists in the world, return it - otherwise return pure white.
     pub fn gather(res: &Resources) -> vec3 {
         let ambient_color = <Option<Read<'_, AmbientColor>>>::fetch(res);
-        ambient_color.map_or([0.0, 0.0, 0.0].into(), |c| {
-            let (r, g, b, _) = c.0.into_components();
-            [r, g, b].into()
-        })
+        ambient_color.map_or([0.0, 0.0, 0.0].into(), |c| c.0.color.into_pod())
+    }
+
+    /// The ambient color pre-exposed with the multiplier of the `Exposure`, see the `exposure`
+    /// module.
+    pub fn gather_exposed(res: &Resources) -> vec3 {
+        let multiplier = pre_exposure(res);
+        let ambient_color = <Option<Read<'_, AmbientColor>>>::fetch(res);
+        let [r, g, b]: [f32; 3] = ambient_color.map_or([0.0; 3], |c| c.0.color.into_pod());
+        [r * multiplier, g * multiplier, b * multiplier].into()
     }
 }
@@ -0,0 +1,280 @@
+//! Batching and upload of custom per-instance data, for user render groups drawing meshes.
+//!
+//! A group needing its own per-instance attributes declares them in a `#[repr(C)]` struct
+//! implementing `InstanceData`: its vertex layout with `AsVertex`, usually the `Model` and `Tint`
+//! attributes of `VertexArgs` followed by its own `AsAttribute`s, and how it's made from the
+//! components of an entity. `InstanceSub` gathers the visible entities with a mesh, a material
+//! and the component of the struct into batches of the same mesh and material, uploads them to
+//! an instance-rate vertex buffer of every frame and draws them as instanced draws. The vertex
+//! description of the pipeline is made by `util::instanced_vertex_desc`.
+//!
+//! ```rust,ignore
+//! #[derive(Clone, Copy, Debug)]
+//! #[repr(C, packed)]
+//! struct DissolveArgs {
+//!     model: [[f32; 4]; 4],
+//!     tint: [f32; 4],
+//!     amount: f32,
+//! }
+//!
+//! impl AsVertex for DissolveArgs {
+//!     fn vertex() -> VertexFormat {
+//!         VertexFormat::new((Model::vertex(), Tint::vertex(), DissolveAmount::vertex()))
+//!     }
+//! }
+//!
+//! impl InstanceData for DissolveArgs {
+//!     type Component = Dissolve;
+//!
+//!     fn from_object_data(transform: &Transform, tint: Option<&Tint>, dissolve: &Dissolve) -> Self {
+//!         DissolveArgs {
+//!             model: convert::<_, Matrix4<f32>>(*transform.global_matrix()).into(),
+//!             tint: tint.map_or([1.0; 4], |t| t.0.into_pod()),
+//!             amount: dissolve.0,
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! The size of the struct must be the stride of its vertex format, which `#[repr(C, packed)]`
+//! guarantees.
+//!
+//! See the `custom_instance_data` example for a group drawing them.
+use crate::{
+    batch::{GroupIterator, TwoLevelBatch},
+    content_errors, deterministic,
+    error::RenderError,
+    mtl::{Material, StaticTextureSet},
+    rendy::{
+        command::RenderPassEncoder,
+        factory::Factory,
+        mesh::{AsVertex, VertexFormat},
+    },
+    resources::Tint,
+    submodules::{DynamicVertexBuffer, MaterialId, MaterialSub},
+    transparent::Transparent,
+    types::{Backend, Mesh},
+    visibility::Visibility,
+};
+use amethyst_assets::{AssetStorage, Handle};
+use amethyst_core::{
+    ecs::{Component, Join, Read, ReadStorage, Resources, SystemData},
+    transform::Transform,
+    Hidden, HiddenPropagate,
+};
+use derivative::Derivative;
+use smallvec::SmallVec;
+
+#[cfg(feature = "profiler")]
+use thread_profiler::profile_scope;
+
+/// Instance-rate data of a custom render group, made from the components of each entity drawn.
+pub trait InstanceData: AsVertex + Copy + std::fmt::Debug + Send + Sync + 'static {
+    /// The component of the entities drawn with this data.
+    type Component: Component;
+
+    /// Make the data of an entity from its transform, tint and component.
+    fn from_object_data(
+        transform: &Transform,
+        tint: Option<&Tint>,
+        component: &Self::Component,
+    ) -> Self;
+}
+
+/// Batches of instances of `I`, of the same mesh and material, with their per-image buffers.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+pub struct InstanceSub<B: Backend, I: InstanceData> {
+    batches: TwoLevelBatch<MaterialId, u32, SmallVec<[I; 4]>>,
+    buffer: DynamicVertexBuffer<B, I>,
+}
+
+impl<B: Backend, I: InstanceData> InstanceSub<B, I> {
+    /// Create an empty `InstanceSub`.
+    pub fn new() -> Self {
+        Self {
+            batches: TwoLevelBatch::default(),
+            buffer: DynamicVertexBuffer::new(),
+        }
+    }
+
+    /// Gather the visible entities with a loaded mesh, a material and `I::Component` into
+    /// batches, replacing the ones of the previous frame. Transparent entities aren't included.
+    pub fn gather<T>(
+        &mut self,
+        factory: &Factory<B>,
+        resources: &Resources,
+        materials: &mut MaterialSub<B, T>,
+    ) where
+        T: for<'a> StaticTextureSet<'a>,
+    {
+        #[cfg(feature = "profiler")]
+        profile_scope!("gather");
+
+        let (
+            mesh_storage,
+            visibility,
+            transparent,
+            hiddens,
+            hiddens_prop,
+            meshes,
+            mats,
+            transforms,
+            tints,
+            components,
+        ) = <(
+            Read<'_, AssetStorage<Mesh>>,
+            Option<Read<'_, Visibility>>,
+            ReadStorage<'_, Transparent>,
+            ReadStorage<'_, Hidden>,
+            ReadStorage<'_, HiddenPropagate>,
+            ReadStorage<'_, Handle<Mesh>>,
+            ReadStorage<'_, Handle<Material>>,
+            ReadStorage<'_, Transform>,
+            ReadStorage<'_, Tint>,
+            ReadStorage<'_, I::Component>,
+        )>::fetch(resources);
+
+        self.batches
+            .reset(deterministic::is_deterministic(resources));
+        let batches = &mut self.batches;
+        let input = || (&mats, &meshes, &transforms, tints.maybe(), &components);
+        let mut insert = |(mat, mesh_id): (&Handle<Material>, u32), data: &mut Vec<I>| {
+            if mesh_storage.contains_id(mesh_id) {
+                if let Some((mat, _)) = materials.insert(factory, resources, mat) {
+                    batches.insert(mat, mesh_id, data.drain(..));
+                }
+            }
+        };
+
+        match &visibility {
+            None => (input(), (!&hiddens, !&hiddens_prop, !&transparent))
+                .join()
+                .map(|((mat, mesh, tform, tint, component), _)| {
+                    (
+                        (mat, mesh.id()),
+                        I::from_object_data(tform, tint, component),
+                    )
+                })
+                .for_each_group(&mut insert),
+            Some(visibility) => (input(), &visibility.visible_unordered)
+                .join()
+                .map(|((mat, mesh, tform, tint, component), _)| {
+                    (
+                        (mat, mesh.id()),
+                        I::from_object_data(tform, tint, component),
+                    )
+                })
+                .for_each_group(&mut insert),
+        }
+        self.batches.prune();
+    }
+
+    /// Number of instances gathered.
+    pub fn count(&self) -> usize {
+        self.batches.count()
+    }
+
+    /// Upload the instances to the buffer of the frame `index`. Returns whether the buffer was
+    /// allocated, so command buffers binding the previous one must be recorded again.
+    pub fn write(&mut self, factory: &Factory<B>, index: usize, resources: &Resources) -> bool {
+        let allocated = self.buffer.write(
+            factory,
+            index,
+            self.batches.count() as u64,
+            self.batches.data(),
+        );
+        self.buffer.report(index, resources);
+        allocated
+    }
+
+    /// Draw the batches of the frame `index` with the pipeline and environment bound, binding
+    /// the material of each batch to the set `material_set` of `layout`. `mesh_formats` are the
+    /// sorted vertex formats the pipeline was built with by `util::instanced_vertex_desc`.
+    pub fn draw<T>(
+        &self,
+        index: usize,
+        mesh_formats: &[VertexFormat],
+        materials: &MaterialSub<B, T>,
+        layout: &B::PipelineLayout,
+        material_set: u32,
+        encoder: &mut RenderPassEncoder<'_, B>,
+        resources: &Resources,
+    ) where
+        T: for<'a> StaticTextureSet<'a>,
+    {
+        #[cfg(feature = "profiler")]
+        profile_scope!("draw");
+
+        if !self
+            .buffer
+            .bind(index, mesh_formats.len() as u32, 0, encoder)
+        {
+            return;
+        }
+        let mesh_storage = <Read<'_, AssetStorage<Mesh>>>::fetch(resources);
+        let mut instances_drawn = 0;
+        for (&mat_id, batches) in self.batches.iter() {
+            let loaded = materials.bind(layout, material_set, mat_id, encoder);
+            for (mesh_id, batch_data) in batches {
+                let instances = instances_drawn..instances_drawn + batch_data.len() as u32;
+                instances_drawn = instances.end;
+                if !loaded {
+                    continue;
+                }
+                debug_assert!(mesh_storage.contains_id(*mesh_id));
+                if let Some(mesh) =
+                    B::unwrap_mesh(unsafe { mesh_storage.get_by_id_unchecked(*mesh_id) })
+                {
+                    if mesh
+                        .bind_and_draw(0, mesh_formats, instances, encoder)
+                        .is_err()
+                    {
+                        content_errors::report(
+                            resources,
+                            RenderError::UnsupportedVertexLayout {
+                                mesh: *mesh_id,
+                                group: "InstanceSub",
+                            },
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        pod::VertexArgs,
+        rendy::{
+            hal::pso::VertexInputRate,
+            mesh::{AsVertex, Position, TexCoord},
+        },
+        util,
+    };
+
+    #[test]
+    fn instance_attributes_follow_mesh() {
+        let (buffers, attributes) = util::instanced_vertex_desc(
+            &[TexCoord::vertex(), Position::vertex()],
+            VertexArgs::vertex(),
+        );
+        assert_eq!(buffers.len(), 3);
+        assert_eq!(buffers[2].rate, VertexInputRate::Instance(1));
+        assert_eq!(buffers[2].stride, 80);
+
+        let locations = |binding| {
+            attributes
+                .iter()
+                .filter(|a| a.binding == binding)
+                .map(|a| a.location)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(locations(2), vec![2, 3, 4, 5, 6]);
+        let mut mesh = [locations(0), locations(1)].concat();
+        mesh.sort();
+        assert_eq!(mesh, vec![0, 1]);
+    }
+}
@@ -1,21 +1,23 @@
 //! Material abstraction submodule.
 use crate::{
+    fallback::FallbackAssets,
     mtl::{Material, StaticTextureSet},
     pod,
     rendy::{
         command::RenderPassEncoder,
         factory::Factory,
-        hal::{self, adapter::PhysicalDevice, device::Device, pso::Descriptor},
+        hal::{self, device::Device, pso::Descriptor},
         memory::Write as _,
         resource::{
             Buffer, BufferInfo, DescriptorSet, DescriptorSetLayout, Escape, Handle as RendyHandle,
         },
     },
+    texture_upload::TextureUploads,
     types::{Backend, Texture},
     util,
 };
 use amethyst_assets::{AssetStorage, Handle};
-use amethyst_core::ecs::{Read, Resources, SystemData};
+use amethyst_core::ecs::{Read, Resources, SystemData, Write};
 use glsl_layout::*;
 
 #[cfg(feature = "profiler")]
@@ -129,6 +131,15 @@ enum MaterialState<B: Backend> {
         set: Escape<DescriptorSet<B>>,
         slot: usize,
         generation: u32,
+        handle: Handle<Material>,
+        /// Versions of the material, followed by the versions of its textures. Textures drawn
+        /// with the placeholder of `TextureUploads` or the fallback texture have no version.
+        versions: Vec<Option<u32>>,
+        /// Whether the set binds the fallback material of `FallbackAssets`, as the material
+        /// failed to load or stayed unloaded for too long.
+        fallback: bool,
+        /// Number of textures drawn with the fallback texture.
+        fallback_textures: usize,
     },
 }
 
@@ -163,12 +174,13 @@ impl<B: Backend, T: for<'a> StaticTextureSet<'a>> MaterialSub<B, T> {
     }
 
     fn create_buffer(factory: &Factory<B>) -> Result<SlottedBuffer<B>, failure::Error> {
-        let align = factory
-            .physical()
-            .limits()
-            .min_uniform_buffer_offset_alignment;
-        let material_step = util::align_size::<pod::Material>(align, 1);
-        SlottedBuffer::new(factory, material_step, 1024, hal::buffer::Usage::UNIFORM)
+        // Each slot has its own descriptor, at the offset alignment of the uniform rings.
+        let usage = hal::buffer::Usage::UNIFORM;
+        let material_step = util::align_offset(
+            std::mem::size_of::<<pod::Material as AsStd140>::Std140>() as u64,
+            util::offset_alignment(factory, usage),
+        );
+        SlottedBuffer::new(factory, material_step, 1024, usage)
     }
 
     /// Returns the raw `DescriptorSetLayout` for this environment
@@ -176,9 +188,84 @@ impl<B: Backend, T: for<'a> StaticTextureSet<'a>> MaterialSub<B, T> {
         self.layout.raw()
     }
 
-    /// Increment the internal generation counter.
-    pub fn maintain(&mut self) {
-        self.generation += self.generation.wrapping_add(1);
+    /// Increment the internal generation counter, and rebuild the descriptor sets of materials
+    /// whose data or textures changed, like after a hot reload. Returns `true` if any
+    /// descriptor set was replaced, so draws recorded with the old sets must be re-recorded.
+    ///
+    /// Changed materials get a new descriptor set instead of rewriting the old one, which may
+    /// still be used by frames in flight. The old set and images are released once those frames
+    /// completed. Materials drawn with placeholder textures are rebuilt once they are loaded,
+    /// or once their fallback is due.
+    pub fn maintain(&mut self, factory: &Factory<B>, res: &Resources) -> bool {
+        #[cfg(feature = "profiler")]
+        profile_scope!("maintain");
+
+        let stale: Vec<_> = {
+            let (mat_storage, tex_storage, mut fallback) = <(
+                Read<'_, AssetStorage<Material>>,
+                Read<'_, AssetStorage<Texture>>,
+                Option<Write<'_, FallbackAssets>>,
+            )>::fetch(res);
+
+            self.materials
+                .iter()
+                .enumerate()
+                .filter_map(|(id, state)| match state {
+                    MaterialState::Loaded {
+                        handle,
+                        versions,
+                        fallback: is_fallback,
+                        fallback_textures,
+                        ..
+                    } => {
+                        let current = match mat_storage.get_with_version(handle) {
+                            Some((mat, version)) => {
+                                let due_textures = T::textures(mat)
+                                    .filter(|t| {
+                                        tex_storage.get_version(t).is_none()
+                                            && fallback.as_mut().map_or(false, |f| {
+                                                f.texture_for(t, tex_storage.failed(t)).is_some()
+                                            })
+                                    })
+                                    .count();
+                                !*is_fallback
+                                    && due_textures == *fallback_textures
+                                    && std::iter::once(Some(*version))
+                                        .chain(T::textures(mat).map(|t| tex_storage.get_version(t)))
+                                        .eq(versions.iter().cloned())
+                            }
+                            None => {
+                                *is_fallback
+                                    && fallback.as_mut().map_or(false, |f| {
+                                        f.material_for(handle, mat_storage.failed(handle)).is_some()
+                                    })
+                            }
+                        };
+                        if current {
+                            None
+                        } else {
+                            Some((id, handle.clone()))
+                        }
+                    }
+                    MaterialState::Unloaded { .. } => None,
+                })
+                .collect()
+        };
+
+        self.generation = self.generation.wrapping_add(1);
+        let changed = !stale.is_empty();
+        for (id, handle) in stale {
+            if let MaterialState::Loaded { slot, .. } = self.materials[id] {
+                self.allocator.release(slot);
+            }
+            self.materials[id] = MaterialState::Unloaded {
+                generation: self.generation,
+            };
+            if let Some(state) = self.try_insert(factory, res, &handle) {
+                self.materials[id] = state;
+            }
+        }
+        changed
     }
 
     /// Releases any materials not used in the current generation.
@@ -209,19 +296,57 @@ impl<B: Backend, T: for<'a> StaticTextureSet<'a>> MaterialSub<B, T> {
         profile_scope!("try_insert");
 
         use util::{desc_write, slice_as_bytes, texture_desc};
-        let (mat_storage, tex_storage) = <(
+        let (mat_storage, tex_storage, uploads, mut fallback) = <(
             Read<'_, AssetStorage<Material>>,
             Read<'_, AssetStorage<Texture>>,
+            Option<Read<'_, TextureUploads>>,
+            Option<Write<'_, FallbackAssets>>,
         )>::fetch(res);
 
-        let mat = mat_storage.get(handle)?;
+        let (mat, version, is_fallback) = match mat_storage.get_with_version(handle) {
+            Some((mat, version)) => (mat, version, false),
+            None => {
+                let substitute = fallback
+                    .as_mut()
+                    .and_then(|f| f.material_for(handle, mat_storage.failed(handle)))
+                    .cloned()?;
+                let (mat, version) = mat_storage.get_with_version(&substitute)?;
+                (mat, version, true)
+            }
+        };
 
-        if T::textures(mat).any(|t| {
-            !tex_storage
+        let loaded = |t: &Handle<Texture>| {
+            tex_storage
                 .get(t)
-                .map_or(false, |tex| B::unwrap_texture(tex).is_some())
-        }) {
-            return None;
+                .filter(|tex| B::unwrap_texture(tex).is_some())
+        };
+        let placeholder = uploads
+            .as_ref()
+            .and_then(|u| u.placeholder_handle())
+            .and_then(&loaded);
+        let fallback_texture = fallback
+            .as_ref()
+            .and_then(|f| f.texture())
+            .and_then(&loaded);
+        let mut fallback_textures = 0;
+        let mut textures = Vec::new();
+        for t in T::textures(mat) {
+            let tex = match loaded(t) {
+                Some(tex) => tex,
+                None => {
+                    let due = fallback
+                        .as_mut()
+                        .map_or(false, |f| f.texture_for(t, tex_storage.failed(t)).is_some());
+                    match fallback_texture.filter(|_| due) {
+                        Some(tex) => {
+                            fallback_textures += 1;
+                            tex
+                        }
+                        None => placeholder?,
+                    }
+                }
+            };
+            textures.push(tex);
         }
 
         let pod = pod::Material::from_material(&mat).std140();
@@ -244,25 +369,28 @@ impl<B: Backend, T: for<'a> StaticTextureSet<'a>> MaterialSub<B, T> {
         unsafe {
             let set = set.raw();
 
-            let tex_descs = T::textures(mat).enumerate().map(|(i, t)| {
+            let tex_descs = textures.iter().enumerate().map(|(i, tex)| {
                 desc_write(
                     set,
                     (i + 1) as u32,
-                    texture_desc(
-                        tex_storage.get(t).unwrap(),
-                        hal::image::Layout::ShaderReadOnlyOptimal,
-                    )
-                    .unwrap(),
+                    texture_desc(tex, hal::image::Layout::ShaderReadOnlyOptimal).unwrap(),
                 )
             });
 
             let desc_iter = std::iter::once(desc_write(set, 0, buf_desc)).chain(tex_descs);
             factory.write_descriptor_sets(desc_iter);
         }
+        let versions = std::iter::once(Some(*version))
+            .chain(T::textures(mat).map(|t| loaded(t).and(tex_storage.get_version(t))))
+            .collect();
         Some(MaterialState::Loaded {
             set,
             slot,
             generation: self.generation,
+            handle: handle.clone(),
+            versions,
+            fallback: is_fallback,
+            fallback_textures,
         })
     }
 
@@ -323,7 +451,8 @@ impl<B: Backend, T: for<'a> StaticTextureSet<'a>> MaterialSub<B, T> {
         }
     }
 
-    /// Binds all material descriptor sets and textures contained in this collection.
+    /// Binds all material descriptor sets and textures contained in this collection. Returns
+    /// whether the material is loaded, binding nothing otherwise.
     #[inline]
     pub fn bind(
         &self,
@@ -331,17 +460,20 @@ impl<B: Backend, T: for<'a> StaticTextureSet<'a>> MaterialSub<B, T> {
         set_id: u32,
         material_id: MaterialId,
         encoder: &mut RenderPassEncoder<'_, B>,
-    ) {
+    ) -> bool {
         match &self.materials[material_id.0 as usize] {
-            MaterialState::Loaded { set, .. } => unsafe {
-                encoder.bind_graphics_descriptor_sets(
-                    pipeline_layout,
-                    set_id,
-                    Some(set.raw()),
-                    std::iter::empty(),
-                );
-            },
-            _ => panic!("Trying to bind unloaded material"),
-        };
+            MaterialState::Loaded { set, .. } => {
+                unsafe {
+                    encoder.bind_graphics_descriptor_sets(
+                        pipeline_layout,
+                        set_id,
+                        Some(set.raw()),
+                        std::iter::empty(),
+                    );
+                }
+                true
+            }
+            _ => false,
+        }
     }
 }
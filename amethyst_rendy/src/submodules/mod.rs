@@ -1,18 +1,24 @@
 //! Various helpers and implementations for sub functions of render passes.
 mod environment;
 mod flat_environment;
+mod instance;
 mod material;
+mod push_constant;
 mod skinning;
 mod texture;
 mod uniform;
+mod uniform_ring;
 mod vertex;
 
 pub mod gather;
 
 pub use environment::*;
 pub use flat_environment::*;
+pub use instance::*;
 pub use material::*;
+pub use push_constant::*;
 pub use skinning::*;
 pub use texture::*;
 pub use uniform::*;
+pub use uniform_ring::*;
 pub use vertex::*;
@@ -0,0 +1,166 @@
+//! Small per-draw data passed as push constants, or through a dynamic uniform when it's too large.
+//!
+//! Push constants are recorded into the command buffer, without the buffer, descriptor set and
+//! alignment padding of a uniform. `PushConstants` declares a `pod` struct as the push constant
+//! range of a pipeline layout and pushes it when drawing. Structs larger than
+//! `MAX_PUSH_CONSTANTS_SIZE`, the size every device supports, fall back to a `DynamicUniform` of
+//! the next descriptor set, and so do the ones created with `PushConstants::uniform`.
+//!
+//! The shaders of both modes differ, declaring the struct as a `push_constant` block or as a
+//! uniform block of a descriptor set, so a group builds its pipelines with the shaders of the
+//! mode chosen, as told by `pushed`. The debug lines groups draw this way.
+use crate::{
+    rendy::{command::RenderPassEncoder, factory::Factory, hal::pso::ShaderStageFlags},
+    submodules::DynamicUniform,
+    types::Backend,
+};
+use derivative::Derivative;
+use glsl_layout::AsStd140;
+use std::ops::Range;
+
+/// Size in bytes of the push constants every device supports. gfx-hal doesn't report the limit
+/// of the device, which can be larger.
+pub const MAX_PUSH_CONSTANTS_SIZE: u32 = 128;
+
+/// Whether a struct of `size` bytes can be pushed.
+fn fits_push_constants(size: usize) -> bool {
+    size as u32 <= MAX_PUSH_CONSTANTS_SIZE && size % 4 == 0
+}
+
+/// The words of a struct whose size is a multiple of 4, as checked by `fits_push_constants`.
+fn as_words<T>(item: &T) -> &[u32] {
+    unsafe {
+        std::slice::from_raw_parts(item as *const T as *const u32, std::mem::size_of::<T>() / 4)
+    }
+}
+
+/// A `T` of each image, passed as push constants when it fits, or as a `DynamicUniform`.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+pub struct PushConstants<B: Backend, T: AsStd140>
+where
+    T::Std140: Sized,
+{
+    stages: ShaderStageFlags,
+    uniform: Option<DynamicUniform<B, T>>,
+    #[derivative(Debug = "ignore")]
+    per_image: Vec<Option<T::Std140>>,
+}
+
+impl<B: Backend, T: AsStd140> PushConstants<B, T>
+where
+    T::Std140: Sized,
+{
+    /// Pass `T` to the shaders of `stages` as push constants, or as a uniform if it doesn't fit.
+    pub fn new(factory: &Factory<B>, stages: ShaderStageFlags) -> Result<Self, failure::Error> {
+        if fits_push_constants(std::mem::size_of::<T::Std140>()) {
+            Ok(Self {
+                stages,
+                uniform: None,
+                per_image: Vec::new(),
+            })
+        } else {
+            Self::uniform(factory, stages)
+        }
+    }
+
+    /// Pass `T` to the shaders of `stages` as a uniform, for backends without push constants.
+    pub fn uniform(factory: &Factory<B>, stages: ShaderStageFlags) -> Result<Self, failure::Error> {
+        Ok(Self {
+            stages,
+            uniform: Some(DynamicUniform::new(factory, stages)?),
+            per_image: Vec::new(),
+        })
+    }
+
+    /// Whether `T` is passed as push constants, rather than as a uniform.
+    pub fn pushed(&self) -> bool {
+        self.uniform.is_none()
+    }
+
+    /// The layout of the descriptor set of the uniform, when `T` isn't pushed.
+    pub fn raw_layout(&self) -> Option<&B::DescriptorSetLayout> {
+        self.uniform.as_ref().map(DynamicUniform::raw_layout)
+    }
+
+    /// The push constant range of the pipeline layout, when `T` is pushed.
+    pub fn range(&self) -> Option<(ShaderStageFlags, Range<u32>)> {
+        if self.pushed() {
+            Some((self.stages, 0..std::mem::size_of::<T::Std140>() as u32))
+        } else {
+            None
+        }
+    }
+
+    /// Set the `T` of the frame `index`. Returns whether command buffers must be recorded
+    /// again: when a pushed `T` differs from the one recorded into them, or when the descriptor
+    /// set of the uniform was created.
+    pub fn write(&mut self, factory: &Factory<B>, index: usize, item: T::Std140) -> bool {
+        match &mut self.uniform {
+            Some(uniform) => uniform.write(factory, index, item),
+            None => {
+                while self.per_image.len() <= index {
+                    self.per_image.push(None);
+                }
+                let changed = self.per_image[index]
+                    .as_ref()
+                    .map_or(true, |old| as_words(old) != as_words(&item));
+                self.per_image[index] = Some(item);
+                changed
+            }
+        }
+    }
+
+    /// Push the `T` of the frame `index`, or bind its uniform to the set `set_id` of
+    /// `pipeline_layout`.
+    pub fn bind(
+        &self,
+        index: usize,
+        pipeline_layout: &B::PipelineLayout,
+        set_id: u32,
+        encoder: &mut RenderPassEncoder<'_, B>,
+    ) {
+        match &self.uniform {
+            Some(uniform) => uniform.bind(index, pipeline_layout, set_id, encoder),
+            None => {
+                if let Some(Some(item)) = self.per_image.get(index) {
+                    self.push(pipeline_layout, item, encoder);
+                }
+            }
+        }
+    }
+
+    /// Push `item` for the next draws, replacing the `T` of the frame. Draws with their own `T`
+    /// need push constants, this does nothing when `T` is a uniform.
+    pub fn push(
+        &self,
+        pipeline_layout: &B::PipelineLayout,
+        item: &T::Std140,
+        encoder: &mut RenderPassEncoder<'_, B>,
+    ) {
+        debug_assert!(self.pushed(), "Pushing a `T` passed as a uniform");
+        if !self.pushed() {
+            return;
+        }
+        unsafe {
+            encoder.push_constants(pipeline_layout, self.stages, 0, as_words(item));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn large_structs_fall_back() {
+        assert!(fits_push_constants(8));
+        assert!(fits_push_constants(MAX_PUSH_CONSTANTS_SIZE as usize));
+        assert!(!fits_push_constants(MAX_PUSH_CONSTANTS_SIZE as usize + 16));
+        assert!(!fits_push_constants(6));
+        assert_eq!(
+            as_words(&[1.0f32, 2.0]),
+            &[1.0f32.to_bits(), 2.0f32.to_bits()]
+        );
+    }
+}
@@ -3,83 +3,185 @@ use crate::{
     rendy::{
         command::RenderPassEncoder,
         factory::Factory,
-        hal::{self, device::Device, pso::Descriptor},
-        memory::Write as _,
-        resource::{Buffer, DescriptorSet, DescriptorSetLayout, Escape, Handle as RendyHandle},
+        hal::{self, adapter::PhysicalDevice, pso::Descriptor},
+        resource::{DescriptorSet, DescriptorSetLayout, Escape, Handle as RendyHandle},
     },
     skinning::JointTransforms,
+    submodules::DynamicUniformRing,
     types::Backend,
     util,
 };
+use amethyst_core::ecs::Resources;
 use fnv::FnvHashMap;
+use std::mem::size_of;
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
 
+/// Number of joint matrices a frame can hold on devices without storage buffers, which reports
+/// a storage buffer range of 0. The joints are then bound as a uniform block of this many
+/// matrices, the 16KiB every Vulkan device guarantees for a uniform buffer.
+pub const FALLBACK_MAX_JOINTS: u32 = 256;
+
+/// Size in bytes of the uniform block of joints, see `FALLBACK_MAX_JOINTS`.
+const UNIFORM_JOINTS_SIZE: u64 = FALLBACK_MAX_JOINTS as u64 * size_of::<[[f32; 4]; 4]>() as u64;
+
+/// How the `SkinningSub` binds the joint matrices to the vertex shaders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JointBuffer {
+    /// A storage buffer, holding as many joints as the storage buffer range of the device.
+    Storage,
+    /// A uniform buffer of `FALLBACK_MAX_JOINTS` joints, for devices without storage buffers.
+    /// Passes draw skinned meshes with their `vertex_skinned_uniform_shader`.
+    Uniform,
+}
+
 /// Provides per-image abstraction for submitting skinned mesh skeletal information.
+///
+/// The joint matrices of every skin are pushed contiguously to a `DynamicUniformRing`, each
+/// instance reading its skin at its own offset. The ring holds storage buffers, limiting the
+/// total number of joints by the storage buffer range of the device, or on devices without
+/// storage buffers a uniform buffer of `FALLBACK_MAX_JOINTS` joints. See `JointBuffer`.
+///
+/// A single `SkinningSub` is kept as a resource by the `RenderingSystem` and shared by all
+/// passes drawing skinned meshes. The first pass prepared in a frame uploads the joints, later
+/// passes reuse them.
 #[derive(Debug)]
 pub struct SkinningSub<B: Backend> {
     layout: RendyHandle<DescriptorSetLayout<B>>,
-    skin_offset_map: FnvHashMap<u32, u32>,
-    staging: Vec<[[f32; 4]; 4]>,
+    buffer: JointBuffer,
+    staging: JointStaging,
+    ring: DynamicUniformRing<B>,
     per_image: Vec<PerImageSkinningSub<B>>,
+    committed: bool,
+}
+
+/// Joint matrices of a frame, waiting to be written to GPU memory.
+#[derive(Debug)]
+struct JointStaging {
+    max_joints: u32,
+    skin_offset_map: FnvHashMap<(u32, u32), u32>,
+    matrices: Vec<[[f32; 4]; 4]>,
+    warned: bool,
 }
 
 #[derive(Debug)]
 struct PerImageSkinningSub<B: Backend> {
-    buffer: Option<Escape<Buffer<B>>>,
     set: Escape<DescriptorSet<B>>,
 }
 
 impl<B: Backend> SkinningSub<B> {
     /// Create a new `SkinningSub`, allocating using the provided `Factory`
     pub fn new(factory: &Factory<B>) -> Result<Self, failure::Error> {
+        let range = factory.physical().limits().max_storage_buffer_range;
+        let (buffer, max_joints) = joint_buffer(range);
+        let (layout, usage) = match buffer {
+            JointBuffer::Storage => (
+                set_layout! {factory, [1] StorageBuffer VERTEX},
+                hal::buffer::Usage::STORAGE,
+            ),
+            JointBuffer::Uniform => (
+                set_layout! {factory, [1] UniformBuffer VERTEX},
+                hal::buffer::Usage::UNIFORM,
+            ),
+        };
+
         Ok(Self {
-            layout: set_layout! {factory, [1] StorageBuffer VERTEX},
-            skin_offset_map: Default::default(),
-            staging: Vec::new(),
+            layout,
+            buffer,
+            staging: JointStaging::new(max_joints),
+            ring: DynamicUniformRing::new(factory, usage),
             per_image: Vec::new(),
+            committed: false,
         })
     }
 
+    /// Returns how the joints are bound to the vertex shaders.
+    pub fn joint_buffer(&self) -> JointBuffer {
+        self.buffer
+    }
+
+    /// Returns the maximum number of joint matrices that can be submitted in one frame.
+    pub fn max_joints(&self) -> u32 {
+        self.staging.max_joints
+    }
+
     /// Returns the raw `DescriptorSetLayout` of a skinning submission.
     pub fn raw_layout(&self) -> &B::DescriptorSetLayout {
         self.layout.raw()
     }
 
-    /// Allocates and writes the skinning information to GPU memory
-    pub fn commit(&mut self, factory: &Factory<B>, index: usize) {
-        let this_image = {
-            while self.per_image.len() <= index {
-                self.per_image
-                    .push(PerImageSkinningSub::new(factory, &self.layout));
-            }
-            &mut self.per_image[index]
-        };
-        this_image.commit(factory, util::slice_as_bytes(&self.staging));
+    /// Writes the joints of all `JointTransforms` to GPU memory, unless they were already
+    /// written this frame.
+    pub fn prepare<'a>(
+        &mut self,
+        factory: &Factory<B>,
+        index: usize,
+        joints: impl IntoIterator<Item = &'a JointTransforms>,
+        resources: &Resources,
+    ) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("prepare");
+
+        if self.committed {
+            return;
+        }
         self.staging.clear();
-        self.skin_offset_map.clear();
+        for joints in joints {
+            self.staging.insert(joints);
+        }
+        self.commit(factory, index, resources);
+        self.committed = true;
     }
 
-    /// Insert a new `JointTransforms` instance for submission. Returns an index.
-    pub fn insert(&mut self, joints: &JointTransforms) -> u32 {
-        #[cfg(feature = "profiler")]
-        profile_scope!("insert");
-
-        let staging = &mut self.staging;
-        *self
-            .skin_offset_map
-            .entry(joints.skin.id())
-            .or_insert_with(|| {
-                let len = staging.len();
-                staging.extend(
-                    joints
-                        .matrices
-                        .iter()
-                        .map(|m| -> [[f32; 4]; 4] { (*m).into() }),
-                );
-                len as u32
-            })
+    /// Returns the offset of the first joint of a `JointTransforms` written this frame, or
+    /// `None` if its joints did not fit in the buffer.
+    pub fn offset(&self, joints: &JointTransforms) -> Option<u32> {
+        self.staging.offset(joints)
+    }
+
+    /// Marks the joints as outdated, so that they are written again by the next `prepare`.
+    pub(crate) fn next_frame(&mut self) {
+        self.committed = false;
+    }
+
+    /// Frees the buffers of the frames from `frames_in_flight` on, once the render graph was
+    /// rebuilt with fewer frames in flight.
+    pub(crate) fn retain_frames(&mut self, frames_in_flight: usize) {
+        self.per_image.truncate(frames_in_flight);
+        self.ring.retain_frames(frames_in_flight);
+    }
+
+    fn commit(&mut self, factory: &Factory<B>, index: usize, resources: &Resources) {
+        while self.per_image.len() <= index {
+            self.per_image
+                .push(PerImageSkinningSub::new(factory, &self.layout));
+        }
+        if self.staging.matrices.is_empty() {
+            return;
+        }
+
+        self.ring.clear();
+        let range = match self.buffer {
+            JointBuffer::Storage => {
+                self.ring.push_slice(&self.staging.matrices);
+                None
+            }
+            JointBuffer::Uniform => {
+                // The whole block is bound, with the matrices at its start.
+                let block = self.ring.reserve(UNIFORM_JOINTS_SIZE);
+                let matrices = util::slice_as_bytes(&self.staging.matrices);
+                let start = block.start as usize;
+                self.ring.data_mut()[start..start + matrices.len()].copy_from_slice(matrices);
+                Some(block.end)
+            }
+        };
+        if self.ring.commit(factory, index) {
+            if let Some(buffer) = self.ring.buffer(index) {
+                self.per_image[index].write_descriptor(factory, buffer, range);
+            }
+        }
+        self.ring.report(index, resources);
     }
 
     /// Bind the skinned skeletal information.
@@ -91,51 +193,90 @@ impl<B: Backend> SkinningSub<B> {
         set_id: u32,
         encoder: &mut RenderPassEncoder<'_, B>,
     ) {
-        self.per_image[index].bind(pipeline_layout, set_id, encoder);
+        if let Some(image) = self.per_image.get(index) {
+            image.bind(pipeline_layout, set_id, encoder);
+        }
     }
 }
 
-impl<B: Backend> PerImageSkinningSub<B> {
-    fn new(factory: &Factory<B>, layout: &RendyHandle<DescriptorSetLayout<B>>) -> Self {
+/// The binding of the joints and their maximum count for a storage buffer range.
+fn joint_buffer(storage_buffer_range: u64) -> (JointBuffer, u32) {
+    if storage_buffer_range == 0 {
+        (JointBuffer::Uniform, FALLBACK_MAX_JOINTS)
+    } else {
+        let max_joints = storage_buffer_range / size_of::<[[f32; 4]; 4]>() as u64;
+        (
+            JointBuffer::Storage,
+            max_joints.min(u64::from(u32::max_value())) as u32,
+        )
+    }
+}
+
+impl JointStaging {
+    fn new(max_joints: u32) -> Self {
         Self {
-            buffer: None,
-            set: factory.create_descriptor_set(layout.clone()).unwrap(),
+            max_joints,
+            skin_offset_map: Default::default(),
+            matrices: Vec::new(),
+            warned: false,
         }
     }
 
-    fn commit(&mut self, factory: &Factory<B>, data: &[u8]) {
-        if data.is_empty() {
-            return;
+    fn insert(&mut self, joints: &JointTransforms) -> Option<u32> {
+        // Meshes sharing a skin share its matrices, unless they disagree on the joint count.
+        if let Some(offset) = self.offset(joints) {
+            return Some(offset);
         }
 
-        let allocated = util::ensure_buffer(
-            &factory,
-            &mut self.buffer,
-            hal::buffer::Usage::STORAGE,
-            rendy::memory::Dynamic,
-            data.len() as u64,
-        )
-        .unwrap();
-
-        if let Some(buffer) = self.buffer.as_mut() {
-            if allocated {
-                unsafe {
-                    factory.write_descriptor_sets(Some(util::desc_write(
-                        self.set.raw(),
-                        0,
-                        Descriptor::Buffer(buffer.raw(), Some(0)..None),
-                    )));
-                }
+        let count = joints.matrices.len() as u32;
+        let offset = self.matrices.len() as u32;
+        if u64::from(offset) + u64::from(count) > u64::from(self.max_joints) {
+            if !self.warned {
+                log::warn!(
+                    "Skinned meshes need more than {} joints this frame, skipping some of them",
+                    self.max_joints
+                );
+                self.warned = true;
             }
+            return None;
+        }
+
+        self.matrices.extend(
+            joints
+                .matrices
+                .iter()
+                .map(|m| -> [[f32; 4]; 4] { (*m).into() }),
+        );
+        self.skin_offset_map
+            .insert((joints.skin.id(), count), offset);
+        Some(offset)
+    }
+
+    fn offset(&self, joints: &JointTransforms) -> Option<u32> {
+        let key = (joints.skin.id(), joints.matrices.len() as u32);
+        self.skin_offset_map.get(&key).cloned()
+    }
 
-            let mut mapped = buffer.map(factory.device(), 0..data.len() as u64).unwrap();
-            let mut writer = unsafe {
-                mapped
-                    .write(factory.device(), 0..data.len() as u64)
-                    .unwrap()
-            };
-            let dst_slice = unsafe { writer.slice() };
-            dst_slice.copy_from_slice(data);
+    fn clear(&mut self) {
+        self.matrices.clear();
+        self.skin_offset_map.clear();
+    }
+}
+
+impl<B: Backend> PerImageSkinningSub<B> {
+    fn new(factory: &Factory<B>, layout: &RendyHandle<DescriptorSetLayout<B>>) -> Self {
+        Self {
+            set: factory.create_descriptor_set(layout.clone()).unwrap(),
+        }
+    }
+
+    fn write_descriptor(&self, factory: &Factory<B>, buffer: &B::Buffer, end: Option<u64>) {
+        unsafe {
+            factory.write_descriptor_sets(Some(util::desc_write(
+                self.set.raw(),
+                0,
+                Descriptor::Buffer(buffer, Some(0)..end),
+            )));
         }
     }
 
@@ -156,3 +297,90 @@ impl<B: Backend> PerImageSkinningSub<B> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst_core::{
+        ecs::prelude::{Builder, Entity, World},
+        math::Matrix4,
+    };
+
+    fn joints(skin: Entity, count: usize, value: f32) -> JointTransforms {
+        JointTransforms {
+            skin,
+            matrices: vec![Matrix4::from_element(value); count],
+        }
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn skins_do_not_overlap() {
+        let mut world = World::new();
+        let big = world.create_entity().build();
+        let small = world.create_entity().build();
+        let mut staging = JointStaging::new(1024);
+
+        assert_eq!(staging.insert(&joints(big, 256, 1.0)), Some(0));
+        assert_eq!(staging.insert(&joints(small, 3, 2.0)), Some(256));
+        assert_eq!(staging.insert(&joints(big, 256, 1.0)), Some(0));
+        assert_eq!(staging.matrices.len(), 259);
+        assert!(staging.matrices[..256].iter().all(|m| m[3][3] == 1.0));
+        assert!(staging.matrices[256..].iter().all(|m| m[3][3] == 2.0));
+
+        // A mesh disagreeing on the joint count of a skin gets its own copy.
+        assert_eq!(staging.insert(&joints(small, 5, 3.0)), Some(259));
+        assert_eq!(staging.matrices.len(), 264);
+        assert_eq!(staging.offset(&joints(small, 3, 0.0)), Some(256));
+        assert_eq!(staging.offset(&joints(small, 5, 0.0)), Some(259));
+
+        staging.clear();
+        assert_eq!(staging.insert(&joints(small, 3, 2.0)), Some(0));
+    }
+
+    #[test]
+    fn devices_without_storage_buffers_use_a_uniform_block() {
+        assert_eq!(joint_buffer(0), (JointBuffer::Uniform, FALLBACK_MAX_JOINTS));
+        assert_eq!(UNIFORM_JOINTS_SIZE, 16 * 1024);
+        assert_eq!(joint_buffer(128 << 20), (JointBuffer::Storage, 2 << 20));
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)]
+    fn skeleton_of_256_joints_fits_the_fallback() {
+        let mut world = World::new();
+        let skeleton = world.create_entity().build();
+        let other = world.create_entity().build();
+        let (_, max_joints) = joint_buffer(0);
+        let mut staging = JointStaging::new(max_joints);
+
+        let skin = JointTransforms {
+            skin: skeleton,
+            matrices: (0..256).map(|i| Matrix4::from_element(i as f32)).collect(),
+        };
+        assert_eq!(staging.insert(&skin), Some(0));
+        assert_eq!(staging.matrices.len(), 256);
+        for (i, matrix) in staging.matrices.iter().enumerate() {
+            assert_eq!(matrix[0][0], i as f32);
+        }
+        assert!(!staging.warned);
+
+        // The next skin doesn't fit, it's skipped with a warning.
+        assert_eq!(staging.insert(&joints(other, 1, 1.0)), None);
+        assert!(staging.warned);
+        assert_eq!(staging.matrices.len(), 256);
+    }
+
+    #[test]
+    fn joints_over_limit_are_skipped() {
+        let mut world = World::new();
+        let first = world.create_entity().build();
+        let second = world.create_entity().build();
+        let mut staging = JointStaging::new(FALLBACK_MAX_JOINTS);
+
+        assert_eq!(staging.insert(&joints(first, 200, 1.0)), Some(0));
+        assert_eq!(staging.insert(&joints(second, 100, 1.0)), None);
+        assert_eq!(staging.offset(&joints(second, 100, 1.0)), None);
+        assert_eq!(staging.matrices.len(), 200);
+    }
+}
@@ -1,16 +1,18 @@
 //! Texture submodule for per-image submission.
 use crate::{
+    fallback::FallbackAssets,
     rendy::{
         command::RenderPassEncoder,
         factory::Factory,
         hal::{self, device::Device},
         resource::{DescriptorSet, DescriptorSetLayout, Escape, Handle as RendyHandle},
     },
+    texture_upload::TextureUploads,
     types::{Backend, Texture},
     util,
 };
 use amethyst_assets::{AssetStorage, Handle};
-use amethyst_core::ecs::{Read, Resources, SystemData};
+use amethyst_core::ecs::{Read, Resources, SystemData, Write};
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
@@ -26,6 +28,12 @@ enum TextureState<B: Backend> {
         version: u32,
         handle: Handle<Texture>,
         layout: hal::image::Layout,
+        /// Whether the set binds the placeholder of `TextureUploads`, as the texture is loading,
+        /// or the fallback texture.
+        placeholder: bool,
+        /// Whether the set binds the fallback texture of `FallbackAssets`, as the texture failed
+        /// to load or stayed unloaded for too long.
+        fallback: bool,
     },
 }
 
@@ -58,80 +66,147 @@ impl<B: Backend> TextureSub<B> {
         self.layout.raw()
     }
 
-    /// Generationally track our currently allocated vs. used textures and release memory for any
-    /// textures which have been removed from this submission set.
-    pub fn maintain(&mut self, factory: &Factory<B>, res: &Resources) {
+    /// Generationally track our currently allocated vs. used textures and refresh the
+    /// descriptor sets of textures whose asset changed, like after a hot reload. Returns `true`
+    /// if any descriptor set was replaced, so draws recorded with the old sets must be
+    /// re-recorded.
+    ///
+    /// Changed textures get a new descriptor set instead of rewriting the old one, which may
+    /// still be used by frames in flight. The old set and image are released once those frames
+    /// completed. Textures drawn with a placeholder get their set once they are loaded, or the
+    /// set of the fallback texture once it's due.
+    ///
+    /// Together with `MaterialSub::maintain` this covers every set sampling a texture asset: the
+    /// sets of `EnvironmentSub` and of the skybox only hold uniform buffers.
+    pub fn maintain(&mut self, factory: &Factory<B>, res: &Resources) -> bool {
         #[cfg(feature = "profiler")]
         profile_scope!("maintain");
 
-        use util::{desc_write, texture_desc};
-        let tex_storage = <(Read<'_, AssetStorage<Texture>>)>::fetch(res);
-        for state in self.textures.iter_mut() {
-            match state {
+        let (tex_storage, uploads, mut fallback) = <(
+            Read<'_, AssetStorage<Texture>>,
+            Option<Read<'_, TextureUploads>>,
+            Option<Write<'_, FallbackAssets>>,
+        )>::fetch(res);
+        let placeholder = uploads.as_ref().and_then(|u| u.placeholder_handle());
+        let mut changed = false;
+        for i in 0..self.textures.len() {
+            let (handle, layout) = match &self.textures[i] {
                 TextureState::Loaded {
-                    generation,
-                    set,
                     version,
                     handle,
                     layout,
-                } if *generation == self.generation => {
-                    if let Some((new_tex, new_version)) = tex_storage.get_with_version(handle) {
-                        if version != new_version {
-                            if let Some(desc) = texture_desc(new_tex, *layout) {
-                                unsafe {
-                                    let set = set.raw();
-                                    factory.write_descriptor_sets(vec![desc_write(set, 0, desc)]);
-                                }
-                                *version = *new_version;
-                            } else {
-                                *state = TextureState::Unloaded {
-                                    generation: self.generation,
-                                };
-                            }
+                    placeholder: is_placeholder,
+                    fallback: is_fallback,
+                    ..
+                } => match tex_storage.get_version(handle) {
+                    Some(new_version) if !is_placeholder && new_version == *version => continue,
+                    None if *is_placeholder => {
+                        let due = fallback.as_mut().map_or(false, |f| {
+                            f.texture_for(handle, tex_storage.failed(handle)).is_some()
+                        });
+                        if due == *is_fallback {
+                            continue;
                         }
-                    } else {
-                        *state = TextureState::Unloaded {
-                            generation: self.generation,
-                        };
+                        (handle.clone(), *layout)
                     }
-                }
-                // Todo: cleanup long unused textures
-                _ => {}
-            }
+                    _ => (handle.clone(), *layout),
+                },
+                TextureState::Unloaded { .. } => continue,
+            };
+            changed = true;
+            let state = Self::create_state(
+                factory,
+                &tex_storage,
+                placeholder,
+                fallback.as_mut().map(|f| &mut **f),
+                &self.layout,
+                &handle,
+                layout,
+                self.generation,
+            );
+            self.textures[i] = state.unwrap_or(TextureState::Unloaded {
+                generation: self.generation,
+            });
         }
-        self.generation += self.generation.wrapping_add(1);
+        self.generation = self.generation.wrapping_add(1);
+        changed
     }
 
-    /// Try to insert a new texture for submission in this texture batch. Returns None if it fails.
-    fn try_insert(
-        &mut self,
+    fn create_state(
         factory: &Factory<B>,
-        res: &Resources,
+        tex_storage: &AssetStorage<Texture>,
+        placeholder: Option<&Handle<Texture>>,
+        fallback: Option<&mut FallbackAssets>,
+        layout_handle: &RendyHandle<DescriptorSetLayout<B>>,
         handle: &Handle<Texture>,
         layout: hal::image::Layout,
+        generation: u32,
     ) -> Option<TextureState<B>> {
-        #[cfg(feature = "profiler")]
-        profile_scope!("try_insert");
-
         use util::{desc_write, texture_desc};
-        let tex_storage = <(Read<'_, AssetStorage<Texture>>)>::fetch(res);
 
-        let (tex, version) = tex_storage.get_with_version(handle)?;
+        let (tex, version, is_placeholder, is_fallback) = match tex_storage.get_with_version(handle)
+        {
+            Some((tex, version)) => (tex, version, false, false),
+            None => {
+                let fallback = fallback
+                    .and_then(|f| f.texture_for(handle, tex_storage.failed(handle)))
+                    .and_then(|f| tex_storage.get_with_version(f));
+                match fallback {
+                    Some((tex, version)) => (tex, version, true, true),
+                    None => {
+                        let (tex, version) = tex_storage.get_with_version(placeholder?)?;
+                        (tex, version, true, false)
+                    }
+                }
+            }
+        };
         let desc = texture_desc(tex, layout)?;
-        let set = factory.create_descriptor_set(self.layout.clone()).unwrap();
+        let set = factory
+            .create_descriptor_set(layout_handle.clone())
+            .unwrap();
         unsafe {
             let set = set.raw();
             factory.write_descriptor_sets(vec![desc_write(set, 0, desc)]);
         }
         Some(TextureState::Loaded {
             set,
-            generation: self.generation,
+            generation,
             version: *version,
             handle: handle.clone(),
             layout,
+            placeholder: is_placeholder,
+            fallback: is_fallback,
         })
     }
 
+    /// Try to insert a new texture for submission in this texture batch. Returns None if it fails.
+    fn try_insert(
+        &mut self,
+        factory: &Factory<B>,
+        res: &Resources,
+        handle: &Handle<Texture>,
+        layout: hal::image::Layout,
+    ) -> Option<TextureState<B>> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("try_insert");
+
+        let (tex_storage, uploads, mut fallback) = <(
+            Read<'_, AssetStorage<Texture>>,
+            Option<Read<'_, TextureUploads>>,
+            Option<Write<'_, FallbackAssets>>,
+        )>::fetch(res);
+        Self::create_state(
+            factory,
+            &tex_storage,
+            uploads.as_ref().and_then(|u| u.placeholder_handle()),
+            fallback.as_mut().map(|f| &mut **f),
+            &self.layout,
+            handle,
+            layout,
+            self.generation,
+        )
+    }
+
     /// Try to insert a new texture for submission in this texture batch.
     pub fn insert(
         &mut self,
@@ -188,7 +263,7 @@ impl<B: Backend> TextureSub<B> {
         }
     }
 
-    /// Bind all textures
+    /// Bind all textures. Returns whether the texture is loaded, binding nothing otherwise.
     #[inline]
     pub fn bind(
         &self,
@@ -196,17 +271,20 @@ impl<B: Backend> TextureSub<B> {
         set_id: u32,
         texture_id: TextureId,
         encoder: &mut RenderPassEncoder<'_, B>,
-    ) {
+    ) -> bool {
         match &self.textures[texture_id.0 as usize] {
-            TextureState::Loaded { set, .. } => unsafe {
-                encoder.bind_graphics_descriptor_sets(
-                    pipeline_layout,
-                    set_id,
-                    Some(set.raw()),
-                    std::iter::empty(),
-                );
-            },
-            _ => panic!("Trying to bind unloaded texture"),
+            TextureState::Loaded { set, .. } => {
+                unsafe {
+                    encoder.bind_graphics_descriptor_sets(
+                        pipeline_layout,
+                        set_id,
+                        Some(set.raw()),
+                        std::iter::empty(),
+                    );
+                }
+                true
+            }
+            _ => false,
         }
     }
 }
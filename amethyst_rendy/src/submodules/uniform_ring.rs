@@ -0,0 +1,280 @@
+//! Per-image buffers of aligned uniform or storage data, pushed anew every frame.
+//!
+//! A `DynamicUniformRing` stages the data of a frame in CPU memory, each push starting at the
+//! offset alignment the device requires for the descriptors of the buffer usage, and uploads it
+//! to the buffer of the frame index, of which there's one per frame in flight. Buffers grow to
+//! the next power of two of the size pushed. Every commit is added to the `UniformRingStats`
+//! resource, with the bytes lost to alignment.
+//!
+//! The environment and skinning submodules upload through it, the material slots use the same
+//! alignment.
+use crate::{
+    rendy::{
+        factory::Factory,
+        hal,
+        memory::Write as _,
+        resource::{Buffer, Escape},
+    },
+    types::Backend,
+    util,
+};
+use amethyst_core::ecs::Resources;
+use std::ops::Range;
+
+/// Resource with the usage of the uniform rings of the render groups, reset every frame by the
+/// `RenderingSystem`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct UniformRingStats {
+    /// Bytes allocated by the buffers committed this frame.
+    pub allocated_bytes: u64,
+    /// Bytes pushed to them this frame, with the alignment padding.
+    pub used_bytes: u64,
+    /// Bytes of alignment padding between the values pushed this frame.
+    pub padding_bytes: u64,
+    /// Bytes uploaded this frame.
+    pub uploaded_bytes: u64,
+    /// Buffers allocated again this frame, to grow them.
+    pub reallocations: u32,
+}
+
+impl UniformRingStats {
+    fn record(&mut self, commit: &LastCommit) {
+        self.allocated_bytes += commit.capacity;
+        self.used_bytes += commit.used;
+        self.padding_bytes += commit.padding;
+        self.uploaded_bytes += commit.uploaded;
+        if commit.reallocated {
+            self.reallocations += 1;
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Usage of a buffer by its last commit.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct LastCommit {
+    capacity: u64,
+    used: u64,
+    padding: u64,
+    uploaded: u64,
+    reallocated: bool,
+}
+
+/// The data pushed this frame, as it's laid out in the buffers.
+#[derive(Debug, Clone)]
+struct RingStaging {
+    align: u64,
+    data: Vec<u8>,
+    padding: u64,
+}
+
+impl RingStaging {
+    fn new(align: u64) -> Self {
+        Self {
+            align: align.max(1),
+            data: Vec::new(),
+            padding: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+        self.padding = 0;
+    }
+
+    fn reserve(&mut self, size: u64) -> Range<u64> {
+        let len = self.data.len() as u64;
+        let start = util::align_offset(len, self.align);
+        self.padding += start - len;
+        self.data.resize((start + size) as usize, 0);
+        start..start + size
+    }
+
+    fn push(&mut self, bytes: &[u8]) -> u64 {
+        let range = self.reserve(bytes.len() as u64);
+        self.data[util::usize_range(range.clone())].copy_from_slice(bytes);
+        range.start
+    }
+}
+
+#[derive(Debug)]
+struct RingFrame<B: Backend> {
+    buffer: Option<Escape<Buffer<B>>>,
+    last_commit: LastCommit,
+}
+
+/// Aligned uniform or storage data pushed every frame, with a buffer per frame index.
+#[derive(Debug)]
+pub struct DynamicUniformRing<B: Backend> {
+    usage: hal::buffer::Usage,
+    staging: RingStaging,
+    per_image: Vec<RingFrame<B>>,
+}
+
+impl<B: Backend> DynamicUniformRing<B> {
+    /// Create a ring of buffers of `usage`, aligning the values pushed for its descriptors.
+    pub fn new(factory: &Factory<B>, usage: hal::buffer::Usage) -> Self {
+        Self {
+            usage,
+            staging: RingStaging::new(util::offset_alignment(factory, usage)),
+            per_image: Vec::new(),
+        }
+    }
+
+    /// The alignment of the offsets of the values pushed.
+    pub fn align(&self) -> u64 {
+        self.staging.align
+    }
+
+    /// Remove the values pushed, to push the ones of the next frame.
+    pub fn clear(&mut self) {
+        self.staging.clear();
+    }
+
+    /// Push a value, returning its offset.
+    pub fn push<T: Copy>(&mut self, value: &T) -> u64 {
+        self.push_slice(std::slice::from_ref(value))
+    }
+
+    /// Push contiguous values, returning the offset of the first one.
+    pub fn push_slice<T: Copy>(&mut self, values: &[T]) -> u64 {
+        self.staging.push(util::slice_as_bytes(values))
+    }
+
+    /// Push `size` zeroed bytes, to be written with `data_mut`, returning their range.
+    pub fn reserve(&mut self, size: u64) -> Range<u64> {
+        self.staging.reserve(size)
+    }
+
+    /// The data pushed this frame.
+    pub fn data(&self) -> &[u8] {
+        &self.staging.data
+    }
+
+    /// The data pushed this frame, mutably.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.staging.data
+    }
+
+    /// Make the buffer of the frame `index` large enough for the data pushed. Returns whether
+    /// it was allocated, so descriptor sets of the previous buffer must be written again.
+    pub fn ensure(&mut self, factory: &Factory<B>, index: usize) -> bool {
+        while self.per_image.len() <= index {
+            self.per_image.push(RingFrame {
+                buffer: None,
+                last_commit: LastCommit::default(),
+            });
+        }
+        let this_image = &mut self.per_image[index];
+        let reallocated = util::ensure_buffer(
+            factory,
+            &mut this_image.buffer,
+            self.usage,
+            rendy::memory::Dynamic,
+            self.staging.data.len() as u64,
+        )
+        .unwrap_or_else(|e| {
+            log::error!("Failed to allocate a uniform ring buffer: {}", e);
+            false
+        });
+        this_image.last_commit = LastCommit {
+            capacity: this_image.buffer.as_ref().map_or(0, |b| b.size()),
+            used: self.staging.data.len() as u64,
+            padding: self.staging.padding,
+            uploaded: 0,
+            reallocated,
+        };
+        reallocated
+    }
+
+    /// Upload the `range` of the data pushed to the buffer of the frame `index`, made large
+    /// enough by `ensure`.
+    pub fn write(&mut self, factory: &Factory<B>, index: usize, range: Range<u64>) {
+        if range.start >= range.end {
+            return;
+        }
+        let this_image = match self.per_image.get_mut(index) {
+            Some(this_image) => this_image,
+            None => return,
+        };
+        if let Some(buffer) = this_image.buffer.as_mut() {
+            let mut mapped = buffer.map(factory.device(), range.clone()).unwrap();
+            let mut writer = unsafe {
+                mapped
+                    .write(factory.device(), 0..range.end - range.start)
+                    .unwrap()
+            };
+            let dst_slice = unsafe { writer.slice() };
+            dst_slice.copy_from_slice(&self.staging.data[util::usize_range(range.clone())]);
+            this_image.last_commit.uploaded += range.end - range.start;
+        }
+    }
+
+    /// Upload all the data pushed to the buffer of the frame `index`. Returns whether the
+    /// buffer was allocated, as `ensure`.
+    pub fn commit(&mut self, factory: &Factory<B>, index: usize) -> bool {
+        let reallocated = self.ensure(factory, index);
+        self.write(factory, index, 0..self.staging.data.len() as u64);
+        reallocated
+    }
+
+    /// The buffer of the frame `index`, once data was committed to it.
+    pub fn buffer(&self, index: usize) -> Option<&B::Buffer> {
+        self.per_image
+            .get(index)
+            .and_then(|i| i.buffer.as_ref())
+            .map(|b| b.raw())
+    }
+
+    /// Size in bytes of the buffer of the frame `index`.
+    pub fn capacity(&self, index: usize) -> u64 {
+        self.per_image
+            .get(index)
+            .and_then(|i| i.buffer.as_ref())
+            .map_or(0, |b| b.size())
+    }
+
+    /// Free the buffers of the frames from `frames_in_flight` on.
+    pub fn retain_frames(&mut self, frames_in_flight: usize) {
+        self.per_image.truncate(frames_in_flight);
+    }
+
+    /// Add the last commit of the frame `index` to the `UniformRingStats` resource.
+    pub fn report(&self, index: usize, resources: &Resources) {
+        if let (Some(this_image), Some(mut stats)) = (
+            self.per_image.get(index),
+            resources.try_fetch_mut::<UniformRingStats>(),
+        ) {
+            stats.record(&this_image.last_commit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pushes_are_aligned() {
+        let mut staging = RingStaging::new(256);
+        assert_eq!(staging.push(&[1; 80]), 0);
+        assert_eq!(staging.push(&[2; 16]), 256);
+        assert_eq!(staging.reserve(64), 512..576);
+        assert_eq!(staging.data.len(), 576);
+        assert_eq!(staging.padding, 176 + 240);
+        assert!(staging.data[80..256].iter().all(|b| *b == 0));
+        assert!(staging.data[256..272].iter().all(|b| *b == 2));
+
+        staging.clear();
+        assert_eq!(staging.push(&[3; 4]), 0);
+        assert_eq!(staging.padding, 0);
+
+        // Devices without an offset alignment pack the values.
+        let mut packed = RingStaging::new(0);
+        assert_eq!(packed.push(&[1; 3]), 0);
+        assert_eq!(packed.push(&[1; 3]), 3);
+    }
+}
@@ -1,19 +1,98 @@
 //! Wrapper and management data structures for providing automatic buffering, resizing and management
 //! of rendy vertex buffer types.
+//!
+//! The buffers of every image are kept across frames. They grow to the next power of two of the
+//! size written, and only shrink after `SHRINK_FRAMES` writes using less than a quarter of them.
+//! Each write compares the data of every batch with the copy of the previous write to the same
+//! buffer, and only uploads the range of the batches that changed.
 
 use crate::{
     rendy::{
         command::RenderPassEncoder,
         factory::Factory,
         hal,
-        memory::{MappedRange, Write},
+        memory::Write,
         resource::{Buffer, Escape},
     },
     types::Backend,
     util,
 };
+use amethyst_core::ecs::Resources;
 use core::{marker::PhantomData, ops::Range};
 
+/// Number of consecutive writes using less than a quarter of a buffer after which it shrinks.
+pub const SHRINK_FRAMES: u32 = 240;
+
+/// Resource with the usage of the dynamic vertex buffers of the render groups, reset every
+/// frame by the `RenderingSystem`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VertexBufferStats {
+    /// Bytes allocated by the buffers written this frame.
+    pub allocated_bytes: u64,
+    /// Bytes written to them this frame.
+    pub used_bytes: u64,
+    /// Bytes uploaded this frame, without the batches unchanged since the previous write.
+    pub uploaded_bytes: u64,
+    /// Highest `used_bytes` of a frame during this session.
+    pub high_water_bytes: u64,
+    /// Buffers allocated again this frame, to grow or shrink them.
+    pub reallocations: u32,
+}
+
+impl VertexBufferStats {
+    fn record(&mut self, write: &LastWrite) {
+        self.allocated_bytes += write.capacity;
+        self.used_bytes += write.used;
+        self.uploaded_bytes += write.uploaded;
+        self.high_water_bytes = self.high_water_bytes.max(self.used_bytes);
+        if write.reallocated {
+            self.reallocations += 1;
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Self {
+            high_water_bytes: self.high_water_bytes,
+            ..Self::default()
+        };
+    }
+}
+
+/// Usage of a buffer by its last write.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct LastWrite {
+    capacity: u64,
+    used: u64,
+    uploaded: u64,
+    reallocated: bool,
+}
+
+/// Writes of a buffer with less than a quarter of it used, and the largest size written by them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct ShrinkPolicy {
+    low_writes: u32,
+    low_peak: u64,
+}
+
+impl ShrinkPolicy {
+    /// Count a write of `used` bytes to a buffer of `capacity` bytes, returning the size to
+    /// shrink it to when it's been used too little for long enough.
+    fn next(&mut self, capacity: u64, used: u64) -> Option<u64> {
+        if used.saturating_mul(4) >= capacity {
+            *self = Self::default();
+            return None;
+        }
+        self.low_writes += 1;
+        self.low_peak = self.low_peak.max(used);
+        if self.low_writes < SHRINK_FRAMES {
+            return None;
+        }
+        let size = self.low_peak.max(1).next_power_of_two();
+        *self = Self::default();
+        Some(size)
+    }
+}
+
 /// Type alias for a set of dynamic vertex buffer data to be managed. See the documentation
 /// for [DynamicVertexData] for implementation details.
 pub type DynamicVertexBuffer<B, T> = DynamicVertexData<B, VertexData<B, T>, T>;
@@ -92,7 +171,6 @@ impl<B: Backend> IndexData<B, u32> {
     /// Bind a 32-bit index buffer
     #[inline]
     pub fn bind(
-        _: u32,
         encoder: &mut RenderPassEncoder<'_, B>,
         buffer: &Option<Escape<Buffer<B>>>,
         offset: u64,
@@ -126,6 +204,36 @@ impl<B: Backend, V: VertexDataBufferType, T: 'static> DynamicVertexData<B, V, T>
         }
     }
 
+    /// Size in bytes of the buffer allocated for the specified frame index.
+    ///
+    /// Buffers are kept across frames, grow to the next power of two of the size written and
+    /// shrink after `SHRINK_FRAMES` writes using less than a quarter of them.
+    pub fn capacity(&self, index: usize) -> u64 {
+        self.per_image
+            .get(index)
+            .and_then(|i| i.buffer.as_ref())
+            .map_or(0, |b| b.size())
+    }
+
+    /// Largest size in bytes written to the buffer of any frame index.
+    pub fn high_water_mark(&self) -> u64 {
+        self.per_image
+            .iter()
+            .map(|i| i.high_water)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Add the last write of the specified frame index to the `VertexBufferStats` resource.
+    pub fn report(&self, index: usize, resources: &Resources) {
+        if let (Some(this_image), Some(mut stats)) = (
+            self.per_image.get(index),
+            resources.try_fetch_mut::<VertexBufferStats>(),
+        ) {
+            stats.record(&this_image.last_write);
+        }
+    }
+
     /// Write to the allocated rendy buffer for the specified frame index.
     pub fn write<I>(
         &mut self,
@@ -150,22 +258,76 @@ impl<B: Backend, V: VertexDataBufferType, T: 'static> DynamicVertexData<B, V, T>
         };
 
         let buf_size = max_num_items * core::mem::size_of::<T>() as u64;
-        if let Some((allocated, mut mapped)) = this_image.map(factory, 0..buf_size) {
-            let mut writer = unsafe { mapped.write::<u8>(factory.device(), 0..buf_size).unwrap() };
-            let mut slice = unsafe { writer.slice() };
-
-            iter.into_iter().for_each(|data| {
-                let data_slice = util::slice_as_bytes(data.as_ref());
-                let tmp = std::mem::replace(&mut slice, &mut []);
-                let (dst_slice, rest) = tmp.split_at_mut(data_slice.len());
-                dst_slice.copy_from_slice(data_slice);
-                std::mem::replace(&mut slice, rest);
-            });
-            allocated
-        } else {
-            false
+        let allocated = this_image.ensure(factory, buf_size);
+        match &mut this_image.buffer {
+            Some(buffer) => {
+                let uploaded = upload_changed::<B, T, I>(
+                    factory,
+                    buffer,
+                    &mut this_image.shadow,
+                    buf_size,
+                    iter,
+                );
+                this_image.last_write = LastWrite {
+                    capacity: buffer.size(),
+                    used: buf_size,
+                    uploaded,
+                    reallocated: allocated,
+                };
+                this_image.high_water = this_image.high_water.max(buf_size);
+                allocated
+            }
+            None => false,
+        }
+    }
+}
+
+/// Compare the batches of `iter` with `shadow`, the copy of the data of `buffer`, and upload the
+/// range of the ones that changed. Returns the number of bytes uploaded.
+fn upload_changed<B, T, I>(
+    factory: &Factory<B>,
+    buffer: &mut Escape<Buffer<B>>,
+    shadow: &mut Vec<u8>,
+    buf_size: u64,
+    iter: I,
+) -> u64
+where
+    B: Backend,
+    T: 'static,
+    I: IntoIterator,
+    I::Item: AsRef<[T]>,
+{
+    let buf_size = buf_size as usize;
+    let mut dirty: Option<Range<usize>> = None;
+    let mut offset = 0;
+    for data in iter {
+        let data_slice = util::slice_as_bytes(data.as_ref());
+        let end = offset + data_slice.len();
+        assert!(
+            end <= buf_size,
+            "Wrote more than max_num_items to a vertex buffer"
+        );
+        if shadow.get(offset..end) != Some(data_slice) {
+            if shadow.len() < end {
+                shadow.resize(end, 0);
+            }
+            shadow[offset..end].copy_from_slice(data_slice);
+            dirty = Some(dirty.map_or(offset..end, |dirty| dirty.start.min(offset)..end));
         }
+        offset = end;
+    }
+
+    let dirty = match dirty {
+        Some(dirty) => dirty,
+        None => return 0,
+    };
+    let range = dirty.start as u64..dirty.end as u64;
+    let mut mapped = buffer.map(factory.device(), 0..range.end).unwrap();
+    unsafe {
+        let mut writer = mapped.write::<u8>(factory.device(), range).unwrap();
+        writer.slice().copy_from_slice(&shadow[dirty.clone()]);
     }
+    (dirty.end - dirty.start) as u64
 }
 
 impl<B: Backend, T: 'static> DynamicVertexData<B, VertexData<B, T>, T> {
@@ -199,7 +361,7 @@ impl<B: Backend> DynamicVertexData<B, IndexData<B, u32>, u32> {
     #[inline]
     pub fn bind(&self, index: usize, offset: u64, encoder: &mut RenderPassEncoder<'_, B>) -> bool {
         self.per_image.get(index).map_or(false, |i| {
-            IndexData::<B, u16>::bind(encoder, &i.buffer, offset)
+            IndexData::<B, u32>::bind(encoder, &i.buffer, offset)
         })
     }
 }
@@ -210,6 +372,11 @@ impl<B: Backend> DynamicVertexData<B, IndexData<B, u32>, u32> {
 #[derive(Debug)]
 struct PerImageDynamicVertexData<B: Backend, V: VertexDataBufferType> {
     buffer: Option<Escape<Buffer<B>>>,
+    /// Copy of the data written to the buffer, compared with the next write.
+    shadow: Vec<u8>,
+    shrink: ShrinkPolicy,
+    high_water: u64,
+    last_write: LastWrite,
     marker: PhantomData<V>,
 }
 
@@ -218,34 +385,86 @@ impl<B: Backend, V: VertexDataBufferType> PerImageDynamicVertexData<B, V> {
     fn new() -> Self {
         Self {
             buffer: None,
+            shadow: Vec::new(),
+            shrink: ShrinkPolicy::default(),
+            high_water: 0,
+            last_write: LastWrite::default(),
             marker: PhantomData,
         }
     }
 
     /// Garuntees that at least max_size bytes of memory is allocated for this buffer
-    /// Calls the utility function, [util::ensure_buffer] to dynamically grow the buffer if needed.
+    /// Calls the utility function, [util::ensure_buffer] to dynamically grow the buffer if needed,
+    /// and releases it first when it should shrink. Returns whether the buffer was allocated,
+    /// in which case it holds none of the previous data.
     fn ensure(&mut self, factory: &Factory<B>, max_size: u64) -> bool {
-        util::ensure_buffer(
+        let capacity = self.buffer.as_ref().map_or(0, |b| b.size());
+        let mut min_size = max_size;
+        if let Some(size) = self.shrink.next(capacity, max_size) {
+            log::debug!(
+                "Shrinking a vertex buffer from {} to {} bytes",
+                capacity,
+                size
+            );
+            self.buffer = None;
+            min_size = size;
+        }
+        let allocated = util::ensure_buffer(
             &factory,
             &mut self.buffer,
             V::usage(),
             rendy::memory::Dynamic,
-            max_size,
+            min_size,
         )
-        .unwrap()
+        .unwrap_or_else(|e| {
+            log::error!("Failed to allocate a vertex buffer: {}", e);
+            false
+        });
+        if allocated || self.buffer.is_none() {
+            self.shadow.clear();
+        }
+        allocated
     }
+}
 
-    /// Maps the allocated buffer for writing.
-    fn map<'a>(
-        &'a mut self,
-        factory: &Factory<B>,
-        range: Range<u64>,
-    ) -> Option<(bool, MappedRange<'a, B>)> {
-        let alloc = self.ensure(factory, range.end);
-        if let Some(buffer) = &mut self.buffer {
-            Some((alloc, buffer.map(factory.device(), range).unwrap()))
-        } else {
-            None
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shrink_after_low_usage() {
+        let mut policy = ShrinkPolicy::default();
+        for _ in 1..SHRINK_FRAMES {
+            assert_eq!(policy.next(4096, 100), None);
+        }
+        // A write using the buffer again restarts the count.
+        assert_eq!(policy.next(4096, 2048), None);
+        for i in 1..SHRINK_FRAMES {
+            assert_eq!(policy.next(4096, if i == 10 { 600 } else { 100 }), None);
         }
+        assert_eq!(policy.next(4096, 100), Some(1024));
+        assert_eq!(policy, ShrinkPolicy::default());
+
+        let mut stats = VertexBufferStats::default();
+        let write = LastWrite {
+            capacity: 1024,
+            used: 600,
+            uploaded: 64,
+            reallocated: true,
+        };
+        stats.record(&write);
+        stats.record(&write);
+        stats.reset();
+        stats.record(&write);
+        assert_eq!(
+            stats,
+            VertexBufferStats {
+                allocated_bytes: 1024,
+                used_bytes: 600,
+                uploaded_bytes: 64,
+                high_water_bytes: 1200,
+                reallocations: 1,
+            }
+        );
     }
 }
@@ -1,15 +1,43 @@
 //! Renderer system
 use crate::{
+    adapter::{AdapterSelection, Adapters, SelectAdapter},
     camera::{ActiveCamera, Camera},
+    capture::FrameCapture,
+    clear::{ClearConfig, ClearWatch},
+    content_errors::ContentErrors,
+    control::{RenderControl, RenderControlEvent},
     debug_drawing::DebugLinesComponent,
+    deterministic::Deterministic,
+    dissolve::{self, DissolveNoise},
+    dynamic_mesh::{DynamicMeshBuffers, DynamicMeshes, Pending},
+    dynamic_texture::DynamicTextures,
+    error::{RenderError, TextureError},
+    events::{FrameEventsDesc, RenderEvent},
+    fallback::{self, FallbackAssets},
+    formats::dds::{block_layout, round_up},
+    gpu_memory::GpuMemoryStats,
+    indirect::DrawCallStats,
     light::Light,
+    mesh_util::ProceduralMesh,
     mtl::{Material, MaterialDefaults},
+    pipeline_cache::PipelineCache,
+    presentation::{PresentationConfig, PresentationWatch},
+    recovery::{self, RecoveryWatch, RenderRecovery},
+    render_stats::RenderStats3D,
     resources::Tint,
     skinning::JointTransforms,
-    sprite::SpriteRender,
-    transparent::Transparent,
-    types::{Backend, Mesh, Texture},
-    visibility::Visibility,
+    sprite::{persist::SpriteSheetPaths, SpriteRender},
+    static_instances::StaticInstanceSet,
+    submodules::{SkinningSub, UniformRingStats, VertexBufferStats},
+    texture_upload::{TextureUploadStats, TextureUploads, UploadBudget},
+    timing::GpuTimingStats,
+    transparent::{BlendMode, Transparent},
+    types::{Backend, GpuAssetStats, GpuMesh, Mesh, MeshBounds, Texture, TextureData, TextureMeta},
+    util::{ChangeDetectionSet, ChangeWatch},
+    validation::{ValidationConfig, ValidationLog, ValidationReport},
+    view::{RenderView, WindowCameras},
+    view_model::ViewModelProjection,
+    visibility::{MeshBoundingBoxes, MeshBoundingSpheres, Visibility},
 };
 use amethyst_assets::{
     AssetStorage, Handle, HotReloadStrategy, ProcessableAsset, ProcessingState, ThreadPool,
@@ -17,17 +45,33 @@ use amethyst_assets::{
 use amethyst_core::{
     components::Transform,
     ecs::{Read, ReadExpect, ReadStorage, Resources, RunNow, SystemData, Write, WriteExpect},
+    shrev::EventChannel,
     timing::Time,
     Hidden, HiddenPropagate,
 };
+use amethyst_window::{ScreenDimensions, SecondaryWindows};
 use palette::{LinSrgba, Srgba};
 use rendy::{
     command::{Families, QueueId},
-    factory::{Factory, ImageState},
-    graph::{Graph, GraphBuilder},
-    texture::palette::{load_from_linear_rgba, load_from_srgba},
+    factory::{Factory, ImageState, ImageStateOrLayout},
+    graph::{Graph, GraphBuilder, NodeDesc},
+    hal::{
+        device::Device,
+        format::{Format, ImageFeature},
+        image::{Anisotropic, SamplerInfo},
+        PhysicalDevice,
+    },
+    texture::{
+        palette::{load_from_linear_rgba, load_from_srgba},
+        MipLevels,
+    },
+};
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Arc,
 };
-use std::sync::Arc;
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
@@ -44,6 +88,20 @@ pub trait GraphCreator<B: Backend> {
 }
 
 /// Amethyst rendering system
+///
+/// The graph is rebuilt when its `GraphCreator` asks for it, and when the `ScreenDimensions` of
+/// the window change, as recorded in the channels of the `ChangeDetectionSet` resource.
+/// Rendering is skipped while the window is minimized. Swapchains that are out of date or
+/// suboptimal are recreated by the present node of the graph. Lost devices and
+/// surfaces are recovered from, see the `recovery` module.
+///
+/// The graph runs according to the `RenderControl` resource, see the `control` module. The
+/// rendering of every frame is announced with `RenderEvent`s, see the `events` module.
+///
+/// A headless `RenderingSystem`, made with `with_headless`, creates no device nor graph, for
+/// dedicated servers and tests running the other systems without a GPU. It loads the meshes and
+/// textures as stubs keeping the metadata of their data, see `Mesh::stub` and `Texture::stub`,
+/// and processes the materials.
 #[allow(missing_debug_implementations)]
 pub struct RenderingSystem<B, G>
 where
@@ -53,6 +111,19 @@ where
     graph: Option<Graph<B, Resources>>,
     families: Option<Families<B>>,
     graph_creator: G,
+    presentation: PresentationWatch,
+    resize: ResizeWatch,
+    rebuild_watch: ChangeWatch,
+    pipeline_cache: Option<PathBuf>,
+    adapter: AdapterSelection,
+    recovery: RecoveryWatch,
+    frame_index: u64,
+    image_count: Option<u32>,
+    frames_in_flight: Option<u32>,
+    validation: ValidationConfig,
+    validation_log: Option<ValidationLog>,
+    deterministic: bool,
+    headless: bool,
 }
 
 impl<B, G> RenderingSystem<B, G>
@@ -66,7 +137,153 @@ where
             graph: None,
             families: None,
             graph_creator,
+            presentation: PresentationWatch::default(),
+            resize: ResizeWatch::default(),
+            rebuild_watch: ChangeWatch::new(vec![
+                ChangeDetectionSet::SURFACE,
+                ChangeDetectionSet::GRAPH_STRUCTURE,
+            ]),
+            pipeline_cache: None,
+            adapter: AdapterSelection::default(),
+            recovery: RecoveryWatch::default(),
+            frame_index: 0,
+            image_count: None,
+            frames_in_flight: None,
+            validation: ValidationConfig::default(),
+            validation_log: None,
+            deterministic: false,
+            headless: false,
+        }
+    }
+
+    /// Load the `PipelineCache` from the file at `path` at startup and save it there on shutdown,
+    /// see the `pipeline_cache` module.
+    pub fn with_pipeline_cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.pipeline_cache = Some(path.into());
+        self
+    }
+
+    /// Render with the adapter chosen by `selection` instead of preferring a discrete GPU. The
+    /// adapters found are described by the `Adapters` resource.
+    pub fn with_adapter(mut self, selection: AdapterSelection) -> Self {
+        self.adapter = selection;
+        self
+    }
+
+    /// Start with `image_count` swapchain images, see the `presentation` module. The count is
+    /// kept in the `PresentationConfig` resource, where it can be changed at runtime.
+    pub fn with_image_count(mut self, image_count: u32) -> Self {
+        self.image_count = Some(image_count);
+        self
+    }
+
+    /// Start with at most `frames_in_flight` frames in flight, see the `presentation` module. The
+    /// count is kept in the `PresentationConfig` resource, where it can be changed at runtime.
+    pub fn with_frames_in_flight(mut self, frames_in_flight: u32) -> Self {
+        self.frames_in_flight = Some(frames_in_flight);
+        self
+    }
+
+    /// Enable the validation layer or the debug markers, see the `validation` module.
+    pub fn with_validation(mut self, config: ValidationConfig) -> Self {
+        self.validation = config;
+        self
+    }
+
+    /// Start rendering deterministically, see the `deterministic` module. The flag is kept in
+    /// the `Deterministic` resource, where it can be changed at runtime.
+    pub fn with_deterministic(mut self) -> Self {
+        self.deterministic = true;
+        self
+    }
+
+    /// Run without a device, loading stub meshes and textures and never building the graph.
+    pub fn with_headless(mut self) -> Self {
+        self.headless = true;
+        self
+    }
+}
+
+/// Why a frame could not be rendered.
+enum GraphFailure {
+    Build(failure::Error),
+    Run(Box<dyn Any + Send>),
+}
+
+impl GraphFailure {
+    /// The message of the error, with its causes, or of the panic.
+    fn message(&self) -> String {
+        match self {
+            GraphFailure::Build(e) => e
+                .iter_chain()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(": "),
+            GraphFailure::Run(payload) => payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Frames to wait between two rebuilds of the graph while the window is continuously resized.
+const RESIZE_REBUILD_INTERVAL: u32 = 10;
+
+/// What the `RenderingSystem` does with the graph in a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FrameAction {
+    /// Run the graph as it is.
+    Run,
+    /// Rebuild the graph for the new screen dimensions, then run it.
+    Rebuild,
+    /// Don't run the graph, as the window has no area to render to.
+    Skip,
+}
+
+/// Debounces graph rebuilds on `ScreenDimensions` changes, including changes of the DPI factor.
+///
+/// The graph is rebuilt once the dimensions stayed the same for a frame, or at most every
+/// `RESIZE_REBUILD_INTERVAL` frames while they keep changing. Rendering is skipped while the
+/// window is minimized to a zero extent.
+#[derive(Debug, Default)]
+struct ResizeWatch {
+    built: Option<ScreenDimensions>,
+    last: Option<ScreenDimensions>,
+    frames_since_build: u32,
+}
+
+impl ResizeWatch {
+    fn update(&mut self, dimensions: Option<&ScreenDimensions>) -> FrameAction {
+        self.frames_since_build = self.frames_since_build.saturating_add(1);
+        let dimensions = match dimensions {
+            Some(dimensions) => dimensions,
+            // Rendering offscreen
+            None => return FrameAction::Run,
+        };
+        if dimensions.width() < 1.0 || dimensions.height() < 1.0 {
+            self.last = None;
+            return FrameAction::Skip;
+        }
+        if self.built.as_ref() == Some(dimensions) {
+            self.last = None;
+            return FrameAction::Run;
         }
+
+        let settled = self.last.as_ref() == Some(dimensions);
+        self.last = Some(dimensions.clone());
+        if self.built.is_none() || settled || self.frames_since_build >= RESIZE_REBUILD_INTERVAL {
+            FrameAction::Rebuild
+        } else {
+            FrameAction::Run
+        }
+    }
+
+    fn built(&mut self, dimensions: Option<&ScreenDimensions>) {
+        self.built = dimensions.cloned();
+        self.last = None;
+        self.frames_since_build = 0;
     }
 }
 
@@ -78,9 +295,30 @@ type AssetLoadingData<'a, B> = (
     Write<'a, AssetStorage<Mesh>>,
     Write<'a, AssetStorage<Texture>>,
     Write<'a, AssetStorage<Material>>,
+    Write<'a, DynamicMeshes>,
+    Write<'a, DynamicTextures>,
+    Write<'a, MeshBoundingSpheres>,
+    Write<'a, MeshBoundingBoxes>,
+    Write<'a, GpuAssetStats>,
+    Write<'a, GpuMemoryStats>,
+    Read<'a, TextureUploads>,
+    Write<'a, TextureUploadStats>,
     ReadExpect<'a, QueueId>,
 );
 
+type StubLoadingData<'a> = (
+    Read<'a, Time>,
+    ReadExpect<'a, Arc<ThreadPool>>,
+    Option<Read<'a, HotReloadStrategy>>,
+    Write<'a, AssetStorage<Mesh>>,
+    Write<'a, AssetStorage<Texture>>,
+    Write<'a, AssetStorage<Material>>,
+    Write<'a, DynamicMeshes>,
+    Write<'a, DynamicTextures>,
+    Write<'a, MeshBoundingSpheres>,
+    Write<'a, MeshBoundingBoxes>,
+);
+
 type SetupData<'a> = (
     ReadStorage<'a, Handle<Mesh>>,
     ReadStorage<'a, Handle<Texture>>,
@@ -92,11 +330,22 @@ type SetupData<'a> = (
     ReadStorage<'a, HiddenPropagate>,
     ReadStorage<'a, DebugLinesComponent>,
     ReadStorage<'a, Transparent>,
+    ReadStorage<'a, BlendMode>,
     ReadStorage<'a, Transform>,
     ReadStorage<'a, SpriteRender>,
     Option<Read<'a, Visibility>>,
     Read<'a, ActiveCamera>,
     ReadStorage<'a, JointTransforms>,
+    Read<'a, FrameCapture>,
+    Read<'a, PresentationConfig>,
+    Read<'a, RenderView>,
+    Read<'a, WindowCameras>,
+    Read<'a, GpuTimingStats>,
+    Read<'a, ClearConfig>,
+    Write<'a, EventChannel<RenderRecovery>>,
+    Write<'a, RenderControl>,
+    Write<'a, EventChannel<RenderControlEvent>>,
+    Write<'a, EventChannel<RenderEvent>>,
 );
 
 // struct MeshProcessor<B: Backend>(PhantomData<B>);
@@ -116,65 +365,186 @@ where
             mut mesh_storage,
             mut texture_storage,
             mut material_storage,
+            mut dynamic_meshes,
+            mut dynamic_textures,
+            mut mesh_spheres,
+            mut mesh_boxes,
+            mut stats,
+            mut memory_stats,
+            uploads,
+            mut upload_stats,
             queue_id,
         ): AssetLoadingData<'_, B>,
     ) {
         use std::ops::Deref;
         let strategy = strategy.as_ref().map(Deref::deref);
 
-        mesh_storage.process(
+        // Loaded and dropped assets are tracked separately, as both closures are alive at once
+        let mut loaded = GpuAssetStats::default();
+        let mut dropped = GpuAssetStats::default();
+
+        mesh_storage.process_custom_drop(
             |b| {
                 #[cfg(feature = "profiler")]
                 profile_scope!("process_mesh");
 
+                let (layout, bounds) = b.read_back();
                 b.0.build(*queue_id, &factory)
-                    .map(B::wrap_mesh)
+                    .map(|mesh| {
+                        let mesh = B::wrap_mesh(mesh.into())
+                            .with_layout(layout)
+                            .with_bounds(bounds);
+                        loaded.add_mesh(&mesh);
+                        mesh
+                    })
                     .map(ProcessingState::Loaded)
                     .map_err(|e| e.compat().into())
             },
+            |mesh| dropped.add_mesh(&mesh),
             time.frame_number(),
             &**pool,
             strategy,
         );
+        if loaded.mesh_count > 0 || dropped.mesh_count > 0 {
+            mesh_spheres.sync(&mesh_storage);
+            mesh_boxes.sync(&mesh_storage);
+        }
 
-        texture_storage.process(
+        dynamic_meshes.upload_pending(|handle, data, pending| {
+            #[cfg(feature = "profiler")]
+            profile_scope!("update_dynamic_mesh");
+
+            let mesh = match mesh_storage.get_mut(handle) {
+                Some(mesh) => mesh,
+                None => return false,
+            };
+            if let Some(sphere) = data.bounding_sphere() {
+                mesh_spheres.insert(handle, sphere);
+            }
+            if let Some(aabb) = data.bounding_box() {
+                mesh_boxes.insert(handle, aabb);
+            }
+            dropped.add_mesh(mesh);
+            if let Err(e) = write_dynamic_mesh(&factory, *queue_id, mesh, data, pending) {
+                log::error!("Failed to update dynamic mesh: {}", e);
+            }
+            loaded.add_mesh(mesh);
+            true
+        });
+
+        let mut budget = UploadBudget::new(uploads.budget);
+        texture_storage.process_custom_drop(
             |b| {
                 #[cfg(feature = "profiler")]
                 profile_scope!("process_texture");
 
-                b.0.build(
-                    ImageState {
-                        queue: *queue_id,
-                        stage: rendy::hal::pso::PipelineStage::VERTEX_SHADER
-                            | rendy::hal::pso::PipelineStage::FRAGMENT_SHADER,
-                        access: rendy::hal::image::Access::SHADER_READ,
-                        layout: rendy::hal::image::Layout::ShaderReadOnlyOptimal,
-                    },
-                    &mut factory,
-                )
-                .map(B::wrap_texture)
-                .map(ProcessingState::Loaded)
-                .map_err(|e| e.compat().into())
+                if !budget.allows(&b) {
+                    return Ok(ProcessingState::Loading(b));
+                }
+                build_texture(&mut factory, *queue_id, b)
+                    .map(|texture| {
+                        budget.uploaded(texture.info().byte_size());
+                        loaded.add_texture(&texture);
+                        texture
+                    })
+                    .map(ProcessingState::Loaded)
             },
+            |texture| dropped.add_texture(&texture),
             time.frame_number(),
             &**pool,
             strategy,
         );
 
-        material_storage.process(
-            |b| {
-                #[cfg(feature = "profiler")]
-                profile_scope!("process_material");
+        for (handle, data) in dynamic_textures.drain_pending(|h| texture_storage.contains(h)) {
+            #[cfg(feature = "profiler")]
+            profile_scope!("update_dynamic_texture");
+
+            match build_texture(&mut factory, *queue_id, data) {
+                Ok(texture) => {
+                    loaded.add_texture(&texture);
+                    dropped.add_texture(&texture_storage.replace(&handle, texture));
+                }
+                Err(e) => log::error!("Failed to update dynamic texture: {}", e),
+            }
+        }
 
-                ProcessableAsset::process(b)
+        stats.merge(&loaded, &dropped);
+        *upload_stats = budget.finish();
+        let heaps = factory.memory_utilization().heaps;
+        memory_stats.update(
+            &stats,
+            heaps
+                .iter()
+                .map(|heap| (heap.size, heap.utilization.used, heap.utilization.effective)),
+        );
+
+        process_materials(&mut material_storage, &time, &**pool, strategy);
+    }
+
+    /// Load the meshes and textures as stubs, without a device.
+    fn stub_loading(
+        &mut self,
+        (
+            time,
+            pool,
+            strategy,
+            mut mesh_storage,
+            mut texture_storage,
+            mut material_storage,
+            mut dynamic_meshes,
+            mut dynamic_textures,
+            mut mesh_spheres,
+            mut mesh_boxes,
+        ): StubLoadingData<'_>,
+    ) {
+        use std::ops::Deref;
+        let strategy = strategy.as_ref().map(Deref::deref);
+
+        let (mut loaded, mut dropped) = (false, false);
+        mesh_storage.process_custom_drop(
+            |b| {
+                loaded = true;
+                Ok(ProcessingState::Loaded(Mesh::stub(&b)))
             },
+            |_| dropped = true,
             time.frame_number(),
             &**pool,
             strategy,
         );
+        if loaded || dropped {
+            mesh_spheres.sync(&mesh_storage);
+            mesh_boxes.sync(&mesh_storage);
+        }
+        dynamic_meshes.upload_pending(|handle, data, _| {
+            let mesh = match mesh_storage.get_mut(handle) {
+                Some(mesh) => mesh,
+                None => return false,
+            };
+            if let Some(sphere) = data.bounding_sphere() {
+                mesh_spheres.insert(handle, sphere);
+            }
+            if let Some(aabb) = data.bounding_box() {
+                mesh_boxes.insert(handle, aabb);
+            }
+            mesh.set_layout(Some(data.layout()));
+            mesh.set_bounds(MeshBounds::of(data.positions()));
+            true
+        });
+
+        texture_storage.process(
+            |b| Ok(ProcessingState::Loaded(Texture::stub(&b))),
+            time.frame_number(),
+            &**pool,
+            strategy,
+        );
+        for (handle, data) in dynamic_textures.drain_pending(|h| texture_storage.contains(h)) {
+            texture_storage.replace(&handle, Texture::stub(&data));
+        }
+
+        process_materials(&mut material_storage, &time, &**pool, strategy);
     }
 
-    fn rebuild_graph(&mut self, res: &Resources) {
+    fn rebuild_graph(&mut self, res: &Resources) -> Result<(), failure::Error> {
         #[cfg(feature = "profiler")]
         profile_scope!("rebuild_graph");
 
@@ -185,31 +555,211 @@ where
             profile_scope!("dispose_graph");
             graph.dispose(&mut *factory, res);
         }
+        // The surfaces of closed windows went with the old graph.
+        if let Some(mut windows) = res.try_fetch_mut::<SecondaryWindows>() {
+            windows.drop_closed();
+        }
 
-        let builder = {
+        let mut builder = {
             #[cfg(feature = "profiler")]
             profile_scope!("run_graph_creator");
             self.graph_creator.builder(&mut factory, res)
         };
+        // Read after the graph creator, which reports the image count of the surface.
+        if let Some(frames) = res
+            .try_fetch::<PresentationConfig>()
+            .and_then(|config| config.clamped_frames_in_flight())
+        {
+            builder = builder.with_frames_in_flight(frames);
+        }
+        builder.add_node(FrameEventsDesc::new(self.frame_index).builder());
 
         let graph = {
             #[cfg(feature = "profiler")]
             profile_scope!("build_graph");
-            builder
-                .build(&mut factory, self.families.as_mut().unwrap(), res)
-                .unwrap()
+            builder.build(&mut factory, self.families.as_mut().unwrap(), res)?
         };
 
         self.graph = Some(graph);
+        // The joints of frames beyond the ones now in flight would never be written again.
+        if let Some(frames) = res.fetch::<PresentationConfig>().active_frames_in_flight() {
+            res.fetch_mut::<SkinningSub<B>>()
+                .retain_frames(frames as usize);
+        }
+        res.fetch_mut::<EventChannel<RenderEvent>>()
+            .single_write(RenderEvent::GraphRebuilt);
+        Ok(())
     }
 
-    fn run_graph(&mut self, res: &Resources) {
+    fn run_graph(&mut self, res: &Resources) -> Result<(), GraphFailure> {
         let mut factory = res.fetch_mut::<Factory<B>>();
         factory.maintain(self.families.as_mut().unwrap());
-        self.graph
-            .as_mut()
-            .unwrap()
-            .run(&mut factory, self.families.as_mut().unwrap(), res)
+        res.fetch_mut::<SkinningSub<B>>().next_frame();
+        res.fetch_mut::<DrawCallStats>().reset();
+        res.fetch_mut::<VertexBufferStats>().reset();
+        res.fetch_mut::<UniformRingStats>().reset();
+        res.fetch_mut::<RenderStats3D>().reset();
+        fallback::log_substitutions(res);
+        res.fetch_mut::<FallbackAssets>().next_frame();
+        res.fetch_mut::<EventChannel<RenderEvent>>()
+            .single_write(RenderEvent::FrameBegin {
+                frame_index: self.frame_index,
+            });
+        self.frame_index += 1;
+
+        let graph = self.graph.as_mut().unwrap();
+        let families = self.families.as_mut().unwrap();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            graph.run(&mut factory, families, res);
+        }));
+        *res.fetch_mut::<RenderView>() = RenderView::default();
+        result.map_err(GraphFailure::Run)?;
+        if res
+            .fetch_mut::<ClearWatch>()
+            .frame(&res.fetch::<ClearConfig>())
+        {
+            log::warn!(
+                "The ClearConfig is ignored, as no pass of the graph starts with a ClearDesc like \
+                 those of a RenderingBundle"
+            );
+        }
+        res.fetch_mut::<EventChannel<RenderEvent>>()
+            .single_write(RenderEvent::FrameSubmitted);
+        Ok(())
+    }
+
+    /// Recover from a failure of the graph caused by a lost device or surface, or resume it if
+    /// it has another cause or the previous frame failed too.
+    fn recover(&mut self, res: &Resources, failure: GraphFailure) {
+        let device_lost = recovery::device_lost(&res.fetch::<Factory<B>>());
+        let recovery = recovery::recovery_for(device_lost, &failure.message())
+            .and_then(|recovery| self.recovery.failed(recovery));
+        let recovery = match (recovery, failure) {
+            (Some(recovery), GraphFailure::Build(e)) => {
+                log::error!("Failed to build the render graph: {}", e);
+                recovery
+            }
+            (Some(recovery), GraphFailure::Run(_)) => recovery,
+            (None, GraphFailure::Build(e)) => panic!("Failed to build the render graph: {}", e),
+            (None, GraphFailure::Run(payload)) => panic::resume_unwind(payload),
+        };
+        if recovery == RenderRecovery::DeviceLost {
+            // The next frame fails on the lost device again, and gives up.
+            if let Err(e) = self.recreate_device(res) {
+                res.fetch_mut::<ContentErrors>().report(e);
+                return;
+            }
+        }
+        res.fetch_mut::<ChangeDetectionSet>()
+            .mark(ChangeDetectionSet::GRAPH_STRUCTURE);
+        recovery::announce(res, recovery);
+    }
+
+    /// Create the factory, graph and GPU copies of the assets again after the device was lost,
+    /// disposing of those of the lost device.
+    fn recreate_device(&mut self, res: &Resources) -> Result<(), RenderError> {
+        #[cfg(feature = "profiler")]
+        profile_scope!("recreate_device");
+
+        log::error!("The device was lost, creating it again");
+        let recreation = |e: failure::Error| RenderError::DeviceRecreation(e.to_string());
+        let (factory, families, adapters) = init_factory::<B>(&self.adapter).map_err(recreation)?;
+        let skinning = SkinningSub::new(&factory).map_err(recreation)?;
+        let queue_id = main_queue(&families);
+
+        // A lost device is idle, what was created on it can be destroyed once it was waited on.
+        let mut old_factory = std::mem::replace(&mut *res.fetch_mut::<Factory<B>>(), factory);
+        let old_families = self.families.replace(families);
+        if let Err(e) = old_factory.device().wait_idle() {
+            log::debug!("Waited on the lost device: {:?}", e);
+        }
+        if let Some(graph) = self.graph.take() {
+            log::debug!("Dispose graph of the lost device");
+            graph.dispose(&mut old_factory, res);
+        }
+        drop(std::mem::replace(
+            &mut *res.fetch_mut::<SkinningSub<B>>(),
+            skinning,
+        ));
+        *res.fetch_mut::<QueueId>() = queue_id;
+        if let (Some(adapters), Some(mut resource)) = (adapters, res.try_fetch_mut::<Adapters>()) {
+            *resource = adapters;
+        }
+
+        let mut factory = res.fetch_mut::<Factory<B>>();
+        if let Some(mut cache) = res.try_fetch_mut::<PipelineCache<B>>() {
+            match PipelineCache::load(&factory, cache.path().map(Path::to_path_buf)) {
+                Ok(new_cache) => std::mem::replace(&mut *cache, new_cache).destroy(&old_factory),
+                Err(e) => log::warn!("Failed to create the pipeline cache: {}", e),
+            }
+        }
+
+        // The GPU copies of the old device are destroyed with its factory. Assets loaded from
+        // files or data are loaded again by the next `process`, the dynamic ones right away from
+        // their current data.
+        let pool = res.fetch::<Arc<ThreadPool>>();
+        let mut meshes = res.fetch_mut::<AssetStorage<Mesh>>();
+        let mut textures = res.fetch_mut::<AssetStorage<Texture>>();
+        let mut dynamic_meshes = res.fetch_mut::<DynamicMeshes>();
+        let dynamic_textures = res.fetch_mut::<DynamicTextures>().registered();
+        dynamic_meshes.for_each_registered(|handle, _| meshes.forget_reload(handle));
+        for (handle, _) in &dynamic_textures {
+            textures.forget_reload(handle);
+        }
+        meshes.reload_all(&**pool, drop);
+        textures.reload_all(&**pool, drop);
+        *res.fetch_mut::<GpuAssetStats>() = GpuAssetStats::default();
+
+        dynamic_meshes.for_each_registered(|handle, data| {
+            match DynamicMeshBuffers::new(&factory, queue_id, data) {
+                Ok(buffers) => {
+                    let mesh = B::wrap_mesh(buffers.into())
+                        .with_layout(Some(data.layout()))
+                        .with_bounds(MeshBounds::of(data.positions()));
+                    meshes.set(handle, mesh);
+                }
+                Err(e) => log::error!("Failed to upload dynamic mesh again: {}", e),
+            }
+        });
+        for (handle, data) in dynamic_textures {
+            match build_texture(&mut factory, queue_id, data) {
+                Ok(texture) => {
+                    textures.set(&handle, texture);
+                }
+                Err(e) => log::error!("Failed to upload dynamic texture again: {}", e),
+            }
+        }
+
+        log::debug!("Drop families and factory of the lost device");
+        drop(old_families);
+        drop(old_factory);
+        Ok(())
+    }
+
+    /// Create the factory and the resources made with it.
+    fn setup_device(&mut self, res: &mut Resources) {
+        if self.validation.layer {
+            self.validation_log = ValidationLog::enable();
+        }
+        let (factory, families, adapters) = init_factory::<B>(&self.adapter).unwrap();
+        if let Some(log) = &self.validation_log {
+            log.check_started();
+        }
+        if let Some(adapters) = adapters {
+            res.insert(adapters);
+        }
+        let queue_id = main_queue(&families);
+
+        let skinning = SkinningSub::new(&factory).unwrap();
+        match PipelineCache::load(&factory, self.pipeline_cache.take()) {
+            Ok(cache) => res.insert(cache),
+            Err(e) => log::warn!("Failed to create the pipeline cache: {}", e),
+        }
+
+        self.families = Some(families);
+        res.insert(factory);
+        res.insert(skinning);
+        res.insert(queue_id);
     }
 }
 
@@ -219,32 +769,113 @@ where
     G: GraphCreator<B>,
 {
     fn run_now(&mut self, res: &'a Resources) {
+        if self.headless {
+            self.stub_loading(SystemData::fetch(res));
+            return;
+        }
         self.asset_loading(SystemData::fetch(res));
 
-        let rebuild = self.graph_creator.rebuild(res);
-        if self.graph.is_none() || rebuild {
-            self.rebuild_graph(res);
+        let (present_mode_changed, frame_time) = {
+            let config = res.fetch::<PresentationConfig>();
+            (self.presentation.changed(&config), config.frame_time())
+        };
+        let graph_changed = self.graph_creator.rebuild(res);
+
+        let dimensions = res.try_fetch::<ScreenDimensions>().map(|d| (*d).clone());
+        let action = self.resize.update(dimensions.as_ref());
+        {
+            let mut changes = res.fetch_mut::<ChangeDetectionSet>();
+            if present_mode_changed || action == FrameAction::Rebuild {
+                changes.mark(ChangeDetectionSet::SURFACE);
+            }
+            if graph_changed {
+                changes.mark(ChangeDetectionSet::GRAPH_STRUCTURE);
+            }
+        }
+        let (render, event) = res
+            .fetch_mut::<RenderControl>()
+            .frame(action == FrameAction::Skip);
+        if let Some(event) = event {
+            res.fetch_mut::<EventChannel<RenderControlEvent>>()
+                .single_write(event);
         }
-        self.run_graph(res);
+        if render {
+            let mut result = Ok(());
+            // Changes of skipped frames are seen by the next frame rendered.
+            let changed = self
+                .rebuild_watch
+                .changed(&res.fetch::<ChangeDetectionSet>());
+            if self.graph.is_none() || changed {
+                result = self.rebuild_graph(res).map_err(GraphFailure::Build);
+                self.resize.built(dimensions.as_ref());
+                self.rebuild_watch
+                    .update(&res.fetch::<ChangeDetectionSet>());
+            }
+            match result.and_then(|()| self.run_graph(res)) {
+                Ok(()) => self.recovery.succeeded(),
+                Err(failure) => self.recover(res, failure),
+            }
+        }
+        if let Some(log) = &mut self.validation_log {
+            log.forward(&mut res.fetch_mut::<ValidationReport>());
+        }
+        self.presentation.limit(frame_time);
     }
 
     fn setup(&mut self, res: &mut Resources) {
-        let config: rendy::factory::Config = Default::default();
-        let (factory, families): (Factory<B>, _) = rendy::factory::init(config).unwrap();
-
-        let queue_id = QueueId {
-            family: families.family_by_index(0).id(),
-            index: 0,
-        };
-
-        self.families = Some(families);
-        res.insert(factory);
-        res.insert(queue_id);
+        if !self.headless {
+            self.setup_device(res);
+        }
+        res.insert(B::variant());
+        res.insert(self.validation);
+        res.insert(ValidationReport::default());
+        res.insert(DrawCallStats::default());
+        res.insert(VertexBufferStats::default());
+        res.insert(UniformRingStats::default());
+        res.insert(ChangeDetectionSet::default());
+        res.insert(ClearWatch::default());
+        res.entry::<RenderStats3D>()
+            .or_insert_with(RenderStats3D::default);
+        res.entry::<ContentErrors>()
+            .or_insert_with(ContentErrors::default);
+        res.entry::<FallbackAssets>()
+            .or_insert_with(FallbackAssets::default);
+        res.entry::<DissolveNoise>()
+            .or_insert_with(DissolveNoise::default);
+        res.entry::<SpriteSheetPaths>()
+            .or_insert_with(SpriteSheetPaths::default);
+        res.entry::<ViewModelProjection>()
+            .or_insert_with(ViewModelProjection::default);
+        res.entry::<StaticInstanceSet>()
+            .or_insert_with(StaticInstanceSet::default);
+        if self.deterministic {
+            res.insert(Deterministic::enabled());
+        } else {
+            res.entry::<Deterministic>()
+                .or_insert_with(Deterministic::default);
+        }
         AssetLoadingData::<B>::setup(res);
         SetupData::setup(res);
+        if !self.headless {
+            // To upload them again if the device is lost.
+            res.fetch_mut::<AssetStorage<Mesh>>().keep_loaded_data();
+            res.fetch_mut::<AssetStorage<Texture>>().keep_loaded_data();
+        }
+        {
+            let mut config = res.fetch_mut::<PresentationConfig>();
+            if let Some(image_count) = self.image_count {
+                config.image_count = Some(image_count);
+            }
+            if let Some(frames_in_flight) = self.frames_in_flight {
+                config.frames_in_flight = Some(frames_in_flight);
+            }
+        }
 
         let mat = create_default_mat::<B>(res);
+        create_fallback_assets(res, &mat);
         res.insert(MaterialDefaults(mat));
+        create_neutral_texture(res);
+        create_dissolve_noise(res);
     }
 
     fn dispose(mut self: Box<Self>, res: &mut Resources) {
@@ -253,6 +884,10 @@ where
             log::debug!("Dispose graph");
             graph.dispose(&mut *factory, res);
         }
+        res.remove::<SkinningSub<B>>();
+        if let Some(cache) = res.remove::<PipelineCache<B>>() {
+            cache.save(&res.fetch::<Factory<B>>());
+        }
 
         log::debug!("Unload resources");
         if let Some(mut storage) = res.try_fetch_mut::<AssetStorage<Mesh>>() {
@@ -262,12 +897,264 @@ where
             storage.unload_all();
         }
 
+        if let Some(log) = &mut self.validation_log {
+            log.forward(&mut res.fetch_mut::<ValidationReport>());
+        }
+
         log::debug!("Drop families");
         drop(self.families);
     }
 }
 
-fn create_default_mat<B: Backend>(res: &mut Resources) -> Material {
+/// Upload texture data for sampling in shaders, checking that the device supports its format.
+fn build_texture<B: Backend>(
+    factory: &mut Factory<B>,
+    queue: QueueId,
+    TextureData(mut builder, meta): TextureData,
+) -> Result<Texture, amethyst_error::Error> {
+    if let Some(format) = meta.required_format {
+        if !format_supported(factory, format, ImageFeature::SAMPLED) {
+            let fallback = meta
+                .fallback
+                .ok_or(TextureError::UnsupportedFormat(format))?;
+            log::warn!(
+                "Textures of format {:?} can't be sampled by the device, converting them to {:?}",
+                format,
+                fallback.format()
+            );
+            let TextureData(converted, converted_meta) = fallback.convert()?;
+            return build_texture(
+                factory,
+                queue,
+                TextureData(
+                    converted,
+                    TextureMeta {
+                        sampler_info: meta.sampler_info.or(converted_meta.sampler_info),
+                        generate_mips: meta.generate_mips || converted_meta.generate_mips,
+                        ..converted_meta
+                    },
+                ),
+            );
+        }
+        if meta.generate_mips && meta.mip_chain.is_empty() {
+            let blit =
+                ImageFeature::BLIT_SRC | ImageFeature::BLIT_DST | ImageFeature::SAMPLED_LINEAR;
+            if format_supported(factory, format, blit) {
+                builder = builder.with_mip_levels(MipLevels::GenerateAuto);
+            } else {
+                log::warn!(
+                    "Textures of format {:?} can't be blitted by the device, skipping their mips",
+                    format
+                );
+            }
+        }
+    }
+    let builder = match meta.sampler_info {
+        Some(info) => builder.with_sampler_info(clamp_anisotropy(factory, info)),
+        None => builder,
+    };
+    let texture = builder
+        .build(shader_read(queue), factory)
+        .map_err(|e| e.compat())?;
+    upload_mip_chain(factory, queue, &texture, &meta.mip_chain)?;
+    Ok(B::wrap_texture(texture).with_palette(meta.palette))
+}
+
+/// State of textures sampled by the vertex and fragment shaders.
+fn shader_read(queue: QueueId) -> ImageState {
+    ImageState {
+        queue,
+        stage: rendy::hal::pso::PipelineStage::VERTEX_SHADER
+            | rendy::hal::pso::PipelineStage::FRAGMENT_SHADER,
+        access: rendy::hal::image::Access::SHADER_READ,
+        layout: rendy::hal::image::Layout::ShaderReadOnlyOptimal,
+    }
+}
+
+/// Upload the levels after the largest one of a texture built with a level per entry of
+/// `mip_chain` plus one, see `TextureData::with_mip_chain`.
+fn upload_mip_chain<B: Backend>(
+    factory: &mut Factory<B>,
+    queue: QueueId,
+    texture: &rendy::texture::Texture<B>,
+    mip_chain: &[Vec<u8>],
+) -> Result<(), amethyst_error::Error> {
+    use rendy::hal::image::{Extent, Offset, SubresourceLayers};
+
+    let image = texture.image();
+    let (block_width, block_height, _) = block_layout(image.format());
+    let extent = image.kind().extent();
+    let layers = image.kind().num_layers();
+    for (index, data) in mip_chain.iter().enumerate() {
+        let level = index as u8 + 1;
+        let width = (extent.width >> level).max(1);
+        let height = (extent.height >> level).max(1);
+        unsafe {
+            factory.upload_image(
+                image.clone(),
+                round_up(width, block_width),
+                round_up(height, block_height),
+                SubresourceLayers {
+                    aspects: rendy::hal::format::Aspects::COLOR,
+                    level,
+                    layers: 0..layers,
+                },
+                Offset { x: 0, y: 0, z: 0 },
+                Extent {
+                    width,
+                    height,
+                    depth: 1,
+                },
+                data,
+                ImageStateOrLayout::undefined(),
+                shader_read(queue),
+            )
+        }
+        .map_err(|e| e.compat())?;
+    }
+    Ok(())
+}
+
+/// Whether the device supports `features` for optimally tiled textures of `format`.
+fn format_supported<B: Backend>(
+    factory: &Factory<B>,
+    format: Format,
+    features: ImageFeature,
+) -> bool {
+    factory
+        .physical()
+        .format_properties(Some(format))
+        .optimal_tiling
+        .contains(features)
+}
+
+/// Lower the anisotropy of `info` to what the device supports, with a warning if it had to.
+fn clamp_anisotropy<B: Backend>(factory: &Factory<B>, mut info: SamplerInfo) -> SamplerInfo {
+    if let Anisotropic::On(level) = info.anisotropic {
+        let max = factory.physical().limits().max_sampler_anisotropy as u8;
+        if level > max {
+            log::warn!(
+                "Anisotropy {} is above the device limit of {}, clamping it",
+                level,
+                max
+            );
+            info.anisotropic = if max > 1 {
+                Anisotropic::On(max)
+            } else {
+                Anisotropic::Off
+            };
+        }
+    }
+    info
+}
+
+/// Write the data of a dynamic mesh into its buffers, or into new ones if the mesh was built
+/// from `MeshData`.
+fn write_dynamic_mesh<B: Backend>(
+    factory: &Factory<B>,
+    queue: QueueId,
+    mesh: &mut Mesh,
+    data: &ProceduralMesh,
+    pending: &Pending,
+) -> Result<(), failure::Error> {
+    if let Some(GpuMesh::Dynamic(buffers)) = B::unwrap_mesh_mut(mesh) {
+        buffers.write(factory, queue, data, pending)?;
+    } else {
+        *mesh = B::wrap_mesh(DynamicMeshBuffers::new(factory, queue, data)?.into());
+    }
+    mesh.set_layout(Some(data.layout()));
+    mesh.set_bounds(MeshBounds::of(data.positions()));
+    Ok(())
+}
+
+/// Create the factory on the adapter of `selection`.
+fn init_factory<B: Backend>(
+    selection: &AdapterSelection,
+) -> Result<(Factory<B>, Families<B>, Option<Adapters>), failure::Error> {
+    let select = SelectAdapter::new(selection.clone());
+    let defaults: rendy::factory::Config = Default::default();
+    let config = rendy::factory::Config {
+        devices: select.clone(),
+        heaps: defaults.heaps,
+        queues: defaults.queues,
+    };
+    let (factory, families) = rendy::factory::init(config)?;
+    Ok((factory, families, select.adapters()))
+}
+
+fn process_materials(
+    storage: &mut AssetStorage<Material>,
+    time: &Time,
+    pool: &ThreadPool,
+    strategy: Option<&HotReloadStrategy>,
+) {
+    storage.process(
+        |b| {
+            #[cfg(feature = "profiler")]
+            profile_scope!("process_material");
+
+            ProcessableAsset::process(b)
+        },
+        time.frame_number(),
+        pool,
+        strategy,
+    );
+}
+
+fn main_queue<B: Backend>(families: &Families<B>) -> QueueId {
+    QueueId {
+        family: families.family_by_index(0).id(),
+        index: 0,
+    }
+}
+
+/// Create the texture of `TexturePlaceholder::Neutral`.
+fn create_neutral_texture(res: &Resources) {
+    use amethyst_assets::Loader;
+
+    let handle = {
+        let loader = res.fetch::<Loader>();
+        let white = load_from_srgba(Srgba::new(1.0, 1.0, 1.0, 1.0));
+        loader.load_from_data(white.into(), (), &res.fetch::<AssetStorage<Texture>>())
+    };
+    res.fetch_mut::<TextureUploads>().set_neutral(handle);
+}
+
+/// Create the noise texture of the dissolving meshes.
+fn create_dissolve_noise(res: &Resources) {
+    use amethyst_assets::Loader;
+
+    let handle = {
+        let loader = res.fetch::<Loader>();
+        loader.load_from_data(dissolve::noise(), (), &res.fetch::<AssetStorage<Texture>>())
+    };
+    res.fetch_mut::<DissolveNoise>().set_texture(handle);
+}
+
+/// Create the checkerboard texture and the fallback material, made of the `defaults` with the
+/// checkerboard as albedo.
+fn create_fallback_assets(res: &Resources, defaults: &Material) {
+    use amethyst_assets::Loader;
+
+    let (texture, material) = {
+        let loader = res.fetch::<Loader>();
+        let texture = loader.load_from_data(
+            fallback::checkerboard(),
+            (),
+            &res.fetch::<AssetStorage<Texture>>(),
+        );
+        let material = Material {
+            albedo: texture.clone(),
+            ..defaults.clone()
+        };
+        let material = loader.load_from_data(material, (), &res.fetch::<AssetStorage<Material>>());
+        (texture, material)
+    };
+    res.fetch_mut::<FallbackAssets>()
+        .set_assets(texture, material);
+}
+
+fn create_default_mat<B: Backend>(res: &Resources) -> Material {
     use crate::mtl::TextureOffset;
 
     use amethyst_assets::Loader;
@@ -301,3 +1188,53 @@ fn create_default_mat<B: Backend>(res: &mut Resources) -> Material {
         uv_offset: TextureOffset::default(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_extent_skips_rendering() {
+        let mut watch = ResizeWatch::default();
+        let window = ScreenDimensions::new(640, 480, 1.0);
+        assert_eq!(watch.update(Some(&window)), FrameAction::Rebuild);
+        watch.built(Some(&window));
+        assert_eq!(watch.update(Some(&window)), FrameAction::Run);
+
+        let minimized = ScreenDimensions::new(0, 0, 1.0);
+        assert_eq!(watch.update(Some(&minimized)), FrameAction::Skip);
+        assert_eq!(watch.update(Some(&minimized)), FrameAction::Skip);
+
+        // Restored to the size the graph was built for
+        assert_eq!(watch.update(Some(&window)), FrameAction::Run);
+        assert_eq!(watch.update(None), FrameAction::Run);
+    }
+
+    #[test]
+    fn resizes_are_debounced() {
+        let mut watch = ResizeWatch::default();
+        let window = ScreenDimensions::new(640, 480, 1.0);
+        watch.built(Some(&window));
+
+        let mut rebuilds = 0;
+        for width in 641..661 {
+            let resized = ScreenDimensions::new(width, 480, 1.0);
+            if watch.update(Some(&resized)) == FrameAction::Rebuild {
+                watch.built(Some(&resized));
+                rebuilds += 1;
+            }
+        }
+        assert_eq!(rebuilds, 2);
+
+        // Settled for a frame
+        let resized = ScreenDimensions::new(800, 600, 1.0);
+        assert_eq!(watch.update(Some(&resized)), FrameAction::Run);
+        assert_eq!(watch.update(Some(&resized)), FrameAction::Rebuild);
+
+        // Moved to a monitor with another DPI factor
+        watch.built(Some(&resized));
+        let hidpi = ScreenDimensions::new(800, 600, 2.0);
+        assert_eq!(watch.update(Some(&hidpi)), FrameAction::Run);
+        assert_eq!(watch.update(Some(&hidpi)), FrameAction::Rebuild);
+    }
+}
@@ -0,0 +1,204 @@
+//! Budgeted uploads of loaded textures, spreading them over several frames.
+use crate::types::{Texture, TextureData};
+use amethyst_assets::Handle;
+
+/// Resource configuring how the `RenderingSystem` uploads loaded textures to the GPU.
+///
+/// By default every texture is uploaded in the frame its data finished loading, which can
+/// cause a hitch when large textures are loaded during gameplay. With a `budget`, textures are
+/// uploaded in the order they finished loading until the budget of the frame is spent, and the
+/// others wait for the following frames. At least one texture is uploaded every frame, so a
+/// texture larger than the budget takes a frame on its own.
+///
+/// A texture only becomes ready once its upload completed. Until then, the `placeholder` is
+/// drawn in its place, or the previous texture if it is being reloaded.
+///
+/// Insert the resource before the `RenderingSystem` is set up, or modify it in place, as the
+/// neutral placeholder texture is registered during setup.
+#[derive(Debug, Clone, Default)]
+pub struct TextureUploads {
+    /// Bytes of texture data to upload per frame, unlimited if `None`.
+    pub budget: Option<u64>,
+    /// Texture drawn in place of textures that are still loading.
+    pub placeholder: TexturePlaceholder,
+    neutral: Option<Handle<Texture>>,
+}
+
+/// Texture drawn in place of textures that are still loading.
+#[derive(Debug, Clone)]
+pub enum TexturePlaceholder {
+    /// Skip the objects using the texture until it is loaded.
+    None,
+    /// A 1x1 opaque white texture, created by the `RenderingSystem`.
+    Neutral,
+    /// A texture of your own, which is only used once it is loaded itself.
+    Texture(Handle<Texture>),
+}
+
+impl Default for TexturePlaceholder {
+    fn default() -> Self {
+        TexturePlaceholder::None
+    }
+}
+
+impl TextureUploads {
+    /// Upload at most `bytes` of texture data per frame.
+    pub fn with_budget(mut self, bytes: u64) -> Self {
+        self.budget = Some(bytes);
+        self
+    }
+
+    /// Draw `placeholder` in place of textures that are still loading.
+    pub fn with_placeholder(mut self, placeholder: TexturePlaceholder) -> Self {
+        self.placeholder = placeholder;
+        self
+    }
+
+    /// Handle of the placeholder texture, if any.
+    pub fn placeholder_handle(&self) -> Option<&Handle<Texture>> {
+        match self.placeholder {
+            TexturePlaceholder::None => None,
+            TexturePlaceholder::Neutral => self.neutral.as_ref(),
+            TexturePlaceholder::Texture(ref handle) => Some(handle),
+        }
+    }
+
+    pub(crate) fn set_neutral(&mut self, handle: Handle<Texture>) {
+        self.neutral = Some(handle);
+    }
+}
+
+/// Resource with statistics of the texture uploads, updated every frame by the
+/// `RenderingSystem`. Use it to tune the budget of `TextureUploads`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextureUploadStats {
+    /// Number of loaded textures waiting for their upload.
+    pub queued: usize,
+    /// Approximate size in bytes of the waiting textures. Textures of unknown size, like those
+    /// generated by rendy, aren't counted.
+    pub bytes_pending: u64,
+    /// Number of textures uploaded in the last frame.
+    pub uploaded: usize,
+    /// Size in bytes of the textures uploaded in the last frame.
+    pub bytes_uploaded: u64,
+}
+
+/// Spends the upload budget of one frame.
+#[derive(Debug)]
+pub(crate) struct UploadBudget {
+    remaining: Option<u64>,
+    stats: TextureUploadStats,
+}
+
+impl UploadBudget {
+    pub(crate) fn new(budget: Option<u64>) -> Self {
+        UploadBudget {
+            remaining: budget,
+            stats: TextureUploadStats::default(),
+        }
+    }
+
+    /// Whether `data` may be uploaded in this frame. Data that must wait is counted as queued.
+    pub(crate) fn allows(&mut self, data: &TextureData) -> bool {
+        let fits = match (self.remaining, data.1.byte_size) {
+            (None, _) => true,
+            _ if self.stats.uploaded == 0 => true,
+            (Some(remaining), Some(size)) => size <= remaining,
+            (Some(remaining), None) => remaining > 0,
+        };
+        if !fits {
+            self.stats.queued += 1;
+            self.stats.bytes_pending += data.1.byte_size.unwrap_or(0);
+        }
+        fits
+    }
+
+    /// Spend the budget on an uploaded texture of `bytes`.
+    pub(crate) fn uploaded(&mut self, bytes: u64) {
+        self.stats.uploaded += 1;
+        self.stats.bytes_uploaded += bytes;
+        if let Some(ref mut remaining) = self.remaining {
+            *remaining = remaining.saturating_sub(bytes);
+        }
+    }
+
+    pub(crate) fn finish(self) -> TextureUploadStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rendy::{hal::format::Format, texture::TextureBuilder};
+
+    fn data(size: Option<u64>) -> TextureData {
+        let data = TextureData::from(TextureBuilder::new().with_raw_data(vec![], Format::R8Unorm));
+        match size {
+            Some(size) => data.with_byte_size(size),
+            None => data,
+        }
+    }
+
+    #[test]
+    fn unlimited_budget_uploads_everything() {
+        let mut budget = UploadBudget::new(None);
+        for _ in 0..3 {
+            assert!(budget.allows(&data(Some(1 << 30))));
+            budget.uploaded(1 << 30);
+        }
+        let stats = budget.finish();
+        assert_eq!(stats.queued, 0);
+        assert_eq!(stats.uploaded, 3);
+    }
+
+    #[test]
+    fn budget_defers_textures_to_later_frames() {
+        let mut budget = UploadBudget::new(Some(100));
+        // The first texture of a frame is always uploaded, even above the budget
+        assert!(budget.allows(&data(Some(150))));
+        budget.uploaded(150);
+        assert!(!budget.allows(&data(Some(10))));
+        assert!(!budget.allows(&data(None)));
+        assert_eq!(
+            budget.finish(),
+            TextureUploadStats {
+                queued: 2,
+                bytes_pending: 10,
+                uploaded: 1,
+                bytes_uploaded: 150,
+            }
+        );
+
+        let mut budget = UploadBudget::new(Some(100));
+        assert!(budget.allows(&data(Some(60))));
+        budget.uploaded(60);
+        assert!(!budget.allows(&data(Some(60))));
+        // Smaller textures still fit in the rest of the budget
+        assert!(budget.allows(&data(Some(40))));
+        budget.uploaded(40);
+        assert!(!budget.allows(&data(None)));
+        assert_eq!(budget.finish().queued, 2);
+    }
+
+    #[test]
+    fn placeholder_handle() {
+        use amethyst_assets::{AssetStorage, Loader};
+        use rayon::ThreadPoolBuilder;
+        use std::sync::Arc;
+
+        let loader = Loader::new(".", Arc::new(ThreadPoolBuilder::new().build().unwrap()));
+        let storage = AssetStorage::<Texture>::default();
+        let neutral = loader.load_from_data(data(None), (), &storage);
+        let custom = loader.load_from_data(data(None), (), &storage);
+
+        let mut uploads = TextureUploads::default();
+        uploads.set_neutral(neutral.clone());
+        assert!(uploads.placeholder_handle().is_none());
+
+        let uploads = uploads.with_placeholder(TexturePlaceholder::Neutral);
+        assert_eq!(uploads.placeholder_handle(), Some(&neutral));
+        let uploads = uploads.with_placeholder(TexturePlaceholder::Texture(custom.clone()));
+        assert_eq!(uploads.placeholder_handle(), Some(&custom));
+    }
+}
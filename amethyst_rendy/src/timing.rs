@@ -0,0 +1,629 @@
+//! GPU time spent drawing each render group, measured with timestamp queries.
+//!
+//! Timing is opt-in, with `RenderingBundle::with_gpu_timing`. Every group of the graph is then
+//! wrapped in a `TimedGroupDesc`, writing a timestamp before and after drawing it, and a
+//! `GpuTimingDesc` node reads the timestamps back once the GPU finished the frame, which is a few
+//! frames later. The results land in the `GpuTimingStats` resource, and the
+//! `GpuTimingLogSystem` logs them periodically.
+use crate::{
+    rendy::{
+        command::{
+            CommandBuffer, CommandPool, ExecutableState, Family, Fence, Graphics, MultiShot,
+            PendingState, Queue, QueueId, QueueType, RenderPassEncoder, SimultaneousUse,
+            Submission, Submit,
+        },
+        factory::Factory,
+        frame::Frames,
+        graph::{
+            render::{PrepareResult, RenderGroup, RenderGroupDesc},
+            GraphContext, Node, NodeBuffer, NodeDesc, NodeImage,
+        },
+        hal::{self, adapter::PhysicalDevice, command::RawCommandBuffer, device::Device},
+    },
+    types::Backend,
+};
+use amethyst_core::ecs::{Read, Resources, System};
+use derivative::Derivative;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Number of frames the average time of a group is computed over.
+pub const AVERAGE_FRAMES: usize = 60;
+
+/// Whether the render groups are timed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpuTimingStatus {
+    /// Timing wasn't requested when the graph was built.
+    Disabled,
+    /// Timing was requested, but the adapter doesn't support timestamps on the graphics queue.
+    Unavailable,
+    /// The groups are timed.
+    Enabled,
+}
+
+impl Default for GpuTimingStatus {
+    fn default() -> Self {
+        GpuTimingStatus::Disabled
+    }
+}
+
+/// GPU time spent drawing a render group.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GroupTiming {
+    /// Milliseconds of the last frame read back.
+    pub last_ms: f32,
+    /// Average milliseconds of the last `AVERAGE_FRAMES` frames read back.
+    pub average_ms: f32,
+    samples: VecDeque<f32>,
+}
+
+impl GroupTiming {
+    fn record(&mut self, ms: f32) {
+        if self.samples.len() == AVERAGE_FRAMES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(ms);
+        self.last_ms = ms;
+        self.average_ms = self.samples.iter().sum::<f32>() / self.samples.len() as f32;
+    }
+}
+
+/// Resource with the GPU time spent drawing each render group, updated by the render graph when
+/// it is built with timing.
+///
+/// Groups are named after their description, like `DrawFlat2DDesc`, prefixed by the window name
+/// for the groups drawing secondary windows.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GpuTimingStats {
+    status: GpuTimingStatus,
+    groups: Vec<(String, GroupTiming)>,
+}
+
+impl GpuTimingStats {
+    /// Whether the render groups are timed.
+    pub fn status(&self) -> GpuTimingStatus {
+        self.status
+    }
+
+    /// Timing of the group of the given name.
+    pub fn get(&self, name: &str) -> Option<&GroupTiming> {
+        self.groups
+            .iter()
+            .find(|(group, _)| group == name)
+            .map(|(_, timing)| timing)
+    }
+
+    /// Names and timings of the groups, in drawing order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &GroupTiming)> {
+        self.groups
+            .iter()
+            .map(|(name, timing)| (name.as_str(), timing))
+    }
+
+    /// Average milliseconds of all groups together.
+    pub fn total_ms(&self) -> f32 {
+        self.groups
+            .iter()
+            .map(|(_, timing)| timing.average_ms)
+            .sum()
+    }
+
+    /// Lines with the average and last time of every group, and the total.
+    pub fn breakdown(&self) -> Vec<String> {
+        match self.status {
+            GpuTimingStatus::Disabled => return vec!["GPU timing is disabled".to_string()],
+            GpuTimingStatus::Unavailable => {
+                return vec!["GPU timing is unavailable on this adapter".to_string()]
+            }
+            GpuTimingStatus::Enabled => {}
+        }
+        let mut lines: Vec<_> = self
+            .groups
+            .iter()
+            .map(|(name, timing)| {
+                format!(
+                    "{}: {:.3} ms (last {:.3} ms)",
+                    name, timing.average_ms, timing.last_ms
+                )
+            })
+            .collect();
+        lines.push(format!("Total: {:.3} ms", self.total_ms()));
+        lines
+    }
+
+    /// Start over with the groups of a newly built graph. Timings of groups kept by the new graph
+    /// are kept.
+    pub(crate) fn reset(&mut self, status: GpuTimingStatus, names: &[String]) {
+        let mut groups = std::mem::replace(&mut self.groups, Vec::new());
+        self.status = status;
+        self.groups = names
+            .iter()
+            .map(|name| {
+                let timing = groups
+                    .iter()
+                    .position(|(group, _)| group == name)
+                    .map(|i| groups.swap_remove(i).1)
+                    .unwrap_or_default();
+                (name.clone(), timing)
+            })
+            .collect();
+    }
+
+    /// Record the time of the groups of a frame, in the order of `reset`. Groups without a time
+    /// weren't drawn and keep their previous timing.
+    fn record(&mut self, times: impl IntoIterator<Item = Option<f32>>) {
+        for ((_, timing), ms) in self.groups.iter_mut().zip(times) {
+            if let Some(ms) = ms {
+                timing.record(ms);
+            }
+        }
+    }
+}
+
+/// Whether the adapter of `factory` writes timestamps on graphics queues.
+pub fn timestamps_supported<B: Backend>(factory: &Factory<B>) -> bool {
+    let limits = factory.physical().limits();
+    limits.timestamp_compute_and_graphics && limits.timestamp_period > 0.0
+}
+
+/// Name of a render group for the `GpuTimingStats`, from its `Debug` output: the type name,
+/// without fields or generic parameters.
+pub(crate) fn group_name(group: &impl std::fmt::Debug) -> String {
+    let debug = format!("{:?}", group);
+    debug
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Query pool shared by the timed groups of a graph and its `GpuTimingDesc` node. It is
+/// created by the node and destroyed by the last of them to be disposed.
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""), Clone(bound = ""))]
+pub struct GpuTimer<B: Backend> {
+    state: Arc<Mutex<TimerState<B>>>,
+}
+
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+struct TimerState<B: Backend> {
+    pool: Option<B::QueryPool>,
+    groups: u32,
+    slots: u32,
+}
+
+impl<B: Backend> GpuTimer<B> {
+    /// Timer for `groups` render groups.
+    pub fn new(groups: usize) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(TimerState {
+                pool: None,
+                groups: groups as u32,
+                slots: 0,
+            })),
+        }
+    }
+
+    /// Queries of a frame slot, two per group.
+    fn slot_queries(state: &TimerState<B>, slot: u32) -> std::ops::Range<hal::query::Id> {
+        let start = slot * state.groups * 2;
+        start..start + state.groups * 2
+    }
+
+    /// Write the timestamp of group `group` in the frame slot `index`, at its start or end.
+    unsafe fn write(
+        &self,
+        encoder: &mut RenderPassEncoder<'_, B>,
+        index: usize,
+        group: u32,
+        end: bool,
+    ) {
+        let state = self.state.lock().unwrap();
+        if let Some(pool) = &state.pool {
+            let slot = index as u32 % state.slots;
+            let id = Self::slot_queries(&state, slot).start + group * 2 + end as u32;
+            let stage = if end {
+                hal::pso::PipelineStage::BOTTOM_OF_PIPE
+            } else {
+                hal::pso::PipelineStage::TOP_OF_PIPE
+            };
+            encoder
+                .raw()
+                .write_timestamp(stage, hal::query::Query { pool, id });
+        }
+    }
+
+    unsafe fn release(self, factory: &mut Factory<B>) {
+        if let Ok(state) = Arc::try_unwrap(self.state) {
+            let state = state.into_inner().unwrap_or_else(|e| e.into_inner());
+            if let Some(pool) = state.pool {
+                factory.device().destroy_query_pool(pool);
+            }
+        }
+    }
+}
+
+/// Render group description timing the group it wraps, as group `index` of its `GpuTimer`.
+///
+/// The timestamps are written into the queries of the frame slot drawn, so command buffers
+/// reused by the wrapped group keep timing the right frame.
+#[derive(Derivative)]
+#[derivative(Debug(bound = "G: std::fmt::Debug"))]
+pub struct TimedGroupDesc<B: Backend, G> {
+    group: G,
+    timer: GpuTimer<B>,
+    index: u32,
+}
+
+impl<B: Backend, G> TimedGroupDesc<B, G> {
+    /// Time `group` as group `index` of `timer`.
+    pub fn new(group: G, timer: GpuTimer<B>, index: usize) -> Self {
+        Self {
+            group,
+            timer,
+            index: index as u32,
+        }
+    }
+}
+
+impl<B, G> RenderGroupDesc<B, Resources> for TimedGroupDesc<B, G>
+where
+    B: Backend,
+    G: RenderGroupDesc<B, Resources>,
+{
+    fn buffers(&self) -> Vec<rendy::graph::BufferAccess> {
+        self.group.buffers()
+    }
+
+    fn images(&self) -> Vec<rendy::graph::ImageAccess> {
+        self.group.images()
+    }
+
+    fn depth(&self) -> bool {
+        self.group.depth()
+    }
+
+    fn colors(&self) -> usize {
+        self.group.colors()
+    }
+
+    fn build(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        aux: &Resources,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, Resources>>, failure::Error> {
+        let group = self.group.build(
+            ctx,
+            factory,
+            queue,
+            aux,
+            framebuffer_width,
+            framebuffer_height,
+            subpass,
+            buffers,
+            images,
+        )?;
+        Ok(Box::new(TimedGroup {
+            group,
+            timer: self.timer,
+            index: self.index,
+        }))
+    }
+}
+
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+struct TimedGroup<B: Backend> {
+    #[derivative(Debug = "ignore")]
+    group: Box<dyn RenderGroup<B, Resources>>,
+    timer: GpuTimer<B>,
+    index: u32,
+}
+
+impl<B: Backend> RenderGroup<B, Resources> for TimedGroup<B> {
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        queue: QueueId,
+        index: usize,
+        subpass: hal::pass::Subpass<'_, B>,
+        aux: &Resources,
+    ) -> PrepareResult {
+        self.group.prepare(factory, queue, index, subpass, aux)
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        subpass: hal::pass::Subpass<'_, B>,
+        aux: &Resources,
+    ) {
+        unsafe {
+            self.timer.write(&mut encoder, index, self.index, false);
+        }
+        self.group
+            .draw_inline(encoder.reborrow(), index, subpass, aux);
+        unsafe {
+            self.timer.write(&mut encoder, index, self.index, true);
+        }
+    }
+
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, aux: &Resources) {
+        let TimedGroup { group, timer, .. } = *self;
+        group.dispose(factory, aux);
+        unsafe {
+            timer.release(factory);
+        }
+    }
+}
+
+/// Render graph node creating the queries of a `GpuTimer` and reading them back into the
+/// `GpuTimingStats` resource. The passes drawing the timed groups must depend on it, as it
+/// resets the queries of a frame before they are written.
+#[derive(Debug)]
+pub struct GpuTimingDesc<B: Backend> {
+    timer: GpuTimer<B>,
+}
+
+impl<B: Backend> GpuTimingDesc<B> {
+    /// Node for the groups timed with `timer`.
+    pub fn new(timer: GpuTimer<B>) -> Self {
+        Self { timer }
+    }
+}
+
+/// Render graph node built by `GpuTimingDesc`.
+#[derive(Debug)]
+pub struct GpuTimingNode<B: Backend> {
+    timer: GpuTimer<B>,
+    pool: CommandPool<B, QueueType>,
+    slots: Vec<TimingSlot<B>>,
+    period: f32,
+}
+
+/// Reset of the queries of one frame in flight.
+#[derive(Debug)]
+struct TimingSlot<B: Backend> {
+    submit: Submit<B, SimultaneousUse>,
+    command_buffer:
+        CommandBuffer<B, QueueType, PendingState<ExecutableState<MultiShot<SimultaneousUse>>>>,
+    frame: Option<u64>,
+}
+
+impl<B: Backend> NodeDesc<B, Resources> for GpuTimingDesc<B> {
+    type Node = GpuTimingNode<B>;
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        family: &mut Family<B>,
+        _queue: usize,
+        _aux: &Resources,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Self::Node, failure::Error> {
+        assert!(buffers.is_empty());
+        assert!(images.is_empty());
+
+        let slots = ctx.frames_in_flight();
+        let mut pool = factory.create_command_pool(family)?;
+        let mut state = self.timer.state.lock().unwrap();
+        state.slots = slots;
+        let queries = unsafe {
+            factory
+                .device()
+                .create_query_pool(hal::query::Type::Timestamp, slots * state.groups * 2)?
+        };
+
+        let mut timing_slots = Vec::new();
+        for (slot, initial) in pool
+            .allocate_buffers(slots as usize)
+            .into_iter()
+            .enumerate()
+        {
+            let mut recording = initial.begin(MultiShot(SimultaneousUse), ());
+            unsafe {
+                recording
+                    .encoder()
+                    .raw()
+                    .reset_query_pool(&queries, GpuTimer::slot_queries(&state, slot as u32));
+            }
+            let (submit, command_buffer) = recording.finish().submit();
+            timing_slots.push(TimingSlot {
+                submit,
+                command_buffer,
+                frame: None,
+            });
+        }
+        state.pool = Some(queries);
+        drop(state);
+
+        Ok(GpuTimingNode {
+            timer: self.timer,
+            pool,
+            slots: timing_slots,
+            period: factory.physical().limits().timestamp_period,
+        })
+    }
+}
+
+impl<B: Backend> Node<B, Resources> for GpuTimingNode<B> {
+    type Capability = Graphics;
+
+    fn run<'a>(
+        &mut self,
+        _ctx: &GraphContext<B>,
+        factory: &Factory<B>,
+        queue: &mut Queue<B>,
+        aux: &Resources,
+        frames: &Frames<B>,
+        waits: &[(&'a B::Semaphore, hal::pso::PipelineStage)],
+        signals: &[&'a B::Semaphore],
+        fence: Option<&mut Fence<B>>,
+    ) {
+        let frame = frames.next().index();
+        let index = (frame % self.slots.len() as u64) as usize;
+        let slot = &mut self.slots[index];
+
+        // The queries of the slot are read back before being reset for this frame.
+        if let Some(written) = slot.frame.take() {
+            if frames.is_complete(written) {
+                let state = self.timer.state.lock().unwrap();
+                let range = GpuTimer::slot_queries(&state, index as u32);
+                let mut data = vec![0u8; range.len() * 8];
+                let ready = state.pool.as_ref().map_or(false, |pool| unsafe {
+                    factory
+                        .device()
+                        .get_query_pool_results(
+                            pool,
+                            range,
+                            &mut data,
+                            8,
+                            hal::query::ResultFlags::BITS_64,
+                        )
+                        .unwrap_or(false)
+                });
+                if ready {
+                    if let Some(mut stats) = aux.try_fetch_mut::<GpuTimingStats>() {
+                        stats.record(elapsed_ms(&data, self.period));
+                    }
+                }
+            }
+        }
+        slot.frame = Some(frame);
+
+        unsafe {
+            queue.submit(
+                Some(
+                    Submission::new()
+                        .wait(waits.iter().cloned())
+                        .submits(Some(&slot.submit))
+                        .signal(signals.iter().cloned()),
+                ),
+                fence,
+            );
+        }
+    }
+
+    unsafe fn dispose(mut self, factory: &mut Factory<B>, _aux: &Resources) {
+        self.pool.free_buffers(
+            self.slots
+                .drain(..)
+                .map(|slot| slot.command_buffer.mark_complete()),
+        );
+        factory.destroy_command_pool(self.pool);
+        self.timer.release(factory);
+    }
+}
+
+/// Milliseconds between the pairs of 64 bit timestamps of `data`, counting `period`
+/// nanoseconds per tick. Pairs going backwards weren't written this frame.
+fn elapsed_ms(data: &[u8], period: f32) -> Vec<Option<f32>> {
+    let mut timestamps = data.chunks_exact(8).map(|bytes| {
+        let mut value = [0; 8];
+        value.copy_from_slice(bytes);
+        u64::from_ne_bytes(value)
+    });
+    let mut times = Vec::new();
+    while let (Some(start), Some(end)) = (timestamps.next(), timestamps.next()) {
+        times.push(if end >= start && start != 0 {
+            Some((end - start) as f32 * period / 1_000_000.0)
+        } else {
+            None
+        });
+    }
+    times
+}
+
+/// Logs the breakdown of the `GpuTimingStats` at a fixed interval.
+#[derive(Debug)]
+pub struct GpuTimingLogSystem {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl GpuTimingLogSystem {
+    /// Log every `interval`.
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last: None,
+        }
+    }
+}
+
+impl<'a> System<'a> for GpuTimingLogSystem {
+    type SystemData = Read<'a, GpuTimingStats>;
+
+    fn run(&mut self, stats: Self::SystemData) {
+        let now = Instant::now();
+        match self.last {
+            Some(last) if now.duration_since(last) < self.interval => return,
+            None => {
+                self.last = Some(now);
+                return;
+            }
+            _ => {}
+        }
+        self.last = Some(now);
+        log::info!("GPU time per render group:");
+        for line in stats.breakdown() {
+            log::info!("  {}", line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timings_are_averaged() {
+        let mut stats = GpuTimingStats::default();
+        let names = vec!["DrawFlat2DDesc".to_string(), "DrawSkyboxDesc".to_string()];
+        stats.reset(GpuTimingStatus::Enabled, &names);
+        stats.record(vec![Some(1.0), Some(0.5)]);
+        stats.record(vec![Some(3.0), None]);
+
+        let flat = stats.get("DrawFlat2DDesc").unwrap();
+        assert_eq!((flat.last_ms, flat.average_ms), (3.0, 2.0));
+        let skybox = stats.get("DrawSkyboxDesc").unwrap();
+        assert_eq!((skybox.last_ms, skybox.average_ms), (0.5, 0.5));
+        assert_eq!(stats.total_ms(), 2.5);
+
+        // Rebuilding keeps the timings of the groups still drawn.
+        stats.reset(GpuTimingStatus::Enabled, &names[1..]);
+        assert_eq!(stats.iter().count(), 1);
+        assert_eq!(stats.get("DrawSkyboxDesc").unwrap().average_ms, 0.5);
+
+        stats.reset(GpuTimingStatus::Unavailable, &[]);
+        assert_eq!(
+            stats.breakdown(),
+            vec!["GPU timing is unavailable on this adapter".to_string()]
+        );
+    }
+
+    #[test]
+    fn timestamps_to_milliseconds() {
+        let data: Vec<u8> = [100u64, 2_100_100, 50, 40, 0, 10]
+            .iter()
+            .flat_map(|t| t.to_ne_bytes().to_vec())
+            .collect();
+        assert_eq!(elapsed_ms(&data, 1.0), vec![Some(2.0), None, None]);
+        assert_eq!(group_name(&GpuTimingStatus::Enabled), "Enabled");
+        assert_eq!(group_name(&GroupTiming::default()), "GroupTiming");
+    }
+}
@@ -1,7 +1,13 @@
 //! Transparency component implementation
+use crate::rendy::hal::pso::{BlendOp, BlendState, Factor};
 use amethyst_assets::PrefabData;
-use amethyst_core::ecs::{prelude::Component, storage::NullStorage, Entity, WriteStorage};
+use amethyst_core::ecs::{
+    prelude::{Component, DenseVecStorage},
+    storage::NullStorage,
+    Entity, WriteStorage,
+};
 use amethyst_error::Error;
+use hibitset::BitSet;
 
 /// Transparent mesh component
 #[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
@@ -26,3 +32,263 @@ impl<'a> PrefabData<'a> for Transparent {
         Ok(())
     }
 }
+
+/// Overrides the order in which a `Transparent` entity is drawn.
+///
+/// Transparent entities are drawn in increasing key order, so higher keys draw later, on top of
+/// lower ones. Entities without this component use a key of zero. Entities with the same key are
+/// drawn back to front based on their distance from the camera, then by entity id.
+///
+/// Useful when centroid sorting gives wrong results, like for a large water plane with objects
+/// partially in it.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+pub struct TransparencySortKey(pub i32);
+
+impl Component for TransparencySortKey {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl<'a> PrefabData<'a> for TransparencySortKey {
+    type SystemData = WriteStorage<'a, TransparencySortKey>;
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        entity: Entity,
+        storage: &mut Self::SystemData,
+        _: &[Entity],
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        storage.insert(entity, *self)?;
+        Ok(())
+    }
+}
+
+/// Tint alpha below which entities are drawn as transparent, by default.
+pub const DEFAULT_TINT_ENTER: f32 = 0.99;
+/// Tint alpha from which they are drawn as opaque again, by default.
+pub const DEFAULT_TINT_EXIT: f32 = 0.999;
+
+/// Drawing entities whose `Tint` is translucent as transparent, without a `Transparent`
+/// component, so meshes and sprites fade in and out by animating their tint alone.
+///
+/// Enabled on the visibility sorting systems with `with_tint_transparency`. An entity joins the
+/// transparent entities once its tint alpha goes below `enter`, and goes back to the opaque ones
+/// once it's `exit` or above, so alphas hovering near one don't move it between them every frame.
+/// Entities with `NoTintTransparency` are left where their `Transparent` component puts them.
+#[derive(Debug, Clone)]
+pub struct TintTransparency {
+    /// Tint alpha below which an entity is drawn as transparent.
+    pub enter: f32,
+    /// Tint alpha from which an entity drawn as transparent for its tint is drawn as opaque.
+    pub exit: f32,
+    translucent: BitSet,
+    previous: BitSet,
+}
+
+impl Default for TintTransparency {
+    fn default() -> Self {
+        Self::new(DEFAULT_TINT_ENTER, DEFAULT_TINT_EXIT)
+    }
+}
+
+impl TintTransparency {
+    /// Draw entities as transparent below the tint alpha `enter`, and as opaque again from
+    /// `exit`, which should be higher.
+    pub fn new(enter: f32, exit: f32) -> Self {
+        Self {
+            enter,
+            exit,
+            translucent: BitSet::new(),
+            previous: BitSet::new(),
+        }
+    }
+
+    /// Start a frame, forgetting the entities not checked during the previous one.
+    pub(crate) fn begin(&mut self) {
+        std::mem::swap(&mut self.translucent, &mut self.previous);
+        self.translucent.clear();
+    }
+
+    /// Whether `entity` is drawn as transparent for the tint `alpha`, `None` when it has no tint
+    /// or opted out.
+    pub(crate) fn is_transparent(&mut self, entity: Entity, alpha: Option<f32>) -> bool {
+        let was_translucent = self.previous.contains(entity.id());
+        let translucent = match alpha {
+            Some(alpha) if was_translucent => alpha < self.exit,
+            Some(alpha) => alpha < self.enter,
+            None => false,
+        };
+        if translucent {
+            self.translucent.add(entity.id());
+        }
+        translucent
+    }
+}
+
+/// Marks an entity that isn't drawn as transparent for the alpha of its `Tint`, with
+/// `TintTransparency`.
+///
+/// Useful for entities whose tint alpha means something else to their shader, or which are
+/// drawn correctly without sorting, like alpha tested foliage.
+#[derive(Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct NoTintTransparency;
+
+impl Component for NoTintTransparency {
+    type Storage = NullStorage<Self>;
+}
+
+impl<'a> PrefabData<'a> for NoTintTransparency {
+    type SystemData = WriteStorage<'a, NoTintTransparency>;
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        entity: Entity,
+        storage: &mut Self::SystemData,
+        _: &[Entity],
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        storage.insert(entity, NoTintTransparency)?;
+        Ok(())
+    }
+}
+
+/// How a `Transparent` entity blends with what's drawn behind it.
+///
+/// Entities without this component blend as before: meshes as `Premultiplied`, sprites
+/// according to the `AlphaMode` of their sprite sheet. Entities of all modes are still drawn back
+/// to front, the transparent groups switching pipelines between consecutive entities of different
+/// modes.
+///
+/// `Additive` and `Multiply` expect the colors drawn to be premultiplied by their alpha, like the
+/// ones of meshes and of premultiplied sprite sheets, to fade with it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum BlendMode {
+    /// Straight alpha blending, with `(SrcAlpha, OneMinusSrcAlpha)`.
+    Alpha,
+    /// Alpha blending of premultiplied colors, with `(One, OneMinusSrcAlpha)`.
+    Premultiplied,
+    /// Adds the color to the one behind, like for glowing projectiles and sparks.
+    Additive,
+    /// Multiplies the color behind by the color, like for shadows and decals.
+    Multiply,
+}
+
+impl BlendMode {
+    /// All the blend modes, in the order of the pipelines of the transparent groups.
+    pub const ALL: [BlendMode; 4] = [
+        BlendMode::Alpha,
+        BlendMode::Premultiplied,
+        BlendMode::Additive,
+        BlendMode::Multiply,
+    ];
+
+    /// Index of the mode in `ALL`.
+    pub fn index(self) -> usize {
+        match self {
+            BlendMode::Alpha => 0,
+            BlendMode::Premultiplied => 1,
+            BlendMode::Additive => 2,
+            BlendMode::Multiply => 3,
+        }
+    }
+
+    /// Blend state of the pipelines drawing this mode. The alpha drawn behind is kept by
+    /// `Additive` and `Multiply`.
+    pub fn blend_state(self) -> BlendState {
+        match self {
+            BlendMode::Alpha => BlendState::ALPHA,
+            BlendMode::Premultiplied => BlendState::PREMULTIPLIED_ALPHA,
+            BlendMode::Additive => BlendState::On {
+                color: BlendOp::Add {
+                    src: Factor::One,
+                    dst: Factor::One,
+                },
+                alpha: BlendOp::Add {
+                    src: Factor::Zero,
+                    dst: Factor::One,
+                },
+            },
+            BlendMode::Multiply => BlendState::On {
+                color: BlendOp::Add {
+                    src: Factor::DstColor,
+                    dst: Factor::OneMinusSrcAlpha,
+                },
+                alpha: BlendOp::Add {
+                    src: Factor::Zero,
+                    dst: Factor::One,
+                },
+            },
+        }
+    }
+}
+
+impl Component for BlendMode {
+    type Storage = DenseVecStorage<Self>;
+}
+
+impl<'a> PrefabData<'a> for BlendMode {
+    type SystemData = WriteStorage<'a, BlendMode>;
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        entity: Entity,
+        storage: &mut Self::SystemData,
+        _: &[Entity],
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        storage.insert(entity, *self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_index_all() {
+        for (i, mode) in BlendMode::ALL.iter().enumerate() {
+            assert_eq!(mode.index(), i);
+        }
+        assert_eq!(BlendMode::Alpha.blend_state(), BlendState::ALPHA);
+        let mode: BlendMode = ron::de::from_str("Additive").unwrap();
+        assert_eq!(mode, BlendMode::Additive);
+    }
+
+    #[test]
+    fn tint_transparency_has_hysteresis() {
+        let world = amethyst_core::ecs::World::new();
+        let entity = world.entities().create();
+        let mut tint = TintTransparency::default();
+        let mut frame = |alpha| {
+            tint.begin();
+            tint.is_transparent(entity, alpha)
+        };
+
+        assert!(!frame(Some(1.0)));
+        assert!(!frame(Some(0.995)));
+        assert!(frame(Some(0.5)));
+        // Stays transparent until the alpha is back near one
+        assert!(frame(Some(0.995)));
+        assert!(!frame(Some(1.0)));
+        assert!(!frame(Some(0.995)));
+
+        assert!(frame(Some(0.5)));
+        assert!(!frame(None));
+    }
+}
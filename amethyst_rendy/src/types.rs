@@ -1,18 +1,52 @@
 //! 'Global' rendering type declarations
+use crate::{
+    dynamic_mesh::DynamicMeshBuffers,
+    error::MeshError,
+    mesh_reader::{self, Attribute},
+    mesh_util::{self, ProceduralMesh},
+    visibility::{BoundingBox, BoundingSphere},
+};
 use amethyst_assets::{Asset, Handle};
-use amethyst_core::ecs::DenseVecStorage;
+use amethyst_core::ecs::{DenseVecStorage, FlaggedStorage};
+use amethyst_error::Error;
+use derivative::Derivative;
+use palette::Srgba;
+use rendy::{
+    command::RenderPassEncoder,
+    hal::{
+        format::Format,
+        image::{Kind, Level, SamplerInfo},
+        IndexType, Primitive,
+    },
+    mesh::{Incompatible, VertexFormat},
+};
 use serde::{Deserialize, Serialize};
+use std::{ops::Range, sync::Arc};
 
 /// Extension of the rendy Backend trait.
 pub trait Backend: rendy::hal::Backend {
-    /// Unwrap a Backend to a rendy `Mesh`
-    fn unwrap_mesh(mesh: &Mesh) -> Option<&rendy::mesh::Mesh<Self>>;
+    /// Unwrap a Backend to its `GpuMesh`
+    fn unwrap_mesh(mesh: &Mesh) -> Option<&GpuMesh<Self>>;
+    /// Unwrap a Backend to its mutable `GpuMesh`
+    fn unwrap_mesh_mut(mesh: &mut Mesh) -> Option<&mut GpuMesh<Self>>;
     /// Unwrap a Backend to a rendy `Texture`
     fn unwrap_texture(texture: &Texture) -> Option<&rendy::texture::Texture<Self>>;
-    /// Wrap a rendy `Mesh` to its Backend generic.
-    fn wrap_mesh(mesh: rendy::mesh::Mesh<Self>) -> Mesh;
+    /// Wrap a `GpuMesh` to its Backend generic.
+    fn wrap_mesh(mesh: GpuMesh<Self>) -> Mesh;
     /// Wrap a rendy `Texture` to its Backend generic.
     fn wrap_texture(texture: rendy::texture::Texture<Self>) -> Texture;
+    /// The variant of the backend.
+    fn variant() -> BackendVariant;
+}
+
+/// Code generic over the backend, run with the backend of a `BackendVariant` chosen at runtime by
+/// `BackendVariant::dispatch`.
+pub trait BackendVisitor {
+    /// Result of the code.
+    type Output;
+
+    /// Run the code with the backend `B`.
+    fn visit<B: Backend>(self) -> Self::Output;
 }
 
 macro_rules! impl_backends {
@@ -27,7 +61,10 @@ macro_rules! impl_backends {
         );
 
         /// Backend wrapper.
-        #[derive(Debug)]
+        ///
+        /// The variants are the backends compiled in, in the order they're preferred in by
+        /// default, see the `backend` module.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
         pub enum BackendVariant {
             $(
                 #[cfg(feature = $feature)]
@@ -36,24 +73,229 @@ macro_rules! impl_backends {
             )*
         }
 
+        impl BackendVariant {
+            /// The backends compiled in, in order of preference.
+            pub fn compiled() -> Vec<BackendVariant> {
+                #[allow(unused_mut)]
+                let mut variants = Vec::new();
+                $(
+                    #[cfg(feature = $feature)]
+                    variants.push(BackendVariant::$variant);
+                )*
+                variants
+            }
+
+            /// Name of the backend, the name of its cargo feature.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        BackendVariant::$variant => $feature,
+                    )*
+                }
+            }
+
+            /// Run `visitor` with the backend of this variant.
+            pub fn dispatch<V: BackendVisitor>(self, visitor: V) -> V::Output {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        BackendVariant::$variant => visitor.visit::<$backend>(),
+                    )*
+                }
+            }
+        }
+
         /// Mesh wrapper.
+        ///
+        /// Besides the GPU mesh, it keeps the `MeshLayout` of the data it was built from, if
+        /// that was known, and the `MeshBounds` of its vertex positions.
         #[derive(Debug)]
         pub enum Mesh {
             $(
                 #[cfg(feature = $feature)]
                 #[doc = "Mesh Variant"]
-                $variant(rendy::mesh::Mesh<$backend>),
+                $variant(GpuMesh<$backend>, Option<MeshLayout>, Option<MeshBounds>),
             )*
+            /// Mesh loaded by a headless `RenderingSystem`, keeping only the `MeshLayout` and
+            /// `MeshBounds` of its data.
+            Stub(Option<MeshLayout>, Option<MeshBounds>),
+        }
+
+        impl Mesh {
+            /// Set the layout of the data the mesh was built from.
+            pub fn with_layout(self, layout: Option<MeshLayout>) -> Self {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        Mesh::$variant(inner, _, bounds) => Mesh::$variant(inner, layout, bounds),
+                    )*
+                    Mesh::Stub(_, bounds) => Mesh::Stub(layout, bounds),
+                }
+            }
+
+            /// Set the bounds of the vertex positions of the mesh.
+            pub fn with_bounds(mut self, bounds: Option<MeshBounds>) -> Self {
+                self.set_bounds(bounds);
+                self
+            }
+
+            /// Set the bounds of the mesh in place, for dynamic meshes written again.
+            pub(crate) fn set_bounds(&mut self, bounds: Option<MeshBounds>) {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        Mesh::$variant(_, _, current) => *current = bounds,
+                    )*
+                    Mesh::Stub(_, current) => *current = bounds,
+                }
+            }
+
+            /// Bounds of the vertex positions, in mesh space. They are computed when the mesh is
+            /// loaded, for data whose positions can be read back from its `MeshBuilder`.
+            pub fn bounds(&self) -> Option<&MeshBounds> {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        Mesh::$variant(_, _, bounds) => bounds.as_ref(),
+                    )*
+                    Mesh::Stub(_, bounds) => bounds.as_ref(),
+                }
+            }
+
+            /// Set the layout of the mesh in place, for dynamic meshes written again.
+            pub(crate) fn set_layout(&mut self, layout: Option<MeshLayout>) {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        Mesh::$variant(_, current, _) => *current = layout,
+                    )*
+                    Mesh::Stub(current, _) => *current = layout,
+                }
+            }
+
+            /// Layout of the data the mesh was built from, if known. This is not available for
+            /// meshes whose `MeshData` was created directly from a rendy `MeshBuilder`.
+            pub fn layout(&self) -> Option<&MeshLayout> {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        Mesh::$variant(_, layout, _) => layout.as_ref(),
+                    )*
+                    Mesh::Stub(layout, _) => layout.as_ref(),
+                }
+            }
+
+            /// Number of vertices, or indices for indexed meshes, drawn for the mesh. Stubs
+            /// without a layout have none.
+            pub fn draw_count(&self) -> u32 {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        Mesh::$variant(inner, ..) => inner.len(),
+                    )*
+                    Mesh::Stub(layout, _) => layout.as_ref().map_or(0, |layout| {
+                        if layout.index_count > 0 {
+                            layout.index_count
+                        } else {
+                            layout.vertex_count
+                        }
+                    }),
+                }
+            }
+
+            /// Primitive topology of the mesh. The mesh builders don't tell theirs, so stubs are
+            /// triangle lists, the default of the builders.
+            pub fn primitive(&self) -> Primitive {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        Mesh::$variant(inner, ..) => inner.primitive(),
+                    )*
+                    Mesh::Stub(..) => Primitive::TriangleList,
+                }
+            }
+
+            /// Backend the mesh was built with, `None` for stubs.
+            pub fn backend(&self) -> Option<BackendVariant> {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        Mesh::$variant(..) => Some(BackendVariant::$variant),
+                    )*
+                    Mesh::Stub(..) => None,
+                }
+            }
+        }
+
+        impl Texture {
+            /// Set the palette of the indexed image the texture was loaded from.
+            pub fn with_palette(self, palette: Option<Arc<[Srgba<u8>]>>) -> Self {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        Texture::$variant(inner, _) => Texture::$variant(inner, palette),
+                    )*
+                    Texture::Stub(info, _) => Texture::Stub(info, palette),
+                }
+            }
+
+            /// Colors of the palette, for textures of the raw indices of an indexed image loaded
+            /// with `ImageFormat::with_indexed`.
+            pub fn palette(&self) -> Option<&[Srgba<u8>]> {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        Texture::$variant(_, palette) => palette.as_ref().map(|p| &p[..]),
+                    )*
+                    Texture::Stub(_, palette) => palette.as_ref().map(|p| &p[..]),
+                }
+            }
+
+            /// Read-only metadata of the texture image.
+            pub fn info(&self) -> TextureInfo {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        Texture::$variant(inner, _) => {
+                            let image = inner.image();
+                            TextureInfo {
+                                kind: image.kind(),
+                                format: image.format(),
+                                mip_levels: image.levels(),
+                            }
+                        }
+                    )*
+                    Texture::Stub(info, _) => *info,
+                }
+            }
+
+            /// Backend the texture was uploaded with, `None` for stubs.
+            pub fn backend(&self) -> Option<BackendVariant> {
+                match self {
+                    $(
+                        #[cfg(feature = $feature)]
+                        Texture::$variant(..) => Some(BackendVariant::$variant),
+                    )*
+                    Texture::Stub(..) => None,
+                }
+            }
         }
 
         /// Texture wrapper.
+        ///
+        /// Besides the rendy texture, it keeps the palette of indexed images loaded as raw
+        /// indices.
         #[derive(Debug)]
         pub enum Texture {
             $(
                 #[cfg(feature = $feature)]
                 #[doc = "Texture Variant"]
-                $variant(rendy::texture::Texture<$backend>),
+                $variant(rendy::texture::Texture<$backend>, Option<Arc<[Srgba<u8>]>>),
             )*
+            /// Texture loaded by a headless `RenderingSystem`, keeping only metadata and the
+            /// palette of its data.
+            Stub(TextureInfo, Option<Arc<[Srgba<u8>]>>),
         }
 
         $(
@@ -61,8 +303,17 @@ macro_rules! impl_backends {
             impl Backend for $backend {
                 #[inline]
                 #[allow(irrefutable_let_patterns)]
-                fn unwrap_mesh(mesh: &Mesh) -> Option<&rendy::mesh::Mesh<Self>> {
-                    if let Mesh::$variant(inner) = mesh {
+                fn unwrap_mesh(mesh: &Mesh) -> Option<&GpuMesh<Self>> {
+                    if let Mesh::$variant(inner, ..) = mesh {
+                        Some(inner)
+                    } else {
+                        None
+                    }
+                }
+                #[inline]
+                #[allow(irrefutable_let_patterns)]
+                fn unwrap_mesh_mut(mesh: &mut Mesh) -> Option<&mut GpuMesh<Self>> {
+                    if let Mesh::$variant(inner, ..) = mesh {
                         Some(inner)
                     } else {
                         None
@@ -71,19 +322,23 @@ macro_rules! impl_backends {
                 #[inline]
                 #[allow(irrefutable_let_patterns)]
                 fn unwrap_texture(texture: &Texture) -> Option<&rendy::texture::Texture<Self>> {
-                    if let Texture::$variant(inner) = texture {
+                    if let Texture::$variant(inner, _) = texture {
                         Some(inner)
                     } else {
                         None
                     }
                 }
                 #[inline]
-                fn wrap_mesh(mesh: rendy::mesh::Mesh<Self>) -> Mesh {
-                    Mesh::$variant(mesh)
+                fn wrap_mesh(mesh: GpuMesh<Self>) -> Mesh {
+                    Mesh::$variant(mesh, None, None)
                 }
                 #[inline]
                 fn wrap_texture(texture: rendy::texture::Texture<Self>) -> Texture {
-                    Texture::$variant(texture)
+                    Texture::$variant(texture, None)
+                }
+                #[inline]
+                fn variant() -> BackendVariant {
+                    BackendVariant::$variant
                 }
             }
         )*
@@ -112,16 +367,151 @@ macro_rules! impl_single_default {
 impl_backends!(
     // DirectX 12 is currently disabled because of incomplete gfx-hal support for it.
     // It will be re-enabled when it actually works.
-    // Dx12, "dx12", rendy::dx12::Backend; 
-    Metal, "metal", rendy::metal::Backend;
+    // Dx12, "dx12", rendy::dx12::Backend;
+    // In order of preference, see `BackendVariant::compiled`.
     Vulkan, "vulkan", rendy::vulkan::Backend;
+    Metal, "metal", rendy::metal::Backend;
     Empty, "empty", rendy::empty::Backend;
 );
 
+/// GPU buffers of a `Mesh`.
+#[derive(Debug)]
+pub enum GpuMesh<B: Backend> {
+    /// Mesh built once from its `MeshData`.
+    Static(rendy::mesh::Mesh<B>),
+    /// Persistent buffers of a mesh updated through `DynamicMeshes`.
+    Dynamic(DynamicMeshBuffers<B>),
+}
+
+impl<B: Backend> GpuMesh<B> {
+    /// Bind the vertex buffers matching `formats` to consecutive bindings starting at
+    /// `first_binding`, and the index buffer if the mesh has one. Returns the number of vertices,
+    /// or indices for indexed meshes, to draw.
+    pub fn bind(
+        &self,
+        first_binding: u32,
+        formats: &[VertexFormat],
+        encoder: &mut RenderPassEncoder<'_, B>,
+    ) -> Result<u32, Incompatible> {
+        match self {
+            GpuMesh::Static(mesh) => mesh.bind(first_binding, formats, encoder),
+            GpuMesh::Dynamic(buffers) => buffers.bind(first_binding, formats, encoder),
+        }
+    }
+
+    /// Bind the buffers like `bind`, and draw `instances` of the mesh.
+    pub fn bind_and_draw(
+        &self,
+        first_binding: u32,
+        formats: &[VertexFormat],
+        instances: Range<u32>,
+        encoder: &mut RenderPassEncoder<'_, B>,
+    ) -> Result<u32, Incompatible> {
+        match self {
+            GpuMesh::Static(mesh) => mesh.bind_and_draw(first_binding, formats, instances, encoder),
+            GpuMesh::Dynamic(buffers) => {
+                let count = buffers.bind(first_binding, formats, encoder)?;
+                unsafe {
+                    if buffers.index_type().is_some() {
+                        encoder.draw_indexed(0..count, 0, instances);
+                    } else {
+                        encoder.draw(0..count, instances);
+                    }
+                }
+                Ok(count)
+            }
+        }
+    }
+
+    /// Number of vertices, or indices for indexed meshes, drawn for the mesh.
+    pub fn len(&self) -> u32 {
+        match self {
+            GpuMesh::Static(mesh) => mesh.len(),
+            GpuMesh::Dynamic(buffers) => buffers.len(),
+        }
+    }
+
+    /// Returns `true` if the mesh draws nothing.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Type of the indices, `None` for meshes without index buffer.
+    pub fn index_type(&self) -> Option<IndexType> {
+        match self {
+            GpuMesh::Static(mesh) => mesh.index_type(),
+            GpuMesh::Dynamic(buffers) => buffers.index_type(),
+        }
+    }
+
+    /// Primitive topology of the mesh. Dynamic meshes are triangle lists, like the
+    /// `ProceduralMesh` they are written from.
+    pub fn primitive(&self) -> Primitive {
+        match self {
+            GpuMesh::Static(mesh) => mesh.primitive(),
+            GpuMesh::Dynamic(_) => Primitive::TriangleList,
+        }
+    }
+}
+
+impl<B: Backend> From<rendy::mesh::Mesh<B>> for GpuMesh<B> {
+    fn from(mesh: rendy::mesh::Mesh<B>) -> Self {
+        GpuMesh::Static(mesh)
+    }
+}
+
+impl<B: Backend> From<DynamicMeshBuffers<B>> for GpuMesh<B> {
+    fn from(buffers: DynamicMeshBuffers<B>) -> Self {
+        GpuMesh::Dynamic(buffers)
+    }
+}
+
+impl Mesh {
+    /// Stub of the mesh built from `data`, without a device.
+    pub fn stub(data: &MeshData) -> Self {
+        let (layout, bounds) = data.read_back();
+        Mesh::Stub(layout, bounds)
+    }
+
+    /// Whether the mesh is a stub loaded by a headless `RenderingSystem`, without GPU buffers.
+    pub fn is_stub(&self) -> bool {
+        if let Mesh::Stub(..) = self {
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Texture {
+    /// Stub of the texture uploaded from `data`, without a device.
+    ///
+    /// The texture builders don't tell the extent of their data, so stubs have an empty 2D
+    /// extent and take no memory. Their format is the required format of the data, if any.
+    pub fn stub(data: &TextureData) -> Self {
+        let info = TextureInfo {
+            kind: Kind::D2(0, 0, 1, 1),
+            format: data.1.required_format.unwrap_or(Format::Rgba8Srgb),
+            mip_levels: 1 + data.1.mip_chain.len() as u8,
+        };
+        Texture::Stub(info, data.1.palette.clone())
+    }
+
+    /// Whether the texture is a stub loaded by a headless `RenderingSystem`, without a GPU
+    /// image.
+    pub fn is_stub(&self) -> bool {
+        if let Texture::Stub(..) = self {
+            true
+        } else {
+            false
+        }
+    }
+}
+
 impl Asset for Mesh {
     const NAME: &'static str = "Mesh";
     type Data = MeshData;
-    type HandleStorage = DenseVecStorage<Handle<Self>>;
+    type HandleStorage = FlaggedStorage<Handle<Self>, DenseVecStorage<Handle<Self>>>;
 }
 
 impl Asset for Texture {
@@ -130,25 +520,391 @@ impl Asset for Texture {
     type HandleStorage = DenseVecStorage<Handle<Self>>;
 }
 
+/// Layout and size of the vertex data a `Mesh` was built from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshLayout {
+    /// Number of vertices.
+    pub vertex_count: u32,
+    /// Number of indices, `0` for meshes without index buffer.
+    pub index_count: u32,
+    /// Type of the indices, `None` for meshes without index buffer.
+    pub index_type: Option<IndexType>,
+    /// Formats of the vertex buffers, one per buffer.
+    pub vertex_formats: Vec<VertexFormat>,
+}
+
+impl MeshLayout {
+    /// Approximate size in bytes of the vertex and index buffers.
+    pub fn byte_size(&self) -> u64 {
+        let vertex_size: u64 = self
+            .vertex_formats
+            .iter()
+            .map(|f| u64::from(f.stride))
+            .sum();
+        let index_size = match self.index_type {
+            Some(IndexType::U16) => 2,
+            Some(IndexType::U32) => 4,
+            None => 0,
+        };
+        vertex_size * u64::from(self.vertex_count) + index_size * u64::from(self.index_count)
+    }
+}
+
+/// Bounds of the vertex positions of a `Mesh`, in mesh space.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshBounds {
+    /// Bounding sphere of the positions, see `mesh_util::bounding_sphere`.
+    pub sphere: BoundingSphere,
+    /// Bounding box of the positions, see `mesh_util::bounding_box`.
+    pub aabb: BoundingBox,
+}
+
+impl MeshBounds {
+    /// Bounds of `positions`, `None` if there are none.
+    pub fn of(positions: &[[f32; 3]]) -> Option<Self> {
+        Some(MeshBounds {
+            sphere: mesh_util::bounding_sphere(positions)?,
+            aabb: mesh_util::bounding_box(positions)?,
+        })
+    }
+}
+
+/// Read-only metadata of a `Texture`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureInfo {
+    /// Kind and extent of the image.
+    pub kind: Kind,
+    /// Format of the image.
+    pub format: Format,
+    /// Number of mip levels.
+    pub mip_levels: Level,
+}
+
+impl TextureInfo {
+    /// Size in bytes of all mip levels and layers, not including any padding or alignment the
+    /// driver may add.
+    pub fn byte_size(&self) -> u64 {
+        let desc = self.format.surface_desc();
+        let (block_width, block_height) = (u64::from(desc.dim.0), u64::from(desc.dim.1));
+        let layers = u64::from(self.kind.num_layers());
+        (0..self.mip_levels)
+            .map(|level| {
+                let extent = self.kind.level_extent(level);
+                let blocks_x = (u64::from(extent.width) + block_width - 1) / block_width;
+                let blocks_y = (u64::from(extent.height) + block_height - 1) / block_height;
+                blocks_x * blocks_y * u64::from(extent.depth) * u64::from(desc.bits) / 8
+            })
+            .sum::<u64>()
+            * layers
+    }
+}
+
+/// Resource with aggregate statistics of the meshes and textures loaded on the GPU, maintained
+/// by the `RenderingSystem`.
+///
+/// Mesh sizes only include meshes with a known `MeshLayout`, meshes without one, whose builder
+/// can't be read back, are counted in `meshes_without_layout`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GpuAssetStats {
+    /// Number of loaded meshes.
+    pub mesh_count: usize,
+    /// Number of loaded meshes without a known layout.
+    pub meshes_without_layout: usize,
+    /// Approximate size in bytes of all meshes with a known layout.
+    pub mesh_bytes: u64,
+    /// Number of loaded textures.
+    pub texture_count: usize,
+    /// Approximate size in bytes of all textures.
+    pub texture_bytes: u64,
+}
+
+impl GpuAssetStats {
+    /// Approximate size in bytes of all loaded meshes and textures.
+    pub fn total_bytes(&self) -> u64 {
+        self.mesh_bytes + self.texture_bytes
+    }
+
+    pub(crate) fn add_mesh(&mut self, mesh: &Mesh) {
+        self.mesh_count += 1;
+        match mesh.layout() {
+            Some(layout) => self.mesh_bytes += layout.byte_size(),
+            None => self.meshes_without_layout += 1,
+        }
+    }
+
+    pub(crate) fn add_texture(&mut self, texture: &Texture) {
+        self.texture_count += 1;
+        self.texture_bytes += texture.info().byte_size();
+    }
+
+    /// Apply the assets loaded and dropped during one frame.
+    pub(crate) fn merge(&mut self, loaded: &GpuAssetStats, dropped: &GpuAssetStats) {
+        self.mesh_count = (self.mesh_count + loaded.mesh_count).saturating_sub(dropped.mesh_count);
+        self.meshes_without_layout = (self.meshes_without_layout + loaded.meshes_without_layout)
+            .saturating_sub(dropped.meshes_without_layout);
+        self.mesh_bytes = (self.mesh_bytes + loaded.mesh_bytes).saturating_sub(dropped.mesh_bytes);
+        self.texture_count =
+            (self.texture_count + loaded.texture_count).saturating_sub(dropped.texture_count);
+        self.texture_bytes =
+            (self.texture_bytes + loaded.texture_bytes).saturating_sub(dropped.texture_bytes);
+    }
+}
+
 /// Newtype for MeshBuilder prefab usage.
+///
+/// The optional `MeshLayout` is carried over to the loaded `Mesh`, it is filled in by the
+/// mesh generators of this crate.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MeshData(
     #[serde(deserialize_with = "deserialize_data")] pub rendy::mesh::MeshBuilder<'static>,
+    #[serde(skip)] pub Option<MeshLayout>,
 );
 
+impl MeshData {
+    /// Attach the layout of the vertex data to the mesh data.
+    pub fn with_layout(mut self, layout: MeshLayout) -> Self {
+        self.1 = Some(layout);
+        self
+    }
+
+    /// Bounds of the vertex positions, read back from the builder. `None` for data without
+    /// positions in one of the vertex formats `ProceduralMesh::from_builder` can read.
+    pub fn bounds(&self) -> Option<MeshBounds> {
+        let positions = mesh_reader::read(&self.0)?.attribute::<[f32; 3]>(Attribute::Position)?;
+        MeshBounds::of(&positions)
+    }
+
+    /// Layout of the vertex data, the attached one or else one read back from the builder, as
+    /// for meshes loaded from files. `None` for data the builder doesn't let read back.
+    pub fn layout(&self) -> Option<MeshLayout> {
+        self.1
+            .clone()
+            .or_else(|| mesh_reader::read(&self.0).map(|buffers| buffers.layout()))
+    }
+
+    /// `layout` and `bounds`, reading the builder back only once.
+    pub(crate) fn read_back(&self) -> (Option<MeshLayout>, Option<MeshBounds>) {
+        let buffers = mesh_reader::read(&self.0);
+        let bounds = buffers
+            .as_ref()
+            .and_then(|buffers| buffers.attribute::<[f32; 3]>(Attribute::Position))
+            .and_then(|positions| MeshBounds::of(&positions));
+        let layout = self
+            .1
+            .clone()
+            .or_else(|| buffers.as_ref().map(|buffers| buffers.layout()));
+        (layout, bounds)
+    }
+
+    /// Generate tangents with `mesh_util::generate_tangents` for mesh data without any, such as
+    /// meshes loaded from OBJ files. Requires normals and texture coordinates.
+    ///
+    /// The vertex data is read back with `ProceduralMesh::from_builder` and rebuilt with every
+    /// attribute in its own buffer, which fails for data `from_builder` can't read.
+    pub fn with_generated_tangents(self) -> Result<Self, MeshError> {
+        let mesh = ProceduralMesh::from_builder(&self.0)?;
+        if mesh.tangents().is_some() {
+            return Ok(self);
+        }
+        mesh.with_generated_tangents().build()
+    }
+}
+
 /// Newtype for TextureBuilder prefab usage.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TextureData(pub rendy::texture::TextureBuilder<'static>);
+pub struct TextureData(
+    pub rendy::texture::TextureBuilder<'static>,
+    #[serde(skip)] pub TextureMeta,
+);
+
+/// Options of `TextureData` that are applied when the texture is uploaded, as they depend on
+/// the device.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextureMeta {
+    /// Pixel format of the data, if it may not be supported by every device. It is checked
+    /// before the texture is uploaded, so that unsupported files fail to load with a
+    /// `TextureError` instead of failing validation on the GPU.
+    pub required_format: Option<Format>,
+    /// Sampler of the texture, with its anisotropy clamped to the device limit at upload.
+    pub sampler_info: Option<SamplerInfo>,
+    /// Conversion of the data that is uploaded instead when the device can't sample the
+    /// required format.
+    pub fallback: Option<TextureFallback>,
+    /// Generate the mip chain of data with a required format after it is uploaded, if the
+    /// device can blit that format.
+    pub generate_mips: bool,
+    /// Approximate size in bytes of the uploaded data, if known, to budget texture uploads.
+    pub byte_size: Option<u64>,
+    /// Palette of the indexed image the data holds the raw indices of, kept by the texture.
+    pub palette: Option<Arc<[Srgba<u8>]>>,
+    /// Pixel data of the mip levels after the largest one, uploaded after the data of the
+    /// builder. Each level holds the pixels of all layers, in the layout of the builder data.
+    pub mip_chain: Vec<Vec<u8>>,
+}
+
+/// Converts `TextureData` to a format every device can sample, when the format of the data is
+/// optional.
+#[derive(Clone, Derivative)]
+#[derivative(Debug, PartialEq)]
+pub struct TextureFallback {
+    format: Format,
+    #[derivative(Debug = "ignore", PartialEq = "ignore")]
+    convert: Arc<dyn Fn() -> Result<TextureData, Error> + Send + Sync>,
+}
+
+impl TextureFallback {
+    /// Fallback creating data of `format` with `convert`.
+    pub fn new<F>(format: Format, convert: F) -> Self
+    where
+        F: Fn() -> Result<TextureData, Error> + Send + Sync + 'static,
+    {
+        TextureFallback {
+            format,
+            convert: Arc::new(convert),
+        }
+    }
+
+    /// Format of the converted data.
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Create the converted data.
+    pub fn convert(&self) -> Result<TextureData, Error> {
+        (self.convert)()
+    }
+}
+
+impl TextureData {
+    /// Texture data of `width` by `height` raw pixels of an uncompressed `format`, for textures
+    /// created at runtime. Register the loaded texture with `DynamicTextures` to update it later.
+    pub fn from_pixels(
+        width: u32,
+        height: u32,
+        format: Format,
+        pixels: Vec<u8>,
+        sampler_info: SamplerInfo,
+    ) -> Result<Self, crate::error::TextureError> {
+        crate::dynamic_texture::TexturePixels::new(width, height, format, pixels, sampler_info)
+            .map(Into::into)
+    }
+
+    /// Texture data of a `palette` as a row of `Rgba8Srgb` pixels, sampled with nearest
+    /// filtering. The color of index `i` is at the texel `(i, 0)`.
+    pub fn from_palette(palette: &[Srgba<u8>]) -> Result<Self, crate::error::TextureError> {
+        use rendy::hal::image::{Filter, WrapMode};
+
+        let mut pixels = Vec::with_capacity(palette.len() * 4);
+        for color in palette {
+            let (r, g, b, a) = color.into_components();
+            pixels.extend_from_slice(&[r, g, b, a]);
+        }
+        TextureData::from_pixels(
+            palette.len() as u32,
+            1,
+            Format::Rgba8Srgb,
+            pixels,
+            SamplerInfo::new(Filter::Nearest, WrapMode::Clamp),
+        )
+    }
+
+    /// Keep the `palette` of the indexed image the data holds the raw indices of, see
+    /// `Texture::palette`.
+    pub fn with_palette(mut self, palette: Vec<Srgba<u8>>) -> Self {
+        self.1.palette = Some(palette.into());
+        self
+    }
+
+    /// Require the device to support sampling textures of `format` before uploading the data.
+    pub fn with_required_format(mut self, format: Format) -> Self {
+        self.1.required_format = Some(format);
+        self
+    }
+
+    /// Set the approximate size in bytes of the uploaded data, see `TextureUploads`.
+    pub fn with_byte_size(mut self, bytes: u64) -> Self {
+        self.1.byte_size = Some(bytes);
+        self
+    }
+
+    /// Upload the smaller mip levels in `mip_chain` after the data of the builder, which becomes
+    /// the largest level, as files with precomputed mips store them. The texture has a level
+    /// per entry of the chain plus one, and no mips are generated for it.
+    pub fn with_mip_chain(mut self, mip_chain: Vec<Vec<u8>>) -> Self {
+        if mip_chain.is_empty() {
+            return self;
+        }
+        let levels = std::num::NonZeroU8::new(1 + mip_chain.len() as u8).unwrap();
+        let chain_size = mip_chain
+            .iter()
+            .map(|level| level.len() as u64)
+            .sum::<u64>();
+        self.1.byte_size = self.1.byte_size.map(|bytes| bytes + chain_size);
+        self.1.mip_chain = mip_chain;
+        self.1.generate_mips = false;
+        TextureData(
+            self.0
+                .with_mip_levels(rendy::texture::MipLevels::Levels(levels)),
+            self.1,
+        )
+    }
+
+    /// Number of mip levels the data holds, the largest one included.
+    pub fn mip_levels(&self) -> u32 {
+        1 + self.1.mip_chain.len() as u32
+    }
+
+    /// Upload the data converted by `fallback` when the device can't sample the required
+    /// format.
+    pub fn with_fallback(mut self, fallback: TextureFallback) -> Self {
+        self.1.fallback = Some(fallback);
+        self
+    }
+
+    /// Sample the texture with `sampler_info`.
+    pub fn with_sampler_info(self, sampler_info: SamplerInfo) -> Self {
+        let TextureData(builder, meta) = self;
+        TextureData(
+            builder.with_sampler_info(sampler_info.clone()),
+            TextureMeta {
+                sampler_info: Some(sampler_info),
+                ..meta
+            },
+        )
+    }
+
+    /// Generate the full mip chain of the texture after it is uploaded, by blitting each level
+    /// from the previous one on the GPU. Any size works, and sRGB textures are filtered in
+    /// linear space.
+    ///
+    /// Data that requires a format check, like block compressed DDS and KTX2 files, can't be
+    /// blitted and is returned unchanged. Data with a fallback, like grayscale and 16 bit images,
+    /// generates its mips after upload, unless the device can't blit its format.
+    pub fn with_generated_mips(mut self) -> Self {
+        match self.1.required_format {
+            Some(_) if self.1.fallback.is_some() => {
+                self.1.generate_mips = true;
+                self
+            }
+            Some(_) => self,
+            None => TextureData(
+                self.0
+                    .with_mip_levels(rendy::texture::MipLevels::GenerateAuto),
+                self.1,
+            ),
+        }
+    }
+}
 
 impl From<rendy::mesh::MeshBuilder<'static>> for MeshData {
     fn from(builder: rendy::mesh::MeshBuilder<'static>) -> Self {
-        Self(builder)
+        Self(builder, None)
     }
 }
 
 impl From<rendy::texture::TextureBuilder<'static>> for TextureData {
     fn from(builder: rendy::texture::TextureBuilder<'static>) -> Self {
-        Self(builder)
+        Self(builder, TextureMeta::default())
     }
 }
 
@@ -158,3 +914,81 @@ where
 {
     Ok(rendy::mesh::MeshBuilder::deserialize(deserializer)?.into_owned())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn texture_byte_size() {
+        let info = TextureInfo {
+            kind: Kind::D2(256, 128, 1, 1),
+            format: Format::Rgba8Srgb,
+            mip_levels: 3,
+        };
+        assert_eq!(info.byte_size(), (256 * 128 + 128 * 64 + 64 * 32) * 4);
+
+        // Block compressed formats round up to whole 4x4 blocks of 8 bytes
+        let info = TextureInfo {
+            kind: Kind::D2(6, 6, 2, 1),
+            format: Format::Bc1RgbUnorm,
+            mip_levels: 1,
+        };
+        assert_eq!(info.byte_size(), 2 * 2 * 8 * 2);
+    }
+
+    #[test]
+    fn stubs_keep_the_metadata_of_their_data() {
+        let layout = MeshLayout {
+            vertex_count: 24,
+            index_count: 36,
+            index_type: Some(IndexType::U16),
+            vertex_formats: Vec::new(),
+        };
+        let data = MeshData(rendy::mesh::MeshBuilder::new(), None).with_layout(layout.clone());
+        let mesh = Mesh::stub(&data);
+        assert!(mesh.is_stub());
+        assert_eq!(mesh.layout(), Some(&layout));
+        assert_eq!(mesh.draw_count(), 36);
+        assert_eq!(mesh.primitive(), Primitive::TriangleList);
+
+        let mut data = TextureData::from(rendy::texture::TextureBuilder::new());
+        data.1.required_format = Some(Format::Bc1RgbUnorm);
+        let texture = Texture::stub(&data);
+        assert!(texture.is_stub());
+        assert_eq!(texture.info().format, Format::Bc1RgbUnorm);
+        assert_eq!(texture.info().byte_size(), 0);
+        assert!(texture.palette().is_none());
+    }
+
+    #[test]
+    fn layouts_are_read_back_from_file_data() {
+        use rendy::mesh::{AsVertex, PosTex};
+
+        let vertices = vec![
+            PosTex {
+                position: [0.0, 0.0, 0.0].into(),
+                tex_coord: [0.0, 0.0].into(),
+            };
+            3
+        ];
+        let builder = rendy::mesh::MeshBuilder::new()
+            .with_vertices(vertices)
+            .with_indices(vec![0u16, 1, 2]);
+        let data = MeshData::from(builder);
+        let layout = MeshLayout {
+            vertex_count: 3,
+            index_count: 3,
+            index_type: Some(IndexType::U16),
+            vertex_formats: vec![PosTex::vertex()],
+        };
+        assert_eq!(data.layout(), Some(layout.clone()));
+
+        let mesh = Mesh::stub(&data);
+        assert_eq!(mesh.layout(), Some(&layout));
+        let mut stats = GpuAssetStats::default();
+        stats.add_mesh(&mesh);
+        assert_eq!(stats.meshes_without_layout, 0);
+        assert_eq!(stats.mesh_bytes, 3 * 20 + 3 * 2);
+    }
+}
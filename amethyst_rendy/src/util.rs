@@ -1,6 +1,10 @@
 //! Misc. rendy and rendering utility functions and types.
-use crate::types::{Backend, Texture};
-use amethyst_core::num::PrimInt;
+use crate::{
+    error::RenderError,
+    pipeline_cache::PipelineCache,
+    types::{Backend, Texture},
+};
+use amethyst_core::{ecs::Resources, num::PrimInt};
 use core::{
     hash::Hash,
     iter::{DoubleEndedIterator, ExactSizeIterator, FusedIterator},
@@ -11,12 +15,14 @@ use glsl_layout::*;
 use rendy::{
     factory::Factory,
     graph::render::PrepareResult,
-    hal::{self, buffer::Usage, format, pso},
+    hal::{self, adapter::PhysicalDevice, buffer::Usage, device::Device, format, pso},
     memory::MemoryUsage,
     mesh::VertexFormat,
     resource::{BufferInfo, Escape},
+    shader::{Shader, SpirvShader},
 };
 use smallvec::SmallVec;
+use std::{borrow::Cow, time::Instant};
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
@@ -43,6 +49,9 @@ pub fn usize_range(range: Range<u64>) -> Range<usize> {
 /// their use. This function will either allocate a new buffer, resize the current buffer, or perform
 /// no action depending on the needs of the function call. This can be used for dynamic buffer
 /// allocation or single static buffer allocation.
+///
+/// When the allocation fails the buffer is released, and running out of memory is reported as a
+/// `RenderError::OutOfMemory`.
 pub fn ensure_buffer<B: Backend>(
     factory: &Factory<B>,
     buffer: &mut Option<Escape<rendy::resource::Buffer<B>>>,
@@ -55,13 +64,17 @@ pub fn ensure_buffer<B: Backend>(
 
     if buffer.as_ref().map(|b| b.size()).unwrap_or(0) < min_size {
         let new_size = min_size.next_power_of_two();
-        let new_buffer = factory.create_buffer(
-            BufferInfo {
-                size: new_size,
-                usage,
-            },
-            memory_usage,
-        )?;
+        // The previous buffer is too small either way.
+        *buffer = None;
+        let new_buffer = factory
+            .create_buffer(
+                BufferInfo {
+                    size: new_size,
+                    usage,
+                },
+                memory_usage,
+            )
+            .map_err(|e| RenderError::from_allocation(e, new_size))?;
         *buffer = Some(new_buffer);
         Ok(true)
     } else {
@@ -74,8 +87,28 @@ pub fn align_size<T: AsStd140>(align: u64, array_len: usize) -> u64
 where
     T::Std140: Sized,
 {
-    let size = (core::mem::size_of::<T::Std140>() * array_len) as u64;
-    ((size + align - 1) / align) * align
+    align_offset(
+        (core::mem::size_of::<T::Std140>() * array_len) as u64,
+        align,
+    )
+}
+
+/// Round `offset` up to a multiple of `align`. An alignment of 0 leaves it as is.
+pub fn align_offset(offset: u64, align: u64) -> u64 {
+    let align = align.max(1);
+    ((offset + align - 1) / align) * align
+}
+
+/// The alignment the device requires for the offsets of the descriptors of buffers of `usage`,
+/// storage buffers or uniform buffers otherwise.
+pub fn offset_alignment<B: Backend>(factory: &Factory<B>, usage: Usage) -> u64 {
+    let limits = factory.physical().limits();
+    let align = if usage.contains(Usage::STORAGE) {
+        limits.min_storage_buffer_offset_alignment
+    } else {
+        limits.min_uniform_buffer_offset_alignment
+    };
+    align.max(1)
 }
 
 /// Helper function to create a `GraphicsShaderSet`
@@ -111,6 +144,85 @@ pub fn simple_shader_set_ext<'a, B: Backend>(
     }
 }
 
+/// Helper function to create a `GraphicsShaderSet` with a geometry stage, after checking that the
+/// device of `factory` supports geometry shaders.
+///
+/// Geometry shaders expand primitives on the GPU. The debug lines passes draw wide lines as quads
+/// with one when the device supports it, and a shadow pass could render the six faces of a
+/// cubemap in one pass with one.
+pub fn shader_set_with_geometry<'a, B: Backend>(
+    factory: &Factory<B>,
+    vertex: &'a B::ShaderModule,
+    geometry: &'a B::ShaderModule,
+    fragment: Option<&'a B::ShaderModule>,
+) -> Result<pso::GraphicsShaderSet<'a, B>, RenderError> {
+    check_shader_stages(factory, pso::ShaderStageFlags::GEOMETRY)?;
+    Ok(simple_shader_set_ext(
+        vertex,
+        fragment,
+        None,
+        None,
+        Some(geometry),
+    ))
+}
+
+/// Helper function to create a `GraphicsShaderSet` with `hull` (tessellation control) and
+/// `domain` (tessellation evaluation) stages, after checking that the device of `factory`
+/// supports tessellation shaders.
+///
+/// Tessellation refines meshes on the GPU, as for terrain detailed by distance or displacement
+/// mapping in the PBR pass. No built-in pass uses it yet.
+pub fn shader_set_with_tessellation<'a, B: Backend>(
+    factory: &Factory<B>,
+    vertex: &'a B::ShaderModule,
+    hull: &'a B::ShaderModule,
+    domain: &'a B::ShaderModule,
+    fragment: Option<&'a B::ShaderModule>,
+) -> Result<pso::GraphicsShaderSet<'a, B>, RenderError> {
+    check_shader_stages(
+        factory,
+        pso::ShaderStageFlags::HULL | pso::ShaderStageFlags::DOMAIN,
+    )?;
+    Ok(simple_shader_set_ext(
+        vertex,
+        fragment,
+        Some(hull),
+        Some(domain),
+        None,
+    ))
+}
+
+/// Check that the device of `factory` supports the shader stages `stages`, returning
+/// `RenderError::UnsupportedShaderStages` with the ones it doesn't. The vertex, fragment and
+/// compute stages are always supported.
+pub fn check_shader_stages<B: Backend>(
+    factory: &Factory<B>,
+    stages: pso::ShaderStageFlags,
+) -> Result<(), RenderError> {
+    let missing = missing_shader_stages(factory.physical().features(), stages);
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(RenderError::UnsupportedShaderStages(missing))
+    }
+}
+
+fn missing_shader_stages(
+    features: hal::Features,
+    stages: pso::ShaderStageFlags,
+) -> pso::ShaderStageFlags {
+    use pso::ShaderStageFlags as Stages;
+
+    let mut missing = Stages::empty();
+    if !features.contains(hal::Features::GEOMETRY_SHADER) {
+        missing |= stages & Stages::GEOMETRY;
+    }
+    if !features.contains(hal::Features::TESSELLATION_SHADER) {
+        missing |= stages & (Stages::HULL | Stages::DOMAIN);
+    }
+    missing
+}
+
 /// Helper function which takes an array of vertex format information and returns allocated
 /// `VertexBufferDesc` and `AttributeDesc` collections.
 pub fn vertex_desc(
@@ -164,6 +276,44 @@ pub fn push_vertex_desc(
     }
 }
 
+/// Helper function which returns the `VertexBufferDesc` and `AttributeDesc` collections of a
+/// pipeline drawing meshes with instance-rate data of format `instance`.
+///
+/// The mesh buffers are bound in the sorted order of `mesh_formats`, the one `Mesh::bind`
+/// expects, and the instance buffer in the binding after them, `mesh_formats.len()`. Attribute
+/// locations follow the order of `mesh_formats`, and the instance attributes come last.
+pub fn instanced_vertex_desc(
+    mesh_formats: &[VertexFormat],
+    instance: VertexFormat,
+) -> (Vec<pso::VertexBufferDesc>, Vec<pso::AttributeDesc>) {
+    let mut vertex_buffers = Vec::with_capacity(mesh_formats.len() + 1);
+    let mut attributes = Vec::new();
+
+    let mut locations: SmallVec<[(&VertexFormat, pso::Location); 16]> = SmallVec::new();
+    let mut location = 0;
+    for format in mesh_formats {
+        locations.push((format, location));
+        location += format.attributes.len() as pso::Location;
+    }
+    locations.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (format, location) in locations {
+        push_vertex_desc(
+            format.gfx_vertex_input_desc(pso::VertexInputRate::Vertex),
+            location,
+            &mut vertex_buffers,
+            &mut attributes,
+        );
+    }
+    push_vertex_desc(
+        instance.gfx_vertex_input_desc(pso::VertexInputRate::Instance(1)),
+        location,
+        &mut vertex_buffers,
+        &mut attributes,
+    );
+    (vertex_buffers, attributes)
+}
+
 /// Helper function to create a `DescriptorSetWrite` from arguments
 #[inline]
 pub fn desc_write<'a, B: Backend>(
@@ -179,6 +329,61 @@ pub fn desc_write<'a, B: Backend>(
     }
 }
 
+/// Helper function to create a compute pipeline from a SPIR-V compute shader, with the
+/// `PipelineCache` resource of `res` if there is one. The layout of the pipeline has the given
+/// descriptor set layouts and `push_constants` bytes of push constants.
+pub fn compute_pipeline<B: Backend>(
+    factory: &Factory<B>,
+    res: &Resources,
+    shader: &SpirvShader,
+    set_layouts: &[&B::DescriptorSetLayout],
+    push_constants: u32,
+) -> Result<(B::ComputePipeline, B::PipelineLayout), failure::Error> {
+    let push_constants = if push_constants > 0 {
+        Some((pso::ShaderStageFlags::COMPUTE, 0..push_constants))
+    } else {
+        None
+    };
+    let pipeline_layout = unsafe {
+        factory
+            .device()
+            .create_pipeline_layout(set_layouts.iter().cloned(), push_constants)
+    }?;
+    let module = unsafe { shader.module(factory) }?;
+
+    let cache = res.try_fetch::<PipelineCache<B>>();
+    let start = Instant::now();
+    let pipeline = unsafe {
+        factory.device().create_compute_pipeline(
+            &pso::ComputePipelineDesc::new(
+                pso::EntryPoint {
+                    entry: "main",
+                    module: &module,
+                    specialization: pso::Specialization::default(),
+                },
+                &pipeline_layout,
+            ),
+            cache.as_ref().map(|cache| cache.raw()),
+        )
+    };
+    if let Some(cache) = &cache {
+        cache.record(1, start.elapsed());
+    }
+    unsafe {
+        factory.destroy_shader_module(module);
+    }
+
+    match pipeline {
+        Ok(pipeline) => Ok((pipeline, pipeline_layout)),
+        Err(err) => {
+            unsafe {
+                factory.device().destroy_pipeline_layout(pipeline_layout);
+            }
+            failure::bail!(err)
+        }
+    }
+}
+
 /// Helper function to create a `CombinedImageSampler` from a supplied `Texture` and `Layout`
 #[inline]
 pub fn texture_desc<'a, B: Backend>(
@@ -356,3 +561,131 @@ impl ChangeDetection {
         }
     }
 }
+
+/// Resource with the generations of named change channels, so the work depending on a kind of
+/// change is only done again when that change occurs.
+///
+/// The rendering system marks the `SURFACE` channel when the size or the presentation of the
+/// window change, and the `GRAPH_STRUCTURE` channel when its `GraphCreator` asks for a rebuild.
+/// The graph is rebuilt when either was marked. The environment submodules mark `ENVIRONMENT`
+/// when the camera or the ambient color change and `LIGHTS` when the lights change, and upload
+/// only what changed. Any code can mark these channels to force the work depending on them, or
+/// use channels of its own.
+#[derive(Debug, Default, Clone)]
+pub struct ChangeDetectionSet {
+    generations: fnv::FnvHashMap<Cow<'static, str>, u64>,
+}
+
+impl ChangeDetectionSet {
+    /// Channel of the size and the presentation of the surface.
+    pub const SURFACE: &'static str = "surface";
+    /// Channel of the structure of the render graph.
+    pub const GRAPH_STRUCTURE: &'static str = "graph-structure";
+    /// Channel of the camera and ambient color of the environment.
+    pub const ENVIRONMENT: &'static str = "environment";
+    /// Channel of the lights of the environment.
+    pub const LIGHTS: &'static str = "lights";
+
+    /// Record a change in `channel`.
+    pub fn mark(&mut self, channel: impl Into<Cow<'static, str>>) {
+        *self.generations.entry(channel.into()).or_insert(0) += 1;
+    }
+
+    /// The number of changes recorded in `channel`.
+    pub fn generation(&self, channel: &str) -> u64 {
+        self.generations.get(channel).cloned().unwrap_or(0)
+    }
+}
+
+/// The generations of the channels of a `ChangeDetectionSet` last seen by a subscriber.
+#[derive(Debug, Clone)]
+pub struct ChangeWatch {
+    seen: SmallVec<[(Cow<'static, str>, u64); 2]>,
+}
+
+impl ChangeWatch {
+    /// Watch `channels`, with no change seen yet.
+    pub fn new<I>(channels: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<Cow<'static, str>>,
+    {
+        Self {
+            seen: channels.into_iter().map(|c| (c.into(), 0)).collect(),
+        }
+    }
+
+    /// Whether `channel` changed since `update`. Channels not watched never changed.
+    pub fn changed_channel(&self, set: &ChangeDetectionSet, channel: &str) -> bool {
+        self.seen
+            .iter()
+            .any(|(c, seen)| c == channel && set.generation(c) != *seen)
+    }
+
+    /// Whether any watched channel changed since `update`.
+    pub fn changed(&self, set: &ChangeDetectionSet) -> bool {
+        self.seen.iter().any(|(c, seen)| set.generation(c) != *seen)
+    }
+
+    /// Mark the changes of the watched channels as seen.
+    pub fn update(&mut self, set: &ChangeDetectionSet) {
+        for (c, seen) in &mut self.seen {
+            *seen = set.generation(c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_change_independently() {
+        let mut set = ChangeDetectionSet::default();
+        let mut graph = ChangeWatch::new(vec![
+            ChangeDetectionSet::SURFACE,
+            ChangeDetectionSet::GRAPH_STRUCTURE,
+        ]);
+        let mut lights = ChangeWatch::new(Some(ChangeDetectionSet::LIGHTS));
+        assert!(!graph.changed(&set));
+        assert!(!lights.changed(&set));
+
+        set.mark(ChangeDetectionSet::LIGHTS);
+        assert!(!graph.changed(&set));
+        assert!(lights.changed(&set));
+        lights.update(&set);
+        assert!(!lights.changed(&set));
+
+        set.mark(ChangeDetectionSet::SURFACE);
+        set.mark("custom");
+        assert!(graph.changed_channel(&set, ChangeDetectionSet::SURFACE));
+        assert!(!graph.changed_channel(&set, ChangeDetectionSet::GRAPH_STRUCTURE));
+        assert!(!lights.changed(&set));
+        graph.update(&set);
+        assert!(!graph.changed(&set));
+        assert_eq!(set.generation("custom"), 1);
+        assert_eq!(set.generation("unknown"), 0);
+    }
+
+    #[test]
+    fn missing_stages() {
+        use hal::Features;
+        use pso::ShaderStageFlags as Stages;
+
+        let all = Stages::VERTEX | Stages::GEOMETRY | Stages::HULL | Stages::DOMAIN;
+        assert_eq!(
+            missing_shader_stages(Features::empty(), all),
+            Stages::GEOMETRY | Stages::HULL | Stages::DOMAIN
+        );
+        assert_eq!(
+            missing_shader_stages(Features::GEOMETRY_SHADER, all),
+            Stages::HULL | Stages::DOMAIN
+        );
+        assert!(missing_shader_stages(
+            Features::GEOMETRY_SHADER | Features::TESSELLATION_SHADER,
+            all
+        )
+        .is_empty());
+        assert!(missing_shader_stages(Features::empty(), Stages::VERTEX).is_empty());
+    }
+}
@@ -0,0 +1,422 @@
+//! Validation of the graphics API usage, and labels for graphics debuggers.
+//!
+//! With `ValidationConfig::layer`, the `RenderingSystem` enables the Khronos validation layer of
+//! the Vulkan SDK when creating the instance, and forwards its messages to `log` every frame
+//! under the `vulkan_validation` target, errors as errors and warnings or performance hints as
+//! warnings. The counts and the last error are kept in the `ValidationReport` resource. A
+//! `VK_LAYER_SETTINGS_PATH` set by the user is left alone, and its messages aren't forwarded.
+//! Debug builds of the Vulkan backend already enable the validation layers on their own,
+//! logging under the `gfx_backend_vulkan` target.
+//!
+//! With `ValidationConfig::markers`, the passes of a `RenderingBundle` and their render groups
+//! are wrapped in debug marker regions, named like `main` or `main/DrawPbrDesc` for the main
+//! target and after the window for secondary targets, so captures of RenderDoc read as the
+//! plan of the graph. The images and buffers of the graph are owned by rendy, which doesn't
+//! give the mutable access needed to name them, so the regions are what carries the names of
+//! amethyst.
+use crate::{
+    rendy::{
+        command::{QueueId, RenderPassEncoder},
+        factory::Factory,
+        graph::{
+            render::{PrepareResult, RenderGroup, RenderGroupDesc},
+            GraphContext, NodeBuffer, NodeImage,
+        },
+        hal::{self, command::RawCommandBuffer},
+    },
+    types::Backend,
+};
+use amethyst_core::ecs::Resources;
+use derivative::Derivative;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// Name of the validation layer enabled by `ValidationConfig::layer`.
+pub const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Target of the validation messages logged by the `RenderingSystem`.
+pub const LOG_TARGET: &str = "vulkan_validation";
+
+/// Color of the marker regions of passes, as RGBA bytes.
+const PASS_MARKER_COLOR: u32 = 0x4080_c0ff;
+
+/// Color of the marker regions of render groups, as RGBA bytes.
+const GROUP_MARKER_COLOR: u32 = 0x80c0_40ff;
+
+/// Debugging aids of the `RenderingSystem`, set with `RenderingSystem::with_validation` and
+/// kept as a resource.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ValidationConfig {
+    /// Enable the validation layer when creating the instance. Takes effect at startup only.
+    pub layer: bool,
+    /// Wrap passes and render groups in debug marker regions. Takes effect when the graph is
+    /// rebuilt.
+    pub markers: bool,
+}
+
+impl ValidationConfig {
+    /// Enable the validation layer and the debug markers.
+    pub fn all() -> Self {
+        Self {
+            layer: true,
+            markers: true,
+        }
+    }
+}
+
+/// Resource with the validation messages forwarded to `log` since startup.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Number of errors.
+    pub errors: u64,
+    /// Number of warnings and performance hints.
+    pub warnings: u64,
+    /// The last error.
+    pub last_error: Option<String>,
+}
+
+impl ValidationReport {
+    fn record(&mut self, level: log::Level, message: &str) {
+        match level {
+            log::Level::Error => {
+                self.errors += 1;
+                self.last_error = Some(message.to_string());
+            }
+            log::Level::Warn => self.warnings += 1,
+            _ => {}
+        }
+    }
+}
+
+/// Level of a message written by the validation layer, from the flags starting it like
+/// `ERROR` or `WARN|PERF`. Lines continuing a message have none.
+fn message_level(line: &str) -> Option<log::Level> {
+    let flags = line
+        .split(|c: char| c == '(' || c == ':' || c.is_whitespace())
+        .next()
+        .unwrap_or_default();
+    let has = |flag: &str| flags.split('|').any(|f| f == flag);
+    if has("ERROR") {
+        Some(log::Level::Error)
+    } else if has("WARN") || has("PERF") {
+        Some(log::Level::Warn)
+    } else if has("INFO") {
+        Some(log::Level::Info)
+    } else if has("DEBUG") {
+        Some(log::Level::Debug)
+    } else {
+        None
+    }
+}
+
+/// Messages of the validation layer, read from the log file it writes to.
+#[derive(Debug)]
+pub(crate) struct ValidationLog {
+    path: PathBuf,
+    file: Option<File>,
+    pending: Vec<u8>,
+    level: log::Level,
+}
+
+impl ValidationLog {
+    /// Enable the validation layer of the next instance created, writing its messages to a log
+    /// file. Returns `None` when the user configured the layer.
+    pub(crate) fn enable() -> Option<Self> {
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        let layers = std::env::var("VK_INSTANCE_LAYERS").unwrap_or_default();
+        if !layers
+            .split(separator)
+            .any(|layer| layer == VALIDATION_LAYER)
+        {
+            let layers = if layers.is_empty() {
+                VALIDATION_LAYER.to_string()
+            } else {
+                format!("{}{}{}", layers, separator, VALIDATION_LAYER)
+            };
+            std::env::set_var("VK_INSTANCE_LAYERS", layers);
+        }
+
+        if std::env::var_os("VK_LAYER_SETTINGS_PATH").is_some() {
+            log::info!("VK_LAYER_SETTINGS_PATH is set, validation messages aren't forwarded");
+            return None;
+        }
+        let dir = std::env::temp_dir().join(format!("amethyst-validation-{}", std::process::id()));
+        let path = dir.join("validation.log");
+        if let Err(e) = write_settings(&dir, &path) {
+            log::warn!("Failed to configure the validation layer: {}", e);
+            return None;
+        }
+        std::env::set_var("VK_LAYER_SETTINGS_PATH", &dir);
+        Some(Self {
+            path,
+            file: None,
+            pending: Vec::new(),
+            level: log::Level::Info,
+        })
+    }
+
+    /// Warn if the layer didn't start with the instance.
+    pub(crate) fn check_started(&self) {
+        if !self.path.exists() {
+            log::warn!(
+                "The {} layer didn't start, is the Vulkan SDK installed?",
+                VALIDATION_LAYER
+            );
+        }
+    }
+
+    /// Log the messages written since the last call, and count them in `report`.
+    pub(crate) fn forward(&mut self, report: &mut ValidationReport) {
+        if self.file.is_none() {
+            self.file = File::open(&self.path).ok();
+        }
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => return,
+        };
+        if let Err(e) = file.read_to_end(&mut self.pending) {
+            log::warn!("Failed to read the validation messages: {}", e);
+            return;
+        }
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(level) = message_level(line) {
+                self.level = level;
+                report.record(level, line);
+            }
+            log::log!(target: LOG_TARGET, self.level, "{}", line);
+        }
+    }
+}
+
+impl Drop for ValidationLog {
+    fn drop(&mut self) {
+        self.file = None;
+        if let Some(dir) = self.path.parent() {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+fn write_settings(dir: &Path, log: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(
+        dir.join("vk_layer_settings.txt"),
+        format!(
+            "khronos_validation.debug_action = VK_DBG_LAYER_ACTION_LOG_MSG\n\
+             khronos_validation.report_flags = error,warn,perf\n\
+             khronos_validation.log_filename = {}\n",
+            log.display()
+        ),
+    )
+}
+
+/// Render group description drawing the group it wraps in a debug marker region, or as is
+/// without a label.
+#[derive(Debug, Clone)]
+pub struct LabeledGroupDesc<G> {
+    group: G,
+    label: Option<String>,
+}
+
+impl<G> LabeledGroupDesc<G> {
+    /// Label `group` with `label`, if any.
+    pub fn new(group: G, label: Option<String>) -> Self {
+        Self { group, label }
+    }
+}
+
+impl<B, G> RenderGroupDesc<B, Resources> for LabeledGroupDesc<G>
+where
+    B: Backend,
+    G: RenderGroupDesc<B, Resources>,
+{
+    fn buffers(&self) -> Vec<rendy::graph::BufferAccess> {
+        self.group.buffers()
+    }
+
+    fn images(&self) -> Vec<rendy::graph::ImageAccess> {
+        self.group.images()
+    }
+
+    fn depth(&self) -> bool {
+        self.group.depth()
+    }
+
+    fn colors(&self) -> usize {
+        self.group.colors()
+    }
+
+    fn build(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        aux: &Resources,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, Resources>>, failure::Error> {
+        let group = self.group.build(
+            ctx,
+            factory,
+            queue,
+            aux,
+            framebuffer_width,
+            framebuffer_height,
+            subpass,
+            buffers,
+            images,
+        )?;
+        match self.label {
+            Some(label) => Ok(Box::new(LabeledGroup { group, label })),
+            None => Ok(group),
+        }
+    }
+}
+
+#[derive(Derivative)]
+#[derivative(Debug(bound = ""))]
+struct LabeledGroup<B: Backend> {
+    #[derivative(Debug = "ignore")]
+    group: Box<dyn RenderGroup<B, Resources>>,
+    label: String,
+}
+
+impl<B: Backend> RenderGroup<B, Resources> for LabeledGroup<B> {
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        queue: QueueId,
+        index: usize,
+        subpass: hal::pass::Subpass<'_, B>,
+        aux: &Resources,
+    ) -> PrepareResult {
+        self.group.prepare(factory, queue, index, subpass, aux)
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        subpass: hal::pass::Subpass<'_, B>,
+        aux: &Resources,
+    ) {
+        unsafe {
+            encoder
+                .raw()
+                .begin_debug_marker(&self.label, GROUP_MARKER_COLOR);
+        }
+        self.group
+            .draw_inline(encoder.reborrow(), index, subpass, aux);
+        unsafe {
+            encoder.raw().end_debug_marker();
+        }
+    }
+
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, aux: &Resources) {
+        self.group.dispose(factory, aux);
+    }
+}
+
+/// Render groups opening a debug marker region as the first group of a pass, and closing it as
+/// the last one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PassMarkerDesc {
+    /// Open the region of the pass drawing the target `label`.
+    Begin(String),
+    /// Close the region opened by `Begin`.
+    End,
+}
+
+impl<B: Backend> RenderGroupDesc<B, Resources> for PassMarkerDesc {
+    fn build(
+        self,
+        _ctx: &GraphContext<B>,
+        _factory: &mut Factory<B>,
+        _queue: QueueId,
+        _aux: &Resources,
+        _framebuffer_width: u32,
+        _framebuffer_height: u32,
+        _subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, Resources>>, failure::Error> {
+        Ok(Box::new(self))
+    }
+}
+
+impl<B: Backend> RenderGroup<B, Resources> for PassMarkerDesc {
+    fn prepare(
+        &mut self,
+        _factory: &Factory<B>,
+        _queue: QueueId,
+        _index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        _aux: &Resources,
+    ) -> PrepareResult {
+        PrepareResult::DrawReuse
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        _index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        _aux: &Resources,
+    ) {
+        unsafe {
+            match self {
+                PassMarkerDesc::Begin(label) => {
+                    encoder.raw().begin_debug_marker(label, PASS_MARKER_COLOR)
+                }
+                PassMarkerDesc::End => encoder.raw().end_debug_marker(),
+            }
+        }
+    }
+
+    fn dispose(self: Box<Self>, _factory: &mut Factory<B>, _aux: &Resources) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_leveled() {
+        assert_eq!(
+            message_level("ERROR : VALIDATION - Message Id Number: 0"),
+            Some(log::Level::Error)
+        );
+        assert_eq!(
+            message_level("WARN|PERF(Validation): msg_code: 0: Pipeline barrier"),
+            Some(log::Level::Warn)
+        );
+        assert_eq!(message_level("INFO: loaded"), Some(log::Level::Info));
+        assert_eq!(message_level("    Objects: 1"), None);
+        assert_eq!(message_level("ERRORS are fine"), None);
+
+        let mut report = ValidationReport::default();
+        report.record(log::Level::Error, "image layout");
+        report.record(log::Level::Warn, "barrier");
+        report.record(log::Level::Info, "loaded");
+        assert_eq!(report.errors, 1);
+        assert_eq!(report.warnings, 1);
+        assert_eq!(
+            report.last_error.as_ref().map(String::as_str),
+            Some("image layout")
+        );
+    }
+}
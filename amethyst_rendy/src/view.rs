@@ -0,0 +1,177 @@
+//! Rendering several windows with their own cameras in one render graph.
+//!
+//! Every window gets its own images and passes in the graph. A `ViewDesc` node running before
+//! the passes of a window sets the `RenderView` resource, which the passes read the camera and
+//! screen dimensions from instead of `ActiveCamera` and `ScreenDimensions`. The nodes of the
+//! windows must be chained with dependencies, so that each `ViewDesc` runs after the passes of
+//! the previous window.
+//!
+//! The cameras of secondary windows are bound in the `WindowCameras` resource. Visibility sorting
+//! is only done for the `ActiveCamera`, so transparent objects of secondary windows may be drawn
+//! out of order.
+use crate::{
+    rendy::{
+        command::{Family, Fence, Queue, Submission, Transfer},
+        factory::Factory,
+        frame::Frames,
+        graph::{GraphContext, Node, NodeBuffer, NodeDesc, NodeImage},
+        hal,
+    },
+    types::Backend,
+};
+use amethyst_core::ecs::{Entity, Resources};
+use amethyst_window::{ScreenDimensions, SecondaryWindows};
+use fnv::FnvHashMap;
+
+/// Resource with the cameras of the secondary windows, by window name. Windows without a camera
+/// use the `ActiveCamera`.
+#[derive(Debug, Default, Clone)]
+pub struct WindowCameras {
+    cameras: FnvHashMap<String, Entity>,
+}
+
+impl WindowCameras {
+    /// Render the window of the given name with the camera of `entity`.
+    pub fn set(&mut self, window: impl Into<String>, entity: Entity) {
+        self.cameras.insert(window.into(), entity);
+    }
+
+    /// The camera of the window of the given name.
+    pub fn get(&self, window: &str) -> Option<Entity> {
+        self.cameras.get(window).cloned()
+    }
+
+    /// Render the window of the given name with the `ActiveCamera` again.
+    pub fn remove(&mut self, window: &str) -> Option<Entity> {
+        self.cameras.remove(window)
+    }
+
+    /// The windows with a camera, and their cameras.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, Entity)> + '_ {
+        self.cameras
+            .iter()
+            .map(|(window, camera)| (window.as_str(), *camera))
+    }
+}
+
+/// Resource with the view the render groups currently draw, set by `ViewDesc` nodes while the
+/// graph runs. Outside of the graph, and for the main window, it is the default view.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RenderView {
+    /// Name of the secondary window drawn, `None` for the main window.
+    pub window: Option<String>,
+    /// Camera to draw with instead of the `ActiveCamera`.
+    pub camera: Option<Entity>,
+    /// Dimensions to use instead of the `ScreenDimensions`.
+    pub dimensions: Option<ScreenDimensions>,
+}
+
+/// Render graph node setting the `RenderView` resource for the passes depending on it.
+#[derive(Debug, Default, Clone)]
+pub struct ViewDesc {
+    window: Option<String>,
+}
+
+impl ViewDesc {
+    /// View of the main window, with the `ActiveCamera`.
+    pub fn main() -> Self {
+        Self::default()
+    }
+
+    /// View of a secondary window of `SecondaryWindows`, with its camera of `WindowCameras`.
+    pub fn window(name: impl Into<String>) -> Self {
+        Self {
+            window: Some(name.into()),
+        }
+    }
+}
+
+/// Render graph node built by `ViewDesc`.
+#[derive(Debug)]
+pub struct ViewNode {
+    window: Option<String>,
+}
+
+impl<B: Backend> NodeDesc<B, Resources> for ViewDesc {
+    type Node = ViewNode;
+
+    fn build<'a>(
+        self,
+        _ctx: &GraphContext<B>,
+        _factory: &mut Factory<B>,
+        _family: &mut Family<B>,
+        _queue: usize,
+        _aux: &Resources,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Self::Node, failure::Error> {
+        assert!(buffers.is_empty());
+        assert!(images.is_empty());
+        Ok(ViewNode {
+            window: self.window,
+        })
+    }
+}
+
+impl<B: Backend> Node<B, Resources> for ViewNode {
+    type Capability = Transfer;
+
+    fn run<'a>(
+        &mut self,
+        _ctx: &GraphContext<B>,
+        _factory: &Factory<B>,
+        queue: &mut Queue<B>,
+        aux: &Resources,
+        _frames: &Frames<B>,
+        waits: &[(&'a B::Semaphore, hal::pso::PipelineStage)],
+        signals: &[&'a B::Semaphore],
+        fence: Option<&mut Fence<B>>,
+    ) {
+        let view = match &self.window {
+            None => RenderView::default(),
+            Some(name) => RenderView {
+                window: Some(name.clone()),
+                camera: aux
+                    .try_fetch::<WindowCameras>()
+                    .and_then(|cameras| cameras.get(name)),
+                dimensions: aux
+                    .try_fetch::<SecondaryWindows>()
+                    .and_then(|windows| windows.dimensions(name).cloned()),
+            },
+        };
+        *aux.fetch_mut::<RenderView>() = view;
+
+        unsafe {
+            queue.submit(
+                Some(
+                    Submission::new()
+                        .wait(waits.iter().cloned())
+                        .signal(signals.iter().cloned()),
+                ),
+                fence,
+            );
+        }
+    }
+
+    unsafe fn dispose(self, _factory: &mut Factory<B>, _aux: &Resources) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst_core::ecs::{Builder, World};
+
+    #[test]
+    fn window_cameras() {
+        let mut world = World::new();
+        let (first, second) = (world.create_entity().build(), world.create_entity().build());
+        let mut cameras = WindowCameras::default();
+        cameras.set("preview", first);
+        cameras.set("palette", second);
+        cameras.set("preview", second);
+        assert_eq!(cameras.get("preview"), Some(second));
+        assert_eq!(cameras.remove("palette"), Some(second));
+        assert_eq!(cameras.get("palette"), None);
+        assert_eq!(RenderView::default().camera, None);
+    }
+}
@@ -0,0 +1,85 @@
+//! View models, like the weapon and hands of a first person camera, drawn over the world.
+//!
+//! Entities with a `ViewModel` are left out of the `VisibilitySortingSystem` and of the main
+//! groups of the 3D passes. With `RenderBase3D::with_view_models`, they're drawn by a group of
+//! their own once the transparent geometry is drawn, after clearing the depth image, so they
+//! never clip into walls they're pushed into. Their depth is only tested against each other.
+//!
+//! They're projected with the `ViewModelProjection` resource, a perspective of its own field of
+//! view and depth range with the view and aspect ratio of the camera, so a weapon keeps its shape
+//! whatever the field of view of the world. Parent them to the camera to have them follow it.
+//! The environment is the one of the world: they're lit by the lights of the world at the
+//! position of the camera, and like all meshes, they cast no shadows on it.
+//!
+//! View models are drawn opaque whether they're `Transparent` or not, and aren't culled. `Hidden`
+//! and `HiddenPropagate` still apply.
+use crate::camera::{Camera, Projection};
+use amethyst_core::{
+    ecs::{storage::NullStorage, Component},
+    math::Matrix4,
+};
+use serde::{Deserialize, Serialize};
+
+/// Vertical field of view of the view models by default, in radians.
+pub const DEFAULT_VIEW_MODEL_FOV: f32 = std::f32::consts::FRAC_PI_4;
+
+/// Marks an entity drawn as a view model, see the module documentation.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct ViewModel;
+
+impl Component for ViewModel {
+    type Storage = NullStorage<Self>;
+}
+
+/// Resource with the projection of the view models.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ViewModelProjection {
+    /// Vertical field of view, in radians.
+    pub fov: f32,
+    /// Distance of the near plane, short enough for the view models close to the camera.
+    pub near: f32,
+    /// Distance of the far plane.
+    pub far: f32,
+}
+
+impl Default for ViewModelProjection {
+    fn default() -> Self {
+        Self {
+            fov: DEFAULT_VIEW_MODEL_FOV,
+            near: 0.01,
+            far: 100.0,
+        }
+    }
+}
+
+impl ViewModelProjection {
+    /// The projection matrix of the view models seen by `camera`, with its aspect ratio.
+    pub fn matrix(&self, camera: &Camera) -> Matrix4<f32> {
+        let camera = camera.as_matrix();
+        let aspect = (camera[(1, 1)] / camera[(0, 0)]).abs();
+        *Projection::perspective(aspect, self.fov, self.near, self.far).as_matrix()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn projection_keeps_the_camera_aspect() {
+        let camera = Camera::standard_3d(1600.0, 900.0);
+        let narrow = ViewModelProjection {
+            fov: std::f32::consts::FRAC_PI_6,
+            ..ViewModelProjection::default()
+        };
+        let wide = ViewModelProjection::default();
+
+        let matrix = narrow.matrix(&camera);
+        let aspect = (matrix[(1, 1)] / matrix[(0, 0)]).abs();
+        assert!((aspect - 1600.0 / 900.0).abs() < 1e-5);
+        // A narrower field of view magnifies the view models.
+        assert!(matrix[(0, 0)] > wide.matrix(&camera)[(0, 0)]);
+        assert!(matrix[(1, 1)].abs() > wide.matrix(&camera)[(1, 1)].abs());
+    }
+}
@@ -1,22 +1,43 @@
 //! Transparency, visibility sorting and camera centroid culling for 3D Meshes.
 use crate::{
-    camera::{ActiveCamera, Camera},
-    transparent::Transparent,
+    camera::{ActiveCamera, Camera, CullingCamera},
+    fade_in::AssetReadiness,
+    layers::RenderLayers,
+    occlusion::{Occluder, OcclusionBuffer},
+    resources::Tint,
+    spatial::StaticGrid,
+    static_instances::StaticInstanceSet,
+    transparent::{NoTintTransparency, TintTransparency, TransparencySortKey, Transparent},
+    types::{Mesh, MeshBounds},
+    view_model::ViewModel,
 };
+use amethyst_assets::{AssetStorage, Handle, PrefabData};
 use amethyst_core::{
     ecs::prelude::{
-        Component, DenseVecStorage, Entities, Entity, Join, Read, ReadExpect, ReadStorage, System,
-        Write,
+        Component, DenseVecStorage, Entities, Entity, FlaggedStorage, Join, NullStorage, ParJoin,
+        Read, ReadExpect, ReadStorage, Resources, System, SystemData, Write, WriteStorage,
+    },
+    math::{
+        self as na, convert, distance_squared, ComplexField, Matrix4, Point3, RealField, Vector3,
+        Vector4,
     },
-    math::{self as na, convert, distance_squared, Matrix4, Point3, RealField, Vector4},
     num::One,
     Float, Hidden, HiddenPropagate, Transform,
 };
+use amethyst_error::Error;
 use amethyst_window::ScreenDimensions;
 
-use hibitset::BitSet;
+use fnv::FnvHashMap;
+use hibitset::{BitSet, BitSetNot, BitSetOr};
+use rayon::{
+    iter::{IntoParallelRefIterator, ParallelExtend, ParallelIterator},
+    slice::ParallelSliceMut,
+};
 use serde::{Deserialize, Serialize};
-use std::cmp::Ordering;
+use std::{
+    cmp::Ordering,
+    time::{Duration, Instant},
+};
 
 #[cfg(feature = "profiler")]
 use thread_profiler::profile_scope;
@@ -29,20 +50,186 @@ pub struct Visibility {
     pub visible_unordered: BitSet,
     /// Visible entities that need to be drawn in the given order
     pub visible_ordered: Vec<Entity>,
+    /// Alpha factor of entities fading out near their `DrawDistance` or fading in with
+    /// `FadeInOnLoad`, always below one.
+    ///
+    /// Fading entities are drawn ordered, even when they are not `Transparent`.
+    pub fade: FnvHashMap<Entity, f32>,
+}
+
+impl Visibility {
+    /// Alpha factor the tint of `entity` is multiplied with, one unless it is fading.
+    pub fn fade(&self, entity: Entity) -> f32 {
+        self.fade.get(&entity).cloned().unwrap_or(1.0)
+    }
+}
+
+/// Where the entities went during one run of a visibility sorting system.
+///
+/// Entities are counted at the first step that rejects them, in the order the fields are listed.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct VisibilityCounts {
+    /// Entities with a `Transform` that are not hidden.
+    ///
+    /// With a static grid, static entities are counted even when hidden, and those outside of
+    /// the frustum are counted as culled by the frustum.
+    pub considered: usize,
+    /// Entities outside of the camera frustum, or behind the camera for sprites.
+    pub culled_by_frustum: usize,
+    /// Entities on `RenderLayers` the camera doesn't see.
+    pub culled_by_layers: usize,
+    /// Entities hidden behind an `Occluder`.
+    pub culled_by_occlusion: usize,
+    /// Entities beyond their `DrawDistance`.
+    pub culled_by_distance: usize,
+    /// Entities drawn in any order.
+    pub visible_opaque: usize,
+    /// Entities drawn back to front, including those fading out.
+    pub visible_transparent: usize,
+    /// Time the system took to cull and sort the entities.
+    pub duration: Duration,
+}
+
+/// Resource with the culling statistics of the last frame.
+///
+/// Insert it into the world to have `VisibilitySortingSystem` and `SpriteVisibilitySortingSystem`
+/// fill it, nothing is counted without it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct VisibilityStats {
+    /// Statistics of `VisibilitySortingSystem`.
+    pub meshes: VisibilityCounts,
+    /// Statistics of `SpriteVisibilitySortingSystem`.
+    pub sprites: VisibilityCounts,
 }
 
 /// Determine what entities are visible to the camera, and which are not. Will also sort transparent
 /// entities back to front based on distance from camera.
 ///
+/// The camera is the one of `CullingCamera` if set, of `ActiveCamera` otherwise. Entities with a
+/// `ViewModel` are left out, they're drawn over the world by the view model group of the 3D passes.
+///
+/// Every entity is tested against the camera frustum each frame by default. For scenes with many
+/// entities that don't move, `with_static_grid` makes the system keep those in a spatial grid,
+/// so only the parts of the grid intersecting the frustum are visited.
+///
+/// Frustum tests and camera distances are computed in parallel. The output doesn't depend on
+/// the number of threads.
+///
 /// Note that this should run after `Transform` has been updated for the current frame, and
 /// before rendering occurs.
 #[derive(Default, Debug)]
 pub struct VisibilitySortingSystem {
     centroids: Vec<Internals>,
     transparent: Vec<Internals>,
+    candidates: Vec<(Entity, WorldBounds)>,
+    distances: Vec<(Entity, Point3<Float>, Float, bool)>,
+    culled: BitSet,
+    static_grid: Option<StaticGrid>,
+    occlusion: Option<OcclusionBuffer>,
+    tint_transparency: Option<TintTransparency>,
+}
+
+/// Fraction of the maximum draw distance an entity culled by its `DrawDistance` has to come
+/// closer than the maximum before it is drawn again.
+pub const DRAW_DISTANCE_HYSTERESIS: f32 = 0.02;
+
+/// Limits the distance from the camera up to which an entity is drawn.
+///
+/// Entities further away than `max` are culled. When `fade` is set, the alpha of the entity's
+/// tint goes from one to zero over the last `fade` units before `max`, and the entity is drawn
+/// with the transparent entities while it fades.
+///
+/// A culled entity is only drawn again once it is `DRAW_DISTANCE_HYSTERESIS * max` closer than
+/// `max`, so entities right at the limit don't flicker.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DrawDistance {
+    /// Distance from the camera after which the entity is culled.
+    pub max: f32,
+    /// Length of the range before `max` over which the entity fades out.
+    #[serde(default)]
+    pub fade: Option<f32>,
+}
+
+impl DrawDistance {
+    /// Create a new `DrawDistance` culling the entity beyond `max`, without fading.
+    pub fn new(max: f32) -> Self {
+        Self { max, fade: None }
+    }
+
+    /// Fade the entity out over the last `fade` units before the maximum distance.
+    pub fn with_fade(mut self, fade: f32) -> Self {
+        self.fade = Some(fade);
+        self
+    }
+
+    /// Returns the alpha factor of an entity at `distance` from the camera, or `None` if it is
+    /// culled. `was_culled` tells whether the entity was culled in the previous frame.
+    pub fn factor(&self, distance: f32, was_culled: bool) -> Option<f32> {
+        let limit = if was_culled {
+            self.max * (1.0 - DRAW_DISTANCE_HYSTERESIS)
+        } else {
+            self.max
+        };
+        if distance >= limit {
+            return None;
+        }
+        match self.fade {
+            Some(fade) if fade > 0.0 => Some(((self.max - distance) / fade).min(1.0).max(0.0)),
+            _ => Some(1.0),
+        }
+    }
+}
+
+impl Component for DrawDistance {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Applies the `DrawDistance` of an entity, updating the set of culled entities.
+///
+/// Returns `None` if the entity is culled, or its alpha factor otherwise.
+pub(crate) fn apply_draw_distance(
+    culled: &mut BitSet,
+    entity: Entity,
+    draw_distance: Option<&DrawDistance>,
+    distance: f32,
+) -> Option<f32> {
+    let draw_distance = match draw_distance {
+        Some(draw_distance) => draw_distance,
+        None => {
+            culled.remove(entity.id());
+            return Some(1.0);
+        }
+    };
+    match draw_distance.factor(distance, culled.contains(entity.id())) {
+        Some(factor) => {
+            culled.remove(entity.id());
+            Some(factor)
+        }
+        None => {
+            culled.add(entity.id());
+            None
+        }
+    }
+}
+
+/// The alpha of the `Tint` of `entity` for `TintTransparency`, unless it has
+/// `NoTintTransparency`.
+pub(crate) fn tint_alpha(
+    tints: &ReadStorage<'_, Tint>,
+    no_tint_transparency: &ReadStorage<'_, NoTintTransparency>,
+    entity: Entity,
+) -> Option<f32> {
+    if no_tint_transparency.contains(entity) {
+        None
+    } else {
+        tints.get(entity).map(|tint| tint.0.alpha)
+    }
 }
 
 /// Defines a object's bounding sphere used by frustum culling.
+///
+/// Entities whose bounds can't be known on the CPU, like skydomes or meshes displaced in a
+/// shader, can opt out of culling with `NoCull` instead.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct BoundingSphere {
     /// Center of the bounding sphere
@@ -79,7 +266,368 @@ impl BoundingSphere {
 }
 
 impl Component for BoundingSphere {
-    type Storage = DenseVecStorage<Self>;
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
+}
+
+/// Marks an entity that is never culled, whatever its bounds, camera distance or `DrawDistance`.
+///
+/// Useful for skydomes, full screen quads, first person weapons, or entities whose vertices are
+/// moved in a shader beyond their `BoundingSphere`. The entity is still sorted with the other
+/// entities when it is `Transparent`, and `Hidden` and `RenderLayers` still apply.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct NoCull;
+
+impl Component for NoCull {
+    type Storage = NullStorage<Self>;
+}
+
+impl<'a> PrefabData<'a> for NoCull {
+    type SystemData = WriteStorage<'a, NoCull>;
+    type Result = ();
+
+    fn add_to_entity(
+        &self,
+        entity: Entity,
+        storage: &mut Self::SystemData,
+        _: &[Entity],
+        _: &[Entity],
+    ) -> Result<(), Error> {
+        storage.insert(entity, NoCull)?;
+        Ok(())
+    }
+}
+
+/// Defines a object's axis aligned bounding box, in local space, used by frustum culling.
+///
+/// When an entity has both a `BoundingBox` and a `BoundingSphere`, the box is used. Boxes fit
+/// long and thin meshes, like roads or walls, much tighter than spheres.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BoundingBox {
+    /// Corner of the box with the smallest coordinates.
+    pub min: Point3<Float>,
+    /// Corner of the box with the largest coordinates.
+    pub max: Point3<Float>,
+}
+
+impl Default for BoundingBox {
+    fn default() -> Self {
+        Self {
+            min: Point3::new(-Float::one(), -Float::one(), -Float::one()),
+            max: Point3::new(Float::one(), Float::one(), Float::one()),
+        }
+    }
+}
+
+impl BoundingBox {
+    /// Create a new `BoundingBox` from its minimum and maximum corners.
+    pub fn new(min: Point3<Float>, max: Point3<Float>) -> Self {
+        Self { min, max }
+    }
+
+    /// Returns the center of the box.
+    pub fn center(&self) -> Point3<Float> {
+        na::center(&self.min, &self.max)
+    }
+
+    /// Returns half the size of the box along each axis.
+    pub fn half_extents(&self) -> Vector3<Float> {
+        (self.max - self.min) * Float::from(0.5f32)
+    }
+
+    /// Returns the axis aligned box enclosing this box transformed by `matrix`.
+    pub fn transform(&self, matrix: &Matrix4<Float>) -> Self {
+        let center = matrix.transform_point(&self.center());
+        let half = self.half_extents();
+        let extents = Vector3::from_fn(|row, _| {
+            (0..3).fold(Float::from(0.0f32), |sum, col| {
+                sum + matrix[(row, col)].abs() * half[col]
+            })
+        });
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+
+    /// Returns the box enclosing both this box and `other`.
+    pub fn union(&self, other: &BoundingBox) -> Self {
+        Self {
+            min: Vector3::from_fn(|i, _| self.min[i].min(other.min[i])).into(),
+            max: Vector3::from_fn(|i, _| self.max[i].max(other.max[i])).into(),
+        }
+    }
+}
+
+impl Component for BoundingBox {
+    type Storage = FlaggedStorage<Self, DenseVecStorage<Self>>;
+}
+
+/// Bounds of an entity in world space, as tested against the camera frustum.
+#[derive(Debug, Clone)]
+pub(crate) enum WorldBounds {
+    Sphere {
+        center: Point3<Float>,
+        radius: Float,
+    },
+    Box(BoundingBox),
+}
+
+impl WorldBounds {
+    /// Bounds of an entity, preferring its box over its sphere and using a unit sphere when it
+    /// has neither.
+    pub(crate) fn new(
+        transform: &Transform,
+        sphere: Option<&BoundingSphere>,
+        aabb: Option<&BoundingBox>,
+    ) -> Self {
+        let matrix = transform.global_matrix();
+        if let Some(aabb) = aabb {
+            return WorldBounds::Box(aabb.transform(matrix));
+        }
+        let origin = Point3::origin();
+        let pos = sphere.map_or(&origin, |s| &s.center);
+        let radius = sphere.map_or(na::one(), |s| s.radius)
+            * matrix[(0, 0)].max(matrix[(1, 1)]).max(matrix[(2, 2)]);
+        WorldBounds::Sphere {
+            center: matrix.transform_point(&pos),
+            radius,
+        }
+    }
+
+    pub(crate) fn centroid(&self) -> Point3<Float> {
+        match self {
+            WorldBounds::Sphere { center, .. } => *center,
+            WorldBounds::Box(aabb) => aabb.center(),
+        }
+    }
+
+    pub(crate) fn check(&self, frustum: &Frustum) -> bool {
+        match self {
+            WorldBounds::Sphere { center, radius } => frustum.check_sphere(center, *radius),
+            WorldBounds::Box(aabb) => frustum.check_box(&aabb.min, &aabb.max),
+        }
+    }
+
+    pub(crate) fn enclosing_box(&self) -> BoundingBox {
+        match self {
+            WorldBounds::Sphere { center, radius } => {
+                let extents = Vector3::repeat(*radius);
+                BoundingBox::new(center - extents, center + extents)
+            }
+            WorldBounds::Box(aabb) => aabb.clone(),
+        }
+    }
+}
+
+/// Resource holding the bounding spheres computed for mesh assets, in mesh space.
+///
+/// The `RenderingSystem` fills it with the `Mesh::bounds` of every mesh it loads, and removes
+/// the spheres of meshes that are unloaded. Spheres are also set when meshes are generated from
+/// a `Shape`, before they are loaded, and when they are updated through `DynamicMeshes`. Meshes
+/// whose vertex formats can't be read back have no bounds; they can be registered manually, for
+/// example with the sphere returned by `ProceduralMesh::bounding_sphere`.
+#[derive(Debug, Default)]
+pub struct MeshBoundingSpheres {
+    spheres: FnvHashMap<u32, BoundingSphere>,
+    loaded: BitSet,
+}
+
+impl MeshBoundingSpheres {
+    /// Set the bounding sphere of a mesh.
+    pub fn insert(&mut self, mesh: &Handle<Mesh>, sphere: BoundingSphere) {
+        self.spheres.insert(mesh.id(), sphere);
+    }
+
+    /// Get the bounding sphere of a mesh.
+    pub fn get(&self, mesh: &Handle<Mesh>) -> Option<&BoundingSphere> {
+        self.spheres.get(&mesh.id())
+    }
+
+    /// Remove the bounding sphere of a mesh.
+    pub fn remove(&mut self, mesh: &Handle<Mesh>) -> Option<BoundingSphere> {
+        self.spheres.remove(&mesh.id())
+    }
+
+    /// Set the spheres of the loaded meshes with bounds, and remove those of the meshes
+    /// unloaded since the last call.
+    pub(crate) fn sync(&mut self, storage: &AssetStorage<Mesh>) {
+        sync_mesh_bounds(&mut self.spheres, &mut self.loaded, storage, |b| &b.sphere);
+    }
+}
+
+/// Resource holding the bounding boxes computed for mesh assets, in mesh space.
+///
+/// Filled like `MeshBoundingSpheres`, see there.
+#[derive(Debug, Default)]
+pub struct MeshBoundingBoxes {
+    boxes: FnvHashMap<u32, BoundingBox>,
+    loaded: BitSet,
+}
+
+impl MeshBoundingBoxes {
+    /// Set the bounding box of a mesh.
+    pub fn insert(&mut self, mesh: &Handle<Mesh>, aabb: BoundingBox) {
+        self.boxes.insert(mesh.id(), aabb);
+    }
+
+    /// Get the bounding box of a mesh.
+    pub fn get(&self, mesh: &Handle<Mesh>) -> Option<&BoundingBox> {
+        self.boxes.get(&mesh.id())
+    }
+
+    /// Remove the bounding box of a mesh.
+    pub fn remove(&mut self, mesh: &Handle<Mesh>) -> Option<BoundingBox> {
+        self.boxes.remove(&mesh.id())
+    }
+
+    /// Set the boxes of the loaded meshes with bounds, and remove those of the meshes unloaded
+    /// since the last call.
+    pub(crate) fn sync(&mut self, storage: &AssetStorage<Mesh>) {
+        sync_mesh_bounds(&mut self.boxes, &mut self.loaded, storage, |b| &b.aabb);
+    }
+}
+
+fn sync_mesh_bounds<T: Clone>(
+    bounds: &mut FnvHashMap<u32, T>,
+    loaded: &mut BitSet,
+    storage: &AssetStorage<Mesh>,
+    get: impl Fn(&MeshBounds) -> &T,
+) {
+    let mut now_loaded = BitSet::new();
+    for (handle, mesh) in storage.iter() {
+        now_loaded.add(handle.id());
+        if let Some(mesh_bounds) = mesh.bounds() {
+            bounds.insert(handle.id(), get(mesh_bounds).clone());
+        }
+    }
+    // Bounds inserted for meshes that are still loading are kept.
+    for id in (&*loaded, BitSetNot(&now_loaded)).join() {
+        bounds.remove(&id);
+    }
+    *loaded = now_loaded;
+}
+
+/// Copies the bounding spheres from `MeshBoundingSpheres` onto entities that have a
+/// `Handle<Mesh>` but no `BoundingSphere` of their own.
+///
+/// Spheres set by this system follow the entity's mesh when it changes. A sphere inserted or
+/// modified by the user is considered explicit and is never touched again. Scale of the
+/// entity's `Transform` is applied during culling, as for explicit spheres.
+///
+/// Note that this should run before `VisibilitySortingSystem`.
+#[derive(Default, Debug)]
+pub struct MeshBoundingSphereSystem {
+    assigned: FnvHashMap<Entity, BoundingSphere>,
+}
+
+impl MeshBoundingSphereSystem {
+    /// Create new bounding sphere system
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for MeshBoundingSphereSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, MeshBoundingSpheres>,
+        ReadStorage<'a, Handle<Mesh>>,
+        WriteStorage<'a, BoundingSphere>,
+    );
+
+    fn run(&mut self, (entities, mesh_spheres, meshes, mut bounds): Self::SystemData) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("mesh_bounding_sphere_system");
+
+        assign_mesh_bounds(
+            &mut self.assigned,
+            &entities,
+            |mesh| mesh_spheres.get(mesh),
+            &meshes,
+            &mut bounds,
+        );
+    }
+}
+
+/// Copies the bounding boxes from `MeshBoundingBoxes` onto entities that have a
+/// `Handle<Mesh>` but no `BoundingBox` of their own, following the same rules as
+/// `MeshBoundingSphereSystem`.
+///
+/// Note that this should run before `VisibilitySortingSystem`.
+#[derive(Default, Debug)]
+pub struct MeshBoundingBoxSystem {
+    assigned: FnvHashMap<Entity, BoundingBox>,
+}
+
+impl MeshBoundingBoxSystem {
+    /// Create new bounding box system
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<'a> System<'a> for MeshBoundingBoxSystem {
+    type SystemData = (
+        Entities<'a>,
+        Read<'a, MeshBoundingBoxes>,
+        ReadStorage<'a, Handle<Mesh>>,
+        WriteStorage<'a, BoundingBox>,
+    );
+
+    fn run(&mut self, (entities, mesh_boxes, meshes, mut bounds): Self::SystemData) {
+        #[cfg(feature = "profiler")]
+        profile_scope!("mesh_bounding_box_system");
+
+        assign_mesh_bounds(
+            &mut self.assigned,
+            &entities,
+            |mesh| mesh_boxes.get(mesh),
+            &meshes,
+            &mut bounds,
+        );
+    }
+}
+
+fn assign_mesh_bounds<'a, T, F>(
+    assigned: &mut FnvHashMap<Entity, T>,
+    entities: &Entities<'_>,
+    lookup: F,
+    meshes: &ReadStorage<'_, Handle<Mesh>>,
+    bounds: &mut WriteStorage<'_, T>,
+) where
+    T: Component + Clone + PartialEq + 'a,
+    F: Fn(&Handle<Mesh>) -> Option<&'a T>,
+{
+    // Forget entities that were deleted, lost their mesh or got explicit bounds
+    let mut removed = Vec::new();
+    assigned.retain(|entity, value| {
+        if !entities.is_alive(*entity) || bounds.get(*entity) != Some(value) {
+            return false;
+        }
+        if !meshes.contains(*entity) {
+            removed.push(*entity);
+            return false;
+        }
+        true
+    });
+    for entity in removed {
+        bounds.remove(entity);
+    }
+
+    for (entity, mesh) in (&**entities, meshes).join() {
+        let value = match lookup(mesh) {
+            Some(value) => value,
+            None => continue,
+        };
+        let auto = assigned.contains_key(&entity);
+        if (auto || !bounds.contains(entity)) && bounds.get(entity) != Some(value) {
+            if let Err(e) = bounds.insert(entity, value.clone()) {
+                log::error!("Failed to insert mesh bounds: {}", e);
+                continue;
+            }
+            assigned.insert(entity, value.clone());
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -88,6 +636,8 @@ struct Internals {
     transparent: bool,
     centroid: Point3<Float>,
     camera_distance: Float,
+    fade: f32,
+    sort_key: i32,
 }
 
 impl VisibilitySortingSystem {
@@ -95,6 +645,33 @@ impl VisibilitySortingSystem {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Keep entities that don't move in a grid of `cell_size` sized cells, and skip the cells
+    /// outside of the camera frustum during culling.
+    ///
+    /// An entity is considered static once its `Transform`, `BoundingSphere` and `BoundingBox`
+    /// stayed unmodified for `settle_frames` frames. Modifying any of them makes it dynamic
+    /// again.
+    pub fn with_static_grid(mut self, cell_size: f32, settle_frames: u32) -> Self {
+        self.static_grid = Some(StaticGrid::new(cell_size, settle_frames));
+        self
+    }
+
+    /// Cull entities hidden behind `Occluder`s, using a depth buffer of `width` by `height`
+    /// texels drawn on the CPU each frame.
+    ///
+    /// Only entities with a `BoundingSphere` or a `BoundingBox` are tested. Small buffers, like
+    /// 256 by 128, are usually enough and keep the cost of drawing the occluders low.
+    pub fn with_occlusion_culling(mut self, width: u32, height: u32) -> Self {
+        self.occlusion = Some(OcclusionBuffer::new(width, height));
+        self
+    }
+
+    /// Draw entities with a translucent `Tint` as transparent, as if they were `Transparent`.
+    pub fn with_tint_transparency(mut self, tint_transparency: TintTransparency) -> Self {
+        self.tint_transparency = Some(tint_transparency);
+        self
+    }
 }
 
 impl<'a> System<'a> for VisibilitySortingSystem {
@@ -104,13 +681,33 @@ impl<'a> System<'a> for VisibilitySortingSystem {
         ReadStorage<'a, Hidden>,
         ReadStorage<'a, HiddenPropagate>,
         Read<'a, ActiveCamera>,
+        Read<'a, CullingCamera>,
         ReadStorage<'a, Camera>,
         ReadStorage<'a, Transparent>,
+        ReadStorage<'a, TransparencySortKey>,
         ReadStorage<'a, Transform>,
         ReadStorage<'a, BoundingSphere>,
+        ReadStorage<'a, BoundingBox>,
+        ReadStorage<'a, RenderLayers>,
+        ReadStorage<'a, DrawDistance>,
+        ReadStorage<'a, NoCull>,
+        ReadStorage<'a, ViewModel>,
+        ReadStorage<'a, Occluder>,
+        ReadStorage<'a, Tint>,
+        ReadStorage<'a, NoTintTransparency>,
         ReadExpect<'a, ScreenDimensions>,
+        Option<Write<'a, StaticInstanceSet>>,
+        Option<Read<'a, AssetReadiness>>,
+        Option<Write<'a, VisibilityStats>>,
     );
 
+    fn setup(&mut self, res: &mut Resources) {
+        Self::SystemData::setup(res);
+        if let Some(grid) = self.static_grid.as_mut() {
+            grid.setup(res);
+        }
+    }
+
     fn run(
         &mut self,
         (
@@ -119,69 +716,198 @@ impl<'a> System<'a> for VisibilitySortingSystem {
             hidden,
             hidden_prop,
             active,
+            culling,
             camera,
             transparent,
+            sort_keys,
             transform,
             bound,
+            aabb,
+            layers,
+            draw_distances,
+            no_cull,
+            view_models,
+            occluders,
+            tints,
+            no_tint_transparency,
             dimensions,
+            mut static_instances,
+            readiness,
+            stats,
         ): Self::SystemData,
     ) {
         #[cfg(feature = "profiler")]
         profile_scope!("visibility_sorting_system");
 
+        let start = stats.as_ref().map(|_| Instant::now());
         let origin = Point3::origin();
         let defcam = Camera::standard_2d(dimensions.width(), dimensions.height());
         let identity = Transform::default();
 
         let mut camera_join = (&camera, &transform).join();
-        let (camera, camera_transform) = active
+        let (camera, camera_transform) = culling
             .entity
+            .or(active.entity)
             .and_then(|a| camera_join.get(a, &entities))
             .or_else(|| camera_join.next())
             .unwrap_or((&defcam, &identity));
 
         let camera_centroid = camera_transform.global_matrix().transform_point(&origin);
-        let frustum = Frustum::new(
-            convert::<_, Matrix4<Float>>(*camera.as_matrix())
-                * camera_transform.global_matrix().try_inverse().unwrap(),
-        );
+        let view_proj = convert::<_, Matrix4<Float>>(*camera.as_matrix())
+            * camera_transform.global_matrix().try_inverse().unwrap();
+        let frustum = Frustum::new(view_proj);
+        let camera_layers = camera.layers();
+        // The static instances culled on the GPU are culled against the same frustum.
+        if let Some(set) = static_instances.as_mut() {
+            set.set_frustum(&frustum);
+        }
+        let no_statics = BitSet::new();
+        let statics = static_instances
+            .as_ref()
+            .map_or(&no_statics, |set| set.members());
 
-        self.centroids.clear();
-        self.centroids.extend(
+        let view_proj: Matrix4<f32> = convert(view_proj);
+        if let Some(occlusion) = self.occlusion.as_mut() {
+            occlusion.clear();
+            for (entity, occluder, transform, _, _) in
+                (&*entities, &occluders, &transform, !&hidden, !&hidden_prop).join()
+            {
+                if layers
+                    .get(entity)
+                    .map_or(true, |l| l.intersects(camera_layers))
+                {
+                    let model: Matrix4<f32> = convert(*transform.global_matrix());
+                    occlusion.rasterize(&(view_proj * model), occluder);
+                }
+            }
+            occlusion.build_pyramid();
+        }
+        let occlusion = self.occlusion.as_ref();
+
+        if let Some(grid) = self.static_grid.as_mut() {
+            grid.maintain(&entities, &transform, &bound, &aabb);
+        }
+        let dynamic = self
+            .static_grid
+            .as_ref()
+            .map_or(transform.mask(), StaticGrid::dynamic);
+
+        let candidates = &mut self.candidates;
+        candidates.clear();
+        candidates.par_extend(
             (
                 &*entities,
                 &transform,
                 bound.maybe(),
+                aabb.maybe(),
+                BitSetOr(dynamic, no_cull.mask()),
                 !&hidden,
                 !&hidden_prop,
+                !&view_models,
+                BitSetNot(statics),
             )
-                .join()
-                .map(|(entity, transform, sphere, _, _)| {
-                    let pos = sphere.map_or(&origin, |s| &s.center);
-                    let matrix = transform.global_matrix();
+                .par_join()
+                .map(|(entity, transform, sphere, aabb, _, _, _, _, _)| {
+                    (entity, WorldBounds::new(transform, sphere, aabb))
+                })
+                .filter(|(entity, bounds)| no_cull.contains(*entity) || bounds.check(&frustum)),
+        );
+        if let Some(grid) = self.static_grid.as_ref() {
+            grid.query(&frustum, |entity, bounds| {
+                // Entities with `NoCull` were added above
+                if !hidden.contains(entity)
+                    && !hidden_prop.contains(entity)
+                    && !no_cull.contains(entity)
+                    && !view_models.contains(entity)
+                    && !statics.contains(entity.id())
+                {
+                    candidates.push((entity, bounds.clone()));
+                }
+            });
+        }
+
+        self.distances.clear();
+        self.distances.par_extend(
+            candidates
+                .par_iter()
+                .filter(|(entity, _)| {
+                    layers
+                        .get(*entity)
+                        .map_or(true, |l| l.intersects(camera_layers))
+                })
+                .map(|(entity, bounds)| {
+                    let centroid = bounds.centroid();
+                    let occluded = occlusion.map_or(false, |occlusion| {
+                        (bound.contains(*entity) || aabb.contains(*entity))
+                            && !no_cull.contains(*entity)
+                            && occlusion.is_occluded(&view_proj, &bounds.enclosing_box())
+                    });
                     (
-                        entity,
-                        matrix.transform_point(&pos),
-                        sphere.map_or(na::one(), |s| s.radius)
-                            * matrix[(0, 0)].max(matrix[(1, 1)]).max(matrix[(2, 2)]),
+                        *entity,
+                        centroid,
+                        distance_squared(&centroid, &camera_centroid),
+                        occluded,
                     )
-                })
-                .filter(|(_, centroid, radius)| frustum.check_sphere(centroid, *radius))
-                .map(|(entity, centroid, _)| Internals {
-                    entity,
-                    transparent: transparent.contains(entity),
-                    centroid,
-                    camera_distance: distance_squared(&centroid, &camera_centroid),
                 }),
         );
+
+        // Draw distances and tint transparency remember entities, so they are applied
+        // sequentially
+        let mut occluded = 0;
+        let culled = &mut self.culled;
+        let mut tint_transparency = self.tint_transparency.as_mut();
+        if let Some(tint_transparency) = tint_transparency.as_mut() {
+            tint_transparency.begin();
+        }
+        self.centroids.clear();
+        self.centroids.extend(self.distances.iter().filter_map(
+            |&(entity, centroid, camera_distance, is_occluded)| {
+                if is_occluded {
+                    occluded += 1;
+                    return None;
+                }
+                let fade = if no_cull.contains(entity) {
+                    1.0
+                } else {
+                    apply_draw_distance(
+                        culled,
+                        entity,
+                        draw_distances.get(entity),
+                        camera_distance.sqrt().as_f32(),
+                    )?
+                };
+                let fade = fade * readiness.as_ref().map_or(1.0, |r| r.fade(entity));
+                let tinted = tint_transparency
+                    .as_mut()
+                    .map_or(false, |tint_transparency| {
+                        tint_transparency.is_transparent(
+                            entity,
+                            tint_alpha(&tints, &no_tint_transparency, entity),
+                        )
+                    });
+                Some(Internals {
+                    entity,
+                    transparent: transparent.contains(entity) || fade < 1.0 || tinted,
+                    centroid,
+                    camera_distance,
+                    fade,
+                    sort_key: sort_keys.get(entity).map_or(0, |k| k.0),
+                })
+            },
+        ));
         self.transparent.clear();
         self.transparent
             .extend(self.centroids.iter().filter(|c| c.transparent).cloned());
 
-        self.transparent.sort_by(|a, b| {
-            b.camera_distance
-                .partial_cmp(&a.camera_distance)
-                .unwrap_or(Ordering::Equal)
+        self.transparent.par_sort_by(|a, b| {
+            a.sort_key
+                .cmp(&b.sort_key)
+                .then_with(|| {
+                    b.camera_distance
+                        .partial_cmp(&a.camera_distance)
+                        .unwrap_or(Ordering::Equal)
+                })
+                .then_with(|| a.entity.id().cmp(&b.entity.id()))
         });
 
         visibility.visible_unordered.clear();
@@ -196,6 +922,44 @@ impl<'a> System<'a> for VisibilitySortingSystem {
         visibility
             .visible_ordered
             .extend(self.transparent.iter().map(|c| c.entity));
+
+        visibility.fade.clear();
+        visibility.fade.extend(
+            self.transparent
+                .iter()
+                .filter(|c| c.fade < 1.0)
+                .map(|c| (c.entity, c.fade)),
+        );
+
+        if let (Some(mut stats), Some(start)) = (stats, start) {
+            let mut considered = (
+                &*entities,
+                &transform,
+                BitSetOr(dynamic, no_cull.mask()),
+                !&hidden,
+                !&hidden_prop,
+                !&view_models,
+                BitSetNot(statics),
+            )
+                .join()
+                .count();
+            if let Some(grid) = self.static_grid.as_ref() {
+                // Entities with `NoCull` in the grid were counted above already
+                considered +=
+                    grid.len() - (&no_cull, &transform, BitSetNot(dynamic)).join().count();
+            }
+            let (after_frustum, after_layers) = (self.candidates.len(), self.distances.len());
+            stats.meshes = VisibilityCounts {
+                considered,
+                culled_by_frustum: considered - after_frustum,
+                culled_by_layers: after_frustum - after_layers,
+                culled_by_occlusion: occluded,
+                culled_by_distance: after_layers - occluded - self.centroids.len(),
+                visible_opaque: self.centroids.len() - self.transparent.len(),
+                visible_transparent: self.transparent.len(),
+                duration: start.elapsed(),
+            };
+        }
     }
 }
 
@@ -239,4 +1003,278 @@ impl Frustum {
         }
         true
     }
+
+    /// Check if the given axis aligned box is within the Frustum
+    pub fn check_box(&self, min: &Point3<Float>, max: &Point3<Float>) -> bool {
+        for plane in &self.planes {
+            // Corner of the box furthest along the plane normal
+            let corner = Vector3::from_fn(|i, _| {
+                if plane[i] >= na::zero() {
+                    max[i]
+                } else {
+                    min[i]
+                }
+            });
+            if plane.xyz().dot(&corner) + plane.w < na::zero() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst_core::{
+        ecs::prelude::{Builder, RunNow, World},
+        math::UnitQuaternion,
+        transform::TransformSystem,
+    };
+
+    fn rotated(axis: Vector3<f32>, angle: f32, translation: Vector3<f32>) -> Matrix4<Float> {
+        let rotation = UnitQuaternion::from_axis_angle(&na::Unit::new_normalize(axis), angle);
+        convert(Matrix4::new_translation(&translation) * rotation.to_homogeneous())
+    }
+
+    /// A world with a 3D camera at `z = 10` looking at the origin, and the systems sorting it.
+    fn sorting_world() -> (World, TransformSystem, VisibilitySortingSystem) {
+        let mut world = World::new();
+        let mut transforms = TransformSystem::new();
+        let mut sorting = VisibilitySortingSystem::new();
+        RunNow::setup(&mut transforms, &mut world.res);
+        RunNow::setup(&mut sorting, &mut world.res);
+        world.add_resource(ScreenDimensions::new(640, 480, 1.0));
+        world
+            .create_entity()
+            .with(Camera::standard_3d(640.0, 480.0))
+            .with(at(0.0, 0.0, 10.0))
+            .build();
+        (world, transforms, sorting)
+    }
+
+    fn at(x: f32, y: f32, z: f32) -> Transform {
+        let mut transform = Transform::default();
+        transform.set_translation_xyz(x, y, z);
+        transform
+    }
+
+    fn sort(
+        world: &World,
+        transforms: &mut TransformSystem,
+        sorting: &mut VisibilitySortingSystem,
+    ) {
+        transforms.run_now(&world.res);
+        sorting.run_now(&world.res);
+    }
+
+    fn elongated() -> BoundingBox {
+        BoundingBox::new(
+            Point3::new((-10.0f32).into(), (-0.1f32).into(), (-0.1f32).into()),
+            Point3::new(10.0f32.into(), 0.1f32.into(), 0.1f32.into()),
+        )
+    }
+
+    #[test]
+    fn draw_distance_fade_and_hysteresis() {
+        let draw_distance = DrawDistance::new(100.0).with_fade(10.0);
+        assert_eq!(draw_distance.factor(50.0, false), Some(1.0));
+        assert_eq!(draw_distance.factor(95.0, false), Some(0.5));
+        assert_eq!(draw_distance.factor(100.0, false), None);
+
+        // Once culled, the entity stays culled until it is well inside the limit
+        assert_eq!(draw_distance.factor(99.0, true), None);
+        assert!(draw_distance.factor(97.0, true).is_some());
+
+        let mut culled = BitSet::new();
+        let mut world = World::new();
+        let entity = world.create_entity().build();
+        let sequence = [99.5, 100.0, 99.5, 99.0, 97.5, 99.5];
+        let visible: Vec<bool> = sequence
+            .iter()
+            .map(|&d| apply_draw_distance(&mut culled, entity, Some(&draw_distance), d).is_some())
+            .collect();
+        assert_eq!(visible, vec![true, false, false, false, true, true]);
+        assert_eq!(
+            apply_draw_distance(&mut culled, entity, None, 1000.0),
+            Some(1.0)
+        );
+    }
+
+    #[test]
+    fn no_cull_entities_outside_the_frustum_stay_visible() {
+        let (mut world, mut transforms, mut sorting) = sorting_world();
+        let mut sphere_at = |x, y, z| {
+            world
+                .create_entity()
+                .with(at(x, y, z))
+                .with(BoundingSphere::origin(1.0))
+                .build()
+        };
+        let inside = sphere_at(0.0, 0.0, 0.0);
+        let behind = sphere_at(0.0, 0.0, 100.0);
+        let aside = sphere_at(500.0, 0.0, 0.0);
+        let always = sphere_at(0.0, 0.0, 100.0);
+        let far = sphere_at(0.0, 0.0, -50.0);
+        for &entity in &[always, far] {
+            world
+                .write_storage::<NoCull>()
+                .insert(entity, NoCull)
+                .unwrap();
+        }
+        world
+            .write_storage::<DrawDistance>()
+            .insert(far, DrawDistance::new(10.0))
+            .unwrap();
+
+        sort(&world, &mut transforms, &mut sorting);
+        let visibility = world.read_resource::<Visibility>();
+        let visible = |entity: Entity| visibility.visible_unordered.contains(entity.id());
+        assert!(visible(inside));
+        assert!(!visible(behind) && !visible(aside));
+        // Neither the frustum nor the draw distance cull them.
+        assert!(visible(always) && visible(far));
+        assert_eq!(visibility.fade(far), 1.0);
+    }
+
+    #[test]
+    fn sort_keys_override_the_camera_distance() {
+        let (mut world, mut transforms, mut sorting) = sorting_world();
+        let mut transparent_at = |z| {
+            world
+                .create_entity()
+                .with(at(0.0, 0.0, z))
+                .with(BoundingSphere::origin(1.0))
+                .with(Transparent)
+                .build()
+        };
+        let near = transparent_at(5.0);
+        let middle = transparent_at(0.0);
+        let far = transparent_at(-5.0);
+
+        // Back to front without keys.
+        sort(&world, &mut transforms, &mut sorting);
+        assert_eq!(
+            world.read_resource::<Visibility>().visible_ordered,
+            vec![far, middle, near]
+        );
+
+        // Lower keys are drawn first whatever their distance, equal keys back to front.
+        {
+            let mut keys = world.write_storage::<TransparencySortKey>();
+            keys.insert(near, TransparencySortKey(-1)).unwrap();
+            keys.insert(far, TransparencySortKey(1)).unwrap();
+        }
+        sort(&world, &mut transforms, &mut sorting);
+        assert_eq!(
+            world.read_resource::<Visibility>().visible_ordered,
+            vec![near, middle, far]
+        );
+
+        world
+            .write_storage::<TransparencySortKey>()
+            .insert(middle, TransparencySortKey(-1))
+            .unwrap();
+        sort(&world, &mut transforms, &mut sorting);
+        assert_eq!(
+            world.read_resource::<Visibility>().visible_ordered,
+            vec![middle, near, far]
+        );
+    }
+
+    #[test]
+    fn transformed_box_encloses_corners() {
+        let aabb = elongated();
+        let matrix = rotated(
+            Vector3::new(1.0, 2.0, 3.0),
+            0.7,
+            Vector3::new(1.0, -2.0, 0.5),
+        );
+        let world = aabb.transform(&matrix);
+        for i in 0..8 {
+            let corner = Point3::new(
+                if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+            );
+            let corner = matrix.transform_point(&corner);
+            for axis in 0..3 {
+                let epsilon = Float::from(1e-4f32);
+                assert!(corner[axis] >= world.min[axis] - epsilon);
+                assert!(corner[axis] <= world.max[axis] + epsilon);
+            }
+        }
+    }
+
+    #[test]
+    fn elongated_box_near_frustum_edge() {
+        // The identity matrix gives a frustum covering -1..1 along every axis
+        let frustum = Frustum::new(Matrix4::identity());
+        let aabb = elongated();
+        let above = Vector3::new(0.0, 1.5, 0.0);
+
+        // Lying along x just above the frustum, rotating around its long axis keeps it out
+        for step in 0..8 {
+            let angle = step as f32 * std::f32::consts::FRAC_PI_4;
+            let world = aabb.transform(&rotated(Vector3::x(), angle, above));
+            assert!(!frustum.check_box(&world.min, &world.max));
+        }
+
+        // Tilting it around z makes it reach into the frustum
+        for &angle in &[
+            0.3,
+            std::f32::consts::FRAC_PI_4,
+            std::f32::consts::FRAC_PI_2,
+        ] {
+            let world = aabb.transform(&rotated(Vector3::z(), angle, above));
+            assert!(frustum.check_box(&world.min, &world.max));
+        }
+
+        // The bounding sphere of the same mesh is always considered visible
+        let sphere = BoundingSphere::origin(aabb.half_extents().norm());
+        let center = rotated(Vector3::x(), 0.0, above).transform_point(&sphere.center);
+        assert!(frustum.check_sphere(&center, sphere.radius));
+
+        // Next to the frustum, the box reaches into it only when turned towards it
+        let side = Vector3::new(0.0, 0.0, 10.5);
+        let quarter = std::f32::consts::FRAC_PI_4;
+        for &(angle, expected) in &[
+            (0.0, false),
+            (quarter, false),
+            (quarter * 2.0 - 0.05, true),
+            (quarter * 2.0, true),
+            (quarter * 3.0, false),
+        ] {
+            let world = aabb.transform(&rotated(Vector3::y(), angle, side));
+            assert_eq!(frustum.check_box(&world.min, &world.max), expected);
+        }
+    }
+
+    #[test]
+    fn mesh_bounds_follow_loaded_meshes() {
+        let pool = rayon::ThreadPoolBuilder::new().build().unwrap();
+        let mut storage = AssetStorage::<Mesh>::default();
+        let bounds = MeshBounds::of(&[[-1.0, 0.0, 0.0], [1.0, 0.0, 0.0]]).unwrap();
+        let loaded = storage.insert(Mesh::Stub(None, Some(bounds.clone())));
+        let unreadable = storage.insert(Mesh::Stub(None, None));
+
+        let mut spheres = MeshBoundingSpheres::default();
+        let mut boxes = MeshBoundingBoxes::default();
+        spheres.insert(&unreadable, BoundingSphere::origin(3.0));
+        spheres.sync(&storage);
+        boxes.sync(&storage);
+        assert_eq!(spheres.get(&loaded), Some(&bounds.sphere));
+        assert_eq!(boxes.get(&loaded), Some(&bounds.aabb));
+        assert_eq!(spheres.get(&unreadable), Some(&BoundingSphere::origin(3.0)));
+
+        // Freed meshes lose their bounds, the manual ones included
+        let (loaded_id, unreadable_id) = (loaded.id(), unreadable.id());
+        drop((loaded, unreadable));
+        storage.process(|_| unreachable!(), 0, &pool, None);
+        spheres.sync(&storage);
+        boxes.sync(&storage);
+        assert!(spheres.spheres.get(&loaded_id).is_none());
+        assert!(spheres.spheres.get(&unreadable_id).is_none());
+        assert!(boxes.boxes.is_empty());
+    }
 }
@@ -0,0 +1,186 @@
+//! Reading rendered frames back in tests with `capture_frame`.
+//!
+//! The empty backend renders nothing and returns stub frames, whose colors aren't checked.
+#![cfg(all(
+    feature = "test-support",
+    any(feature = "vulkan", feature = "metal", feature = "empty")
+))]
+
+use amethyst_assets::{AssetStorage, Handle, Loader};
+use amethyst_core::{
+    ecs::{Builder, Join, World},
+    math::Vector3,
+    Transform,
+};
+use amethyst_rendy::{
+    capture_frame, capture_scene_frames,
+    palette::Srgba,
+    rendy::texture::palette::load_from_srgba,
+    sprite::{Sprite, SpriteRender, SpriteSheet},
+    types::{DefaultBackend, Texture},
+    Camera, GoldenScene, Material,
+};
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+
+#[test]
+fn empty_frame_has_the_clear_color() {
+    let frame = capture_frame::<DefaultBackend, _>(WIDTH, HEIGHT, 3, |_, _| {}).unwrap();
+    assert_eq!((frame.width(), frame.height()), (WIDTH, HEIGHT));
+    if frame.is_stub() {
+        return;
+    }
+    assert_eq!(
+        frame.average_color(0..WIDTH, 0..HEIGHT),
+        Some([0.0, 0.0, 0.0, 1.0])
+    );
+}
+
+#[test]
+fn sprite_is_drawn_at_the_center() {
+    let frame = capture_frame::<DefaultBackend, _>(WIDTH, HEIGHT, 3, |world, progress| {
+        let mut camera = Transform::default();
+        camera.set_translation_z(10.0);
+        world
+            .create_entity()
+            .with(Camera::standard_2d(WIDTH as f32, HEIGHT as f32))
+            .with(camera)
+            .build();
+
+        let sheet = {
+            let loader = world.read_resource::<Loader>();
+            let texture = loader.load_from_data(
+                load_from_srgba(Srgba::new(1.0, 0.0, 0.0, 1.0)).into(),
+                &mut *progress,
+                &world.read_resource::<AssetStorage<Texture>>(),
+            );
+            let sheet = SpriteSheet {
+                texture,
+                sprites: vec![Sprite::from_pixel_values(
+                    1, 1, 1, 1, 0, 0, [0.0; 2], false, false,
+                )],
+                alpha_mode: Default::default(),
+            };
+            loader.load_from_data(
+                sheet,
+                &mut *progress,
+                &world.read_resource::<AssetStorage<SpriteSheet>>(),
+            )
+        };
+        let mut transform = Transform::default();
+        transform.set_scale(Vector3::new(16.0f32, 16.0, 1.0));
+        world
+            .create_entity()
+            .with(SpriteRender {
+                sprite_sheet: sheet,
+                sprite_number: 0,
+            })
+            .with(transform)
+            .build();
+    })
+    .unwrap();
+    if frame.is_stub() {
+        return;
+    }
+
+    let [red, green, blue, _] = frame.pixel(WIDTH / 2, HEIGHT / 2).unwrap();
+    assert!(red > 200 && green < 30 && blue < 30);
+    // The corners are outside of the sprite.
+    assert_eq!(frame.pixel(0, 0), Some([0, 0, 0, 255]));
+}
+
+/// Load a texture of `color` to replace one of the scene with.
+fn load_replacement(
+    world: &mut World,
+    progress: &mut amethyst_assets::ProgressCounter,
+    color: Srgba,
+) -> Handle<Texture> {
+    world.read_resource::<Loader>().load_from_data(
+        load_from_srgba(color).into(),
+        progress,
+        &world.read_resource::<AssetStorage<Texture>>(),
+    )
+}
+
+/// Swap the textures of `target` and `replacement`, like a hot reload of `target` would.
+fn swap_textures(world: &mut World, target: &Handle<Texture>, replacement: &Handle<Texture>) {
+    let mut textures = world.write_resource::<AssetStorage<Texture>>();
+    let placeholder = Texture::stub(&load_from_srgba(Srgba::new(0.0, 0.0, 0.0, 1.0)).into());
+    let old = textures.replace(target, placeholder);
+    let new = textures.replace(replacement, old);
+    textures.replace(target, new);
+}
+
+#[test]
+fn replaced_sprite_texture_is_drawn_the_next_frame() {
+    let frames = capture_scene_frames::<DefaultBackend, _, _, _>(
+        GoldenScene::Flat2D,
+        WIDTH,
+        HEIGHT,
+        2,
+        |world, progress| load_replacement(world, progress, Srgba::new(1.0, 0.0, 0.0, 1.0)),
+        |world, replacement| {
+            // The sprite at the center is green.
+            let target = {
+                let sheets = world.read_resource::<AssetStorage<SpriteSheet>>();
+                (
+                    &world.read_storage::<SpriteRender>(),
+                    &world.read_storage::<Transform>(),
+                )
+                    .join()
+                    .find(|(_, transform)| transform.translation().x.abs() < 1.0)
+                    .and_then(|(sprite, _)| sheets.get(&sprite.sprite_sheet))
+                    .map(|sheet| sheet.texture.clone())
+                    .unwrap()
+            };
+            swap_textures(world, &target, replacement);
+        },
+    )
+    .unwrap();
+    if frames[0].is_stub() {
+        return;
+    }
+
+    let [red, green, _, _] = frames[0].pixel(WIDTH / 2, HEIGHT / 2).unwrap();
+    assert!(green > red);
+    let [red, green, blue, _] = frames[1].pixel(WIDTH / 2, HEIGHT / 2).unwrap();
+    assert!(red > 200 && green < 30 && blue < 30);
+}
+
+#[test]
+fn replaced_material_texture_is_drawn_the_next_frame() {
+    let frames = capture_scene_frames::<DefaultBackend, _, _, _>(
+        GoldenScene::Flat,
+        WIDTH,
+        HEIGHT,
+        2,
+        |world, progress| load_replacement(world, progress, Srgba::new(0.0, 1.0, 0.0, 1.0)),
+        |world, replacement| {
+            // The sphere on the left is red.
+            let target = {
+                let materials = world.read_resource::<AssetStorage<Material>>();
+                (
+                    &world.read_storage::<Handle<Material>>(),
+                    &world.read_storage::<Transform>(),
+                )
+                    .join()
+                    .find(|(_, transform)| transform.translation().x < 0.0)
+                    .and_then(|(material, _)| materials.get(material))
+                    .map(|material| material.albedo.clone())
+                    .unwrap()
+            };
+            swap_textures(world, &target, replacement);
+        },
+    )
+    .unwrap();
+    if frames[0].is_stub() {
+        return;
+    }
+
+    // Draws recorded with the old descriptor set would still show the red sphere.
+    let [red, green, _, _] = frames[0].average_color(0..WIDTH / 2, 0..HEIGHT).unwrap();
+    assert!(red > green);
+    let [red, green, _, _] = frames[1].average_color(0..WIDTH / 2, 0..HEIGHT).unwrap();
+    assert!(green > red);
+}
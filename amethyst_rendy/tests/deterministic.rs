@@ -0,0 +1,190 @@
+//! What the `Deterministic` resource fixes, checked without rendering: the batches submitted by
+//! the passes and the frame time of the animations of the renderer. The order of the transparent
+//! entities of `Visibility` is deterministic in both modes.
+#![cfg(any(feature = "vulkan", feature = "metal", feature = "empty"))]
+
+use amethyst_assets::{AssetStorage, Loader};
+use amethyst_core::{
+    ecs::{Builder, Entity, RunNow, World},
+    transform::TransformSystem,
+    Time, Transform,
+};
+use amethyst_rendy::{
+    batch::{OneLevelBatch, TwoLevelBatch},
+    deterministic::Deterministic,
+    flipbook::{
+        AnimatedTexture, AnimatedTextureSystem, Flipbook, FlipbookData, FlipbookLayout,
+        PlaybackMode,
+    },
+    rendy::hal::image::{Filter, SamplerInfo, WrapMode},
+    transparent::{TransparencySortKey, Transparent},
+    visibility::{BoundingSphere, Visibility, VisibilitySortingSystem},
+    Camera,
+};
+use amethyst_window::ScreenDimensions;
+use rayon::ThreadPoolBuilder;
+use std::{sync::Arc, time::Duration};
+
+/// Keys of the batches inserted by the frames before the one checked.
+fn history() -> Vec<u32> {
+    (100..1100).collect()
+}
+
+/// Keys of the batches inserted by the frame checked.
+const KEYS: [u32; 8] = [3, 7, 1, 12, 5, 9, 2, 20];
+
+/// Primary keys of the two level batches submitted by a frame inserting `KEYS`, after frames
+/// inserting `history`.
+fn two_level_order(deterministic: Deterministic, history: &[u32]) -> Vec<u32> {
+    let mut batch = TwoLevelBatch::<u32, u32, Vec<u32>>::default();
+    for &key in history {
+        batch.insert(key, 0, Some(key));
+    }
+    batch.reset(deterministic.enabled);
+    for &key in KEYS.iter() {
+        batch.insert(key, 0, Some(key));
+    }
+    batch
+        .iter()
+        .filter_map(|(&key, mut batches)| batches.find(|(_, data)| !data.is_empty()).map(|_| key))
+        .collect()
+}
+
+/// Keys of the one level batches submitted by a frame inserting `KEYS`, after frames inserting
+/// `history`.
+fn one_level_order(deterministic: Deterministic, history: &[u32]) -> Vec<u32> {
+    let mut batch = OneLevelBatch::<u32, u32>::default();
+    for &key in history {
+        batch.insert(key, Some(key));
+    }
+    batch.reset(deterministic.enabled);
+    for &key in KEYS.iter() {
+        batch.insert(key, Some(key));
+    }
+    batch
+        .iter()
+        .filter(|(_, range)| range.start != range.end)
+        .map(|(&key, _)| key)
+        .collect()
+}
+
+#[test]
+fn batches_are_submitted_in_the_same_order_in_every_run() {
+    let fresh = two_level_order(Deterministic::enabled(), &[]);
+    assert_eq!(fresh.len(), KEYS.len());
+    assert_eq!(two_level_order(Deterministic::enabled(), &history()), fresh);
+    assert_ne!(two_level_order(Deterministic::default(), &history()), fresh);
+
+    let fresh = one_level_order(Deterministic::enabled(), &[]);
+    assert_eq!(fresh.len(), KEYS.len());
+    assert_eq!(one_level_order(Deterministic::enabled(), &history()), fresh);
+    assert_ne!(one_level_order(Deterministic::default(), &history()), fresh);
+}
+
+/// Frames shown by a flipbook of three frames of 20 ms played once, over three frames of 250 ms.
+fn flipbook_frames(deterministic: Deterministic) -> Vec<usize> {
+    let mut world = World::new();
+    let mut system = AnimatedTextureSystem::new();
+    RunNow::setup(&mut system, &mut world.res);
+    let pool = Arc::new(ThreadPoolBuilder::new().num_threads(1).build().unwrap());
+    world.add_resource(Loader::new(".", pool.clone()));
+    world.add_resource(pool);
+    world.add_resource(deterministic);
+    let mut time = Time::default();
+    time.set_delta_time(Duration::from_millis(250));
+    world.add_resource(time);
+
+    let mut data = FlipbookData::new(
+        1,
+        1,
+        FlipbookLayout::Array,
+        SamplerInfo::new(Filter::Nearest, WrapMode::Clamp),
+    );
+    for _ in 0..3 {
+        data.push_frame(&[255, 255, 255, 255], 0.02).unwrap();
+    }
+    let flipbook = world.read_resource::<Loader>().load_from_data(
+        data,
+        (),
+        &world.read_resource::<AssetStorage<Flipbook>>(),
+    );
+    let entity = world
+        .create_entity()
+        .with(AnimatedTexture::new(flipbook).with_mode(PlaybackMode::Once))
+        .build();
+
+    (0..3)
+        .map(|_| {
+            system.run_now(&world.res);
+            world.maintain();
+            world
+                .read_storage::<AnimatedTexture>()
+                .get(entity)
+                .unwrap()
+                .frame()
+        })
+        .collect()
+}
+
+#[test]
+fn animations_advance_by_the_fixed_frame_time() {
+    // A frame of 1/60 s every frame, whatever the frame time.
+    assert_eq!(flipbook_frames(Deterministic::enabled()), vec![0, 1, 2]);
+    assert_eq!(flipbook_frames(Deterministic::default()), vec![2, 2, 2]);
+}
+
+/// A transparent entity at the origin, with the same `TransparencySortKey` as the others.
+fn tied(world: &mut World) -> Entity {
+    world
+        .create_entity()
+        .with(Transform::default())
+        .with(BoundingSphere::origin(1.0))
+        .with(Transparent)
+        .with(TransparencySortKey(1))
+        .build()
+}
+
+/// Tied entities, of which the one created last reuses the id of a deleted one, in id order.
+fn tied_entities(world: &mut World) -> Vec<Entity> {
+    let mut entities = (0..4).map(|_| tied(world)).collect::<Vec<_>>();
+    world.delete_entity(entities.remove(1)).unwrap();
+    world.maintain();
+    entities.push(tied(world));
+    entities.sort_by_key(|entity| entity.id());
+    entities
+}
+
+fn sorted(deterministic: Deterministic) -> Vec<Entity> {
+    let mut world = World::new();
+    let mut transforms = TransformSystem::new();
+    let mut sorting = VisibilitySortingSystem::new();
+    RunNow::setup(&mut transforms, &mut world.res);
+    RunNow::setup(&mut sorting, &mut world.res);
+    world.add_resource(ScreenDimensions::new(640, 480, 1.0));
+    world.add_resource(deterministic);
+    let mut camera = Transform::default();
+    camera.set_translation_z(10.0);
+    world
+        .create_entity()
+        .with(Camera::standard_3d(640.0, 480.0))
+        .with(camera)
+        .build();
+    let expected = tied_entities(&mut world);
+
+    let mut frames = Vec::new();
+    for _ in 0..2 {
+        transforms.run_now(&world.res);
+        sorting.run_now(&world.res);
+        frames.push(world.read_resource::<Visibility>().visible_ordered.clone());
+    }
+    assert_eq!(frames[0], expected);
+    assert_eq!(frames[1], expected);
+    expected
+}
+
+#[test]
+fn equal_sort_keys_are_drawn_in_entity_id_order() {
+    let ordered = sorted(Deterministic::enabled());
+    assert_eq!(ordered.len(), 4);
+    assert_eq!(sorted(Deterministic::default()), ordered);
+}
@@ -0,0 +1,103 @@
+//! Golden-image checks of the passes, rendering the scenes of `GoldenScene` offscreen and
+//! comparing them with the references of `tests/fixtures/golden`.
+//!
+//! The checks run whenever the default backend starts, and pass with a message on machines
+//! without an adapter. GPUs and drivers differ in rasterization rules at the edges of triangles
+//! and lines, and in the precision of the lighting, so every pass has its own tolerance, looser
+//! for the lit passes than for the flat ones. Mismatching frames are written with their diff to
+//! `target/golden`.
+//!
+//! After an intended change to the output of a pass, record the references again with the
+//! `AMETHYST_UPDATE_GOLDEN` environment variable set and check in the new images:
+//!
+//! ```text
+//! AMETHYST_UPDATE_GOLDEN=1 cargo test -p amethyst_rendy --features "test-support vulkan" \
+//!     --test golden
+//! ```
+#![cfg(all(feature = "test-support", any(feature = "vulkan", feature = "metal")))]
+
+use amethyst_rendy::{
+    golden::{check_golden, GoldenTolerance},
+    render_golden_scene,
+    types::{Backend, DefaultBackend},
+    GoldenScene,
+};
+use std::path::Path;
+
+const WIDTH: u32 = 160;
+const HEIGHT: u32 = 120;
+
+/// How much the frame of `scene` may differ from its reference.
+fn tolerance(scene: GoldenScene) -> GoldenTolerance {
+    match scene {
+        GoldenScene::Flat2D | GoldenScene::Flat => GoldenTolerance {
+            channel: 2,
+            different_pixels: 2.0,
+        },
+        GoldenScene::Shaded => GoldenTolerance {
+            channel: 3,
+            different_pixels: 3.0,
+        },
+        // The tangents of the faces of the cube facing up and down are zero, which leaves their
+        // normals to the driver.
+        GoldenScene::Pbr => GoldenTolerance {
+            channel: 4,
+            different_pixels: 4.0,
+        },
+        // Lines are a pixel wide, so every pixel rasterized differently counts.
+        GoldenScene::DebugLines => GoldenTolerance {
+            channel: 2,
+            different_pixels: 4.0,
+        },
+    }
+}
+
+fn check_scene(scene: GoldenScene) {
+    let backend = DefaultBackend::variant();
+    if !backend.starts() {
+        eprintln!(
+            "Skipping the golden image of {}: the {} backend has no adapter",
+            scene.name(),
+            backend
+        );
+        return;
+    }
+
+    let frame = render_golden_scene::<DefaultBackend>(scene, WIDTH, HEIGHT).unwrap();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let diff = check_golden(
+        &frame,
+        scene.name(),
+        &dir.join("tests").join("fixtures").join("golden"),
+        &dir.join("..").join("target").join("golden"),
+        &tolerance(scene),
+    );
+    if let Err(e) = diff {
+        panic!("{}", e);
+    }
+}
+
+#[test]
+fn flat2d_matches_golden() {
+    check_scene(GoldenScene::Flat2D);
+}
+
+#[test]
+fn flat_matches_golden() {
+    check_scene(GoldenScene::Flat);
+}
+
+#[test]
+fn shaded_matches_golden() {
+    check_scene(GoldenScene::Shaded);
+}
+
+#[test]
+fn pbr_matches_golden() {
+    check_scene(GoldenScene::Pbr);
+}
+
+#[test]
+fn debug_lines_matches_golden() {
+    check_scene(GoldenScene::DebugLines);
+}
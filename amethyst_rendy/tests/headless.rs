@@ -0,0 +1,178 @@
+//! Running the render systems headless, without a GPU, like on a dedicated server.
+#![cfg(any(feature = "vulkan", feature = "metal", feature = "empty"))]
+
+use std::sync::Arc;
+
+use amethyst_assets::{AssetStorage, Handle, Loader, ProgressCounter};
+use amethyst_core::{
+    bundle::SystemBundle,
+    ecs::{Builder, DispatcherBuilder, World},
+    math::{Matrix4, Point3},
+    Transform, TransformBundle,
+};
+use amethyst_rendy::{
+    debug_drawing::DebugLinesComponent,
+    light::{Light, PointLight},
+    palette::Srgba,
+    rendy::{
+        mesh::{Normal, Position, TexCoord},
+        texture::palette::load_from_srgba,
+    },
+    resources::Tint,
+    shape::Shape,
+    skinning::JointTransforms,
+    sprite::{Sprite, SpriteRender, SpriteSheet},
+    sprite_visibility::SpriteVisibility,
+    types::{DefaultBackend, Mesh, Texture},
+    visibility::Visibility,
+    Camera, Material, MaterialDefaults, RenderDebugLines, RenderFlat2D, RenderShaded3D,
+    RenderingBundle, StaticCulling, StaticInstanceSet, StaticInstances, Transparent, ViewModel,
+};
+
+const FRAMES: u32 = 100;
+
+#[test]
+fn render_components_run_headless() {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+    let mut world = World::new();
+    world.add_resource(Loader::new(".", Arc::new(pool)));
+
+    let mut builder = DispatcherBuilder::new();
+    TransformBundle::new().build(&mut builder).unwrap();
+    RenderingBundle::<DefaultBackend>::new()
+        .with_plugin(RenderFlat2D::default())
+        .with_plugin(
+            RenderShaded3D::default()
+                .with_view_models()
+                .with_static_instances(StaticCulling::default()),
+        )
+        .with_plugin(RenderDebugLines::default())
+        .with_headless()
+        .build(&mut builder)
+        .unwrap();
+    let mut dispatcher = builder.build();
+    dispatcher.setup(&mut world.res);
+
+    let mut progress = ProgressCounter::new();
+    let mut camera = Transform::default();
+    camera.set_translation_z(10.0);
+    world
+        .create_entity()
+        .with(Camera::standard_3d(640.0, 480.0))
+        .with(camera)
+        .build();
+
+    let defaults = world.read_resource::<MaterialDefaults>().0.clone();
+    let (mesh, material, sheet): (Handle<Mesh>, Handle<Material>, Handle<SpriteSheet>) = {
+        let loader = world.read_resource::<Loader>();
+        let mesh = loader.load_from_data(
+            Shape::Cube
+                .generate::<(Vec<Position>, Vec<Normal>, Vec<TexCoord>)>(None)
+                .into(),
+            &mut progress,
+            &world.read_resource::<AssetStorage<Mesh>>(),
+        );
+        let texture = loader.load_from_data(
+            load_from_srgba(Srgba::new(1.0, 0.0, 0.0, 1.0)).into(),
+            &mut progress,
+            &world.read_resource::<AssetStorage<Texture>>(),
+        );
+        let material = loader.load_from_data(
+            Material {
+                albedo: texture.clone(),
+                ..defaults
+            },
+            &mut progress,
+            &world.read_resource::<AssetStorage<Material>>(),
+        );
+        let sheet = loader.load_from_data(
+            SpriteSheet {
+                texture,
+                sprites: vec![Sprite::from_pixel_values(
+                    1, 1, 1, 1, 0, 0, [0.0; 2], false, false,
+                )],
+                alpha_mode: Default::default(),
+            },
+            &mut progress,
+            &world.read_resource::<AssetStorage<SpriteSheet>>(),
+        );
+        (mesh, material, sheet)
+    };
+
+    let cube = world
+        .create_entity()
+        .with(mesh.clone())
+        .with(material.clone())
+        .with(Transform::default())
+        .build();
+    let view_model = world
+        .create_entity()
+        .with(mesh.clone())
+        .with(material.clone())
+        .with(ViewModel)
+        .with(Transform::default())
+        .build();
+    let grass = world
+        .create_entity()
+        .with(mesh.clone())
+        .with(material.clone())
+        .with(StaticInstances)
+        .with(Transform::default())
+        .build();
+    let skinned = world
+        .create_entity()
+        .with(mesh.clone())
+        .with(material)
+        .with(Tint(Srgba::new(1.0, 1.0, 1.0, 0.5)))
+        .with(Transparent)
+        .with(JointTransforms {
+            skin: cube,
+            matrices: vec![Matrix4::identity(); 4],
+        })
+        .with(Transform::default())
+        .build();
+    let sprite = world
+        .create_entity()
+        .with(SpriteRender {
+            sprite_sheet: sheet,
+            sprite_number: 0,
+        })
+        .with(Transform::default())
+        .build();
+    let light: Light = PointLight::default().into();
+    world
+        .create_entity()
+        .with(light)
+        .with(Transform::default())
+        .build();
+    let mut lines = DebugLinesComponent::new();
+    lines.add_line(
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(1.0, 1.0, 0.0),
+        Srgba::new(0.2, 0.4, 1.0, 1.0),
+    );
+    world.create_entity().with(lines).build();
+
+    for _ in 0..FRAMES {
+        dispatcher.dispatch(&world.res);
+        world.maintain();
+    }
+
+    assert!(progress.is_complete());
+    assert_eq!(progress.num_failed(), 0);
+    let meshes = world.read_resource::<AssetStorage<Mesh>>();
+    assert!(meshes.get(&mesh).unwrap().is_stub());
+
+    let visibility = world.read_resource::<Visibility>();
+    assert!(visibility.visible_unordered.contains(cube.id()));
+    assert!(!visibility.visible_unordered.contains(view_model.id()));
+    // Without a GPU, the static instances are culled and drawn like the other meshes.
+    assert!(visibility.visible_unordered.contains(grass.id()));
+    assert!(!world.read_resource::<StaticInstanceSet>().contains(grass));
+    assert!(visibility.visible_ordered.contains(&skinned));
+    let sprites = world.read_resource::<SpriteVisibility>();
+    assert!(sprites.visible_unordered.contains(sprite.id()));
+}
@@ -5,7 +5,8 @@ use amethyst::{
     assets::{AssetStorage, Handle, Loader},
     ecs::prelude::*,
     renderer::{
-        loaders::load_from_srgba, palette::Srgba, Sprite, SpriteRender, SpriteSheet, Texture,
+        loaders::load_from_srgba, palette::Srgba, sprite::AlphaMode, Sprite, SpriteRender,
+        SpriteSheet, Texture,
     },
 };
 
@@ -99,6 +100,7 @@ impl SpriteRenderAnimationFixture {
                 offsets: [5.; 2],
                 tex_coords: [0.0, 1.0, 0.0, 1.0].into(),
             }],
+            alpha_mode: AlphaMode::default(),
         }
     }
 }
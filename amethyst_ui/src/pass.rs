@@ -10,7 +10,7 @@ use amethyst_core::{
 use amethyst_rendy::{
     batch::OrderedOneLevelBatch,
     palette,
-    pipeline::{PipelineDescBuilder, PipelinesBuilder},
+    pipeline::{self, PipelineDescBuilder, PipelinesBuilder},
     rendy::{
         command::{QueueId, RenderPassEncoder},
         factory::Factory,
@@ -25,10 +25,11 @@ use amethyst_rendy::{
             pso::{self, ShaderStageFlags},
         },
         mesh::{AsVertex, VertexFormat},
-        shader::{Shader, SpirvShader},
+        shader::SpirvShader,
         texture::palette::load_from_srgba,
     },
     resources::Tint,
+    shader_reload::{self, shader, ShaderWatch},
     simple_shader_set,
     submodules::{DynamicUniform, DynamicVertexBuffer, TextureId, TextureSub},
     types::{Backend, Texture},
@@ -70,6 +71,8 @@ struct UiViewArgs {
     inverse_window_size: vec2,
 }
 
+const SHADER_NAMES: [&str; 2] = ["ui.vert", "ui.frag"];
+
 lazy_static::lazy_static! {
     static ref UI_VERTEX: SpirvShader = SpirvShader::new(
         include_bytes!("../compiled/ui.vert.spv").to_vec(),
@@ -117,6 +120,7 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawUiDesc {
 
         let (pipeline, pipeline_layout) = build_ui_pipeline(
             factory,
+            resources,
             subpass,
             framebuffer_width,
             framebuffer_height,
@@ -141,6 +145,8 @@ impl<B: Backend> RenderGroupDesc<B, Resources> for DrawUiDesc {
             cached_draw_order: Default::default(),
             batches: Default::default(),
             white_tex,
+            framebuffer_size: (framebuffer_width, framebuffer_height),
+            shaders: ShaderWatch::new(resources, &SHADER_NAMES),
         }))
     }
 }
@@ -157,6 +163,8 @@ pub struct DrawUi<B: Backend> {
     change: ChangeDetection,
     cached_draw_order: CachedDrawOrder,
     white_tex: Handle<Texture>,
+    framebuffer_size: (u32, u32),
+    shaders: ShaderWatch,
 }
 
 #[derive(Clone, Debug, Derivative)]
@@ -172,7 +180,7 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawUi<B> {
         factory: &Factory<B>,
         _queue: QueueId,
         index: usize,
-        _subpass: hal::pass::Subpass<'_, B>,
+        subpass: hal::pass::Subpass<'_, B>,
         resources: &Resources,
     ) -> PrepareResult {
         #[cfg(feature = "profiler")]
@@ -373,19 +381,20 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawUi<B> {
             }
         }
 
-        self.textures.maintain(factory, resources);
+        changed = self.textures.maintain(factory, resources) || changed;
         changed = changed || self.batches.changed();
 
         {
             #[cfg(feature = "profiler")]
             profile_scope!("write");
 
-            self.vertex.write(
+            changed = self.vertex.write(
                 factory,
                 index,
                 self.batches.count() as u64,
                 Some(self.batches.data()),
-            );
+            ) || changed;
+            self.vertex.report(index, resources);
 
             let view_args = UiViewArgs {
                 inverse_window_size: [
@@ -397,6 +406,32 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawUi<B> {
             changed = self.env.write(factory, index, view_args.std140()) || changed;
         }
 
+        if self.shaders.changed(resources) {
+            let (framebuffer_width, framebuffer_height) = self.framebuffer_size;
+            let built = build_ui_pipeline(
+                factory,
+                resources,
+                subpass,
+                framebuffer_width,
+                framebuffer_height,
+                vec![self.env.raw_layout(), self.textures.raw_layout()],
+            );
+            if let Some((pipeline, pipeline_layout)) =
+                shader_reload::rebuilt(factory, "DrawUi", built)
+            {
+                unsafe {
+                    let device = factory.device();
+                    device
+                        .destroy_graphics_pipeline(std::mem::replace(&mut self.pipeline, pipeline));
+                    device.destroy_pipeline_layout(std::mem::replace(
+                        &mut self.pipeline_layout,
+                        pipeline_layout,
+                    ));
+                }
+                changed = true;
+            }
+        }
+
         self.change.prepare_result(index, changed)
     }
 
@@ -436,6 +471,7 @@ impl<B: Backend> RenderGroup<B, Resources> for DrawUi<B> {
 
 fn build_ui_pipeline<B: Backend>(
     factory: &Factory<B>,
+    aux: &Resources,
     subpass: hal::pass::Subpass<'_, B>,
     framebuffer_width: u32,
     framebuffer_height: u32,
@@ -447,13 +483,17 @@ fn build_ui_pipeline<B: Backend>(
             .create_pipeline_layout(layouts, None as Option<(_, _)>)
     }?;
 
-    let shader_vertex = unsafe { UI_VERTEX.module(factory).unwrap() };
-    let shader_fragment = unsafe { UI_FRAGMENT.module(factory).unwrap() };
+    let vertex = shader(aux, SHADER_NAMES[0], &UI_VERTEX);
+    let fragment = shader(aux, SHADER_NAMES[1], &UI_FRAGMENT);
+    let shader_vertex = unsafe { pipeline::shader_module(factory, "DrawUi", &vertex) }?;
+    let shader_fragment = unsafe { pipeline::shader_module(factory, "DrawUi", &fragment) }?;
 
     let pipes = PipelinesBuilder::new()
+        .with_group("DrawUi")
         .with_pipeline(
             PipelineDescBuilder::new()
                 .with_vertex_desc(&[(UiArgs::vertex(), pso::VertexInputRate::Instance(1))])
+                .with_vertex_inputs(pipeline::vertex_inputs(&vertex))
                 .with_input_assembler(pso::InputAssemblerDesc::new(hal::Primitive::TriangleStrip))
                 .with_shaders(simple_shader_set(&shader_vertex, Some(&shader_fragment)))
                 .with_layout(&pipeline_layout)
@@ -464,7 +504,7 @@ fn build_ui_pipeline<B: Backend>(
                     pso::BlendState::ALPHA,
                 )]),
         )
-        .build(factory, None);
+        .build_cached(factory, aux);
 
     unsafe {
         factory.destroy_shader_module(shader_vertex);
@@ -13,12 +13,23 @@ pub const SCREEN_HEIGHT: u32 = 600;
 
 pub struct WindowBundle {
     config: DisplayConfig,
+    secondary: Vec<(String, DisplayConfig)>,
 }
 
 impl WindowBundle {
     /// Builds a new window bundle from a loaded `DisplayConfig`.
     pub fn from_config(config: DisplayConfig) -> Self {
-        WindowBundle { config }
+        WindowBundle {
+            config,
+            secondary: Vec::new(),
+        }
+    }
+
+    /// Also open a secondary window with its own configuration, available by name from the
+    /// `SecondaryWindows` resource.
+    pub fn with_secondary_window(mut self, name: impl Into<String>, config: DisplayConfig) -> Self {
+        self.secondary.push((name.into(), config));
+        self
     }
 
     /// Builds a new window bundle by loading the `DisplayConfig` from `path`.
@@ -47,11 +58,11 @@ impl WindowBundle {
 impl<'a, 'b> SystemBundle<'a, 'b> for WindowBundle {
     fn build(self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
         let event_loop = EventsLoop::new();
-        builder.add(
-            WindowSystem::from_config(&event_loop, self.config),
-            "window",
-            &[],
-        );
+        let mut window_system = WindowSystem::from_config(&event_loop, self.config);
+        for (name, config) in self.secondary {
+            window_system = window_system.with_secondary_window(&event_loop, name, config);
+        }
+        builder.add(window_system, "window", &[]);
         builder.add_thread_local(EventsLoopSystem::new(event_loop));
         Ok(())
     }
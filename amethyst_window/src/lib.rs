@@ -10,7 +10,7 @@ pub use crate::{
     bundle::WindowBundle,
     config::DisplayConfig,
     monitor::{MonitorIdent, MonitorsAccess},
-    resources::ScreenDimensions,
+    resources::{ScreenDimensions, SecondaryWindows},
     system::{EventsLoopSystem, WindowSystem},
 };
-pub use winit::{Icon, Window};
+pub use winit::{Icon, Window, WindowId};
@@ -1,3 +1,5 @@
+use winit::{Window, WindowId};
+
 /// World resource that stores screen dimensions.
 #[derive(Debug, PartialEq, Clone)]
 pub struct ScreenDimensions {
@@ -66,3 +68,108 @@ impl ScreenDimensions {
         self.hidpi = factor;
     }
 }
+
+/// World resource with the windows opened next to the main window with
+/// `WindowBundle::with_secondary_window`, by name.
+///
+/// Their dimensions are kept up to date by the `WindowSystem`. Use `name_of` to find which
+/// window a `winit::Event` is for.
+#[derive(Default)]
+pub struct SecondaryWindows {
+    windows: Vec<SecondaryWindow>,
+    closed: Vec<Window>,
+}
+
+struct SecondaryWindow {
+    name: String,
+    window: Window,
+    dimensions: ScreenDimensions,
+}
+
+impl std::fmt::Debug for SecondaryWindows {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(
+                self.windows
+                    .iter()
+                    .map(|window| (&window.name, &window.dimensions)),
+            )
+            .finish()
+    }
+}
+
+impl SecondaryWindows {
+    /// The open window of the given name.
+    pub fn get(&self, name: &str) -> Option<&Window> {
+        self.find(name).map(|window| &window.window)
+    }
+
+    /// The dimensions of the open window of the given name.
+    pub fn dimensions(&self, name: &str) -> Option<&ScreenDimensions> {
+        self.find(name).map(|window| &window.dimensions)
+    }
+
+    /// The name of the open window with the given id.
+    pub fn name_of(&self, id: WindowId) -> Option<&str> {
+        self.windows
+            .iter()
+            .find(|window| window.window.id() == id)
+            .map(|window| window.name.as_str())
+    }
+
+    /// Names of the open windows.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.windows.iter().map(|window| window.name.as_str())
+    }
+
+    /// Close the window of the given name, returning whether it was open.
+    ///
+    /// The window is hidden right away, and destroyed once the renderer dropped its surface,
+    /// see `drop_closed`.
+    pub fn close(&mut self, name: &str) -> bool {
+        match self.windows.iter().position(|window| window.name == name) {
+            Some(index) => {
+                let window = self.windows.remove(index).window;
+                window.hide();
+                self.closed.push(window);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Destroy the closed windows. Called by the renderer once it no longer renders to them.
+    pub fn drop_closed(&mut self) {
+        self.closed.clear();
+    }
+
+    pub(crate) fn push(&mut self, name: String, window: Window) {
+        let dimensions =
+            window_dimensions(&window).unwrap_or_else(|| ScreenDimensions::new(0, 0, 1.0));
+        self.windows.push(SecondaryWindow {
+            name,
+            window,
+            dimensions,
+        });
+    }
+
+    /// Update the dimensions of the windows from their current size.
+    pub(crate) fn update_dimensions(&mut self) {
+        for window in &mut self.windows {
+            // Closed or minimized windows have no area
+            window.dimensions = window_dimensions(&window.window)
+                .unwrap_or_else(|| ScreenDimensions::new(0, 0, window.dimensions.hidpi));
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<&SecondaryWindow> {
+        self.windows.iter().find(|window| window.name == name)
+    }
+}
+
+/// Physical size and DPI factor of a window.
+fn window_dimensions(window: &Window) -> Option<ScreenDimensions> {
+    let hidpi = window.get_hidpi_factor();
+    let (width, height) = window.get_inner_size()?.to_physical(hidpi).into();
+    Some(ScreenDimensions::new(width, height, hidpi))
+}
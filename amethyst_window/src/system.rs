@@ -1,4 +1,7 @@
-use crate::{config::DisplayConfig, resources::ScreenDimensions};
+use crate::{
+    config::DisplayConfig,
+    resources::{ScreenDimensions, SecondaryWindows},
+};
 use amethyst_config::Config;
 use amethyst_core::{
     ecs::{ReadExpect, Resources, RunNow, System, SystemData, Write, WriteExpect},
@@ -10,6 +13,7 @@ use winit::{Event, EventsLoop, Window};
 /// System for opening and managing the window.
 pub struct WindowSystem {
     window: Option<Window>,
+    secondary: Vec<(String, Window)>,
 }
 
 impl WindowSystem {
@@ -28,9 +32,25 @@ impl WindowSystem {
     pub fn new(window: Window) -> Self {
         Self {
             window: Some(window),
+            secondary: Vec::new(),
         }
     }
 
+    /// Also open a secondary window, available by name from the `SecondaryWindows` resource.
+    pub fn with_secondary_window(
+        mut self,
+        events_loop: &EventsLoop,
+        name: impl Into<String>,
+        config: DisplayConfig,
+    ) -> Self {
+        let window = config
+            .to_window_builder(events_loop)
+            .build(events_loop)
+            .unwrap();
+        self.secondary.push((name.into(), window));
+        self
+    }
+
     fn manage_dimensions(&mut self, mut screen_dimensions: &mut ScreenDimensions, window: &Window) {
         let width = screen_dimensions.w;
         let height = screen_dimensions.h;
@@ -60,24 +80,37 @@ impl WindowSystem {
 }
 
 impl<'a> System<'a> for WindowSystem {
-    type SystemData = (WriteExpect<'a, ScreenDimensions>, ReadExpect<'a, Window>);
+    type SystemData = (
+        WriteExpect<'a, ScreenDimensions>,
+        ReadExpect<'a, Window>,
+        Write<'a, SecondaryWindows>,
+    );
 
-    fn run(&mut self, (mut screen_dimensions, window): Self::SystemData) {
+    fn run(&mut self, (mut screen_dimensions, window, mut secondary): Self::SystemData) {
         #[cfg(feature = "profiler")]
         profile_scope!("window_system");
 
         self.manage_dimensions(&mut screen_dimensions, &window);
+        secondary.update_dimensions();
     }
     fn setup(&mut self, res: &mut Resources) {
         if let Some(window) = self.window.take() {
+            // Start with the physical size, so the first frame isn't rendered at the logical size
+            // and upscaled on high DPI displays.
+            let hidpi = window.get_hidpi_factor();
             let (width, height) = window
                 .get_inner_size()
                 .expect("Window closed during initialization!")
+                .to_physical(hidpi)
                 .into();
-            let hidpi = window.get_hidpi_factor();
             res.insert(ScreenDimensions::new(width, height, hidpi));
             res.insert(window);
         }
+        let mut secondary = SecondaryWindows::default();
+        for (name, window) in self.secondary.drain(..) {
+            secondary.push(name, window);
+        }
+        res.insert(secondary);
     }
 }
 
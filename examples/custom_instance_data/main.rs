@@ -0,0 +1,417 @@
+//! Dissolves spheres with a custom render group drawing a per-instance `Dissolve` amount.
+//!
+//! The amount of every entity is gathered into the instance stream of the group with the
+//! `InstanceSub` submodule, next to the model matrix and tint of the built-in 3D groups, and read
+//! by the shaders of the group as an instance-rate vertex attribute.
+
+use amethyst::{
+    assets::AssetLoaderSystemData,
+    core::{
+        ecs::{
+            Builder, Component, DenseVecStorage, DispatcherBuilder, Join, Read, ReadStorage,
+            Resources, System, WriteStorage,
+        },
+        math::{convert, Matrix4},
+        Time, Transform, TransformBundle,
+    },
+    error::Error,
+    renderer::{
+        bundle::{RenderOrder, RenderPlan, RenderPlugin, RenderToWindow, RenderingBundle},
+        camera::Camera,
+        mtl::{Material, MaterialDefaults, TexAlbedo},
+        palette::{LinSrgba, Srgba},
+        pipeline::{self, PipelineDescBuilder, PipelinesBuilder},
+        pod::{IntoPod, Tint as TintArgs},
+        rendy::{
+            command::{QueueId, RenderPassEncoder},
+            factory::Factory,
+            graph::{
+                render::{PrepareResult, RenderGroup, RenderGroupDesc},
+                GraphContext, NodeBuffer, NodeImage,
+            },
+            hal::{self, device::Device, format::Format, pso},
+            mesh::{AsAttribute, AsVertex, Model, Position, TexCoord, VertexFormat},
+            shader::{ShaderKind, SourceLanguage, SourceShaderInfo, SpirvShader},
+            texture::palette::load_from_linear_rgba,
+        },
+        resources::Tint,
+        shape::Shape,
+        submodules::{FlatEnvironmentSub, InstanceData, InstanceSub, MaterialSub},
+        types::{Backend, DefaultBackend, Texture},
+        util, Mesh,
+    },
+    utils::application_root_dir,
+    window::{ScreenDimensions, WindowBundle},
+    Application, GameData, GameDataBuilder, SimpleState, StateData,
+};
+
+/// How much of an entity is dissolved, from 0 for none to 1 for all of it.
+#[derive(Debug, Clone, Copy, Default)]
+struct Dissolve(f32);
+
+impl Component for Dissolve {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// The dissolve amount as a vertex attribute.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct DissolveAmount(f32);
+
+impl AsAttribute for DissolveAmount {
+    const NAME: &'static str = "dissolve";
+    const FORMAT: Format = Format::R32Sfloat;
+}
+
+/// Instance-rate data of the dissolve group.
+/// ```glsl,ignore
+///  mat4 model;
+///  vec4 tint;
+///  float dissolve;
+/// ```
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct DissolveArgs {
+    model: [[f32; 4]; 4],
+    tint: [f32; 4],
+    amount: f32,
+}
+
+impl AsVertex for DissolveArgs {
+    fn vertex() -> VertexFormat {
+        VertexFormat::new((
+            Model::vertex(),
+            TintArgs::vertex(),
+            DissolveAmount::vertex(),
+        ))
+    }
+}
+
+impl InstanceData for DissolveArgs {
+    type Component = Dissolve;
+
+    fn from_object_data(transform: &Transform, tint: Option<&Tint>, dissolve: &Dissolve) -> Self {
+        DissolveArgs {
+            model: convert::<_, Matrix4<f32>>(*transform.global_matrix()).into(),
+            tint: tint.map_or([1.0; 4], |t| t.0.into_pod()),
+            amount: dissolve.0,
+        }
+    }
+}
+
+/// Vertex formats of the meshes drawn by the group.
+fn mesh_formats() -> Vec<VertexFormat> {
+    vec![Position::vertex(), TexCoord::vertex()]
+}
+
+/// Compile a GLSL shader of the example, which needs the `shader-compiler` feature.
+fn compile(source: &str, path: &str, kind: ShaderKind) -> Result<SpirvShader, failure::Error> {
+    SourceShaderInfo::new(source, path, kind, SourceLanguage::GLSL, "main")
+        .precompile()
+        .map_err(|e| failure::format_err!("Failed to compile {}: {}", path, e))
+}
+
+/// Draws the entities with a `Dissolve` component, dissolving them with noise.
+#[derive(Clone, Debug, Default)]
+struct DrawDissolveDesc;
+
+impl<B: Backend> RenderGroupDesc<B, Resources> for DrawDissolveDesc {
+    fn build(
+        self,
+        _ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        resources: &Resources,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, Resources>>, failure::Error> {
+        let env = FlatEnvironmentSub::new(factory)?;
+        let materials = MaterialSub::new(factory)?;
+
+        let mut formats = mesh_formats();
+        let (pipeline, pipeline_layout) = build_dissolve_pipeline(
+            factory,
+            resources,
+            subpass,
+            framebuffer_width,
+            framebuffer_height,
+            &formats,
+            vec![env.raw_layout(), materials.raw_layout()],
+        )?;
+        // The meshes are bound in the sorted order of the formats, like the pipeline expects.
+        formats.sort();
+
+        Ok(Box::new(DrawDissolve::<B> {
+            pipeline,
+            pipeline_layout,
+            formats,
+            env,
+            materials,
+            instances: InstanceSub::new(),
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct DrawDissolve<B: Backend> {
+    pipeline: B::GraphicsPipeline,
+    pipeline_layout: B::PipelineLayout,
+    formats: Vec<VertexFormat>,
+    env: FlatEnvironmentSub<B>,
+    materials: MaterialSub<B, TexAlbedo>,
+    instances: InstanceSub<B, DissolveArgs>,
+}
+
+impl<B: Backend> RenderGroup<B, Resources> for DrawDissolve<B> {
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        _queue: QueueId,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        resources: &Resources,
+    ) -> PrepareResult {
+        self.env.process(factory, index, resources);
+        self.materials.maintain(factory, resources);
+        self.instances
+            .gather(factory, resources, &mut self.materials);
+        self.instances.write(factory, index, resources);
+        PrepareResult::DrawRecord
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        resources: &Resources,
+    ) {
+        encoder.bind_graphics_pipeline(&self.pipeline);
+        self.env.bind(index, &self.pipeline_layout, 0, &mut encoder);
+        self.instances.draw(
+            index,
+            &self.formats,
+            &self.materials,
+            &self.pipeline_layout,
+            1,
+            &mut encoder,
+            resources,
+        );
+    }
+
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, _resources: &Resources) {
+        unsafe {
+            factory.device().destroy_graphics_pipeline(self.pipeline);
+            factory
+                .device()
+                .destroy_pipeline_layout(self.pipeline_layout);
+        }
+    }
+}
+
+fn build_dissolve_pipeline<B: Backend>(
+    factory: &Factory<B>,
+    resources: &Resources,
+    subpass: hal::pass::Subpass<'_, B>,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    formats: &[VertexFormat],
+    layouts: Vec<&B::DescriptorSetLayout>,
+) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
+    let vertex = compile(
+        include_str!("shaders/dissolve.vert"),
+        "dissolve.vert",
+        ShaderKind::Vertex,
+    )?;
+    let fragment = compile(
+        include_str!("shaders/dissolve.frag"),
+        "dissolve.frag",
+        ShaderKind::Fragment,
+    )?;
+
+    let pipeline_layout = unsafe {
+        factory
+            .device()
+            .create_pipeline_layout(layouts, None as Option<(_, _)>)
+    }?;
+    let shader_vertex = unsafe { pipeline::shader_module(factory, "DrawDissolve", &vertex) }?;
+    let shader_fragment = unsafe { pipeline::shader_module(factory, "DrawDissolve", &fragment) }?;
+
+    let (vertex_buffers, attributes) = util::instanced_vertex_desc(formats, DissolveArgs::vertex());
+    let pipes = PipelinesBuilder::new()
+        .with_group("DrawDissolve")
+        .with_pipeline(
+            PipelineDescBuilder::new()
+                .with_vertex_buffers(vertex_buffers)
+                .with_attributes(attributes)
+                .with_vertex_inputs(pipeline::vertex_inputs(&vertex))
+                .with_shaders(util::simple_shader_set(
+                    &shader_vertex,
+                    Some(&shader_fragment),
+                ))
+                .with_layout(&pipeline_layout)
+                .with_subpass(subpass)
+                .with_framebuffer_size(framebuffer_width, framebuffer_height)
+                .with_face_culling(pso::Face::NONE)
+                .with_depth_test(pso::DepthTest::On {
+                    fun: pso::Comparison::Less,
+                    write: true,
+                })
+                .with_blend_targets(vec![pso::ColorBlendDesc(
+                    pso::ColorMask::ALL,
+                    pso::BlendState::Off,
+                )]),
+        )
+        .build_cached(factory, resources);
+
+    unsafe {
+        factory.destroy_shader_module(shader_vertex);
+        factory.destroy_shader_module(shader_fragment);
+    }
+
+    match pipes {
+        Err(e) => {
+            unsafe {
+                factory.device().destroy_pipeline_layout(pipeline_layout);
+            }
+            Err(e)
+        }
+        Ok(mut pipes) => Ok((pipes.remove(0), pipeline_layout)),
+    }
+}
+
+/// Draws the dissolving entities with the opaque geometry.
+#[derive(Debug, Default)]
+struct RenderDissolve;
+
+impl<B: Backend> RenderPlugin<B> for RenderDissolve {
+    fn on_build<'a, 'b>(&mut self, builder: &mut DispatcherBuilder<'a, 'b>) -> Result<(), Error> {
+        builder.add(DissolveSystem, "dissolve_system", &[]);
+        Ok(())
+    }
+
+    fn on_plan(
+        &mut self,
+        plan: &mut RenderPlan<B>,
+        _factory: &mut Factory<B>,
+        _res: &Resources,
+    ) -> Result<(), Error> {
+        plan.add_group(RenderOrder::Opaque, DrawDissolveDesc);
+        Ok(())
+    }
+}
+
+/// Dissolves the entities back and forth, each with its own phase.
+struct DissolveSystem;
+
+impl<'a> System<'a> for DissolveSystem {
+    type SystemData = (
+        Read<'a, Time>,
+        ReadStorage<'a, Transform>,
+        WriteStorage<'a, Dissolve>,
+    );
+
+    fn run(&mut self, (time, transforms, mut dissolves): Self::SystemData) {
+        let time = time.absolute_time_seconds() as f32;
+        for (transform, dissolve) in (&transforms, &mut dissolves).join() {
+            let phase = transform.translation().x.as_f32() * 0.7;
+            dissolve.0 = ((time + phase).sin() + 1.0) * 0.5;
+        }
+    }
+}
+
+struct Example;
+
+impl SimpleState for Example {
+    fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
+        let StateData { world, .. } = data;
+        world.register::<Dissolve>();
+        let mat_defaults = world.read_resource::<MaterialDefaults>().0.clone();
+
+        let mesh = world.exec(|loader: AssetLoaderSystemData<'_, Mesh>| {
+            loader.load_from_data(
+                Shape::Sphere(32, 32)
+                    .generate::<(Vec<Position>, Vec<TexCoord>)>(None)
+                    .into(),
+                (),
+            )
+        });
+        let albedo = world.exec(|loader: AssetLoaderSystemData<'_, Texture>| {
+            loader.load_from_data(
+                load_from_linear_rgba(LinSrgba::new(0.8, 0.8, 0.8, 1.0)).into(),
+                (),
+            )
+        });
+        let material = world.exec(|loader: AssetLoaderSystemData<'_, Material>| {
+            loader.load_from_data(
+                Material {
+                    albedo,
+                    ..mat_defaults
+                },
+                (),
+            )
+        });
+
+        for i in 0..5 {
+            for j in 0..5 {
+                let mut transform = Transform::default();
+                transform.set_translation_xyz(2.0 * (i - 2) as f32, 2.0 * (j - 2) as f32, 0.0);
+                let tint = Tint(Srgba::new(
+                    0.4 + 0.15 * i as f32,
+                    0.4 + 0.15 * j as f32,
+                    1.0,
+                    1.0,
+                ));
+
+                world
+                    .create_entity()
+                    .with(transform)
+                    .with(mesh.clone())
+                    .with(material.clone())
+                    .with(tint)
+                    .with(Dissolve::default())
+                    .build();
+            }
+        }
+
+        let mut transform = Transform::default();
+        transform.set_translation_xyz(0.0, 0.0, -12.0);
+        transform.prepend_rotation_y_axis(std::f32::consts::PI);
+
+        let (width, height) = {
+            let dim = world.read_resource::<ScreenDimensions>();
+            (dim.width(), dim.height())
+        };
+
+        world
+            .create_entity()
+            .with(Camera::standard_3d(width, height))
+            .with(transform)
+            .build();
+    }
+}
+
+fn main() -> amethyst::Result<()> {
+    amethyst::start_logger(Default::default());
+
+    let app_root = application_root_dir()?;
+    let display_config_path =
+        app_root.join("examples/custom_instance_data/resources/display_config.ron");
+    let resources = app_root.join("examples/assets/");
+
+    let game_data = GameDataBuilder::default()
+        .with_bundle(WindowBundle::from_config_path(display_config_path))?
+        .with_bundle(TransformBundle::new())?
+        .with_bundle(
+            RenderingBundle::<DefaultBackend>::new()
+                .with_plugin(RenderToWindow::new().with_clear([0.1, 0.1, 0.15, 1.0]))
+                .with_plugin(RenderDissolve),
+        )?;
+
+    let mut game = Application::new(&resources, Example, game_data)?;
+    game.run();
+    Ok(())
+}
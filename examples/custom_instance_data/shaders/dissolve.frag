@@ -0,0 +1,57 @@
+#version 450
+
+struct UvOffset {
+    vec2 u_offset;
+    vec2 v_offset;
+};
+
+layout(std140, set = 1, binding = 0) uniform Material {
+    UvOffset uv_offset;
+    float alpha_cutoff;
+};
+
+layout(set = 1, binding = 1) uniform sampler2D albedo;
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec2 tex_coord;
+    vec4 color;
+    float dissolve;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+const vec4 EDGE_COLOR = vec4(1.0, 0.45, 0.1, 1.0);
+const float EDGE_WIDTH = 0.08;
+
+float tex_coord(float coord, vec2 offset) {
+    return offset.x + coord * (offset.y - offset.x);
+}
+
+vec2 tex_coords(vec2 coord, vec2 u, vec2 v) {
+    return vec2(tex_coord(coord.x, u), tex_coord(coord.y, v));
+}
+
+// Value noise over cells of the texture coordinates.
+float hash(vec2 cell) {
+    return fract(sin(dot(cell, vec2(12.9898, 78.233))) * 43758.5453);
+}
+
+float noise(vec2 coord) {
+    vec2 cell = floor(coord);
+    vec2 f = smoothstep(0.0, 1.0, fract(coord));
+    float a = hash(cell);
+    float b = hash(cell + vec2(1.0, 0.0));
+    float c = hash(cell + vec2(0.0, 1.0));
+    float d = hash(cell + vec2(1.0, 1.0));
+    return mix(mix(a, b, f.x), mix(c, d, f.x), f.y);
+}
+
+void main() {
+    float threshold = noise(vertex.tex_coord * vec2(32.0, 16.0));
+    if (threshold < vertex.dissolve) discard;
+
+    vec4 albedo = texture(albedo, tex_coords(vertex.tex_coord, uv_offset.u_offset, uv_offset.v_offset));
+    float edge = 1.0 - smoothstep(0.0, EDGE_WIDTH, threshold - vertex.dissolve);
+    out_color = mix(albedo * vertex.color, EDGE_COLOR, edge * step(0.001, vertex.dissolve));
+}
@@ -1,7 +1,7 @@
 use amethyst::{
     assets::Handle,
     renderer::{
-        sprite::{Sprite, SpriteSheet},
+        sprite::{AlphaMode, Sprite, SpriteSheet},
         Texture,
     },
 };
@@ -58,7 +58,11 @@ pub fn load(texture: Handle<Texture>, definition: &sprite::SpriteSheetDefinition
         }
     }
     println!("Sheet: {:?}", sprites);
-    SpriteSheet { texture, sprites }
+    SpriteSheet {
+        texture,
+        sprites,
+        alpha_mode: AlphaMode::Premultiplied,
+    }
 }
 
 /// Returns the pixel offset distances per sprite.
@@ -707,8 +707,13 @@ where
     ///
     ///         let loader = data.world.read_resource::<Loader>();
     ///         // Load a teapot mesh from the directory that registered above.
-    ///         let mesh: Handle<Mesh> =
-    ///             loader.load_from("teapot", ObjFormat, "custom_directory", (), &storage);
+    ///         let mesh: Handle<Mesh> = loader.load_from(
+    ///             "teapot",
+    ///             ObjFormat::default(),
+    ///             "custom_directory",
+    ///             (),
+    ///             &storage,
+    ///         );
     ///     }
     /// }
     /// ~~~
@@ -761,7 +766,7 @@ where
     ///
     ///         let loader = data.world.read_resource::<Loader>();
     ///         // Load a teapot mesh from the directory that registered above.
-    ///         let mesh: Handle<Mesh> = loader.load("teapot", ObjFormat, (), &storage);
+    ///         let mesh: Handle<Mesh> = loader.load("teapot", ObjFormat::default(), (), &storage);
     ///     }
     /// }
     /// ~~~